@@ -58,6 +58,10 @@ where
     fn str_len(&self) -> usize {
         self.strings.len()
     }
+
+    fn alloc_size(&self) -> usize {
+        self.key_to_str.capacity() * std::mem::size_of::<Idx>() + self.strings.capacity()
+    }
 }
 
 // InternStr ///////////////////
@@ -75,10 +79,12 @@ where
     // Specifically, see `StrToKey`.
     fn intern<T: AsRef<str>>(&mut self, s: T) -> Result<Key> {
         let str_len = self.strings.len();
-        let start = Idx::try_from(str_len).map_err(|_| Error::StringIndexOutOfBounds(str_len))?;
+        let start = Idx::try_from(str_len)
+            .map_err(|_| Error::StringIndexOutOfBounds(str_len, std::any::type_name::<Idx>()))?;
 
         let keys_len = self.key_to_str.len();
-        let k = Key::try_from(keys_len).map_err(|_| Error::OutOfKeySpace(keys_len))?;
+        let k = Key::try_from(keys_len)
+            .map_err(|_| Error::OutOfKeySpace(keys_len, std::any::type_name::<Key>()))?;
 
         self.key_to_str.push(start);
         self.strings.push_str(s.as_ref());
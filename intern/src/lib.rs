@@ -14,13 +14,21 @@ use key_to_str::KeyToStr;
 mod typed;
 pub use typed::TypedInterner;
 
+/// Interner that can be shared between threads.
+mod sync;
+pub use sync::SyncInterner;
+
 /// Internals for mapping interned strings to keys.
 mod str_to_key;
 use str_to_key::StrToKey;
 
+/// serde support for interners, behind the "serde" feature.
+#[cfg(feature = "serde")]
+mod serde_impl;
+
 /// Core traits this crate is built on:
 mod traits;
-pub use traits::{GetStr, InternStr};
+pub use traits::{GetStr, InternStr, SyncInternStr};
 
 type Hasher = std::hash::BuildHasherDefault<rustc_hash::FxHasher>;
 
@@ -35,8 +43,12 @@ pub type LooseInterner<Key = u32, Idx = usize> = KeyToStr<Key, Idx>;
 pub enum Error {
     #[error("Key not found in the interner mapping: {0}")]
     KeyNotFound(usize),
-    #[error("Out of string index space; {0} is greater than the maximum string index")]
-    StringIndexOutOfBounds(usize),
-    #[error("Out of interner key space; {0} is greater than the maximum key value")]
-    OutOfKeySpace(usize),
+    #[error("Out of string index space; {0} is greater than the maximum {1} string index")]
+    StringIndexOutOfBounds(usize, &'static str),
+    #[error("Out of interner key space; {0} is greater than the maximum {1} key")]
+    OutOfKeySpace(usize, &'static str),
+    #[error("Interner capacity exceeded for {0} strings; increase the key or index type of its interner")]
+    CapacityExceeded(&'static str),
+    #[error("Interner lock poisoned by a thread that panicked while interning")]
+    Poisoned,
 }
@@ -30,6 +30,16 @@ impl<Key, Idx> PackedInterner<Key, Idx, crate::Hasher> {
     }
 }
 
+impl<Key: Copy, Idx, H: BuildHasher> PackedInterner<Key, Idx, H>
+where
+    KeyToStr<Key, Idx>: GetStr<Key = Key>,
+{
+    /// Key of `s`, if it's already been interned, without interning it.
+    pub fn find<T: AsRef<str>>(&self, s: T) -> Option<Key> {
+        self.str_to_key.find(s.as_ref(), &self.key_to_str)
+    }
+}
+
 // GetStr /////////////////////
 impl<Key, Idx, H: BuildHasher> GetStr for PackedInterner<Key, Idx, H>
 where
@@ -48,6 +58,10 @@ where
     fn str_len(&self) -> usize {
         self.key_to_str.str_len()
     }
+
+    fn alloc_size(&self) -> usize {
+        self.str_to_key.alloc_size() + self.key_to_str.alloc_size()
+    }
 }
 
 // InternStr ///////////////////
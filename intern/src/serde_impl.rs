@@ -0,0 +1,165 @@
+//! Interners are serialized as their strings in key order, and deserialized by
+//! re-interning them, which gives each string the same key again.
+
+use std::fmt;
+
+use serde::de::{Deserialize, Deserializer, Error, SeqAccess, Visitor};
+use serde::ser::{Serialize, Serializer};
+
+use super::{GetStr, InternStr, KeyToStr, PackedInterner, Strs, TypedInterner};
+
+fn serialize_strs<T, S>(interner: &T, serializer: S) -> Result<S::Ok, S::Error>
+where
+    T: GetStr,
+    T::Key: TryFrom<usize> + Copy,
+    S: Serializer,
+{
+    serializer.collect_seq(interner.iter().map(|(_, s)| s))
+}
+
+/// Re-interns each string of a sequence into an empty interner.
+struct StrsVisitor<T>(T);
+
+impl<'de, T: GetStr + InternStr> Visitor<'de> for StrsVisitor<T> {
+    type Value = T;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a sequence of interned strings")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(mut self, mut seq: A) -> Result<T, A::Error> {
+        while let Some(s) = seq.next_element::<String>()? {
+            let len = self.0.len();
+            self.0.intern(&s).map_err(|e| A::Error::custom(format!("{e:#}")))?;
+            // a string that was interned twice would give every later string the wrong key:
+            if self.0.len() != len + 1 {
+                return Err(A::Error::custom(format!(
+                    "duplicate interned string \"{s}\""
+                )));
+            }
+        }
+        Ok(self.0)
+    }
+}
+
+// PackedInterner /////////////
+impl<Key, Idx> Serialize for PackedInterner<Key, Idx>
+where
+    Self: GetStr<Key = Key>,
+    Key: TryFrom<usize> + Copy,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_strs(self, serializer)
+    }
+}
+
+impl<'de, Key, Idx> Deserialize<'de> for PackedInterner<Key, Idx>
+where
+    Self: GetStr + InternStr,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let interner = Self::with_capacity_and_avg_len(0, 0);
+        deserializer.deserialize_seq(StrsVisitor(interner))
+    }
+}
+
+// KeyToStr ///////////////////
+impl<Key, Idx> Serialize for KeyToStr<Key, Idx>
+where
+    Self: GetStr<Key = Key>,
+    Key: TryFrom<usize> + Copy,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_strs(self, serializer)
+    }
+}
+
+impl<'de, Key, Idx> Deserialize<'de> for KeyToStr<Key, Idx>
+where
+    Self: GetStr + InternStr,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let interner = Self::with_capacity_and_avg_len(0, 0);
+        deserializer.deserialize_seq(StrsVisitor(interner))
+    }
+}
+
+// Strs ///////////////////////
+impl<Key, Idx> Serialize for Strs<Key, Idx>
+where
+    Self: GetStr<Key = Key>,
+    Key: TryFrom<usize> + Copy,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_strs(self, serializer)
+    }
+}
+
+impl<'de, Key, Idx> Deserialize<'de> for Strs<Key, Idx>
+where
+    KeyToStr<Key, Idx>: GetStr + InternStr,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        KeyToStr::deserialize(deserializer).map(Self::new)
+    }
+}
+
+// TypedInterner //////////////
+impl<Key, T: Serialize> Serialize for TypedInterner<Key, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.inner().serialize(serializer)
+    }
+}
+
+impl<'de, Key, T: Deserialize<'de>> Deserialize<'de> for TypedInterner<Key, T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        T::deserialize(deserializer).map(Self::new)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct TaskId(u16);
+
+    impl From<TaskId> for u16 {
+        fn from(id: TaskId) -> u16 {
+            id.0
+        }
+    }
+
+    impl From<u16> for TaskId {
+        fn from(val: u16) -> Self {
+            Self(val)
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut tasks: TypedInterner<TaskId, PackedInterner<u16, u32>> =
+            TypedInterner::new(PackedInterner::with_capacity_and_avg_len(4, 8));
+        for name in ["prep", "build", "test"] {
+            tasks.intern(name).unwrap();
+        }
+        let json = serde_json::to_string(&tasks).unwrap();
+        assert_eq!(json, r#"["prep","build","test"]"#);
+
+        let mut tasks: TypedInterner<TaskId, PackedInterner<u16, u32>> =
+            serde_json::from_str(&json).unwrap();
+        assert_eq!(tasks.get(TaskId(1)).unwrap(), "build");
+        // still dedupes after deserializing:
+        assert_eq!(tasks.intern("test").unwrap(), TaskId(2));
+        assert_eq!(tasks.intern("docs").unwrap(), TaskId(3));
+
+        let strs: Strs<u8, u16> = serde_json::from_str(&json).unwrap();
+        assert_eq!(strs.get(2).unwrap(), "test");
+
+        let e = serde_json::from_str::<PackedInterner>(r#"["a","b","a"]"#).unwrap_err();
+        assert!(
+            e.to_string().starts_with("duplicate interned string \"a\""),
+            "{e}"
+        );
+    }
+}
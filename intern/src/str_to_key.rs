@@ -23,7 +23,30 @@ impl<Key, H: Default> StrToKey<Key, H> {
     }
 }
 
+impl<Key, H> StrToKey<Key, H> {
+    /// Bytes allocated for the map, approximately: a key and a control byte for each entry
+    /// it has capacity for (hashbrown allocates a few more buckets than that).
+    pub fn alloc_size(&self) -> usize {
+        self.map.capacity() * (std::mem::size_of::<Key>() + 1)
+    }
+}
+
 impl<Key: Copy, H: BuildHasher> StrToKey<Key, H> {
+    /// Key of `s`, if it's already been interned into `key_to_str`.
+    pub fn find<T>(&self, s: &str, key_to_str: &T) -> Option<Key>
+    where
+        T: GetStr<Key = Key>,
+    {
+        let hash = self.hasher.hash_one(s);
+        let entry = self.map.raw_entry().from_hash(hash, |colliding_key| {
+            let already_interned = key_to_str
+                .get(*colliding_key)
+                .expect("This key should be guaranteed to work in the key-to-str map");
+            s == already_interned
+        });
+        entry.map(|(k, _)| *k)
+    }
+
     pub fn intern<T>(&mut self, s: &str, key_to_str: &mut T) -> Result<Key>
     where
         T: GetStr<Key = Key> + InternStr<Key = Key>,
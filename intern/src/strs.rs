@@ -33,4 +33,8 @@ where
     fn str_len(&self) -> usize {
         self.key_to_str.str_len()
     }
+
+    fn alloc_size(&self) -> usize {
+        self.key_to_str.alloc_size()
+    }
 }
@@ -0,0 +1,103 @@
+use std::sync::RwLock;
+
+use anyhow::Result;
+
+use super::{Error, GetStr, InternStr, KeyToStr, PackedInterner, SyncInternStr};
+
+/// A `PackedInterner` behind a lock, so that worker threads can intern strings
+/// through a shared reference. Strings that are already interned only take a read lock,
+/// so threads interning the same strings don't wait on each other.
+/// Strings can't be retrieved while it's shared; use `into_inner` when the threads are done.
+#[derive(Debug)]
+pub struct SyncInterner<Key = u32, Idx = usize> {
+    interner: RwLock<PackedInterner<Key, Idx>>,
+}
+
+impl<Key, Idx> SyncInterner<Key, Idx> {
+    pub fn new(interner: PackedInterner<Key, Idx>) -> Self {
+        Self {
+            interner: RwLock::new(interner),
+        }
+    }
+
+    pub fn into_inner(self) -> Result<PackedInterner<Key, Idx>> {
+        self.interner.into_inner().map_err(|_| Error::Poisoned.into())
+    }
+}
+
+impl<Key, Idx> From<PackedInterner<Key, Idx>> for SyncInterner<Key, Idx> {
+    fn from(val: PackedInterner<Key, Idx>) -> Self {
+        Self::new(val)
+    }
+}
+
+// SyncInternStr ///////////////
+impl<Key, Idx> SyncInternStr for SyncInterner<Key, Idx>
+where
+    Key: Copy,
+    KeyToStr<Key, Idx>: GetStr<Key = Key> + InternStr<Key = Key>,
+{
+    type Key = Key;
+
+    fn intern_sync<T: AsRef<str>>(&self, s: T) -> Result<Key> {
+        let s = s.as_ref();
+        if let Some(k) = self.interner.read().map_err(|_| Error::Poisoned)?.find(s) {
+            return Ok(k);
+        }
+        // NB another thread may have interned it since we checked,
+        // which `intern` checks for again:
+        self.interner.write().map_err(|_| Error::Poisoned)?.intern(s)
+    }
+}
+
+// InternStr ///////////////////
+// Interning through an exclusive reference doesn't need to lock:
+impl<Key, Idx> InternStr for SyncInterner<Key, Idx>
+where
+    Key: Copy,
+    KeyToStr<Key, Idx>: GetStr<Key = Key> + InternStr<Key = Key>,
+{
+    type Key = Key;
+
+    fn intern<T: AsRef<str>>(&mut self, s: T) -> Result<Key> {
+        self.interner.get_mut().map_err(|_| Error::Poisoned)?.intern(s)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_intern_sync() {
+        let mut interner: SyncInterner<u32, u32> =
+            PackedInterner::with_capacity_and_avg_len(16, 8).into();
+        interner.intern("shared").unwrap();
+        let keys: Vec<Vec<u32>> = std::thread::scope(|scope| {
+            let threads: Vec<_> = (0..4)
+                .map(|t| {
+                    let interner = &interner;
+                    scope.spawn(move || {
+                        let mut keys = vec![interner.intern_sync("shared").unwrap()];
+                        for i in 0..8 {
+                            keys.push(
+                                interner.intern_sync(format!("path/{}", (t + i) % 8)).unwrap(),
+                            );
+                        }
+                        keys
+                    })
+                })
+                .collect();
+            threads.into_iter().map(|t| t.join().unwrap()).collect()
+        });
+        let interner = interner.into_inner().unwrap();
+        // every string was interned once, no matter how many threads interned it:
+        assert_eq!(interner.len(), 9);
+        for (t, keys) in keys.iter().enumerate() {
+            assert_eq!(keys[0], 0);
+            for (i, k) in keys[1..].iter().enumerate() {
+                assert_eq!(interner.get(*k).unwrap(), format!("path/{}", (t + i) % 8));
+            }
+        }
+    }
+}
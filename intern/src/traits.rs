@@ -18,6 +18,38 @@ pub trait GetStr {
 
     /// Size of interned strings.
     fn str_len(&self) -> usize;
+
+    /// Bytes allocated for the interned strings and their keys, including unused capacity.
+    fn alloc_size(&self) -> usize;
+
+    /// Iterate over all interned strings and their keys, in the order they were interned.
+    fn iter(&self) -> impl ExactSizeIterator<Item = (Self::Key, &str)> + DoubleEndedIterator
+    where
+        Self::Key: TryFrom<usize> + Copy,
+    {
+        self.keys().map(|k| (k, self.get(k).expect("every key below len is interned")))
+    }
+
+    /// Iterate over the keys of all interned strings, in the order they were interned.
+    fn keys(&self) -> impl ExactSizeIterator<Item = Self::Key> + DoubleEndedIterator
+    where
+        Self::Key: TryFrom<usize> + Copy,
+    {
+        // NB every key below len was created from its index when it was interned:
+        (0..self.len()).map(|i| {
+            Self::Key::try_from(i).unwrap_or_else(|_| unreachable!("key {i} was interned"))
+        })
+    }
+}
+
+/// Trait for interners that can intern a string through a shared reference,
+/// so that several threads can intern strings at once.
+pub trait SyncInternStr {
+    /// Key type returned from intern_sync, can be used to fetch string later.
+    type Key;
+
+    /// Intern string `s` and return a key that can be used to retrieve it later.
+    fn intern_sync<T: AsRef<str>>(&self, s: T) -> Result<Self::Key>;
 }
 
 /// Trait for interners that can intern a string and return a `Key`.
@@ -2,7 +2,7 @@ use std::marker::PhantomData;
 
 use anyhow::Result;
 
-use super::{GetStr, InternStr};
+use super::{Error, GetStr, InternStr, SyncInternStr};
 
 #[derive(Debug)]
 pub struct TypedInterner<Key, T> {
@@ -18,6 +18,10 @@ impl<K, T> TypedInterner<K, T> {
         }
     }
 
+    pub fn inner(&self) -> &T {
+        &self.interner
+    }
+
     pub fn into_inner(self) -> T {
         self.interner
     }
@@ -42,6 +46,10 @@ where
     fn str_len(&self) -> usize {
         self.interner.str_len()
     }
+
+    fn alloc_size(&self) -> usize {
+        self.interner.alloc_size()
+    }
 }
 
 // InternStr ///////////////
@@ -53,6 +61,96 @@ where
     type Key = Key;
 
     fn intern<U: AsRef<str>>(&mut self, s: U) -> Result<Key> {
-        self.interner.intern(s).map(T::Key::into)
+        self.interner.intern(s).map(T::Key::into).map_err(capacity_context::<Key>)
+    }
+}
+
+// SyncInternStr ///////////
+impl<Key, T> SyncInternStr for TypedInterner<Key, T>
+where
+    T: SyncInternStr,
+    T::Key: Into<Key>,
+{
+    type Key = Key;
+
+    fn intern_sync<U: AsRef<str>>(&self, s: U) -> Result<Key> {
+        self.interner.intern_sync(s).map(T::Key::into).map_err(capacity_context::<Key>)
+    }
+}
+
+/// Tell which interner ran out of space, if that's why interning failed.
+fn capacity_context<Key>(e: anyhow::Error) -> anyhow::Error {
+    match e.downcast_ref() {
+        Some(Error::OutOfKeySpace(..) | Error::StringIndexOutOfBounds(..)) => {
+            e.context(Error::CapacityExceeded(key_name::<Key>()))
+        }
+        _ => e,
+    }
+}
+
+/// Name of the key type `Key`, without its path (e.g. "AbstractTaskId"),
+/// to tell which interner ran out of space.
+fn key_name<Key>() -> &'static str {
+    let name = std::any::type_name::<Key>();
+    name.rsplit("::").next().unwrap_or(name)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::PackedInterner;
+
+    #[derive(Debug, Clone, Copy)]
+    struct ModuleId(u8);
+
+    impl From<u8> for ModuleId {
+        fn from(val: u8) -> Self {
+            Self(val)
+        }
+    }
+
+    #[test]
+    fn test_capacity_exceeded() {
+        let mut modules: TypedInterner<ModuleId, PackedInterner<u8, u16>> =
+            TypedInterner::new(PackedInterner::with_capacity_and_str_len(256, 1024));
+        for i in 0..256 {
+            assert_eq!(modules.intern(format!("m{i}")).unwrap().0 as usize, i);
+        }
+        // interning an existing string doesn't need a new key:
+        assert_eq!(modules.intern("m7").unwrap().0, 7);
+        let e = modules.intern("m256").unwrap_err();
+        assert_eq!(
+            format!("{e:#}"),
+            "Interner capacity exceeded for ModuleId strings; increase the key or index type \
+             of its interner: Out of interner key space; 256 is greater than the maximum u8 key"
+        );
+
+        let mut short: TypedInterner<ModuleId, PackedInterner<u8, u8>> =
+            TypedInterner::new(PackedInterner::with_capacity_and_str_len(8, 256));
+        short.intern("x".repeat(256)).unwrap();
+        let e = short.intern("y").unwrap_err();
+        assert!(matches!(
+            e.downcast_ref(),
+            Some(Error::CapacityExceeded("ModuleId"))
+        ));
+    }
+
+    #[test]
+    fn test_iter() {
+        let mut tasks: PackedInterner<u8, u16> = PackedInterner::with_capacity_and_str_len(4, 16);
+        for name in ["prep", "build", "prep", "test"] {
+            tasks.intern(name).unwrap();
+        }
+        let all: Vec<_> = tasks.iter().collect();
+        assert_eq!(all, [(0, "prep"), (1, "build"), (2, "test")]);
+        assert_eq!(tasks.iter().len(), 3);
+        assert_eq!(tasks.iter().next_back(), Some((2, "test")));
+        assert_eq!(tasks.keys().collect::<Vec<_>>(), [0, 1, 2]);
+        // 4 u16 string indices and 16 bytes of strings, and at least 4 u8 keys in the map:
+        assert!(
+            tasks.alloc_size() >= 4 * 2 + 16 + 4,
+            "{}",
+            tasks.alloc_size()
+        );
     }
 }
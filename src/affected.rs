@@ -0,0 +1,242 @@
+use std::io::Write;
+
+use anyhow::Result;
+
+use util::{HashMap, HashSet};
+use workflow::{cross_product, AbstractTaskId, BranchSpec, BranchpointId, RealTaskKey, Workflow};
+
+use crate::fs::Fs;
+use crate::prep::Probe;
+
+/// States of an affected realization on disk, in the order they're counted.
+const STATES: [&str; 3] = ["complete", "incomplete", "missing"];
+
+/// Find every realization in the workflow that would have to run again if the realizations
+/// of `roots` matching any of `branches` changed, including those realizations themselves.
+/// Unspecified branchpoints in `branches` match any branch; an empty list matches nothing.
+///
+/// This works forwards from `roots` through the whole abstract workflow, rather than
+/// backwards from the goals of a plan, so realizations no plan reaches are included.
+/// A task using a root's outputs only on some of its branches is assumed to use them on all.
+pub fn find_affected(
+    wf: &Workflow,
+    roots: &[AbstractTaskId],
+    branches: &[BranchSpec],
+) -> Result<Vec<RealTaskKey>> {
+    let mut realization_bps = HashMap::default();
+
+    // the realizations of each task that are affected, as branches to match against:
+    let mut patterns: HashMap<AbstractTaskId, Vec<BranchSpec>> = HashMap::default();
+    for root in roots {
+        let root_bps = realization_branchpoints(wf, *root, &mut realization_bps, &mut Vec::new())?;
+        let mut root_patterns = Vec::with_capacity(branches.len());
+        for branch in branches {
+            let mut pattern = BranchSpec::default();
+            for (k, v) in branch.specified().filter(|(k, _)| root_bps.contains(k)) {
+                pattern.insert(k, v);
+            }
+            if !root_patterns.contains(&pattern) {
+                root_patterns.push(pattern);
+            }
+        }
+        patterns.insert(*root, root_patterns);
+    }
+
+    let mut tasks = roots.to_vec();
+    for task in wf.downstream_tasks(roots)? {
+        let mut task_patterns = Vec::new();
+        for dep in wf.task_dependencies(task)? {
+            let Some(dep_patterns) = patterns.get(&dep) else {
+                continue;
+            };
+            for dependent in wf.dependents(dep).iter().filter(|d| d.task == task) {
+                for pattern in dep_patterns {
+                    if let Some(pattern) = follow_graft(pattern, &dependent.graft, &dependent.globs)
+                    {
+                        if !task_patterns.contains(&pattern) {
+                            task_patterns.push(pattern);
+                        }
+                    }
+                }
+            }
+        }
+        if !task_patterns.is_empty() {
+            patterns.insert(task, task_patterns);
+            tasks.push(task);
+        }
+    }
+
+    let mut affected = Vec::new();
+    for task in tasks {
+        let bps = realization_branchpoints(wf, task, &mut realization_bps, &mut Vec::new())?;
+        let choices: Vec<_> =
+            bps.iter().map(|k| (*k, wf.strings.baselines.values(*k).to_vec())).collect();
+        for branch in cross_product(&choices) {
+            if patterns[&task].iter().any(|pattern| pattern.is_exact_match(&branch)) {
+                affected.push(RealTaskKey { id: task, branch });
+            }
+        }
+    }
+    Ok(affected)
+}
+
+/// The affected realizations of a task using the outputs of a realization matching `pattern`
+/// from `graft` (and from every branch of `globs`), or None if the graft never uses them.
+fn follow_graft(
+    pattern: &BranchSpec,
+    graft: &BranchSpec,
+    globs: &[BranchpointId],
+) -> Option<BranchSpec> {
+    let mut followed = pattern.clone();
+    for (k, v) in graft.specified() {
+        if pattern.get_specified(k).is_some_and(|pattern_v| pattern_v != v) {
+            return None;
+        }
+        // the graft uses the same branch of `k` no matter which branch the dependent is on:
+        followed.unset(k);
+    }
+    for k in globs {
+        followed.unset(*k);
+    }
+    Some(followed)
+}
+
+/// Branchpoints that realizations of `task` have: the ones its own variables branch on,
+/// plus the ones of the tasks it depends on that aren't fixed by a graft.
+fn realization_branchpoints(
+    wf: &Workflow,
+    task: AbstractTaskId,
+    memo: &mut HashMap<AbstractTaskId, Vec<BranchpointId>>,
+    visiting: &mut Vec<AbstractTaskId>,
+) -> Result<Vec<BranchpointId>> {
+    if let Some(bps) = memo.get(&task) {
+        return Ok(bps.clone());
+    }
+    // a cycle is reported when the workflow is traversed; here, just don't loop forever:
+    if visiting.contains(&task) {
+        return Ok(Vec::new());
+    }
+    visiting.push(task);
+    let mut bps: HashSet<BranchpointId> = wf.task_branchpoints(task)?.into_iter().collect();
+    for dep in wf.task_dependencies(task)? {
+        let dep_bps = realization_branchpoints(wf, dep, memo, visiting)?;
+        for dependent in wf.dependents(dep).iter().filter(|d| d.task == task) {
+            bps.extend(
+                dep_bps.iter().filter(|k| {
+                    dependent.graft.is_unspecified(**k) && !dependent.globs.contains(k)
+                }),
+            );
+        }
+    }
+    visiting.pop();
+    let mut bps: Vec<BranchpointId> = bps.into_iter().collect();
+    bps.sort_unstable_by_key(|k| usize::from(*k));
+    memo.insert(task, bps.clone());
+    Ok(bps)
+}
+
+/// Write the state on disk of each of the `affected` realizations to `out`,
+/// followed by the number of realizations in each state.
+pub fn write_affected(
+    affected: &[RealTaskKey],
+    wf: &Workflow,
+    fs: &Fs,
+    out: &mut impl Write,
+) -> Result<()> {
+    let mut locator = fs.locator();
+    let mut strbuf = String::with_capacity(128);
+    let mut rows = Vec::with_capacity(affected.len());
+    for key in affected {
+        locator.locate_key(key, wf, &mut strbuf)?;
+        let probe = Probe::new(locator.realization(), fs)?;
+        let state = match (probe.exists, probe.succeeded) {
+            (true, true) => "complete",
+            (true, false) => "incomplete",
+            (false, _) => "missing",
+        };
+        rows.push((state, wf.strings.get_real_task_str(key)?.to_owned()));
+    }
+
+    writeln!(out, "{:<11}  REALIZATION", "STATE")?;
+    for (state, realization) in &rows {
+        writeln!(out, "{state:<11}  {realization}")?;
+    }
+
+    let mut counts = Vec::new();
+    for state in STATES {
+        let count = rows.iter().filter(|(s, _)| *s == state).count();
+        if count > 0 {
+            counts.push(format!("{count} {state}"));
+        }
+    }
+    let mut tasks: Vec<AbstractTaskId> = affected.iter().map(|key| key.id).collect();
+    tasks.dedup();
+    writeln!(
+        out,
+        "\n{} realizations of {} tasks: {}",
+        rows.len(),
+        tasks.len(),
+        counts.join(", ")
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use intern::{GetStr, InternStr};
+    use std::path::Path;
+
+    fn names(wf: &Workflow, keys: &[RealTaskKey]) -> Result<Vec<String>> {
+        let mut names = Vec::with_capacity(keys.len());
+        for key in keys {
+            let mut branch = String::new();
+            wf.strings.make_compact_branch_string(&key.branch, &mut branch)?;
+            names.push(format!("{}[{branch}]", wf.strings.tasks.get(key.id)?));
+        }
+        Ok(names)
+    }
+
+    #[test]
+    fn test_find_affected() -> Result<()> {
+        let text = "task build > out :: os=(Os: mac win) { touch $out }\n\
+                    task test < in=$out@build :: p=(Profile: debug release) { cat $in }\n\
+                    task package < in=$out@build[Os: win] { cat $in }\n\
+                    task docs { echo docs }\n";
+        let mut wf = Workflow::default();
+        wf.load(syntax::parse(text)?, Path::new("."))?;
+        let build = wf.strings.tasks.intern("build")?;
+        let docs = wf.strings.tasks.intern("docs")?;
+
+        let all = find_affected(&wf, &[build], &[BranchSpec::default()])?;
+        assert_eq!(
+            names(&wf, &all)?,
+            vec![
+                "build[Baseline.baseline]",
+                "build[Os.win]",
+                "test[Baseline.baseline]",
+                "test[Baseline.baseline+Os.win]",
+                "test[Baseline.baseline+Profile.release]",
+                "test[Os.win+Profile.release]",
+                "package[Baseline.baseline]",
+            ]
+        );
+
+        // the graft only uses build's outputs on Os.win:
+        let os = wf.strings.branchpoints.intern("Os")?;
+        let mac = wf.strings.idents.intern("mac")?;
+        let mac = find_affected(&wf, &[build], &[BranchSpec::simple(os, mac)])?;
+        assert_eq!(
+            names(&wf, &mac)?,
+            vec![
+                "build[Baseline.baseline]",
+                "test[Baseline.baseline]",
+                "test[Baseline.baseline+Profile.release]",
+            ]
+        );
+
+        let docs = find_affected(&wf, &[docs], &[BranchSpec::default()])?;
+        assert_eq!(names(&wf, &docs)?, vec!["docs[Baseline.baseline]"]);
+        Ok(())
+    }
+}
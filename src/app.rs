@@ -1,28 +1,87 @@
-use std::path::PathBuf;
+use std::cell::RefCell;
+use std::io::Write;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use anyhow::{Context, Result};
 use colored::Colorize;
+use serde::Serialize;
 
 use intern::{GetStr, InternStr, TypedInterner};
 use syntax::{self, ast};
 use traverse::Traversal;
-use workflow::{BranchSpec, Plan, Workflow};
+use util::{
+    format_size, glob_match, Digest, Digester, DynBitmask, HashSet, PathEncodingError, Timer,
+};
+use workflow::{
+    BranchSpec, Errors, Plan, RealTaskKey, TaskRedefinition, Workflow, WorkflowStrings,
+    BRANCH_KV_DELIM,
+};
 
-use crate::exec::WorkflowRunner;
-use crate::fs::Fs;
+use crate::affected::{find_affected, write_affected};
+use crate::args::{Compat, ExportFormat, ListKind, OutputFormat, VizFormat};
+use crate::diff::{write_diff, write_invalidated, WorkflowDiff};
+use crate::exec::{
+    write_junit, ExecObserver, Notifier, TaskReport, TestCase, Trace, Watchdog, WorkflowRunner,
+};
+use crate::exit_code::{NothingToRun, RunFailed};
+use crate::explain::write_explanation;
+use crate::export::{write_export, write_workflow_export};
+use crate::fs::{
+    Artifact, BranchpointsFile, CachedTraversal, CancelRequest, Estimates, Fs, RealizationLocator,
+    LATEST_LINK, REALIZATIONS_DIR,
+};
+use crate::gc::GarbageCollector;
+use crate::history::{latest_run, runs, write_history};
 use crate::invalidate::Invalidator;
-use crate::prep::{PreRunner, TraversalResolver};
-use crate::settings::{ArgsBranch, Settings};
-use crate::ui::Ui;
+use crate::list::write_listing;
+use crate::logs::{find_log_dir, write_log};
+use crate::prep::{
+    explain_dedup, ActionsReport, PreRunner, ResolverOptions, TaskRunnerBuilder, TraversalResolver,
+};
+use crate::run_log::{self, mirror_eprintln};
+use crate::serve::{self, Response};
+use crate::settings::{
+    is_glob, ArgsBranch, ConfirmPolicy, FmtAction, Locate, LogsQuery, Mode, Settings, TrashAction,
+};
+use crate::stats::write_stats;
+use crate::status::{write_status, StatusReport};
+use crate::ui::{print_timings, timed, Confirm, Ui};
+use crate::viz::Graph;
+use crate::watch::{ModuleDirs, SharedObserver, Watcher};
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
-    #[error("Nothing to run: no target specified with --plan or --task")]
+    #[error("Nothing to run: no target specified with --plan, --task, or --produces")]
     NoTargetSpecified,
-    #[error("Multiple branches on command line are not yet supported")]
-    MultiBranch,
-    #[error("Too many branchpoints; maximum supported is 128")]
-    TooManyBranchpoints,
+    #[error("Config file couldn't be parsed, but no syntax errors were reported")]
+    NoSyntaxErrors,
+    #[error("Incomplete realizations would be deleted, but --no-delete was specified")]
+    DeleteRefused,
+    #[error("Realization dir {0} does not exist; run the task before opening a shell in it")]
+    NoRealizationForShell(String),
+    #[error("Realization dir {0} does not exist; has the task run?")]
+    NoRealizationForLogs(String),
+    #[error("No trash batch named {0} (run 'hr trash' to list them)")]
+    NoSuchTrashBatch(String),
+    #[error("Check failed: {0} of {1} targets have errors")]
+    CheckFailed(usize, usize),
+    #[error("{0} config file(s) aren't formatted; run 'hr fmt' to format them")]
+    Unformatted(usize),
+    #[error("No tasks match '{0}'")]
+    NoMatchingTasks(String),
+    #[error("'hr {0}' needs a single task, but '{1}' matches {2}")]
+    MultiTask(&'static str, String, String),
+    #[error("Task '{1}' given to --{0} isn't part of the target{2}")]
+    BoundNotInTarget(&'static str, String, String),
+    #[error("No task produces '{0}'{1}")]
+    NoProducer(String, String),
+    #[error("'{0}' is produced by several tasks ({1}); give its path in the output dir instead")]
+    AmbiguousProducer(String, String),
+    #[error("Task '{0}' has no output '{1}'{2}")]
+    NoSuchOutput(String, String, String),
 }
 
 /// This struct actually runs the command-line app.
@@ -33,57 +92,155 @@ pub struct App {
     fs: Fs,
     /// User interface
     ui: Ui,
+    /// Told about the progress of the workflow, if it runs
+    observers: Vec<Box<dyn ExecObserver>>,
+    /// With 'hr watch', told which module dirs the workflow uses
+    module_dirs: Option<ModuleDirs>,
 }
 
 impl App {
     /// Create a new `App`.
     pub fn new(settings: Settings) -> Self {
-        let fs = Fs::new(&settings.output, settings.dry_run);
+        let mut fs = Fs::new(&settings.output, settings.dry_run);
+        fs.set_short_names(settings.short_names);
         let ui = Ui::new(&settings);
-        Self { settings, fs, ui }
+        Self {
+            settings,
+            fs,
+            ui,
+            observers: Vec::with_capacity(0),
+            module_dirs: None,
+        }
+    }
+
+    /// Tell `observer` about each task as it starts and finishes, if the app runs a workflow.
+    pub fn add_observer(&mut self, observer: Box<dyn ExecObserver>) {
+        self.observers.push(observer);
     }
 
     /// Run the app, using settings to determine which task to run.
     pub fn run(mut self) -> Result<()> {
+        match self.settings.mode.clone() {
+            // these don't create (or otherwise modify) the output dir:
+            Mode::Check => self.check_workflow(),
+            Mode::Fmt(action) => self.format_configs(action),
+            Mode::Diff => self.diff(),
+            Mode::Status => self.status(),
+            Mode::Stats(memory) => self.stats(memory),
+            Mode::Affected => self.affected(),
+            Mode::Explain(task) => self.explain(&task),
+            Mode::Locate(locate) => self.locate(locate),
+            Mode::Logs(query) => self.logs(query),
+            Mode::CatScript => self.cat_script(),
+            Mode::List(kind) => self.list(kind),
+            Mode::History(query) => write_history(&self.fs, query, &mut std::io::stdout().lock()),
+            Mode::Viz(format) => self.visualize(format),
+            Mode::Export(format) if !format.is_prepared() => self.export_workflow(format),
+            Mode::Serve(addr) => self.serve(&addr),
+            Mode::Watch => self.watch(),
+
+            Mode::RepairBranchpoints => {
+                let branch_file = self.prepare_output_dir()?;
+                let mut strbuf = String::with_capacity(0);
+                self.repair_branchpoints(&branch_file, &mut strbuf, &mut Workflow::default())
+            }
+            Mode::Run | Mode::Export(_) => {
+                let (branch_file, mut strbuf, wf) = self.open_output_dir()?;
+                let (timer, verbose) = (self.ui.timer(), self.ui.verbose);
+                let result = self.run_workflow(&mut strbuf, wf, &branch_file);
+                if verbose {
+                    print_timings(&timer.borrow());
+                }
+                result
+            }
+            Mode::Invalidate => {
+                let (_, mut strbuf, mut wf) = self.open_output_dir()?;
+                if !self.settings.dry_run {
+                    self.fs.acquire_lock(self.settings.force_unlock)?;
+                }
+                if self.settings.downstream || self.settings.has_task_patterns() {
+                    // we need the workflow graph to find dependents, or the tasks a pattern matches:
+                    self.parse_workflow(&mut strbuf, &mut wf)?;
+                }
+                let invalidator = Invalidator::new(&self.settings, &self.ui, &self.fs);
+                invalidator.invalidate(&mut wf)
+            }
+            Mode::Gc => {
+                let (_, mut strbuf, mut wf) = self.open_output_dir()?;
+                self.parse_workflow(&mut strbuf, &mut wf)?;
+                if !self.settings.dry_run {
+                    self.fs.acquire_lock(self.settings.force_unlock)?;
+                }
+                let live = self.live_realizations(&mut wf)?;
+                let collector = GarbageCollector::new(&self.settings, &self.ui, &self.fs);
+                collector.collect(&mut wf, live.as_ref())
+            }
+            Mode::Trash(action) => {
+                self.open_output_dir()?;
+                if !self.settings.dry_run {
+                    self.fs.acquire_lock(self.settings.force_unlock)?;
+                }
+                self.manage_trash(&action)
+            }
+            Mode::Cancel(request) => {
+                let (mut branch_file, mut strbuf, mut wf) = self.open_output_dir()?;
+                if !request.tasks.is_empty() {
+                    // make sure the tasks exist, so a typo doesn't go unnoticed:
+                    self.parse_workflow(&mut strbuf, &mut wf)?;
+                    for task in &request.tasks {
+                        let task = wf.strings.tasks.intern(task)?;
+                        wf.get_task(task)?;
+                    }
+                }
+                self.request_cancel(&request, &mut branch_file)
+            }
+            Mode::Shell => {
+                let (_, mut strbuf, mut wf) = self.open_output_dir()?;
+                self.parse_workflow(&mut strbuf, &mut wf)?;
+                let traversal = self.make_traversal(&mut wf)?;
+                self.open_shell(wf, traversal)
+            }
+        }
+    }
+
+    /// Create the output dir if it doesn't exist, and enable the caches;
+    /// returns the path of its branchpoints.txt file.
+    fn prepare_output_dir(&mut self) -> Result<PathBuf> {
         if self.settings.verbose > 0 {
-            eprintln!("Using output directory {:?}", self.settings.output);
+            mirror_eprintln!("Using output directory {:?}", self.settings.output);
         }
         self.fs.ensure_out_dir_exists(self.settings.verbose > 0)?;
-
+        if let Some(cache) = &self.settings.cache {
+            self.ui.verbose_msg(&format!("Using cache directory {cache:?}"));
+            self.fs.enable_cache(cache)?;
+        }
+        if let Some(url) = &self.settings.remote_cache {
+            self.ui.verbose_msg(&format!("Using remote cache {url}"));
+            self.fs.enable_remote_cache(url)?;
+        }
         let mut branch_file = PathBuf::with_capacity(512);
         self.fs.branchpoints_txt(&mut branch_file);
+        Ok(branch_file)
+    }
 
+    /// Prepare the output dir, and load its branchpoints.txt file into a new workflow,
+    /// so that branch ordering is consistent between runs.
+    /// Returns the path of branchpoints.txt, a buffer for reading configs, and the workflow.
+    fn open_output_dir(&mut self) -> Result<(PathBuf, String, Workflow)> {
+        let branch_file = self.prepare_output_dir()?;
         let mut strbuf = String::with_capacity(0); // will be resized later.
-
         let mut wf = Workflow::default();
-
-        // load branch file into wf first (if it exists),
-        // so that branch ordering is consistent between runs:
         self.fs.load_branches(&branch_file, &mut wf, &mut strbuf, &self.ui)?;
-
-        if self.settings.invalidate {
-            let invalidator = Invalidator::new(&self.settings, &self.ui, &self.fs);
-            invalidator.invalidate(&mut wf)?;
-        }
-
-        if self.settings.run {
-            self.parse_workflow(&mut strbuf, &mut wf)?;
-
-            if !self.settings.dry_run {
-                log::info!("writing branchpoints.txt file");
-                self.fs.write_branches(&branch_file, &wf, &mut strbuf)?;
-            }
-
-            let traversal = self.make_traversal(&mut wf)?;
-            self.run_traversal(wf, traversal)?;
-        }
-
-        Ok(())
+        Ok((branch_file, strbuf, wf))
     }
 
     fn make_traversal(&self, wf: &mut Workflow) -> Result<Traversal> {
-        let plan = self.get_target_for_run(wf)?;
+        let mut plan = self.get_target_for_run(wf)?;
+        plan.apply_baselines(&mut wf.strings);
+        self.create_traversal(wf, plan)
+    }
 
+    fn create_traversal(&self, wf: &mut Workflow, plan: Plan) -> Result<Traversal> {
         wf.strings.alloc_for_traversal();
         self.ui.verbose_progress("Creating traversal");
         let traversal = match wf.strings.branchpoints.len() {
@@ -92,9 +249,13 @@ impl App {
             x if x <= 32 => Traversal::create::<u32>(wf, plan)?,
             x if x <= 64 => Traversal::create::<u64>(wf, plan)?,
             x if x <= 128 => Traversal::create::<u128>(wf, plan)?,
-            _ => return Err(Error::TooManyBranchpoints.into()),
+            _ => Traversal::create::<DynBitmask>(wf, plan)?,
         };
         self.ui.done();
+        self.ui.verbose_msg(&traversal.stats.to_string());
+        self.ui.add_phase("bfs", traversal.stats.bfs);
+        self.ui.add_phase("cleanup", traversal.stats.cleanup);
+        self.ui.add_phase("sort", traversal.stats.sort);
 
         log::debug!(
             "Traversal has {} inputs and {} outputs/params.",
@@ -109,19 +270,53 @@ impl App {
 // PARSING //////////////////
 impl App {
     fn parse_workflow(&mut self, strbuf: &mut String, wf: &mut Workflow) -> Result<()> {
-        self.read_config_to_buf(strbuf)?;
-        let blocks = self.parse_config(&*strbuf)?;
+        let layer_bufs = self.read_configs(strbuf)?;
+        self.load_workflow(strbuf, &layer_bufs, wf)?;
+        Ok(())
+    }
 
-        self.ui.verbose_progress("Creating workflow");
-        self.ui.start_timer();
+    /// Read the first config file into `strbuf`, and return any layered over it in their own bufs.
+    fn read_configs(&mut self, strbuf: &mut String) -> Result<Vec<String>> {
+        let configs = self.settings.configs.clone();
+        let mut layer_bufs = vec![String::new(); configs.len().saturating_sub(1)];
+        self.read_config_to_buf(&configs[0], strbuf)?;
+        for (config, buf) in configs[1..].iter().zip(&mut layer_bufs) {
+            self.read_config_to_buf(config, buf)?;
+        }
+        Ok(layer_bufs)
+    }
 
-        wf.load(blocks, self.settings.config_parent_dir()?)?;
+    /// Parse the config files read by `read_configs` and load them into `wf`,
+    /// returning the tasks that later config files redefine.
+    fn load_workflow(
+        &mut self,
+        text: &str,
+        layer_texts: &[String],
+        wf: &mut Workflow,
+    ) -> Result<Vec<TaskRedefinition>> {
+        let configs = self.settings.configs.clone();
+        let texts = std::iter::once(text).chain(layer_texts.iter().map(String::as_str));
+        let mut layers = Vec::with_capacity(configs.len());
+        for (config, text) in configs.iter().zip(texts) {
+            let blocks = self.parse_config(text, config, &wf.strings)?;
+            layers.push((blocks, Settings::config_parent_dir(config)?));
+        }
 
+        self.ui.verbose_progress("Creating workflow");
+        let redefinitions = self.ui.phase("load", || wf.load_layers(layers))?;
         self.ui.done();
-        self.ui.print_elapsed("Creating workflow")?;
+
+        self.warn_redefinitions(wf, &redefinitions)?;
+        for (k, v) in &self.settings.defines {
+            if wf.override_config_value(k, v)? {
+                self.ui.verbose_msg(&format!("Overriding config value {k}={v:?}"));
+            } else {
+                self.ui.verbose_msg(&format!("Defining config value {k}={v:?}"));
+            }
+        }
 
         if self.settings.verbose > 0 {
-            eprintln!(
+            mirror_eprintln!(
                 "Created workflow with {} tasks and {} branchpoints.",
                 wf.strings.tasks.len(),
                 wf.strings.branchpoints.len()
@@ -129,41 +324,362 @@ impl App {
             wf.strings.log_sizes();
         }
 
+        self.expand_task_patterns(wf)?;
+        Ok(redefinitions)
+    }
+
+    fn warn_redefinitions(&self, wf: &Workflow, redefinitions: &[TaskRedefinition]) -> Result<()> {
+        let configs = &self.settings.configs;
+        for r in redefinitions {
+            mirror_eprintln!(
+                "{} task {} in {:?} replaces the one in {:?}",
+                "WARNING".yellow(),
+                wf.strings.tasks.get(r.task)?,
+                configs[r.layer],
+                configs[r.first_layer],
+            );
+        }
+        Ok(())
+    }
+
+    /// Replace target task globs and regexes with the names of the tasks they match,
+    /// which is an error if they don't match any.
+    fn expand_task_patterns(&mut self, wf: &Workflow) -> Result<()> {
+        if !self.settings.has_task_patterns() {
+            return Ok(());
+        }
+        let mut tasks: Vec<String> = Vec::with_capacity(self.settings.tasks.len());
+        let mut add = |pattern: &str, matches: Vec<&str>| {
+            if matches.is_empty() {
+                return Err(Error::NoMatchingTasks(pattern.to_owned()));
+            }
+            for task in matches {
+                if !tasks.iter().any(|t| t == task) {
+                    tasks.push(task.to_owned());
+                }
+            }
+            Ok(())
+        };
+        for task in &self.settings.tasks {
+            if is_glob(task) {
+                add(
+                    task,
+                    wf.task_names().filter(|t| glob_match(task, t)).collect(),
+                )?;
+            } else {
+                add(task, vec![task])?;
+            }
+        }
+        for re in std::mem::take(&mut self.settings.task_regexes) {
+            add(
+                re.as_str(),
+                wf.task_names().filter(|t| re.is_match(t)).collect(),
+            )?;
+        }
+        if let Some(cmd) = self.settings.mode.single_realization().filter(|_| tasks.len() > 1) {
+            let pattern = self.settings.tasks.join(", ");
+            return Err(Error::MultiTask(cmd, pattern, tasks.join(", ")).into());
+        }
+        self.ui.verbose_msg(&format!("Targeting tasks {}", tasks.join(", ")));
+        self.settings.tasks = tasks;
         Ok(())
     }
 
-    fn read_config_to_buf(&mut self, strbuf: &mut String) -> Result<()> {
-        self.ui.verbose_progress_debug("Reading config file", &self.settings.config);
+    fn read_config_to_buf(&mut self, config: &Path, strbuf: &mut String) -> Result<()> {
+        self.ui.verbose_progress_debug("Reading config file", config);
         self.fs
-            .read_to_buf(&self.settings.config, strbuf)
-            .with_context(|| format!("while reading config file \"{:?}\"", self.settings.config))?;
+            .read_to_buf(config, strbuf)
+            .with_context(|| format!("while reading config file \"{config:?}\""))?;
         self.ui.done();
         Ok(())
     }
 
-    fn parse_config<'a>(&mut self, text: &'a str) -> Result<Vec<ast::Item<'a>>> {
+    fn parse_config<'a>(
+        &mut self,
+        text: &'a str,
+        config: &Path,
+        strings: &WorkflowStrings,
+    ) -> Result<Vec<ast::Item<'a>>> {
         self.ui.verbose_progress("Parsing config file");
-        self.ui.start_timer();
-        let blocks = syntax::parse(text)
-            .with_context(|| format!("while parsing config file \"{:?}\"", self.settings.config))?;
+        let name = config.file_name().unwrap_or(config.as_os_str()).to_string_lossy();
+        let parsed = self.ui.phase(&format!("parse {name}"), || match self.settings.compat {
+            None => syntax::parse_file(text, config.to_str()).map(|blocks| (blocks, Vec::new())),
+            Some(Compat::Ducttape) => syntax::parse_ducttape(text, config.to_str()),
+        });
+        let (blocks, unsupported) = match parsed {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                // list every syntax error, not just the first:
+                let e = match e.downcast::<syntax::ParseErrors>() {
+                    Ok(syntax_errors) => {
+                        let mut errors = Errors::default();
+                        for e in syntax_errors.0 {
+                            errors.add(e.into());
+                        }
+                        match errors.print_recap("parsing config file", strings) {
+                            Err(e) => e,
+                            Ok(()) => Error::NoSyntaxErrors.into(),
+                        }
+                    }
+                    Err(e) => e,
+                };
+                return Err(e.context(format!("while parsing config file \"{config:?}\"")));
+            }
+        };
         self.ui.done();
-        self.ui.print_elapsed("Parsing config file")?;
+        for feature in unsupported {
+            mirror_eprintln!("{} {feature}\n", "WARNING".yellow());
+        }
         Ok(blocks)
     }
 }
 
+/// Summary of the actions and results of a run, for '--output-format json'.
+#[derive(Debug, Default, Serialize)]
+struct RunReport {
+    succeeded: bool,
+    /// why the run failed, if it did (and not just because there was nothing to run)
+    error: Option<String>,
+    /// completed/delete/checkout/run lists, once the traversal has been resolved
+    actions: Option<ActionsReport>,
+    /// state, realization dir, and duration of each task that was supposed to run
+    tasks: Vec<TaskReport>,
+}
+
 // RUNNING /////////////////
 impl App {
-    fn run_traversal(mut self, mut wf: Workflow, traversal: Traversal) -> Result<()> {
+    /// Load the workflow and run the target, sending a trace of the whole run
+    /// to the OTLP endpoint (if there is one), whether or not it succeeds.
+    fn run_workflow(self, strbuf: &mut String, wf: Workflow, branch_file: &Path) -> Result<()> {
+        let Some(endpoint) = self.settings.otlp_endpoint.clone() else {
+            return self.parse_and_run(strbuf, wf, branch_file, None);
+        };
+        let mut trace = Trace::new("hr run");
+        let root = trace.root();
+        trace.set_attr(root, "hr.plans", self.describe_targets());
+        trace.set_attr(root, "hr.branch", self.settings.branches.describe());
+        let result = self.parse_and_run(strbuf, wf, branch_file, Some(&mut trace));
+        trace.end(root, result.as_ref().err().map(|e| format!("{e:#}")));
+        if let Err(e) = trace.export(&endpoint) {
+            log::warn!("unable to export trace: {e:?}");
+        }
+        result
+    }
+
+    fn parse_and_run(
+        mut self,
+        strbuf: &mut String,
+        mut wf: Workflow,
+        branch_file: &Path,
+        mut trace: Option<&mut Trace>,
+    ) -> Result<()> {
+        let timer = self.ui.timer();
+        let layer_bufs = self.read_configs(strbuf)?;
+        let cache_key = self.traversal_cache_key(strbuf, &layer_bufs, branch_file)?;
+        let (cached, redefinitions) = match cache_key.and_then(|key| self.read_traversal_cache(key))
+        {
+            Some(cached) => {
+                wf = cached.wf;
+                traced(&mut trace, &timer, "parse", || {
+                    self.warn_redefinitions(&wf, &cached.redefinitions)?;
+                    self.expand_task_patterns(&wf)
+                })?;
+                (Some(cached.traversal), cached.redefinitions)
+            }
+            None => {
+                let redefinitions = traced(&mut trace, &timer, "parse", || {
+                    self.load_workflow(strbuf, &layer_bufs, &mut wf)
+                })?;
+                (None, redefinitions)
+            }
+        };
+        if self.settings.warnings {
+            warn_unused(&wf)?;
+        }
+
+        // a cached workflow was loaded from the same branchpoints.txt it would write:
+        if cached.is_none() && !self.settings.dry_run {
+            log::info!("writing branchpoints.txt file");
+            self.fs.write_branches(branch_file, &wf)?;
+        }
+
+        let (traversal, assumed) = traced(&mut trace, &timer, "traverse", || {
+            let traversal = match cached {
+                Some(traversal) => traversal,
+                None => {
+                    let traversal = self.make_traversal(&mut wf)?;
+                    if let Some(key) = cache_key {
+                        self.write_traversal_cache(key, &wf, &traversal, &redefinitions);
+                    }
+                    traversal
+                }
+            };
+            self.apply_bounds(&mut wf, traversal)
+        })?;
+        self.run_traversal(wf, traversal, assumed, trace)
+    }
+
+    /// Digest of everything the workflow and traversal of a run depend on: the config files,
+    /// branchpoints.txt, the environment variables the config files use, and the target.
+    /// None if they shouldn't be cached.
+    fn traversal_cache_key(
+        &self,
+        text: &str,
+        layer_texts: &[String],
+        branch_file: &Path,
+    ) -> Result<Option<Digest>> {
+        // outputs given by path are looked up in the output dir, which the key doesn't cover:
+        if !self.settings.traversal_cache || !self.settings.produces.is_empty() {
+            return Ok(None);
+        }
+        let mut digester = Digester::default();
+        let update_len = |digester: &mut Digester, len: usize| {
+            digester.update(&(len as u64).to_le_bytes());
+        };
+        digester.update_str(env!("CARGO_PKG_VERSION"));
+
+        let configs = &self.settings.configs;
+        let texts = std::iter::once(text).chain(layer_texts.iter().map(String::as_str));
+        update_len(&mut digester, configs.len());
+        for (config, text) in configs.iter().zip(texts) {
+            digester.update_str(config.to_str().ok_or(PathEncodingError)?);
+            digester.update_str(text);
+            for name in workflow::env_var_names(text) {
+                digester.update_str(name);
+                digester.update_str(&std::env::var(name).unwrap_or_default());
+            }
+        }
+
+        let mut branchpoints = String::new();
+        if self.fs.exists(branch_file) {
+            self.fs.read_to_buf(branch_file, &mut branchpoints)?;
+        }
+        digester.update_str(&branchpoints);
+
+        digester.update_str(&format!("{:?}", self.settings.compat));
+        update_len(&mut digester, self.settings.defines.len());
+        for (k, v) in &self.settings.defines {
+            digester.update_str(k);
+            digester.update_str(v);
+        }
+        for list in [&self.settings.plans, &self.settings.tasks] {
+            update_len(&mut digester, list.len());
+            for item in list {
+                digester.update_str(item);
+            }
+        }
+        update_len(&mut digester, self.settings.task_regexes.len());
+        for re in &self.settings.task_regexes {
+            digester.update_str(re.as_str());
+        }
+        digester.update_str(&self.settings.branches.describe());
+        Ok(Some(digester.finish()))
+    }
+
+    /// The workflow and traversal cached with `key`, if there are any.
+    /// Failing to read the cache just means loading and traversing the workflow again.
+    fn read_traversal_cache(&self, key: Digest) -> Option<CachedTraversal> {
+        match self.fs.read_traversal_cache(key) {
+            Ok(Some(cached)) => {
+                self.ui.verbose_msg("Reusing the workflow and traversal cached by the last run");
+                self.ui.verbose_msg(&cached.traversal.stats.to_string());
+                Some(cached)
+            }
+            Ok(None) => None,
+            Err(e) => {
+                log::warn!("unable to read traversal cache: {e:?}");
+                None
+            }
+        }
+    }
+
+    fn write_traversal_cache(
+        &self,
+        key: Digest,
+        wf: &Workflow,
+        traversal: &Traversal,
+        redefinitions: &[TaskRedefinition],
+    ) {
+        if self.settings.dry_run {
+            return;
+        }
+        if let Err(e) = self.fs.write_traversal_cache(key, wf, traversal, redefinitions) {
+            log::warn!("unable to write traversal cache: {e:?}");
+        }
+    }
+
+    fn run_traversal(
+        self,
+        wf: Workflow,
+        traversal: Traversal,
+        assumed: HashSet<RealTaskKey>,
+        trace: Option<&mut Trace>,
+    ) -> Result<()> {
+        if self.settings.output_format == OutputFormat::Text {
+            return self.prepare_and_run(wf, traversal, assumed, None, trace);
+        }
+        // report whatever we got to on stdout, even if the run failed:
+        let mut report = RunReport::default();
+        let result = self.prepare_and_run(wf, traversal, assumed, Some(&mut report), trace);
+        report.error = match &result {
+            Err(e) if !e.is::<NothingToRun>() => Some(format!("{e:#}")),
+            _ => None,
+        };
+        report.succeeded = report.error.is_none();
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        result
+    }
+
+    fn prepare_and_run(
+        mut self,
+        mut wf: Workflow,
+        traversal: Traversal,
+        assumed: HashSet<RealTaskKey>,
+        mut report: Option<&mut RunReport>,
+        mut trace: Option<&mut Trace>,
+    ) -> Result<()> {
+        let started = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let timer = self.ui.timer();
+
         // allocate space for run strs:
         wf.strings.alloc_for_run();
 
+        // make sure no other run modifies the output dir between
+        // deciding what to delete/run and finishing the run:
+        if !self.settings.dry_run {
+            self.fs.acquire_lock(self.settings.force_unlock)?;
+            // no other run can be going now, so a cancel request is left over from an earlier one.
+            // (one made from here on, e.g. while we wait for confirmation, is for this run.)
+            self.fs.clear_cancel_request(&mut PathBuf::with_capacity(128))?;
+            match run_log::start(&self.fs, self.settings.keep_run_logs) {
+                Ok(Some(path)) => self.ui.verbose_msg(&format!("Logging run to {path:?}")),
+                Ok(None) => (),
+                Err(e) => log::warn!("unable to start run log: {e:?}"),
+            }
+        }
+
         // ensure no destructive operations on fs:
         self.fs.set_dry_run(true);
 
+        if self.settings.explain_dedup {
+            explain_dedup(&traversal.nodes, &wf)?;
+        }
+
         // resolve traversal into completed/delete/run actions:
-        let mut resolver = TraversalResolver::new(traversal.nodes.len(), &self.fs, &mut wf);
-        let actions = resolver.resolve_to_actions(traversal)?;
+        let mut resolver = TraversalResolver::new(
+            traversal.nodes.len(),
+            &self.fs,
+            &mut wf,
+            self.resolver_options(),
+        );
+        resolver.set_assumed_complete(assumed);
+        let mut actions = traced(&mut trace, &timer, "resolve", || {
+            resolver.resolve_to_actions(traversal)
+        })?;
+
+        if let Some(module_dirs) = &self.module_dirs {
+            *module_dirs.borrow_mut() = actions.module_dirs().map(Path::to_owned).collect();
+        }
 
         log::debug!(
             "{} Run strs, str len {}",
@@ -171,54 +687,1051 @@ impl App {
             wf.strings.run.str_len()
         );
 
+        if let Some(report) = report.as_deref_mut() {
+            let pre_runner = PreRunner::new(&self.fs, &wf, self.settings.verbose > 0);
+            report.actions = Some(pre_runner.actions_report(&actions)?);
+        }
+
         if !actions.has_tasks_to_run() {
-            eprintln!("{}", "No tasks to run; exiting.".green());
+            mirror_eprintln!("{}", "No tasks to run; exiting.".green());
+            if let (Some(junit), false) = (&self.settings.junit, self.settings.dry_run) {
+                write_junit_file(&self.fs, junit, &[])?;
+            }
+            if let (Mode::Export(format), false) = (&self.settings.mode, self.settings.dry_run) {
+                write_export(*format, &[], &wf.strings.run, &mut std::io::stdout().lock())?;
+            }
+            if self.settings.non_interactive {
+                return Err(NothingToRun.into());
+            }
             return Ok(());
         }
 
         // allow destructive fs operations again:
         self.fs.set_dry_run(false);
 
+        let estimates = self.fs.estimates().unwrap_or_else(|e| {
+            log::info!("unable to estimate task durations from run history: {e:?}");
+            Estimates::default()
+        });
+
         // print summary of actions and confirm w/ user:
         let mut pre_runner = PreRunner::new(&self.fs, &wf, self.settings.verbose > 0);
-        pre_runner.print_actions(&actions)?;
-        if self.settings.dry_run || !self.ui.confirm("Proceed?")? {
+        pre_runner.set_estimates(estimates.clone());
+        pre_runner.set_full(self.settings.full);
+        let mut summary = Vec::with_capacity(4096);
+        pre_runner.write_actions(&actions, &mut summary)?;
+        self.ui.page(&String::from_utf8_lossy(&summary))?;
+        if self.settings.dry_run {
             return Ok(());
         }
+        if actions.has_tasks_to_delete() && self.settings.confirm.delete == ConfirmPolicy::No {
+            return Err(Error::DeleteRefused.into());
+        }
+        if self.settings.interactive {
+            // the user confirms each action instead of all of them at once:
+            if !pre_runner.select_actions(&mut actions, |prompt| self.ui.select(prompt))? {
+                return Ok(());
+            }
+            if let Some(report) = report.as_deref_mut() {
+                report.actions = Some(pre_runner.actions_report(&actions)?);
+            }
+            if !actions.has_tasks_to_run() {
+                mirror_eprintln!("{}", "No tasks selected to run; exiting.".green());
+                return Ok(());
+            }
+        } else {
+            if actions.has_tasks_to_delete()
+                && !self.ui.confirm("Delete incomplete realizations?", Confirm::Delete)?
+            {
+                return Ok(());
+            }
+            if !self.ui.confirm("Proceed?", Confirm::Run)? {
+                return Ok(());
+            }
+        }
 
         // delete old incomplete tasks and create new task dirs:
-        let tasks = pre_runner
-            .do_pre_run_actions(actions)
-            .context("while preparing output directory for workflow run")?;
+        let tasks = traced(&mut trace, &timer, "prepare", || {
+            pre_runner
+                .do_pre_run_actions(actions)
+                .context("while preparing output directory for workflow run")
+        })?;
 
-        eprintln!("\n{}.", "Workflow preparation complete".green());
-        eprintln!("\n{}.\n", "Starting workflow execution".magenta());
+        mirror_eprintln!("\n{}.", "Workflow preparation complete".green());
+
+        if let Mode::Export(format) = self.settings.mode {
+            write_export(
+                format,
+                &tasks,
+                &wf.strings.run,
+                &mut std::io::stdout().lock(),
+            )?;
+            mirror_eprintln!(
+                "{} {} task realizations; nothing was run.",
+                "Exported".green(),
+                tasks.len()
+            );
+            return Ok(());
+        }
+        mirror_eprintln!("\n{}.\n", "Starting workflow execution".magenta());
 
         // actually run the tasks:
         let run_strs = TypedInterner::new(wf.strings.run.into_inner().into());
-        let mut runner = WorkflowRunner::new(run_strs, self.fs, self.ui);
-        runner.run(tasks).context("while running workflow")?;
+        let watchdog = Watchdog {
+            idle_timeout: self.settings.idle_timeout.map(Duration::from_secs),
+            kill: self.settings.kill_idle,
+        };
+        let plans = self.describe_targets();
+        let mut runner = WorkflowRunner::new(run_strs, self.fs, self.ui, watchdog);
+        runner.set_stdout_to_stderr(report.is_some());
+        runner.set_estimates(estimates);
+        runner.set_jobs(self.settings.jobs);
+        for observer in self.observers {
+            runner.add_observer(observer);
+        }
+        if let Some(notify) = &self.settings.notify {
+            runner.add_observer(Box::new(Notifier::new(notify.command.clone(), notify.on)));
+        }
+        if let Some(metrics_file) = &self.settings.metrics_file {
+            runner.set_metrics_file(metrics_file.clone(), plans.clone(), started);
+        }
+        run_log::set_phase("execute");
+        let execute = trace.as_mut().map(|trace| trace.start(trace.root(), "execute"));
+        let result = timed(&timer, "execute", || runner.run(tasks));
+        if let (Some(trace), Some(execute)) = (trace, execute) {
+            runner.trace_tasks(trace, execute)?;
+            trace.end(execute, result.as_ref().err().map(|e| format!("{e:#}")));
+        }
+        if let Some(report) = report {
+            report.tasks = runner.results_report()?;
+        }
+        let branch = self.settings.branches.describe();
+        if let Err(e) = runner.record_history(plans, branch, started, result.is_ok()) {
+            log::warn!("unable to record run history: {e:?}");
+        }
+        runner.finish_metrics(result.is_ok());
+        let partial = runner.completed() > 0;
+        let result = result.context(RunFailed { partial });
+        if let Some(junit) = &self.settings.junit {
+            let written = runner
+                .junit_cases()
+                .and_then(|cases| write_junit_file(runner.fs(), junit, &cases));
+            match (written, &result) {
+                // a failed run is reported as such, even if the report about it can't be written:
+                (Err(e), Err(_)) => {
+                    mirror_eprintln!("{} Unable to write JUnit report: {e:#}", "WARNING".yellow())
+                }
+                (written, _) => written?,
+            }
+        }
+        result
+    }
+}
+
+impl App {
+    /// How to resolve traversals, from the settings.
+    fn resolver_options(&self) -> ResolverOptions {
+        ResolverOptions {
+            atomic: self.settings.atomic,
+            require_clean_modules: self.settings.require_clean_modules,
+            strict_vars: self.settings.strict_vars,
+        }
+    }
+
+    /// Plans (or tasks, if no plan was given) to run, comma-separated.
+    fn describe_targets(&self) -> String {
+        match &self.settings.plans[..] {
+            [] => {
+                let targets = self.settings.tasks.iter().chain(&self.settings.produces);
+                targets.map(String::as_str).collect::<Vec<_>>().join(",")
+            }
+            plans => plans.join(","),
+        }
+    }
+}
+
+/// Run `f` as a phase called `name` in `timer`, also recording it as a span
+/// under the root of `trace`, if there is one.
+fn traced<T>(
+    trace: &mut Option<&mut Trace>,
+    timer: &RefCell<Timer>,
+    name: &str,
+    f: impl FnOnce() -> Result<T>,
+) -> Result<T> {
+    run_log::set_phase(name);
+    let Some(trace) = trace else {
+        return timed(timer, name, f);
+    };
+    let span = trace.start(trace.root(), name);
+    let result = timed(timer, name, f);
+    trace.end(span, result.as_ref().err().map(|e| format!("{e:#}")));
+    result
+}
+
+/// Write a JUnit XML report of a run's tasks to `path`, for '--junit'.
+fn write_junit_file(fs: &Fs, path: &Path, cases: &[TestCase]) -> Result<()> {
+    let mut out = Vec::with_capacity(1024);
+    write_junit(cases, &mut out)?;
+    fs.write_report(path, std::str::from_utf8(&out)?)
+        .context("while writing JUnit report")
+}
+
+// CHECKING ///////////////
+impl App {
+    /// Load the workflow and resolve the traversal of every plan (or the targets given on the
+    /// command line) as if nothing had run yet, reporting all errors without running anything.
+    fn check_workflow(&mut self) -> Result<()> {
+        self.fs.set_dry_run(true);
+        let mut branch_file = PathBuf::with_capacity(512);
+        self.fs.branchpoints_txt(&mut branch_file);
+        let mut strbuf = String::with_capacity(0);
+        let mut wf = Workflow::default();
+        self.fs.load_branches(&branch_file, &mut wf, &mut strbuf, &self.ui)?;
+        self.parse_workflow(&mut strbuf, &mut wf)?;
+        warn_unused(&wf)?;
+
+        let targets = if self.settings.plans.is_empty() && self.settings.tasks.is_empty() {
+            let mut targets = Vec::new();
+            for (name, plan) in wf.plans() {
+                let name = wf.strings.idents.get(name)?;
+                targets.push((format!("plan {name}"), plan.clone()));
+            }
+            targets
+        } else {
+            vec![("targets".to_owned(), self.get_target_for_run(&mut wf)?)]
+        };
+        if targets.is_empty() {
+            mirror_eprintln!("No plans defined; only checked the config file itself.");
+        }
+
+        wf.strings.alloc_for_run();
+        let mut failed = 0;
+        for (label, plan) in &targets {
+            let mut plan = plan.clone();
+            plan.apply_baselines(&mut wf.strings);
+            let result = self.create_traversal(&mut wf, plan).and_then(|traversal| {
+                let mut resolver = TraversalResolver::new(
+                    traversal.nodes.len(),
+                    &self.fs,
+                    &mut wf,
+                    self.resolver_options(),
+                );
+                resolver.check(traversal)
+            });
+            match result {
+                Ok(()) => mirror_eprintln!("{} {label}", "OK".green()),
+                Err(e) => {
+                    mirror_eprintln!("{} {label}: {e:?}\n", "FAILED".red());
+                    failed += 1;
+                }
+            }
+        }
+        if failed > 0 {
+            return Err(Error::CheckFailed(failed, targets.len()).into());
+        }
+        mirror_eprintln!("{}", "No problems found.".green());
+        Ok(())
+    }
+}
+
+// FORMATTING ///////////////
+impl App {
+    /// Rewrite each config file in canonical format (see [`syntax::format`]),
+    /// or just list the ones that aren't, with `FmtAction::Check` or '-n'.
+    fn format_configs(&self, action: FmtAction) -> Result<()> {
+        let check = action == FmtAction::Check || self.settings.dry_run;
+        let mut unformatted = 0;
+        for config in &self.settings.configs {
+            let text = std::fs::read_to_string(config)
+                .with_context(|| format!("while reading config file {config:?}"))?;
+            let formatted = syntax::format(&text, config.to_str())?;
+            if formatted == text {
+                self.ui.verbose_msg(&format!("{} is already formatted", config.display()));
+            } else if check {
+                mirror_eprintln!("{} {}", "Would reformat".yellow(), config.display());
+                unformatted += 1;
+            } else {
+                std::fs::write(config, formatted)
+                    .with_context(|| format!("while writing config file {config:?}"))?;
+                mirror_eprintln!("{} {}", "Formatted".green(), config.display());
+            }
+        }
+        if action == FmtAction::Check && unformatted > 0 {
+            return Err(Error::Unformatted(unformatted).into());
+        }
+        Ok(())
+    }
+}
+
+// STATUS / LISTING / VISUALIZING ////
+impl App {
+    /// Load the workflow without touching the output dir
+    /// (except to read the branches known from earlier runs).
+    fn load_workflow_read_only(&mut self, wf: &mut Workflow) -> Result<()> {
+        self.fs.set_dry_run(true);
+        let mut branch_file = PathBuf::with_capacity(512);
+        self.fs.branchpoints_txt(&mut branch_file);
+        let mut strbuf = String::with_capacity(0);
+        self.fs.load_branches(&branch_file, wf, &mut strbuf, &self.ui)?;
+        self.parse_workflow(&mut strbuf, wf)
+    }
+
+    /// Print the state of every realization in the traversal of the target plan or tasks
+    /// to stdout, without modifying the output dir.
+    fn status(&mut self) -> Result<()> {
+        let mut wf = Workflow::default();
+        self.load_workflow_read_only(&mut wf)?;
+        let traversal = self.make_traversal(&mut wf)?;
+        wf.strings.alloc_for_run();
+        let mut resolver = TraversalResolver::new(
+            traversal.nodes.len(),
+            &self.fs,
+            &mut wf,
+            self.resolver_options(),
+        );
+        let states = resolver.resolve_to_states(traversal)?;
+        write_status(&states, &wf, &self.fs, &mut std::io::stdout().lock())
+    }
+
+    /// Print the size of the traversal of the target plan or tasks to stdout, and with `memory`,
+    /// the memory used by the interners, string caches, and traversal,
+    /// without modifying the output dir.
+    fn stats(&mut self, memory: bool) -> Result<()> {
+        let mut wf = Workflow::default();
+        self.load_workflow_read_only(&mut wf)?;
+        let traversal = self.make_traversal(&mut wf)?;
+        let mut out = std::io::stdout().lock();
+        if !memory {
+            return write_stats(&traversal.stats, &[], &mut out);
+        }
+        let stats = traversal.stats.clone();
+        let traversal_usage = traversal.memory_usage();
+        // resolve the traversal like a run would, so the run strings are interned too:
+        wf.strings.alloc_for_run();
+        let mut resolver = TraversalResolver::new(
+            traversal.nodes.len(),
+            &self.fs,
+            &mut wf,
+            self.resolver_options(),
+        );
+        resolver.resolve_to_states(traversal)?;
+        let memory = [
+            ("strings", wf.strings.memory_usage()),
+            ("traversal", traversal_usage),
+        ];
+        write_stats(&stats, &memory, &mut out)
+    }
+
+    /// Print the tasks, plans, and/or branchpoints defined in the workflow to stdout.
+    fn list(&mut self, kind: ListKind) -> Result<()> {
+        let mut wf = Workflow::default();
+        self.load_workflow_read_only(&mut wf)?;
+        write_listing(&wf, kind, &mut std::io::stdout().lock())
+    }
+
+    /// Print every realization that depends on the target tasks (on the branches given),
+    /// with its state on disk, to stdout, without modifying the output dir.
+    fn affected(&mut self) -> Result<()> {
+        let mut wf = Workflow::default();
+        self.load_workflow_read_only(&mut wf)?;
+        let mut tasks = Vec::with_capacity(self.settings.tasks.len());
+        for task in &self.settings.tasks {
+            let task = wf.strings.tasks.intern(task)?;
+            wf.get_task(task)?;
+            tasks.push(task);
+        }
+        let branches = self.branch_patterns(&mut wf)?;
+        let affected = find_affected(&wf, &tasks, &branches)?;
+        wf.strings.alloc_for_traversal();
+        write_affected(&affected, &wf, &self.fs, &mut std::io::stdout().lock())
+    }
+
+    /// Print why the realizations of `task` (on the branches given) are in the traversal
+    /// of the target plan or tasks, or why it isn't, to stdout, without touching the output dir.
+    fn explain(&mut self, task: &str) -> Result<()> {
+        let mut wf = Workflow::default();
+        self.load_workflow_read_only(&mut wf)?;
+        let task = wf.strings.tasks.intern(task)?;
+        wf.get_task(task)?;
+        let branches = self.branch_patterns(&mut wf)?;
+        let traversal = self.make_traversal(&mut wf)?;
+        write_explanation(
+            &traversal,
+            &wf,
+            task,
+            &branches,
+            &mut std::io::stdout().lock(),
+        )
+    }
+
+    /// Branches given with -b, to match realizations against:
+    /// unspecified branchpoints match any branch, so no -b matches every realization.
+    fn branch_patterns(&self, wf: &mut Workflow) -> Result<Vec<BranchSpec>> {
+        match &self.settings.branches {
+            ArgsBranch::Empty => Ok(vec![BranchSpec::default()]),
+            ArgsBranch::Baseline => {
+                let mut baseline = BranchSpec::default();
+                for (k, v) in wf.strings.baselines.iter() {
+                    baseline.insert(k.into(), *v);
+                }
+                Ok(vec![baseline])
+            }
+            branches => branches.intern(wf),
+        }
+    }
+
+    /// Print what changed between the two config files given to 'hr diff' to stdout,
+    /// followed by the realizations in the output dir that would run again b/c of it.
+    fn diff(&mut self) -> Result<()> {
+        let (old, new) = (
+            self.settings.configs[0].clone(),
+            self.settings.configs[1].clone(),
+        );
+        let mut old_wf = Workflow::default();
+        let mut new_wf = Workflow::default();
+        // the branches known from earlier runs aren't part of either version:
+        self.settings.configs = vec![old];
+        self.parse_workflow(&mut String::with_capacity(0), &mut old_wf)?;
+        self.settings.configs = vec![new];
+        self.parse_workflow(&mut String::with_capacity(0), &mut new_wf)?;
+        let diff = WorkflowDiff::new(&old_wf, &new_wf)?;
+        let mut out = std::io::stdout().lock();
+        write_diff(&diff, &mut out)?;
+
+        // but they are needed to find the realizations:
+        let mut wf = Workflow::default();
+        self.load_workflow_read_only(&mut wf)?;
+        let mut invalidated = Vec::new();
+        let mut seen = HashSet::default();
+        for task in &diff.changed_tasks {
+            let id = wf.strings.tasks.intern(&task.name)?;
+            let branches = task.branch_specs(&mut wf)?;
+            for key in find_affected(&wf, &[id], &branches)? {
+                if seen.insert(key.clone()) {
+                    invalidated.push(key);
+                }
+            }
+        }
+        wf.strings.alloc_for_traversal();
+        write_invalidated(&invalidated, &wf, &self.fs, &mut out)
+    }
+
+    /// Print the graph of the whole workflow, or of the traversal of the targets
+    /// given on the command line, to stdout, without touching the output dir.
+    fn visualize(&mut self, format: VizFormat) -> Result<()> {
+        let mut wf = Workflow::default();
+        self.load_workflow_read_only(&mut wf)?;
+
+        let graph = if self.settings.plans.is_empty() && self.settings.tasks.is_empty() {
+            Graph::from_workflow(&wf)?
+        } else {
+            let traversal = self.make_traversal(&mut wf)?;
+            Graph::from_traversal(&traversal, &wf)?
+        };
+        graph.write(format, &mut std::io::stdout().lock())
+    }
+
+    /// Print the abstract workflow to stdout as a build file for another workflow engine,
+    /// targeting the plans or tasks given on the command line, or else every plan.
+    fn export_workflow(&mut self, format: ExportFormat) -> Result<()> {
+        let mut wf = Workflow::default();
+        self.load_workflow_read_only(&mut wf)?;
+
+        let mut plan = if self.settings.plans.is_empty() && self.settings.tasks.is_empty() {
+            let mut every_plan = Plan::default();
+            for (_, plan) in wf.plans() {
+                every_plan.extend(plan.clone());
+            }
+            every_plan
+        } else {
+            self.get_target_for_run(&mut wf)?
+        };
+        plan.expand_globs(&wf.strings)?;
+        write_workflow_export(format, &wf, &plan, &mut std::io::stdout().lock())
+    }
+}
+
+/// Print a warning for each task input or param, or config value, that's never used.
+fn warn_unused(wf: &Workflow) -> Result<()> {
+    for unused in wf.find_unused()? {
+        mirror_eprintln!("{} {}", "WARNING".yellow(), unused.describe(&wf.strings)?);
+    }
+    Ok(())
+}
+
+// GARBAGE COLLECTION ///////
+impl App {
+    /// Realization dirs and symlinks reached by the plan given on the command line,
+    /// or by any plan in the workflow. None if there are no plans to check against.
+    fn live_realizations(&self, wf: &mut Workflow) -> Result<Option<HashSet<PathBuf>>> {
+        let plans: Vec<Plan> = if !self.settings.plans.is_empty() {
+            vec![self.get_plan_target(wf)?]
+        } else {
+            wf.plans().map(|(_, plan)| plan.clone()).collect()
+        };
+        if plans.is_empty() {
+            mirror_eprintln!("No plans defined; only checking for tasks that no longer exist.");
+            return Ok(None);
+        }
+
+        let mut live = HashSet::default();
+        let mut locator = self.fs.locator();
+        let mut strbuf = String::with_capacity(128);
+        for mut plan in plans {
+            plan.apply_baselines(&mut wf.strings);
+            let traversal = self.create_traversal(wf, plan)?;
+            for node in &traversal.nodes {
+                locator.locate_key(&node.key, wf, &mut strbuf)?;
+                live.insert(locator.realization().to_owned());
+                live.insert(locator.link_src().to_owned());
+            }
+        }
+        Ok(Some(live))
+    }
+}
+
+// BRANCHPOINTS /////////////
+impl App {
+    /// Rebuild branchpoints.txt from its valid lines, the config file,
+    /// and the branches of the realizations on disk.
+    fn repair_branchpoints(
+        &mut self,
+        branch_file: &Path,
+        strbuf: &mut String,
+        wf: &mut Workflow,
+    ) -> Result<()> {
+        let (old, invalid) = if self.fs.exists(branch_file) {
+            self.fs.read_to_buf(branch_file, strbuf)?;
+            BranchpointsFile::parse_lenient(strbuf)?
+        } else {
+            mirror_eprintln!("No branchpoints.txt file; creating a new one.");
+            Default::default()
+        };
+        for e in &invalid {
+            mirror_eprintln!("{} {e}", "DROP".red());
+        }
+        if old.load_into(wf)? {
+            self.fs.set_short_names(true);
+        }
+        self.parse_workflow(strbuf, wf)?;
+
+        // branches that are no longer defined in the config are dropped,
+        // even if realizations on disk still use them:
+        let mut file = BranchpointsFile::from_config(wf)?;
+        for (k, values) in &old.branchpoints {
+            for v in values.iter().filter(|v| !file.has_branch(k, v)) {
+                mirror_eprintln!(
+                    "{} branch {k}{BRANCH_KV_DELIM}{v} (no longer defined in the config)",
+                    "DROP".red()
+                );
+            }
+        }
+        for (k, values) in &file.branchpoints {
+            for v in values.iter().filter(|v| !old.has_branch(k, v)) {
+                mirror_eprintln!("{} branch {k}{BRANCH_KV_DELIM}{v}", "ADD".green());
+            }
+        }
+        for (name, branch) in &old.short_names {
+            if file.check_branch(branch).is_ok() {
+                file.add_short_name(name, branch);
+            } else {
+                mirror_eprintln!("{} realization {name} {branch}", "DROP".red());
+            }
+        }
+
+        let mut undefined: Vec<String> = Vec::new();
+        for (name, branch) in self.fs.realization_branches_on_disk()? {
+            if let Err(kv) = file.check_branch(&branch) {
+                if !undefined.iter().any(|u| u == kv) {
+                    undefined.push(kv.to_owned());
+                    mirror_eprintln!(
+                        "{} realizations on branch {kv} (e.g. {name}) are no longer \
+                         defined in the config; remove them with 'hr gc'.",
+                        "WARNING".yellow()
+                    );
+                }
+                continue;
+            }
+            if self.fs.short_names() && file.add_short_name(&name, &branch) {
+                mirror_eprintln!("{} realization {name} {branch}", "ADD".green());
+            }
+        }
+
+        if invalid.is_empty() && file == old {
+            mirror_eprintln!("{}", "branchpoints.txt is up to date.".green());
+            return Ok(());
+        }
+        if self.settings.dry_run {
+            mirror_eprintln!("Dry run. Not writing branchpoints.txt.");
+            return Ok(());
+        }
+        self.fs.acquire_lock(self.settings.force_unlock)?;
+        self.fs.write_branches_file(branch_file, &file)?;
+        mirror_eprintln!("{} {branch_file:?}.", "Rewrote".green());
+        Ok(())
+    }
+}
+
+// TRASH ////////////////////
+impl App {
+    fn manage_trash(&self, action: &TrashAction) -> Result<()> {
+        let batches = self.fs.trash_batches()?;
+        let Some(latest) = batches.last() else {
+            mirror_eprintln!("Trash is empty.");
+            return Ok(());
+        };
+        match action {
+            TrashAction::List => {
+                for batch in &batches {
+                    let size = self.fs.disk_usage(self.fs.trash_dir().join(batch))?;
+                    mirror_eprintln!("{} ({})", batch.cyan(), format_size(size));
+                    for path in self.fs.trash_contents(batch)? {
+                        mirror_eprintln!("    {path:?}");
+                    }
+                }
+                mirror_eprintln!("\nRestore a batch with 'hr trash --restore <BATCH>'.");
+            }
+            TrashAction::Empty => {
+                let size = self.fs.disk_usage(self.fs.trash_dir())?;
+                mirror_eprintln!(
+                    "Trash holds {} batch(es), {} on disk.",
+                    batches.len(),
+                    format_size(size)
+                );
+                if self.settings.dry_run {
+                    mirror_eprintln!("Dry run. Not deleting.");
+                    return Ok(());
+                }
+                if self.ui.confirm(
+                    "Permanently delete everything in the trash?",
+                    Confirm::Delete,
+                )? {
+                    self.fs.empty_trash()?;
+                }
+            }
+            TrashAction::Restore(batch) => {
+                let batch = batch.as_ref().unwrap_or(latest);
+                if !batches.contains(batch) {
+                    return Err(Error::NoSuchTrashBatch(batch.to_owned()).into());
+                }
+                if self.settings.dry_run {
+                    mirror_eprintln!("Dry run. Would restore:");
+                    for path in self.fs.trash_contents(batch)? {
+                        mirror_eprintln!("    {path:?}");
+                    }
+                    return Ok(());
+                }
+                let restored = self.fs.restore_trash(batch)?;
+                for path in &restored.restored {
+                    mirror_eprintln!("{} {path:?}", "Restored".green());
+                }
+                for path in &restored.skipped {
+                    mirror_eprintln!("{} {path:?}: it already exists", "Not restoring".yellow());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// SERVING //////////////////
+/// Paths 'hr serve' answers, listed by its index.
+const ENDPOINTS: &[&str] = &[
+    "/workflow",
+    "/status",
+    "/status/<plan>",
+    "/runs",
+    "/runs/latest",
+    "/logs/<task>/<branch|latest>/<stdout|stderr>",
+];
+/// How many of the most recent runs '/runs' returns.
+const SERVED_RUNS: usize = 20;
+
+impl App {
+    /// Answer read-only HTTP requests about the workflow and the output dir on `addr`
+    /// until killed, without modifying the output dir.
+    fn serve(&mut self, addr: &str) -> Result<()> {
+        self.fs.set_dry_run(true);
+        let listener =
+            TcpListener::bind(addr).with_context(|| format!("while listening on {addr}"))?;
+        mirror_eprintln!(
+            "{} {:?} on http://{}/",
+            "Serving".magenta(),
+            self.settings.output,
+            listener.local_addr()?,
+        );
+        serve::serve(&listener, |path| {
+            self.respond(path).unwrap_or_else(|e| Response::error(500, format!("{e:#}")))
+        })
+    }
+
+    /// Answer a GET request for `path` (split into segments), as 'hr serve' would.
+    /// The workflow is reloaded for every request, so responses reflect any changes to it.
+    pub fn respond(&mut self, path: &[String]) -> Result<Response> {
+        let path: Vec<&str> = path.iter().map(String::as_str).collect();
+        match path[..] {
+            [] => self.respond_index(),
+            ["workflow"] => {
+                let mut wf = Workflow::default();
+                self.load_workflow_read_only(&mut wf)?;
+                Response::json(&Graph::from_workflow(&wf)?)
+            }
+            ["status"] => self.respond_status(None),
+            ["status", plan] => self.respond_status(Some(plan)),
+            ["runs"] => Response::json(&runs(&self.fs, SERVED_RUNS)?.unwrap_or_default()),
+            ["runs", "latest"] => Response::json(&latest_run(&self.fs)?),
+            ["logs", task, branch, artifact] => self.respond_log(task, branch, artifact),
+            _ => Ok(Response::error(404, "No such endpoint; see / for the list")),
+        }
+    }
+
+    /// The output dir, the process running a workflow in it (if any), and the endpoints.
+    fn respond_index(&self) -> Result<Response> {
+        #[derive(Serialize)]
+        struct Index<'a> {
+            output: String,
+            configs: Vec<&'a str>,
+            running: Option<Running>,
+            endpoints: &'a [&'a str],
+        }
+        /// pid of the process holding the lock, and when it took it.
+        #[derive(Serialize)]
+        struct Running {
+            pid: u32,
+            since: u64,
+        }
+        Response::json(&Index {
+            output: self.settings.output.to_string_lossy().into_owned(),
+            configs: self.settings.configs.iter().filter_map(|c| c.to_str()).collect(),
+            running: self.fs.lock_holder()?.map(|(pid, since)| Running { pid, since }),
+            endpoints: ENDPOINTS,
+        })
+    }
+
+    /// Status of every realization in `plan`, or else in each plan (or the targets given
+    /// on the command line), like 'hr status'.
+    fn respond_status(&mut self, plan: Option<&str>) -> Result<Response> {
+        let mut wf = Workflow::default();
+        self.load_workflow_read_only(&mut wf)?;
+        let mut targets = Vec::new();
+        if plan.is_none() && !(self.settings.plans.is_empty() && self.settings.tasks.is_empty()) {
+            targets.push(("targets".to_owned(), self.get_target_for_run(&mut wf)?));
+        } else {
+            for (id, target) in wf.plans() {
+                let name = wf.strings.idents.get(id)?;
+                if plan.is_none() || plan == Some(name) {
+                    targets.push((format!("plan {name}"), target.clone()));
+                }
+            }
+        }
+        if let (Some(plan), true) = (plan, targets.is_empty()) {
+            return Ok(Response::error(404, format!("No plan named {plan}")));
+        }
+
+        wf.strings.alloc_for_run();
+        let mut statuses = Vec::with_capacity(targets.len());
+        for (label, mut target) in targets {
+            target.apply_baselines(&mut wf.strings);
+            let traversal = self.create_traversal(&mut wf, target)?;
+            let mut resolver = TraversalResolver::new(
+                traversal.nodes.len(),
+                &self.fs,
+                &mut wf,
+                self.resolver_options(),
+            );
+            let states = resolver.resolve_to_states(traversal)?;
+            statuses.push(StatusReport::new(label, &states, &wf, &self.fs)?);
+        }
+        match plan {
+            Some(_) => Response::json(&statuses[0]),
+            None => Response::json(&statuses),
+        }
+    }
+
+    /// Stdout or stderr of a realization (or the task's most recent one, for branch "latest").
+    fn respond_log(&self, task: &str, branch: &str, artifact: &str) -> Result<Response> {
+        let artifact = match artifact {
+            "stdout" => Artifact::Stdout,
+            "stderr" => Artifact::Stderr,
+            _ => return Ok(Response::error(404, "Logs are either stdout or stderr")),
+        };
+        // only tasks in the output dir, and branches that stay inside it:
+        if !self.fs.task_names()?.iter().any(|name| name == task) {
+            return Ok(Response::error(
+                404,
+                format!("No realizations of task {task}"),
+            ));
+        }
+        if branch.starts_with('.') || branch.contains(['/', '\\']) {
+            return Ok(Response::error(400, format!("Invalid branch: {branch}")));
+        }
+        let mut locator = RealizationLocator::new(&self.fs);
+        locator.locate(task, branch);
+        let mut buf = PathBuf::with_capacity(512);
+        let path = if branch == LATEST_LINK {
+            artifact.path_in(locator.task_base().join(LATEST_LINK), &mut buf)
+        } else {
+            // a task that didn't finish leaves its logs in its scratch dir:
+            let dir = find_log_dir(&locator, &self.fs)?
+                .unwrap_or_else(|| locator.realization().to_path_buf());
+            artifact.path_in(dir, &mut buf)
+        };
+        match std::fs::read(path) {
+            Ok(log) => Ok(Response::text(log)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Response::error(
+                404,
+                format!("No {} for {task}[{branch}]", artifact.file_name()),
+            )),
+            Err(e) => Err(e).with_context(|| format!("while reading {path:?}")),
+        }
+    }
+}
+
+// CANCELLING ///////////////
+impl App {
+    fn request_cancel(&self, request: &CancelRequest, pathbuf: &mut PathBuf) -> Result<()> {
+        if self.settings.dry_run {
+            let cancel_request = self.fs.cancel_request(pathbuf);
+            mirror_eprintln!("Dry run. Not writing cancel request {cancel_request:?}");
+            return Ok(());
+        }
+        self.fs.request_cancel(request, pathbuf)?;
+        let what = if request.tasks.is_empty() {
+            "its next task".to_owned()
+        } else {
+            format!("any more of {}", request.tasks.join(", "))
+        };
+        let running = if request.kill {
+            "; cancelled tasks that are running now will be killed"
+        } else {
+            ""
+        };
+        mirror_eprintln!(
+            "{} Any workflow running in {:?} will stop before starting {what}{running}.",
+            "Cancel requested.".magenta(),
+            self.settings.output,
+        );
+        Ok(())
+    }
+}
+
+// SHELL ////////////////////
+impl App {
+    /// Print the realization dir of the target task, or the paths of its outputs, to stdout,
+    /// whether or not it has run, without modifying the output dir.
+    fn locate(&mut self, locate: Locate) -> Result<()> {
+        let mut wf = Workflow::default();
+        let builder = self.resolve_goal_read_only(&mut wf)?;
+
+        let mut out = std::io::stdout().lock();
+        match locate {
+            Locate::Realization => {
+                writeln!(out, "{}", wf.strings.run.get(builder.realization_id)?)?;
+            }
+            Locate::Outputs(None) => {
+                for (id, file) in builder.final_outputs() {
+                    let name = wf.strings.idents.get(id)?;
+                    writeln!(out, "{name}={}", wf.strings.run.get(file)?)?;
+                }
+            }
+            Locate::Outputs(Some(output)) => {
+                let mut names = Vec::with_capacity(builder.vars.outputs.len());
+                for (id, file) in builder.final_outputs() {
+                    let name = wf.strings.idents.get(id)?;
+                    if name == output {
+                        writeln!(out, "{}", wf.strings.run.get(file)?)?;
+                        return Ok(());
+                    }
+                    names.push(name);
+                }
+                let suggestion = util::did_you_mean(util::closest_match(&output, names));
+                let task = wf.strings.tasks.get(builder.key.id)?.to_owned();
+                return Err(Error::NoSuchOutput(task, output, suggestion).into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Print the task.sh the target task would run to stdout, with all of its variables
+    /// assigned, whether or not it has run, without modifying the output dir.
+    fn cat_script(&mut self) -> Result<()> {
+        let mut wf = Workflow::default();
+        let builder = self.resolve_goal_read_only(&mut wf)?;
+        let mut task_sh = String::with_capacity(1024);
+        builder.into_task_runner(&wf.strings.run, &wf, &mut task_sh)?;
+        std::io::stdout().lock().write_all(task_sh.as_bytes())?;
+        Ok(())
+    }
+
+    /// Load the workflow and fully resolve the target task, without modifying the output dir.
+    fn resolve_goal_read_only(&mut self, wf: &mut Workflow) -> Result<TaskRunnerBuilder> {
+        self.load_workflow_read_only(wf)?;
+        let traversal = self.make_traversal(wf)?;
+        wf.strings.alloc_for_run();
 
+        let mut resolver = TraversalResolver::new(
+            traversal.nodes.len(),
+            &self.fs,
+            wf,
+            ResolverOptions::default(),
+        );
+        resolver.resolve_goal(traversal)
+    }
+
+    /// Print the stdout or stderr of the target task to stdout (following it, if asked),
+    /// without modifying the output dir.
+    fn logs(&mut self, query: LogsQuery) -> Result<()> {
+        let mut wf = Workflow::default();
+        self.load_workflow_read_only(&mut wf)?;
+        let traversal = self.make_traversal(&mut wf)?;
+        let goal = traversal.nodes.last().ok_or(Error::NoTargetSpecified)?;
+
+        let mut locator = self.fs.locator();
+        locator.locate_key(&goal.key, &wf, &mut String::with_capacity(128))?;
+        let Some(dir) = find_log_dir(&locator, &self.fs)? else {
+            let realization = locator.realization().display().to_string();
+            return Err(Error::NoRealizationForLogs(realization).into());
+        };
+        write_log(&dir, query, &mut std::io::stdout().lock())
+    }
+
+    /// Run the target, then run it again (with a new `App`) whenever the config files
+    /// change, or the git commit of a module the workflow uses. Only stops when killed.
+    fn watch(mut self) -> Result<()> {
+        let mut settings = self.settings.clone();
+        settings.mode = Mode::Run;
+        let observers: Vec<_> = self
+            .observers
+            .drain(..)
+            .map(|observer| Rc::new(RefCell::new(observer)))
+            .collect();
+        let module_dirs = ModuleDirs::default();
+        let mut watcher = Watcher::new(settings.configs.clone());
+        loop {
+            // changes made while the workflow runs count too:
+            watcher.update(&self.fs);
+            let mut app = App::new(settings.clone());
+            app.module_dirs = Some(module_dirs.clone());
+            for observer in &observers {
+                app.add_observer(Box::new(SharedObserver(observer.clone())));
+            }
+            match app.run() {
+                Err(e) if !e.is::<NothingToRun>() => mirror_eprintln!("{}: {e:?}\n", "Error".red()),
+                _ => (),
+            }
+            watcher.set_modules(module_dirs.take(), &self.fs);
+            watcher.wait_for_change(&self.fs);
+        }
+    }
+
+    fn open_shell(mut self, mut wf: Workflow, traversal: Traversal) -> Result<()> {
+        wf.strings.alloc_for_run();
+        self.fs.set_dry_run(true);
+
+        let mut resolver = TraversalResolver::new(
+            traversal.nodes.len(),
+            &self.fs,
+            &mut wf,
+            ResolverOptions::default(),
+        );
+        let builder = resolver.resolve_goal(traversal)?;
+
+        let realization = wf.strings.run.get(builder.realization_id)?;
+        if !self.fs.exists(realization) {
+            return Err(Error::NoRealizationForShell(realization.to_owned()).into());
+        }
+        let task_str = wf.strings.run.get(builder.print_id)?.to_owned();
+        let cmd_dir = wf.strings.run.get(builder.cmd_dir_id())?.to_owned();
+
+        let mut cmd = builder.into_shell_cmd(&wf.strings.run, &wf)?;
+        mirror_eprintln!(
+            "{} for {task_str}\nin {cmd_dir}\n(exit the shell to return)\n",
+            "Starting shell".magenta()
+        );
+        cmd.status().context("while running interactive shell")?;
         Ok(())
     }
 }
 
 // GETTING TARGETS ////////////
 impl App {
+    /// Apply '--until' and '--from' to the traversal of the target: with '--until', only
+    /// that task's realizations and their antecedents are traversed; with '--from', that
+    /// task's realizations and their antecedents are returned, to be assumed complete.
+    fn apply_bounds(
+        &self,
+        wf: &mut Workflow,
+        traversal: Traversal,
+    ) -> Result<(Traversal, HashSet<RealTaskKey>)> {
+        let traversal = match &self.settings.until {
+            Some(task) => self.traverse_antecedents(wf, &traversal, "until", task)?,
+            None => traversal,
+        };
+        let mut assumed = HashSet::default();
+        if let Some(task) = &self.settings.from {
+            let antecedents = self.traverse_antecedents(wf, &traversal, "from", task)?;
+            assumed.extend(antecedents.nodes.into_iter().map(|node| node.key));
+        }
+        Ok((traversal, assumed))
+    }
+
+    /// Traverse the realizations of `task` in `traversal`, and everything they depend on.
+    fn traverse_antecedents(
+        &self,
+        wf: &mut Workflow,
+        traversal: &Traversal,
+        flag: &'static str,
+        task: &str,
+    ) -> Result<Traversal> {
+        let id = wf.strings.tasks.intern(task)?;
+        let mut branches: Vec<BranchSpec> = Vec::new();
+        for node in traversal.nodes.iter().filter(|node| node.key.id == id) {
+            if !branches.contains(&node.requested_branch) {
+                branches.push(node.requested_branch.clone());
+            }
+        }
+        if branches.is_empty() {
+            let suggestion = util::did_you_mean(wf.closest_task(task));
+            return Err(Error::BoundNotInTarget(flag, task.to_owned(), suggestion).into());
+        }
+        log::debug!(
+            "Traversing {} realizations of {task} for --{flag}",
+            branches.len()
+        );
+        let plan = Plan::create_anonymous(&mut wf.strings, &[task.to_owned()], branches)?;
+        self.create_traversal(wf, plan)
+    }
+
     fn get_target_for_run(&self, wf: &mut Workflow) -> Result<Plan> {
-        if let Some(plan_name) = &self.settings.plan {
-            self.get_plan_target(plan_name, wf)
-        } else if !self.settings.tasks.is_empty() {
-            self.get_task_target(wf)
+        if !self.settings.plans.is_empty() {
+            self.get_plan_target(wf)
+        } else if !self.settings.tasks.is_empty() || !self.settings.produces.is_empty() {
+            let mut target = Plan::default();
+            if !self.settings.tasks.is_empty() {
+                target.extend(self.get_task_target(wf)?);
+            }
+            for output in &self.settings.produces {
+                target.extend(self.get_output_target(wf, output)?);
+            }
+            Ok(target)
         } else {
             Err(Error::NoTargetSpecified.into())
         }
     }
 
-    fn get_plan_target(&self, plan_name: &str, wf: &mut Workflow) -> Result<Plan> {
-        log::debug!("Using plan {plan_name} specified on command line");
-        let id = wf.strings.idents.intern(plan_name)?;
-        Ok(wf.get_plan(id)?.clone())
+    /// The union of the plans specified on the command line.
+    fn get_plan_target(&self, wf: &mut Workflow) -> Result<Plan> {
+        log::debug!(
+            "Using plans '{}' specified on command line",
+            self.settings.plans.join(", "),
+        );
+        let mut target = Plan::default();
+        for plan_name in &self.settings.plans {
+            let id = wf.strings.idents.intern(plan_name)?;
+            let plan = wf.get_plan(id)?;
+            target.extend(plan.clone());
+        }
+        Ok(target)
     }
 
     fn get_task_target(&self, wf: &mut Workflow) -> Result<Plan> {
@@ -226,22 +1739,106 @@ impl App {
             "No plan specified; running tasks '{}' specified on command line",
             self.settings.tasks.join(", "),
         );
-        let branch = self.get_target_branch(wf)?;
-        Plan::create_anonymous(&mut wf.strings, &self.settings.tasks, branch)
+        let branches = self.settings.branches.intern(wf)?;
+        Plan::create_anonymous(&mut wf.strings, &self.settings.tasks, branches)
     }
 
-    fn get_target_branch(&self, wf: &mut Workflow) -> Result<BranchSpec> {
-        let mut branch = BranchSpec::default();
-        if let ArgsBranch::Specified(branch_pairs) = &self.settings.branches {
-            for (k, v) in branch_pairs {
-                let k = wf.strings.branchpoints.intern(k)?;
-                if branch.is_specified(k) {
-                    return Err(Error::MultiBranch.into());
-                }
-                let v = wf.strings.idents.intern(v)?;
-                branch.insert(k, v);
+    /// The tasks producing `output`, given as a path in the output dir, or else as the name
+    /// or path of an output (which is reached via the branch it has that path on, if any).
+    fn get_output_target(&self, wf: &mut Workflow, output: &str) -> Result<Plan> {
+        if let Some((task, branch)) = self.locate_output_path(wf, output)? {
+            self.ui.verbose_msg(&format!("'{output}' is in the output dir of task {task}"));
+            let branches = match branch {
+                Some(branch) => vec![branch],
+                None => self.settings.branches.intern(wf)?,
+            };
+            return Plan::create_anonymous(&mut wf.strings, &[task], branches);
+        }
+
+        let producers = wf.producers(output).to_vec();
+        if producers.is_empty() {
+            let suggestion = util::did_you_mean(wf.closest_output(output));
+            return Err(Error::NoProducer(output.to_owned(), suggestion).into());
+        }
+        let mut tasks = Vec::with_capacity(producers.len());
+        for producer in &producers {
+            let task = wf.strings.tasks.get(producer.task)?;
+            if !tasks.contains(&task) {
+                tasks.push(task);
+            }
+        }
+        if tasks.len() > 1 {
+            return Err(Error::AmbiguousProducer(output.to_owned(), tasks.join(", ")).into());
+        }
+        let mut target = Plan::default();
+        for producer in producers {
+            let task = wf.strings.tasks.get(producer.task)?.to_owned();
+            self.ui.verbose_msg(&format!(
+                "'{output}' is output {} of task {task}",
+                wf.strings.idents.get(producer.output)?,
+            ));
+            // the output's own branch wins over any given with -b:
+            let mut branches = self.settings.branches.intern(wf)?;
+            for branch in &mut branches {
+                branch.insert_all(&producer.branch);
             }
+            target.extend(Plan::create_anonymous(&mut wf.strings, &[task], branches)?);
         }
-        Ok(branch)
+        Ok(target)
+    }
+
+    /// If `output` is a path in a task's dir in the output dir, that task, with the branch
+    /// of the realization it's in (if it's in one). Paths are compared without following
+    /// symlinks, so the path doesn't have to exist yet.
+    fn locate_output_path(
+        &self,
+        wf: &mut Workflow,
+        output: &str,
+    ) -> Result<Option<(String, Option<BranchSpec>)>> {
+        let cwd = std::env::current_dir()?;
+        let path = cwd.join(output);
+        let Ok(relative) = path.strip_prefix(cwd.join(&self.settings.output)) else {
+            return Ok(None);
+        };
+        let parts: Vec<&str> = relative
+            .components()
+            .map(|c| c.as_os_str().to_str().ok_or(util::PathEncodingError))
+            .collect::<Result<_, _>>()?;
+        // if it isn't in a task's dir, it may still be the name of an output:
+        let Some((task, rest)) = parts.split_first() else {
+            return Ok(None);
+        };
+        if !wf.task_names().any(|t| t == *task) {
+            return Ok(None);
+        }
+
+        let task_base = self.settings.output.join(task);
+        let realization = match rest {
+            [] | [REALIZATIONS_DIR] => return Ok(Some((task.to_string(), None))),
+            [REALIZATIONS_DIR, name, ..] => name.to_string(),
+            // `latest`, and the links named by each realization's full branch string,
+            // point to realization dirs; nothing else in the task dir is a realization:
+            [link, ..] => {
+                let link = task_base.join(link);
+                if !self.fs.is_symlink(&link) {
+                    return Ok(None);
+                }
+                let target = self.fs.read_link(&link)?;
+                let (Some(REALIZATIONS_DIR), Some(name)) = (
+                    target.parent().and_then(|dir| dir.to_str()),
+                    target.file_name().and_then(|name| name.to_str()),
+                ) else {
+                    return Ok(None);
+                };
+                name.to_owned()
+            }
+        };
+        // with short names, realization dirs are named by a hash of their branch:
+        let dir = task_base.join(REALIZATIONS_DIR).join(&realization);
+        let branch = self.fs.read_realization_branch(&dir)?.unwrap_or(realization);
+        Ok(Some((
+            task.to_string(),
+            Some(wf.parse_compact_branch_str(&branch)?),
+        )))
     }
 }
@@ -1,52 +1,517 @@
-use clap::Parser;
+use std::num::NonZeroUsize;
+
+use clap::{Parser, Subcommand, ValueEnum};
 
 const CMD_NAME: &str = "hr";
-const DEFAULT_CONFIG: &str = "rebuild.hr";
-const DEFAULT_OUTPUT: &str = "output";
 
 /// Stores our command-line args format.
 #[derive(Parser)]
 #[command(name = CMD_NAME, version, about = None, long_about = None)]
 pub struct Args {
-    /// Workflow definition file
-    #[arg(short, long, value_name = "FILE", default_value = DEFAULT_CONFIG)]
-    #[arg(env = "HERON_REBUILD_CONFIG")]
-    pub config: String,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Workflow definition file; give more than once to layer later files over earlier ones
+    /// [default: $HR_CONFIG, .heron-rebuild.toml's config, or the nearest rebuild.hr or *.tconf
+    /// in this dir or its parents]
+    #[arg(short, long = "config", value_name = "FILE")]
+    #[arg(env = "HERON_REBUILD_CONFIG", global = true)]
+    pub configs: Vec<String>,
+
+    /// Name of target plan; give more than once to run the union of several plans
+    #[arg(short, long = "plan", value_name = "PLAN", global = true)]
+    pub plans: Vec<String>,
 
-    /// Name of target plan
-    #[arg(short, long, value_name = "PLAN")]
-    pub plan: Option<String>,
+    /// Set a global config value, overriding the config file; can be given more than once
+    #[arg(short = 'D', long = "define", value_name = "KEY=VALUE", global = true)]
+    pub defines: Vec<String>,
 
-    /// Name of target task
-    #[arg(short, long = "task", value_name = "TASK")]
+    /// Also accept config files written for another workflow tool, to help migrate them
+    #[arg(long, value_enum, value_name = "TOOL", global = true)]
+    pub compat: Option<Compat>,
+
+    /// Name of target task, or a glob matching several (e.g. 'test_*')
+    #[arg(short, long = "task", value_name = "TASK", global = true)]
     pub tasks: Vec<String>,
 
-    /// Invalidate specified task
-    #[arg(short = 'x', long)]
-    pub invalidate: bool,
+    /// Target every task whose name matches this regex (e.g. '^test_')
+    #[arg(long = "task-regex", value_name = "REGEX", global = true)]
+    pub task_regexes: Vec<String>,
 
     /// Output directory
-    #[arg(short, long, value_name = "DIR", default_value = DEFAULT_OUTPUT)]
+    /// [default: $HR_OUTPUT, .heron-rebuild.toml's output, or 'output', next to the config file
+    /// if it was found in a parent dir]
+    #[arg(short, long, value_name = "DIR", global = true)]
     #[arg(env = "HERON_REBUILD_OUTPUT")]
-    pub output: String,
+    pub output: Option<String>,
+
+    /// Task output cache directory, shared between output directories
+    #[arg(long, value_name = "DIR", global = true)]
+    #[arg(env = "HERON_REBUILD_CACHE")]
+    pub cache: Option<String>,
+
+    /// Remote cache url (http, https, file, or s3) to share the cache dir with other machines
+    #[arg(long, value_name = "URL", global = true)]
+    #[arg(env = "HERON_REBUILD_REMOTE_CACHE")]
+    pub remote_cache: Option<String>,
 
     /// Bypass user confirmation
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     pub yes: bool,
 
+    /// Bypass user confirmation before running tasks
+    #[arg(long, global = true)]
+    pub yes_run: bool,
+
+    /// Bypass user confirmation before deleting incomplete realizations
+    #[arg(long, global = true)]
+    pub yes_delete: bool,
+
+    /// Bypass user confirmation before invalidating tasks
+    #[arg(long, global = true)]
+    pub yes_invalidate: bool,
+
+    /// Never delete realization dirs; abort the run or invalidation instead (overrides -y)
+    #[arg(long, conflicts_with = "yes_delete", global = true)]
+    pub no_delete: bool,
+
+    /// Fail instead of asking for confirmation (e.g. in CI), and exit with a distinct code when
+    /// there's nothing to run
+    #[arg(long, global = true)]
+    #[arg(env = "HERON_REBUILD_NON_INTERACTIVE")]
+    pub non_interactive: bool,
+
     /// Print additional debugging info
-    #[arg(short, long, action = clap::ArgAction::Count)]
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
     pub verbose: u8,
 
-    /// Target branch
-    #[arg(short, long, value_name = "K1.V1[+K2.V2]")]
+    /// How to write log messages, on stderr and in the run log (text by default)
+    #[arg(long, value_enum, value_name = "FORMAT", global = true)]
+    #[arg(env = "HERON_REBUILD_LOG_FORMAT")]
+    pub log_format: Option<LogFormat>,
+
+    /// Target branch; give more than once (or separate with commas) to target every combination
+    /// of the branches given for each branchpoint
+    #[arg(short, long, value_name = "K1.V1[+K2.V2]", global = true)]
     pub branch: Vec<String>,
 
     /// Use baseline branch ('-b Baseline.baseline')
-    #[arg(short = 'B', long)]
+    #[arg(short = 'B', long, global = true)]
     pub baseline: bool,
 
+    /// Refuse to run tasks in modules whose git checkout has uncommitted changes
+    #[arg(long, global = true)]
+    pub require_clean_modules: bool,
+
+    /// Fail, rather than warn, when task code uses a variable that isn't defined
+    #[arg(long, global = true)]
+    pub strict_vars: bool,
+
+    /// Name realization dirs by a short hash of their branch (remembered for the output dir)
+    #[arg(long, global = true)]
+    pub short_names: bool,
+
+    /// Remove an existing lock on the output directory before running
+    #[arg(long, global = true)]
+    pub force_unlock: bool,
+
     /// Dry run; print info but don't modify anything.
-    #[arg(short = 'n', long)]
+    #[arg(short = 'n', long, global = true)]
     pub dry_run: bool,
+
+    #[command(flatten, next_help_heading = "Run options")]
+    pub run_args: RunArgs,
+
+    /// Invalidate the tasks given (same as 'hr invalidate')
+    #[arg(short = 'x', long, help_heading = "Invalidate options")]
+    pub invalidate: bool,
+
+    #[command(flatten, next_help_heading = "Invalidate options")]
+    pub invalidate_args: InvalidateArgs,
+}
+
+/// Options for running tasks, given either to 'hr run' or, with no subcommand, to 'hr' itself.
+#[derive(clap::Args, Debug, Default)]
+pub struct RunArgs {
+    /// How to report the actions to take and the results of a run (text by default)
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    pub output_format: Option<OutputFormat>,
+
+    /// Write a JUnit XML report of the tasks that ran to this file, e.g. for CI
+    #[arg(long, value_name = "FILE")]
+    pub junit: Option<String>,
+
+    /// Keep Prometheus metrics about the run in this file (e.g. for node_exporter's textfile collector)
+    #[arg(long, value_name = "FILE")]
+    pub metrics_file: Option<String>,
+
+    /// Send an OpenTelemetry trace of the run to this OTLP/HTTP collector, e.g. http://localhost:4318
+    #[arg(long, value_name = "URL")]
+    #[arg(env = "OTEL_EXPORTER_OTLP_ENDPOINT")]
+    pub otlp_endpoint: Option<String>,
+
+    /// Run tasks directly in their realization dirs, instead of in temporary dirs
+    /// that are moved into place only on success
+    #[arg(long)]
+    pub in_place: bool,
+
+    /// Warn about task inputs and params, and config values, that are never used
+    #[arg(long)]
+    pub warnings: bool,
+
+    /// Print the branches that were merged into each deduplicated task realization, and why
+    #[arg(long)]
+    pub explain_dedup: bool,
+
+    /// Warn when a task produces no output for this many seconds
+    #[arg(long, value_name = "SECS")]
+    pub idle_timeout: Option<u64>,
+
+    /// Kill tasks that exceed --idle-timeout instead of just warning
+    #[arg(long, requires = "idle_timeout")]
+    pub kill_idle: bool,
+
+    /// Run up to this many tasks at once, when they don't depend on each other [default: 1]
+    #[arg(short, long, value_name = "N")]
+    pub jobs: Option<NonZeroUsize>,
+
+    /// Run this shell command when the run finishes, with $HR_RUN_STATUS set to
+    /// 'succeeded' or 'failed' and $HR_FAILED_TASKS to the realizations that failed
+    #[arg(long, value_name = "COMMAND")]
+    pub notify: Option<String>,
+
+    /// Which runs to run the --notify command after [default: always]
+    #[arg(long, value_enum, value_name = "WHEN", requires = "notify")]
+    pub notify_on: Option<NotifyOn>,
+
+    /// Only run this task of the target, and the tasks it depends on
+    #[arg(long, value_name = "TASK")]
+    pub until: Option<String>,
+
+    /// Assume this task of the target, and the tasks it depends on, are complete,
+    /// and don't run them even if they haven't completed
+    #[arg(long, value_name = "TASK")]
+    pub from: Option<String>,
+
+    /// Target the task that produces this output: an output name, a file name (e.g.
+    /// 'installer.pkg'), or a path in the output dir; can be given more than once
+    #[arg(long, value_name = "OUTPUT")]
+    pub produces: Vec<String>,
+
+    /// Choose which incomplete realizations to delete and which tasks to run, one at a time
+    #[arg(short, long)]
+    pub interactive: bool,
+
+    /// List every realization before running, instead of counting each task's when there are many
+    #[arg(long)]
+    pub full: bool,
+
+    /// How many logs of past runs to keep in the output dir's 'logs' dir; 0 to stop writing them
+    /// [default: 20]
+    #[arg(long, value_name = "N")]
+    pub keep_run_logs: Option<usize>,
+
+    /// Always parse the config and traverse the target, instead of reusing the workflow and
+    /// traversal cached by the last run when neither has changed
+    #[arg(long)]
+    pub no_traversal_cache: bool,
+}
+
+impl RunArgs {
+    /// Add the options given after 'hr run' to those given before it.
+    pub fn merge(&mut self, other: Self) {
+        self.output_format = other.output_format.or(self.output_format);
+        self.junit = other.junit.or(self.junit.take());
+        self.metrics_file = other.metrics_file.or(self.metrics_file.take());
+        self.otlp_endpoint = other.otlp_endpoint.or(self.otlp_endpoint.take());
+        self.in_place |= other.in_place;
+        self.warnings |= other.warnings;
+        self.explain_dedup |= other.explain_dedup;
+        self.idle_timeout = other.idle_timeout.or(self.idle_timeout);
+        self.kill_idle |= other.kill_idle;
+        self.jobs = other.jobs.or(self.jobs);
+        self.notify = other.notify.or(self.notify.take());
+        self.notify_on = other.notify_on.or(self.notify_on);
+        self.until = other.until.or(self.until.take());
+        self.from = other.from.or(self.from.take());
+        self.produces.extend(other.produces);
+        self.interactive |= other.interactive;
+        self.full |= other.full;
+        self.keep_run_logs = other.keep_run_logs.or(self.keep_run_logs);
+        self.no_traversal_cache |= other.no_traversal_cache;
+    }
+}
+
+/// Options for invalidating tasks, given either to 'hr invalidate' or to 'hr -x'.
+#[derive(clap::Args, Debug, Default)]
+pub struct InvalidateArgs {
+    /// Also invalidate realizations that depend on the invalidated ones
+    #[arg(long)]
+    pub downstream: bool,
+
+    /// Only invalidate realizations last run at least this long ago (e.g. '30d')
+    #[arg(long, value_name = "AGE")]
+    pub older_than: Option<String>,
+
+    /// Only invalidate realizations that failed or never finished
+    #[arg(long)]
+    pub failed_only: bool,
+
+    /// Only invalidate realizations whose branch matches this glob (e.g. 'Profile.*')
+    #[arg(long, value_name = "GLOB")]
+    pub branch_glob: Option<String>,
+}
+
+impl InvalidateArgs {
+    /// true if any option was given.
+    pub fn is_given(&self) -> bool {
+        self.downstream
+            || self.older_than.is_some()
+            || self.failed_only
+            || self.branch_glob.is_some()
+    }
+
+    /// Add the options given after 'hr invalidate' to those given before it.
+    pub fn merge(&mut self, other: Self) {
+        self.downstream |= other.downstream;
+        self.older_than = other.older_than.or(self.older_than.take());
+        self.failed_only |= other.failed_only;
+        self.branch_glob = other.branch_glob.or(self.branch_glob.take());
+    }
+}
+
+/// Subcommands. With none, 'hr' runs the target, like 'hr run' (or invalidates it, with '-x').
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run the plan (or the tasks given); this is what 'hr' does when no command is given
+    Run(RunArgs),
+    /// Run the plan (or the tasks given), then run it again whenever the config file changes
+    Watch(RunArgs),
+    /// Invalidate the tasks given, so they run again next time (same as 'hr -x')
+    Invalidate(InvalidateArgs),
+    /// Open an interactive shell in a task's realization dir, with its variables exported
+    Shell {
+        /// Name of task
+        task: String,
+    },
+    /// Print the realization dir of a task (on the branch given with -b)
+    Which {
+        /// Name of task
+        task: String,
+    },
+    /// Print the paths of a task's output files (on the branch given with -b)
+    Outputs {
+        /// Name of task
+        task: String,
+        /// Only print the path of this output
+        #[arg(value_name = "OUTPUT")]
+        name: Option<String>,
+    },
+    /// Print the stdout (or stderr) of a task (on the branch given with -b)
+    Logs {
+        /// Name of task
+        task: String,
+        /// Print stderr instead of stdout
+        #[arg(long)]
+        stderr: bool,
+        /// Keep printing what the task writes until it finishes
+        #[arg(short, long)]
+        follow: bool,
+    },
+    /// Print the task.sh a task would run (on the branch given with -b), without running it
+    CatScript {
+        /// Name of task
+        task: String,
+    },
+    /// List every realization that would run again if a task changed (or just its branches given with -b)
+    Affected {
+        /// Name of task
+        task: String,
+    },
+    /// Explain why a task's realizations (on the branch given with -b) are in the traversal
+    /// of the plan (or the tasks given), or why it isn't
+    Explain {
+        /// Name of task
+        task: String,
+    },
+    /// Ask a workflow running in the output directory to stop before starting its next task
+    Cancel {
+        /// Only cancel this task (and the tasks that need it); the rest of the run carries on
+        #[arg(long = "task", value_name = "TASK")]
+        tasks: Vec<String>,
+        /// Also kill cancelled tasks that are already running, instead of letting them finish
+        #[arg(long)]
+        kill: bool,
+    },
+    /// Delete realizations that are no longer part of the workflow (or of the plan given with -p)
+    Gc,
+    /// Rebuild branchpoints.txt from the config file, dropping branches it no longer defines
+    RepairBranchpoints,
+    /// Check the config file and every plan (or the plan/tasks given) for errors, without running
+    Check,
+    /// Format the config file(s) consistently, keeping comments (or with --check, fail if any aren't)
+    Fmt {
+        /// Don't change any files; just list the ones that aren't formatted
+        #[arg(long)]
+        check: bool,
+    },
+    /// Compare two versions of a config file: what changed, and which realizations would run again
+    Diff {
+        /// Old version of the config file
+        old: String,
+        /// New version of the config file (its realizations are looked for in the output dir)
+        new: String,
+    },
+    /// List realizations that earlier runs moved to the trash, or restore or delete them
+    Trash {
+        /// Permanently delete everything in the trash
+        #[arg(long, conflicts_with = "restore")]
+        empty: bool,
+        /// Move a batch of trashed realizations (by default, the most recent) back into place
+        #[arg(long, value_name = "BATCH")]
+        restore: Option<Option<String>>,
+    },
+    /// Show the state, size, and run times of every realization in the plan (or the tasks given)
+    Status,
+    /// Print the size of the traversal of the plan (or the tasks given), and how long creating it took
+    Stats {
+        /// Also print how many bytes the interners, string caches, and traversal use
+        #[arg(long)]
+        memory: bool,
+    },
+    /// List the tasks, plans, and branchpoints defined in the config file
+    List {
+        /// What to list
+        #[arg(value_enum, default_value = "all")]
+        what: ListKind,
+    },
+    /// Show past runs in the output directory, which tasks failed in them, or how long tasks took
+    History {
+        /// What to show
+        #[arg(value_enum, default_value = "runs")]
+        what: HistoryKind,
+        /// Only look at the most recent N runs
+        #[arg(long, value_name = "N", default_value_t = 10)]
+        last: usize,
+    },
+    /// Print the workflow's task graph (or the traversal of the plan/tasks given) to stdout
+    Viz {
+        /// Graph format
+        #[arg(long, value_enum, default_value = "dot")]
+        format: VizFormat,
+    },
+    /// Print a build file for the plan (or the tasks given) to stdout, for make, Snakemake, or CWL
+    Export {
+        /// Build file format
+        #[arg(long, value_enum, default_value = "make")]
+        format: ExportFormat,
+    },
+    /// Serve the workflow, the status of its plans, and task logs over HTTP, for dashboards
+    Serve {
+        /// Address to listen on
+        #[arg(long, value_name = "ADDR", default_value = "127.0.0.1:8080")]
+        addr: String,
+    },
+    /// Create a starter config file (rebuild.hr) and project settings file in a dir
+    Init {
+        /// Dir to create them in
+        #[arg(value_name = "DIR", default_value = ".")]
+        dir: String,
+        /// Overwrite files that already exist
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+/// Formats for reporting the actions and results of a run.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Human-readable text on stderr
+    Text,
+    /// Also print a JSON summary of the actions and results to stdout
+    /// (task stdout goes to stderr instead, to keep stdout machine-readable)
+    Json,
+}
+
+/// Which runs a notification is sent after.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NotifyOn {
+    /// Every run
+    #[default]
+    Always,
+    /// Runs that succeed
+    Success,
+    /// Runs that fail (or are cancelled)
+    Failure,
+}
+
+/// Formats for log messages.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human-readable text
+    Text,
+    /// One JSON object per line, with the timestamp, level, phase, task, branch, and message
+    /// (for log collectors like Loki or Elasticsearch)
+    Json,
+}
+
+/// Things to list with 'hr list'.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListKind {
+    /// Tasks, plans, and branchpoints
+    All,
+    /// Tasks, with their modules, inputs, outputs, and params
+    Tasks,
+    /// Plans, with their goals
+    Plans,
+    /// Branchpoints, with their known branches and baselines
+    Branchpoints,
+}
+
+/// Things to show with 'hr history'.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryKind {
+    /// Each run, with its result and how many of its tasks completed
+    Runs,
+    /// Tasks that failed, with how often and in which runs
+    Failures,
+    /// Average, min, and max duration of each task that completed
+    Durations,
+}
+
+/// Build file formats for 'hr export'.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// Makefile with a target for each task realization
+    Make,
+    /// Snakefile with a rule for each task, whose wildcards are its branchpoints
+    Snakemake,
+    /// CWL workflow with a tool for each task, whose inputs include the branchpoints
+    Cwl,
+}
+
+impl ExportFormat {
+    /// Whether the format exports the task realizations prepared for a run,
+    /// rather than the abstract workflow.
+    pub fn is_prepared(self) -> bool {
+        matches!(self, Self::Make)
+    }
+}
+
+/// Other workflow tools whose config files we can read, for '--compat'.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compat {
+    /// Ducttape .tape files; features we have no equivalent for are reported and left out
+    Ducttape,
+}
+
+/// Output formats for 'hr viz'.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VizFormat {
+    /// Graphviz DOT
+    Dot,
+    /// Mermaid flowchart, e.g. for pasting into docs
+    Mermaid,
+    /// JSON nodes and edges, with branches and resolved values of task realizations
+    Json,
 }
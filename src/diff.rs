@@ -0,0 +1,579 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Write;
+
+use anyhow::Result;
+
+use intern::{GetStr, InternStr};
+use util::HashMap;
+use workflow::{
+    AbstractValueId, BranchSpec, RealTaskKey, Value, Workflow, BRANCH_DELIM, BRANCH_KV_DELIM,
+};
+
+use crate::fs::Fs;
+use crate::prep::Probe;
+
+/// Name of the part of a task that's its code, which is too long to show.
+const CODE: &str = "code";
+
+/// Kinds of things defined in a config file, in the order their changes are shown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Kind {
+    Task,
+    Global,
+    Branchpoint,
+    Plan,
+}
+
+impl Kind {
+    /// What goes between the name and the description of a part that was added or removed.
+    fn sep(self) -> &'static str {
+        match self {
+            Self::Plan => " via ",
+            _ => "=",
+        }
+    }
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Task => "task",
+            Self::Global => "global",
+            Self::Branchpoint => "branchpoint",
+            Self::Plan => "plan",
+        })
+    }
+}
+
+/// Named parts of something defined in a config file, e.g. each input of a task,
+/// with a description of each to compare, e.g. `"< in" => "$out@build"`.
+type Parts = BTreeMap<String, String>;
+
+/// A branch, as (branchpoint, branch) names sorted by branchpoint, e.g. `[("Os", "mac")]`.
+pub type Branch = Vec<(String, String)>;
+
+/// The branches of a branched part, with a description of its value on each.
+type Arms = BTreeMap<Branch, String>;
+
+/// Something defined in a config file, broken down into parts.
+#[derive(Debug, Default)]
+struct Item {
+    parts: Parts,
+    /// each branched part's value on each of its branches, by part name.
+    arms: BTreeMap<String, Arms>,
+}
+
+impl Item {
+    fn insert_value(&mut self, name: String, v: AbstractValueId, wf: &Workflow) -> Result<()> {
+        let val = wf.get_value(v)?;
+        if let Value::Branched(vals) = val {
+            let mut arms = Arms::new();
+            for (branch, val) in vals {
+                arms.insert(branch_names(branch, wf)?, val.describe(&wf.strings)?);
+            }
+            self.arms.insert(name.clone(), arms);
+        }
+        self.parts.insert(name, val.describe(&wf.strings)?);
+        Ok(())
+    }
+}
+
+/// How one part of something defined in both versions of a config file changed.
+#[derive(Debug, PartialEq, Eq)]
+pub enum PartChange {
+    Added(String),
+    Removed(String),
+    /// name, old description, new description.
+    Changed(String, String, String),
+}
+
+/// A task in both versions of a config file whose realizations would run again.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ChangedTask {
+    pub name: String,
+    /// The branches of the realizations that would run again (which may not have been
+    /// defined in the old version), or None if all of them would.
+    pub branches: Option<Vec<Branch>>,
+}
+
+impl ChangedTask {
+    /// The realizations of this task that would run again, as branches to match against:
+    /// unspecified branchpoints match any branch.
+    pub fn branch_specs(&self, wf: &mut Workflow) -> Result<Vec<BranchSpec>> {
+        let Some(branches) = &self.branches else {
+            return Ok(vec![BranchSpec::default()]);
+        };
+        let mut specs = Vec::with_capacity(branches.len());
+        for branch in branches {
+            let mut spec = BranchSpec::default();
+            for (k, v) in branch {
+                spec.insert(
+                    wf.strings.branchpoints.intern(k)?,
+                    wf.strings.idents.intern(v)?,
+                );
+            }
+            specs.push(spec);
+        }
+        Ok(specs)
+    }
+
+    /// Add the realizations on `branches` (None for all of them) to the ones that run again.
+    fn add(&mut self, branches: Option<Vec<Branch>>) {
+        match (&mut self.branches, branches) {
+            (Some(ours), Some(theirs)) => {
+                for branch in theirs {
+                    if !ours.contains(&branch) {
+                        ours.push(branch);
+                    }
+                }
+            }
+            (ours, _) => *ours = None,
+        }
+    }
+}
+
+/// How something defined in a config file (e.g. "task build") changed between versions.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Change {
+    Added(String),
+    Removed(String),
+    Changed(String, Vec<PartChange>),
+}
+
+/// What changed between two versions of a workflow, for 'hr diff'.
+#[derive(Debug, Default)]
+pub struct WorkflowDiff {
+    pub changes: Vec<Change>,
+    /// Tasks in both versions whose realizations would run again, b/c their code, inputs,
+    /// or params changed, or they use a global config value that did. A change to
+    /// some branches of a branched value only affects the realizations on those branches.
+    /// The tasks that depend on them aren't included.
+    pub changed_tasks: Vec<ChangedTask>,
+}
+
+impl WorkflowDiff {
+    pub fn new(old: &Workflow, new: &Workflow) -> Result<Self> {
+        let old_items = summarize(old)?;
+        let new_items = summarize(new)?;
+
+        let mut keys: Vec<&(Kind, String)> = old_items.keys().chain(new_items.keys()).collect();
+        keys.sort_unstable();
+        keys.dedup();
+
+        let mut diff = Self::default();
+        let mut changed_config: HashMap<&str, Option<Vec<Branch>>> = HashMap::default();
+        for key @ (kind, name) in keys {
+            let label = match kind {
+                Kind::Global => kind.to_string(),
+                kind => format!("{kind} {name}"),
+            };
+            let (old_item, new_item) = match (old_items.get(key), new_items.get(key)) {
+                (Some(old_item), Some(new_item)) => (old_item, new_item),
+                (Some(_), None) => {
+                    diff.changes.push(Change::Removed(label));
+                    continue;
+                }
+                _ => {
+                    diff.changes.push(Change::Added(label));
+                    continue;
+                }
+            };
+            let parts = compare(&old_item.parts, &new_item.parts, kind.sep());
+            if parts.is_empty() {
+                continue;
+            }
+            match kind {
+                Kind::Task => {
+                    let names: Vec<&str> = parts
+                        .iter()
+                        .filter(|part| affects_realizations(part))
+                        .map(part_name)
+                        .collect();
+                    if !names.is_empty() {
+                        diff.changed_tasks.push(ChangedTask {
+                            name: name.clone(),
+                            branches: changed_branches(&names, old_item, new_item),
+                        });
+                    }
+                }
+                Kind::Global => {
+                    for part in &parts {
+                        let name = part_name(part);
+                        let key = new_item.parts.get_key_value(name);
+                        let Some((name, _)) = key.or(old_item.parts.get_key_value(name)) else {
+                            continue;
+                        };
+                        changed_config.insert(name, changed_branches(&[name], old_item, new_item));
+                    }
+                }
+                _ => (),
+            }
+            diff.changes.push(Change::Changed(label, parts));
+        }
+
+        // tasks that use config values that changed, on the branches they changed on:
+        for (id, name) in new.strings.tasks.iter() {
+            let in_both =
+                new.get_task(id).is_ok() && old_items.contains_key(&(Kind::Task, name.to_owned()));
+            if !in_both {
+                continue;
+            }
+            for k in new.config_values_used_by(id)? {
+                let Some(branches) = changed_config.get(new.strings.idents.get(k)?) else {
+                    continue;
+                };
+                match diff.changed_tasks.iter_mut().find(|task| task.name == name) {
+                    Some(task) => task.add(branches.clone()),
+                    None => diff.changed_tasks.push(ChangedTask {
+                        name: name.to_owned(),
+                        branches: branches.clone(),
+                    }),
+                }
+            }
+        }
+        diff.changed_tasks.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(diff)
+    }
+}
+
+/// Whether a change to this part of a task changes the fingerprint of its realizations:
+/// outputs and dot params aren't part of it.
+fn affects_realizations(part: &PartChange) -> bool {
+    !part_name(part).starts_with(['>', '.'])
+}
+
+/// The name of the part that changed (for parts that were added or removed, followed by
+/// a description of their value).
+fn part_name(part: &PartChange) -> &str {
+    match part {
+        PartChange::Changed(name, ..) => name,
+        PartChange::Added(name) | PartChange::Removed(name) => match name.split_once('=') {
+            Some((name, _)) => name,
+            None => name,
+        },
+    }
+}
+
+/// The branches on which the `names` parts of an item differ between versions, or None if
+/// one of them differs on every branch, e.g. b/c it wasn't branched in both versions.
+fn changed_branches(names: &[&str], old: &Item, new: &Item) -> Option<Vec<Branch>> {
+    let mut branches = Vec::new();
+    for name in names {
+        let (old_arms, new_arms) = (old.arms.get(*name)?, new.arms.get(*name)?);
+        for branch in old_arms.keys().chain(new_arms.keys()) {
+            if old_arms.get(branch) != new_arms.get(branch) && !branches.contains(branch) {
+                branches.push(branch.clone());
+            }
+        }
+    }
+    Some(branches)
+}
+
+/// The names of the branchpoints and branches of `branch`, sorted by branchpoint.
+fn branch_names(branch: &BranchSpec, wf: &Workflow) -> Result<Branch> {
+    let mut names = Vec::with_capacity(branch.len());
+    for (k, v) in branch.specified() {
+        names.push((
+            wf.strings.branchpoints.get(k)?.to_owned(),
+            wf.strings.idents.get(v)?.to_owned(),
+        ));
+    }
+    names.sort_unstable();
+    Ok(names)
+}
+
+/// Everything defined in `wf`, by kind and name, broken down into parts.
+fn summarize(wf: &Workflow) -> Result<BTreeMap<(Kind, String), Item>> {
+    let mut items = BTreeMap::new();
+
+    for (id, name) in wf.strings.tasks.iter() {
+        // tasks that are referenced but never defined aren't compared:
+        let Ok(task) = wf.get_task(id) else {
+            continue;
+        };
+        let mut item = Item::default();
+        let parts = &mut item.parts;
+        parts.insert(
+            CODE.to_owned(),
+            wf.strings.literals.get(task.code)?.to_owned(),
+        );
+        let vars = [
+            ("<", &task.vars.inputs),
+            (">", &task.vars.outputs),
+            ("::", &task.vars.params),
+        ];
+        for (sigil, vars) in vars {
+            for (k, v) in vars {
+                item.insert_value(format!("{sigil} {}", wf.strings.idents.get(*k)?), *v, wf)?;
+            }
+        }
+        let parts = &mut item.parts;
+        for module in &task.modules {
+            parts.insert(
+                format!("@{}", wf.strings.modules.get(*module)?),
+                String::new(),
+            );
+        }
+        if let Some(size) = task.max_output_size {
+            parts.insert(".max_output_size".to_owned(), size.to_string());
+        }
+        for (k, v) in &task.dot_params {
+            let k = wf.strings.idents.get(*k)?;
+            parts.insert(format!(".{k}"), wf.strings.literals.get(*v)?.to_owned());
+        }
+        items.insert((Kind::Task, name.to_owned()), item);
+    }
+
+    let mut global = Item::default();
+    for (k, v) in wf.config_values() {
+        global.insert_value(wf.strings.idents.get(k)?.to_owned(), v, wf)?;
+    }
+    items.insert((Kind::Global, String::new()), global);
+
+    for (k, name) in wf.strings.branchpoints.iter() {
+        let mut parts = Parts::new();
+        for v in wf.strings.baselines.values(k) {
+            parts.insert(
+                format!("branch {}", wf.strings.idents.get(*v)?),
+                String::new(),
+            );
+        }
+        parts.insert(
+            "baseline".to_owned(),
+            wf.strings.idents.get(wf.strings.baselines.get(k))?.to_owned(),
+        );
+        items.insert(
+            (Kind::Branchpoint, name.to_owned()),
+            Item {
+                parts,
+                ..Default::default()
+            },
+        );
+    }
+
+    for (name, plan) in wf.plans() {
+        let mut plan = plan.clone();
+        plan.expand_globs(&wf.strings)?;
+        let mut parts = Parts::new();
+        for subplan in &plan.subplans {
+            let mut goals = Vec::with_capacity(subplan.goals.len());
+            for goal in &subplan.goals {
+                goals.push(wf.strings.tasks.get(*goal)?);
+            }
+            let mut branches = Vec::with_capacity(subplan.branches.len());
+            for spec in &subplan.branches {
+                let mut branch = Vec::with_capacity(spec.len());
+                for (k, v) in spec.specified() {
+                    let (k, v) = (wf.strings.branchpoints.get(k)?, wf.strings.idents.get(v)?);
+                    branch.push(format!("{k}{BRANCH_KV_DELIM}{v}"));
+                }
+                branches.push(branch.join(&BRANCH_DELIM.to_string()));
+            }
+            // the same goals may be reached on different branches on different lines:
+            let goals = format!("reach {}", goals.join(", "));
+            let mut key = goals.clone();
+            for i in 2.. {
+                if !parts.contains_key(&key) {
+                    break;
+                }
+                key = format!("{goals} ({i})");
+            }
+            parts.insert(key, branches.join(" "));
+        }
+        items.insert(
+            (Kind::Plan, wf.strings.idents.get(name)?.to_owned()),
+            Item {
+                parts,
+                ..Default::default()
+            },
+        );
+    }
+    Ok(items)
+}
+
+/// How each part that's different in `new` than in `old` changed.
+fn compare(old: &Parts, new: &Parts, sep: &str) -> Vec<PartChange> {
+    let describe = |k: &str, v: &str| match v {
+        "" => k.to_owned(),
+        v => format!("{k}{sep}{v}"),
+    };
+    let mut keys: Vec<&String> = old.keys().chain(new.keys()).collect();
+    keys.sort_unstable();
+    keys.dedup();
+    let mut changes = Vec::new();
+    for k in keys {
+        match (old.get(k), new.get(k)) {
+            (Some(old), Some(new)) if old != new => {
+                changes.push(PartChange::Changed(k.clone(), old.clone(), new.clone()));
+            }
+            (Some(old), None) => changes.push(PartChange::Removed(describe(k, old))),
+            (None, Some(new)) => changes.push(PartChange::Added(describe(k, new))),
+            _ => (),
+        }
+    }
+    changes
+}
+
+/// Write the changes in `diff` to `out`, e.g.:
+/// ```text
+/// ~ task build
+///     ~ :: profile: debug -> release
+///     ~ code
+/// + task package
+/// ```
+pub fn write_diff(diff: &WorkflowDiff, out: &mut impl Write) -> Result<()> {
+    if diff.changes.is_empty() {
+        writeln!(out, "No differences.")?;
+    }
+    for change in &diff.changes {
+        match change {
+            Change::Added(label) => writeln!(out, "+ {label}")?,
+            Change::Removed(label) => writeln!(out, "- {label}")?,
+            Change::Changed(label, parts) => {
+                writeln!(out, "~ {label}")?;
+                for part in parts {
+                    match part {
+                        PartChange::Added(part) => writeln!(out, "    + {part}")?,
+                        PartChange::Removed(part) => writeln!(out, "    - {part}")?,
+                        PartChange::Changed(name, _, _) if name == CODE => {
+                            writeln!(out, "    ~ {CODE}")?;
+                        }
+                        PartChange::Changed(name, old, new) => {
+                            writeln!(out, "    ~ {name}: {old} -> {new}")?;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Write the ones of the `invalidated` realizations that exist in the output dir to `out`,
+/// with their state, followed by how many there are.
+pub fn write_invalidated(
+    invalidated: &[RealTaskKey],
+    wf: &Workflow,
+    fs: &Fs,
+    out: &mut impl Write,
+) -> Result<()> {
+    let mut locator = fs.locator();
+    let mut strbuf = String::with_capacity(128);
+    let mut rows = Vec::new();
+    for key in invalidated {
+        locator.locate_key(key, wf, &mut strbuf)?;
+        let probe = Probe::new(locator.realization(), fs)?;
+        if probe.exists {
+            let state = if probe.succeeded {
+                "complete"
+            } else {
+                "incomplete"
+            };
+            rows.push((state, wf.strings.get_real_task_str(key)?.to_owned()));
+        }
+    }
+    if rows.is_empty() {
+        writeln!(out, "\nNo existing realizations would run again.")?;
+        return Ok(());
+    }
+    writeln!(out, "\nExisting realizations that would run again:")?;
+    writeln!(out, "{:<11}  REALIZATION", "STATE")?;
+    for (state, realization) in &rows {
+        writeln!(out, "{state:<11}  {realization}")?;
+    }
+    writeln!(out, "\n{} realizations would run again.", rows.len())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::Path;
+
+    fn load(text: &str) -> Result<Workflow> {
+        let mut wf = Workflow::default();
+        wf.load(syntax::parse(text)?, Path::new("."))?;
+        Ok(wf)
+    }
+
+    #[test]
+    fn test_diff() -> Result<()> {
+        let old = load(
+            "global {\n  name=a\n  greeting=\"hi $name\"\n  cc=(Os: mac=clang win=cl)\n}\n\
+             plan main {\n  reach test via (Os: mac)\n}\n\
+             task build > out :: os=(Os: mac win) cc=$cc { touch $out }\n\
+             task test < in=$out@build :: g=$greeting { cat $in }\n\
+             task docs { echo docs }\n\
+             task old { true }\n",
+        )?;
+        let new = load(
+            "global {\n  name=b\n  greeting=\"hi $name\"\n  cc=(Os: mac=clang win=msvc)\n}\n\
+             plan main {\n  reach test via (Os: *)\n}\n\
+             task build > out=build.txt :: os=(Os: mac win linux) cc=$cc { touch $out }\n\
+             task test < in=$out@build :: g=$greeting { cat $in }\n\
+             task docs :: .cpus=2 { echo docs; }\n\
+             task new { true }\n",
+        )?;
+        let diff = WorkflowDiff::new(&old, &new)?;
+        let mut out = Vec::new();
+        write_diff(&diff, &mut out)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "~ task build\n    \
+               ~ :: os: (Os: mac=mac win=win) -> (Os: mac=mac win=win linux=linux)\n    \
+               ~ > out: out -> build.txt\n\
+             ~ task docs\n    \
+               + .cpus=2\n    \
+               ~ code\n\
+             + task new\n\
+             - task old\n\
+             ~ global\n    \
+               ~ cc: (Os: mac=clang win=cl) -> (Os: mac=clang win=msvc)\n    \
+               ~ name: a -> b\n\
+             ~ branchpoint Os\n    \
+               + branch linux\n\
+             ~ plan main\n    \
+               ~ reach test: Os.mac -> Os.mac Os.win Os.linux\n"
+        );
+        // build's param only changed on the new Os.linux branch, and the $cc it uses only on
+        // Os.win; docs' code changed; test uses $name (on every branch) through $greeting:
+        let branch = |v: &str| vec![("Os".to_owned(), v.to_owned())];
+        let changed = |name: &str, branches: Option<Vec<Branch>>| ChangedTask {
+            name: name.to_owned(),
+            branches,
+        };
+        assert_eq!(
+            diff.changed_tasks,
+            vec![
+                changed("build", Some(vec![branch("linux"), branch("win")])),
+                changed("docs", None),
+                changed("test", None),
+            ]
+        );
+
+        // only the realizations on the changed branches are invalidated:
+        let mut new = new;
+        let mut invalidated = Vec::new();
+        for task in &diff.changed_tasks[..1] {
+            let id = new.strings.tasks.intern(&task.name)?;
+            let branches = task.branch_specs(&mut new)?;
+            for key in crate::affected::find_affected(&new, &[id], &branches)? {
+                invalidated.push(new.strings.get_real_task_str(&key)?.to_owned());
+            }
+        }
+        assert_eq!(
+            invalidated,
+            [
+                "build[Os.win]",
+                "build[Os.linux]",
+                "test[Os.win]",
+                "test[Os.linux]"
+            ]
+        );
+
+        let diff = WorkflowDiff::new(&new, &new)?;
+        assert!(diff.changes.is_empty() && diff.changed_tasks.is_empty());
+        Ok(())
+    }
+}
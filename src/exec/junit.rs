@@ -0,0 +1,162 @@
+use std::io::Write;
+
+use anyhow::Result;
+
+/// Name of the single test suite in the report.
+const SUITE_NAME: &str = "heron-rebuild";
+
+/// How many lines from the end of a failed task's `stderr.txt` to include in the report.
+pub const STDERR_TAIL_LINES: usize = 50;
+
+/// One task realization, as a JUnit test case.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestCase {
+    /// Task and branch, e.g. 'task[Profile.debug]'
+    pub name: String,
+    /// Name of the task
+    pub classname: String,
+    /// Duration in seconds (0 if it didn't run)
+    pub time: f64,
+    pub outcome: Outcome,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Passed,
+    /// Error message, and the tail of the task's stderr
+    Failed(String, String),
+    /// Reason it didn't run
+    Skipped(&'static str),
+}
+
+/// Write `cases` to `out` as a JUnit XML document with a single test suite.
+pub fn write_junit(cases: &[TestCase], out: &mut impl Write) -> Result<()> {
+    let failures = cases.iter().filter(|c| matches!(c.outcome, Outcome::Failed(..))).count();
+    let skipped = cases.iter().filter(|c| matches!(c.outcome, Outcome::Skipped(_))).count();
+    let time: f64 = cases.iter().map(|c| c.time).sum();
+
+    writeln!(out, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+    writeln!(
+        out,
+        "<testsuites tests=\"{}\" failures=\"{failures}\" skipped=\"{skipped}\" time=\"{time:.3}\">",
+        cases.len()
+    )?;
+    writeln!(
+        out,
+        "  <testsuite name=\"{SUITE_NAME}\" tests=\"{}\" failures=\"{failures}\" skipped=\"{skipped}\" time=\"{time:.3}\">",
+        cases.len()
+    )?;
+    for case in cases {
+        write!(
+            out,
+            "    <testcase name=\"{}\" classname=\"{}\" time=\"{:.3}\"",
+            escape(&case.name),
+            escape(&case.classname),
+            case.time
+        )?;
+        match &case.outcome {
+            Outcome::Passed => writeln!(out, "/>")?,
+            Outcome::Failed(message, details) => {
+                writeln!(
+                    out,
+                    ">\n      <failure message=\"{}\">{}</failure>\n    </testcase>",
+                    escape(message),
+                    escape(details)
+                )?;
+            }
+            Outcome::Skipped(reason) => {
+                writeln!(
+                    out,
+                    ">\n      <skipped message=\"{}\"/>\n    </testcase>",
+                    escape(reason)
+                )?;
+            }
+        }
+    }
+    writeln!(out, "  </testsuite>\n</testsuites>")?;
+    Ok(())
+}
+
+/// The last `n` lines of `text`.
+pub fn tail(text: &str, n: usize) -> &str {
+    if n == 0 {
+        return "";
+    }
+    let text = text.trim_end_matches('\n');
+    match text.rmatch_indices('\n').nth(n - 1) {
+        Some((i, _)) => &text[i + 1..],
+        None => text,
+    }
+}
+
+/// Escape text for use in an XML attribute or element,
+/// dropping control characters that XML 1.0 doesn't allow.
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\n' | '\r' | '\t' => escaped.push(c),
+            c if (c as u32) < 0x20 => (),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_write_junit() -> Result<()> {
+        let case = |name: &str, time, outcome| TestCase {
+            name: name.to_owned(),
+            classname: name[..1].to_owned(),
+            time,
+            outcome,
+        };
+        let cases = [
+            case("a[X.x1]", 1.5, Outcome::Passed),
+            case(
+                "b[X.x1]",
+                0.25,
+                Outcome::Failed(
+                    "Subprocess failed".to_owned(),
+                    "x < y\n\x1b[31mno\n".to_owned(),
+                ),
+            ),
+            case("c[X.x1]", 0.0, Outcome::Skipped("not run")),
+        ];
+        let mut out = Vec::new();
+        write_junit(&cases, &mut out)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <testsuites tests=\"3\" failures=\"1\" skipped=\"1\" time=\"1.750\">\n  \
+               <testsuite name=\"heron-rebuild\" tests=\"3\" failures=\"1\" skipped=\"1\" time=\"1.750\">\n    \
+                 <testcase name=\"a[X.x1]\" classname=\"a\" time=\"1.500\"/>\n    \
+                 <testcase name=\"b[X.x1]\" classname=\"b\" time=\"0.250\">\n      \
+                   <failure message=\"Subprocess failed\">x &lt; y\n[31mno\n</failure>\n    \
+                 </testcase>\n    \
+                 <testcase name=\"c[X.x1]\" classname=\"c\" time=\"0.000\">\n      \
+                   <skipped message=\"not run\"/>\n    \
+                 </testcase>\n  \
+               </testsuite>\n\
+             </testsuites>\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_tail() {
+        assert_eq!(tail("a\nb\nc\n", 2), "b\nc");
+        assert_eq!(tail("a\nb\nc", 5), "a\nb\nc");
+        assert_eq!(tail("a\nb\nc", 0), "");
+        assert_eq!(tail("", 3), "");
+    }
+}
@@ -0,0 +1,212 @@
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+use crate::fs::TaskRecord;
+
+/// States a task can be in during a run, as recorded in `TaskRecord::state`.
+const TASK_STATES: [&str; 6] = [
+    "completed",
+    "restored",
+    "failed",
+    "running",
+    "cancelled",
+    "not-run",
+];
+
+/// A run, as Prometheus metrics. Timestamps are seconds since the unix epoch.
+#[derive(Debug)]
+pub struct RunMetrics<'a> {
+    /// Plans (or tasks) the run is for, used as the `plan` label
+    pub plans: &'a str,
+    pub started: u64,
+    /// When the run finished and whether it succeeded, or None if it's still running
+    pub finished: Option<(u64, bool)>,
+    /// Every task the run is supposed to run, in order
+    pub tasks: &'a [TaskRecord],
+    /// Numbers of succeeded and failed runs in the output dir's history, if it has one
+    pub runs_total: Option<(u64, u64)>,
+}
+
+/// Write `metrics` to `out` in the Prometheus text exposition format.
+pub fn write_metrics(metrics: &RunMetrics, out: &mut impl Write) -> Result<()> {
+    let plan = format!("plan=\"{}\"", escape(metrics.plans));
+
+    let in_progress = u8::from(metrics.finished.is_none());
+    metric(
+        out,
+        "hr_run_in_progress",
+        "gauge",
+        "Whether a run is in progress.",
+    )?;
+    writeln!(out, "hr_run_in_progress{{{plan}}} {in_progress}")?;
+    metric(
+        out,
+        "hr_run_start_timestamp_seconds",
+        "gauge",
+        "When the most recent run started.",
+    )?;
+    writeln!(
+        out,
+        "hr_run_start_timestamp_seconds{{{plan}}} {}",
+        metrics.started
+    )?;
+    if let Some((finished, succeeded)) = metrics.finished {
+        metric(
+            out,
+            "hr_run_duration_seconds",
+            "gauge",
+            "How long the most recent run took.",
+        )?;
+        let duration = finished.saturating_sub(metrics.started);
+        writeln!(out, "hr_run_duration_seconds{{{plan}}} {duration}")?;
+        metric(
+            out,
+            "hr_run_success",
+            "gauge",
+            "Whether the most recent run succeeded.",
+        )?;
+        writeln!(out, "hr_run_success{{{plan}}} {}", u8::from(succeeded))?;
+    }
+
+    let queued = metrics.tasks.iter().filter(|t| t.state == "not-run").count();
+    metric(
+        out,
+        "hr_run_queue_depth",
+        "gauge",
+        "Tasks in the run that haven't started yet.",
+    )?;
+    writeln!(out, "hr_run_queue_depth{{{plan}}} {queued}")?;
+    metric(out, "hr_run_tasks", "gauge", "Tasks in the run, by state.")?;
+    for state in TASK_STATES {
+        let n = metrics.tasks.iter().filter(|t| t.state == state).count();
+        writeln!(out, "hr_run_tasks{{{plan},state=\"{state}\"}} {n}")?;
+    }
+
+    metric(
+        out,
+        "hr_task_duration_seconds",
+        "gauge",
+        "How long each task realization took in the run.",
+    )?;
+    for task in metrics.tasks.iter().filter(|t| t.state != "failed") {
+        if let Some(duration) = task.duration {
+            writeln!(
+                out,
+                "hr_task_duration_seconds{{{}}} {duration}",
+                task_labels(&plan, task)
+            )?;
+        }
+    }
+    metric(
+        out,
+        "hr_task_success",
+        "gauge",
+        "Whether each task realization that finished in the run succeeded.",
+    )?;
+    for task in metrics.tasks.iter().filter(|t| t.duration.is_some()) {
+        let success = u8::from(task.state != "failed");
+        writeln!(
+            out,
+            "hr_task_success{{{}}} {success}",
+            task_labels(&plan, task)
+        )?;
+    }
+
+    if let Some((succeeded, failed)) = metrics.runs_total {
+        metric(
+            out,
+            "hr_runs_total",
+            "counter",
+            "Runs recorded in the output dir's history, by result.",
+        )?;
+        writeln!(out, "hr_runs_total{{result=\"succeeded\"}} {succeeded}")?;
+        writeln!(out, "hr_runs_total{{result=\"failed\"}} {failed}")?;
+    }
+    Ok(())
+}
+
+/// Write `metrics` to the file at `path`, replacing it atomically
+/// so that a collector never reads a partial file.
+pub fn write_metrics_file(path: &Path, metrics: &RunMetrics) -> Result<()> {
+    let mut text = Vec::with_capacity(2048);
+    write_metrics(metrics, &mut text)?;
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    std::fs::write(&tmp, text).with_context(|| format!("while writing metrics file {tmp:?}"))?;
+    std::fs::rename(&tmp, path).with_context(|| format!("while moving metrics file to {path:?}"))
+}
+
+fn metric(out: &mut impl Write, name: &str, kind: &str, help: &str) -> Result<()> {
+    writeln!(out, "# HELP {name} {help}\n# TYPE {name} {kind}")?;
+    Ok(())
+}
+
+fn task_labels(plan: &str, task: &TaskRecord) -> String {
+    format!(
+        "{plan},task=\"{}\",realization=\"{}\"",
+        escape(&task.task),
+        escape(&task.realization)
+    )
+}
+
+/// Escape a label value.
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_write_metrics() -> Result<()> {
+        let task = |task: &str, state, duration| TaskRecord {
+            task: task.to_owned(),
+            realization: format!("{task}[X.x1]"),
+            state,
+            duration,
+        };
+        let tasks = [
+            task("a", "completed", Some(1.5)),
+            task("b", "failed", Some(0.25)),
+            task("c", "not-run", None),
+        ];
+        let metrics = RunMetrics {
+            plans: "main",
+            started: 100,
+            finished: Some((160, false)),
+            tasks: &tasks,
+            runs_total: Some((3, 1)),
+        };
+        let mut out = Vec::new();
+        write_metrics(&metrics, &mut out)?;
+        let text = String::from_utf8(out)?;
+        let samples: Vec<&str> = text.lines().filter(|line| !line.starts_with('#')).collect();
+        assert_eq!(
+            samples,
+            [
+                "hr_run_in_progress{plan=\"main\"} 0",
+                "hr_run_start_timestamp_seconds{plan=\"main\"} 100",
+                "hr_run_duration_seconds{plan=\"main\"} 60",
+                "hr_run_success{plan=\"main\"} 0",
+                "hr_run_queue_depth{plan=\"main\"} 1",
+                "hr_run_tasks{plan=\"main\",state=\"completed\"} 1",
+                "hr_run_tasks{plan=\"main\",state=\"restored\"} 0",
+                "hr_run_tasks{plan=\"main\",state=\"failed\"} 1",
+                "hr_run_tasks{plan=\"main\",state=\"running\"} 0",
+                "hr_run_tasks{plan=\"main\",state=\"cancelled\"} 0",
+                "hr_run_tasks{plan=\"main\",state=\"not-run\"} 1",
+                "hr_task_duration_seconds{plan=\"main\",task=\"a\",realization=\"a[X.x1]\"} 1.5",
+                "hr_task_success{plan=\"main\",task=\"a\",realization=\"a[X.x1]\"} 1",
+                "hr_task_success{plan=\"main\",task=\"b\",realization=\"b[X.x1]\"} 0",
+                "hr_runs_total{result=\"succeeded\"} 3",
+                "hr_runs_total{result=\"failed\"} 1",
+            ]
+        );
+        assert!(text.contains("# TYPE hr_runs_total counter\n"));
+        assert_eq!(escape("a\"b\\c\n"), "a\\\"b\\\\c\\n");
+        Ok(())
+    }
+}
@@ -1,9 +1,29 @@
 /// Runs the workflow
 mod workflow_runner;
-pub use workflow_runner::WorkflowRunner;
+pub use workflow_runner::{TaskReport, WorkflowRunner};
+
+/// JUnit XML reports of a run
+mod junit;
+pub use junit::{write_junit, TestCase};
+
+/// Prometheus metrics about a run
+mod metrics;
+
+/// Events for following the progress of a run
+mod observer;
+pub use observer::{ExecObserver, TaskEvent, TaskPrinter};
+
+/// Notifying someone when a run finishes
+mod notify;
+pub use notify::Notifier;
+
+/// OpenTelemetry traces of a run
+mod trace;
+pub use trace::Trace;
 
 /// Run a subprocess
 mod run_cmd;
+pub use run_cmd::Watchdog;
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -11,4 +31,16 @@ pub enum Error {
     ExpectedFileNotFound(String),
     #[error("Subprocess failed")]
     SubprocessFailed,
+    #[error("Realization dir {0} is {1} bytes, which exceeds the task's .max_output_size of {2}")]
+    OutputTooLarge(String, u64, u64),
+    #[error("Task was killed after producing no output for {0}s")]
+    IdleTimeout(u64),
+    #[error("Workflow was cancelled")]
+    Cancelled,
+    #[error("Task was killed because it was cancelled")]
+    TaskCancelled,
+    #[error("hr crashed while running the task")]
+    TaskPanicked,
+    #[error("Unable to send trace to {0}: {1}")]
+    TraceExport(String, String),
 }
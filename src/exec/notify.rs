@@ -0,0 +1,64 @@
+use std::process::Command;
+use std::time::Duration;
+
+use crate::args::NotifyOn;
+
+use super::observer::{ExecObserver, TaskEvent};
+
+/// Runs a shell command when a run finishes, e.g. to send a desktop notification
+/// or post to a chat, with `$HR_RUN_STATUS` set to "succeeded" or "failed"
+/// and `$HR_FAILED_TASKS` to the realizations that failed, separated by spaces.
+#[derive(Debug)]
+pub struct Notifier {
+    command: String,
+    on: NotifyOn,
+    failed: Vec<String>,
+}
+
+impl Notifier {
+    pub fn new(command: String, on: NotifyOn) -> Self {
+        Self {
+            command,
+            on,
+            failed: Vec::with_capacity(0),
+        }
+    }
+
+    fn notify(&self, succeeded: bool) -> std::io::Result<std::process::ExitStatus> {
+        Command::new("/bin/sh")
+            .arg("-c")
+            .arg(&self.command)
+            .env(
+                "HR_RUN_STATUS",
+                if succeeded { "succeeded" } else { "failed" },
+            )
+            .env("HR_FAILED_TASKS", self.failed.join(" "))
+            .status()
+    }
+}
+
+impl ExecObserver for Notifier {
+    fn on_task_failed(&mut self, task: &TaskEvent, _error: &anyhow::Error, _duration: Duration) {
+        self.failed.push(task.realization.to_owned());
+    }
+
+    fn on_workflow_complete(&mut self, succeeded: bool) {
+        let wanted = match self.on {
+            NotifyOn::Always => true,
+            NotifyOn::Success => succeeded,
+            NotifyOn::Failure => !succeeded,
+        };
+        if !wanted {
+            return;
+        }
+        // the run shouldn't fail b/c its notification did:
+        match self.notify(succeeded) {
+            Ok(status) if status.success() => (),
+            Ok(status) => log::warn!(
+                "notification command {:?} failed with {status}",
+                self.command
+            ),
+            Err(e) => log::warn!("unable to run notification command {:?}: {e}", self.command),
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use std::time::Duration;
+
+use colored::Colorize;
+
+use crate::run_log::mirror_eprintln;
+
+/// A task realization given to an [`ExecObserver`].
+#[derive(Debug, Clone, Copy)]
+pub struct TaskEvent<'a> {
+    /// name of the task
+    pub task: &'a str,
+    /// e.g. 'Profile.debug+Os.mac' (or 'Baseline.baseline')
+    pub branch: &'a str,
+    /// how the realization is printed, e.g. 'build[Profile.debug]'
+    pub realization: &'a str,
+    /// dir the task runs in
+    pub exec_dir: &'a str,
+}
+
+/// Receives events from `WorkflowRunner` as a workflow runs,
+/// so that progress can be displayed (or otherwise used) without changing the runner.
+/// Every method does nothing by default.
+pub trait ExecObserver {
+    /// A task is about to run (or have its outputs restored from the cache).
+    fn on_task_start(&mut self, _task: &TaskEvent) {}
+
+    /// A task succeeded, after `duration`; `restored` if its outputs came from the cache.
+    fn on_task_complete(&mut self, _task: &TaskEvent, _restored: bool, _duration: Duration) {}
+
+    /// A task failed with `error`, after `duration`; no more tasks will start.
+    fn on_task_failed(&mut self, _task: &TaskEvent, _error: &anyhow::Error, _duration: Duration) {}
+
+    /// The run is over, whether every task `succeeded` or not.
+    fn on_workflow_complete(&mut self, _succeeded: bool) {}
+}
+
+/// Prints each task's progress to stderr; the command-line app installs one of these.
+#[derive(Debug, Default)]
+pub struct TaskPrinter;
+
+impl ExecObserver for TaskPrinter {
+    fn on_task_start(&mut self, task: &TaskEvent) {
+        mirror_eprintln!(
+            "{} {}\nin {}\n",
+            "RUN".green(),
+            task.realization,
+            task.exec_dir
+        );
+    }
+
+    fn on_task_complete(&mut self, task: &TaskEvent, restored: bool, _duration: Duration) {
+        if restored {
+            mirror_eprintln!(
+                "{} outputs of {} from cache.",
+                "RESTORED".green(),
+                task.realization
+            );
+        }
+        mirror_eprintln!("{} {}.\n", "COMPLETED".green(), task.realization);
+    }
+
+    fn on_task_failed(&mut self, task: &TaskEvent, _error: &anyhow::Error, _duration: Duration) {
+        mirror_eprintln!(
+            "{} {}. To debug it interactively, use 'hr shell <task> -b <branch>'.\n",
+            "FAILED".red(),
+            task.realization
+        );
+    }
+
+    fn on_workflow_complete(&mut self, succeeded: bool) {
+        if succeeded {
+            mirror_eprintln!("{}\n", "Completed workflow.".green());
+        }
+    }
+}
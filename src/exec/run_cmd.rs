@@ -1,33 +1,88 @@
 use std::fs::File;
-use std::io::{stderr, stdout, Read, Write};
+use std::io::{stdout, Read, Write};
 use std::path::PathBuf;
-use std::process::{Command, Stdio};
-use std::thread;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use anyhow::{Context, Result};
 use colored::Colorize;
 
-use crate::fs::Fs;
+use crate::fs::{Artifact, Fs};
+use crate::run_log::{self, mirror_eprintln};
 
-/// Run a subprocess, storing stdout and stderr in the given `artifacts_dir`.
+use super::Error;
+
+/// How often we check on a running task.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// How long to wait for a task's stdout and stderr to close after it exits,
+/// in case something it left running in the background still holds them open.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Process groups of the tasks that are running now, so we can pass signals on to them.
+static RUNNING_GROUPS: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+fn running_groups() -> MutexGuard<'static, Vec<u32>> {
+    RUNNING_GROUPS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// What to do about tasks that stop producing output.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Watchdog {
+    /// How long a task may go without writing to stdout or stderr.
+    pub idle_timeout: Option<Duration>,
+    /// If true, kill idle tasks; otherwise just warn.
+    pub kill: bool,
+}
+
+/// How to run a task's command with `run_cmd`.
+#[derive(Debug, Clone, Copy)]
+pub struct CmdOptions<'a> {
+    /// Name of the task, so 'hr cancel --task' can find it.
+    pub task: &'a str,
+    pub verbose: bool,
+    pub watchdog: Watchdog,
+    /// Copy the task's stdout to our stderr instead of our stdout.
+    pub stdout_to_stderr: bool,
+}
+
+/// Run a subprocess, storing stdout and stderr in the given `artifacts_dir`,
+/// and return its exit status. Its stdout is also copied to ours, or to our stderr
+/// if `opts.stdout_to_stderr` is true. If 'hr cancel --kill' covers the task, it's killed.
 /// Based on:
 /// <https://stackoverflow.com/questions/66060139/how-to-tee-stdout-stderr-from-a-subprocess-in-rust>
 pub fn run_cmd(
     cmd: &mut Command,
     artifacts_dir: &str,
-    fs: &mut Fs,
+    fs: &Fs,
     pathbuf: &mut PathBuf,
-    verbose: bool,
-) -> Result<bool> {
+    opts: CmdOptions,
+) -> Result<ExitStatus> {
+    let CmdOptions {
+        verbose,
+        stdout_to_stderr,
+        ..
+    } = opts;
     if verbose {
-        eprintln!("{}", "Creating stdout and stderr files...".magenta());
+        mirror_eprintln!("{}", "Creating stdout and stderr files...".magenta());
     }
 
     let (out_file, err_file) = make_log_files(fs, artifacts_dir, pathbuf)?;
 
     if verbose {
-        eprintln!("{}", "Running command...".magenta());
+        mirror_eprintln!("{}", "Running command...".magenta());
     }
+    #[cfg(unix)]
+    {
+        // run in a new process group, so we can kill the task's subprocesses too.
+        // the terminal's ctrl-c no longer reaches it, so we pass that on ourselves:
+        use std::os::unix::process::CommandExt;
+        cmd.process_group(0);
+        forward_signals();
+    }
+
     let mut cmd = cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn().unwrap_or_else(|_| {
         panic!(
             "failed to execute child process {:?} {:?}",
@@ -36,31 +91,185 @@ pub fn run_cmd(
         )
     });
 
+    running_groups().push(cmd.id());
+
     let child_out = cmd.stdout.take().expect("Cannot attach to child stdout");
     let child_err = cmd.stderr.take().expect("Cannot attach to child stderr");
 
+    let last_output = Arc::new(AtomicU64::new(now_secs()));
+    let last_out = last_output.clone();
     let thread_out = thread::spawn(move || {
-        communicate(child_out, out_file, stdout()).expect("error communicating with child stdout")
+        let output: Box<dyn Write> = if stdout_to_stderr {
+            Box::new(run_log::Stderr)
+        } else {
+            Box::new(stdout())
+        };
+        communicate(child_out, out_file, output, &last_out)
+            .expect("error communicating with child stdout")
     });
+    let last_err = last_output.clone();
     let thread_err = thread::spawn(move || {
-        communicate(child_err, err_file, stderr()).expect("error communicating with child stderr")
+        communicate(child_err, err_file, run_log::Stderr, &last_err)
+            .expect("error communicating with child stderr")
     });
 
-    thread_out.join().expect("Error joining stdout thread");
-    thread_err.join().expect("Error joining stderr thread");
+    let watched = watch(&mut cmd, fs, pathbuf, opts, &last_output);
+    running_groups().retain(|group| *group != cmd.id());
+    // the task removes its heartbeat when it exits, unless it was killed:
+    let heartbeat = Artifact::Heartbeat.path_in(artifacts_dir, pathbuf);
+    if fs.exists(heartbeat) {
+        fs.delete_file(heartbeat)?;
+    }
+
+    let drained = Instant::now() + DRAIN_TIMEOUT;
+    join_until(thread_out, "stdout", drained);
+    join_until(thread_err, "stderr", drained);
 
-    let status = cmd.wait().expect("failed to wait on child process");
+    let status = watched?;
 
     if verbose {
-        eprintln!("\n{} with {status}.", "Process finished".green());
+        mirror_eprintln!("\n{} with {status}.", "Process finished".green());
+    }
+    Ok(status)
+}
+
+/// Wait for `child` to finish, enforcing the idle timeout and killing it if it's cancelled.
+fn watch(
+    child: &mut Child,
+    fs: &Fs,
+    pathbuf: &mut PathBuf,
+    opts: CmdOptions,
+    last_output: &AtomicU64,
+) -> Result<std::process::ExitStatus> {
+    let watchdog = opts.watchdog;
+    let mut warned = false;
+    // start polling quickly, so short tasks don't have to wait:
+    let mut poll_interval = Duration::from_millis(1);
+    loop {
+        if let Some(status) = child.try_wait().context("waiting on child process")? {
+            return Ok(status);
+        }
+
+        let cancelled = fs.cancel_requested(pathbuf)?;
+        if cancelled.is_some_and(|request| request.kill && request.covers(opts.task)) {
+            mirror_eprintln!("\n{} killing task.", "Cancel requested;".red());
+            kill(child)?;
+            child.wait().context("waiting on killed child process")?;
+            return Err(Error::TaskCancelled.into());
+        }
+
+        let now = now_secs();
+        let last = last_output.load(Ordering::Relaxed);
+        if let Some(timeout) = watchdog.idle_timeout {
+            let idle = now.saturating_sub(last);
+            if idle < timeout.as_secs() {
+                warned = false;
+            } else if watchdog.kill {
+                mirror_eprintln!(
+                    "\n{} for {idle}s; killing it.",
+                    "Task produced no output".red()
+                );
+                kill(child)?;
+                child.wait().context("waiting on killed child process")?;
+                return Err(Error::IdleTimeout(idle).into());
+            } else if !warned {
+                warned = true;
+                mirror_eprintln!(
+                    "\n{} for {idle}s.",
+                    "WARNING: task produced no output".yellow()
+                );
+            }
+        }
+
+        thread::sleep(poll_interval);
+        poll_interval = (poll_interval * 2).min(POLL_INTERVAL);
+    }
+}
+
+/// Kill `child` along with the rest of its process group.
+fn kill(child: &mut Child) -> Result<()> {
+    if signal_group(child.id(), "KILL") {
+        return Ok(());
+    }
+    child.kill().context("killing task")
+}
+
+/// Send `signal` (e.g. "KILL") to every process in the process group `group`,
+/// returning true if it was sent.
+fn signal_group(group: u32, signal: &str) -> bool {
+    #[cfg(unix)]
+    {
+        Command::new("kill")
+            .arg(format!("-{signal}"))
+            .arg("--")
+            .arg(format!("-{group}"))
+            .status()
+            .is_ok_and(|status| status.success())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (group, signal);
+        false
     }
-    Ok(status.success())
+}
+
+/// Pass SIGINT and SIGTERM on to the running tasks' process groups from now on.
+/// With no task running, exit the way the signal would have made us.
+#[cfg(unix)]
+fn forward_signals() {
+    use signal_hook::consts::{SIGINT, SIGTERM};
+    use signal_hook::iterator::Signals;
+    use std::sync::Once;
+
+    static FORWARDING: Once = Once::new();
+    FORWARDING.call_once(|| {
+        let mut signals = match Signals::new([SIGINT, SIGTERM]) {
+            Ok(signals) => signals,
+            Err(e) => {
+                log::warn!("unable to pass signals on to tasks: {e}");
+                return;
+            }
+        };
+        thread::spawn(move || {
+            for signal in signals.forever() {
+                let groups = running_groups().clone();
+                let name = if signal == SIGINT { "INT" } else { "TERM" };
+                let mut forwarded = !groups.is_empty();
+                for group in groups {
+                    forwarded &= signal_group(group, name);
+                }
+                if !forwarded {
+                    std::process::exit(128 + signal);
+                }
+            }
+        });
+    });
+}
+
+/// Join `thread`, which copies the task's `stream`, unless it's still going at `deadline`.
+fn join_until(thread: JoinHandle<()>, stream: &str, deadline: Instant) {
+    while !thread.is_finished() {
+        if Instant::now() >= deadline {
+            log::warn!("task's {stream} is still open after it exited; not waiting for it");
+            return;
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+    thread.join().unwrap_or_else(|_| panic!("Error joining {stream} thread"));
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
 }
 
 fn communicate<R: Read, W: Write>(
     mut stream: R,
     mut file: File,
     mut output: W,
+    last_output: &AtomicU64,
 ) -> std::io::Result<()> {
     let mut buf = [0u8; 1024];
     loop {
@@ -68,6 +277,7 @@ fn communicate<R: Read, W: Write>(
         if num_read == 0 {
             break;
         }
+        last_output.store(now_secs(), Ordering::Relaxed);
 
         let buf = &buf[..num_read];
         file.write_all(buf)?;
@@ -77,13 +287,13 @@ fn communicate<R: Read, W: Write>(
     Ok(())
 }
 
-fn make_log_files(fs: &mut Fs, artifacts_dir: &str, pathbuf: &mut PathBuf) -> Result<(File, File)> {
+fn make_log_files(fs: &Fs, artifacts_dir: &str, pathbuf: &mut PathBuf) -> Result<(File, File)> {
     let out_file = fs
-        .create_file(fs.stdout(artifacts_dir, pathbuf))
+        .create_file(Artifact::Stdout.path_in(artifacts_dir, pathbuf))
         .context("creating stdout.txt file")?;
 
     let err_file = fs
-        .create_file(fs.stderr(artifacts_dir, pathbuf))
+        .create_file(Artifact::Stderr.path_in(artifacts_dir, pathbuf))
         .context("creating stderr.txt file")?;
 
     Ok((out_file, err_file))
@@ -0,0 +1,357 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use serde::Serialize;
+
+use super::Error;
+
+/// `service.name` of exported traces, unless `OTEL_SERVICE_NAME` is set.
+const SERVICE_NAME: &str = "heron-rebuild";
+
+/// Name of the instrumentation scope that spans are reported under.
+const SCOPE_NAME: &str = "hr";
+
+/// OTLP enum values, which are numbers in OTLP/JSON.
+const SPAN_KIND_INTERNAL: u8 = 1;
+const STATUS_CODE_UNSET: u8 = 0;
+const STATUS_CODE_ERROR: u8 = 2;
+
+/// Index of a span in a `Trace`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpanId(usize);
+
+/// Value of a span attribute.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AttrValue {
+    Str(String),
+    Int(i64),
+}
+
+impl From<&str> for AttrValue {
+    fn from(s: &str) -> Self {
+        Self::Str(s.to_owned())
+    }
+}
+
+impl From<String> for AttrValue {
+    fn from(s: String) -> Self {
+        Self::Str(s)
+    }
+}
+
+impl From<i64> for AttrValue {
+    fn from(n: i64) -> Self {
+        Self::Int(n)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Span {
+    /// 8 random bytes, as hex
+    id: String,
+    parent: Option<SpanId>,
+    name: String,
+    start: SystemTime,
+    /// None until the span is ended
+    end: Option<SystemTime>,
+    attributes: Vec<(&'static str, AttrValue)>,
+    /// Why the operation failed, if it did
+    error: Option<String>,
+}
+
+/// A trace of one run, for '--otlp-endpoint'.
+/// The first span is the root, and every other span descends from it.
+#[derive(Debug, Clone)]
+pub struct Trace {
+    /// 16 random bytes, as hex
+    trace_id: String,
+    spans: Vec<Span>,
+}
+
+impl Trace {
+    /// Start a new trace whose root span is called `name`.
+    pub fn new(name: &str) -> Self {
+        let mut trace = Self {
+            trace_id: format!("{}{}", random_hex(), random_hex()),
+            spans: Vec::with_capacity(16),
+        };
+        trace.add(None, name, SystemTime::now());
+        trace
+    }
+
+    pub fn root(&self) -> SpanId {
+        SpanId(0)
+    }
+
+    /// Start a span called `name` under `parent`.
+    pub fn start(&mut self, parent: SpanId, name: &str) -> SpanId {
+        self.add(Some(parent), name, SystemTime::now())
+    }
+
+    /// Add a span called `name` under `parent` that started at `start`,
+    /// for operations that were timed before the span was created.
+    pub fn start_at(&mut self, parent: SpanId, name: &str, start: SystemTime) -> SpanId {
+        self.add(Some(parent), name, start)
+    }
+
+    pub fn set_attr(&mut self, span: SpanId, key: &'static str, value: impl Into<AttrValue>) {
+        self.spans[span.0].attributes.push((key, value.into()));
+    }
+
+    /// End `span` now, marking it as failed if there's an `error`.
+    pub fn end(&mut self, span: SpanId, error: Option<String>) {
+        self.end_at(span, SystemTime::now(), error);
+    }
+
+    pub fn end_at(&mut self, span: SpanId, end: SystemTime, error: Option<String>) {
+        let span = &mut self.spans[span.0];
+        span.end = Some(end);
+        span.error = error;
+    }
+
+    fn add(&mut self, parent: Option<SpanId>, name: &str, start: SystemTime) -> SpanId {
+        self.spans.push(Span {
+            id: random_hex(),
+            parent,
+            name: name.to_owned(),
+            start,
+            end: None,
+            attributes: Vec::with_capacity(0),
+            error: None,
+        });
+        SpanId(self.spans.len() - 1)
+    }
+
+    /// The trace as an OTLP/JSON `ExportTraceServiceRequest`.
+    /// Spans that were never ended end when this is called.
+    pub fn to_otlp(&self, service: &str) -> Result<ExportTraceRequest> {
+        let now = SystemTime::now();
+        let mut spans = Vec::with_capacity(self.spans.len());
+        for span in &self.spans {
+            let status = match &span.error {
+                Some(e) => OtlpStatus {
+                    code: STATUS_CODE_ERROR,
+                    message: Some(e.clone()),
+                },
+                None => OtlpStatus {
+                    code: STATUS_CODE_UNSET,
+                    message: None,
+                },
+            };
+            spans.push(OtlpSpan {
+                trace_id: self.trace_id.clone(),
+                span_id: span.id.clone(),
+                parent_span_id: span.parent.map(|parent| self.spans[parent.0].id.clone()),
+                name: span.name.clone(),
+                kind: SPAN_KIND_INTERNAL,
+                start_time_unix_nano: nanos(span.start)?,
+                end_time_unix_nano: nanos(span.end.unwrap_or(now))?,
+                attributes: attributes(&span.attributes),
+                status,
+            });
+        }
+
+        let resource = Resource {
+            attributes: attributes(&[("service.name", AttrValue::from(service))]),
+        };
+        let scope = Scope {
+            name: SCOPE_NAME,
+            version: env!("CARGO_PKG_VERSION"),
+        };
+        Ok(ExportTraceRequest {
+            resource_spans: vec![ResourceSpans {
+                resource,
+                scope_spans: vec![ScopeSpans { scope, spans }],
+            }],
+        })
+    }
+
+    /// Send the trace to the OTLP/HTTP collector at `endpoint` (e.g. `http://localhost:4318`),
+    /// by shelling out to `curl`.
+    pub fn export(&self, endpoint: &str) -> Result<()> {
+        let service =
+            std::env::var("OTEL_SERVICE_NAME").unwrap_or_else(|_| SERVICE_NAME.to_owned());
+        let body = serde_json::to_string(&self.to_otlp(&service)?)?;
+        let url = format!("{}/v1/traces", endpoint.trim_end_matches('/'));
+
+        let err = |msg: String| Error::TraceExport(url.clone(), msg);
+
+        let mut child = Command::new("curl")
+            .args([
+                "-fsS",
+                "-X",
+                "POST",
+                "-H",
+                "Content-Type: application/json",
+                "-T",
+                "-",
+            ])
+            .arg(&url)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| err(format!("unable to run curl: {e}")))?;
+        child
+            .stdin
+            .take()
+            .expect("Cannot attach to curl stdin")
+            .write_all(body.as_bytes())
+            .map_err(|e| err(e.to_string()))?;
+        let output = child.wait_with_output().map_err(|e| err(e.to_string()))?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(err(stderr.trim().to_owned()).into());
+        }
+        Ok(())
+    }
+}
+
+/// OTLP/JSON `ExportTraceServiceRequest`, with only the fields hr uses.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportTraceRequest {
+    resource_spans: Vec<ResourceSpans>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ResourceSpans {
+    resource: Resource,
+    scope_spans: Vec<ScopeSpans>,
+}
+
+#[derive(Debug, Serialize)]
+struct Resource {
+    attributes: Vec<KeyValue>,
+}
+
+#[derive(Debug, Serialize)]
+struct ScopeSpans {
+    scope: Scope,
+    spans: Vec<OtlpSpan>,
+}
+
+#[derive(Debug, Serialize)]
+struct Scope {
+    name: &'static str,
+    version: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct OtlpSpan {
+    trace_id: String,
+    span_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parent_span_id: Option<String>,
+    name: String,
+    kind: u8,
+    start_time_unix_nano: String,
+    end_time_unix_nano: String,
+    attributes: Vec<KeyValue>,
+    status: OtlpStatus,
+}
+
+#[derive(Debug, Serialize)]
+struct KeyValue {
+    key: &'static str,
+    value: AnyValue,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+enum AnyValue {
+    StringValue(String),
+    IntValue(String),
+}
+
+#[derive(Debug, Serialize)]
+struct OtlpStatus {
+    code: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+}
+
+/// 8 random bytes, as hex.
+fn random_hex() -> String {
+    // each RandomState is seeded differently, so this is random enough for ids:
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos());
+    format!("{:016x}", hasher.finish())
+}
+
+/// Nanoseconds since the unix epoch, as a string.
+fn nanos(t: SystemTime) -> Result<String> {
+    Ok(t.duration_since(UNIX_EPOCH)?.as_nanos().to_string())
+}
+
+fn attributes(attrs: &[(&'static str, AttrValue)]) -> Vec<KeyValue> {
+    let attrs = attrs.iter().map(|(key, value)| KeyValue {
+        key,
+        value: match value {
+            AttrValue::Str(s) => AnyValue::StringValue(s.clone()),
+            // int64 is a string in OTLP/JSON:
+            AttrValue::Int(n) => AnyValue::IntValue(n.to_string()),
+        },
+    });
+    attrs.collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+    use std::time::Duration;
+
+    #[test]
+    fn test_to_otlp() -> Result<()> {
+        let mut trace = Trace::new("hr run");
+        let t0 = UNIX_EPOCH + Duration::from_secs(1_800_000_000);
+        trace.spans[0].start = t0;
+        let task = trace.start_at(trace.root(), "a[X.x1]", t0 + Duration::from_millis(500));
+        trace.set_attr(task, "hr.task", "a");
+        trace.set_attr(task, "process.exit.code", 1i64);
+        trace.end_at(
+            task,
+            t0 + Duration::from_secs(2),
+            Some("Subprocess failed".to_owned()),
+        );
+        trace.end_at(trace.root(), t0 + Duration::from_secs(3), None);
+
+        let json = serde_json::to_value(trace.to_otlp("test")?)?;
+        let resource_spans = &json["resourceSpans"][0];
+        assert_eq!(
+            resource_spans["resource"],
+            json!({"attributes": [{"key": "service.name", "value": {"stringValue": "test"}}]})
+        );
+        let spans = resource_spans["scopeSpans"][0]["spans"].as_array().unwrap();
+        assert_eq!(spans.len(), 2);
+
+        let (root, task) = (&spans[0], &spans[1]);
+        assert_eq!(root["traceId"].as_str().unwrap().len(), 32);
+        assert_eq!(root["spanId"].as_str().unwrap().len(), 16);
+        assert_eq!(root.get("parentSpanId"), None);
+        assert_eq!(task["traceId"], root["traceId"]);
+        assert_eq!(task["parentSpanId"], root["spanId"]);
+        assert_ne!(task["spanId"], root["spanId"]);
+        assert_eq!(task["name"], "a[X.x1]");
+        assert_eq!(task["startTimeUnixNano"], "1800000000500000000");
+        assert_eq!(task["endTimeUnixNano"], "1800000002000000000");
+        assert_eq!(
+            task["attributes"],
+            json!([{"key": "hr.task", "value": {"stringValue": "a"}},
+                   {"key": "process.exit.code", "value": {"intValue": "1"}}])
+        );
+        assert_eq!(
+            task["status"],
+            json!({"code": 2, "message": "Subprocess failed"})
+        );
+        assert_eq!(root["status"], json!({"code": 0}));
+        Ok(())
+    }
+}
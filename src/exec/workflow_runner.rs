@@ -1,23 +1,112 @@
-use std::path::PathBuf;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use anyhow::{Context, Result};
 use colored::Colorize;
+use serde::Serialize;
 
 use intern::{GetStr, TypedStrs};
-use workflow::RunStrId;
+use util::{Digest, HashMap};
+use workflow::{RunStrId, BRANCH_DELIM, BRANCH_KV_DELIM};
 
-use crate::fs::Fs;
-use crate::prep::TaskRunner;
+use crate::fs::{format_estimate, Artifact, Estimates, Fs, RunRecord, TaskRecord, BASELINE_BRANCH};
+use crate::prep::{finish_fingerprint, TaskRunner};
+use crate::run_log::mirror_eprintln;
 use crate::ui::Ui;
 
-use super::{run_cmd::run_cmd, Error};
+use super::junit::{tail, Outcome, TestCase, STDERR_TAIL_LINES};
+use super::metrics::{write_metrics_file, RunMetrics};
+use super::observer::{ExecObserver, TaskEvent};
+use super::run_cmd::{run_cmd, CmdOptions};
+use super::trace::{SpanId, Trace};
+use super::{Error, Watchdog};
+
+/// What happened to a task given to `WorkflowRunner::run`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TaskState {
+    /// It ran successfully.
+    Completed,
+    /// Its outputs were restored from the cache.
+    Restored,
+    /// It failed (or was killed, or its outputs were missing).
+    Failed,
+    /// It's running now.
+    Running,
+    /// It was cancelled with 'hr cancel' (or needed a task that was), and didn't finish.
+    Cancelled,
+    /// It didn't start, b/c an earlier task failed.
+    NotRun,
+}
+
+impl TaskState {
+    /// True if the task has finished, one way or another, or will never run.
+    fn is_over(self) -> bool {
+        !matches!(self, Self::NotRun | Self::Running)
+    }
+
+    fn describe(self) -> &'static str {
+        match self {
+            Self::Completed => "completed",
+            Self::Restored => "restored",
+            Self::Failed => "failed",
+            Self::Running => "running",
+            Self::Cancelled => "cancelled",
+            Self::NotRun => "not-run",
+        }
+    }
+}
+
+/// Where to write Prometheus metrics while running, and the run they're for.
+struct MetricsTarget {
+    path: PathBuf,
+    /// plans (or tasks) the run is for
+    plans: String,
+    started: u64,
+}
+
+/// State, realization dir, and duration in seconds of a task given to `run`,
+/// for '--output-format json'.
+#[derive(Debug, Serialize)]
+pub struct TaskReport {
+    /// task and branch, e.g. 'task[Profile.debug]'
+    task: String,
+    realization: String,
+    state: &'static str,
+    duration: Option<f64>,
+}
+
+/// Record of one task's run, for the run report.
+struct TaskResult {
+    /// name of the task
+    task: String,
+    /// e.g. 'Profile.debug+Os.mac'
+    branch: String,
+    /// module dir the task runs in, if any
+    module: Option<String>,
+    print_id: RunStrId,
+    realization_dir: RunStrId,
+    exec_dir: RunStrId,
+    state: TaskState,
+    /// Why the task failed, if it did.
+    error: Option<String>,
+    /// When the task started, if it did.
+    started: Option<SystemTime>,
+    /// How long the task took, if it started.
+    duration: Option<Duration>,
+    /// Exit code of the task's code, if it ran and exited normally.
+    exit_code: Option<i32>,
+}
 
 /// `WorkflowRunner` is the struct that actually runs a workflow.
 ///
 /// It traverses the list of tasks provided by the `prep` module one final time,
 /// ensures that all input files exist, then executes the task code.
-/// After execution, it confirms that all output files exist before moving to
-/// the next task. In the case of module tasks, it will copy output files from
+/// After execution, it confirms that all output files exist before starting
+/// the tasks that need them (with more than one job, tasks that don't need each
+/// other's outputs run at the same time). In the case of module tasks, it will copy output files from
 /// the module directory back into the task directory so that dependent tasks
 /// can find them. When a task is complete, it writes an `exit_code` file to
 /// the task directory so that subsequent runs will not try to execute the
@@ -31,86 +120,617 @@ pub struct WorkflowRunner {
     fs: Fs,
     /// User interface
     ui: Ui,
+    /// Detects tasks that stop producing output
+    watchdog: Watchdog,
+    /// Tee task stdout to our stderr instead of our stdout
+    stdout_to_stderr: bool,
+    /// What happened to each task in the current run
+    results: Vec<TaskResult>,
+    /// Expected durations of tasks, from the run history
+    estimates: Estimates,
+    /// Prometheus textfile to keep up to date, if any
+    metrics: Option<MetricsTarget>,
+    /// Told about each task as it starts and finishes
+    observers: Vec<Box<dyn ExecObserver>>,
+    /// Most tasks to run at once
+    jobs: usize,
+}
+
+/// What running a single task needs, shared by all the tasks running at once.
+struct TaskExec<'a> {
+    run_strs: &'a TypedStrs<RunStrId>,
+    fs: &'a Fs,
+    verbose: bool,
+    watchdog: Watchdog,
+    stdout_to_stderr: bool,
+}
+
+/// A task that finished running (or having its outputs restored).
+struct Finished {
+    /// index of the task in the list given to `run`
+    i: usize,
+    /// true if its outputs were restored from the cache
+    result: Result<bool>,
+    exit_code: Option<i32>,
+    duration: Duration,
 }
 
 impl WorkflowRunner {
     /// Create a new `WorkflowRunner`.
-    pub fn new(run_strs: TypedStrs<RunStrId>, fs: Fs, ui: Ui) -> Self {
+    pub fn new(run_strs: TypedStrs<RunStrId>, fs: Fs, ui: Ui, watchdog: Watchdog) -> Self {
         Self {
             run_strs,
             pathbuf: PathBuf::with_capacity(256),
             fs,
             ui,
+            watchdog,
+            stdout_to_stderr: false,
+            results: Vec::with_capacity(0),
+            estimates: Estimates::default(),
+            metrics: None,
+            observers: Vec::with_capacity(0),
+            jobs: 1,
         }
     }
 
-    pub fn run(&mut self, mut tasks: Vec<TaskRunner>) -> Result<()> {
+    /// Filesystem interface the tasks run with.
+    pub fn fs(&self) -> &Fs {
+        &self.fs
+    }
+
+    /// Tell `observer` about the progress of the run.
+    pub fn add_observer(&mut self, observer: Box<dyn ExecObserver>) {
+        self.observers.push(observer);
+    }
+
+    /// Keep Prometheus metrics about the run (which started at `started`) in the file at `path`.
+    pub fn set_metrics_file(&mut self, path: PathBuf, plans: String, started: u64) {
+        self.metrics = Some(MetricsTarget {
+            path,
+            plans,
+            started,
+        });
+    }
+
+    /// Show how long each task is expected to take, and how long the rest of the run should take.
+    pub fn set_estimates(&mut self, estimates: Estimates) {
+        self.estimates = estimates;
+    }
+
+    /// Run up to `jobs` tasks at once, as long as they don't need each other's outputs.
+    pub fn set_jobs(&mut self, jobs: usize) {
+        self.jobs = jobs.max(1);
+    }
+
+    /// Keep our stdout free of task output, e.g. so it only contains the JSON run report.
+    pub fn set_stdout_to_stderr(&mut self, stdout_to_stderr: bool) {
+        self.stdout_to_stderr = stdout_to_stderr;
+    }
+
+    pub fn run(&mut self, tasks: Vec<TaskRunner>) -> Result<()> {
+        let result = self.run_tasks(tasks);
+        for observer in &mut self.observers {
+            observer.on_workflow_complete(result.is_ok());
+        }
+        result
+    }
+
+    fn run_tasks(&mut self, mut tasks: Vec<TaskRunner>) -> Result<()> {
         debug_assert!(!tasks.is_empty());
 
-        for task in &mut tasks {
-            self.ui.start_timer();
-            let realization_dir = self.run_strs.get(task.realization_dir)?;
-            let task_str = self.run_strs.get(task.print_id)?;
-            eprintln!("{} {task_str}\nin {realization_dir}\n", "RUN".green());
+        self.results = tasks
+            .iter()
+            .map(|task| TaskResult {
+                task: task.info.task.clone(),
+                branch: describe_branch(&task.info.branch),
+                module: task.info.module.clone(),
+                print_id: task.print_id,
+                realization_dir: task.realization_dir,
+                exec_dir: task.exec_dir,
+                state: TaskState::NotRun,
+                error: None,
+                started: None,
+                duration: None,
+                exit_code: None,
+            })
+            .collect();
+        let needs = self.dependencies(&tasks)?;
+        // the tasks running at once share the rest of the runner:
+        let mut results = std::mem::take(&mut self.results);
+        let mut observers = std::mem::take(&mut self.observers);
+        let mut pathbuf = PathBuf::with_capacity(256);
 
-            if self.ui.verbose {
-                eprintln!("\n{}", "Checking that all inputs exist...".magenta());
-            }
-            self.check_files_exist(&task.inputs).context("while checking for input files")?;
-            if self.ui.verbose {
-                eprintln!("All input files were found.\n");
-            }
+        let exec = TaskExec {
+            run_strs: &self.run_strs,
+            fs: &self.fs,
+            verbose: self.ui.verbose,
+            watchdog: self.watchdog,
+            stdout_to_stderr: self.stdout_to_stderr,
+        };
+        // tasks that haven't started yet:
+        let mut waiting: Vec<Option<&mut TaskRunner>> = tasks.iter_mut().map(Some).collect();
 
-            let success = run_cmd(
-                &mut task.cmd,
-                realization_dir,
-                &mut self.fs,
-                &mut self.pathbuf,
-                self.ui.verbose,
-            )?;
-            if !success {
-                return Err(Error::SubprocessFailed.into());
-            }
+        let scheduled = thread::scope(|scope| -> Result<()> {
+            let (finished_tx, finished_rx) = mpsc::channel::<Finished>();
+            let mut running = 0;
+            let mut stopped = false;
+            let mut failure = None;
+            loop {
+                while !stopped && running < self.jobs {
+                    let ready = waiting.iter().enumerate().position(|(i, task)| {
+                        task.is_some() && needs[i].iter().all(|j| results[*j].state.is_over())
+                    });
+                    let Some(i) = ready else {
+                        break;
+                    };
+                    let task = waiting[i].take().expect("ready task is waiting");
+
+                    let request = self.fs.cancel_requested(&mut pathbuf)?;
+                    if request.as_ref().is_some_and(|request| request.tasks.is_empty()) {
+                        mirror_eprintln!(
+                            "{} Not starting any more tasks.\n",
+                            "Cancel requested.".red()
+                        );
+                        results[i].state = TaskState::Cancelled;
+                        for (task, result) in waiting.iter_mut().zip(&mut results) {
+                            if task.take().is_some() {
+                                result.state = TaskState::Cancelled;
+                            }
+                        }
+                        stopped = true;
+                        break;
+                    }
+                    let covered = request.is_some_and(|request| request.covers(&results[i].task));
+                    let cancelled =
+                        needs[i].iter().any(|j| results[*j].state == TaskState::Cancelled);
+                    if covered || cancelled {
+                        mirror_eprintln!(
+                            "{} {}\n",
+                            "CANCELLED".red(),
+                            self.run_strs.get(task.print_id)?
+                        );
+                        results[i].state = TaskState::Cancelled;
+                        continue;
+                    }
 
-            if !task.copy_outputs_to.is_empty() {
-                if self.ui.verbose {
-                    eprintln!(
-                        "\n{}\n",
-                        "Copying outputs from module back to task dir...".magenta()
-                    );
+                    self.print_estimate(&results, i);
+                    results[i].state = TaskState::Running;
+                    self.update_metrics(&results, None);
+
+                    results[i].started = Some(SystemTime::now());
+                    notify(
+                        &mut observers,
+                        &self.run_strs,
+                        &results[i],
+                        |observer, task| observer.on_task_start(task),
+                    )?;
+                    let exec = &exec;
+                    let finished_tx = finished_tx.clone();
+                    scope.spawn(move || {
+                        let start = Instant::now();
+                        let mut exit_code = None;
+                        let run = panic::catch_unwind(AssertUnwindSafe(|| {
+                            exec.run_task(task, &mut exit_code)
+                        }));
+                        // tell the scheduler the task is over even if it panicked,
+                        // so it doesn't wait for it forever:
+                        let (result, panicked) = match run {
+                            Ok(result) => (result, None),
+                            Err(panic) => (Err(Error::TaskPanicked.into()), Some(panic)),
+                        };
+                        let _ = finished_tx.send(Finished {
+                            i,
+                            result,
+                            exit_code,
+                            duration: start.elapsed(),
+                        });
+                        if let Some(panic) = panicked {
+                            panic::resume_unwind(panic);
+                        }
+                    });
+                    running += 1;
                 }
-                self.copy_module_outputs(task, &self.fs)
-                    .context("while copying module outputs to realization dir")?;
-                if self.ui.verbose {
-                    eprintln!("All module outputs copied.");
+                if running == 0 {
+                    break;
                 }
-            } else {
-                if self.ui.verbose {
-                    eprintln!(
-                        "\n{}",
-                        "Checking that all expected outputs exist...".magenta()
-                    );
+
+                let Finished {
+                    i,
+                    result,
+                    exit_code,
+                    duration,
+                } = finished_rx.recv().expect("a running task always reports back");
+                running -= 1;
+                self.ui.add_phase(self.run_strs.get(results[i].print_id)?, duration);
+                results[i].duration = Some(duration);
+                results[i].exit_code = exit_code;
+                match &result {
+                    Ok(restored) => notify(
+                        &mut observers,
+                        &self.run_strs,
+                        &results[i],
+                        |observer, task| observer.on_task_complete(task, *restored, duration),
+                    )?,
+                    Err(e) => notify(
+                        &mut observers,
+                        &self.run_strs,
+                        &results[i],
+                        |observer, task| observer.on_task_failed(task, e, duration),
+                    )?,
                 }
-                self.check_files_exist(&task.outputs)
-                    .context("while checking for output files")?;
-                if self.ui.verbose {
-                    eprintln!("All output files were found.");
+                results[i].state = match &result {
+                    Ok(true) => TaskState::Restored,
+                    Ok(false) => TaskState::Completed,
+                    Err(e) if matches!(e.downcast_ref(), Some(Error::TaskCancelled)) => {
+                        TaskState::Cancelled
+                    }
+                    Err(e) => {
+                        results[i].error = Some(format!("{e:#}"));
+                        TaskState::Failed
+                    }
+                };
+                // no more tasks start after one fails, but the ones already running can finish:
+                if let (Err(e), None) = (result, &failure) {
+                    if results[i].state != TaskState::Cancelled {
+                        stopped = true;
+                        failure = Some(e);
+                    }
                 }
             }
+            failure.map_or(Ok(()), Err)
+        });
+        self.results = results;
+        self.observers = observers;
+        scheduled?;
 
-            self.ui.print_elapsed("Task execution")?;
+        if self.results.iter().any(|r| r.state == TaskState::Cancelled) {
+            self.fs.clear_cancel_request(&mut self.pathbuf)?;
+            return Err(Error::Cancelled.into());
+        }
+        Ok(())
+    }
+
+    /// For each task, the earlier tasks whose realization dirs any of its inputs are in,
+    /// which it has to wait for.
+    fn dependencies(&self, tasks: &[TaskRunner]) -> Result<Vec<Vec<usize>>> {
+        let mut realizations = HashMap::default();
+        for (i, task) in tasks.iter().enumerate() {
+            realizations.insert(Path::new(self.run_strs.get(task.realization_dir)?), i);
+        }
+        let mut needs = Vec::with_capacity(tasks.len());
+        for (i, task) in tasks.iter().enumerate() {
+            let mut deps = Vec::with_capacity(task.inputs.len());
+            for input in &task.inputs {
+                let input = Path::new(self.run_strs.get(*input)?);
+                let dep = input.ancestors().find_map(|dir| realizations.get(dir));
+                if let Some(&j) = dep.filter(|j| **j < i) {
+                    if !deps.contains(&j) {
+                        deps.push(j);
+                    }
+                }
+            }
+            needs.push(deps);
+        }
+        Ok(needs)
+    }
+
+    /// Print how long the `i`th task given to `run`, which is about to start,
+    /// and the rest of the run (the tasks that haven't started yet) should take.
+    fn print_estimate(&self, results: &[TaskResult], i: usize) {
+        let Some(next) = self.estimates.get(&results[i].task) else {
+            return;
+        };
+        let remaining = results.iter().filter(|r| r.state == TaskState::NotRun);
+        let (total, unknown) = self.estimates.total(remaining.map(|r| r.task.as_str()));
+        let more = if unknown > 0 { "+" } else { "" };
+        mirror_eprintln!(
+            "{} next task {}, rest of run {}{more}",
+            "ESTIMATE".magenta(),
+            format_estimate(next),
+            format_estimate(total),
+        );
+    }
+
+    /// How many tasks have completed (or had their outputs restored) so far.
+    pub fn completed(&self) -> usize {
+        let done = |r: &&TaskResult| matches!(r.state, TaskState::Completed | TaskState::Restored);
+        self.results.iter().filter(done).count()
+    }
+
+    /// The state, realization dir, and duration of each task given to `run`,
+    /// for '--output-format json'.
+    pub fn results_report(&self) -> Result<Vec<TaskReport>> {
+        let mut tasks = Vec::with_capacity(self.results.len());
+        for result in &self.results {
+            tasks.push(TaskReport {
+                task: self.run_strs.get(result.print_id)?.to_owned(),
+                realization: self.run_strs.get(result.realization_dir)?.to_owned(),
+                state: result.state.describe(),
+                duration: result.duration.map(|d| d.as_secs_f64()),
+            });
+        }
+        Ok(tasks)
+    }
+
+    /// Each task given to `run`, for the run history and metrics.
+    fn task_records(&self, results: &[TaskResult]) -> Result<Vec<TaskRecord>> {
+        let mut tasks = Vec::with_capacity(results.len());
+        for result in results {
+            tasks.push(TaskRecord {
+                task: result.task.clone(),
+                realization: self.run_strs.get(result.print_id)?.to_owned(),
+                state: result.state.describe(),
+                duration: result.duration.map(|d| d.as_secs_f64()),
+            });
+        }
+        Ok(tasks)
+    }
 
-            eprintln!(
-                "{} {task_str}. Writing exit_code file.\n",
-                "COMPLETED".green()
-            );
-            let exit_code = self.fs.exit_code(realization_dir.as_ref(), &mut self.pathbuf);
+    /// Add this run to the output dir's run history.
+    pub fn record_history(
+        &self,
+        plans: String,
+        branch: String,
+        started: u64,
+        succeeded: bool,
+    ) -> Result<()> {
+        self.fs.record_run(&RunRecord {
+            plans,
+            branch,
+            started,
+            finished: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+            succeeded,
+            tasks: self.task_records(&self.results)?,
+        })
+    }
+
+    /// Write final metrics for the run to the metrics file, if there is one,
+    /// including the number of runs in the history (so record the run first).
+    pub fn finish_metrics(&self, succeeded: bool) {
+        self.update_metrics(&self.results, Some(succeeded));
+    }
+
+    /// Update the metrics file, if there is one, with the run's progress
+    /// (and its result, if `succeeded` is given). Failures are only logged,
+    /// since the run shouldn't fail b/c of its monitoring.
+    fn update_metrics(&self, results: &[TaskResult], succeeded: Option<bool>) {
+        let Some(target) = &self.metrics else {
+            return;
+        };
+        let result = (|| -> Result<()> {
+            let finished = match succeeded {
+                Some(succeeded) => {
+                    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+                    Some((now, succeeded))
+                }
+                None => None,
+            };
+            let runs_total = match finished {
+                Some(_) => self.fs.run_totals()?,
+                None => None,
+            };
+            let tasks = self.task_records(results)?;
+            let metrics = RunMetrics {
+                plans: &target.plans,
+                started: target.started,
+                finished,
+                tasks: &tasks,
+                runs_total,
+            };
+            write_metrics_file(&target.path, &metrics)
+        })();
+        if let Err(e) = result {
+            log::warn!("unable to write metrics file {:?}: {e:?}", target.path);
+        }
+    }
+
+    /// Each task given to `run` as a JUnit test case, for '--junit'.
+    /// Failures include the end of the task's `stderr.txt`.
+    pub fn junit_cases(&self) -> Result<Vec<TestCase>> {
+        let mut cases = Vec::with_capacity(self.results.len());
+        for result in &self.results {
+            let outcome = match result.state {
+                TaskState::Completed | TaskState::Restored => Outcome::Passed,
+                TaskState::NotRun | TaskState::Running => Outcome::Skipped("not run"),
+                TaskState::Cancelled => Outcome::Skipped("cancelled"),
+                TaskState::Failed => {
+                    let exec_dir = self.run_strs.get(result.exec_dir)?;
+                    let stderr = Path::new(exec_dir).join(Artifact::Stderr.file_name());
+                    let stderr = std::fs::read(stderr).unwrap_or_default();
+                    let stderr = String::from_utf8_lossy(&stderr);
+                    Outcome::Failed(
+                        result.error.clone().unwrap_or_default(),
+                        tail(&stderr, STDERR_TAIL_LINES).to_owned(),
+                    )
+                }
+            };
+            cases.push(TestCase {
+                name: self.run_strs.get(result.print_id)?.to_owned(),
+                classname: result.task.clone(),
+                time: result.duration.map_or(0.0, |d| d.as_secs_f64()),
+                outcome,
+            });
+        }
+        Ok(cases)
+    }
+
+    /// Add a span under `parent` to `trace` for each task that started, for '--otlp-endpoint'.
+    pub fn trace_tasks(&self, trace: &mut Trace, parent: SpanId) -> Result<()> {
+        for result in &self.results {
+            let Some(started) = result.started else {
+                continue;
+            };
+            let span = trace.start_at(parent, self.run_strs.get(result.print_id)?, started);
+            trace.set_attr(span, "hr.task", result.task.as_str());
+            trace.set_attr(span, "hr.branch", result.branch.as_str());
+            if let Some(module) = &result.module {
+                trace.set_attr(span, "hr.module", module.as_str());
+            }
+            trace.set_attr(span, "hr.state", result.state.describe());
+            if let Some(code) = result.exit_code {
+                trace.set_attr(span, "process.exit.code", i64::from(code));
+            }
+            let ended = started + result.duration.unwrap_or_default();
+            trace.end_at(span, ended, result.error.clone());
+        }
+        Ok(())
+    }
+}
+
+impl TaskExec<'_> {
+    /// Run a single task, returning true if its outputs were restored from the cache.
+    /// If its code ran and exited normally, its exit code goes in `exit_code`.
+    fn run_task(&self, task: &mut TaskRunner, exit_code: &mut Option<i32>) -> Result<bool> {
+        let mut pathbuf = PathBuf::with_capacity(256);
+        let realization_dir = self.run_strs.get(task.realization_dir)?;
+        let exec_dir = self.run_strs.get(task.exec_dir)?;
+        let task_str = self.run_strs.get(task.print_id)?;
+
+        task.info.start();
+        task.info
+            .write(exec_dir, self.fs)
+            .context("while writing task_info.json file")?;
+
+        if self.verbose {
+            mirror_eprintln!("\n{}", "Checking that all inputs exist...".magenta());
+        }
+        self.check_files_exist(&task.inputs).context("while checking for input files")?;
+        if self.verbose {
+            mirror_eprintln!("All input files were found.\n");
+        }
+
+        let cache_key = self.cache_key(task).context("while computing cache key")?;
+        let relative_outputs: Vec<&Path> =
+            task.relative_outputs.iter().map(PathBuf::as_path).collect();
+        let restored = match cache_key {
+            Some(key) => self
+                .fs
+                .cache_restore(key, exec_dir.as_ref(), &relative_outputs)
+                .unwrap_or_else(|e| {
+                    // a broken cache entry shouldn't stop the run; the task can still run:
+                    log::warn!("unable to restore outputs of {task_str} from cache: {e:?}");
+                    false
+                }),
+            None => false,
+        };
+
+        if !restored {
+            let opts = CmdOptions {
+                task: &task.info.task,
+                verbose: self.verbose,
+                watchdog: self.watchdog,
+                stdout_to_stderr: self.stdout_to_stderr,
+            };
+            let status = run_cmd(&mut task.cmd, exec_dir, self.fs, &mut pathbuf, opts)?;
+            *exit_code = status.code();
+            if !status.success() {
+                task.info.finish(false);
+                task.info
+                    .write(exec_dir, self.fs)
+                    .context("while writing task_info.json file for failed task")?;
+                return Err(Error::SubprocessFailed.into());
+            }
+        }
+
+        if !task.copy_outputs_to.is_empty() {
+            if self.verbose {
+                mirror_eprintln!(
+                    "\n{}\n",
+                    "Copying outputs from module back to task dir...".magenta()
+                );
+            }
+            self.copy_module_outputs(task, self.fs)
+                .context("while copying module outputs to realization dir")?;
+            if self.verbose {
+                mirror_eprintln!("All module outputs copied.");
+            }
+        } else {
+            if self.verbose {
+                mirror_eprintln!(
+                    "\n{}",
+                    "Checking that all expected outputs exist...".magenta()
+                );
+            }
+            self.check_files_exist(&task.outputs)
+                .context("while checking for output files")?;
+            if self.verbose {
+                mirror_eprintln!("All output files were found.");
+            }
+        }
+
+        if let Some(limit) = task.max_output_size {
+            self.check_output_size(exec_dir, limit)?;
+        }
+
+        if let (Some(key), false) = (cache_key, restored) {
+            if let Err(e) = self.fs.cache_store(key, exec_dir.as_ref(), &relative_outputs) {
+                log::warn!("unable to store outputs of {task_str} in cache: {e:?}");
+            } else if let Err(e) = self.fs.cache_push(key) {
+                log::warn!("unable to push outputs of {task_str} to remote cache: {e:?}");
+            }
+        }
+
+        task.info.restored_from_cache = restored;
+        task.info.finish(true);
+        task.info
+            .write(exec_dir, self.fs)
+            .context("while writing task_info.json file for successful task")?;
+        self.write_fingerprint(task, exec_dir)
+            .context("while writing fingerprint file for successful task")?;
+
+        let exit_code_file = Artifact::ExitCode.path_in(exec_dir, &mut pathbuf);
+        self.fs
+            .write_file(exit_code_file, "0")
+            .context("while writing exit_code file for successful task.")?;
+
+        if exec_dir != realization_dir {
+            if self.verbose {
+                mirror_eprintln!("{} {exec_dir} to {realization_dir}", "Renaming".magenta());
+            }
             self.fs
-                .write_file(exit_code, "0")
-                .context("while writing exit_code file for successful task.")?;
+                .rename(exec_dir, realization_dir)
+                .context("while moving completed task into its realization dir")?;
         }
-        eprintln!("{}\n", "Completed workflow.".green());
+        self.fs
+            .update_latest_link(realization_dir)
+            .context("while updating 'latest' symlink")?;
+        Ok(restored)
+    }
+
+    /// Complete the task's cache key with the digests of its input files,
+    /// or return None if we aren't caching or the task can't be cached.
+    fn cache_key(&self, task: &TaskRunner) -> Result<Option<Digest>> {
+        let Some(mut digester) = task.cache_key.clone().filter(|_| self.fs.has_cache()) else {
+            return Ok(None);
+        };
+        for input in &task.inputs {
+            self.fs.digest_path(self.run_strs.get(*input)?, &mut digester)?;
+        }
+        Ok(Some(digester.finish()))
+    }
 
+    /// Record the task's code, params, and input metadata,
+    /// so that later runs can tell if it needs to run again.
+    fn write_fingerprint(&self, task: &TaskRunner, exec_dir: &str) -> Result<()> {
+        let inputs = task
+            .inputs
+            .iter()
+            .map(|file| self.run_strs.get(*file))
+            .collect::<Result<Vec<_>, _>>()?;
+        let fingerprint = finish_fingerprint(task.fingerprint.clone(), inputs, self.fs)?;
+        let fingerprint_file = Path::new(exec_dir).join(Artifact::Fingerprint.file_name());
+        self.fs.write_file(fingerprint_file, &fingerprint.to_string())
+    }
+
+    fn check_output_size(&self, realization_dir: &str, limit: u64) -> Result<()> {
+        let size = self.fs.disk_usage(realization_dir)?;
+        if size > limit {
+            mirror_eprintln!("{} {realization_dir}\n", "OUTPUT TOO LARGE".red());
+            return Err(Error::OutputTooLarge(realization_dir.to_owned(), size, limit).into());
+        }
+        if self.verbose {
+            mirror_eprintln!("Realization dir size {size} is within limit {limit}.");
+        }
         Ok(())
     }
 
@@ -139,10 +759,38 @@ impl WorkflowRunner {
         if !self.fs.exists(file) {
             Err(Error::ExpectedFileNotFound(file.to_owned()))
         } else {
-            if self.ui.verbose {
-                eprintln!(" - {file}");
+            if self.verbose {
+                mirror_eprintln!(" - {file}");
             }
             Ok(())
         }
     }
 }
+
+/// Tell each observer about the task whose result is `result`.
+fn notify(
+    observers: &mut [Box<dyn ExecObserver>],
+    run_strs: &TypedStrs<RunStrId>,
+    result: &TaskResult,
+    event: impl Fn(&mut dyn ExecObserver, &TaskEvent),
+) -> Result<()> {
+    let task = TaskEvent {
+        task: &result.task,
+        branch: &result.branch,
+        realization: run_strs.get(result.print_id)?,
+        exec_dir: run_strs.get(result.exec_dir)?,
+    };
+    for observer in observers {
+        event(observer.as_mut(), &task);
+    }
+    Ok(())
+}
+
+/// Describe a realization's branch, e.g. 'Profile.debug+Os.mac' (or 'Baseline.baseline').
+fn describe_branch(branch: &[(String, String)]) -> String {
+    if branch.is_empty() {
+        return BASELINE_BRANCH.to_owned();
+    }
+    let kvs: Vec<String> = branch.iter().map(|(k, v)| format!("{k}{BRANCH_KV_DELIM}{v}")).collect();
+    kvs.join(&BRANCH_DELIM.to_string())
+}
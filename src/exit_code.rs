@@ -0,0 +1,101 @@
+//! Exit codes, so scripts (e.g. CI pipelines) can tell how a command failed.
+
+use std::fmt;
+
+/// Failed for any other reason, e.g. a confirmation was needed with '--non-interactive'.
+pub const FAILURE: u8 = 1;
+/// There was nothing to run; only with '--non-interactive' (otherwise this is a success).
+pub const NOTHING_TO_RUN: u8 = 3;
+/// The config file (or a command-line arg) is invalid, or the workflow can't be prepared.
+pub const CONFIG_ERROR: u8 = 4;
+/// The first task to run failed, so nothing was done.
+pub const EXECUTION_FAILURE: u8 = 5;
+/// A task failed after other tasks had completed.
+pub const PARTIAL_FAILURE: u8 = 6;
+
+/// Returned instead of succeeding when there's nothing to run and '--non-interactive' was given.
+#[derive(thiserror::Error, Debug)]
+#[error("No tasks to run")]
+pub struct NothingToRun;
+
+/// Context of the error a task failed with.
+#[derive(Debug)]
+pub struct RunFailed {
+    /// some tasks completed before one failed:
+    pub partial: bool,
+}
+
+impl fmt::Display for RunFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("while running workflow")
+    }
+}
+
+/// The exit code to fail with because of `error`.
+pub fn exit_code(error: &anyhow::Error) -> u8 {
+    if error.is::<NothingToRun>() {
+        NOTHING_TO_RUN
+    } else if let Some(failed) = error.downcast_ref::<RunFailed>() {
+        if failed.partial {
+            PARTIAL_FAILURE
+        } else {
+            EXECUTION_FAILURE
+        }
+    } else if is_config_error(error) {
+        CONFIG_ERROR
+    } else {
+        FAILURE
+    }
+}
+
+fn is_config_error(error: &anyhow::Error) -> bool {
+    use crate::app::Error as AppError;
+    if let Some(e) = error.downcast_ref::<AppError>() {
+        return matches!(
+            e,
+            AppError::NoTargetSpecified
+                | AppError::CheckFailed(..)
+                | AppError::NoMatchingTasks(_)
+                | AppError::MultiTask(..)
+                | AppError::BoundNotInTarget(..)
+                | AppError::NoProducer(..)
+                | AppError::NoSuchOutput(..)
+        );
+    }
+    error.is::<syntax::ParseError>()
+        || error.is::<syntax::ParseErrors>()
+        || error.is::<workflow::Error>()
+        || error.is::<workflow::AggregatedErrors>()
+        || error.is::<traverse::Error>()
+        || error.is::<crate::settings::Error>()
+        || error.is::<crate::project::Error>()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::{anyhow, Context, Result};
+
+    #[test]
+    fn test_exit_code() {
+        let failed = |partial| -> Result<()> {
+            Err(anyhow!("Subprocess failed")).context(RunFailed { partial })
+        };
+        assert_eq!(exit_code(&failed(false).unwrap_err()), EXECUTION_FAILURE);
+        assert_eq!(exit_code(&failed(true).unwrap_err()), PARTIAL_FAILURE);
+        assert_eq!(
+            failed(true).unwrap_err().to_string(),
+            "while running workflow"
+        );
+
+        assert_eq!(exit_code(&NothingToRun.into()), NOTHING_TO_RUN);
+        let config = anyhow::Error::from(workflow::Error::Unsupported("x".to_owned()));
+        assert_eq!(exit_code(&config.context("while loading")), CONFIG_ERROR);
+        assert_eq!(
+            exit_code(&crate::app::Error::NoMatchingTasks("x*".to_owned()).into()),
+            CONFIG_ERROR
+        );
+        assert_eq!(exit_code(&crate::app::Error::DeleteRefused.into()), FAILURE);
+        assert_eq!(exit_code(&anyhow!("disk full")), FAILURE);
+    }
+}
@@ -0,0 +1,271 @@
+use std::io::Write;
+
+use anyhow::Result;
+
+use intern::GetStr;
+use traverse::{Node, Traversal};
+use util::HashMap;
+use workflow::{AbstractTaskId, BranchSpec, BranchpointId, IdentId, RealTaskKey, Workflow};
+
+use crate::prep::{format_branchpoints, pruned};
+
+/// Write why the realizations of `task` matching any of `branches` are in `traversal` to `out`:
+/// for each time a realization was reached, the chain of inputs that reached it from a goal,
+/// with the branchpoints grafted and pruned along the way, and whether it was deduplicated.
+/// Unspecified branchpoints in `branches` match any branch.
+/// If no realization matches, write why the task isn't in the traversal instead.
+pub fn write_explanation(
+    traversal: &Traversal,
+    wf: &Workflow,
+    task: AbstractTaskId,
+    branches: &[BranchSpec],
+    out: &mut impl Write,
+) -> Result<()> {
+    let name = wf.strings.tasks.get(task)?;
+
+    // the nodes of each realization of the task, in run order:
+    let mut idxs: HashMap<&RealTaskKey, usize> = HashMap::default();
+    let mut realizations: Vec<(&RealTaskKey, Vec<usize>)> = Vec::new();
+    for (i, node) in traversal.nodes.iter().enumerate() {
+        if node.key.id != task {
+            continue;
+        }
+        if let Some(idx) = idxs.get(&node.key) {
+            realizations[*idx].1.push(i);
+        } else {
+            idxs.insert(&node.key, realizations.len());
+            realizations.push((&node.key, vec![i]));
+        }
+    }
+
+    if realizations.is_empty() {
+        writeln!(out, "{name} isn't in the traversal of the target.")?;
+        let mut users: Vec<&str> = Vec::new();
+        for dependent in wf.dependents(task) {
+            let user = wf.strings.tasks.get(dependent.task)?;
+            if !users.contains(&user) {
+                users.push(user);
+            }
+        }
+        if users.is_empty() {
+            writeln!(
+                out,
+                "No task uses its outputs, so it's only reached as a goal."
+            )?;
+        } else {
+            writeln!(
+                out,
+                "It's used by {}, but by none of their realizations in the traversal.",
+                users.join(", ")
+            )?;
+        }
+        return Ok(());
+    }
+
+    let matches = |node: &Node| {
+        branches.iter().any(|branch| {
+            branch.is_exact_match(&node.key.branch) || branch.is_exact_match(&node.requested_branch)
+        })
+    };
+    let matching: Vec<_> = realizations
+        .iter()
+        .filter(|(_, nodes)| nodes.iter().any(|i| matches(&traversal.nodes[*i])))
+        .collect();
+
+    if matching.is_empty() {
+        let mut patterns = Vec::with_capacity(branches.len());
+        for branch in branches {
+            patterns.push(wf.strings.get_full_branch_str(branch)?.to_owned());
+        }
+        writeln!(
+            out,
+            "No realization of {name} in the traversal matches {}; it has:",
+            patterns.join(", ")
+        )?;
+        for (key, _) in &realizations {
+            writeln!(out, "  {}", &*wf.strings.get_real_task_str(key)?)?;
+        }
+        return Ok(());
+    }
+
+    let dependents = find_dependents(traversal);
+    for (i, (key, nodes)) in matching.iter().enumerate() {
+        if i > 0 {
+            writeln!(out)?;
+        }
+        let mut paths = Vec::new();
+        for idx in nodes {
+            for path in paths_to_goals(*idx, &dependents) {
+                paths.push((*idx, path));
+            }
+        }
+        let times = match paths.len() {
+            1 => "once".to_owned(),
+            n => format!("{n} times, and deduplicated into this one realization"),
+        };
+        writeln!(
+            out,
+            "{} was reached {times}:",
+            &*wf.strings.get_real_task_str(key)?
+        )?;
+        for (idx, path) in paths {
+            let node = &traversal.nodes[idx];
+            writeln!(
+                out,
+                "  as [{}]{}",
+                &*wf.strings.get_full_branch_str(&node.requested_branch)?,
+                describe_branch_changes(node, wf)?
+            )?;
+            // the chain of dependents back to the goal:
+            for (dependent, var) in path {
+                let node = &traversal.nodes[dependent];
+                writeln!(
+                    out,
+                    "    needed by {} (input '{}'){}",
+                    &*wf.strings.get_real_task_str(&node.key)?,
+                    wf.strings.idents.get(var)?,
+                    describe_branch_changes(node, wf)?
+                )?;
+            }
+            writeln!(out, "    which is a goal of the target")?;
+        }
+    }
+
+    if realizations.len() > 1 {
+        let differing: Vec<_> = differing_branchpoints(&realizations)
+            .into_iter()
+            .map(|k| wf.strings.branchpoints.get(k))
+            .collect::<Result<_, _>>()?;
+        writeln!(
+            out,
+            "\nThe traversal has {} realizations of {name}, which weren't deduplicated \
+             b/c their branches differ in {}.",
+            realizations.len(),
+            differing.join(", ")
+        )?;
+    }
+    Ok(())
+}
+
+/// For each node in the traversal, the nodes that use its outputs,
+/// and the input of each that uses them; empty for goals.
+fn find_dependents(traversal: &Traversal) -> Vec<Vec<(usize, IdentId)>> {
+    let mut dependents = vec![Vec::new(); traversal.nodes.len()];
+    for (i, node) in traversal.nodes.iter().enumerate() {
+        for (var, val_id) in &node.vars.inputs {
+            if let Some(input) = traversal.inputs.get(*val_id) {
+                input.for_each_task(&mut |dep| {
+                    let dependent = (i, *var);
+                    let deps: &mut Vec<_> = &mut dependents[usize::from(dep)];
+                    if !deps.contains(&dependent) {
+                        deps.push(dependent);
+                    }
+                });
+            }
+        }
+    }
+    dependents
+}
+
+/// Every chain of dependents from the node `idx` to a goal. The traversal only visits
+/// each realization once, so a node reached by several dependents has a chain through each.
+fn paths_to_goals(idx: usize, dependents: &[Vec<(usize, IdentId)>]) -> Vec<Vec<(usize, IdentId)>> {
+    if dependents[idx].is_empty() {
+        return vec![Vec::new()];
+    }
+    let mut paths = Vec::new();
+    for (dependent, var) in &dependents[idx] {
+        for mut path in paths_to_goals(*dependent, dependents) {
+            path.insert(0, (*dependent, *var));
+            paths.push(path);
+        }
+    }
+    paths
+}
+
+/// "; grafted A.a1; pruned B.b1", listing the branchpoints of the branch a node was requested
+/// with that the dependent reaching it grafted, and that its realization doesn't use.
+fn describe_branch_changes(node: &Node, wf: &Workflow) -> Result<String> {
+    let mut changes = String::new();
+    let grafted = format_branchpoints(&node.grafted, &node.requested_branch, wf)?;
+    if !grafted.is_empty() {
+        changes.push_str(&format!("; grafted {grafted}"));
+    }
+    let pruned = format_branchpoints(&pruned(node), &node.requested_branch, wf)?;
+    if !pruned.is_empty() {
+        changes.push_str(&format!("; pruned {pruned}"));
+    }
+    Ok(changes)
+}
+
+/// Branchpoints whose branch isn't the same in every one of `realizations`.
+fn differing_branchpoints(realizations: &[(&RealTaskKey, Vec<usize>)]) -> Vec<BranchpointId> {
+    let len = realizations.iter().map(|(key, _)| key.branch.len()).max().unwrap_or(0);
+    (0..len)
+        .map(BranchpointId::from)
+        .filter(|k| {
+            let first = realizations[0].0.branch.get_specified(*k);
+            realizations.iter().any(|(key, _)| key.branch.get_specified(*k) != first)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use intern::InternStr;
+    use std::path::Path;
+
+    fn explain(text: &str, task: &str, branches: &[BranchSpec]) -> Result<String> {
+        let mut wf = Workflow::default();
+        wf.load(syntax::parse(text)?, Path::new("."))?;
+        let task = wf.strings.tasks.intern(task)?;
+        let plan = wf.strings.idents.intern("main")?;
+        let plan = wf.get_plan(plan)?.clone();
+        wf.strings.alloc_for_traversal();
+        let traversal = Traversal::create::<u8>(&wf, plan)?;
+        let mut out = Vec::new();
+        write_explanation(&traversal, &wf, task, branches, &mut out)?;
+        Ok(String::from_utf8(out)?)
+    }
+
+    #[test]
+    fn test_explain() -> Result<()> {
+        let text = "plan main { reach report via (Size: small big) }\n\
+                    task prep > out :: os=(Os: mac linux) { touch $out }\n\
+                    task stats < in=$out@prep[Os: linux] > out { cp $in $out }\n\
+                    task report < in=$out@stats > out :: size=(Size: small big) { cp $in $out }\n\
+                    task docs { echo docs }\n\
+                    task unused < in=$out@docs { echo unused }\n";
+        assert_eq!(
+            explain(text, "prep", &[BranchSpec::default()])?,
+            "prep[Os.linux] was reached 2 times, and deduplicated into this one realization:\n\
+             \x20 as [Os.linux]; grafted Os.linux\n\
+             \x20   needed by stats[Baseline.baseline] (input 'in')\n\
+             \x20   needed by report[Size.big] (input 'in')\n\
+             \x20   which is a goal of the target\n\
+             \x20 as [Os.linux]; grafted Os.linux\n\
+             \x20   needed by stats[Baseline.baseline] (input 'in')\n\
+             \x20   needed by report[Size.small] (input 'in')\n\
+             \x20   which is a goal of the target\n"
+        );
+        let report = explain(text, "report", &[BranchSpec::default()])?;
+        assert!(
+            report.starts_with("report[Size.big] was reached once:\n"),
+            "{report}"
+        );
+        assert!(
+            report.ends_with(
+                "The traversal has 2 realizations of report, which weren't deduplicated \
+                 b/c their branches differ in Size.\n"
+            ),
+            "{report}"
+        );
+        assert_eq!(
+            explain(text, "docs", &[BranchSpec::default()])?,
+            "docs isn't in the traversal of the target.\n\
+             It's used by unused, but by none of their realizations in the traversal.\n"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,547 @@
+use std::io::Write;
+
+use anyhow::Result;
+use serde::Serialize;
+
+use intern::GetStr;
+use workflow::{AbstractTaskId, BranchSpec, BranchpointId, Plan, Workflow};
+
+use super::rules::{Expr, OutputRef, Rule, Rules};
+
+const CWL_VERSION: &str = "v1.2";
+
+/// Name of the file each tool writes its task's code to.
+const SCRIPT: &str = "task.sh";
+
+/// The string a tool input's env var is set to: its path if it's a file,
+/// and the items separated by spaces if it's a list, as in hr.
+const HR_STR_JS: &str = "function hr_str(v) {
+  if (v === null) { return \"\"; }
+  if (Array.isArray(v)) { return v.map(hr_str).join(\" \"); }
+  if (typeof v === \"object\") { return v.path; }
+  return String(v);
+}";
+
+/// Type of task outputs, which can be either.
+const OUTPUT_TYPE: [&str; 2] = ["File", "Directory"];
+
+/// A CWL document, with a process for each step and the workflow that runs them.
+#[derive(Debug, Serialize)]
+struct Document {
+    #[serde(rename = "cwlVersion")]
+    cwl_version: &'static str,
+    #[serde(rename = "$graph")]
+    graph: Vec<Process>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "class")]
+enum Process {
+    CommandLineTool(Tool),
+    Workflow(CwlWorkflow),
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "class")]
+enum Requirement {
+    #[serde(rename = "InlineJavascriptRequirement")]
+    InlineJavascript {
+        #[serde(rename = "expressionLib", skip_serializing_if = "Vec::is_empty")]
+        expression_lib: Vec<&'static str>,
+    },
+    #[serde(rename = "InitialWorkDirRequirement")]
+    InitialWorkDir { listing: Vec<Dirent> },
+    #[serde(rename = "EnvVarRequirement")]
+    EnvVar {
+        #[serde(rename = "envDef")]
+        env_def: Vec<EnvDef>,
+    },
+    #[serde(rename = "StepInputExpressionRequirement")]
+    StepInputExpression,
+    #[serde(rename = "MultipleInputFeatureRequirement")]
+    MultipleInputFeature,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Tool {
+    id: String,
+    requirements: Vec<Requirement>,
+    base_command: [&'static str; 4],
+    inputs: Vec<ToolInput>,
+    outputs: Vec<ToolOutput>,
+}
+
+/// A file written to the tool's working dir before it runs.
+#[derive(Debug, Serialize)]
+struct Dirent {
+    entryname: &'static str,
+    entry: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct EnvDef {
+    env_name: String,
+    env_value: String,
+}
+
+#[derive(Debug, Serialize)]
+struct ToolInput {
+    id: String,
+    #[serde(rename = "type")]
+    ty: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ToolOutput {
+    id: String,
+    #[serde(rename = "type")]
+    ty: [&'static str; 2],
+    output_binding: OutputBinding,
+}
+
+#[derive(Debug, Serialize)]
+struct OutputBinding {
+    glob: String,
+}
+
+#[derive(Debug, Serialize)]
+struct CwlWorkflow {
+    id: &'static str,
+    requirements: Vec<Requirement>,
+    inputs: Vec<WorkflowInput>,
+    outputs: Vec<WorkflowOutput>,
+    steps: Vec<WorkflowStep>,
+}
+
+/// A branchpoint, which defaults to its baseline branch.
+#[derive(Debug, Serialize)]
+struct WorkflowInput {
+    id: String,
+    #[serde(rename = "type")]
+    ty: &'static str,
+    default: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct WorkflowOutput {
+    id: String,
+    #[serde(rename = "type")]
+    ty: [&'static str; 2],
+    output_source: String,
+}
+
+#[derive(Debug, Serialize)]
+struct WorkflowStep {
+    id: String,
+    run: String,
+    #[serde(rename = "in")]
+    inputs: Vec<StepInput>,
+    #[serde(rename = "out")]
+    outputs: Vec<String>,
+}
+
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct StepInput {
+    id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    default: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source: Option<Source>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value_from: Option<String>,
+}
+
+/// The `step/output` a step input comes from, or a list of them.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum Source {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// A task realized with some branches fixed by grafts, which becomes a workflow step
+/// (and the tool it runs).
+#[derive(Debug)]
+struct Step {
+    id: String,
+    rule: Rule,
+    fixed: BranchSpec,
+}
+
+/// Write a CWL document with a `CommandLineTool` for each task that the goals of `plan`
+/// (or, if it has none, every task) need, and a `Workflow` that runs them.
+/// Branchpoints are inputs of the workflow, which default to their baseline branches.
+pub fn write_cwl(wf: &Workflow, plan: &Plan, out: &mut impl Write) -> Result<()> {
+    let mut rules = Rules::new(wf);
+    let mut goals: Vec<AbstractTaskId> = Vec::new();
+    for goal in plan.subplans.iter().flat_map(|subplan| &subplan.goals) {
+        if !goals.contains(goal) {
+            goals.push(*goal);
+        }
+    }
+    if goals.is_empty() {
+        goals = rules.tasks();
+    }
+
+    // each output a step uses comes from the step for its task and fixed branches:
+    let mut keys: Vec<(AbstractTaskId, BranchSpec)> =
+        goals.iter().map(|goal| (*goal, BranchSpec::default())).collect();
+    let mut steps = Vec::with_capacity(keys.len());
+    while steps.len() < keys.len() {
+        let (task, fixed) = keys[steps.len()].clone();
+        let rule = rules.rule(task, &fixed)?;
+        for (_, value) in rule.inputs.iter().chain(&rule.params) {
+            for output in value.outputs() {
+                let key = step_key(output);
+                if !keys.contains(&key) {
+                    keys.push(key);
+                }
+            }
+        }
+        let mut id = wf.strings.tasks.get(task)?.to_owned();
+        if fixed.specified().next().is_some() {
+            id.push('-');
+            wf.strings.make_compact_branch_string(&fixed, &mut id)?;
+        }
+        steps.push(Step { id, rule, fixed });
+    }
+
+    let mut graph = Vec::with_capacity(steps.len() + 1);
+    for step in &steps {
+        graph.push(Process::CommandLineTool(tool(step, wf)?));
+    }
+    graph.push(Process::Workflow(workflow(&steps, goals.len(), wf)?));
+    let doc = Document {
+        cwl_version: CWL_VERSION,
+        graph,
+    };
+    writeln!(out, "{}", serde_json::to_string_pretty(&doc)?)?;
+    Ok(())
+}
+
+/// Task and fixed branches of the step that produces `output`.
+fn step_key(output: &OutputRef) -> (AbstractTaskId, BranchSpec) {
+    let mut fixed = BranchSpec::default();
+    for (k, v) in &output.branch {
+        if let Some(v) = v {
+            fixed.insert(*k, *v);
+        }
+    }
+    (output.task, fixed)
+}
+
+/// `step/output` of the step that produces `output`.
+fn source(output: &OutputRef, steps: &[Step], wf: &Workflow) -> Result<String> {
+    let (task, fixed) = step_key(output);
+    let step = steps
+        .iter()
+        .find(|step| step.rule.task == task && step.fixed == fixed)
+        .expect("Should never fail -- a step is created for every output that's used");
+    Ok(format!(
+        "{}/{}",
+        step.id,
+        wf.strings.idents.get(output.output)?
+    ))
+}
+
+/// A tool that runs the step's task code with its variables in the environment, as hr does.
+fn tool(step: &Step, wf: &Workflow) -> Result<Tool> {
+    let task = wf.get_task(step.rule.task)?;
+    let mut inputs = Vec::new();
+    let mut env = Vec::new();
+    for (k, _) in step.rule.inputs.iter().chain(&step.rule.params) {
+        let k = wf.strings.idents.get(*k)?;
+        inputs.push(ToolInput {
+            id: k.to_owned(),
+            ty: "Any",
+        });
+        env.push(EnvDef {
+            env_name: k.to_owned(),
+            env_value: format!("$(hr_str(inputs.{k}))"),
+        });
+    }
+    let mut outputs = Vec::new();
+    for (k, file) in step.rule.output_files(wf)? {
+        let k = wf.strings.idents.get(k)?;
+        let file = escape(file);
+        env.push(EnvDef {
+            env_name: k.to_owned(),
+            env_value: format!("$(runtime.outdir)/{file}"),
+        });
+        outputs.push(ToolOutput {
+            id: k.to_owned(),
+            ty: OUTPUT_TYPE,
+            output_binding: OutputBinding { glob: file },
+        });
+    }
+
+    let script = Dirent {
+        entryname: SCRIPT,
+        entry: escape(wf.strings.literals.get(task.code)?),
+    };
+    Ok(Tool {
+        id: step.id.clone(),
+        requirements: vec![
+            Requirement::InlineJavascript {
+                expression_lib: vec![HR_STR_JS],
+            },
+            Requirement::InitialWorkDir {
+                listing: vec![script],
+            },
+            Requirement::EnvVar { env_def: env },
+        ],
+        base_command: ["bash", "-xeuo", "pipefail", SCRIPT],
+        inputs,
+        outputs,
+    })
+}
+
+/// The workflow that runs every step, whose outputs are the outputs of the first `n_goals` steps.
+fn workflow(steps: &[Step], n_goals: usize, wf: &Workflow) -> Result<CwlWorkflow> {
+    let mut branchpoints: Vec<BranchpointId> = Vec::new();
+    let mut workflow_steps = Vec::with_capacity(steps.len());
+    for step in steps {
+        let mut ins = Vec::new();
+        let mut step_branchpoints = Vec::new();
+        for (k, value) in step.rule.inputs.iter().chain(&step.rule.params) {
+            ins.push(step_input(wf.strings.idents.get(*k)?, value, steps, wf)?);
+            add_switch_branchpoints(value, &mut step_branchpoints);
+        }
+        step_branchpoints.sort_unstable_by_key(|k| usize::from(*k));
+        step_branchpoints.dedup();
+        // branches are passed to the step so that its values can branch on them:
+        for k in step_branchpoints {
+            let k_str = wf.strings.branchpoints.get(k)?;
+            ins.push(StepInput {
+                id: format!("branch_{k_str}"),
+                source: Some(Source::One(k_str.to_owned())),
+                ..StepInput::default()
+            });
+            if !branchpoints.contains(&k) {
+                branchpoints.push(k);
+            }
+        }
+        let mut outs = Vec::new();
+        for (k, _) in &step.rule.outputs {
+            outs.push(wf.strings.idents.get(*k)?.to_owned());
+        }
+        workflow_steps.push(WorkflowStep {
+            id: step.id.clone(),
+            run: format!("#{}", step.id),
+            inputs: ins,
+            outputs: outs,
+        });
+    }
+
+    branchpoints.sort_unstable_by_key(|k| usize::from(*k));
+    let mut inputs = Vec::with_capacity(branchpoints.len());
+    for k in branchpoints {
+        inputs.push(WorkflowInput {
+            id: wf.strings.branchpoints.get(k)?.to_owned(),
+            ty: "string",
+            default: wf.strings.idents.get(wf.strings.baselines.get(k))?.to_owned(),
+        });
+    }
+    let mut outputs = Vec::new();
+    for step in &steps[..n_goals] {
+        for (k, _) in &step.rule.outputs {
+            let k = wf.strings.idents.get(*k)?;
+            outputs.push(WorkflowOutput {
+                id: format!("{}_{k}", step.id),
+                ty: OUTPUT_TYPE,
+                output_source: format!("{}/{k}", step.id),
+            });
+        }
+    }
+
+    Ok(CwlWorkflow {
+        id: "main",
+        requirements: vec![
+            Requirement::InlineJavascript {
+                expression_lib: Vec::with_capacity(0),
+            },
+            Requirement::StepInputExpression,
+            Requirement::MultipleInputFeature,
+        ],
+        inputs,
+        outputs,
+        steps: workflow_steps,
+    })
+}
+
+/// The step input for a task variable: a default for literals, a source for task outputs,
+/// and an expression over its sources and branches for anything else.
+fn step_input(name: &str, value: &Expr, steps: &[Step], wf: &Workflow) -> Result<StepInput> {
+    let mut input = StepInput {
+        id: name.to_owned(),
+        ..StepInput::default()
+    };
+    let mut sources = Vec::new();
+    for output in value.outputs() {
+        let source = source(output, steps, wf)?;
+        if !sources.contains(&source) {
+            sources.push(source);
+        }
+    }
+    match value {
+        Expr::Lit(s) => input.default = Some(s.clone()),
+        Expr::Output(_) => input.source = Some(Source::One(sources.swap_remove(0))),
+        _ => {
+            let js = js(value, &sources, steps, wf)?;
+            input.value_from = Some(format!("${{ return {js}; }}"));
+            input.source = match sources.len() {
+                0 => None,
+                1 => Some(Source::One(sources.swap_remove(0))),
+                _ => Some(Source::Many(sources)),
+            };
+        }
+    }
+    Ok(input)
+}
+
+/// `value` as a javascript expression, where `self` is the value of `sources`
+/// (or a list of their values, if there's more than one).
+fn js(value: &Expr, sources: &[String], steps: &[Step], wf: &Workflow) -> Result<String> {
+    Ok(match value {
+        Expr::Lit(s) => js_str(s),
+        Expr::Concat(items) => {
+            let mut terms = Vec::with_capacity(items.len());
+            for item in items {
+                terms.push(js(item, sources, steps, wf)?);
+            }
+            format!("({})", terms.join(" + "))
+        }
+        Expr::Output(output) => {
+            let source = source(output, steps, wf)?;
+            let i = sources.iter().position(|s| *s == source).unwrap_or_default();
+            if sources.len() == 1 {
+                "self".to_owned()
+            } else {
+                format!("self[{i}]")
+            }
+        }
+        Expr::Switch(arms) => {
+            let mut code = String::from("(");
+            for (conds, value) in arms {
+                let value = js(value, sources, steps, wf)?;
+                if conds.is_empty() {
+                    code.push_str(&value);
+                    code.push(')');
+                    return Ok(code);
+                }
+                let mut tests = Vec::with_capacity(conds.len());
+                for (k, v) in conds {
+                    tests.push(format!(
+                        "inputs.branch_{} == {}",
+                        wf.strings.branchpoints.get(*k)?,
+                        js_str(wf.strings.idents.get(*v)?)
+                    ));
+                }
+                code.push_str(&format!("{} ? {value} : ", tests.join(" && ")));
+            }
+            code.push_str("null)");
+            code
+        }
+        Expr::List(items) => {
+            let mut terms = Vec::with_capacity(items.len());
+            for item in items {
+                terms.push(js(item, sources, steps, wf)?);
+            }
+            format!("[{}]", terms.join(", "))
+        }
+    })
+}
+
+/// Add the branchpoints that `value` branches on to `branchpoints`.
+fn add_switch_branchpoints(value: &Expr, branchpoints: &mut Vec<BranchpointId>) {
+    match value {
+        Expr::Lit(_) | Expr::Output(_) => (),
+        Expr::Concat(items) | Expr::List(items) => {
+            for item in items {
+                add_switch_branchpoints(item, branchpoints);
+            }
+        }
+        Expr::Switch(arms) => {
+            for (conds, value) in arms {
+                branchpoints.extend(conds.iter().map(|(k, _)| *k));
+                add_switch_branchpoints(value, branchpoints);
+            }
+        }
+    }
+}
+
+/// Escape the parameter references in a string that CWL would otherwise interpolate,
+/// e.g. `${var}` in task code.
+fn escape(s: &str) -> String {
+    s.replace("$(", "\\$(").replace("${", "\\${")
+}
+
+/// `s` as a javascript string literal.
+fn js_str(s: &str) -> String {
+    // json string escapes are valid in javascript:
+    serde_json::Value::from(s).to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn test_write_cwl() -> Result<()> {
+        let text = "task build > out :: p=(Profile: debug release) { touch $out }\n\
+                    task test < in=$out@build rel=$out@build[Profile: release] { echo \"${in}\" $rel }\n";
+        let mut wf = Workflow::default();
+        wf.load(syntax::parse(text)?, std::path::Path::new("."))?;
+        let plan = Plan::create_anonymous(
+            &mut wf.strings,
+            &["test".to_owned()],
+            vec![BranchSpec::default()],
+        )?;
+        let mut out = Vec::new();
+        write_cwl(&wf, &plan, &mut out)?;
+        let doc: Value = serde_json::from_slice(&out)?;
+        let graph = doc["$graph"].as_array().unwrap();
+        let ids: Vec<_> = graph.iter().map(|d| d["id"].as_str().unwrap()).collect();
+        assert_eq!(ids, ["test", "build", "build-Profile.release", "main"]);
+
+        let build = &graph[1];
+        assert_eq!(
+            build["requirements"][2],
+            json!({"class": "EnvVarRequirement", "envDef": [
+                {"envName": "p", "envValue": "$(hr_str(inputs.p))"},
+                {"envName": "out", "envValue": "$(runtime.outdir)/out"}]})
+        );
+        let test = &graph[0];
+        assert_eq!(
+            test["requirements"][1]["listing"][0]["entry"],
+            " echo \"\\${in}\" $rel "
+        );
+
+        let main = &graph[3];
+        assert_eq!(
+            main["inputs"],
+            json!([{"id": "Profile", "type": "string", "default": "debug"}])
+        );
+        let steps = &main["steps"];
+        assert_eq!(
+            steps[0]["in"],
+            json!([{"id": "in", "source": "build/out"},
+                   {"id": "rel", "source": "build-Profile.release/out"}])
+        );
+        assert_eq!(
+            steps[1]["in"],
+            json!([{"id": "p",
+                    "valueFrom": "${ return (inputs.branch_Profile == \"debug\" ? \"debug\" : inputs.branch_Profile == \"release\" ? \"release\" : null); }"},
+                   {"id": "branch_Profile", "source": "Profile"}])
+        );
+        // the grafted step's param doesn't branch:
+        assert_eq!(steps[2]["in"], json!([{"id": "p", "default": "release"}]));
+        Ok(())
+    }
+}
@@ -0,0 +1,205 @@
+use std::io::Write;
+
+use anyhow::Result;
+
+use intern::GetStr;
+use util::HashMap;
+use workflow::RunStrId;
+
+use crate::fs::Artifact;
+use crate::prep::TaskRunner;
+
+/// A task realization that's been prepared to run, as it appears in an exported build.
+#[derive(Debug)]
+pub struct ExportTask {
+    /// task and branch, e.g. 'task[Profile.debug]'.
+    name: String,
+    /// dir containing `task.sh`, where the task runs and its artifacts are written.
+    exec_dir: String,
+    /// dir the exec dir is moved to when the task succeeds (the same dir, for exported builds).
+    realization_dir: String,
+    /// indices of the exported tasks this one gets its inputs from.
+    deps: Vec<usize>,
+    /// input files that aren't produced by any exported task,
+    /// e.g. outputs of tasks that already completed.
+    external_inputs: Vec<String>,
+}
+
+impl ExportTask {
+    /// `exit_code` file that marks the task as complete.
+    fn stamp(&self) -> String {
+        format!(
+            "{}/{}",
+            self.realization_dir,
+            Artifact::ExitCode.file_name()
+        )
+    }
+}
+
+/// Find the dependencies of each task from the paths of its inputs.
+pub fn export_tasks(
+    tasks: &[TaskRunner],
+    strs: &impl GetStr<Key = RunStrId>,
+) -> Result<Vec<ExportTask>> {
+    // module tasks copy their outputs back to the realization dir, which is where dependents look:
+    let mut producers: HashMap<&str, usize> = HashMap::default();
+    for (i, task) in tasks.iter().enumerate() {
+        let outputs = if task.copy_outputs_to.is_empty() {
+            &task.outputs
+        } else {
+            &task.copy_outputs_to
+        };
+        for output in outputs {
+            producers.insert(strs.get(*output)?, i);
+        }
+    }
+
+    let mut exported = Vec::with_capacity(tasks.len());
+    for task in tasks {
+        let mut deps = Vec::new();
+        let mut external_inputs = Vec::new();
+        for input in &task.inputs {
+            let input = strs.get(*input)?;
+            match producers.get(input) {
+                Some(i) if !deps.contains(i) => deps.push(*i),
+                Some(_) => (),
+                None => external_inputs.push(input.to_owned()),
+            }
+        }
+        exported.push(ExportTask {
+            name: strs.get(task.print_id)?.to_owned(),
+            exec_dir: strs.get(task.exec_dir)?.to_owned(),
+            realization_dir: strs.get(task.realization_dir)?.to_owned(),
+            deps,
+            external_inputs,
+        });
+    }
+    Ok(exported)
+}
+
+/// A Makefile with a target for each task's `exit_code` file, which depends on the `exit_code`
+/// files of the tasks it gets inputs from, and whose recipe runs the task's `task.sh`.
+pub fn write_makefile(tasks: &[ExportTask], out: &mut impl Write) -> Result<()> {
+    writeln!(out, "# Generated by 'hr export --format make'.")?;
+    writeln!(
+        out,
+        "# Each target runs a task realization's task.sh and, if it succeeds, writes its exit_code file."
+    )?;
+    writeln!(out, "SHELL := /usr/bin/env bash\n")?;
+    writeln!(out, ".PHONY: all")?;
+    write!(out, "all:")?;
+    for task in tasks {
+        write!(out, " \\\n  {}", make_path(&task.stamp()))?;
+    }
+    writeln!(out)?;
+
+    for task in tasks {
+        writeln!(out, "\n# {}", task.name)?;
+        write!(out, "{}:", make_path(&task.stamp()))?;
+        for dep in &task.deps {
+            write!(out, " {}", make_path(&tasks[*dep].stamp()))?;
+        }
+        for input in &task.external_inputs {
+            write!(out, " {}", make_path(input))?;
+        }
+        writeln!(out)?;
+
+        let exec_dir = shell_quote(&task.exec_dir);
+        writeln!(
+            out,
+            "\tcd {exec_dir} && bash {} > {} 2> {}",
+            Artifact::TaskSh.file_name(),
+            Artifact::Stdout.file_name(),
+            Artifact::Stderr.file_name(),
+        )?;
+        writeln!(
+            out,
+            "\tprintf 0 > {}",
+            shell_quote(&format!(
+                "{}/{}",
+                task.exec_dir,
+                Artifact::ExitCode.file_name()
+            ))
+        )?;
+        if task.exec_dir != task.realization_dir {
+            writeln!(
+                out,
+                "\tmv {exec_dir} {}",
+                shell_quote(&task.realization_dir)
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Escape a path for use as a make target or prerequisite.
+fn make_path(path: &str) -> String {
+    let mut escaped = String::with_capacity(path.len());
+    for c in path.chars() {
+        match c {
+            '$' => escaped.push_str("$$"),
+            ' ' | '#' | '%' | ':' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Quote a string for the shell, in a make recipe.
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''").replace('$', "$$"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_write_makefile() -> Result<()> {
+        let task = |name: &str, dir: &str, deps, external_inputs| ExportTask {
+            name: name.to_owned(),
+            exec_dir: dir.to_owned(),
+            realization_dir: dir.to_owned(),
+            deps,
+            external_inputs,
+        };
+        let mut tasks = vec![
+            task(
+                "a[X.x1]",
+                "/out/a/X.x1",
+                vec![],
+                vec!["/src/in file".to_owned()],
+            ),
+            task("b[X.x1]", "/out/b/X.x1", vec![0], vec![]),
+        ];
+        tasks[1].exec_dir = "/out/b/.X.x1.tmp".to_owned();
+        let mut out = Vec::new();
+        write_makefile(&tasks, &mut out)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "# Generated by 'hr export --format make'.\n\
+             # Each target runs a task realization's task.sh and, if it succeeds, writes its exit_code file.\n\
+             SHELL := /usr/bin/env bash\n\
+             \n\
+             .PHONY: all\n\
+             all: \\\n  /out/a/X.x1/exit_code \\\n  /out/b/X.x1/exit_code\n\
+             \n\
+             # a[X.x1]\n\
+             /out/a/X.x1/exit_code: /src/in\\ file\n\
+             \tcd '/out/a/X.x1' && bash task.sh > stdout.txt 2> stderr.txt\n\
+             \tprintf 0 > '/out/a/X.x1/exit_code'\n\
+             \n\
+             # b[X.x1]\n\
+             /out/b/X.x1/exit_code: /out/a/X.x1/exit_code\n\
+             \tcd '/out/b/.X.x1.tmp' && bash task.sh > stdout.txt 2> stderr.txt\n\
+             \tprintf 0 > '/out/b/.X.x1.tmp/exit_code'\n\
+             \tmv '/out/b/.X.x1.tmp' '/out/b/X.x1'\n"
+        );
+        assert_eq!(make_path("a$b#c%d"), "a$$b\\#c\\%d");
+        assert_eq!(shell_quote("it's $HOME"), "'it'\\''s $$HOME'");
+        Ok(())
+    }
+}
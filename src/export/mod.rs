@@ -0,0 +1,67 @@
+use std::io::Write;
+
+use anyhow::Result;
+
+use intern::GetStr;
+use workflow::{Plan, RunStrId, Workflow};
+
+use crate::args::ExportFormat;
+use crate::prep::TaskRunner;
+
+/// CWL documents of the abstract workflow.
+mod cwl;
+/// Makefiles of the task realizations prepared for a run.
+mod make;
+/// The abstract workflow as rules that are realized for any branch.
+mod rules;
+/// Snakefiles of the abstract workflow.
+mod snakemake;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Task {0} runs in a module, so it can't be exported from the abstract workflow")]
+    ModuleTask(String),
+    #[error("Output {1} of task {0} branches, so it can't be exported from the abstract workflow")]
+    BranchedOutput(String, String),
+    #[error("Task {0} has no output named {1}")]
+    UndefinedOutput(String, String),
+    #[error("Config value {0} is not defined")]
+    UndefinedConfigValue(String),
+    #[error("Task {0} depends on its own outputs")]
+    DependencyCycle(String),
+    #[error("No branch of a value matches the grafted branch {0}")]
+    NoMatchingBranch(String),
+}
+
+/// Write `tasks` (as returned by `PreRunner::do_pre_run_actions`, so their realization dirs
+/// and `task.sh` files already exist) to `out` as a build file in the given `format`.
+pub fn write_export(
+    format: ExportFormat,
+    tasks: &[TaskRunner],
+    strs: &impl GetStr<Key = RunStrId>,
+    out: &mut impl Write,
+) -> Result<()> {
+    match format {
+        ExportFormat::Make => make::write_makefile(&make::export_tasks(tasks, strs)?, out),
+        ExportFormat::Snakemake | ExportFormat::Cwl => {
+            unreachable!("{format:?} is exported from the abstract workflow, not a prepared run")
+        }
+    }
+}
+
+/// Write the abstract workflow to `out` in the given `format`,
+/// targeting the goals and branches of `plan`.
+pub fn write_workflow_export(
+    format: ExportFormat,
+    wf: &Workflow,
+    plan: &Plan,
+    out: &mut impl Write,
+) -> Result<()> {
+    match format {
+        ExportFormat::Snakemake => snakemake::write_snakefile(wf, plan, out),
+        ExportFormat::Cwl => cwl::write_cwl(wf, plan, out),
+        ExportFormat::Make => {
+            unreachable!("{format:?} is exported from a prepared run, not the abstract workflow")
+        }
+    }
+}
@@ -0,0 +1,426 @@
+use anyhow::Result;
+
+use intern::GetStr;
+use util::HashMap;
+use workflow::{
+    AbstractTaskId, AbstractValueId, BaseValue, BranchSpec, BranchpointId, DirectValue, IdentId,
+    Value, Workflow,
+};
+
+use super::Error;
+
+/// The value of a task variable, for whichever branch its task is realized for.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    /// A literal string.
+    Lit(String),
+    /// Strings joined together, from interpolating config values.
+    Concat(Vec<Expr>),
+    /// Path of an output of another task.
+    Output(OutputRef),
+    /// The first value whose (branchpoint, branch) conditions all hold for the branch.
+    Switch(Vec<(Vec<(BranchpointId, IdentId)>, Expr)>),
+    /// List of values from every branch of some branchpoints, from a branch glob graft.
+    List(Vec<Expr>),
+}
+
+impl Expr {
+    /// Add the branchpoints this value depends on to `wildcards`.
+    fn add_wildcards(&self, wildcards: &mut Vec<BranchpointId>) {
+        match self {
+            Self::Lit(_) => (),
+            Self::Concat(items) | Self::List(items) => {
+                for item in items {
+                    item.add_wildcards(wildcards);
+                }
+            }
+            Self::Output(output) => {
+                let unfixed = output.branch.iter().filter(|(_, v)| v.is_none());
+                wildcards.extend(unfixed.map(|(k, _)| *k));
+                output.file.add_wildcards(wildcards);
+            }
+            Self::Switch(arms) => {
+                for (conds, value) in arms {
+                    wildcards.extend(conds.iter().map(|(k, _)| *k));
+                    value.add_wildcards(wildcards);
+                }
+            }
+        }
+    }
+
+    /// The task outputs this value uses, in order.
+    pub fn outputs(&self) -> Vec<&OutputRef> {
+        let mut outputs = Vec::new();
+        self.add_outputs(&mut outputs);
+        outputs
+    }
+
+    fn add_outputs<'a>(&'a self, outputs: &mut Vec<&'a OutputRef>) {
+        match self {
+            Self::Lit(_) => (),
+            Self::Concat(items) | Self::List(items) => {
+                for item in items {
+                    item.add_outputs(outputs);
+                }
+            }
+            Self::Output(output) => outputs.push(output),
+            Self::Switch(arms) => {
+                for (_, value) in arms {
+                    value.add_outputs(outputs);
+                }
+            }
+        }
+    }
+}
+
+/// An output of a task, realized for a branch.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputRef {
+    pub task: AbstractTaskId,
+    pub output: IdentId,
+    /// Each of the task's wildcards, with its branch if a graft fixes it,
+    /// or None if it's realized for the same branch as the task that uses it.
+    pub branch: Vec<(BranchpointId, Option<IdentId>)>,
+    /// Path of the output, relative to the realization dir.
+    pub file: Box<Expr>,
+}
+
+/// A task, with the values of its variables for any branch it's realized for.
+#[derive(Debug)]
+pub struct Rule {
+    pub task: AbstractTaskId,
+    /// Branchpoints the task's realizations differ by: the ones its own variables branch on,
+    /// and the ones it inherits from the tasks it depends on, unless a graft fixes them.
+    pub wildcards: Vec<BranchpointId>,
+    pub inputs: Vec<(IdentId, Expr)>,
+    /// Paths relative to the realization dir.
+    pub outputs: Vec<(IdentId, Expr)>,
+    pub params: Vec<(IdentId, Expr)>,
+}
+
+impl Rule {
+    /// Paths of the task's outputs, for formats that need them to be the same on every branch.
+    pub fn output_files(&self, wf: &Workflow) -> Result<Vec<(IdentId, &str)>> {
+        let mut files = Vec::with_capacity(self.outputs.len());
+        for (k, file) in &self.outputs {
+            let Expr::Lit(file) = file else {
+                return Err(Error::BranchedOutput(
+                    wf.strings.tasks.get(self.task)?.to_owned(),
+                    wf.strings.idents.get(*k)?.to_owned(),
+                )
+                .into());
+            };
+            files.push((*k, file.as_str()));
+        }
+        Ok(files)
+    }
+}
+
+/// Translates the tasks of the abstract workflow into `Rule`s.
+#[derive(Debug)]
+pub struct Rules<'a> {
+    wf: &'a Workflow,
+    /// Wildcards of each task whose rule has been created.
+    wildcards: HashMap<AbstractTaskId, Vec<BranchpointId>>,
+    /// Tasks whose wildcards are being found, to catch dependency cycles.
+    visiting: Vec<AbstractTaskId>,
+}
+
+impl<'a> Rules<'a> {
+    pub fn new(wf: &'a Workflow) -> Self {
+        Self {
+            wf,
+            wildcards: HashMap::default(),
+            visiting: Vec::new(),
+        }
+    }
+
+    /// Every task defined in the workflow, in the order they were defined.
+    pub fn tasks(&self) -> Vec<AbstractTaskId> {
+        let tasks = self.wf.strings.tasks.keys();
+        tasks.filter(|id| self.wf.get_task(*id).is_ok()).collect()
+    }
+
+    /// Rule for `task`, with the branches given in `fixed` fixed (e.g. by a graft).
+    pub fn rule(&mut self, task: AbstractTaskId, fixed: &BranchSpec) -> Result<Rule> {
+        let wf = self.wf;
+        let abstract_task = wf.get_task(task)?;
+        if !abstract_task.modules.is_empty() {
+            return Err(Error::ModuleTask(wf.strings.tasks.get(task)?.to_owned()).into());
+        }
+
+        let mut wildcards = self.wildcards(task)?;
+        wildcards.retain(|k| !fixed.is_specified(*k));
+
+        let vars = &abstract_task.vars;
+        let mut inputs = Vec::with_capacity(vars.inputs.len());
+        for (k, v) in &vars.inputs {
+            inputs.push((*k, self.value(*v, fixed)?));
+        }
+        let mut outputs = Vec::with_capacity(vars.outputs.len());
+        for (k, v) in &vars.outputs {
+            outputs.push((*k, self.value(*v, fixed)?));
+        }
+        let mut params = Vec::with_capacity(vars.params.len());
+        for (k, v) in &vars.params {
+            params.push((*k, self.value(*v, fixed)?));
+        }
+        Ok(Rule {
+            task,
+            wildcards,
+            inputs,
+            outputs,
+            params,
+        })
+    }
+
+    /// Branchpoints that any realization of `task` can differ by.
+    fn wildcards(&mut self, task: AbstractTaskId) -> Result<Vec<BranchpointId>> {
+        if let Some(wildcards) = self.wildcards.get(&task) {
+            return Ok(wildcards.clone());
+        }
+        let wf = self.wf;
+        if self.visiting.contains(&task) {
+            return Err(Error::DependencyCycle(wf.strings.tasks.get(task)?.to_owned()).into());
+        }
+        self.visiting.push(task);
+
+        let vars = &wf.get_task(task)?.vars;
+        let mut wildcards = Vec::new();
+        for (_, v) in vars.inputs.iter().chain(&vars.outputs).chain(&vars.params) {
+            self.value(*v, &BranchSpec::default())?.add_wildcards(&mut wildcards);
+        }
+        wildcards.sort_unstable_by_key(|k| usize::from(*k));
+        wildcards.dedup();
+
+        self.visiting.pop();
+        self.wildcards.insert(task, wildcards.clone());
+        Ok(wildcards)
+    }
+
+    fn value(&mut self, value: AbstractValueId, fixed: &BranchSpec) -> Result<Expr> {
+        match self.wf.get_value(value)? {
+            Value::Direct(direct) => self.direct(direct, fixed),
+            Value::Branched(arms) => {
+                let mut switch = Vec::with_capacity(arms.len());
+                for (branch, direct) in arms {
+                    // conditions on fixed branchpoints are already known to hold or not:
+                    let mut conds = Vec::new();
+                    let mut holds = true;
+                    for (k, v) in branch.specified() {
+                        match fixed.get_specified(k) {
+                            Some(fixed_v) => holds &= fixed_v == v,
+                            None => conds.push((k, v)),
+                        }
+                    }
+                    if !holds {
+                        continue;
+                    }
+                    let value = self.direct(direct, fixed)?;
+                    if conds.is_empty() {
+                        if switch.is_empty() {
+                            return Ok(value);
+                        }
+                        // later arms can never be reached:
+                        switch.push((conds, value));
+                        break;
+                    }
+                    switch.push((conds, value));
+                }
+                if switch.is_empty() {
+                    let mut branch = String::with_capacity(32);
+                    self.wf.strings.make_compact_branch_string(fixed, &mut branch)?;
+                    return Err(Error::NoMatchingBranch(branch).into());
+                }
+                Ok(Expr::Switch(switch))
+            }
+        }
+    }
+
+    fn direct(&mut self, direct: &DirectValue, fixed: &BranchSpec) -> Result<Expr> {
+        match direct {
+            DirectValue::Simple(base) => self.base(base, fixed),
+            DirectValue::Graft(base, branch) => {
+                let mut fixed = fixed.clone();
+                fixed.insert_all(branch);
+                self.base(base, &fixed)
+            }
+            DirectValue::GlobGraft(base, branch, globs) => {
+                let mut fixed = fixed.clone();
+                fixed.insert_all(branch);
+                let mut items = Vec::new();
+                self.glob(base, &mut fixed, globs, &mut items)?;
+                Ok(Expr::List(items))
+            }
+        }
+    }
+
+    /// Add the value of `base` in every combination of the known branches
+    /// of the `globs` branchpoints to `items`.
+    fn glob(
+        &mut self,
+        base: &BaseValue,
+        fixed: &mut BranchSpec,
+        globs: &[BranchpointId],
+        items: &mut Vec<Expr>,
+    ) -> Result<()> {
+        let Some((k, rest)) = globs.split_first() else {
+            // flatten, in case a grafted config value is itself a glob graft:
+            match self.base(base, fixed)? {
+                Expr::List(nested) => items.extend(nested),
+                item => items.push(item),
+            }
+            return Ok(());
+        };
+        let wf = self.wf;
+        for v in wf.strings.baselines.defined(*k) {
+            fixed.insert(*k, v);
+            self.glob(base, fixed, rest, items)?;
+        }
+        Ok(())
+    }
+
+    fn base(&mut self, base: &BaseValue, fixed: &BranchSpec) -> Result<Expr> {
+        let wf = self.wf;
+        match base {
+            BaseValue::Literal(lit) => Ok(Expr::Lit(wf.strings.literals.get(*lit)?.to_owned())),
+            BaseValue::Config(ident) => self.config(*ident, fixed),
+            BaseValue::Interp(lit, idents) => {
+                // the idents are in the order they appear in the string:
+                let mut rest = wf.strings.literals.get(*lit)?;
+                let mut parts = Vec::with_capacity(idents.len() * 2 + 1);
+                for ident in idents {
+                    let var = format!("${}", wf.strings.idents.get(*ident)?);
+                    let Some(start) = rest.find(&var) else {
+                        continue;
+                    };
+                    parts.push(Expr::Lit(rest[..start].to_owned()));
+                    parts.push(self.config(*ident, fixed)?);
+                    rest = &rest[start + var.len()..];
+                }
+                parts.push(Expr::Lit(rest.to_owned()));
+                Ok(concat(parts))
+            }
+            BaseValue::Task(task, output) => {
+                let branch = self
+                    .wildcards(*task)?
+                    .into_iter()
+                    .map(|k| (k, fixed.get_specified(k)))
+                    .collect();
+                let outputs = &wf.get_task(*task)?.vars.outputs;
+                let Some((_, value)) = outputs.iter().find(|(k, _)| k == output) else {
+                    return Err(Error::UndefinedOutput(
+                        wf.strings.tasks.get(*task)?.to_owned(),
+                        wf.strings.idents.get(*output)?.to_owned(),
+                    )
+                    .into());
+                };
+                Ok(Expr::Output(OutputRef {
+                    task: *task,
+                    output: *output,
+                    branch,
+                    file: Box::new(self.value(*value, fixed)?),
+                }))
+            }
+        }
+    }
+
+    fn config(&mut self, ident: IdentId, fixed: &BranchSpec) -> Result<Expr> {
+        let Some(value) = self.wf.get_config_value(ident) else {
+            let name = self.wf.strings.idents.get(ident)?.to_owned();
+            return Err(Error::UndefinedConfigValue(name).into());
+        };
+        self.value(value, fixed)
+    }
+}
+
+/// Join `parts`, merging adjacent literals and leaving out empty ones.
+fn concat(parts: Vec<Expr>) -> Expr {
+    let mut joined: Vec<Expr> = Vec::with_capacity(parts.len());
+    for part in parts {
+        match (joined.last_mut(), part) {
+            (_, Expr::Lit(s)) if s.is_empty() => (),
+            (Some(Expr::Lit(prev)), Expr::Lit(s)) => prev.push_str(&s),
+            (_, part) => joined.push(part),
+        }
+    }
+    match joined.len() {
+        0 => Expr::Lit(String::new()),
+        1 => joined.pop().expect("Should never fail -- already checked len"),
+        _ => Expr::Concat(joined),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use intern::InternStr;
+
+    #[test]
+    fn test_rules() -> Result<()> {
+        let text = "global {\n  name=(X: x1=one x2=two)\n}\n\
+                    task a > out=\"$name.txt\" :: name=$name { touch $out }\n\
+                    task b < in=$out@a > out :: p=(Y: y1 y2) { cat $in > $out }\n\
+                    task c < ins=$out@b[X: *] fixed=$out@a[X: x2] { cat $ins $fixed }\n";
+        let mut wf = Workflow::default();
+        wf.load(syntax::parse(text)?, std::path::Path::new("."))?;
+        let a = wf.strings.tasks.intern("a")?;
+        let b = wf.strings.tasks.intern("b")?;
+        let c = wf.strings.tasks.intern("c")?;
+        let x = wf.strings.branchpoints.intern("X")?;
+        let y = wf.strings.branchpoints.intern("Y")?;
+        let x1 = wf.strings.idents.intern("x1")?;
+        let x2 = wf.strings.idents.intern("x2")?;
+        let out = wf.strings.idents.intern("out")?;
+
+        let mut rules = Rules::new(&wf);
+        assert_eq!(rules.tasks(), [a, b, c]);
+
+        let a_rule = rules.rule(a, &BranchSpec::default())?;
+        assert_eq!(a_rule.wildcards, [x]);
+        assert!(a_rule.output_files(&wf).is_err());
+        let a_x2 = rules.rule(a, &BranchSpec::simple(x, x2))?;
+        assert!(a_x2.wildcards.is_empty());
+        assert_eq!(a_x2.output_files(&wf)?, [(out, "two.txt")]);
+        assert_eq!(a_x2.params[0].1, Expr::Lit("two".to_owned()));
+
+        // b inherits X from a:
+        let b_rule = rules.rule(b, &BranchSpec::simple(x, x1))?;
+        assert_eq!(b_rule.wildcards, [y]);
+        let Expr::Output(input) = &b_rule.inputs[0].1 else {
+            panic!("input should be an output of a");
+        };
+        assert_eq!(input.branch, [(x, Some(x1))]);
+        assert_eq!(*input.file, Expr::Lit("one.txt".to_owned()));
+        let Expr::Switch(arms) = &b_rule.params[0].1 else {
+            panic!("param should branch");
+        };
+        assert_eq!(arms.len(), 2);
+        assert_eq!(arms[1].1, Expr::Lit("y2".to_owned()));
+
+        // c globs over X, so only has b's Y:
+        assert_eq!(rules.wildcards(c)?, [y]);
+        let c_rule = rules.rule(c, &BranchSpec::default())?;
+        let Expr::List(items) = &c_rule.inputs[0].1 else {
+            panic!("input should be a list");
+        };
+        assert_eq!(items.len(), 2);
+        assert_eq!(
+            c_rule.inputs[0].1.outputs()[1].branch,
+            [(x, Some(x2)), (y, None)]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_concat() {
+        let lit = |s: &str| Expr::Lit(s.to_owned());
+        assert_eq!(concat(vec![lit(""), lit("a"), lit("b")]), lit("ab"));
+        let list = Expr::List(vec![lit("x")]);
+        assert_eq!(
+            concat(vec![lit("a"), list.clone(), lit(""), lit("b"), lit("c")]),
+            Expr::Concat(vec![lit("a"), list, lit("bc")])
+        );
+        assert_eq!(concat(Vec::new()), lit(""));
+    }
+}
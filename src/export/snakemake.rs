@@ -0,0 +1,445 @@
+use std::io::Write;
+
+use anyhow::Result;
+
+use intern::GetStr;
+use workflow::{
+    AbstractTaskId, BranchSpec, BranchpointId, IdentId, Plan, Workflow, BRANCH_DELIM,
+    BRANCH_KV_DELIM,
+};
+
+use super::rules::{Expr, OutputRef, Rule, Rules};
+use crate::fs::{Artifact, BASELINE_BRANCH, REALIZATIONS_DIR};
+
+/// Name of the output each rule writes when it succeeds, like hr's `exit_code` file.
+const EXIT_CODE_OUTPUT: &str = "hr_exit_code";
+
+/// Tasks run in their realization dir, so they get absolute paths to their inputs and outputs.
+const ABS_FN: &str = "def _hr_abs(paths):
+    if isinstance(paths, str):
+        return os.path.abspath(paths)
+    return \" \".join(os.path.abspath(p) for p in paths)
+";
+
+/// Part of a path or value, as built by a rule.
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Lit(String),
+    /// Branch of a branchpoint the rule is realized for.
+    Wildcard(String),
+    /// Python expression over the rule's wildcards (`wc`).
+    Code(String),
+}
+
+/// Write a Snakefile with a rule for every task in the abstract workflow
+/// (whose wildcards are the branchpoints its realizations differ by),
+/// and a rule `all` that targets the goals of `plan` on each of its branches.
+pub fn write_snakefile(wf: &Workflow, plan: &Plan, out: &mut impl Write) -> Result<()> {
+    let mut rules = Rules::new(wf);
+    let mut translated = Vec::new();
+    for task in rules.tasks() {
+        translated.push(rules.rule(task, &BranchSpec::default())?);
+    }
+
+    writeln!(out, "# Generated by 'hr export --format snakemake'.")?;
+    writeln!(
+        out,
+        "# Each rule runs a task in <task>/{REALIZATIONS_DIR}/<branch>, with a wildcard in <branch>"
+    )?;
+    writeln!(out, "# for each branchpoint its realizations differ by.")?;
+    writeln!(out, "import os\n")?;
+
+    let mut wildcards: Vec<BranchpointId> =
+        translated.iter().flat_map(|rule| rule.wildcards.iter().copied()).collect();
+    wildcards.sort_unstable_by_key(|k| usize::from(*k));
+    wildcards.dedup();
+    if !wildcards.is_empty() {
+        writeln!(out, "wildcard_constraints:")?;
+        for k in wildcards {
+            let mut branches = Vec::new();
+            for v in wf.strings.baselines.defined(k) {
+                branches.push(regex_escape(wf.strings.idents.get(v)?));
+            }
+            writeln!(
+                out,
+                "    {}={},",
+                wf.strings.branchpoints.get(k)?,
+                py_str(&branches.join("|"))
+            )?;
+        }
+        writeln!(out)?;
+    }
+    writeln!(out, "{ABS_FN}")?;
+
+    let targets = targets(&translated, plan, wf)?;
+    if !targets.is_empty() {
+        writeln!(out, "rule all:\n    input:")?;
+        for target in targets {
+            writeln!(out, "        {},", py_str(&target))?;
+        }
+    }
+    for rule in &translated {
+        writeln!(out)?;
+        write_rule(rule, wf, out)?;
+    }
+    Ok(())
+}
+
+/// `exit_code` files of the realizations of each goal of `plan` on each of its branches.
+fn targets(rules: &[Rule], plan: &Plan, wf: &Workflow) -> Result<Vec<String>> {
+    let mut targets = Vec::new();
+    for subplan in &plan.subplans {
+        for branch in &subplan.branches {
+            for goal in &subplan.goals {
+                let Some(rule) = rules.iter().find(|rule| rule.task == *goal) else {
+                    continue;
+                };
+                let mut parts = Vec::new();
+                for k in &rule.wildcards {
+                    let v =
+                        branch.get_specified(*k).unwrap_or_else(|| wf.strings.baselines.get(*k));
+                    parts.push((*k, Some(v)));
+                }
+                let mut path = String::new();
+                for part in realization_dir(rule.task, &parts, wf)? {
+                    if let Part::Lit(s) = part {
+                        path.push_str(&s);
+                    }
+                }
+                path.push_str(Artifact::ExitCode.file_name());
+                if !targets.contains(&path) {
+                    targets.push(path);
+                }
+            }
+        }
+    }
+    Ok(targets)
+}
+
+fn write_rule(rule: &Rule, wf: &Workflow, out: &mut impl Write) -> Result<()> {
+    let task = wf.get_task(rule.task)?;
+    let dir: Vec<(BranchpointId, Option<IdentId>)> =
+        rule.wildcards.iter().map(|k| (*k, None)).collect();
+    let dir = realization_dir(rule.task, &dir, wf)?;
+
+    // every variable is exported from a param, so inputs and outputs can be made absolute:
+    let mut inputs = Vec::with_capacity(rule.inputs.len());
+    let mut params = Vec::with_capacity(rule.inputs.len() + rule.outputs.len() + rule.params.len());
+    for (k, value) in &rule.inputs {
+        let name = wf.strings.idents.get(*k)?;
+        if value.outputs().is_empty() {
+            // literal inputs are passed on as-is, as hr does:
+            params.push((name, value_str(value, true, wf)?));
+        } else {
+            inputs.push((py_name(name), value_str(value, false, wf)?));
+            params.push((
+                name,
+                format!("lambda wc, input: _hr_abs(input.{})", py_name(name)),
+            ));
+        }
+    }
+    let mut outputs = Vec::with_capacity(rule.outputs.len() + 1);
+    for (k, file) in rule.output_files(wf)? {
+        let name = wf.strings.idents.get(k)?;
+        let mut path = dir.clone();
+        path.push(Part::Lit(file.to_owned()));
+        outputs.push((py_name(name), render(&path, false)));
+        params.push((
+            name,
+            format!("lambda wc, output: _hr_abs(output.{})", py_name(name)),
+        ));
+    }
+    let mut exit_code = dir;
+    exit_code.push(Part::Lit(Artifact::ExitCode.file_name().to_owned()));
+    outputs.push((EXIT_CODE_OUTPUT.to_owned(), render(&exit_code, false)));
+    for (k, value) in &rule.params {
+        params.push((wf.strings.idents.get(*k)?, value_str(value, true, wf)?));
+    }
+
+    writeln!(out, "rule {}:", wf.strings.tasks.get(rule.task)?)?;
+    for (section, items) in [("input", &inputs), ("output", &outputs)] {
+        if !items.is_empty() {
+            writeln!(out, "    {section}:")?;
+            for (name, value) in items {
+                writeln!(out, "        {name}={value},")?;
+            }
+        }
+    }
+    if !params.is_empty() {
+        writeln!(out, "    params:")?;
+        for (name, value) in &params {
+            writeln!(out, "        {}={value},", py_name(name))?;
+        }
+    }
+
+    let mut shell = String::with_capacity(256);
+    for (name, _) in &params {
+        shell.push_str(&format!("export {name}={{params.{}:q}}\n", py_name(name)));
+    }
+    shell.push_str(&format!(
+        "cd \"$(dirname {{output.{EXIT_CODE_OUTPUT}:q}})\"\n"
+    ));
+    let code = wf.strings.literals.get(task.code)?.trim_matches('\n');
+    // a one-line code block is padded by the spaces inside its braces:
+    let code = if code.contains('\n') {
+        code
+    } else {
+        code.trim()
+    };
+    for line in code.lines() {
+        shell.push_str(&line.trim_end().replace('{', "{{").replace('}', "}}"));
+        shell.push('\n');
+    }
+    shell.push_str(&format!("printf 0 > {}\n", Artifact::ExitCode.file_name()));
+
+    writeln!(out, "    shell:\n        \"\"\"")?;
+    let shell = shell.replace('\\', "\\\\").replace("\"\"\"", "\\\"\\\"\\\"");
+    for line in shell.lines() {
+        if line.is_empty() {
+            writeln!(out)?;
+        } else {
+            writeln!(out, "        {line}")?;
+        }
+    }
+    writeln!(out, "        \"\"\"")?;
+    Ok(())
+}
+
+/// `<task>/realizations/<branch>/`, where each branchpoint in `branch` is either fixed,
+/// or None to use the wildcard.
+fn realization_dir(
+    task: AbstractTaskId,
+    branch: &[(BranchpointId, Option<IdentId>)],
+    wf: &Workflow,
+) -> Result<Vec<Part>> {
+    let mut parts = vec![Part::Lit(format!(
+        "{}/{REALIZATIONS_DIR}/",
+        wf.strings.tasks.get(task)?
+    ))];
+    if branch.is_empty() {
+        parts.push(Part::Lit(BASELINE_BRANCH.to_owned()));
+    }
+    for (i, (k, v)) in branch.iter().enumerate() {
+        let k = wf.strings.branchpoints.get(*k)?;
+        let delim = if i > 0 {
+            BRANCH_DELIM.to_string()
+        } else {
+            String::new()
+        };
+        parts.push(Part::Lit(format!("{delim}{k}{BRANCH_KV_DELIM}")));
+        match v {
+            Some(v) => parts.push(Part::Lit(wf.strings.idents.get(*v)?.to_owned())),
+            None => parts.push(Part::Wildcard(k.to_owned())),
+        }
+    }
+    parts.push(Part::Lit("/".to_owned()));
+    Ok(parts)
+}
+
+/// `value` as a Snakemake pattern if it only depends on wildcards, or else as an input function.
+/// With `join_lists`, lists are joined into a single space-separated string.
+fn value_str(value: &Expr, join_lists: bool, wf: &Workflow) -> Result<String> {
+    let parts = parts(value, join_lists, wf)?;
+    Ok(if parts.iter().any(|part| matches!(part, Part::Code(_))) {
+        format!("lambda wc: {}", render(&parts, true))
+    } else {
+        render(&parts, false)
+    })
+}
+
+fn parts(value: &Expr, join_lists: bool, wf: &Workflow) -> Result<Vec<Part>> {
+    Ok(match value {
+        Expr::Lit(s) => vec![Part::Lit(s.clone())],
+        Expr::Concat(items) => {
+            let mut parts = Vec::new();
+            for item in items {
+                parts.extend(self::parts(item, join_lists, wf)?);
+            }
+            parts
+        }
+        Expr::Output(output) => output_parts(output, join_lists, wf)?,
+        Expr::Switch(arms) => {
+            let mut code = String::from("(");
+            for (conds, value) in arms {
+                let value = render(&self::parts(value, join_lists, wf)?, true);
+                if conds.is_empty() {
+                    code.push_str(&value);
+                    code.push(')');
+                    return Ok(vec![Part::Code(code)]);
+                }
+                let mut tests = Vec::with_capacity(conds.len());
+                for (k, v) in conds {
+                    tests.push(format!(
+                        "wc.{} == {}",
+                        wf.strings.branchpoints.get(*k)?,
+                        py_str(wf.strings.idents.get(*v)?)
+                    ));
+                }
+                code.push_str(&format!("{value} if {} else ", tests.join(" and ")));
+            }
+            code.push_str("None)");
+            vec![Part::Code(code)]
+        }
+        Expr::List(items) => {
+            let mut rendered = Vec::with_capacity(items.len());
+            for item in items {
+                rendered.push(render(&self::parts(item, join_lists, wf)?, true));
+            }
+            let list = format!("[{}]", rendered.join(", "));
+            if join_lists {
+                vec![Part::Code(format!("\" \".join({list})"))]
+            } else {
+                vec![Part::Code(list)]
+            }
+        }
+    })
+}
+
+fn output_parts(output: &OutputRef, join_lists: bool, wf: &Workflow) -> Result<Vec<Part>> {
+    let mut parts = realization_dir(output.task, &output.branch, wf)?;
+    parts.extend(self::parts(&output.file, join_lists, wf)?);
+    Ok(parts)
+}
+
+/// `parts` as a python expression (`code`), or as a Snakemake pattern,
+/// which can't contain `Part::Code`.
+fn render(parts: &[Part], code: bool) -> String {
+    if code {
+        let mut terms = Vec::with_capacity(parts.len());
+        let mut lit = String::new();
+        for part in parts {
+            match part {
+                Part::Lit(s) => lit.push_str(s),
+                Part::Wildcard(k) | Part::Code(k) => {
+                    if !lit.is_empty() {
+                        terms.push(py_str(&std::mem::take(&mut lit)));
+                    }
+                    terms.push(match part {
+                        Part::Wildcard(_) => format!("wc.{k}"),
+                        _ => k.clone(),
+                    });
+                }
+            }
+        }
+        if !lit.is_empty() || terms.is_empty() {
+            terms.push(py_str(&lit));
+        }
+        return terms.join(" + ");
+    }
+    let mut pattern = String::new();
+    for part in parts {
+        match part {
+            Part::Lit(s) => pattern.push_str(&s.replace('{', "{{").replace('}', "}}")),
+            Part::Wildcard(k) => pattern.push_str(&format!("{{{k}}}")),
+            Part::Code(_) => debug_assert!(false, "patterns can't contain code"),
+        }
+    }
+    py_str(&pattern)
+}
+
+/// Quote a string as a python string literal.
+fn py_str(s: &str) -> String {
+    // json string escapes are valid in python:
+    serde_json::Value::from(s).to_string()
+}
+
+/// Name of a variable as a python keyword argument.
+fn py_name(name: &str) -> String {
+    const KEYWORDS: [&str; 35] = [
+        "False", "None", "True", "and", "as", "assert", "async", "await", "break", "class",
+        "continue", "def", "del", "elif", "else", "except", "finally", "for", "from", "global",
+        "if", "import", "in", "is", "lambda", "nonlocal", "not", "or", "pass", "raise", "return",
+        "try", "while", "with", "yield",
+    ];
+    if KEYWORDS.contains(&name) {
+        format!("{name}_")
+    } else {
+        name.to_owned()
+    }
+}
+
+/// Escape a branch name for use in a wildcard constraint regex.
+fn regex_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if !c.is_alphanumeric() && c != '_' {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_write_snakefile() -> Result<()> {
+        let text = "task build > out :: p=(Profile: debug release) { touch $out }\n\
+                    task test < in=$out@build rel=$out@build[Profile: release] { echo \"${in}\" $rel }\n";
+        let mut wf = Workflow::default();
+        wf.load(syntax::parse(text)?, std::path::Path::new("."))?;
+        let plan = Plan::create_anonymous(
+            &mut wf.strings,
+            &["test".to_owned()],
+            vec![BranchSpec::default()],
+        )?;
+        let mut out = Vec::new();
+        write_snakefile(&wf, &plan, &mut out)?;
+        let snakefile = String::from_utf8(out)?;
+        let rules = &snakefile[snakefile.find("rule all").unwrap()..];
+        assert_eq!(
+            rules,
+            "rule all:\n    input:\n        \"test/realizations/Profile.debug/exit_code\",\n\
+             \n\
+             rule build:\n    \
+                 output:\n        \
+                     out=\"build/realizations/Profile.{Profile}/out\",\n        \
+                     hr_exit_code=\"build/realizations/Profile.{Profile}/exit_code\",\n    \
+                 params:\n        \
+                     out=lambda wc, output: _hr_abs(output.out),\n        \
+                     p=lambda wc: (\"debug\" if wc.Profile == \"debug\" else \"release\" if wc.Profile == \"release\" else None),\n    \
+                 shell:\n        \"\"\"\n        \
+                     export out={params.out:q}\n        \
+                     export p={params.p:q}\n        \
+                     cd \"$(dirname {output.hr_exit_code:q})\"\n        \
+                     touch $out\n        \
+                     printf 0 > exit_code\n        \
+                     \"\"\"\n\
+             \n\
+             rule test:\n    \
+                 input:\n        \
+                     in_=\"build/realizations/Profile.{Profile}/out\",\n        \
+                     rel=\"build/realizations/Profile.release/out\",\n    \
+                 output:\n        \
+                     hr_exit_code=\"test/realizations/Profile.{Profile}/exit_code\",\n    \
+                 params:\n        \
+                     in_=lambda wc, input: _hr_abs(input.in_),\n        \
+                     rel=lambda wc, input: _hr_abs(input.rel),\n    \
+                 shell:\n        \"\"\"\n        \
+                     export in={params.in_:q}\n        \
+                     export rel={params.rel:q}\n        \
+                     cd \"$(dirname {output.hr_exit_code:q})\"\n        \
+                     echo \"${{in}}\" $rel\n        \
+                     printf 0 > exit_code\n        \
+                     \"\"\"\n"
+        );
+        assert!(snakefile.contains("wildcard_constraints:\n    Profile=\"debug|release\",\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_render() {
+        let parts = [
+            Part::Lit("a/{x}/".to_owned()),
+            Part::Wildcard("X".to_owned()),
+            Part::Lit("/f".to_owned()),
+        ];
+        assert_eq!(render(&parts, false), "\"a/{{x}}/{X}/f\"");
+        assert_eq!(render(&parts, true), "\"a/{x}/\" + wc.X + \"/f\"");
+        assert_eq!(render(&[], true), "\"\"");
+        assert_eq!(py_name("in"), "in_");
+        assert_eq!(regex_escape("a-b.c_d"), "a\\-b\\.c_d");
+    }
+}
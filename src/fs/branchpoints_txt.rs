@@ -1,20 +1,278 @@
-//! Utility functions for dealing with the branchpoints.txt file.
+//! Utility functions for dealing with the branchpoints.txt file,
+//! which keeps branchpoint order and baselines consistent between runs:
+//!
+//! ```text
+//! version 2
+//! branchpoint Os linux mac windows          name, baseline, then other known branches
+//! realization <hash> Os.mac+Profile.debug   realization dir names ('--short-names')
+//! ```
+//!
+//! Files without a version line are in the original format,
+//! with a `Branchpoint.baseline` line for each branchpoint.
 
+use std::fmt;
 use std::path::Path;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 
 use intern::GetStr;
-use workflow::{Workflow, BRANCH_KV_DELIM};
+use workflow::{BranchpointId, Workflow, BRANCH_DELIM, BRANCH_KV_DELIM, NULL_IDENT};
 
 use crate::ui::Ui;
 
-use super::{Error, Fs};
+use util::PathEncodingError;
+
+use super::{Error, Fs, RealizationLocator, BASELINE_BRANCH};
+
+/// Current version of the branchpoints.txt format.
+const VERSION: u32 = 2;
+
+/// Parsed contents of a branchpoints.txt file.
+#[derive(Debug, Default, PartialEq)]
+pub struct BranchpointsFile {
+    /// Branchpoints in order, each with its baseline first and then its other known branches.
+    pub branchpoints: Vec<(String, Vec<String>)>,
+    /// Names of realization dirs and their compact branch strings ('--short-names').
+    pub short_names: Vec<(String, String)>,
+}
+
+impl BranchpointsFile {
+    /// Parse the contents of a branchpoints.txt file, failing on the first invalid line.
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        Self::parse_with(text, &mut |e| Err(e))
+    }
+
+    /// Parse the contents of a branchpoints.txt file, skipping invalid lines.
+    /// Returns the errors for the skipped lines along with everything else.
+    pub fn parse_lenient(text: &str) -> Result<(Self, Vec<Error>), Error> {
+        let mut errors = Vec::new();
+        let file = Self::parse_with(text, &mut |e| {
+            errors.push(e);
+            Ok(())
+        })?;
+        Ok((file, errors))
+    }
+
+    /// `on_invalid` decides whether an invalid line is fatal;
+    /// an unsupported version always is.
+    fn parse_with(
+        text: &str,
+        on_invalid: &mut dyn FnMut(Error) -> Result<(), Error>,
+    ) -> Result<Self, Error> {
+        let mut file = Self::default();
+        let mut version = 1;
+        let mut short_name_lines = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let n = i + 1;
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let invalid = |msg: &str| Error::InvalidBranchpointsFile(n, msg.to_owned());
+            let result = match (version, words.as_slice()) {
+                (_, []) => Ok(()),
+                (_, [comment, ..]) if comment.starts_with('#') => Ok(()),
+                (_, ["version", v]) => match v.parse::<u32>() {
+                    Ok(v) if v > VERSION => {
+                        return Err(Error::UnsupportedBranchpointsVersion(v, VERSION));
+                    }
+                    Ok(v) if !file.is_empty() => Err(invalid(&format!(
+                        "'version {v}' must come before everything else"
+                    ))),
+                    Ok(v) => {
+                        version = v;
+                        Ok(())
+                    }
+                    Err(_) => Err(invalid("expected 'version <number>'")),
+                },
+                (1, [kv]) => match kv.split_once(BRANCH_KV_DELIM) {
+                    Some((k, v)) => file.add_branchpoint(k, &[v], &invalid),
+                    None => Err(invalid("expected 'Branchpoint.baseline'")),
+                },
+                (1, [name, branch]) | (2.., ["realization", name, branch]) => {
+                    short_name_lines.push((n, name.to_string(), branch.to_string()));
+                    Ok(())
+                }
+                (2.., ["branchpoint", k, values @ ..]) if !values.is_empty() => {
+                    file.add_branchpoint(k, values, &invalid)
+                }
+                (2.., ["branchpoint", ..]) => Err(invalid(
+                    "expected 'branchpoint <name> <baseline> [<branch>...]'",
+                )),
+                (2.., ["realization", ..]) => {
+                    Err(invalid("expected 'realization <name> <branch>'"))
+                }
+                _ => Err(invalid("unrecognized line")),
+            };
+            if let Err(e) = result {
+                on_invalid(e)?;
+            }
+        }
+
+        // realizations are checked last, so they can refer to branchpoints on later lines:
+        for (n, name, branch) in short_name_lines {
+            let invalid = |msg: String| Error::InvalidBranchpointsFile(n, msg);
+            let result = if let Err(kv) = file.check_branch(&branch) {
+                Err(invalid(format!("{kv} is not a known branch")))
+            } else if let Some((_, other)) =
+                file.short_names.iter().find(|(existing, _)| *existing == name)
+            {
+                if *other == branch {
+                    Ok(())
+                } else {
+                    Err(invalid(format!(
+                        "{name} is already the name of realization {other}"
+                    )))
+                }
+            } else {
+                file.short_names.push((name, branch));
+                Ok(())
+            };
+            if let Err(e) = result {
+                on_invalid(e)?;
+            }
+        }
+        Ok(file)
+    }
+
+    fn add_branchpoint(
+        &mut self,
+        k: &str,
+        values: &[&str],
+        invalid: &dyn Fn(&str) -> Error,
+    ) -> Result<(), Error> {
+        if self.branchpoints.iter().any(|(existing, _)| existing == k) {
+            return Err(invalid(&format!(
+                "branchpoint {k} is listed more than once"
+            )));
+        }
+        let mut owned: Vec<String> = Vec::with_capacity(values.len());
+        for v in values {
+            if owned.iter().any(|existing| existing == v) {
+                return Err(invalid(&format!("branch {k}.{v} is listed more than once")));
+            }
+            owned.push(v.to_string());
+        }
+        self.branchpoints.push((k.to_owned(), owned));
+        Ok(())
+    }
+
+    /// Make sure every part of a compact branch string is a known branch,
+    /// returning the first one that isn't.
+    pub fn check_branch<'a>(&self, compact_branch: &'a str) -> Result<(), &'a str> {
+        if compact_branch == BASELINE_BRANCH {
+            return Ok(());
+        }
+        for kv in compact_branch.split(BRANCH_DELIM) {
+            let (k, v) = kv.split_once(BRANCH_KV_DELIM).ok_or(kv)?;
+            if !self.has_branch(k, v) {
+                return Err(kv);
+            }
+        }
+        Ok(())
+    }
+
+    /// true if `v` is a known branch of branchpoint `k`.
+    pub fn has_branch(&self, k: &str, v: &str) -> bool {
+        let values = self.branchpoints.iter().find(|(existing, _)| existing == k);
+        values.is_some_and(|(_, values)| values.iter().any(|existing| existing == v))
+    }
+
+    /// Add branch `v` of branchpoint `k`, which must already be known (so it has a baseline).
+    /// Returns false if `k` is unknown or `v` was already known.
+    pub fn add_branch(&mut self, k: &str, v: &str) -> bool {
+        match self.branchpoints.iter_mut().find(|(existing, _)| existing == k) {
+            Some((_, values)) if !values.iter().any(|existing| existing == v) => {
+                values.push(v.to_owned());
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Record the name of a realization dir with short names.
+    /// Returns false if it was already recorded.
+    pub fn add_short_name(&mut self, name: &str, compact_branch: &str) -> bool {
+        if self.short_names.iter().any(|(existing, _)| existing == name) {
+            return false;
+        }
+        self.short_names.push((name.to_owned(), compact_branch.to_owned()));
+        true
+    }
+
+    fn is_empty(&self) -> bool {
+        self.branchpoints.is_empty() && self.short_names.is_empty()
+    }
+
+    /// Branchpoints and branches known to `wf`, in branchpoint order.
+    pub fn from_workflow(wf: &Workflow) -> Result<Self> {
+        let mut file = Self::default();
+        for (k, baseline) in wf.strings.baselines.iter() {
+            // no branches were ever defined for this branchpoint (e.g. it's only used in a plan):
+            if *baseline == NULL_IDENT {
+                continue;
+            }
+            let k = BranchpointId::from(k);
+            let mut values = Vec::with_capacity(4);
+            for v in wf.strings.baselines.values(k) {
+                values.push(wf.strings.idents.get(*v)?.to_owned());
+            }
+            let k = wf.strings.branchpoints.get(k)?.to_owned();
+            file.branchpoints.push((k, values));
+        }
+        Ok(file)
+    }
+
+    /// Branchpoints and branches defined in `wf`'s config, in branchpoint order,
+    /// leaving out any that are only known from an earlier branchpoints.txt.
+    pub fn from_config(wf: &Workflow) -> Result<Self> {
+        let mut file = Self::default();
+        for (k, _) in wf.strings.baselines.iter() {
+            let k = BranchpointId::from(k);
+            let mut values = Vec::with_capacity(4);
+            for v in wf.strings.baselines.defined(k) {
+                values.push(wf.strings.idents.get(v)?.to_owned());
+            }
+            if !values.is_empty() {
+                let k = wf.strings.branchpoints.get(k)?.to_owned();
+                file.branchpoints.push((k, values));
+            }
+        }
+        Ok(file)
+    }
+
+    /// Load known branchpoints and branches into `wf`.
+    /// Returns true if realization dirs are named by hash.
+    pub fn load_into(&self, wf: &mut Workflow) -> Result<bool> {
+        for (k, values) in &self.branchpoints {
+            // the first one loaded is the baseline:
+            for v in values {
+                wf.strings.pre_load_baseline(k, v)?;
+            }
+        }
+        Ok(!self.short_names.is_empty())
+    }
+}
+
+impl fmt::Display for BranchpointsFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "version {VERSION}")?;
+        for (k, values) in &self.branchpoints {
+            write!(f, "branchpoint {k}")?;
+            for v in values {
+                write!(f, " {v}")?;
+            }
+            writeln!(f)?;
+        }
+        for (name, branch) in &self.short_names {
+            writeln!(f, "realization {name} {branch}")?;
+        }
+        Ok(())
+    }
+}
 
 impl Fs {
     /// Load the contents of `branchpoints_file` into `wf`.
+    /// If it records any short realization names, short names are enabled.
     pub fn load_branches(
-        &self,
+        &mut self,
         branchpoints_file: &Path,
         wf: &mut Workflow,
         strbuf: &mut String,
@@ -23,12 +281,11 @@ impl Fs {
         ui.verbose_progress("Reading branchpoints.txt file");
         if self.exists(branchpoints_file) {
             self.read_to_buf(branchpoints_file, strbuf)?;
-            for kv in strbuf.split_whitespace() {
-                if let Some((k, v)) = kv.split_once(BRANCH_KV_DELIM) {
-                    wf.strings.pre_load_baseline(k, v)?;
-                } else {
-                    return Err(Error::InvalidBranchpointsFile.into());
-                }
+            let file = BranchpointsFile::parse(strbuf).context(
+                "unable to load branchpoints.txt; fix it, or run 'hr repair-branchpoints' to rebuild it",
+            )?;
+            if file.load_into(wf)? {
+                self.short_names = true;
             }
             ui.done();
         } else {
@@ -37,26 +294,141 @@ impl Fs {
         Ok(())
     }
 
-    /// Write info from `wf` into `branchpoints_file`.
-    pub fn write_branches(
+    /// Read `branchpoints_file`, skipping invalid lines. Empty if it doesn't exist.
+    fn read_branches_lenient(&self, branchpoints_file: &Path) -> Result<BranchpointsFile> {
+        if !self.exists(branchpoints_file) {
+            return Ok(BranchpointsFile::default());
+        }
+        let mut text = String::new();
+        self.read_to_buf(branchpoints_file, &mut text)?;
+        Ok(BranchpointsFile::parse_lenient(&text)?.0)
+    }
+
+    /// Write info from `wf` into `branchpoints_file`,
+    /// keeping any short realization names it already records.
+    pub fn write_branches(&self, branchpoints_file: &Path, wf: &Workflow) -> Result<()> {
+        let mut file = BranchpointsFile::from_workflow(wf)?;
+        file.short_names = self.read_branches_lenient(branchpoints_file)?.short_names;
+        self.write_branches_file(branchpoints_file, &file)
+    }
+
+    /// Replace `branchpoints_file` with the contents of `file`.
+    pub fn write_branches_file(
         &self,
         branchpoints_file: &Path,
-        wf: &Workflow,
-        strbuf: &mut String,
+        file: &BranchpointsFile,
     ) -> Result<()> {
         if self.exists(branchpoints_file) {
             self.delete_file(branchpoints_file)?;
         }
-        strbuf.clear();
-        for (k, v) in wf.strings.baselines.iter() {
-            let branchpt = wf.strings.branchpoints.get(k.into())?;
-            let branchval = wf.strings.idents.get(*v)?;
-            strbuf.push_str(branchpt);
-            strbuf.push(BRANCH_KV_DELIM);
-            strbuf.push_str(branchval);
-            strbuf.push('\n');
+        self.write_file(branchpoints_file, &file.to_string())
+    }
+
+    /// Names of all realization dirs in the output dir, with their compact branch strings.
+    /// With short names, realizations that don't record their branch are skipped.
+    pub fn realization_branches_on_disk(&self) -> Result<Vec<(String, String)>> {
+        let mut realizations = Vec::new();
+        let mut locator = self.locator();
+        for task in self.task_names()? {
+            locator.locate_baseline(&task);
+            for entry in self.read_dir(locator.realizations_dir())? {
+                let entry = entry?;
+                let fname = entry.file_name();
+                let fname = fname.to_str().ok_or(PathEncodingError)?;
+                if !RealizationLocator::is_realization_name(fname) {
+                    continue;
+                }
+                let branch = if self.short_names {
+                    match self.read_realization_branch(entry.path())? {
+                        Some(branch) => branch,
+                        None => continue,
+                    }
+                } else {
+                    fname.to_owned()
+                };
+                realizations.push((fname.to_owned(), branch));
+            }
         }
-        self.write_file(branchpoints_file, strbuf)?;
+        Ok(realizations)
+    }
+
+    /// Record in `branchpoints_file` that realization dirs named `name` have `compact_branch`,
+    /// unless it's already there.
+    pub fn record_short_name(
+        &self,
+        branchpoints_file: &Path,
+        name: &str,
+        compact_branch: &str,
+    ) -> Result<()> {
+        let mut file = self.read_branches_lenient(branchpoints_file)?;
+        if file.add_short_name(name, compact_branch) {
+            self.write_branches_file(branchpoints_file, &file)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() -> Result<(), Error> {
+        let text = "version 2\n\
+                    branchpoint Os linux mac\n\
+                    \n\
+                    # comment\n\
+                    realization 0123456789abcdef Os.mac+Profile.debug\n\
+                    branchpoint Profile release debug\n";
+        let file = BranchpointsFile::parse(text)?;
+        assert_eq!(file.branchpoints.len(), 2);
+        assert!(file.has_branch("Profile", "debug"));
+        assert_eq!(
+            file.short_names,
+            vec![(
+                "0123456789abcdef".to_owned(),
+                "Os.mac+Profile.debug".to_owned()
+            )]
+        );
+        assert_eq!(BranchpointsFile::parse(&file.to_string())?, file);
+
+        // original format:
+        let file = BranchpointsFile::parse("Os.linux\nProfile.release\n")?;
+        assert_eq!(
+            file.branchpoints,
+            vec![
+                ("Os".to_owned(), vec!["linux".to_owned()]),
+                ("Profile".to_owned(), vec!["release".to_owned()])
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_errors() -> Result<(), Error> {
+        let line = |text: &str| match BranchpointsFile::parse(text) {
+            Err(Error::InvalidBranchpointsFile(n, _)) => Some(n),
+            _ => None,
+        };
+        assert_eq!(line("version 2\nbranchpoint Os\n"), Some(2));
+        assert_eq!(
+            line("version 2\nbranchpoint Os a\nbranchpoint Os b\n"),
+            Some(3)
+        );
+        assert_eq!(line("version 2\nbranchpoint Os a b a\n"), Some(2));
+        assert_eq!(line("version 2\nrealization abc Os.mac\n"), Some(2));
+        assert_eq!(line("Os.linux\nversion 2\n"), Some(2));
+        assert_eq!(line("Os\n"), Some(1));
+        assert_eq!(line("version 2\nbogus line\n"), Some(2));
+        assert!(matches!(
+            BranchpointsFile::parse("version 3\n"),
+            Err(Error::UnsupportedBranchpointsVersion(3, 2))
+        ));
+
+        let (file, errors) =
+            BranchpointsFile::parse_lenient("version 2\nbranchpoint Os a\nbogus line\n")?;
+        assert_eq!(errors.len(), 1);
+        assert!(file.has_branch("Os", "a"));
         Ok(())
     }
 }
@@ -0,0 +1,299 @@
+//! Cache of completed task outputs, shared between output dirs and keyed by content digest:
+//!
+//! ```text
+//! $CACHE/<digest>/stdout.txt
+//! $CACHE/<digest>/stderr.txt
+//! $CACHE/<digest>/outputs/<output path relative to the task's exec dir>
+//! ```
+//!
+//! Entries can also be shared with other machines through a [`RemoteCache`].
+
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{Context, Result};
+
+use util::{Digest, Digester};
+
+use crate::run_log::mirror_eprintln;
+
+use super::{ops, Artifact, Error, Fs, RemoteCache};
+
+const OUTPUTS_DIR: &str = "outputs";
+
+impl Fs {
+    /// Use `dir` (created if needed) as the task output cache.
+    /// Files in the cache dir can be modified just like the output dir.
+    pub fn enable_cache(&mut self, dir: &Path) -> Result<()> {
+        if !dir.exists() {
+            if self.dry_run {
+                mirror_eprintln!("Dry run. Not creating cache directory {dir:?}");
+                return Ok(());
+            }
+            fs::create_dir_all(dir).context("creating cache directory")?;
+        }
+        self.cache_prefix = Some(dir.canonicalize()?);
+        Ok(())
+    }
+
+    /// Also push entries to and pull them from the remote cache at `url`.
+    /// Requires a local cache dir, which pulled entries are unpacked into.
+    pub fn enable_remote_cache(&mut self, url: &str) -> Result<()> {
+        if self.cache_prefix.is_none() && !self.dry_run {
+            return Err(Error::RemoteCacheWithoutCache.into());
+        }
+        self.remote_cache = Some(RemoteCache::new(url)?);
+        Ok(())
+    }
+
+    /// True if a cache dir is in use.
+    pub fn has_cache(&self) -> bool {
+        self.cache_prefix.is_some()
+    }
+
+    /// Add the contents of the file or dir at `path` to `digester`.
+    pub fn digest_path<T: AsRef<Path>>(&self, path: T, digester: &mut Digester) -> Result<()> {
+        let path = path.as_ref();
+        ops::digest_path(path, digester).with_context(|| format!("digesting {path:?}"))
+    }
+
+    /// If the cache has an entry for `digest`, copy its outputs and logs into `exec_dir`
+    /// and return true. `outputs` are relative to `exec_dir`.
+    /// If the entry isn't in the local cache, we try to pull it from the remote cache first.
+    pub fn cache_restore(
+        &self,
+        digest: Digest,
+        exec_dir: &Path,
+        outputs: &[&Path],
+    ) -> Result<bool> {
+        let Some(entry) = self.cache_entry(digest) else {
+            return Ok(false);
+        };
+        if !entry.is_dir() && !self.cache_pull(digest, &entry)? {
+            return Ok(false);
+        }
+
+        let mut buf = PathBuf::with_capacity(256);
+        for artifact in [Artifact::Stdout, Artifact::Stderr] {
+            let src = artifact.path_in(&entry, &mut buf).to_owned();
+            if src.exists() {
+                self.copy(&src, artifact.path_in(exec_dir, &mut buf))?;
+            }
+        }
+        for output in outputs {
+            let tgt = exec_dir.join(output);
+            self.create_parent_dir(&tgt)?;
+            self.copy(entry.join(OUTPUTS_DIR).join(output), tgt)?;
+        }
+        Ok(true)
+    }
+
+    /// Copy the outputs and logs of a completed task from `exec_dir` into the cache.
+    /// The entry is first written to a temporary dir and then renamed into place,
+    /// so other runs never see a partial entry.
+    pub fn cache_store(&self, digest: Digest, exec_dir: &Path, outputs: &[&Path]) -> Result<()> {
+        let Some(entry) = self.cache_entry(digest) else {
+            return Ok(());
+        };
+        if entry.exists() {
+            return Ok(());
+        }
+        let tmp = cache_tmp(&entry, digest, "");
+        if tmp.exists() {
+            self.delete_dir(&tmp)?;
+        }
+        self.create_dir(tmp.join(OUTPUTS_DIR))?;
+
+        let mut buf = PathBuf::with_capacity(256);
+        for artifact in [Artifact::Stdout, Artifact::Stderr] {
+            let src = artifact.path_in(exec_dir, &mut buf).to_owned();
+            if src.exists() {
+                self.copy(&src, artifact.path_in(&tmp, &mut buf))?;
+            }
+        }
+        for output in outputs {
+            let tgt = tmp.join(OUTPUTS_DIR).join(output);
+            self.create_parent_dir(&tgt)?;
+            self.copy(exec_dir.join(output), tgt)?;
+        }
+
+        if self.rename(&tmp, &entry).is_err() {
+            // another run stored the same entry first:
+            log::info!("cache entry {digest} already exists; discarding ours");
+            self.delete_dir(&tmp)?;
+        }
+        Ok(())
+    }
+
+    /// Upload the local cache entry for `digest` to the remote cache, if there is one.
+    pub fn cache_push(&self, digest: Digest) -> Result<()> {
+        let (Some(remote), Some(entry)) = (&self.remote_cache, self.cache_entry(digest)) else {
+            return Ok(());
+        };
+        if !entry.is_dir() {
+            return Ok(());
+        }
+        let archive = cache_tmp(&entry, digest, ".tar");
+        let digest_file = cache_tmp(&entry, digest, ".digest");
+        let pushed = self.push_archive(remote, digest, &entry, &archive, &digest_file);
+        self.delete_tmp_files(&[&archive, &digest_file])?;
+        pushed
+    }
+
+    fn push_archive(
+        &self,
+        remote: &RemoteCache,
+        digest: Digest,
+        entry: &Path,
+        archive: &Path,
+        digest_file: &Path,
+    ) -> Result<()> {
+        self.check_whitelist(archive)?;
+        tar(&[
+            "-cf".as_ref(),
+            archive.as_ref(),
+            "-C".as_ref(),
+            entry.as_ref(),
+            ".".as_ref(),
+        ])?;
+        self.write_file(digest_file, &self.archive_digest(archive)?.to_string())?;
+        // archive first, so that readers never see a digest without its archive:
+        remote.push(archive, &format!("{digest}.tar"))?;
+        remote.push(digest_file, &format!("{digest}.digest"))?;
+        log::info!("pushed cache entry {digest} to {}", remote.url(""));
+        Ok(())
+    }
+
+    /// Try to download the entry for `digest` from the remote cache into `entry`.
+    /// Returns false if the remote doesn't have it, or the download is corrupt.
+    fn cache_pull(&self, digest: Digest, entry: &Path) -> Result<bool> {
+        let Some(remote) = &self.remote_cache else {
+            return Ok(false);
+        };
+        let archive = cache_tmp(entry, digest, ".tar");
+        let digest_file = cache_tmp(entry, digest, ".digest");
+        let pulled = self.pull_archive(remote, digest, entry, &archive, &digest_file);
+        self.delete_tmp_files(&[&archive, &digest_file])?;
+        pulled
+    }
+
+    fn pull_archive(
+        &self,
+        remote: &RemoteCache,
+        digest: Digest,
+        entry: &Path,
+        archive: &Path,
+        digest_file: &Path,
+    ) -> Result<bool> {
+        self.check_whitelist(archive)?;
+        self.check_whitelist(digest_file)?;
+        if !remote.fetch(&format!("{digest}.digest"), digest_file)?
+            || !remote.fetch(&format!("{digest}.tar"), archive)?
+        {
+            return Ok(false);
+        }
+
+        let mut strbuf = String::with_capacity(64);
+        self.read_to_buf(digest_file, &mut strbuf)?;
+        let expected: Digest = strbuf.trim().parse()?;
+        let actual = self.archive_digest(archive)?;
+        if actual != expected {
+            log::warn!(
+                "remote cache entry {digest} is corrupt \
+                 (archive digest is {actual}, expected {expected}); ignoring it"
+            );
+            return Ok(false);
+        }
+
+        check_archive_entries(archive)?;
+
+        let unpacked = cache_tmp(entry, digest, "");
+        if unpacked.exists() {
+            self.delete_dir(&unpacked)?;
+        }
+        self.create_dir(&unpacked)?;
+        if let Err(e) = tar(&[
+            "-xf".as_ref(),
+            archive.as_ref(),
+            "-C".as_ref(),
+            unpacked.as_ref(),
+        ]) {
+            self.delete_dir(&unpacked)?;
+            return Err(e);
+        }
+        if self.rename(&unpacked, entry).is_err() {
+            // another run pulled or stored the same entry first:
+            self.delete_dir(&unpacked)?;
+        }
+        log::info!("pulled cache entry {digest} from {}", remote.url(""));
+        Ok(true)
+    }
+
+    fn archive_digest(&self, archive: &Path) -> Result<Digest> {
+        let mut digester = Digester::default();
+        self.digest_path(archive, &mut digester)?;
+        Ok(digester.finish())
+    }
+
+    fn delete_tmp_files(&self, paths: &[&Path]) -> Result<()> {
+        for path in paths {
+            if path.exists() {
+                self.delete_file(path)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn cache_entry(&self, digest: Digest) -> Option<PathBuf> {
+        self.cache_prefix.as_ref().map(|prefix| prefix.join(digest.to_string()))
+    }
+}
+
+/// Temporary path next to a cache entry, unique to this process.
+fn cache_tmp(entry: &Path, digest: Digest, ext: &str) -> PathBuf {
+    entry.with_file_name(format!(".tmp-{digest}.{}{ext}", std::process::id()))
+}
+
+/// Make sure every path in `archive` stays inside the dir it's extracted into.
+fn check_archive_entries(archive: &Path) -> Result<()> {
+    let listing = tar(&["-tf".as_ref(), archive.as_ref()])?;
+    for entry in listing.lines() {
+        if !is_safe_entry(entry) {
+            return Err(Error::UnsafeArchiveEntry(entry.to_owned()).into());
+        }
+    }
+    Ok(())
+}
+
+fn is_safe_entry(entry: &str) -> bool {
+    Path::new(entry)
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+}
+
+/// Run tar with `args`, returning its stdout.
+fn tar(args: &[&OsStr]) -> Result<String> {
+    let output = Command::new("tar").args(args).output().context("running tar")?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_owned();
+        return Err(Error::Tar(stderr).into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_is_safe_entry() {
+        assert!(is_safe_entry("./"));
+        assert!(is_safe_entry("./outputs/a.txt"));
+        assert!(is_safe_entry("stdout.txt"));
+        assert!(!is_safe_entry("/etc/passwd"));
+        assert!(!is_safe_entry("../a.txt"));
+        assert!(!is_safe_entry("./outputs/../../a.txt"));
+    }
+}
@@ -0,0 +1,101 @@
+//! Requests from 'hr cancel' to a workflow running in the output dir.
+//!
+//! ```text
+//! $OUTPUT/cancel_request  "kill" on a line of its own to kill running tasks,
+//!                         and "task NAME" for each task to cancel (none means all of them)
+//! ```
+
+use std::path::PathBuf;
+
+use anyhow::Result;
+
+use super::Fs;
+
+/// What 'hr cancel' asked the running workflow to stop.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CancelRequest {
+    /// Tasks to cancel (along with the tasks that need them); if empty, the whole run.
+    pub tasks: Vec<String>,
+    /// Kill cancelled tasks that are already running, instead of letting them finish.
+    pub kill: bool,
+}
+
+impl CancelRequest {
+    /// True if the task named `task` is cancelled by this request.
+    pub fn covers(&self, task: &str) -> bool {
+        self.tasks.is_empty() || self.tasks.iter().any(|t| t == task)
+    }
+
+    fn parse(contents: &str) -> Self {
+        let mut request = Self::default();
+        for line in contents.lines().map(str::trim) {
+            if line == "kill" {
+                request.kill = true;
+            } else if let Some(task) = line.strip_prefix("task ") {
+                request.tasks.push(task.trim().to_owned());
+            }
+        }
+        request
+    }
+
+    fn write(&self) -> String {
+        let mut contents = String::with_capacity(64);
+        if self.kill {
+            contents.push_str("kill\n");
+        }
+        for task in &self.tasks {
+            contents.push_str("task ");
+            contents.push_str(task);
+            contents.push('\n');
+        }
+        contents
+    }
+}
+
+impl Fs {
+    /// Ask a workflow running in the output dir to cancel what `request` says.
+    pub fn request_cancel(&self, request: &CancelRequest, pathbuf: &mut PathBuf) -> Result<()> {
+        self.write_file(self.cancel_request(pathbuf), &request.write())
+    }
+
+    /// The outstanding cancel request, if there is one.
+    pub fn cancel_requested(&self, pathbuf: &mut PathBuf) -> Result<Option<CancelRequest>> {
+        let path = self.cancel_request(pathbuf);
+        if !self.exists(path) {
+            return Ok(None);
+        }
+        let mut contents = String::with_capacity(64);
+        self.read_to_buf(path, &mut contents)?;
+        Ok(Some(CancelRequest::parse(&contents)))
+    }
+
+    /// Delete the outstanding cancel request, if there is one.
+    pub fn clear_cancel_request(&self, pathbuf: &mut PathBuf) -> Result<()> {
+        let path = self.cancel_request(pathbuf);
+        if self.exists(path) {
+            self.delete_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_request() {
+        let request = CancelRequest {
+            tasks: vec!["a".to_owned(), "b".to_owned()],
+            kill: true,
+        };
+        assert_eq!(CancelRequest::parse(&request.write()), request);
+        assert!(request.covers("a"));
+        assert!(!request.covers("c"));
+
+        // an empty request (as written by older versions) cancels the whole run:
+        let request = CancelRequest::parse("");
+        assert_eq!(request, CancelRequest::default());
+        assert!(request.covers("c"));
+    }
+}
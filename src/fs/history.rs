@@ -0,0 +1,304 @@
+//! Database of past runs in the output dir, for 'hr history'.
+//!
+//! ```text
+//! $OUTPUT/history.db  sqlite database with a row in `runs` for each run,
+//!                     and a row in `tasks` for each task it was supposed to run
+//! ```
+//!
+//! sqlite is linked in (bundled), so the history works without an `sqlite3` install.
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::Result;
+use rusqlite::types::ValueRef;
+use rusqlite::{params, Connection, OpenFlags};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use util::{format_duration, HashMap};
+
+use super::{Error, Fs};
+
+const HISTORY_DB: &str = "history.db";
+
+/// How many of the most recent runs to base estimates of task durations on.
+const ESTIMATE_RUNS: usize = 10;
+
+const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS runs (
+  id INTEGER PRIMARY KEY,
+  plans TEXT NOT NULL,
+  branch TEXT NOT NULL,
+  started INTEGER NOT NULL,
+  finished INTEGER NOT NULL,
+  succeeded INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS tasks (
+  run INTEGER NOT NULL REFERENCES runs(id),
+  task TEXT NOT NULL,
+  realization TEXT NOT NULL,
+  state TEXT NOT NULL,
+  duration REAL
+);
+CREATE INDEX IF NOT EXISTS tasks_by_run ON tasks(run);
+";
+
+/// One run, as recorded in the history database. Timestamps are seconds since the unix epoch.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunRecord {
+    /// Plans (or tasks, if no plan was given) the run was for, comma-separated
+    pub plans: String,
+    /// Branch given on the command line, if any
+    pub branch: String,
+    pub started: u64,
+    pub finished: u64,
+    pub succeeded: bool,
+    pub tasks: Vec<TaskRecord>,
+}
+
+/// One task that a run was supposed to run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TaskRecord {
+    /// Name of the task
+    pub task: String,
+    /// Task and branch, e.g. 'task[Profile.debug]'
+    pub realization: String,
+    /// "completed", "restored", "failed", "running", "cancelled", or "not-run"
+    pub state: &'static str,
+    /// In seconds, if it started
+    pub duration: Option<f64>,
+}
+
+/// Expected duration of each task, based on the run history.
+#[derive(Debug, Default, Clone)]
+pub struct Estimates {
+    by_task: HashMap<String, Duration>,
+}
+
+impl Estimates {
+    /// Expected duration of the task named `task`, if it's completed in a recent run.
+    pub fn get(&self, task: &str) -> Option<Duration> {
+        self.by_task.get(task).copied()
+    }
+
+    /// Total expected duration of `tasks` (by name), and how many of them have no estimate.
+    pub fn total<'a>(&self, tasks: impl IntoIterator<Item = &'a str>) -> (Duration, usize) {
+        let mut total = Duration::ZERO;
+        let mut unknown = 0;
+        for task in tasks {
+            match self.get(task) {
+                Some(d) => total += d,
+                None => unknown += 1,
+            }
+        }
+        (total, unknown)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_task.is_empty()
+    }
+}
+
+/// Format an estimated duration, e.g. "~3m05s".
+pub fn format_estimate(d: Duration) -> String {
+    format!("~{}", format_duration(d))
+}
+
+impl Fs {
+    /// $OUTPUT/history.db
+    pub fn history_db(&self) -> PathBuf {
+        self.output_prefix.join(HISTORY_DB)
+    }
+
+    /// Add `run` to the history database, creating it if necessary.
+    pub fn record_run(&self, run: &RunRecord) -> Result<()> {
+        let db = self.history_db();
+        self.check_whitelist(&db)?;
+
+        let err =
+            |e: rusqlite::Error| Error::History(db.to_string_lossy().into_owned(), e.to_string());
+
+        let mut conn = Connection::open(&db).map_err(err)?;
+        conn.execute_batch(SCHEMA).map_err(err)?;
+        let tx = conn.transaction().map_err(err)?;
+        tx.execute(
+            "INSERT INTO runs (plans, branch, started, finished, succeeded) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                run.plans,
+                run.branch,
+                run.started as i64,
+                run.finished as i64,
+                run.succeeded
+            ],
+        )
+        .map_err(err)?;
+        let id = tx.last_insert_rowid();
+        {
+            let mut insert = tx
+                .prepare(
+                    "INSERT INTO tasks (run, task, realization, state, duration) \
+                     VALUES (?1, ?2, ?3, ?4, ?5)",
+                )
+                .map_err(err)?;
+            for task in &run.tasks {
+                insert
+                    .execute(params![
+                        id,
+                        task.task,
+                        task.realization,
+                        task.state,
+                        task.duration
+                    ])
+                    .map_err(err)?;
+            }
+        }
+        tx.commit().map_err(err)?;
+        Ok(())
+    }
+
+    /// Estimate how long each task will take, from its average duration when it completed
+    /// (rather than being restored from the cache) in the most recent runs.
+    pub fn estimates(&self) -> Result<Estimates> {
+        let sql = format!(
+            "SELECT task, avg(duration) AS avg FROM tasks \
+             WHERE run IN (SELECT id FROM runs ORDER BY id DESC LIMIT {ESTIMATE_RUNS}) \
+               AND state = 'completed' \
+             GROUP BY task;"
+        );
+        #[derive(Deserialize)]
+        struct Average {
+            task: String,
+            avg: Option<f64>,
+        }
+        let mut by_task = HashMap::default();
+        for row in self.query_history::<Average>(&sql)?.unwrap_or_default() {
+            if let Some(avg) = row.avg {
+                by_task.insert(row.task, Duration::from_secs_f64(avg.max(0.0)));
+            }
+        }
+        Ok(Estimates { by_task })
+    }
+
+    /// Numbers of succeeded and failed runs in the history, or None if there is no history.
+    pub fn run_totals(&self) -> Result<Option<(u64, u64)>> {
+        // the sums are null if there are no runs:
+        #[derive(Deserialize)]
+        struct Totals {
+            succeeded: Option<u64>,
+            failed: Option<u64>,
+        }
+        let sql = "SELECT sum(succeeded = 1) AS succeeded, sum(succeeded = 0) AS failed FROM runs;";
+        let Some(rows) = self.query_history::<Totals>(sql)? else {
+            return Ok(None);
+        };
+        let totals = rows.first().map_or((0, 0), |row| {
+            (row.succeeded.unwrap_or(0), row.failed.unwrap_or(0))
+        });
+        Ok(Some(totals))
+    }
+
+    /// Run a read-only query against the history database, returning its rows,
+    /// each deserialized from an object with a field for each column.
+    /// Returns None if there is no history yet.
+    pub fn query_history<T: DeserializeOwned>(&self, sql: &str) -> Result<Option<Vec<T>>> {
+        let db = self.history_db();
+        if !self.exists(&db) {
+            return Ok(None);
+        }
+        let err = |e: String| Error::History(db.to_string_lossy().into_owned(), e);
+        let rows = query(&db, sql).map_err(|e| err(e.to_string()))?;
+        let rows = rows.into_iter().map(serde_json::from_value);
+        Ok(Some(
+            rows.collect::<Result<_, _>>().map_err(|e| err(e.to_string()))?,
+        ))
+    }
+}
+
+/// Run the read-only query `sql` against the database at `db`,
+/// returning its rows as json objects.
+fn query(db: &Path, sql: &str) -> rusqlite::Result<Vec<Value>> {
+    let conn = Connection::open_with_flags(db, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let mut stmt = conn.prepare(sql)?;
+    let names: Vec<String> = stmt.column_names().into_iter().map(str::to_owned).collect();
+    let mut rows = stmt.query([])?;
+    let mut out = Vec::new();
+    while let Some(row) = rows.next()? {
+        let mut obj = Map::with_capacity(names.len());
+        for (i, name) in names.iter().enumerate() {
+            let value = match row.get_ref(i)? {
+                ValueRef::Null => Value::Null,
+                ValueRef::Integer(n) => Value::from(n),
+                ValueRef::Real(n) => Value::from(n),
+                ValueRef::Text(s) | ValueRef::Blob(s) => {
+                    Value::String(String::from_utf8_lossy(s).into_owned())
+                }
+            };
+            obj.insert(name.clone(), value);
+        }
+        out.push(Value::Object(obj));
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_record_and_query() -> Result<()> {
+        let dir = tempdir()?;
+        let fs = Fs::new(dir.path(), false);
+        assert_eq!(fs.query_history::<Value>("SELECT * FROM runs;")?, None);
+        assert!(fs.estimates()?.is_empty());
+
+        let task = |task: &str, state, duration| TaskRecord {
+            task: task.to_owned(),
+            realization: format!("{task}[X.x1]"),
+            state,
+            duration,
+        };
+        let run = RunRecord {
+            plans: "main".to_owned(),
+            branch: "it's".to_owned(),
+            started: 100,
+            finished: 102,
+            succeeded: false,
+            tasks: vec![
+                task("a", "completed", Some(1.5)),
+                task("b", "not-run", None),
+            ],
+        };
+        fs.record_run(&run)?;
+        fs.record_run(&run)?;
+
+        let rows = fs
+            .query_history::<Value>(
+                "SELECT run, task, state, duration FROM tasks WHERE run = 2 ORDER BY task;",
+            )?
+            .unwrap();
+        assert_eq!(
+            rows,
+            [
+                serde_json::json!({"run": 2, "task": "a", "state": "completed", "duration": 1.5}),
+                serde_json::json!({"run": 2, "task": "b", "state": "not-run", "duration": null}),
+            ]
+        );
+        let rows = fs.query_history::<Value>("SELECT branch FROM runs WHERE id = 3;")?.unwrap();
+        assert!(rows.is_empty());
+        let rows = fs.query_history::<Value>("SELECT branch FROM runs WHERE id = 1;")?.unwrap();
+        assert_eq!(rows, [serde_json::json!({"branch": "it's"})]);
+
+        assert_eq!(fs.run_totals()?, Some((0, 2)));
+
+        let estimates = fs.estimates()?;
+        assert_eq!(estimates.get("a"), Some(Duration::from_millis(1500)));
+        assert_eq!(estimates.get("b"), None);
+        assert_eq!(
+            estimates.total(["a", "b", "a"]),
+            (Duration::from_secs(3), 1)
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,327 @@
+//! Single source of truth for where the files belonging to a task realization live:
+//!
+//! ```text
+//! $OUTPUT/task_name/                                   task base dir
+//! $OUTPUT/task_name/realizations/                      realizations dir
+//! $OUTPUT/task_name/realizations/Branchpt.branch/      realization dir
+//! $OUTPUT/task_name/realizations/<hash of branch>/     realization dir ('--short-names')
+//! $OUTPUT/task_name/realizations/.tmp-Branchpt.branch.<suffix>/  scratch dir (unless '--in-place')
+//! $OUTPUT/task_name/Branchpt.branch+Other.baseline     symlink to realization dir
+//! $OUTPUT/task_name/latest                             symlink to most recently completed realization
+//! ```
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+use intern::GetStr;
+use util::Digester;
+use workflow::{RealTaskKey, Workflow};
+
+use super::Fs;
+
+/// Name of the dir inside a task's base dir that holds its realizations.
+pub const REALIZATIONS_DIR: &str = "realizations";
+/// Compact branch string of the baseline branch.
+pub const BASELINE_BRANCH: &str = "Baseline.baseline";
+/// Name of the symlink in a task's base dir to its most recently completed realization.
+pub const LATEST_LINK: &str = "latest";
+/// Length of realization dir names with '--short-names'.
+const SHORT_NAME_LEN: usize = 16;
+/// Prefix of temporary realization dirs that tasks run in (unless '--in-place').
+pub const SCRATCH_PREFIX: &str = ".tmp-";
+
+/// Files that `hr` writes to every realization dir.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Artifact {
+    /// Contains "0" if the task completed successfully.
+    ExitCode,
+    /// Task stdout.
+    Stdout,
+    /// Task stderr.
+    Stderr,
+    /// Archived copy of the code the task ran.
+    TaskSh,
+    /// Updated periodically by the task while it runs.
+    Heartbeat,
+    /// Digest of the task's code, params, and inputs when it completed.
+    Fingerprint,
+    /// Machine-readable manifest of the realization's inputs, outputs, params, etc.
+    TaskInfo,
+    /// Compact branch string of a realization whose dir is named by its hash ('--short-names').
+    Branch,
+}
+
+impl Artifact {
+    pub fn file_name(self) -> &'static str {
+        match self {
+            Self::ExitCode => "exit_code",
+            Self::Stdout => "stdout.txt",
+            Self::Stderr => "stderr.txt",
+            Self::TaskSh => "task.sh",
+            Self::Heartbeat => "heartbeat",
+            Self::Fingerprint => "fingerprint",
+            Self::TaskInfo => "task_info.json",
+            Self::Branch => "branch.txt",
+        }
+    }
+
+    /// Path to this artifact in the given dir (which may be a scratch dir).
+    pub fn path_in(self, dir: impl AsRef<Path>, buf: &mut PathBuf) -> &Path {
+        buf.clear();
+        buf.push(dir);
+        buf.push(self.file_name());
+        &*buf
+    }
+}
+
+/// Reusable set of paths for a single task realization.
+/// Call one of the `locate` fns to point it at a realization, then read the paths you need.
+#[derive(Debug)]
+pub struct RealizationLocator {
+    /// $OUTPUT
+    output_prefix: PathBuf,
+    /// name the realization dir by a hash of its branch string
+    short_names: bool,
+    /// name of the current realization dir
+    name: String,
+    /// $OUTPUT/task_name
+    task_base: PathBuf,
+    /// $OUTPUT/task_name/realizations
+    realizations_dir: PathBuf,
+    /// realizations/Branchpt.branch
+    realization_relative: PathBuf,
+    /// $OUTPUT/task_name/realizations/Branchpt.branch
+    realization: PathBuf,
+    /// $OUTPUT/task_name/Branchpt.branch+Branchpt.branch, or empty if not located by full branch
+    link_src: PathBuf,
+    /// used for constructing artifact paths
+    scratch: PathBuf,
+}
+
+impl RealizationLocator {
+    /// Create a new locator for realizations in `fs`'s output dir.
+    pub fn new(fs: &Fs) -> Self {
+        Self {
+            output_prefix: fs.output_prefix.clone(),
+            short_names: fs.short_names,
+            name: String::with_capacity(128),
+            task_base: PathBuf::with_capacity(256),
+            realizations_dir: PathBuf::with_capacity(256),
+            realization_relative: PathBuf::with_capacity(256),
+            realization: PathBuf::with_capacity(512),
+            link_src: PathBuf::with_capacity(512),
+            scratch: PathBuf::with_capacity(512),
+        }
+    }
+
+    /// Point at the realization of `task` identified by its compact branch string.
+    /// Symlink location is unknown, so `link_src` will be empty.
+    pub fn locate(&mut self, task: &str, compact_branch: &str) {
+        self.name.clear();
+        if self.short_names {
+            self.name.push_str(&Self::short_name(compact_branch));
+        } else {
+            self.name.push_str(compact_branch);
+        }
+
+        self.task_base.clear();
+        self.task_base.push(&self.output_prefix);
+        self.task_base.push(task);
+
+        self.realizations_dir.clear();
+        self.realizations_dir.push(&self.task_base);
+        self.realizations_dir.push(REALIZATIONS_DIR);
+
+        self.realization_relative.clear();
+        self.realization_relative.push(REALIZATIONS_DIR);
+        self.realization_relative.push(&self.name);
+
+        self.realization.clear();
+        self.realization.push(&self.task_base);
+        self.realization.push(&self.realization_relative);
+
+        self.link_src.clear();
+    }
+
+    /// Point at the baseline realization of `task`.
+    pub fn locate_baseline(&mut self, task: &str) {
+        self.locate(task, BASELINE_BRANCH);
+    }
+
+    /// Point at the realization for `key`, including its symlink.
+    pub fn locate_key(
+        &mut self,
+        key: &RealTaskKey,
+        wf: &Workflow,
+        strbuf: &mut String,
+    ) -> Result<()> {
+        strbuf.clear();
+        wf.strings.make_compact_branch_string(&key.branch, strbuf)?;
+        self.locate(wf.strings.tasks.get(key.id)?, strbuf);
+
+        self.link_src.push(&self.task_base);
+        self.link_src.push(&*wf.strings.get_full_branch_str(&key.branch)?);
+        Ok(())
+    }
+
+    /// $OUTPUT/task_name
+    pub fn task_base(&self) -> &Path {
+        &self.task_base
+    }
+
+    /// $OUTPUT/task_name/realizations
+    pub fn realizations_dir(&self) -> &Path {
+        &self.realizations_dir
+    }
+
+    /// realizations/Branchpt.branch
+    pub fn realization_relative(&self) -> &Path {
+        &self.realization_relative
+    }
+
+    /// $OUTPUT/task_name/realizations/Branchpt.branch
+    pub fn realization(&self) -> &Path {
+        &self.realization
+    }
+
+    /// $OUTPUT/task_name/Branchpt.branch+Branchpt.branch
+    pub fn link_src(&self) -> &Path {
+        &self.link_src
+    }
+
+    /// $OUTPUT/task_name/realizations/Branchpt.branch/<artifact>
+    pub fn artifact(&mut self, artifact: Artifact) -> &Path {
+        artifact.path_in(&self.realization, &mut self.scratch)
+    }
+
+    /// $OUTPUT/task_name/realizations/.tmp-Branchpt.branch.<unique suffix>
+    pub fn make_scratch_realization(&self, buf: &mut PathBuf) {
+        use std::time::{SystemTime, UNIX_EPOCH};
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or_default();
+        let suffix = nanos ^ std::process::id().rotate_left(16);
+        buf.clear();
+        buf.push(&self.realizations_dir);
+        buf.push(format!("{SCRATCH_PREFIX}{}.{suffix:08x}", self.name));
+    }
+
+    /// Name of the realization dir for `compact_branch` with '--short-names':
+    /// a stable hash that's short no matter how many branchpoints there are.
+    pub fn short_name(compact_branch: &str) -> String {
+        let mut digester = Digester::default();
+        digester.update_str(compact_branch);
+        let mut digest = digester.finish().to_string();
+        digest.truncate(SHORT_NAME_LEN);
+        digest
+    }
+
+    /// True if `name` is another scratch dir for the same realization as `scratch`
+    /// (they share everything but the unique suffix).
+    pub fn is_sibling_scratch(name: &str, scratch: &Path) -> bool {
+        let prefix = scratch
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.rsplit_once('.'))
+            .map(|(prefix, _suffix)| prefix);
+        match prefix {
+            Some(prefix) => name.strip_prefix(prefix).is_some_and(|rest| rest.starts_with('.')),
+            None => false,
+        }
+    }
+
+    /// True if `name` is a scratch dir for the located realization, made by `make_scratch_realization`.
+    pub fn is_scratch_name(&self, name: &str) -> bool {
+        Self::is_scratch_of(name, &self.name)
+    }
+
+    /// True if `name` is a scratch dir for the realization whose dir is named `realization`.
+    pub fn is_scratch_of(name: &str, realization: &str) -> bool {
+        name.strip_prefix(SCRATCH_PREFIX)
+            .and_then(|rest| rest.strip_prefix(realization))
+            .and_then(|rest| rest.strip_prefix('.'))
+            .is_some_and(|suffix| !suffix.is_empty() && !suffix.contains(['.', '+']))
+    }
+
+    /// True if `name` is an entry in a realizations dir that belongs to a realization
+    /// (as opposed to e.g. a scratch dir).
+    pub fn is_realization_name(name: &str) -> bool {
+        !name.starts_with(SCRATCH_PREFIX)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::{Path, PathBuf};
+
+    use super::{Artifact, RealizationLocator, SCRATCH_PREFIX};
+    use crate::fs::Fs;
+
+    #[test]
+    fn test_locate() {
+        let fs = Fs::new(Path::new("/out"), true);
+        let mut locator = RealizationLocator::new(&fs);
+        locator.locate("task", "Baseline.baseline+Os.mac");
+        assert_eq!(
+            locator.realizations_dir(),
+            Path::new("/out/task/realizations")
+        );
+        assert_eq!(
+            locator.realization_relative(),
+            Path::new("realizations/Baseline.baseline+Os.mac")
+        );
+        assert_eq!(
+            locator.realization(),
+            Path::new("/out/task/realizations/Baseline.baseline+Os.mac")
+        );
+        assert_eq!(
+            locator.artifact(Artifact::ExitCode),
+            Path::new("/out/task/realizations/Baseline.baseline+Os.mac/exit_code")
+        );
+        assert_eq!(locator.link_src(), Path::new(""));
+
+        locator.locate_baseline("other");
+        assert_eq!(
+            locator.realization(),
+            Path::new("/out/other/realizations/Baseline.baseline")
+        );
+
+        let mut scratch = PathBuf::new();
+        locator.make_scratch_realization(&mut scratch);
+        assert_eq!(scratch.parent(), Some(Path::new("/out/other/realizations")));
+        let name = scratch.file_name().unwrap().to_str().unwrap();
+        assert!(name.starts_with(&format!("{SCRATCH_PREFIX}Baseline.baseline.")));
+        assert!(!RealizationLocator::is_realization_name(name));
+        assert!(RealizationLocator::is_realization_name("Baseline.baseline"));
+        assert!(RealizationLocator::is_sibling_scratch(
+            ".tmp-Baseline.baseline.0000abcd",
+            &scratch
+        ));
+        assert!(!RealizationLocator::is_sibling_scratch(
+            ".tmp-Baseline.baseline+Os.mac.0000abcd",
+            &scratch
+        ));
+        assert!(locator.is_scratch_name(name));
+        assert!(!locator.is_scratch_name(".tmp-Baseline.baseline+Os.mac.0000abcd"));
+        assert!(!locator.is_scratch_name("Baseline.baseline"));
+    }
+
+    #[test]
+    fn test_short_names() {
+        let mut fs = Fs::new(Path::new("/out"), true);
+        fs.set_short_names(true);
+        let mut locator = RealizationLocator::new(&fs);
+        let branch = "Os.mac+Profile.debug+Target.arm64";
+        locator.locate("task", branch);
+        let name = RealizationLocator::short_name(branch);
+        assert_eq!(name.len(), 16);
+        assert_eq!(name, RealizationLocator::short_name(branch));
+        assert_ne!(name, RealizationLocator::short_name("Os.mac"));
+        assert_eq!(
+            locator.realization(),
+            Path::new("/out/task/realizations").join(&name)
+        );
+    }
+}
@@ -0,0 +1,90 @@
+//! Lock file that keeps two `hr` processes from modifying the same output dir at once.
+
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+
+use util::PathEncodingError;
+
+use crate::run_log::mirror_eprintln;
+
+use super::{Error, Fs};
+
+/// Held while this process is allowed to modify the output dir.
+/// The lock file is deleted when this is dropped.
+#[derive(Debug)]
+pub struct RunLock {
+    path: PathBuf,
+}
+
+impl Drop for RunLock {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            log::warn!("unable to remove lock file {:?}: {e}", self.path);
+        }
+    }
+}
+
+impl Fs {
+    /// Acquire the lock on the output dir, recording our pid and the current time in it.
+    /// If `force` is true, an existing lock is deleted first.
+    /// Does nothing if this `Fs` already holds the lock.
+    pub fn acquire_lock(&mut self, force: bool) -> Result<()> {
+        if self.lock.is_some() {
+            return Ok(());
+        }
+
+        let mut path = PathBuf::with_capacity(256);
+        self.lock_file(&mut path);
+        self.check_whitelist(&path)?;
+
+        if force && self.exists(&path) {
+            mirror_eprintln!("Removing existing lock file {path:?}.");
+            fs::remove_file(&path).context("removing lock file")?;
+        }
+
+        let mut f = match fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(f) => f,
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                let holder = fs::read_to_string(&path).unwrap_or_default();
+                let path = path.to_str().ok_or(PathEncodingError)?.to_owned();
+                return Err(Error::Locked(
+                    path,
+                    holder.split_whitespace().collect::<Vec<_>>().join(" "),
+                )
+                .into());
+            }
+            Err(e) => return Err(e).context("creating lock file"),
+        };
+
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        writeln!(f, "pid {}\ntime {timestamp}", std::process::id()).context("writing lock file")?;
+
+        self.lock = Some(RunLock { path });
+        Ok(())
+    }
+
+    /// Pid of the process holding the lock on the output dir, and when it acquired the lock
+    /// (in seconds since the unix epoch), or None if the output dir isn't locked.
+    pub fn lock_holder(&self) -> Result<Option<(u32, u64)>> {
+        let mut path = PathBuf::with_capacity(256);
+        self.lock_file(&mut path);
+        let holder = match fs::read_to_string(&path) {
+            Ok(holder) => holder,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).context("reading lock file"),
+        };
+        let (mut pid, mut time) = (0, 0);
+        for line in holder.lines() {
+            match line.split_once(' ') {
+                Some(("pid", v)) => pid = v.trim().parse().context("reading lock file pid")?,
+                Some(("time", v)) => time = v.trim().parse().context("reading lock file time")?,
+                _ => (),
+            }
+        }
+        Ok(Some((pid, time)))
+    }
+}
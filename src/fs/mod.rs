@@ -1,18 +1,57 @@
 use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::SystemTime;
 use std::{fs, io};
 
 use anyhow::{Context, Result};
 
 use util::PathEncodingError;
 
+use crate::run_log::mirror_eprintln;
+
 /// Utility fns
 mod ops;
 
 /// Defines fns for creating common paths in the output directory
 mod paths;
 
+/// Paths to task realization dirs and the files inside them
+mod locator;
+pub use locator::{Artifact, RealizationLocator, BASELINE_BRANCH, LATEST_LINK, REALIZATIONS_DIR};
+
 /// Dealing with the branchpoints.txt file
 mod branchpoints_txt;
+pub use branchpoints_txt::BranchpointsFile;
+
+/// Cache of task outputs shared between output dirs
+mod cache;
+
+/// Remote backend for the task output cache
+mod remote;
+use remote::RemoteCache;
+
+/// Workflow and traversal cached between runs
+mod traversal_cache;
+pub use traversal_cache::CachedTraversal;
+
+/// Trash area for deleted realizations
+mod trash;
+
+/// Locking the output dir during a run
+mod lock;
+use lock::RunLock;
+
+/// Checkouts of versioned modules
+mod modules;
+pub use modules::ModuleRev;
+
+/// Requests to cancel a running workflow
+mod cancel;
+pub use cancel::CancelRequest;
+
+/// Database of past runs
+mod history;
+pub use history::{format_estimate, Estimates, RunRecord, TaskRecord};
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
@@ -22,10 +61,39 @@ pub enum Error {
     NotDirectory(String),
     #[error("Can't perform IO operation: \"{0}\" is not whitelisted")]
     NotWhitelisted(String),
-    #[error("Invalid branchpoints.txt file")]
-    InvalidBranchpointsFile,
+    #[error("Invalid branchpoints.txt, line {0}: {1}")]
+    InvalidBranchpointsFile(usize, String),
+    #[error(
+        "branchpoints.txt has version {0}, but this version of hr only supports up to version {1}"
+    )]
+    UnsupportedBranchpointsVersion(u32, u32),
     #[error("Path has no parent: {0}")]
     NoParent(String),
+    #[error(
+        "Output directory is locked by another run ({1}). \
+         If no other hr process is using it, remove {0} or rerun with --force-unlock"
+    )]
+    Locked(String, String),
+    #[error(
+        "Unsupported remote cache url \"{0}\" (expected http://, https://, file://, or s3://)"
+    )]
+    UnsupportedRemoteCache(String),
+    #[error("Remote cache transfer of {0} failed: {1}")]
+    RemoteCacheCommand(String, String),
+    #[error("A remote cache requires a local cache dir (use --cache)")]
+    RemoteCacheWithoutCache,
+    #[error("tar failed: {0}")]
+    Tar(String),
+    #[error("Remote cache archive contains unsafe path \"{0}\" (absolute or containing \"..\")")]
+    UnsafeArchiveEntry(String),
+    #[error("Can't move {0} to trash: it is not in the output directory")]
+    NotInOutputDir(String),
+    #[error("Unable to resolve rev \"{1}\" of module {0}: {2}")]
+    ModuleRev(String, String, String),
+    #[error("Unable to check out commit {0} of module into {1}: {2}")]
+    ModuleCheckout(String, String, String),
+    #[error("Unable to update run history {0}: {1}")]
+    History(String, String),
 }
 
 /// All file operations in the crate should go through this struct.
@@ -40,6 +108,16 @@ pub struct Fs {
     output_prefix: PathBuf,
     /// if true, prevents all destructive operations
     dry_run: bool,
+    /// lock on the output dir, if we've acquired it
+    lock: Option<RunLock>,
+    /// task output cache dir, which we're also allowed to modify
+    cache_prefix: Option<PathBuf>,
+    /// remote backend for the cache, if any
+    remote_cache: Option<RemoteCache>,
+    /// trash batch that deleted realizations are moved into, created on first use
+    trash_batch: OnceLock<PathBuf>,
+    /// name realization dirs by a hash of their branch string
+    short_names: bool,
 }
 
 impl Fs {
@@ -48,27 +126,90 @@ impl Fs {
         Self {
             output_prefix: output_prefix.to_path_buf(),
             dry_run,
+            lock: None,
+            cache_prefix: None,
+            remote_cache: None,
+            trash_batch: OnceLock::new(),
+            short_names: false,
         }
     }
 
+    /// Create a `RealizationLocator` for finding task realizations in our output dir.
+    pub fn locator(&self) -> RealizationLocator {
+        RealizationLocator::new(self)
+    }
+
     /// Set the `dry_run` variable to true or false.
     /// If true, no destructive operations will be performed.
     pub fn set_dry_run(&mut self, dry_run: bool) {
         self.dry_run = dry_run;
     }
 
+    /// Name realization dirs by a short hash of their branch string
+    /// instead of the branch string itself.
+    pub fn set_short_names(&mut self, short_names: bool) {
+        self.short_names = short_names;
+    }
+
+    /// true if realization dirs are named by a hash of their branch string.
+    pub fn short_names(&self) -> bool {
+        self.short_names
+    }
+
+    /// Compact branch string of the realization in `dir`, named by a hash with short names.
+    /// None if it wasn't recorded.
+    pub fn read_realization_branch<T: AsRef<Path>>(&self, dir: T) -> Result<Option<String>> {
+        let path = dir.as_ref().join(Artifact::Branch.file_name());
+        if !self.exists(&path) {
+            return Ok(None);
+        }
+        let mut branch = String::new();
+        self.read_to_buf(path, &mut branch)?;
+        Ok(Some(branch.trim().to_owned()))
+    }
+
+    /// The newest scratch dir of the realization whose dir is `realization`, if it has any
+    /// (a task that fails, or is still running, leaves its scratch dir next to it).
+    pub fn newest_scratch_dir(&self, realization: &Path) -> Result<Option<PathBuf>> {
+        let (Some(realizations_dir), Some(name)) = (
+            realization.parent(),
+            realization.file_name().and_then(|name| name.to_str()),
+        ) else {
+            return Ok(None);
+        };
+        if !self.exists(realizations_dir) {
+            return Ok(None);
+        }
+        let mut newest: Option<(SystemTime, PathBuf)> = None;
+        for entry in self.read_dir(realizations_dir)? {
+            let path = entry?.path();
+            let is_scratch = path
+                .file_name()
+                .and_then(|entry| entry.to_str())
+                .is_some_and(|entry| RealizationLocator::is_scratch_of(entry, name));
+            if !is_scratch {
+                continue;
+            }
+            let modified = self.modified(&path)?;
+            if newest.as_ref().is_none_or(|(newest, _)| modified > *newest) {
+                newest = Some((modified, path));
+            }
+        }
+        Ok(newest.map(|(_, path)| path))
+    }
+
     /// Check whether output dir exists, and create it if not.
     pub fn ensure_out_dir_exists(&mut self, verbose: bool) -> Result<()> {
         if !self.output_prefix.exists() {
             if self.dry_run {
-                eprintln!(
+                mirror_eprintln!(
                     "Dry run. Not creating output directory {:?}",
                     self.output_prefix
                 );
                 // short-circuit so we don't try to canonicalize:
                 return Ok(());
             } else {
-                eprintln!(
+                mirror_eprintln!(
                     "Output directory {:?} doesn't exist. Creating.",
                     self.output_prefix
                 );
@@ -80,7 +221,7 @@ impl Fs {
             )
             .into());
         } else if verbose {
-            eprintln!(
+            mirror_eprintln!(
                 "Output directory {:?} already exists. Not creating.",
                 self.output_prefix
             );
@@ -96,6 +237,23 @@ impl Fs {
         path.exists() || path.is_symlink()
     }
 
+    /// Check if path is a symlink (whether or not its target exists).
+    pub fn is_symlink<T: AsRef<Path>>(&self, path: T) -> bool {
+        path.as_ref().is_symlink()
+    }
+
+    /// Check if path is a symlink whose target doesn't exist.
+    pub fn is_dangling_symlink<T: AsRef<Path>>(&self, path: T) -> bool {
+        let path = path.as_ref();
+        path.is_symlink() && !path.exists()
+    }
+
+    /// Read the target of a symlink.
+    pub fn read_link<T: AsRef<Path>>(&self, path: T) -> Result<PathBuf> {
+        let path = path.as_ref();
+        fs::read_link(path).with_context(|| format!("reading symlink {path:?}"))
+    }
+
     /// Check if path exists and is a directory.
     pub fn is_dir<T: AsRef<Path>>(&self, path: T) -> Result<bool> {
         let path = path.as_ref();
@@ -146,6 +304,20 @@ impl Fs {
         Ok(())
     }
 
+    /// Write a report that was asked for on the command line (e.g. with '--junit') to `path`,
+    /// which unlike the other files we write can be outside the output dir.
+    /// Like them, it isn't written in a dry run.
+    pub fn write_report<T: AsRef<Path>>(&self, path: T, text: &str) -> Result<()> {
+        let path = path.as_ref();
+        if self.dry_run {
+            return Err(
+                Error::NotWhitelisted(path.to_str().ok_or(PathEncodingError)?.to_owned()).into(),
+            );
+        }
+        fs::write(path, text).with_context(|| format!("writing report {path:?}"))?;
+        Ok(())
+    }
+
     /// Delete a file.
     pub fn delete_file<T: AsRef<Path>>(&self, path: T) -> Result<()> {
         let path = path.as_ref();
@@ -162,6 +334,15 @@ impl Fs {
         Ok(())
     }
 
+    /// Rename `src` to `tgt`. Atomic if both are on the same filesystem.
+    pub fn rename<T: AsRef<Path>, U: AsRef<Path>>(&self, src: T, tgt: U) -> Result<()> {
+        let (src, tgt) = (src.as_ref(), tgt.as_ref());
+        self.check_whitelist(src)?;
+        self.check_whitelist(tgt)?;
+        fs::rename(src, tgt).with_context(|| format!("renaming {:?} to {:?}", src, tgt))?;
+        Ok(())
+    }
+
     /// Symlink `symlink` to `tgt`.
     pub fn symlink<T: AsRef<Path>, U: AsRef<Path>>(&self, tgt: T, symlink: U) -> Result<()> {
         let (tgt, symlink) = (tgt.as_ref(), symlink.as_ref());
@@ -171,6 +352,24 @@ impl Fs {
         Ok(())
     }
 
+    /// Point the `latest` symlink of the task that owns `realization` at it.
+    /// The new link is renamed over the old one, so `latest` never disappears.
+    pub fn update_latest_link<T: AsRef<Path>>(&self, realization: T) -> Result<()> {
+        let realization = realization.as_ref();
+        let task_base = realization.parent().and_then(Path::parent);
+        let (Some(task_base), Some(fname)) = (task_base, realization.file_name()) else {
+            return Ok(());
+        };
+        let target = Path::new(locator::REALIZATIONS_DIR).join(fname);
+        // realizations of the same task can finish at once:
+        let tmp = task_base.join(format!(".{LATEST_LINK}.{}.tmp", fname.to_string_lossy()));
+        if self.exists(&tmp) {
+            self.delete_file(&tmp)?;
+        }
+        self.symlink(target, &tmp)?;
+        self.rename(&tmp, task_base.join(LATEST_LINK))
+    }
+
     /// Copy `src` to `tgt`, recursively if `src` is a directory.
     pub fn copy<T: AsRef<Path>, U: AsRef<Path>>(&self, src: T, tgt: U) -> Result<()> {
         let (src, tgt) = (src.as_ref(), tgt.as_ref());
@@ -193,6 +392,46 @@ impl Fs {
         Ok(())
     }
 
+    /// Total size in bytes of a file, or all files in a directory tree.
+    pub fn disk_usage<T: AsRef<Path>>(&self, path: T) -> Result<u64> {
+        ops::disk_usage(path.as_ref()).context("calculating disk usage")
+    }
+
+    /// Add the size and mtime of the file at `path` (or of all files in the dir) to `digester`.
+    pub fn digest_metadata<T: AsRef<Path>>(
+        &self,
+        path: T,
+        digester: &mut util::Digester,
+    ) -> Result<()> {
+        let path = path.as_ref();
+        ops::digest_metadata(path, digester)
+            .with_context(|| format!("digesting metadata of {path:?}"))
+    }
+
+    /// Last modification time of the file or dir at `path`.
+    pub fn modified<T: AsRef<Path>>(&self, path: T) -> Result<SystemTime> {
+        let path = path.as_ref();
+        let modified = fs::metadata(path).and_then(|meta| meta.modified());
+        modified.with_context(|| format!("reading modification time of {path:?}"))
+    }
+
+    /// Names of all tasks in the output dir that have a realizations dir.
+    pub fn task_names(&self) -> Result<Vec<String>> {
+        let mut tasks = Vec::new();
+        if !self.is_dir(&self.output_prefix)? {
+            return Ok(tasks);
+        }
+        for entry in self.read_dir(&self.output_prefix)? {
+            let entry = entry?;
+            if entry.path().join(locator::REALIZATIONS_DIR).is_dir() {
+                let fname = entry.file_name();
+                tasks.push(fname.to_str().ok_or(PathEncodingError)?.to_owned());
+            }
+        }
+        tasks.sort_unstable();
+        Ok(tasks)
+    }
+
     /// List entries in a directory
     pub fn read_dir<T: AsRef<Path>>(&self, path: T) -> Result<fs::ReadDir, io::Error> {
         fs::read_dir(path)
@@ -205,6 +444,9 @@ impl Fs {
         if path.starts_with(&self.output_prefix) {
             return true;
         }
+        if let Some(cache_prefix) = &self.cache_prefix {
+            return path.starts_with(cache_prefix);
+        }
         false
     }
 
@@ -0,0 +1,158 @@
+//! Checkouts of versioned modules, so tasks can run in a module at a specific version.
+//!
+//! ```text
+//! $OUTPUT/.modules/<module>/<commit>  clone of the module's git repo, checked out at the commit
+//! ```
+//!
+//! Checkouts are cloned into a temporary sibling and renamed into place,
+//! so a checkout dir that exists is always complete.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::Result;
+
+use util::PathEncodingError;
+
+use super::{Error, Fs};
+
+const MODULES_DIR: &str = ".modules";
+
+/// What a module's rev resolved to in its git repo.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModuleRev {
+    /// Full hash of the commit.
+    pub commit: String,
+    /// Top level of the git repo containing the module.
+    pub repo: PathBuf,
+    /// Path of the module dir relative to `repo` (empty if the module is the whole repo).
+    pub prefix: PathBuf,
+}
+
+impl Fs {
+    /// $OUTPUT/.modules
+    pub fn modules_dir(&self) -> PathBuf {
+        self.output_prefix.join(MODULES_DIR)
+    }
+
+    /// $OUTPUT/.modules/<module>/<commit>
+    pub fn module_checkout_dir(&self, module: &str, commit: &str) -> PathBuf {
+        let mut dir = self.modules_dir();
+        dir.push(module);
+        dir.push(commit);
+        dir
+    }
+
+    /// Resolve `rev` (a branch, tag, or commit) in the git repo containing `module_path`.
+    pub fn resolve_module_rev(&self, module_path: &Path, rev: &str) -> Result<ModuleRev> {
+        let path_str = module_path.to_str().ok_or(PathEncodingError)?;
+        let err = |msg: String| Error::ModuleRev(path_str.to_owned(), rev.to_owned(), msg);
+        let commit = git(
+            module_path,
+            &[
+                "rev-parse",
+                "--verify",
+                "--end-of-options",
+                &format!("{rev}^{{commit}}"),
+            ],
+        )
+        .map_err(err)?;
+        let repo = git(module_path, &["rev-parse", "--show-toplevel"]).map_err(err)?;
+        let prefix = git(module_path, &["rev-parse", "--show-prefix"]).map_err(err)?;
+        Ok(ModuleRev {
+            commit,
+            repo: PathBuf::from(repo),
+            prefix: PathBuf::from(prefix),
+        })
+    }
+
+    /// Clone `rev.repo` into `dir` and check out `rev.commit`, unless `dir` already exists.
+    pub fn checkout_module(&self, rev: &ModuleRev, dir: &Path) -> Result<()> {
+        if self.exists(dir) {
+            return Ok(());
+        }
+        let dir_str = dir.to_str().ok_or(PathEncodingError)?;
+        let err = |msg: String| Error::ModuleCheckout(rev.commit.clone(), dir_str.to_owned(), msg);
+
+        let mut tmp = dir.as_os_str().to_owned();
+        tmp.push(format!(".tmp{}", std::process::id()));
+        let tmp = PathBuf::from(tmp);
+        if self.exists(&tmp) {
+            self.delete_dir(&tmp)?;
+        }
+        self.create_parent_dir(&tmp)?;
+
+        let output = Command::new("git")
+            .args(["clone", "--quiet", "--no-checkout"])
+            .arg(&rev.repo)
+            .arg(&tmp)
+            .output()
+            .map_err(|e| err(e.to_string()))?;
+        if !output.status.success() {
+            return Err(err(String::from_utf8_lossy(&output.stderr).trim().to_owned()).into());
+        }
+        git(&tmp, &["checkout", "--quiet", "--detach", &rev.commit]).map_err(err)?;
+        self.rename(&tmp, dir)
+    }
+}
+
+/// Run git in `dir`, returning its trimmed stdout, or its stderr if it fails.
+fn git(dir: &Path, args: &[&str]) -> Result<String, String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .map_err(|e| format!("unable to run git: {e}"))?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).trim().to_owned())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_resolve_and_checkout() -> Result<()> {
+        let dir = tempdir()?;
+        let repo = dir.path().join("repo");
+        std::fs::create_dir_all(repo.join("sub"))?;
+        std::fs::write(repo.join("sub/file.txt"), "v1\n")?;
+        let run = |args: &[&str]| -> Result<()> {
+            let status = Command::new("git")
+                .arg("-C")
+                .arg(&repo)
+                .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+                .args(args)
+                .output()?
+                .status;
+            assert!(status.success(), "git {args:?}");
+            Ok(())
+        };
+        run(&["init", "-q"])?;
+        run(&["add", "."])?;
+        run(&["commit", "-q", "-m", "v1"])?;
+        run(&["tag", "v1"])?;
+        std::fs::write(repo.join("sub/file.txt"), "v2\n")?;
+        run(&["commit", "-q", "-am", "v2"])?;
+
+        let output = dir.path().join("output");
+        let fs = Fs::new(&output, false);
+        let rev = fs.resolve_module_rev(&repo.join("sub"), "v1")?;
+        assert_eq!(rev.commit.len(), 40);
+        assert_eq!(rev.prefix, PathBuf::from("sub/"));
+        assert!(fs.resolve_module_rev(&repo, "no-such-rev").is_err());
+
+        let checkout = fs.module_checkout_dir("m", &rev.commit);
+        fs.checkout_module(&rev, &checkout)?;
+        let text = std::fs::read_to_string(checkout.join(&rev.prefix).join("file.txt"))?;
+        assert_eq!(text, "v1\n");
+        // checking out again is a no-op:
+        fs.checkout_module(&rev, &checkout)?;
+        Ok(())
+    }
+}
@@ -63,6 +63,82 @@ fn resolve_new_link_tgt(
     }
 }
 
+/// Total size in bytes of all files under `path` (symlinks are not followed).
+pub fn disk_usage(path: &Path) -> Result<u64> {
+    let meta = fs::symlink_metadata(path)?;
+    if !meta.is_dir() {
+        return Ok(meta.len());
+    }
+    let mut total = 0;
+    for entry in fs::read_dir(path)? {
+        total += disk_usage(&entry?.path())?;
+    }
+    Ok(total)
+}
+
+/// Add the size and modification time of `path` to `digester`, or for directories
+/// the names and metadata of all entries. Like `digest_path`, but cheap enough to use
+/// on every run. Symlinks are only followed at the top level.
+pub fn digest_metadata(path: &Path, digester: &mut util::Digester) -> Result<()> {
+    digest_meta(path, &fs::metadata(path)?, digester)
+}
+
+fn digest_meta(path: &Path, meta: &fs::Metadata, digester: &mut util::Digester) -> Result<()> {
+    if meta.is_symlink() {
+        digester.update(b"l");
+        let tgt = fs::read_link(path)?;
+        digester.update_str(tgt.to_str().ok_or(PathEncodingError)?);
+    } else if meta.is_dir() {
+        digester.update(b"d");
+        let mut entries = fs::read_dir(path)?.collect::<Result<Vec<_>, _>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            digester.update_str(entry.file_name().to_str().ok_or(PathEncodingError)?);
+            digest_meta(&entry.path(), &entry.metadata()?, digester)?;
+        }
+    } else {
+        let mtime = meta.modified()?.duration_since(std::time::UNIX_EPOCH)?;
+        digester.update(b"f");
+        digester.update(&meta.len().to_le_bytes());
+        digester.update(&mtime.as_nanos().to_le_bytes());
+    }
+    Ok(())
+}
+
+/// Add the contents of `path` to `digester`: file contents, or for directories
+/// the names and contents of all entries (sorted, so order on disk doesn't matter).
+/// Symlinks are not followed; their targets are digested instead.
+pub fn digest_path(path: &Path, digester: &mut util::Digester) -> Result<()> {
+    use std::io::Read;
+    let meta = fs::symlink_metadata(path)?;
+    if meta.is_symlink() {
+        digester.update(b"l");
+        let tgt = fs::read_link(path)?;
+        digester.update_str(tgt.to_str().ok_or(PathEncodingError)?);
+    } else if meta.is_dir() {
+        digester.update(b"d");
+        let mut entries = fs::read_dir(path)?.collect::<Result<Vec<_>, _>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            digester.update_str(entry.file_name().to_str().ok_or(PathEncodingError)?);
+            digest_path(&entry.path(), digester)?;
+        }
+    } else {
+        digester.update(b"f");
+        digester.update(&meta.len().to_le_bytes());
+        let mut f = fs::File::open(path)?;
+        let mut buf = [0u8; 8192];
+        loop {
+            let num_read = f.read(&mut buf)?;
+            if num_read == 0 {
+                break;
+            }
+            digester.update(&buf[..num_read]);
+        }
+    }
+    Ok(())
+}
+
 /// Symlink the given `link` to `tgt`; works for unix and windows.
 pub fn symlink(tgt: &Path, link: &Path) -> Result<()> {
     #[cfg(unix)]
@@ -78,6 +154,7 @@ pub fn symlink(tgt: &Path, link: &Path) -> Result<()> {
 }
 
 #[cfg(test)]
+#[allow(clippy::needless_borrows_for_generic_args)]
 mod tests {
     use super::*;
     use anyhow::Result;
@@ -151,6 +228,67 @@ mod tests {
 
         assert_eq!(buf, "text to copy");
 
+        Ok(())
+    }
+    #[test]
+    fn test_digest_path() -> Result<()> {
+        use std::fs;
+
+        let dir = tempdir()?;
+        let digest = |path: &Path| -> Result<util::Digest> {
+            let mut digester = util::Digester::default();
+            digest_path(path, &mut digester)?;
+            Ok(digester.finish())
+        };
+
+        for name in ["a", "b"] {
+            let sub = dir.path().join(name).join("sub");
+            fs::create_dir_all(&sub)?;
+            fs::write(sub.join("file1"), "contents")?;
+            fs::write(sub.join("file2"), "more contents")?;
+        }
+        assert_eq!(
+            digest(&dir.path().join("a"))?,
+            digest(&dir.path().join("b"))?,
+            "Identical trees in different places have the same digest"
+        );
+
+        fs::write(dir.path().join("b/sub/file2"), "changed")?;
+        assert_ne!(
+            digest(&dir.path().join("a"))?,
+            digest(&dir.path().join("b"))?,
+            "Changed contents change the digest"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_digest_metadata() -> Result<()> {
+        use std::fs;
+        use std::time::{Duration, SystemTime};
+
+        let dir = tempdir()?;
+        let digest = |path: &Path| -> Result<util::Digest> {
+            let mut digester = util::Digester::default();
+            digest_metadata(path, &mut digester)?;
+            Ok(digester.finish())
+        };
+        let file = dir.path().join("sub/file");
+        fs::create_dir(dir.path().join("sub"))?;
+        fs::write(&file, "contents")?;
+
+        let before = digest(dir.path())?;
+        assert_eq!(before, digest(dir.path())?, "Digest is stable");
+
+        let mtime = SystemTime::now() + Duration::from_secs(10);
+        fs::File::options().write(true).open(&file)?.set_modified(mtime)?;
+        assert_ne!(
+            before,
+            digest(dir.path())?,
+            "Changed mtime changes the digest"
+        );
+
         Ok(())
     }
 }
@@ -0,0 +1,111 @@
+//! Remote backend for the task output cache, so that several machines can share outputs.
+//!
+//! Each cache entry is stored remotely as two objects:
+//!
+//! ```text
+//! $REMOTE/<digest>.tar     tar archive of the local cache entry
+//! $REMOTE/<digest>.digest  digest of the archive, used to verify downloads
+//! ```
+//!
+//! The archive is always uploaded before its digest, so a reader that finds the digest
+//! can expect the archive to be complete.
+
+use std::path::Path;
+use std::process::{Command, Output};
+
+use anyhow::Result;
+
+use super::Error;
+
+/// A remote location that cache entries can be pushed to and pulled from.
+/// Transfers are done by shelling out to `curl` (for http, https, and file urls)
+/// or the aws cli (for s3 urls), so that credentials are handled the usual way.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RemoteCache {
+    Curl(String),
+    S3(String),
+}
+
+impl RemoteCache {
+    /// Parse a remote cache url, e.g. `https://cache.example.com/hr` or `s3://bucket/prefix`.
+    pub fn new(url: &str) -> Result<Self, Error> {
+        let base = url.trim_end_matches('/').to_owned();
+        if url.starts_with("s3://") {
+            Ok(Self::S3(base))
+        } else if ["http://", "https://", "file://"].iter().any(|scheme| url.starts_with(scheme)) {
+            Ok(Self::Curl(base))
+        } else {
+            Err(Error::UnsupportedRemoteCache(url.to_owned()))
+        }
+    }
+
+    /// Url of the object with the given name.
+    pub fn url(&self, name: &str) -> String {
+        match self {
+            Self::Curl(base) | Self::S3(base) => format!("{base}/{name}"),
+        }
+    }
+
+    /// Download object `name` to `dest`. Returns false if it couldn't be downloaded,
+    /// which usually just means the object doesn't exist.
+    pub fn fetch(&self, name: &str, dest: &Path) -> Result<bool> {
+        let url = self.url(name);
+        let output = match self {
+            Self::Curl(_) => {
+                Command::new("curl").arg("-fsS").arg("-o").arg(dest).arg(&url).output()
+            }
+            Self::S3(_) => {
+                Command::new("aws").args(["s3", "cp", "--quiet", &url]).arg(dest).output()
+            }
+        }
+        .map_err(|e| Error::RemoteCacheCommand(url.clone(), e.to_string()))?;
+        if !output.status.success() {
+            log::debug!("unable to fetch {url}: {}", stderr(&output));
+        }
+        Ok(output.status.success())
+    }
+
+    /// Upload `src` as object `name`.
+    pub fn push(&self, src: &Path, name: &str) -> Result<()> {
+        let url = self.url(name);
+        let output = match self {
+            Self::Curl(_) => Command::new("curl").arg("-fsS").arg("-T").arg(src).arg(&url).output(),
+            Self::S3(_) => {
+                Command::new("aws").args(["s3", "cp", "--quiet"]).arg(src).arg(&url).output()
+            }
+        }
+        .map_err(|e| Error::RemoteCacheCommand(url.clone(), e.to_string()))?;
+        if !output.status.success() {
+            return Err(Error::RemoteCacheCommand(url, stderr(&output)).into());
+        }
+        Ok(())
+    }
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).trim().to_owned()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_new() -> Result<()> {
+        assert_eq!(
+            RemoteCache::new("https://example.com/cache/")?,
+            RemoteCache::Curl("https://example.com/cache".to_owned())
+        );
+        assert_eq!(
+            RemoteCache::new("s3://bucket/prefix")?,
+            RemoteCache::S3("s3://bucket/prefix".to_owned())
+        );
+        assert_eq!(
+            RemoteCache::new("s3://bucket")?.url("abc.tar"),
+            "s3://bucket/abc.tar"
+        );
+        assert!(RemoteCache::new("/local/path").is_err());
+        assert!(RemoteCache::new("ftp://example.com").is_err());
+        Ok(())
+    }
+}
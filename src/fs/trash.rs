@@ -0,0 +1,186 @@
+//! Trash area for realizations that `hr` deletes, so they can be restored if need be.
+//!
+//! ```text
+//! $OUTPUT/.trash/<batch>/trashed.txt      paths of everything in the batch, relative to $OUTPUT
+//! $OUTPUT/.trash/<batch>/<relative path>  trashed dirs, at their original relative paths
+//! ```
+//!
+//! Everything trashed by a single `hr` invocation goes in one batch,
+//! named for the (UTC) time it first trashed something.
+
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use anyhow::{Context, Result};
+
+use util::PathEncodingError;
+
+use super::{Error, Fs};
+
+const TRASH_DIR: &str = ".trash";
+const MANIFEST: &str = "trashed.txt";
+
+/// Result of restoring a trash batch.
+#[derive(Debug, Default)]
+pub struct Restored {
+    /// Paths that were moved back into place.
+    pub restored: Vec<PathBuf>,
+    /// Paths that were left in the trash because something already exists at their original location.
+    pub skipped: Vec<PathBuf>,
+}
+
+impl Fs {
+    /// $OUTPUT/.trash
+    pub fn trash_dir(&self) -> PathBuf {
+        self.output_prefix.join(TRASH_DIR)
+    }
+
+    /// Move `path` (inside the output dir) into this invocation's trash batch.
+    pub fn trash<T: AsRef<Path>>(&self, path: T) -> Result<()> {
+        let path = path.as_ref();
+        self.check_whitelist(path)?;
+        let relative = path
+            .strip_prefix(&self.output_prefix)
+            .map_err(|_| Error::NotInOutputDir(path.to_str().unwrap_or_default().to_owned()))?;
+
+        let batch = self.trash_batch()?;
+        let tgt = batch.join(relative);
+        self.create_parent_dir(&tgt)?;
+        fs::rename(path, &tgt).with_context(|| format!("moving {path:?} to trash"))?;
+
+        let mut manifest = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(batch.join(MANIFEST))
+            .context("opening trash manifest")?;
+        let relative = relative.to_str().ok_or(PathEncodingError)?;
+        writeln!(manifest, "{relative}").context("writing trash manifest")?;
+        Ok(())
+    }
+
+    /// Dir of this invocation's trash batch, which is created the first time it's needed.
+    /// Invocations in the same second get suffixes, e.g. "20261014T174352Z-2".
+    fn trash_batch(&self) -> Result<&Path> {
+        if let Some(batch) = self.trash_batch.get() {
+            return Ok(batch);
+        }
+        let trash_dir = self.trash_dir();
+        self.create_dir(&trash_dir)?;
+        let timestamp = util::format_utc_timestamp(SystemTime::now());
+        let mut batch = trash_dir.join(&timestamp);
+        let mut n = 1;
+        loop {
+            match fs::create_dir(&batch) {
+                Ok(()) => break,
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                    n += 1;
+                    batch = trash_dir.join(format!("{timestamp}-{n}"));
+                }
+                Err(e) => return Err(e).context("creating trash batch dir"),
+            }
+        }
+        Ok(self.trash_batch.get_or_init(|| batch))
+    }
+
+    /// Names of all trash batches, oldest first.
+    pub fn trash_batches(&self) -> Result<Vec<String>> {
+        let trash_dir = self.trash_dir();
+        let mut batches = Vec::new();
+        if !self.is_dir(&trash_dir)? {
+            return Ok(batches);
+        }
+        for entry in self.read_dir(&trash_dir)? {
+            let entry = entry?;
+            if entry.path().join(MANIFEST).exists() {
+                let fname = entry.file_name();
+                batches.push(fname.to_str().ok_or(PathEncodingError)?.to_owned());
+            }
+        }
+        batches.sort_unstable();
+        Ok(batches)
+    }
+
+    /// Paths (relative to the output dir) of everything in a trash batch.
+    pub fn trash_contents(&self, batch: &str) -> Result<Vec<PathBuf>> {
+        let mut text = String::new();
+        self.read_to_buf(self.trash_dir().join(batch).join(MANIFEST), &mut text)
+            .with_context(|| format!("reading manifest of trash batch {batch}"))?;
+        Ok(text.lines().filter(|line| !line.is_empty()).map(PathBuf::from).collect())
+    }
+
+    /// Move everything in a trash batch back to its original location,
+    /// and delete the batch if nothing had to be left behind.
+    pub fn restore_trash(&self, batch: &str) -> Result<Restored> {
+        let batch_dir = self.trash_dir().join(batch);
+        let mut result = Restored::default();
+        for relative in self.trash_contents(batch)? {
+            let src = batch_dir.join(&relative);
+            let tgt = self.output_prefix.join(&relative);
+            if !self.exists(&src) {
+                continue;
+            }
+            if self.exists(&tgt) {
+                result.skipped.push(tgt);
+                continue;
+            }
+            self.create_parent_dir(&tgt)?;
+            self.rename(&src, &tgt)?;
+            result.restored.push(tgt);
+        }
+        if result.skipped.is_empty() {
+            self.delete_dir(&batch_dir)?;
+        }
+        Ok(result)
+    }
+
+    /// Permanently delete everything in the trash.
+    pub fn empty_trash(&self) -> Result<()> {
+        let trash_dir = self.trash_dir();
+        if self.exists(&trash_dir) {
+            self.delete_dir(&trash_dir)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_trash_and_restore() -> Result<()> {
+        let dir = tempdir()?;
+        let fs = Fs::new(dir.path(), false);
+        let realization = dir.path().join("task/realizations/X.x1");
+        fs.create_dir(&realization)?;
+        fs.write_file(realization.join("out.txt"), "out")?;
+
+        fs.trash(&realization)?;
+        assert!(!realization.exists());
+        let batches = fs.trash_batches()?;
+        assert_eq!(batches.len(), 1);
+        assert_eq!(
+            fs.trash_contents(&batches[0])?,
+            vec![PathBuf::from("task/realizations/X.x1")]
+        );
+
+        let restored = fs.restore_trash(&batches[0])?;
+        assert_eq!(restored.restored, vec![realization.clone()]);
+        assert!(realization.join("out.txt").exists());
+        assert!(fs.trash_batches()?.is_empty());
+
+        // something already at the original location:
+        fs.trash(&realization)?;
+        fs.create_dir(&realization)?;
+        let restored = fs.restore_trash(&batches[0])?;
+        assert_eq!(restored.skipped, vec![realization.clone()]);
+        assert_eq!(fs.trash_batches()?, batches);
+
+        fs.empty_trash()?;
+        assert!(!fs.trash_dir().exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,83 @@
+//! Workflow and traversal of the last run, so that running the same target again
+//! with the same config doesn't have to parse it and traverse the workflow again:
+//!
+//! ```text
+//! $OUTPUT/traversal.cache
+//! ```
+//!
+//! The file starts with the digest of everything the workflow and traversal depend on
+//! (see `App::traversal_cache_key`), and is only used by a run with the same digest.
+//! The rest is the workflow and traversal, serialized with bincode.
+
+use std::fs;
+
+use anyhow::{Context, Result};
+use bincode::Options;
+
+use traverse::Traversal;
+use util::Digest;
+use workflow::{TaskRedefinition, Workflow};
+
+use super::Fs;
+
+const MAGIC: &[u8] = b"hr traversal cache\n";
+
+/// A workflow and traversal read from the cache.
+pub struct CachedTraversal {
+    pub wf: Workflow,
+    pub traversal: Traversal,
+    /// tasks redefined by layered config files, which we warn about when loading the workflow.
+    pub redefinitions: Vec<TaskRedefinition>,
+}
+
+impl Fs {
+    /// The cached workflow and traversal, if they were cached with `key`.
+    pub fn read_traversal_cache(&self, key: Digest) -> Result<Option<CachedTraversal>> {
+        let path = self.traversal_cache();
+        if !self.exists(&path) {
+            return Ok(None);
+        }
+        let bytes = fs::read(&path).context("reading traversal cache")?;
+        let Some(rest) = bytes.strip_prefix(MAGIC).and_then(|rest| rest.strip_prefix(&key.0))
+        else {
+            return Ok(None);
+        };
+        // the limit keeps corrupt lengths from allocating more than the file could hold:
+        let (wf, traversal, redefinitions) = bincode::options()
+            .with_limit(rest.len() as u64)
+            .deserialize(rest)
+            .context("reading traversal cache")?;
+        Ok(Some(CachedTraversal {
+            wf,
+            traversal,
+            redefinitions,
+        }))
+    }
+
+    /// Replace the cached workflow and traversal with `wf` and `traversal`.
+    pub fn write_traversal_cache(
+        &self,
+        key: Digest,
+        wf: &Workflow,
+        traversal: &Traversal,
+        redefinitions: &[TaskRedefinition],
+    ) -> Result<()> {
+        let path = self.traversal_cache();
+        self.check_whitelist(&path)?;
+
+        let mut bytes =
+            Vec::with_capacity(MAGIC.len() + key.0.len() + 4096 + traversal.nodes.len() * 128);
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&key.0);
+        bincode::options()
+            .serialize_into(&mut bytes, &(wf, traversal, redefinitions))
+            .context("serializing traversal cache")?;
+
+        // write to a temporary file first, so that an interrupted write can't leave
+        // a truncated cache behind:
+        let tmp = path.with_extension("cache.tmp");
+        fs::write(&tmp, bytes).context("writing traversal cache")?;
+        fs::rename(&tmp, &path).context("replacing traversal cache")?;
+        Ok(())
+    }
+}
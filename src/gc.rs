@@ -0,0 +1,176 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use colored::Colorize;
+
+use intern::InternStr;
+use util::{format_size, HashSet, PathEncodingError};
+use workflow::Workflow;
+
+use crate::fs::{Fs, RealizationLocator, LATEST_LINK};
+use crate::settings::Settings;
+use crate::ui::{Confirm, Ui};
+
+/// Why something on disk is no longer part of the workflow.
+#[derive(Debug, Clone, Copy)]
+enum Reason {
+    /// The workflow doesn't define the task anymore.
+    TaskRemoved,
+    /// The traversal of the target plans doesn't reach the realization.
+    NotInPlan,
+    /// A symlink to a realization, with a branch name the traversal doesn't use.
+    StaleLink,
+}
+
+impl Reason {
+    fn describe(self) -> &'static str {
+        match self {
+            Self::TaskRemoved => "task no longer exists",
+            Self::NotInPlan => "not in plan",
+            Self::StaleLink => "stale symlink",
+        }
+    }
+}
+
+/// A task dir, realization dir, or symlink that we plan to delete.
+#[derive(Debug)]
+struct Orphan {
+    path: PathBuf,
+    reason: Reason,
+    /// disk usage in bytes
+    size: u64,
+}
+
+/// Logic for deleting realizations that are no longer part of the workflow,
+/// e.g. after tasks or branchpoints have been renamed.
+///
+/// A task dir is orphaned if the workflow no longer defines its task.
+/// A realization dir (or symlink to one) is orphaned if it isn't reached by the
+/// traversal of any of the target plans.
+pub struct GarbageCollector<'a> {
+    fs: &'a Fs,
+    ui: &'a Ui,
+    settings: &'a Settings,
+}
+
+impl<'a> GarbageCollector<'a> {
+    /// Create a new `GarbageCollector`.
+    pub fn new(settings: &'a Settings, ui: &'a Ui, fs: &'a Fs) -> Self {
+        Self { settings, ui, fs }
+    }
+}
+
+impl GarbageCollector<'_> {
+    /// Find orphans in the output dir, list them, and delete them on confirmation.
+    /// `live` holds the realization dirs and symlinks reached by the target plans;
+    /// if it's None, only task dirs are checked.
+    pub fn collect(&self, wf: &mut Workflow, live: Option<&HashSet<PathBuf>>) -> Result<()> {
+        let mut orphans = Vec::new();
+        let mut locator = self.fs.locator();
+        for task in self.fs.task_names()? {
+            locator.locate_baseline(&task);
+            let id = wf.strings.tasks.intern(&task)?;
+            if wf.get_task(id).is_err() {
+                orphans.push(self.orphan(locator.task_base(), Reason::TaskRemoved)?);
+                continue;
+            }
+            if let Some(live) = live {
+                self.find_orphans_in_task(&locator, live, &mut orphans)?;
+            }
+        }
+
+        if orphans.is_empty() {
+            eprintln!("{}", "No orphaned realizations found.".green());
+            return Ok(());
+        }
+        self.print_orphans(&orphans);
+
+        if self.settings.dry_run {
+            eprintln!("Dry run. Not deleting.");
+            return Ok(());
+        }
+        if !self.ui.confirm("Delete orphaned realizations?", Confirm::Delete)? {
+            return Ok(());
+        }
+        for orphan in &orphans {
+            match orphan.reason {
+                Reason::TaskRemoved | Reason::NotInPlan => self.fs.trash(&orphan.path)?,
+                Reason::StaleLink => self.fs.delete_file(&orphan.path)?,
+            }
+        }
+        if orphans.iter().any(|orphan| !matches!(orphan.reason, Reason::StaleLink)) {
+            eprintln!(
+                "Moved orphans to {:?}; use 'hr trash --empty' to free the space.",
+                self.fs.trash_dir()
+            );
+        }
+        Ok(())
+    }
+
+    /// Find realizations and symlinks of the located task that aren't in `live`.
+    fn find_orphans_in_task(
+        &self,
+        locator: &RealizationLocator,
+        live: &HashSet<PathBuf>,
+        orphans: &mut Vec<Orphan>,
+    ) -> Result<()> {
+        let start = orphans.len();
+        if self.fs.is_dir(locator.realizations_dir())? {
+            for entry in self.fs.read_dir(locator.realizations_dir())? {
+                let path = entry?.path();
+                let fname = path.file_name().and_then(|f| f.to_str()).ok_or(PathEncodingError)?;
+                // leave e.g. scratch dirs of failed runs alone:
+                if RealizationLocator::is_realization_name(fname) && !live.contains(&path) {
+                    orphans.push(self.orphan(&path, Reason::NotInPlan)?);
+                }
+            }
+        }
+        for entry in self.fs.read_dir(locator.task_base())? {
+            let path = entry?.path();
+            // 'latest' is never part of a plan, so only delete it once its target is gone:
+            let is_latest = path.file_name().is_some_and(|fname| fname == LATEST_LINK);
+            if is_latest && !self.fs.is_dangling_symlink(&path) {
+                continue;
+            }
+            if self.fs.is_symlink(&path) && !live.contains(&path) {
+                orphans.push(Orphan {
+                    path,
+                    reason: Reason::StaleLink,
+                    size: 0,
+                });
+            }
+        }
+        orphans[start..].sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(())
+    }
+
+    fn orphan(&self, path: &Path, reason: Reason) -> Result<Orphan> {
+        Ok(Orphan {
+            path: path.to_owned(),
+            reason,
+            size: self.fs.disk_usage(path)?,
+        })
+    }
+
+    fn print_orphans(&self, orphans: &[Orphan]) {
+        eprintln!(
+            "\nThe following are no longer part of the workflow and will be {}:",
+            "deleted".red()
+        );
+        for orphan in orphans {
+            eprintln!(
+                "{} {:?} ({}, {})",
+                "DELETE".red(),
+                orphan.path,
+                format_size(orphan.size),
+                orphan.reason.describe(),
+            );
+        }
+        let total: u64 = orphans.iter().map(|orphan| orphan.size).sum();
+        eprintln!(
+            "\n{} orphan(s), {} on disk.\n",
+            orphans.len(),
+            format_size(total)
+        );
+    }
+}
@@ -0,0 +1,273 @@
+use std::io::Write;
+use std::time::{Duration, UNIX_EPOCH};
+
+use anyhow::Result;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use util::format_duration;
+
+use crate::args::HistoryKind;
+use crate::fs::Fs;
+use crate::settings::HistoryQuery;
+
+/// A run in the history, as selected by `runs_sql`. Timestamps are seconds since the unix epoch.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunRow {
+    pub id: u64,
+    pub started: u64,
+    pub finished: u64,
+    /// 1 if the run succeeded, 0 otherwise
+    pub succeeded: u64,
+    pub plans: String,
+    pub branch: String,
+    /// tasks that completed or were restored from the cache
+    pub completed: u64,
+    pub total: u64,
+    pub cancelled: u64,
+}
+
+/// A task that a run was supposed to run.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TaskRow {
+    pub task: String,
+    pub realization: String,
+    pub state: String,
+    /// in seconds, if it started
+    pub duration: Option<f64>,
+}
+
+/// The most recent run, with each task it was supposed to run.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LatestRun {
+    #[serde(flatten)]
+    pub run: RunRow,
+    pub tasks: Vec<TaskRow>,
+}
+
+/// A task that failed in recent runs, as selected by `failures_sql`.
+#[derive(Debug, Deserialize)]
+struct FailureRow {
+    task: String,
+    runs: u64,
+    failed: u64,
+    /// 'x' for each run it failed in, '.' for the others, oldest first
+    trend: String,
+}
+
+/// Durations in seconds of a task in recent runs, as selected by `durations_sql`.
+#[derive(Debug, Deserialize)]
+struct DurationRow {
+    task: String,
+    runs: u64,
+    avg: Option<f64>,
+    min: Option<f64>,
+    max: Option<f64>,
+}
+
+/// Write the runs, task failures, or task durations recorded in the output dir's
+/// run history to `out`, for 'hr history'.
+pub fn write_history(fs: &Fs, query: HistoryQuery, out: &mut impl Write) -> Result<()> {
+    if !fs.exists(fs.history_db()) {
+        eprintln!("No run history in {:?}.", fs.history_db());
+        return Ok(());
+    }
+    fn rows<T: DeserializeOwned>(fs: &Fs, sql: &str) -> Result<Vec<T>> {
+        Ok(fs.query_history(sql)?.unwrap_or_default())
+    }
+    match query.kind {
+        HistoryKind::Runs => write_runs(&rows(fs, &runs_sql(query.last))?, out),
+        HistoryKind::Failures => write_failures(&rows(fs, &failures_sql(query.last))?, out),
+        HistoryKind::Durations => write_durations(&rows(fs, &durations_sql(query.last))?, out),
+    }
+}
+
+/// The last `last` runs in the output dir's run history, most recent first
+/// (for 'hr serve'). Returns None if there is no history yet.
+pub fn runs(fs: &Fs, last: usize) -> Result<Option<Vec<RunRow>>> {
+    fs.query_history(&runs_sql(last))
+}
+
+/// The most recent run in the output dir's run history, with the task, realization,
+/// state, and duration of each task it was supposed to run (for 'hr serve').
+/// Returns None if there is no history yet.
+pub fn latest_run(fs: &Fs) -> Result<Option<LatestRun>> {
+    let Some(run) = runs(fs, 1)?.and_then(|runs| runs.into_iter().next()) else {
+        return Ok(None);
+    };
+    let sql = format!(
+        "SELECT task, realization, state, duration FROM tasks WHERE run = {} ORDER BY rowid;",
+        run.id
+    );
+    let tasks = fs.query_history(&sql)?.unwrap_or_default();
+    Ok(Some(LatestRun { run, tasks }))
+}
+
+/// Most recent runs first, with how many of their tasks completed.
+fn runs_sql(last: usize) -> String {
+    format!(
+        "SELECT id, started, finished, succeeded, plans, branch, \
+           (SELECT count(*) FROM tasks WHERE run = runs.id AND state IN ('completed', 'restored')) AS completed, \
+           (SELECT count(*) FROM tasks WHERE run = runs.id) AS total, \
+           (SELECT count(*) FROM tasks WHERE run = runs.id AND state = 'cancelled') AS cancelled \
+         FROM runs ORDER BY id DESC LIMIT {last};"
+    )
+}
+
+fn write_runs(rows: &[RunRow], out: &mut impl Write) -> Result<()> {
+    writeln!(
+        out,
+        "{:>5}  {:<16}  {:>8}  {:<9}  {:>7}  {:<16}  BRANCH",
+        "RUN", "STARTED", "DURATION", "RESULT", "TASKS", "PLAN"
+    )?;
+    for row in rows {
+        let duration = Duration::from_secs(row.finished.saturating_sub(row.started));
+        let result = if row.succeeded == 1 {
+            "succeeded"
+        } else if row.cancelled > 0 {
+            "cancelled"
+        } else {
+            "failed"
+        };
+        let line = format!(
+            "{:>5}  {:<16}  {:>8}  {:<9}  {:>7}  {:<16}  {}",
+            row.id,
+            util::format_utc_timestamp(UNIX_EPOCH + Duration::from_secs(row.started)),
+            format_duration(duration),
+            result,
+            format!("{}/{}", row.completed, row.total),
+            row.plans,
+            row.branch,
+        );
+        // no branch leaves trailing whitespace:
+        writeln!(out, "{}", line.trim_end())?;
+    }
+    Ok(())
+}
+
+/// Tasks that failed in any of the last `last` runs, most failures first,
+/// with their outcome in each run they took part in.
+fn failures_sql(last: usize) -> String {
+    format!(
+        "SELECT task, count(*) AS runs, sum(state = 'failed') AS failed, \
+           group_concat(CASE state WHEN 'failed' THEN 'x' ELSE '.' END, '') AS trend \
+         FROM (SELECT * FROM tasks \
+               WHERE run IN (SELECT id FROM runs ORDER BY id DESC LIMIT {last}) AND state NOT IN ('not-run', 'cancelled') \
+               ORDER BY run) \
+         GROUP BY task HAVING failed > 0 ORDER BY failed DESC, task;"
+    )
+}
+
+fn write_failures(rows: &[FailureRow], out: &mut impl Write) -> Result<()> {
+    writeln!(
+        out,
+        "{:<24}  {:>6}  {:>6}  TREND (oldest to newest; x = failed)",
+        "TASK", "FAILED", "RUNS"
+    )?;
+    for row in rows {
+        writeln!(
+            out,
+            "{:<24}  {:>6}  {:>6}  {}",
+            row.task, row.failed, row.runs, row.trend,
+        )?;
+    }
+    Ok(())
+}
+
+/// Duration of each task that completed in any of the last `last` runs, slowest first.
+fn durations_sql(last: usize) -> String {
+    format!(
+        "SELECT task, count(*) AS runs, avg(duration) AS avg, min(duration) AS min, max(duration) AS max \
+         FROM tasks \
+         WHERE run IN (SELECT id FROM runs ORDER BY id DESC LIMIT {last}) AND state = 'completed' \
+         GROUP BY task ORDER BY avg DESC, task;"
+    )
+}
+
+fn write_durations(rows: &[DurationRow], out: &mut impl Write) -> Result<()> {
+    writeln!(
+        out,
+        "{:<24}  {:>6}  {:>8}  {:>8}  {:>8}",
+        "TASK", "RUNS", "AVERAGE", "MIN", "MAX"
+    )?;
+    for row in rows {
+        let secs = |secs: Option<f64>| {
+            format_duration(Duration::from_secs_f64(secs.unwrap_or_default().max(0.0)))
+        };
+        writeln!(
+            out,
+            "{:<24}  {:>6}  {:>8}  {:>8}  {:>8}",
+            row.task,
+            row.runs,
+            secs(row.avg),
+            secs(row.min),
+            secs(row.max),
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fs::{RunRecord, TaskRecord};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_write_history() -> Result<()> {
+        let dir = tempdir()?;
+        let fs = Fs::new(dir.path(), false);
+        let task = |task: &str, state, duration| TaskRecord {
+            task: task.to_owned(),
+            realization: format!("{task}[Baseline.baseline]"),
+            state,
+            duration,
+        };
+        for (i, b) in ["completed", "failed", "failed", "cancelled"].into_iter().enumerate() {
+            fs.record_run(&RunRecord {
+                plans: "main".to_owned(),
+                branch: String::new(),
+                started: 1_800_000_000 + i as u64 * 100,
+                finished: 1_800_000_000 + i as u64 * 100 + 75,
+                succeeded: b == "completed",
+                tasks: vec![
+                    task("a", "completed", Some(i as f64 + 1.0)),
+                    task("b", b, Some(0.5)),
+                ],
+            })?;
+        }
+
+        let history = |kind, last| -> Result<String> {
+            let mut out = Vec::new();
+            write_history(&fs, HistoryQuery { kind, last }, &mut out)?;
+            Ok(String::from_utf8(out)?)
+        };
+        assert_eq!(
+            history(HistoryKind::Runs, 2)?,
+            "  RUN  STARTED           DURATION  RESULT       TASKS  PLAN              BRANCH\n    \
+                 4  20270115T080500Z     1m15s  cancelled      1/2  main\n    \
+                 3  20270115T080320Z     1m15s  failed         1/2  main\n"
+        );
+        assert_eq!(
+            history(HistoryKind::Failures, 10)?,
+            "TASK                      FAILED    RUNS  TREND (oldest to newest; x = failed)\n\
+             b                              2       3  .xx\n"
+        );
+        assert_eq!(
+            history(HistoryKind::Durations, 10)?,
+            "TASK                        RUNS   AVERAGE       MIN       MAX\n\
+             a                              4     2.50s     1.00s     4.00s\n\
+             b                              1     0.50s     0.50s     0.50s\n"
+        );
+
+        let latest = latest_run(&fs)?.unwrap();
+        assert_eq!(latest.run.id, 4);
+        let states: Vec<&str> = latest.tasks.iter().map(|task| task.state.as_str()).collect();
+        assert_eq!(states, ["completed", "cancelled"]);
+        let json = serde_json::to_value(&latest)?;
+        assert_eq!(json["id"], 4);
+        assert_eq!(json["tasks"][1]["state"], "cancelled");
+        assert_eq!(runs(&fs, 10)?.map(|runs| runs.len()), Some(4));
+        Ok(())
+    }
+}
@@ -0,0 +1,136 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use colored::Colorize;
+
+use crate::project::PROJECT_FILE;
+
+/// Name of the config file 'hr init' creates, which 'hr' finds without being given '-c'.
+const CONFIG_FILE: &str = "rebuild.hr";
+
+/// Starter config, with an example of each kind of block.
+const CONFIG: &str = r#"# Workflow config for heron-rebuild. Run the 'main' plan with `hr` (or `hr -p main`),
+# check this file for errors with `hr check`, and list what's in it with `hr list`.
+
+# A plan says which tasks to run, and on which branches. Each branch of a
+# branchpoint (here, Profile) gets its own realization of the tasks it reaches:
+plan main {
+    reach package via (Profile: debug release)
+}
+
+# Values in a global block are available to every task.
+global {
+    name=my-project
+    # a branched value: "debug" on branch (Profile: debug), "release" on (Profile: release):
+    profile=(Profile: debug release)
+}
+
+# A module is a source dir (usually a git checkout) that tasks can run in.
+# Relative paths are relative to this file, so this one is the project itself:
+module src=.
+
+# A task runs its code in its own dir in the output dir, or in a module with `@`.
+# `>` declares output files, which must exist after the task runs;
+# `::` declares params, which the code gets as variables.
+task build @src > manifest=.hr-manifest.txt :: name=$name profile=$profile {
+    # this runs in the module dir, and its outputs are copied back to the task's dir.
+    # a real build would be e.g. `cargo build --profile $profile`:
+    echo "$name ($profile build)" > $manifest
+    ls >> $manifest
+}
+
+# `<` declares input files, which must exist before the task runs.
+# `$manifest@build` is the output 'manifest' of the task 'build', on the same branch:
+task package < manifest=$manifest@build > archive="$name.txt" {
+    cp $manifest $archive
+}
+"#;
+
+/// Starter project settings, pointing at the starter config, with the output dir layout.
+const PROJECT: &str = r#"# Project settings for heron-rebuild; command-line args take precedence over them.
+
+# config file(s) to use when -c isn't given:
+config = "rebuild.hr"
+# plan(s) to run when neither -p nor -t is given:
+plan = "main"
+# where to keep what tasks produce, when -o isn't given. After a run, it looks like:
+#   output/
+#     <task>/
+#       realizations/<branch>/   one dir per branch the task ran on, with its outputs,
+#                                task.sh, stdout.txt, stderr.txt, and task_info.json
+#       <branch> -> realizations/<branch>
+#       latest -> the realization that ran last
+#     branchpoints.txt           known branchpoints, so realization names stay the same
+#     history.db                 past runs (see 'hr history')
+#     logs/                      what each run printed
+#     .trash/                    realizations a run deleted (see 'hr trash')
+output = "output"
+"#;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("{0} already exists; give --force to overwrite it")]
+    Exists(String),
+}
+
+/// Write a starter config and project settings file to `dir`, creating it if needed.
+/// Unless `force` is true, nothing is written if either file already exists.
+pub fn init(dir: &Path, force: bool) -> Result<Vec<PathBuf>> {
+    let files = [(CONFIG_FILE, CONFIG), (PROJECT_FILE, PROJECT)];
+    let paths: Vec<PathBuf> = files.iter().map(|(name, _)| dir.join(name)).collect();
+    if !force {
+        if let Some(path) = paths.iter().find(|path| path.exists()) {
+            return Err(Error::Exists(path.display().to_string()).into());
+        }
+    }
+    std::fs::create_dir_all(dir).with_context(|| format!("while creating {dir:?}"))?;
+    for (path, (_, text)) in paths.iter().zip(files) {
+        std::fs::write(path, text).with_context(|| format!("while writing {path:?}"))?;
+        eprintln!("{} {}", "Created".green(), path.display());
+    }
+    eprintln!(
+        "\nRun '{}' to run the example plan, and edit {CONFIG_FILE} to make it your own.",
+        "hr".bold()
+    );
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::app::App;
+    use crate::args::Args;
+    use crate::project::ProjectSettings;
+    use crate::settings::Settings;
+    use clap::Parser;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_init() -> Result<()> {
+        let dir = tempdir()?;
+        let project = dir.path().join("project");
+        let paths = init(&project, false)?;
+        assert_eq!(paths.len(), 2);
+        assert_eq!(syntax::format(CONFIG, None)?, CONFIG);
+
+        let settings = ProjectSettings::discover(&project)?.expect("project file");
+        assert_eq!(settings.configs, vec![project.join(CONFIG_FILE)]);
+        assert_eq!(settings.plans, vec!["main".to_owned()]);
+        assert_eq!(settings.output, Some(project.join("output")));
+
+        // the starter config runs as it is:
+        let config = paths[0].to_string_lossy();
+        let output = project.join("output");
+        let output = output.to_string_lossy();
+        let args = Args::try_parse_from(["hr", "-c", &config, "-o", &output, "-p", "main", "-y"])?;
+        App::new(Settings::try_from(args)?).run()?;
+        assert!(project.join("output/package/realizations").exists());
+
+        let e = init(&project, false).unwrap_err();
+        assert!(e.to_string().ends_with("already exists; give --force to overwrite it"));
+        init(&project, true)?;
+
+        dir.close()?;
+        Ok(())
+    }
+}
@@ -1,17 +1,65 @@
 use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
 use anyhow::Result;
 use colored::Colorize;
 
-use intern::InternStr;
-use util::PathEncodingError;
-use workflow::{BranchSpec, Workflow};
+use intern::{GetStr, InternStr};
+use util::{format_size, glob_match, HashMap, PathEncodingError};
+use workflow::{AbstractTaskId, BranchSpec, BranchpointId, Dependent, Workflow, BRANCH_DELIM};
 
-use crate::fs::Fs;
-use crate::settings::Settings;
-use crate::ui::Ui;
+use crate::fs::{Artifact, Fs, RealizationLocator, BASELINE_BRANCH};
+use crate::run_log::mirror_eprintln;
+use crate::settings::{ConfirmPolicy, Settings};
+use crate::ui::{Confirm, Ui};
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Invalidating would delete {0} realization dir(s), but --no-delete was specified")]
+    DeleteRefused(usize),
+    #[error("No realizations match the given filters; nothing was invalidated")]
+    NoFilterMatches,
+}
+
+/// What gets removed to invalidate a realization.
+#[derive(Debug)]
+enum Removal {
+    /// The task's entire `realizations` dir (used when no branch is specified).
+    AllRealizations(PathBuf),
+    /// A single realization dir.
+    Dir(PathBuf),
+    /// Just the `exit_code` file; the realization dir is deleted on the next run.
+    ExitCode(PathBuf),
+    /// Nothing; the realization is already invalid.
+    Nothing,
+}
+
+/// A realization (or all realizations of a task) that we plan to invalidate.
+#[derive(Debug)]
+struct Planned {
+    task: String,
+    /// realization dir (or `realizations` dir), for display
+    path: PathBuf,
+    /// branches of all realizations covered, so we can find downstream dependents
+    branches: Vec<BranchSpec>,
+    removal: Removal,
+    /// disk usage in bytes
+    size: u64,
+    /// index of the planned invalidation this one depends on (with '--downstream')
+    parent: Option<usize>,
+}
+
+impl Planned {
+    /// true if invalidating this deletes a realization dir (rather than just its exit code).
+    fn removes_dir(&self) -> bool {
+        matches!(self.removal, Removal::AllRealizations(_) | Removal::Dir(_))
+    }
+}
 
 /// Logic for invalidating tasks from previous executions.
+///
+/// We first plan out everything that will be invalidated (including downstream
+/// dependents), show it to the user as a tree, and only then ask for confirmation.
 pub struct Invalidator<'a> {
     fs: &'a Fs,
     ui: &'a Ui,
@@ -27,110 +75,454 @@ impl<'a> Invalidator<'a> {
 
 impl Invalidator<'_> {
     /// Invalidate tasks from `wf`, using the targets defined in settings.
+    /// With '--downstream', `wf` must already be loaded from the config file,
+    /// so that we can find the tasks that depend on the targets.
     pub fn invalidate(&self, wf: &mut Workflow) -> Result<()> {
         use crate::settings::ArgsBranch;
 
-        if self.settings.tasks.is_empty() {
-            eprintln!("No tasks specified; quitting.");
-        }
+        let filter = &self.settings.filter;
+        // with filters, no tasks means every task that has been run:
+        let tasks = if !self.settings.tasks.is_empty() {
+            self.settings.tasks.clone()
+        } else if filter.is_active() {
+            self.fs.task_names()?
+        } else {
+            mirror_eprintln!("No tasks specified; quitting.");
+            return Ok(());
+        };
 
-        let mut pathbuf = PathBuf::with_capacity(256);
+        let mut plan = Vec::new();
+        let mut locator = self.fs.locator();
         match &self.settings.branches {
+            // if no branch specified, but there are filters, we delete each matching realization:
+            ArgsBranch::Empty if filter.is_active() => {
+                for task in &tasks {
+                    if self.settings.verbose > 0 {
+                        mirror_eprintln!(
+                            "{} in task {}.",
+                            "Searching for realizations matching filters".magenta(),
+                            task.cyan()
+                        );
+                    }
+                    locator.locate_baseline(task);
+                    let mut found = false;
+                    for (name, branch) in self.realizations(wf, locator.realizations_dir())? {
+                        locator.locate(task, &name);
+                        if self.passes_filter(&mut locator, &name)? {
+                            found = true;
+                            let dir = locator.realization();
+                            let removal = Removal::Dir(dir.to_owned());
+                            plan.push(self.planned(task, dir, vec![branch], removal)?);
+                        }
+                    }
+                    if !found && self.settings.verbose > 0 {
+                        mirror_eprintln!("No realizations of {} match the filters.", task.cyan());
+                    }
+                }
+            }
             // if no branch specified, we delete entire task realizations dirs:
             ArgsBranch::Empty => {
-                for task in &self.settings.tasks {
-                    eprintln!(
+                for task in &tasks {
+                    mirror_eprintln!(
                         "{} of task {}.",
                         "No branch specified; invalidating all realizations".magenta(),
                         task.cyan()
                     );
-                    let realizations = self.fs.realizations_dir(task, &mut pathbuf);
-                    self.delete_dir_if_exists(realizations)?;
+                    locator.locate_baseline(task);
+                    let dir = locator.realizations_dir();
+                    if !self.fs.is_dir(dir)? {
+                        mirror_eprintln!("{dir:?} does not exist; not deleting.");
+                        continue;
+                    }
+                    let branches = self.realization_branches(wf, dir)?;
+                    plan.push(self.planned(
+                        task,
+                        dir,
+                        branches,
+                        Removal::AllRealizations(dir.to_owned()),
+                    )?);
                 }
             }
             // if branch is literally "Baseline.baseline", we delete that branch for each task:
             ArgsBranch::Baseline => {
-                for task in &self.settings.tasks {
-                    eprintln!(
+                for task in &tasks {
+                    mirror_eprintln!(
                         "{} of task {}.",
                         "Invalidating baseline realization".magenta(),
                         task.cyan()
                     );
-                    let realization = self.fs.baseline_realization(task, &mut pathbuf);
-                    self.delete_dir_if_exists(realization)?;
+                    locator.locate_baseline(task);
+                    if !self.fs.is_dir(locator.realization())? {
+                        mirror_eprintln!(
+                            "{:?} does not exist; not deleting.",
+                            locator.realization()
+                        );
+                        continue;
+                    }
+                    if !self.passes_filter(&mut locator, BASELINE_BRANCH)? {
+                        continue;
+                    }
+                    let dir = locator.realization();
+                    let branches = vec![BranchSpec::default()];
+                    plan.push(self.planned(task, dir, branches, Removal::Dir(dir.to_owned()))?);
                 }
             }
             // o/w, branch was specified, so we look for matching branches in each task:
-            ArgsBranch::Specified(strs) => {
-                let mut arg_branch = BranchSpec::default();
-                for (k, v) in strs {
-                    let k = wf.strings.branchpoints.intern(k)?;
-                    let v = wf.strings.idents.intern(v)?;
-                    arg_branch.insert(k, v);
-                }
-                for task in &self.settings.tasks {
+            ArgsBranch::Specified(_) => {
+                let arg_branches = self.settings.branches.intern(wf)?;
+                for task in &tasks {
                     if self.settings.verbose > 0 {
-                        eprintln!(
+                        mirror_eprintln!(
                             "{} in task {}.",
                             "Searching for realizations to invalidate".magenta(),
                             task.cyan()
                         );
                     }
-                    self.invalidate_task_branch(task, wf, &arg_branch, &mut pathbuf)?;
+                    let mut found = false;
+                    for arg_branch in &arg_branches {
+                        found |=
+                            self.plan_task_branch(task, wf, arg_branch, &mut locator, &mut plan)?;
+                    }
+                    if !found {
+                        mirror_eprintln!(
+                            "No matching realizations of {} to invalidate.",
+                            task.cyan()
+                        );
+                    }
+                }
+            }
+        }
+
+        // a filter that matches nothing must not fall back to anything broader:
+        if plan.is_empty() && filter.is_active() {
+            return Err(Error::NoFilterMatches.into());
+        }
+        if self.settings.downstream {
+            self.plan_downstream(wf, &mut plan, &mut locator)?;
+        }
+
+        if plan.is_empty() {
+            mirror_eprintln!("Nothing to invalidate.");
+            return Ok(());
+        }
+        self.print_plan(&plan);
+
+        if self.settings.dry_run {
+            mirror_eprintln!("Dry run. Not invalidating.");
+            return Ok(());
+        }
+        let dirs = plan.iter().filter(|planned| planned.removes_dir()).count();
+        if dirs > 0 && self.settings.confirm.delete == ConfirmPolicy::No {
+            return Err(Error::DeleteRefused(dirs).into());
+        }
+        if !self.ui.confirm("Proceed?", Confirm::Invalidate)? {
+            return Ok(());
+        }
+        let mut trashed = false;
+        for planned in &plan {
+            match &planned.removal {
+                Removal::AllRealizations(dir) | Removal::Dir(dir) => {
+                    self.fs.trash(dir)?;
+                    trashed = true;
                 }
+                Removal::ExitCode(exit_code) => self.fs.delete_file(exit_code)?,
+                Removal::Nothing => (),
             }
         }
+        if trashed {
+            mirror_eprintln!(
+                "Moved deleted realizations to {:?}; undo with 'hr trash --restore'.",
+                self.fs.trash_dir()
+            );
+        }
         Ok(())
     }
 
-    fn invalidate_task_branch(
+    /// Add the realizations of `task` that match `arg_branch` to `plan`.
+    /// Returns false if there weren't any.
+    fn plan_task_branch(
         &self,
         task: &str,
         wf: &mut Workflow,
         arg_branch: &BranchSpec,
-        pathbuf: &mut PathBuf,
-    ) -> Result<()> {
-        let realizations = self.fs.realizations_dir(task, pathbuf);
+        locator: &mut RealizationLocator,
+        plan: &mut Vec<Planned>,
+    ) -> Result<bool> {
+        // any branch will do here, we just need the realizations dir:
+        locator.locate_baseline(task);
         let mut found_any = false;
-        if self.fs.is_dir(realizations)? {
-            for entry in self.fs.read_dir(self.fs.realizations_dir(task, pathbuf))? {
-                let entry = entry?;
-                let matches = {
-                    let fpath = entry.file_name();
-                    let fname = fpath.to_str().ok_or(PathEncodingError)?;
-                    let entry_branch = wf.parse_compact_branch_str(fname)?;
-                    arg_branch.is_exact_match(&entry_branch)
-                };
-                if matches {
-                    eprintln!("{} {:?}", "Invalidating".magenta(), entry.path());
-                    found_any = true;
-                    let exit_code = self.fs.exit_code(&entry.path(), pathbuf);
-                    if self.fs.exists(exit_code) {
-                        eprintln!("{} {exit_code:?}", "Deleting".red());
-                        if !self.settings.dry_run && self.ui.confirm("Proceed?")? {
-                            self.fs.delete_file(exit_code)?;
-                        }
-                    } else {
-                        eprintln!("Task is already invalid; not deleting.");
-                    }
-                }
+        for (name, branch) in self.realizations(wf, locator.realizations_dir())? {
+            if !arg_branch.is_exact_match(&branch) {
+                continue;
+            }
+            locator.locate(task, &name);
+            if self.passes_filter(locator, &name)? {
+                found_any = true;
+                let removal = self.exit_code_removal(locator);
+                plan.push(self.planned(task, locator.realization(), vec![branch], removal)?);
+            }
+        }
+        Ok(found_any)
+    }
+
+    /// true if the located realization named `name` passes the filters from settings.
+    fn passes_filter(&self, locator: &mut RealizationLocator, name: &str) -> Result<bool> {
+        let filter = &self.settings.filter;
+        if let Some(glob) = &filter.branch_glob {
+            let matches = glob_match(glob, name)
+                || name.split(BRANCH_DELIM).any(|part| glob_match(glob, part));
+            if !matches {
+                return Ok(false);
             }
         }
-        if !found_any {
-            eprintln!("No matching realizations to invalidate.");
+
+        let exit_code = locator.artifact(Artifact::ExitCode);
+        let finished = self.fs.exists(exit_code);
+        if filter.failed_only && finished {
+            let mut strbuf = String::new();
+            self.fs.read_to_buf(exit_code, &mut strbuf)?;
+            if strbuf.trim() == "0" {
+                return Ok(false);
+            }
         }
-        Ok(())
+        if let Some(older_than) = filter.older_than {
+            // a finished realization was last run when its exit code was written:
+            let modified = if finished {
+                self.fs.modified(exit_code)?
+            } else {
+                self.fs.modified(locator.realization())?
+            };
+            let age = SystemTime::now().duration_since(modified).unwrap_or_default();
+            if age < older_than {
+                return Ok(false);
+            }
+        }
+        Ok(true)
     }
 
-    fn delete_dir_if_exists(&self, path: &Path) -> Result<()> {
-        eprintln!("{} {path:?}.", "Deleting".red());
-        if self.settings.dry_run || !self.ui.confirm("Proceed?")? {
-            return Ok(());
-        } else if self.fs.is_dir(path)? {
-            self.fs.delete_dir(path)?;
+    fn planned(
+        &self,
+        task: &str,
+        path: &Path,
+        branches: Vec<BranchSpec>,
+        removal: Removal,
+    ) -> Result<Planned> {
+        Ok(Planned {
+            task: task.to_owned(),
+            path: path.to_owned(),
+            branches,
+            removal,
+            size: self.fs.disk_usage(path)?,
+            parent: None,
+        })
+    }
+
+    /// Invalidate a located realization by removing its `exit_code` file, if it has one.
+    fn exit_code_removal(&self, locator: &mut RealizationLocator) -> Removal {
+        let exit_code = locator.artifact(Artifact::ExitCode);
+        if self.fs.exists(exit_code) {
+            Removal::ExitCode(exit_code.to_owned())
         } else {
-            eprintln!("{path:?} does not exist; not deleting.");
+            Removal::Nothing
+        }
+    }
+
+    fn print_plan(&self, plan: &[Planned]) {
+        mirror_eprintln!(
+            "\nThe following realizations will be {}:",
+            "invalidated".red()
+        );
+        let mut children: Vec<Vec<usize>> = vec![Vec::new(); plan.len()];
+        for (i, planned) in plan.iter().enumerate() {
+            if let Some(parent) = planned.parent {
+                children[parent].push(i);
+            }
+        }
+        for (i, planned) in plan.iter().enumerate() {
+            if planned.parent.is_none() {
+                self.print_planned(plan, &children, i, "", "");
+            }
+        }
+
+        let count: usize = plan.iter().map(|planned| planned.branches.len()).sum();
+        let total: u64 = plan.iter().map(|planned| planned.size).sum();
+        mirror_eprintln!(
+            "\n{count} realization(s), {} on disk.\n",
+            format_size(total)
+        );
+    }
+
+    /// Print a planned invalidation and its downstream dependents as a tree.
+    /// `prefix` goes before this line, and `indent` before its children's lines.
+    fn print_planned(
+        &self,
+        plan: &[Planned],
+        children: &[Vec<usize>],
+        i: usize,
+        prefix: &str,
+        indent: &str,
+    ) {
+        let planned = &plan[i];
+        let action = match planned.removal {
+            Removal::AllRealizations(_) | Removal::Dir(_) => "DELETE".red(),
+            Removal::ExitCode(_) => "INVALIDATE".red(),
+            Removal::Nothing => "ALREADY INVALID".yellow(),
+        };
+        mirror_eprintln!(
+            "{prefix}{action} {:?} ({})",
+            planned.path,
+            format_size(planned.size)
+        );
+        let kids = &children[i];
+        for (n, child) in kids.iter().enumerate() {
+            let (connector, child_indent) = if n == kids.len() - 1 {
+                ("└── ", "    ")
+            } else {
+                ("├── ", "│   ")
+            };
+            let child_prefix = format!("{indent}{connector}");
+            let child_indent = format!("{indent}{child_indent}");
+            self.print_planned(plan, children, *child, &child_prefix, &child_indent);
+        }
+    }
+}
+
+// DOWNSTREAM ///////////////
+impl Invalidator<'_> {
+    /// Add every realization that (transitively) used the outputs
+    /// of a planned invalidation to the plan.
+    ///
+    /// A realization uses the outputs of a realization of one of its dependencies
+    /// if they are on the same branch of every branchpoint the dependency has,
+    /// where the branch of a grafted input is the one it's grafted from
+    /// (and globbed branchpoints match every branch).
+    fn plan_downstream(
+        &self,
+        wf: &mut Workflow,
+        plan: &mut Vec<Planned>,
+        locator: &mut RealizationLocator,
+    ) -> Result<()> {
+        let mut by_task: HashMap<AbstractTaskId, Vec<usize>> = HashMap::default();
+        for (i, planned) in plan.iter().enumerate() {
+            let id = wf.strings.tasks.intern(&planned.task)?;
+            by_task.entry(id).or_default().push(i);
+        }
+        let roots: Vec<AbstractTaskId> = by_task.keys().copied().collect();
+        let mut branchpoints = HashMap::default();
+
+        for task in wf.downstream_tasks(&roots)? {
+            let deps = wf.task_dependencies(task)?;
+            for dep in &deps {
+                if !branchpoints.contains_key(dep) {
+                    let bps = self.branchpoints_on_disk(*dep, wf, locator)?;
+                    branchpoints.insert(*dep, bps);
+                }
+            }
+
+            let task_name = wf.strings.tasks.get(task)?.to_owned();
+            locator.locate_baseline(&task_name);
+            for (name, branch) in self.realizations(wf, locator.realizations_dir())? {
+                let parent = deps.iter().find_map(|dep| {
+                    let edges: Vec<&Dependent> =
+                        wf.dependents(*dep).iter().filter(|d| d.task == task).collect();
+                    by_task.get(dep)?.iter().copied().find(|i| {
+                        plan[*i].branches.iter().any(|spec| {
+                            edges.iter().any(|edge| {
+                                uses_branch(wf, edge, &branch, spec, &branchpoints[dep])
+                            })
+                        })
+                    })
+                });
+                let Some(parent) = parent else {
+                    continue;
+                };
+                locator.locate(&task_name, &name);
+                let removal = self.exit_code_removal(locator);
+                let mut planned =
+                    self.planned(&task_name, locator.realization(), vec![branch], removal)?;
+                planned.parent = Some(parent);
+                by_task.entry(task).or_default().push(plan.len());
+                plan.push(planned);
+            }
         }
         Ok(())
     }
+
+    /// Branchpoints that have a non-baseline branch in any realization of `task`.
+    fn branchpoints_on_disk(
+        &self,
+        task: AbstractTaskId,
+        wf: &mut Workflow,
+        locator: &mut RealizationLocator,
+    ) -> Result<Vec<BranchpointId>> {
+        let task_name = wf.strings.tasks.get(task)?.to_owned();
+        locator.locate_baseline(&task_name);
+        let mut bps = Vec::new();
+        for branch in self.realization_branches(wf, locator.realizations_dir())? {
+            for k in (0..branch.len()).map(BranchpointId::from) {
+                if branch.is_specified(k) && !bps.contains(&k) {
+                    bps.push(k);
+                }
+            }
+        }
+        Ok(bps)
+    }
+
+    fn realization_branches(&self, wf: &mut Workflow, dir: &Path) -> Result<Vec<BranchSpec>> {
+        Ok(self.realizations(wf, dir)?.into_iter().map(|(_, branch)| branch).collect())
+    }
+
+    /// Compact branch strings and branches of all realizations in the given realizations dir.
+    fn realizations(&self, wf: &mut Workflow, dir: &Path) -> Result<Vec<(String, BranchSpec)>> {
+        let mut realizations = Vec::new();
+        if !self.fs.is_dir(dir)? {
+            return Ok(realizations);
+        }
+        for entry in self.fs.read_dir(dir)? {
+            let fpath = entry?.file_name();
+            let fname = fpath.to_str().ok_or(PathEncodingError)?;
+            // skip e.g. scratch dirs left by failed runs:
+            if !RealizationLocator::is_realization_name(fname) {
+                continue;
+            }
+            // with short names, the dir is named by a hash and the branch is recorded inside:
+            let name = if self.fs.short_names() {
+                match self.fs.read_realization_branch(dir.join(fname))? {
+                    Some(branch) => branch,
+                    None => continue,
+                }
+            } else {
+                fname.to_owned()
+            };
+            let branch = wf.parse_compact_branch_str(&name)?;
+            realizations.push((name, branch));
+        }
+        realizations.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(realizations)
+    }
+}
+
+/// true if a realization on `branch` uses the outputs of the dependency realization on `used`
+/// through `edge`, i.e. they're on the same branch (possibly baseline) for all of
+/// `branchpoints`, taking grafts and globs into account.
+fn uses_branch(
+    wf: &Workflow,
+    edge: &Dependent,
+    branch: &BranchSpec,
+    used: &BranchSpec,
+    branchpoints: &[BranchpointId],
+) -> bool {
+    // realization dirs leave out baseline branches, but grafts may name them:
+    let value = |spec: &BranchSpec, k: BranchpointId| {
+        spec.get_specified(k).unwrap_or_else(|| wf.strings.baselines.get(k))
+    };
+    branchpoints.iter().all(|k| {
+        if edge.globs.contains(k) {
+            true
+        } else if edge.graft.is_specified(*k) {
+            value(used, *k) == value(&edge.graft, *k)
+        } else {
+            value(used, *k) == value(branch, *k)
+        }
+    })
 }
@@ -8,25 +8,65 @@ mod exec;
 mod fs;
 /// Structs for preparing a workflow to run
 mod prep;
+/// Per-project defaults from a checked-in settings file
+mod project;
+/// Log file of each run in the output dir
+mod run_log;
 /// Combined command-line and config file run settings
 mod settings;
 /// Text UI
 mod ui;
 
+mod affected;
+mod diff;
+mod exit_code;
+mod explain;
+mod export;
+mod gc;
+mod history;
+mod init;
 mod invalidate;
+mod list;
+mod logs;
+mod serve;
+mod stats;
+mod status;
+mod viz;
+mod watch;
+
+use std::process::ExitCode;
 
 // exported for tests:
 pub use app::App;
-pub use args::Args;
-pub use settings::Settings;
+pub use args::{
+    Args, Command, Compat, ExportFormat, HistoryKind, InvalidateArgs, ListKind, LogFormat,
+    NotifyOn, OutputFormat, RunArgs, VizFormat,
+};
+pub use prep::TaskInfo;
+pub use serve::Response;
+pub use settings::{Mode, Settings};
+
+// for following the progress of a run:
+pub use exec::{ExecObserver, TaskEvent, TaskPrinter};
 
-/// Run the command-line app.
-pub fn run() -> Result<(), anyhow::Error> {
+/// Run the command-line app, returning the code to exit with (see `exit_code`).
+pub fn run() -> ExitCode {
     use clap::Parser;
     let args = Args::parse();
 
+    // 'hr init' creates a config file, so it can't look for one:
+    if let Some(Command::Init { dir, force }) = &args.command {
+        return match init::init(dir.as_ref(), *force) {
+            Ok(_) => ExitCode::SUCCESS,
+            Err(e) => fail(e),
+        };
+    }
+
     // INTERPRET SETTINGS ///////////////
-    let settings: Settings = args.try_into()?;
+    let settings: Settings = match args.try_into() {
+        Ok(settings) => settings,
+        Err(e) => return fail(e),
+    };
 
     let log_level = match settings.verbose {
         0 => log::LevelFilter::Warn,
@@ -34,11 +74,24 @@ pub fn run() -> Result<(), anyhow::Error> {
         2 => log::LevelFilter::Debug,
         _ => log::LevelFilter::Trace,
     };
-    simple_logging::log_to_stderr(log_level);
+    run_log::Logger::init(log_level, settings.log_format);
 
     // RUN THE THING /////////////////
-    let app = App::new(settings);
-    app.run()?;
+    let mut app = App::new(settings);
+    // before the printer, so what it prints is logged as part of the task:
+    app.add_observer(Box::new(run_log::TaskContext));
+    app.add_observer(Box::new(TaskPrinter));
+    match app.run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => fail(e),
+    }
+}
 
-    Ok(())
+/// Print `error` (unless there was just nothing to run), and get the code to exit with.
+fn fail(error: anyhow::Error) -> ExitCode {
+    run_log::record_error(&error);
+    if !error.is::<exit_code::NothingToRun>() {
+        std::eprintln!("Error: {error:?}");
+    }
+    ExitCode::from(exit_code::exit_code(&error))
 }
@@ -0,0 +1,126 @@
+use std::io::Write;
+
+use anyhow::Result;
+
+use intern::GetStr;
+use workflow::{AbstractValueId, IdentId, Workflow};
+
+use crate::args::ListKind;
+
+/// Write the tasks, plans, and/or branchpoints defined in `wf` to `out`, for 'hr list'.
+pub fn write_listing(wf: &Workflow, kind: ListKind, out: &mut impl Write) -> Result<()> {
+    let all = kind == ListKind::All;
+    if all || kind == ListKind::Tasks {
+        write_tasks(wf, out)?;
+    }
+    if all || kind == ListKind::Plans {
+        write_plans(wf, out)?;
+    }
+    if all || kind == ListKind::Branchpoints {
+        write_branchpoints(wf, out)?;
+    }
+    Ok(())
+}
+
+/// Each task, with its modules, and its inputs, outputs, and params with their values.
+fn write_tasks(wf: &Workflow, out: &mut impl Write) -> Result<()> {
+    writeln!(out, "tasks:")?;
+    for (id, name) in wf.strings.tasks.iter() {
+        // tasks that are referenced but never defined aren't listed:
+        let Ok(task) = wf.get_task(id) else {
+            continue;
+        };
+        write!(out, "  {name}")?;
+        for module in &task.modules {
+            write!(out, " @{}", wf.strings.modules.get(*module)?)?;
+        }
+        writeln!(out)?;
+        write_vars(wf, "<", &task.vars.inputs, out)?;
+        write_vars(wf, ">", &task.vars.outputs, out)?;
+        write_vars(wf, "::", &task.vars.params, out)?;
+    }
+    Ok(())
+}
+
+fn write_vars(
+    wf: &Workflow,
+    sigil: &str,
+    vars: &[(IdentId, AbstractValueId)],
+    out: &mut impl Write,
+) -> Result<()> {
+    for (k, v) in vars {
+        let val = wf.get_value(*v)?.describe(&wf.strings)?;
+        writeln!(out, "    {sigil} {}={val}", wf.strings.idents.get(*k)?)?;
+    }
+    Ok(())
+}
+
+/// Each plan, with the goals of each of its lines.
+fn write_plans(wf: &Workflow, out: &mut impl Write) -> Result<()> {
+    writeln!(out, "plans:")?;
+    for (name, plan) in wf.plans() {
+        writeln!(out, "  {}", wf.strings.idents.get(name)?)?;
+        for subplan in &plan.subplans {
+            let mut goals = Vec::with_capacity(subplan.goals.len());
+            for goal in &subplan.goals {
+                goals.push(wf.strings.tasks.get(*goal)?);
+            }
+            writeln!(out, "    reach {}", goals.join(", "))?;
+        }
+    }
+    Ok(())
+}
+
+/// Each branchpoint, with its known branches, starting with the baseline.
+fn write_branchpoints(wf: &Workflow, out: &mut impl Write) -> Result<()> {
+    writeln!(out, "branchpoints:")?;
+    for (k, name) in wf.strings.branchpoints.iter() {
+        write!(out, "  {name}:")?;
+        let baseline = wf.strings.baselines.get(k);
+        for v in wf.strings.baselines.values(k) {
+            write!(out, " {}", wf.strings.idents.get(*v)?)?;
+            if *v == baseline {
+                write!(out, " (baseline)")?;
+            }
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_write_listing() -> Result<()> {
+        let text = "plan main {\n  reach test via (Profile: *)\n}\n\
+                    task build @cargo > out=out.txt :: p=(Profile: debug release) { touch $out }\n\
+                    task test < in=$out@build > log :: os=$ENV{HR_LIST_TEST_OS} { echo $in > $log }\n\
+                    module cargo=/src\n";
+        std::env::set_var("HR_LIST_TEST_OS", "linux");
+        let mut wf = Workflow::default();
+        wf.load(syntax::parse(text)?, std::path::Path::new("."))?;
+
+        let mut out = Vec::new();
+        write_listing(&wf, ListKind::All, &mut out)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "tasks:\n  \
+               test\n    \
+                 < in=$out@build\n    \
+                 > log=log\n    \
+                 :: os=linux\n  \
+               build @cargo\n    \
+                 > out=out.txt\n    \
+                 :: p=(Profile: debug=debug release=release)\n    \
+                 :: HR_MODULE_cargo=/src\n\
+             plans:\n  \
+               main\n    \
+                 reach test\n\
+             branchpoints:\n  \
+               Profile: debug (baseline) release\n"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,115 @@
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+
+use crate::fs::{Artifact, Fs, RealizationLocator};
+use crate::settings::LogsQuery;
+
+/// How long to wait before checking for more output when following a log.
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(200);
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("No {0} in {1}; has the task run?")]
+    NoLog(&'static str, String),
+}
+
+/// The dir holding the logs of the realization `locator` points at: its realization dir,
+/// or if it doesn't exist, the newest of its scratch dirs (where a task writes its logs
+/// until it completes, unless it's run with '--in-place'). None if neither exists.
+pub fn find_log_dir(locator: &RealizationLocator, fs: &Fs) -> Result<Option<PathBuf>> {
+    if fs.exists(locator.realization()) {
+        return Ok(Some(locator.realization().to_path_buf()));
+    }
+    fs.newest_scratch_dir(locator.realization())
+}
+
+/// Copy the stdout log in `dir` (or stderr, with `query.stderr`) to `out`.
+/// With `query.follow`, keep copying whatever the task writes to it until the task
+/// finishes (i.e. writes its exit code, or its scratch dir is moved into place).
+pub fn write_log(dir: &Path, query: LogsQuery, out: &mut impl Write) -> Result<()> {
+    let artifact = if query.stderr {
+        Artifact::Stderr
+    } else {
+        Artifact::Stdout
+    };
+    let path = dir.join(artifact.file_name());
+    let mut file = match File::open(&path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(Error::NoLog(artifact.file_name(), dir.display().to_string()).into());
+        }
+        Err(e) => return Err(e).with_context(|| format!("while opening {path:?}")),
+    };
+
+    let exit_code = dir.join(Artifact::ExitCode.file_name());
+    let mut buf = vec![0; 8192];
+    loop {
+        // check before reading, so nothing written before the task finished is missed:
+        let finished = !query.follow || exit_code.exists() || !dir.exists();
+        loop {
+            let n = file.read(&mut buf).with_context(|| format!("while reading {path:?}"))?;
+            if n == 0 {
+                break;
+            }
+            out.write_all(&buf[..n])?;
+        }
+        out.flush()?;
+        if finished {
+            return Ok(());
+        }
+        std::thread::sleep(FOLLOW_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_find_log_dir() -> Result<()> {
+        let dir = tempdir()?;
+        let fs = Fs::new(dir.path(), true);
+        let mut locator = fs.locator();
+        locator.locate("task", "X.x1");
+        assert_eq!(find_log_dir(&locator, &fs)?, None);
+
+        let mut scratch = PathBuf::new();
+        locator.make_scratch_realization(&mut scratch);
+        std::fs::create_dir_all(&scratch)?;
+        std::fs::create_dir_all(locator.realizations_dir().join(".tmp-X.x1+Y.y1.0000abcd"))?;
+        assert_eq!(find_log_dir(&locator, &fs)?, Some(scratch));
+
+        std::fs::create_dir_all(locator.realization())?;
+        assert_eq!(
+            find_log_dir(&locator, &fs)?,
+            Some(locator.realization().to_path_buf())
+        );
+        dir.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_log() -> Result<()> {
+        let dir = tempdir()?;
+        std::fs::write(dir.path().join("stdout.txt"), "out\n")?;
+        std::fs::write(dir.path().join("exit_code"), "0")?;
+        let query = |stderr| LogsQuery {
+            stderr,
+            follow: true,
+        };
+
+        let mut out = Vec::new();
+        write_log(dir.path(), query(false), &mut out)?;
+        assert_eq!(out, b"out\n");
+
+        let e = write_log(dir.path(), query(true), &mut out).unwrap_err();
+        assert!(e.to_string().starts_with("No stderr.txt in "), "{e}");
+        dir.close()?;
+        Ok(())
+    }
+}
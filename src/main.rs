@@ -1,5 +1,5 @@
 //! A workflow runner.
 
-fn main() -> anyhow::Result<()> {
+fn main() -> std::process::ExitCode {
     heron_rebuild::run()
 }
@@ -1,5 +1,12 @@
+use anyhow::Result;
+use colored::Colorize;
+
+use intern::GetStr;
+use traverse::Node;
 use util::{HashMap, Hasher, IdVec};
-use workflow::{RealTaskId, RealTaskKey};
+use workflow::{BranchSpec, BranchpointId, RealTaskId, RealTaskKey, Workflow, BRANCH_KV_DELIM};
+
+use crate::run_log::mirror_eprintln;
 
 use super::{ActualTaskId, Error};
 
@@ -43,4 +50,88 @@ impl Deduper {
     pub fn get_actual_task_id(&self, id: RealTaskId) -> Result<ActualTaskId, Error> {
         self.id_map.get(id).copied().ok_or(Error::MissingActualTaskId(id))
     }
+
+    /// Real task ids grouped by the actual task they were deduplicated into,
+    /// for each actual task that more than one real task was merged into.
+    pub fn merged(&self) -> Vec<Vec<RealTaskId>> {
+        let mut groups = vec![Vec::new(); self.dedupe_count as usize];
+        for (id, actual_id) in self.id_map.iter().enumerate() {
+            groups[*actual_id as usize].push(RealTaskId::from(id));
+        }
+        groups.retain(|group| group.len() > 1);
+        groups
+    }
+}
+
+/// Print each realization that more than one node of the traversal was deduplicated into,
+/// with the branches the nodes were requested with, and the branchpoints that were
+/// grafted (by the task that depends on them) or pruned (b/c the task doesn't use them).
+pub fn explain_dedup(nodes: &[Node], wf: &Workflow) -> Result<()> {
+    let mut deduper = Deduper::with_capacity(nodes.len());
+    for node in nodes {
+        deduper.is_dupe(&node.key);
+    }
+    let merged = deduper.merged();
+    if merged.is_empty() {
+        mirror_eprintln!("No task realizations were deduplicated.\n");
+        return Ok(());
+    }
+
+    mirror_eprintln!("{}:", "Deduplicated task realizations".cyan());
+    for group in merged {
+        let first = &nodes[usize::from(group[0])];
+        mirror_eprintln!(
+            "{} merges {} branches:",
+            &*wf.strings.get_real_task_str(&first.key)?,
+            group.len()
+        );
+        for id in group {
+            let node = &nodes[usize::from(id)];
+            let mut line = format!(
+                "    [{}]",
+                &*wf.strings.get_full_branch_str(&node.requested_branch)?
+            );
+            let grafted = format_branchpoints(&node.grafted, &node.requested_branch, wf)?;
+            if !grafted.is_empty() {
+                line.push_str(&format!(" {} {grafted}", "grafted".yellow()));
+            }
+            let pruned = format_branchpoints(&pruned(node), &node.requested_branch, wf)?;
+            if !pruned.is_empty() {
+                line.push_str(&format!(" {} {pruned}", "pruned".red()));
+            }
+            mirror_eprintln!("{line}");
+        }
+    }
+    mirror_eprintln!();
+    Ok(())
+}
+
+/// Branchpoints a node was requested with that were removed from its realization,
+/// b/c neither the task nor anything it depends on uses them.
+pub fn pruned(node: &Node) -> Vec<BranchpointId> {
+    (0..node.requested_branch.len())
+        .map(BranchpointId::from)
+        .filter(|k| node.requested_branch.is_specified(*k))
+        .filter(|k| node.key.branch.is_unspecified(*k))
+        .collect()
+}
+
+/// Comma-separated 'Branchpoint.value' pairs, with values taken from `branch`.
+pub fn format_branchpoints(
+    ks: &[BranchpointId],
+    branch: &BranchSpec,
+    wf: &Workflow,
+) -> Result<String> {
+    let mut pairs = Vec::with_capacity(ks.len());
+    for k in ks {
+        let Some(v) = branch.get_specified(*k) else {
+            continue;
+        };
+        pairs.push(format!(
+            "{}{BRANCH_KV_DELIM}{}",
+            wf.strings.branchpoints.get(*k)?,
+            wf.strings.idents.get(v)?
+        ));
+    }
+    Ok(pairs.join(", "))
 }
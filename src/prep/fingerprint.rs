@@ -0,0 +1,36 @@
+use anyhow::Result;
+
+use intern::{GetStr, PackedInterner, TypedInterner};
+use util::{Digest, Digester};
+use workflow::{IdentId, LiteralId, RunStrId, TaskVars, Workflow};
+
+use crate::fs::Fs;
+
+/// Start the fingerprint of a task realization with the parts that are known before
+/// any of its inputs exist: its code, the names and paths of its inputs, and its params.
+pub fn fingerprint_base(
+    code: LiteralId,
+    vars: &TaskVars<(IdentId, RunStrId)>,
+    run_strs: &TypedInterner<RunStrId, PackedInterner>,
+    wf: &Workflow,
+) -> Result<Digester> {
+    let mut digester = Digester::default();
+    digester.update_str(wf.strings.literals.get(code)?);
+    for (id, val) in vars.inputs.iter().chain(&vars.params) {
+        digester.update_str(wf.strings.idents.get(*id)?);
+        digester.update_str(run_strs.get(*val)?);
+    }
+    Ok(digester)
+}
+
+/// Complete a fingerprint with the sizes and mtimes of the task's input files.
+pub fn finish_fingerprint<'a>(
+    mut digester: Digester,
+    inputs: impl IntoIterator<Item = &'a str>,
+    fs: &Fs,
+) -> Result<Digest> {
+    for input in inputs {
+        fs.digest_metadata(input, &mut digester)?;
+    }
+    Ok(digester.finish())
+}
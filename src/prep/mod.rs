@@ -1,16 +1,28 @@
 /// Parse all the info in a traversal, dedupe, and prepare to start running.
 mod traversal_resolver;
-pub use traversal_resolver::TraversalResolver;
+pub use traversal_resolver::{RealizationState, ResolverOptions, TraversalResolver};
 
 /// Clean up old runs and create directories used during execution.
 mod pre_runner;
 use pre_runner::Actions;
-pub use pre_runner::PreRunner;
+pub use pre_runner::{ActionsReport, PreRunner};
 
 /// All the information needed to actually execute a task.
 mod task_runner;
-pub use task_runner::TaskRunner;
-use task_runner::TaskRunnerBuilder;
+pub use task_runner::{TaskRunner, TaskRunnerBuilder};
+
+/// Digests of the code, params, and inputs of a task realization.
+mod fingerprint;
+use fingerprint::fingerprint_base;
+pub use fingerprint::finish_fingerprint;
+
+/// Machine-readable manifest of a task realization (`task_info.json`).
+mod task_info;
+pub use task_info::TaskInfo;
+
+/// Check realization dirs on disk concurrently.
+mod probe;
+pub use probe::Probe;
 
 /// Creates common paths in a task directory.
 mod task_dir_paths;
@@ -23,6 +35,7 @@ use task_script_builder::TaskScriptBuilder;
 /// Eliminate duplicate task realizations from a traversal.
 mod deduper;
 use deduper::Deduper;
+pub use deduper::{explain_dedup, format_branchpoints, pruned};
 
 /// Check that all variables in a task are defined.
 mod task_var_checker;
@@ -31,9 +44,10 @@ use task_var_checker::TaskVarChecker;
 /// Check that modules used by a traversal actually exist.
 mod module_checker;
 use module_checker::ModuleChecker;
+pub use module_checker::ModuleGitState;
 
 /// index into vecs used by TraversalResolver:
-type ActualTaskId = u16;
+type ActualTaskId = u32;
 
 use traverse::{RealInput, RealOutputOrParam};
 
@@ -46,10 +60,35 @@ pub enum Error {
     TaskOutputNotFound(workflow::IdentId),
     #[error("Module dir does not exist: {0:?} (used by task \"{1:?}\"; path: {2})")]
     MissingModule(workflow::ModuleId, workflow::AbstractTaskId, String),
+    #[error("Module has uncommitted changes: {0:?} (used by task \"{1:?}\"; path: {2})")]
+    DirtyModule(workflow::ModuleId, workflow::AbstractTaskId, String),
     #[error("Value does not exist: named {0:?}, id {1:?}")]
     MissingValue(workflow::IdentId, workflow::RealValueId),
     #[error("Attempted to get actual task id for nonexistent real task id: {0:?}")]
     MissingActualTaskId(workflow::RealTaskId),
+    #[error("Traversal contains no tasks")]
+    EmptyTraversal,
+    #[error("Output path {0} is used by more than one task: {1:?} of {2:?} and {3:?} of {4:?}")]
+    ConflictingOutput(
+        String,
+        workflow::IdentId,
+        workflow::RealTaskKey,
+        workflow::IdentId,
+        workflow::RealTaskKey,
+    ),
+    #[error("Invalid task_info.json: {0}")]
+    InvalidTaskInfo(String),
+    #[error("task_info.json is version {0}, but this version of hr reads version {1}")]
+    TaskInfoVersion(u64, u64),
+    #[error("Task {0:?} uses undefined variables: {1:?}")]
+    UndefinedVars(workflow::AbstractTaskId, Vec<workflow::IdentId>),
+    #[error("Param {0:?} of task {1:?} is {2:?}, which isn't {3}")]
+    InvalidParamValue(
+        workflow::IdentId,
+        workflow::RealTaskKey,
+        String,
+        workflow::ParamType,
+    ),
 }
 
 impl workflow::Recap for Error {
@@ -63,10 +102,37 @@ impl workflow::Recap for Error {
                     "Module dir does not exist: {module} (used by task {task}; path: {path}"
                 )))
             }
+            Self::DirtyModule(m, t, path) => {
+                let module = wf.modules.get(*m)?;
+                let task = wf.tasks.get(*t)?;
+                Ok(Some(format!(
+                    "Module {module} has uncommitted changes (used by task {task}; path: {path}); \
+                     commit them, or run without '--require-clean-modules'"
+                )))
+            }
             Self::TaskOutputNotFound(o) => Ok(Some(format!(
                 "Task output value not found: {}",
                 wf.idents.get(*o)?
             ))),
+            Self::ConflictingOutput(path, k1, t1, k2, t2) => Ok(Some(format!(
+                "Output \"{}\" of {} and output \"{}\" of {} both resolve to {path}, \
+                 so one would overwrite the other",
+                wf.idents.get(*k1)?,
+                &*wf.get_real_task_str(t1)?,
+                wf.idents.get(*k2)?,
+                &*wf.get_real_task_str(t2)?,
+            ))),
+            Self::UndefinedVars(t, vars) => Ok(Some(format!(
+                "Task {} uses {}, which {} not one of its inputs, outputs, or params",
+                wf.tasks.get(*t)?,
+                task_var_checker::format_vars(vars, wf)?,
+                if vars.len() == 1 { "is" } else { "are" },
+            ))),
+            Self::InvalidParamValue(k, t, val, ty) => Ok(Some(format!(
+                "Param {} of {} is {val:?}, which isn't {ty}",
+                wf.idents.get(*k)?,
+                &*wf.get_real_task_str(t)?,
+            ))),
             Self::MissingValue(ident, val_id) => Ok(Some(format!(
                 "Value does not exist: named {}, id {val_id:?}",
                 wf.idents.get(*ident)?,
@@ -1,57 +1,136 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
 use anyhow::Result;
+use serde::{Deserialize, Serialize};
 
 use traverse::Node;
-use util::{IdVec, PathEncodingError};
-use workflow::{ModuleId, Recapper};
+use util::{HashMap, PathEncodingError};
+use workflow::{ModuleId, Recapper, Workflow};
 
 use crate::fs::Fs;
 
 use super::{Error, TaskDirPaths};
 
-/// Checks that modules exist.
+/// Git commit and working tree state of a module dir.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModuleGitState {
+    /// Commit hash of HEAD.
+    pub commit: String,
+    /// true if tracked files have uncommitted changes (untracked files are ignored,
+    /// since builds commonly leave them behind).
+    pub dirty: bool,
+}
+
+impl ModuleGitState {
+    /// Read the git state of the checkout at `dir`,
+    /// or None if it isn't in a git repo (or git isn't installed).
+    pub fn read(dir: &Path) -> Option<Self> {
+        let git = |args: &[&str]| {
+            let output = Command::new("git").arg("-C").arg(dir).args(args).output();
+            match output {
+                Ok(output) if output.status.success() => {
+                    Some(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+                }
+                Ok(output) => {
+                    log::debug!(
+                        "git {args:?} failed in {dir:?}: {}",
+                        String::from_utf8_lossy(&output.stderr).trim()
+                    );
+                    None
+                }
+                Err(e) => {
+                    log::debug!("unable to run git in {dir:?}: {e}");
+                    None
+                }
+            }
+        };
+        let commit = git(&["rev-parse", "HEAD"])?;
+        let status = git(&["status", "--porcelain", "--untracked-files=no"])?;
+        Some(Self {
+            commit,
+            dirty: !status.is_empty(),
+        })
+    }
+}
+
+/// Checks that module dirs exist, and records their git state.
+/// NB we check dirs rather than module ids, since a module's path can depend on the branch.
 pub struct ModuleChecker {
-    checked: IdVec<ModuleId, bool>,
+    /// git state of each module dir we've checked so far (None if it's not a git checkout):
+    checked: HashMap<PathBuf, Option<ModuleGitState>>,
+    /// refuse to run tasks in modules with uncommitted changes
+    require_clean: bool,
 }
 
 impl ModuleChecker {
     /// Create a new `ModuleChecker` with the given capacity;
-    /// should be equal to the number of modules in a workflow.
-    pub fn with_capacity(cap: usize) -> Self {
+    /// should be about the number of modules in a workflow.
+    pub fn with_capacity(cap: usize, require_clean: bool) -> Self {
         Self {
-            checked: IdVec::fill(false, cap),
+            checked: HashMap::with_capacity_and_hasher(cap, Default::default()),
+            require_clean,
         }
     }
 
-    /// Ok if module exists, or no module used.
-    /// Adds module id to `module_ids_to_print`, only if this is the first time we've seen it.
+    /// Ok if all of the task's modules exist (and are clean, if required).
+    /// Versioned modules are skipped, since the task runs in a checkout that `hr` makes.
+    /// Adds module ids, dirs, and git states to `modules_to_print`,
+    /// only the first time we see each dir.
     pub fn check(
         &mut self,
         task: &Node,
         paths: &TaskDirPaths,
+        wf: &Workflow,
         fs: &Fs,
-        module_ids_to_print: &mut Vec<ModuleId>,
+        modules_to_print: &mut Vec<(ModuleId, PathBuf, Option<ModuleGitState>)>,
     ) -> Result<()> {
-        if let Some(module_id) = task.module {
-            if !self.is_checked(module_id) {
-                if fs.is_dir(paths.module())? {
-                    self.checked.insert(module_id, true);
-                    module_ids_to_print.push(module_id);
-                    return Ok(());
-                } else {
-                    let module_path = paths.module().to_str().ok_or(PathEncodingError)?.to_owned();
-                    return Err(Recapper::new(Error::MissingModule(
-                        module_id,
-                        task.key.id,
-                        module_path,
-                    ))
-                    .into());
-                }
+        for (module_id, path) in task.modules.iter().zip(paths.modules()) {
+            // the module's dir couldn't be resolved; the error has already been recorded:
+            if path.as_os_str().is_empty() {
+                continue;
+            }
+            if wf.get_module_rev(*module_id).is_none() {
+                self.check_module(*module_id, task, path, fs, modules_to_print)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn check_module(
+        &mut self,
+        module_id: ModuleId,
+        task: &Node,
+        path: &Path,
+        fs: &Fs,
+        modules_to_print: &mut Vec<(ModuleId, PathBuf, Option<ModuleGitState>)>,
+    ) -> Result<()> {
+        if !self.checked.contains_key(path) {
+            if !fs.is_dir(path)? {
+                let module_path = path.to_str().ok_or(PathEncodingError)?.to_owned();
+                return Err(Recapper::new(Error::MissingModule(
+                    module_id,
+                    task.key.id,
+                    module_path,
+                ))
+                .into());
+            }
+            let git = ModuleGitState::read(path);
+            let dirty = git.as_ref().is_some_and(|git| git.dirty);
+            self.checked.insert(path.to_owned(), git.clone());
+            modules_to_print.push((module_id, path.to_owned(), git));
+            if dirty && self.require_clean {
+                let module_path = path.to_str().ok_or(PathEncodingError)?.to_owned();
+                return Err(
+                    Recapper::new(Error::DirtyModule(module_id, task.key.id, module_path)).into(),
+                );
             }
         }
         Ok(())
     }
 
-    fn is_checked(&self, module_id: ModuleId) -> bool {
-        self.checked.get(module_id).copied().unwrap_or(false)
+    /// Git state of a module dir that has already been checked, if it's a git checkout.
+    pub fn git_state(&self, path: &Path) -> Option<&ModuleGitState> {
+        self.checked.get(path)?.as_ref()
     }
 }
@@ -1,20 +1,48 @@
-use std::path::PathBuf;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 
 use anyhow::{Context, Result};
 use colored::Colorize;
+use serde::Serialize;
 
 use intern::GetStr;
-use workflow::{ModuleId, RunStrId, Workflow};
+use util::{HashMap, HashSet, PathEncodingError};
+use workflow::{AbstractTaskId, ModuleId, RunStrId, Workflow};
 
-use crate::fs::Fs;
+use crate::fs::{format_estimate, Artifact, Estimates, Fs, ModuleRev, RealizationLocator};
+use crate::run_log::mirror_eprintln;
+use crate::ui::Select;
 
-use super::{TaskRunner, TaskRunnerBuilder};
+use super::{ModuleGitState, TaskRunner, TaskRunnerBuilder};
+
+/// Above this many realizations, `write_actions` counts the realizations of each task
+/// instead of listing them, unless '--full' was given.
+const GROUP_THRESHOLD: usize = 30;
+
+/// What can happen to a realization, in the order `write_actions` counts them.
+const STATES: [&str; 4] = ["to run", "complete", "skipped", "to delete"];
 
 struct DeleteAction {
+    task: AbstractTaskId,
     realization: RunStrId,
     print: RunStrId,
 }
 
+/// Symlink that should point at a completed realization.
+struct LinkAction {
+    symlink: RunStrId,
+    target: RunStrId,
+}
+
+/// Checkout of a version of a module that doesn't exist yet.
+struct CheckoutAction {
+    module: ModuleId,
+    /// rev as written in the config
+    rev_str: RunStrId,
+    dir: RunStrId,
+    rev: ModuleRev,
+}
+
 /// Cleans up old run dirs and creates new ones in preparation for executing the traversal.
 pub struct PreRunner<'a> {
     /// for filesystem operations
@@ -23,85 +51,450 @@ pub struct PreRunner<'a> {
     wf: &'a Workflow,
     /// print out more ui messages
     verbose: bool,
+    /// expected durations of tasks, from the run history
+    estimates: Estimates,
+    /// list every realization in `write_actions`, however many there are
+    full: bool,
 }
 
 impl<'a> PreRunner<'a> {
     pub fn new(fs: &'a Fs, wf: &'a Workflow, verbose: bool) -> Self {
-        Self { fs, wf, verbose }
+        Self {
+            fs,
+            wf,
+            verbose,
+            estimates: Estimates::default(),
+            full: false,
+        }
+    }
+
+    /// Annotate tasks that will run with how long they're expected to take.
+    pub fn set_estimates(&mut self, estimates: Estimates) {
+        self.estimates = estimates;
+    }
+
+    /// List every realization in `write_actions`, instead of counting them when there are many.
+    pub fn set_full(&mut self, full: bool) {
+        self.full = full;
     }
 
-    /// print list of tasks in a traversal that are:
+    /// Write a list of the tasks in a traversal that are:
     /// - already complete
     /// - to be deleted, directories recreated, and re-run
     /// - new, directories will be created and run for the first time
     /// - if verbose, will also print out modules used.
-    pub fn print_actions(&self, actions: &Actions) -> Result<()> {
+    ///
+    /// If there are more than `GROUP_THRESHOLD` realizations (and '--full' wasn't given),
+    /// the realizations of each task are counted instead of listed.
+    pub fn write_actions(&self, actions: &Actions, out: &mut impl Write) -> Result<()> {
+        // realizations to delete are counted again if they run:
+        let total = actions.completed.len() + actions.skipped.len() + actions.to_run.len();
+        if self.full || total <= GROUP_THRESHOLD {
+            self.write_realizations(actions, out)?;
+        } else {
+            self.write_task_counts(actions, out)?;
+            self.write_checkouts(actions, out)?;
+        }
+
+        if !actions.to_run.is_empty() && !self.estimates.is_empty() {
+            let tasks =
+                actions.to_run.iter().map(|runner| self.wf.strings.tasks.get(runner.key.id));
+            let (total, unknown) = self.estimates.total(tasks.collect::<Result<Vec<_>, _>>()?);
+            let unknown = match unknown {
+                0 => String::new(),
+                1 => " (plus 1 task that hasn't completed before)".to_owned(),
+                n => format!(" (plus {n} tasks that haven't completed before)"),
+            };
+            writeln!(
+                out,
+                "Estimated run time: {}{unknown}",
+                format_estimate(total)
+            )?;
+        }
+
+        if self.verbose && !actions.modules.is_empty() {
+            writeln!(
+                out,
+                "\nThe following {} will be used: ",
+                "modules".magenta()
+            )?;
+            for (module, path, git) in &actions.modules {
+                let git = match git {
+                    Some(git) if git.dirty => format!(" (commit {}, dirty)", git.commit),
+                    Some(git) => format!(" (commit {})", git.commit),
+                    None => String::new(),
+                };
+                writeln!(
+                    out,
+                    "{}: {}{git}",
+                    self.wf.strings.modules.get(*module)?.magenta(),
+                    path.display(),
+                )?;
+            }
+        }
+
+        for (module, _, _) in actions
+            .modules
+            .iter()
+            .filter(|(_, _, git)| git.as_ref().is_some_and(|git| git.dirty))
+        {
+            writeln!(
+                out,
+                "{} module {} has uncommitted changes; they won't be reproducible from its commit.",
+                "WARNING".yellow(),
+                self.wf.strings.modules.get(*module)?.magenta(),
+            )?;
+        }
+
+        writeln!(out)?;
+        Ok(())
+    }
+
+    /// List every realization in `actions`, by what will happen to it.
+    fn write_realizations(&self, actions: &Actions, out: &mut impl Write) -> Result<()> {
+        let run_strs = &self.wf.strings.run;
         if !actions.completed.is_empty() {
-            eprintln!(
+            writeln!(
+                out,
                 "\nThe following tasks are {} and will not run:",
                 "already complete".green()
-            );
-            for id in &actions.completed {
-                eprintln!("{} {}", "COMPLETED".green(), self.wf.strings.run.get(*id)?);
+            )?;
+            for (_, id) in &actions.completed {
+                writeln!(out, "{} {}", "COMPLETED".green(), run_strs.get(*id)?)?;
+            }
+        }
+
+        if !actions.skipped.is_empty() {
+            writeln!(
+                out,
+                "\nThe following tasks are {} and will not run:",
+                "assumed complete (--from)".yellow()
+            )?;
+            for (_, id) in &actions.skipped {
+                writeln!(out, "{} {}", "SKIPPED".yellow(), run_strs.get(*id)?)?;
             }
         }
 
         if !actions.to_delete.is_empty() {
-            eprintln!(
+            writeln!(
+                out,
                 "\nThe following tasks are {} and will be deleted:",
                 "incomplete or invalid".red()
-            );
+            )?;
             for to_delete in &actions.to_delete {
-                eprintln!(
-                    "{} {}",
-                    "DELETE".red(),
-                    self.wf.strings.run.get(to_delete.print)?
-                );
+                writeln!(out, "{} {}", "DELETE".red(), run_strs.get(to_delete.print)?)?;
             }
         }
 
+        self.write_checkouts(actions, out)?;
+
         if !actions.to_run.is_empty() {
-            eprintln!("\nThe following tasks {}:", "will run".green());
+            writeln!(out, "\nThe following tasks {}:", "will run".green())?;
             for runner in &actions.to_run {
-                eprintln!(
-                    "{} {}",
+                let task = self.wf.strings.tasks.get(runner.key.id)?;
+                let estimate = match self.estimates.get(task) {
+                    Some(d) => format!(" ({})", format_estimate(d)),
+                    None => String::new(),
+                };
+                writeln!(
+                    out,
+                    "{} {}{estimate}",
                     "RUN".green(),
-                    self.wf.strings.run.get(runner.print_id)?
-                );
+                    run_strs.get(runner.print_id)?
+                )?;
             }
         }
+        Ok(())
+    }
 
-        if self.verbose && !actions.modules.is_empty() {
-            eprintln!("\nThe following {} will be used: ", "modules".magenta());
-            for module in &actions.modules {
-                eprintln!(
-                    "{}: {}",
-                    self.wf.strings.modules.get(*module)?.magenta(),
-                    self.wf.get_module_path(*module)?,
-                );
-            }
+    /// List the module versions that will be checked out.
+    fn write_checkouts(&self, actions: &Actions, out: &mut impl Write) -> Result<()> {
+        if actions.checkouts.is_empty() {
+            return Ok(());
         }
+        writeln!(
+            out,
+            "\nThe following module versions {}:",
+            "will be checked out".magenta()
+        )?;
+        for checkout in &actions.checkouts {
+            writeln!(
+                out,
+                "{} {} {} ({}): {}",
+                "CHECKOUT".magenta(),
+                self.wf.strings.modules.get(checkout.module)?,
+                self.wf.strings.run.get(checkout.rev_str)?,
+                checkout.rev.commit,
+                self.wf.strings.run.get(checkout.dir)?,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Write a line for each task in `actions` with the number of its realizations
+    /// that will run, are complete, are skipped, and will be deleted,
+    /// e.g. "pkgbuild: 12 to run, 4 complete, 2 to delete".
+    fn write_task_counts(&self, actions: &Actions, out: &mut impl Write) -> Result<()> {
+        // in the order tasks first appear, with counts indexed like `STATES`:
+        let mut counts: Vec<(AbstractTaskId, [usize; 4])> = Vec::new();
+        let mut count = |task: AbstractTaskId, state: usize| match counts
+            .iter_mut()
+            .find(|(t, _)| *t == task)
+        {
+            Some((_, c)) => c[state] += 1,
+            None => {
+                let mut c = [0; 4];
+                c[state] = 1;
+                counts.push((task, c));
+            }
+        };
+        actions.completed.iter().for_each(|(task, _)| count(*task, 1));
+        actions.skipped.iter().for_each(|(task, _)| count(*task, 2));
+        actions.to_run.iter().for_each(|runner| count(runner.key.id, 0));
+        actions.to_delete.iter().for_each(|to_delete| count(to_delete.task, 3));
 
-        eprintln!();
+        let total: usize = counts.iter().map(|(_, c)| c[0] + c[1] + c[2]).sum();
+        writeln!(
+            out,
+            "\n{total} realizations of {} tasks ('--full' lists each one):",
+            counts.len()
+        )?;
+        for (task, task_counts) in &counts {
+            let mut states = Vec::with_capacity(STATES.len());
+            for (n, state) in task_counts.iter().zip(STATES) {
+                if *n > 0 {
+                    states.push(format!("{n} {state}"));
+                }
+            }
+            writeln!(
+                out,
+                "{}: {}",
+                self.wf.strings.tasks.get(*task)?,
+                states.join(", ")
+            )?;
+        }
         Ok(())
     }
 
+    /// With '--interactive', ask about each realization to delete and then each task to run,
+    /// removing the ones the user turns down from `actions`. Tasks whose incomplete realization
+    /// won't be deleted, or that use the outputs of a task that won't run, are removed without
+    /// asking, as are checkouts of module versions that no remaining task uses.
+    /// Returns false if the user quit.
+    pub fn select_actions(
+        &self,
+        actions: &mut Actions,
+        mut ask: impl FnMut(&str) -> Result<Select>,
+    ) -> Result<bool> {
+        let run_strs = &self.wf.strings.run;
+
+        // 'a' and 'd' answer for the rest of the realizations to delete, or of the tasks to run:
+        let mut rest = None;
+        let mut selected = Vec::with_capacity(actions.to_delete.len());
+        let mut kept = HashSet::default();
+        for to_delete in &actions.to_delete {
+            let prompt = format!("{} {}?", "Delete".red(), run_strs.get(to_delete.print)?);
+            let Some(yes) = select(&prompt, &mut rest, &mut ask)? else {
+                return Ok(false);
+            };
+            if !yes {
+                kept.insert(to_delete.realization);
+            }
+            selected.push(yes);
+        }
+        let mut selected = selected.into_iter();
+        actions.to_delete.retain(|_| selected.next().unwrap_or(true));
+
+        let mut rest = None;
+        let mut selected = Vec::with_capacity(actions.to_run.len());
+        let mut declined_outputs = HashSet::default();
+        for builder in &actions.to_run {
+            let task = run_strs.get(builder.print_id)?;
+            let reason = if kept.contains(&builder.realization_id) {
+                Some("its incomplete realization won't be deleted")
+            } else if builder.input_files.iter().any(|f| declined_outputs.contains(f)) {
+                Some("it uses the outputs of a task that won't run")
+            } else {
+                None
+            };
+            let yes = match reason {
+                Some(reason) => {
+                    mirror_eprintln!("{} {task}: {reason}", "SKIP".yellow());
+                    false
+                }
+                None => {
+                    let prompt = format!("{} {task}?", "Run".green());
+                    let Some(yes) = select(&prompt, &mut rest, &mut ask)? else {
+                        return Ok(false);
+                    };
+                    yes
+                }
+            };
+            if !yes {
+                declined_outputs.extend(builder.final_outputs().map(|(_, file)| file));
+            }
+            selected.push(yes);
+        }
+        let mut selected = selected.into_iter();
+        actions.to_run.retain(|_| selected.next().unwrap_or(true));
+
+        let used: HashSet<RunStrId> = actions
+            .to_run
+            .iter()
+            .flat_map(|builder| builder.modules.iter().map(|(_, dir)| *dir))
+            .collect();
+        actions.checkouts.retain(|checkout| used.contains(&checkout.dir));
+        Ok(true)
+    }
+
+    /// The same summary as `print_actions`, for '--output-format json'.
+    pub fn actions_report(&self, actions: &Actions) -> Result<ActionsReport> {
+        let run_strs = &self.wf.strings.run;
+        let string = |id: RunStrId| -> Result<String> { Ok(run_strs.get(id)?.to_owned()) };
+
+        let mut completed = Vec::with_capacity(actions.completed.len());
+        for (_, id) in &actions.completed {
+            completed.push(string(*id)?);
+        }
+
+        let mut skipped = Vec::with_capacity(actions.skipped.len());
+        for (_, id) in &actions.skipped {
+            skipped.push(string(*id)?);
+        }
+
+        let mut delete = Vec::with_capacity(actions.to_delete.len());
+        for action in &actions.to_delete {
+            delete.push(RealizationReport {
+                task: string(action.print)?,
+                realization: string(action.realization)?,
+            });
+        }
+
+        let mut checkout = Vec::with_capacity(actions.checkouts.len());
+        for action in &actions.checkouts {
+            checkout.push(CheckoutReport {
+                module: self.wf.strings.modules.get(action.module)?.to_owned(),
+                rev: string(action.rev_str)?,
+                commit: action.rev.commit.clone(),
+                dir: string(action.dir)?,
+            });
+        }
+
+        let mut run = Vec::with_capacity(actions.to_run.len());
+        for builder in &actions.to_run {
+            run.push(RealizationReport {
+                task: string(builder.print_id)?,
+                realization: string(builder.realization_id)?,
+            });
+        }
+
+        Ok(ActionsReport {
+            completed,
+            skipped,
+            delete,
+            checkout,
+            run,
+        })
+    }
+
     /// actually clean up and prepare the output directory for running the workflow.
     pub fn do_pre_run_actions(&mut self, actions: Actions) -> Result<Vec<TaskRunner>> {
         self.do_delete(&actions)?;
+        self.prune_links(&actions).context("while pruning stale symlinks")?;
+        self.do_checkouts(&actions)?;
         self.prep_and_convert_to_runners(actions)
     }
 
+    fn do_checkouts(&self, actions: &Actions) -> Result<()> {
+        for checkout in &actions.checkouts {
+            let dir = self.wf.strings.run.get(checkout.dir)?;
+            let module = self.wf.strings.modules.get(checkout.module)?;
+            mirror_eprintln!("{} {}", "Checking out".magenta(), dir);
+            self.fs
+                .checkout_module(&checkout.rev, Path::new(dir))
+                .with_context(|| format!("while checking out module {module}"))?;
+        }
+        Ok(())
+    }
+
     fn do_delete(&self, actions: &Actions) -> Result<()> {
         // In the future when we invalidate a task and its antecedents,
         // we'd like to leave a log line in a text file so we can audit over multiple runs.
         for to_delete in &actions.to_delete {
             let realization = self.wf.strings.run.get(to_delete.realization)?;
-            eprintln!("{} {}", "Deleting".red(), realization);
+            mirror_eprintln!("{} {}", "Deleting".red(), realization);
             self.fs
-                .delete_dir(realization)
+                .trash(realization)
                 .with_context(|| format!("while deleting old realization {}", realization))?;
         }
+        if !actions.to_delete.is_empty() {
+            mirror_eprintln!(
+                "Deleted realizations were moved to {:?} ('hr trash --restore' puts them back).",
+                self.fs.trash_dir()
+            );
+        }
+        Ok(())
+    }
+
+    /// Repair or delete symlinks in the task dirs of the traversal that point at the wrong
+    /// realization or at one that doesn't exist (e.g. after branchpoints were added),
+    /// and recreate missing symlinks to completed realizations.
+    /// Symlinks to other existing realizations are left for 'hr gc'.
+    fn prune_links(&self, actions: &Actions) -> Result<()> {
+        let run_strs = &self.wf.strings.run;
+        let mut expected = HashMap::default();
+        let completed = actions.links.iter().map(|link| (link.symlink, link.target));
+        let to_run = actions.to_run.iter().map(|b| (b.symlink_id, b.link_target_id));
+        for (symlink, target) in completed.chain(to_run) {
+            expected.insert(
+                Path::new(run_strs.get(symlink)?),
+                Path::new(run_strs.get(target)?),
+            );
+        }
+
+        let mut task_dirs: Vec<&Path> = expected.keys().filter_map(|link| link.parent()).collect();
+        task_dirs.sort_unstable();
+        task_dirs.dedup();
+        for task_dir in task_dirs {
+            if !self.fs.exists(task_dir) {
+                continue;
+            }
+            for entry in self.fs.read_dir(task_dir)? {
+                let path = entry?.path();
+                if !self.fs.is_symlink(&path) {
+                    continue;
+                }
+                match expected.get(path.as_path()) {
+                    Some(target) if self.fs.read_link(&path)? == *target => (),
+                    Some(target) => {
+                        mirror_eprintln!(
+                            "{} {:?} to {:?}",
+                            "Repairing symlink".magenta(),
+                            path,
+                            target
+                        );
+                        self.fs.delete_file(&path)?;
+                        self.fs.symlink(target, &path)?;
+                    }
+                    None if self.fs.is_dangling_symlink(&path) => {
+                        mirror_eprintln!("{} {:?}", "Deleting stale symlink".red(), path);
+                        self.fs.delete_file(&path)?;
+                    }
+                    None => (),
+                }
+            }
+        }
+
+        for link in &actions.links {
+            let symlink = run_strs.get(link.symlink)?;
+            if !self.fs.exists(symlink) {
+                let target = run_strs.get(link.target)?;
+                if self.verbose {
+                    mirror_eprintln!("{} {} to {}", "Symlinking".magenta(), symlink, target);
+                }
+                self.fs.symlink(target, symlink)?;
+            }
+        }
         Ok(())
     }
 
@@ -112,15 +505,24 @@ impl<'a> PreRunner<'a> {
 
         for builder in actions.to_run {
             let realization = self.wf.strings.run.get(builder.realization_id)?;
+            let exec_dir = self.wf.strings.run.get(builder.exec_id)?;
 
-            eprintln!("{} {}", "Creating".green(), realization);
-            self.fs.create_dir(realization).context("creating realization dir")?;
+            if exec_dir != realization {
+                self.delete_stale_scratch_dirs(exec_dir)?;
+            }
+
+            mirror_eprintln!("{} {}", "Creating".green(), exec_dir);
+            self.fs.create_dir(exec_dir).context("creating realization dir")?;
+            if self.fs.short_names() {
+                self.record_branch(&builder, exec_dir, realization)
+                    .context("recording branch of realization")?;
+            }
 
             let symlink = self.wf.strings.run.get(builder.symlink_id)?;
             let link_target = self.wf.strings.run.get(builder.link_target_id)?;
 
             if self.verbose {
-                eprintln!("{} {} to {}", "Symlinking".magenta(), symlink, link_target);
+                mirror_eprintln!("{} {} to {}", "Symlinking".magenta(), symlink, link_target);
             }
             if self.fs.exists(symlink) {
                 log::info!("symlink {} already exists; deleting", symlink);
@@ -133,32 +535,136 @@ impl<'a> PreRunner<'a> {
                 builder.into_task_runner(&self.wf.strings.run, self.wf, &mut task_sh_contents)?;
 
             if self.verbose {
-                eprintln!("{}", "Writing task.sh file.".magenta());
+                mirror_eprintln!("{}", "Writing task.sh file.".magenta());
             }
-            let task_sh = self.fs.task_sh(realization, &mut task_sh_path);
+            let task_sh = Artifact::TaskSh.path_in(exec_dir, &mut task_sh_path);
             self.fs.write_file(task_sh, &task_sh_contents).context("writing task.sh file")?;
+            runner.info.write(exec_dir, self.fs).context("writing task_info.json file")?;
 
             runners.push(runner);
         }
         Ok(runners)
     }
+
+    /// With short names, record the branch of the realization (whose dir is named by its hash)
+    /// inside the dir, and in branchpoints.txt.
+    fn record_branch(
+        &self,
+        builder: &TaskRunnerBuilder,
+        exec_dir: &str,
+        realization: &str,
+    ) -> Result<()> {
+        let mut branch = String::with_capacity(128);
+        self.wf.strings.make_compact_branch_string(&builder.key.branch, &mut branch)?;
+        let branch_txt = Path::new(exec_dir).join(Artifact::Branch.file_name());
+        self.fs.write_file(branch_txt, &format!("{branch}\n"))?;
+
+        let name = Path::new(realization).file_name().and_then(|f| f.to_str());
+        let name = name.ok_or(PathEncodingError)?;
+        let mut branchpoints_txt = PathBuf::with_capacity(256);
+        self.fs.record_short_name(
+            self.fs.branchpoints_txt(&mut branchpoints_txt),
+            name,
+            &branch,
+        )
+    }
+
+    /// delete temporary dirs left behind by earlier failed or interrupted runs
+    /// of the same realization (they share everything but the unique suffix).
+    fn delete_stale_scratch_dirs(&self, exec_dir: &str) -> Result<()> {
+        let exec_dir = Path::new(exec_dir);
+        let Some(parent) = exec_dir.parent().filter(|p| self.fs.exists(p)) else {
+            return Ok(());
+        };
+        for entry in self.fs.read_dir(parent)? {
+            let entry = entry?;
+            let fname = entry.file_name();
+            let is_stale = fname
+                .to_str()
+                .is_some_and(|name| RealizationLocator::is_sibling_scratch(name, exec_dir));
+            if is_stale {
+                log::info!("deleting stale scratch dir {:?}", entry.path());
+                self.fs.delete_dir(entry.path())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Ask about a single item with `ask`, unless an earlier answer covers the rest of the items.
+/// Returns whether the user said yes, or None if they quit.
+fn select(
+    prompt: &str,
+    rest: &mut Option<bool>,
+    ask: &mut impl FnMut(&str) -> Result<Select>,
+) -> Result<Option<bool>> {
+    if let Some(yes) = *rest {
+        return Ok(Some(yes));
+    }
+    Ok(match ask(prompt)? {
+        Select::Yes => Some(true),
+        Select::No => Some(false),
+        Select::All => {
+            *rest = Some(true);
+            Some(true)
+        }
+        Select::None => {
+            *rest = Some(false);
+            Some(false)
+        }
+        Select::Quit => None,
+    })
+}
+
+/// The realizations that are complete, skipped, to be deleted, and to be run,
+/// and the modules to be checked out, for '--output-format json'.
+#[derive(Debug, Default, Serialize)]
+pub struct ActionsReport {
+    completed: Vec<String>,
+    skipped: Vec<String>,
+    delete: Vec<RealizationReport>,
+    checkout: Vec<CheckoutReport>,
+    run: Vec<RealizationReport>,
+}
+
+#[derive(Debug, Serialize)]
+struct RealizationReport {
+    /// task and branch, e.g. 'task[Profile.debug]'
+    task: String,
+    /// path of the realization dir
+    realization: String,
+}
+
+#[derive(Debug, Serialize)]
+struct CheckoutReport {
+    module: String,
+    rev: String,
+    commit: String,
+    dir: String,
 }
 
 /// Contains the information needed to prepare the workflow directory for a run.
 pub struct Actions {
-    completed: Vec<RunStrId>,
+    completed: Vec<(AbstractTaskId, RunStrId)>,
+    /// incomplete, but assumed to be complete because of '--from':
+    skipped: Vec<(AbstractTaskId, RunStrId)>,
     to_delete: Vec<DeleteAction>,
     to_run: Vec<TaskRunnerBuilder>,
-    modules: Vec<ModuleId>,
+    links: Vec<LinkAction>,
+    modules: Vec<(ModuleId, PathBuf, Option<ModuleGitState>)>,
+    checkouts: Vec<CheckoutAction>,
 }
 
 impl Actions {
     pub fn new(len: usize) -> Self {
         Self {
             completed: Vec::with_capacity(len),
+            skipped: Vec::with_capacity(0),
             to_delete: Vec::with_capacity(len),
             to_run: Vec::with_capacity(len),
+            links: Vec::with_capacity(len),
             modules: Vec::with_capacity(4),
+            checkouts: Vec::with_capacity(0),
         }
     }
 
@@ -166,22 +672,161 @@ impl Actions {
         !self.to_run.is_empty()
     }
 
-    pub fn add_delete(&mut self, print_id: RunStrId, realization_id: RunStrId) {
+    pub fn has_tasks_to_delete(&self) -> bool {
+        !self.to_delete.is_empty()
+    }
+
+    pub fn add_delete(
+        &mut self,
+        task: AbstractTaskId,
+        print_id: RunStrId,
+        realization_id: RunStrId,
+    ) {
         self.to_delete.push(DeleteAction {
+            task,
             realization: realization_id,
             print: print_id,
         });
     }
 
-    pub fn add_completed(&mut self, print_id: RunStrId) {
-        self.completed.push(print_id);
+    pub fn add_completed(&mut self, task: AbstractTaskId, print_id: RunStrId) {
+        self.completed.push((task, print_id));
+    }
+
+    pub fn add_skipped(&mut self, task: AbstractTaskId, print_id: RunStrId) {
+        self.skipped.push((task, print_id));
+    }
+
+    pub fn add_link(&mut self, symlink_id: RunStrId, target_id: RunStrId) {
+        self.links.push(LinkAction {
+            symlink: symlink_id,
+            target: target_id,
+        });
+    }
+
+    pub fn add_checkout(
+        &mut self,
+        module: ModuleId,
+        rev_str: RunStrId,
+        dir: RunStrId,
+        rev: ModuleRev,
+    ) {
+        self.checkouts.push(CheckoutAction {
+            module,
+            rev_str,
+            dir,
+            rev,
+        });
     }
 
     pub fn add_run(&mut self, action: TaskRunnerBuilder) {
         self.to_run.push(action);
     }
 
-    pub fn modules_mut(&mut self) -> &mut Vec<ModuleId> {
+    pub fn pop_run(&mut self) -> Option<TaskRunnerBuilder> {
+        self.to_run.pop()
+    }
+
+    /// Dirs of the modules the realizations in the plan use.
+    pub fn module_dirs(&self) -> impl Iterator<Item = &Path> {
+        self.modules.iter().map(|(_, dir, _)| dir.as_path())
+    }
+
+    pub fn modules_mut(&mut self) -> &mut Vec<(ModuleId, PathBuf, Option<ModuleGitState>)> {
         &mut self.modules
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::prep::{ResolverOptions, TraversalResolver};
+    use intern::InternStr;
+    use tempfile::tempdir;
+    use traverse::Traversal;
+
+    /// Load `text` and resolve its plan 'all', with outputs in `fs`.
+    fn resolve_plan(text: &str, fs: &Fs) -> Result<(Workflow, Actions)> {
+        let mut wf = Workflow::default();
+        wf.load(syntax::parse(text)?, Path::new("."))?;
+        let plan = wf.strings.idents.intern("all")?;
+        let plan = wf.get_plan(plan)?.clone();
+        wf.strings.alloc_for_traversal();
+        let traversal = Traversal::create::<u8>(&wf, plan)?;
+        wf.strings.alloc_for_run();
+        let mut resolver = TraversalResolver::new(
+            traversal.nodes.len(),
+            fs,
+            &mut wf,
+            ResolverOptions::default(),
+        );
+        let actions = resolver.resolve_to_actions(traversal)?;
+        Ok((wf, actions))
+    }
+
+    #[test]
+    fn test_write_actions() -> Result<()> {
+        let fs = Fs::new(Path::new("/out"), true);
+        let text = "plan all { reach pkg via (A: *) * (B: *) }\n\
+                    task build > out :: a=(A: 1 2 3 4 5 6) b=(B: 1 2 3) { touch $out }\n\
+                    task pkg < in=$out@build { cat $in }\n";
+        let (wf, actions) = resolve_plan(text, &fs)?;
+        let mut pre_runner = PreRunner::new(&fs, &wf, false);
+        let mut out = Vec::new();
+        pre_runner.write_actions(&actions, &mut out)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "\n36 realizations of 2 tasks ('--full' lists each one):\n\
+             build: 18 to run\n\
+             pkg: 18 to run\n\n"
+        );
+
+        pre_runner.set_full(true);
+        let mut out = Vec::new();
+        pre_runner.write_actions(&actions, &mut out)?;
+        let out = String::from_utf8(out)?;
+        assert_eq!(
+            out.lines().filter(|line| line.starts_with("RUN ")).count(),
+            36
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_select_actions() -> Result<()> {
+        let dir = tempdir()?;
+        let fs = Fs::new(dir.path(), true);
+        let text = "plan all { reach test via (Profile: debug release) }\n\
+                    task build > out :: p=(Profile: debug release) { touch $out }\n\
+                    task test < in=$out@build :: p=(Profile: debug release) { cat $in }\n";
+        let (wf, mut actions) = resolve_plan(text, &fs)?;
+        assert_eq!(actions.to_run.len(), 4);
+
+        // no to the first build, which skips the test that uses it, then yes to the rest:
+        let mut answers = vec![Select::No, Select::All].into_iter();
+        let mut asked = 0;
+        let pre_runner = PreRunner::new(&fs, &wf, false);
+        let selected = pre_runner.select_actions(&mut actions, |_| {
+            asked += 1;
+            Ok(answers.next().unwrap_or(Select::Quit))
+        })?;
+        assert!(selected);
+        assert_eq!(asked, 2);
+        let mut realizations = Vec::new();
+        for builder in &actions.to_run {
+            let realization = Path::new(wf.strings.run.get(builder.realization_id)?);
+            realizations.push(realization.strip_prefix(dir.path())?.to_path_buf());
+        }
+        assert_eq!(
+            realizations,
+            vec![
+                PathBuf::from("build/realizations/Profile.release"),
+                PathBuf::from("test/realizations/Profile.release"),
+            ]
+        );
+
+        assert!(!pre_runner.select_actions(&mut actions, |_| Ok(Select::Quit))?);
+        dir.close()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,128 @@
+use std::path::{Path, PathBuf};
+use std::thread;
+
+use anyhow::Result;
+
+use crate::fs::{Artifact, Fs};
+
+/// Below this many realizations, probing isn't worth spawning threads for.
+const MIN_PARALLEL: usize = 64;
+/// Filesystem probing is IO-bound, so more threads than this don't help much.
+const MAX_THREADS: usize = 16;
+
+/// What's on disk for a single task realization.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Probe {
+    /// the realization dir exists
+    pub exists: bool,
+    /// the `exit_code` file exists and contains just the string "0"
+    pub succeeded: bool,
+    /// contents of the `fingerprint` file, or None if the realization doesn't have one
+    /// (i.e. it was completed by a version of heron-rebuild that didn't record them)
+    pub fingerprint: Option<String>,
+}
+
+impl Probe {
+    /// Check what's on disk in realization dir `dir`.
+    pub fn new(dir: &Path, fs: &Fs) -> Result<Self> {
+        if !fs.exists(dir) {
+            return Ok(Self::default());
+        }
+        let mut strbuf = String::new();
+        let mut artifact = PathBuf::with_capacity(512);
+
+        let exit_code = Artifact::ExitCode.path_in(dir, &mut artifact);
+        let succeeded = fs.exists(exit_code) && {
+            fs.read_to_buf(exit_code, &mut strbuf)?;
+            strbuf.trim() == "0"
+        };
+
+        let fingerprint_file = Artifact::Fingerprint.path_in(dir, &mut artifact);
+        let fingerprint = if fs.exists(fingerprint_file) {
+            fs.read_to_buf(fingerprint_file, &mut strbuf)?;
+            Some(strbuf.trim().to_owned())
+        } else {
+            None
+        };
+
+        Ok(Self {
+            exists: true,
+            succeeded,
+            fingerprint,
+        })
+    }
+
+    /// Probe all of `dirs` concurrently. The result is in the same order as `dirs`.
+    pub fn all(dirs: &[PathBuf], fs: &Fs) -> Result<Vec<Self>> {
+        let threads = thread::available_parallelism().map_or(1, |n| n.get()).min(MAX_THREADS);
+        if dirs.len() < MIN_PARALLEL || threads == 1 {
+            return dirs.iter().map(|dir| Self::new(dir, fs)).collect();
+        }
+
+        let chunk_size = dirs.len().div_ceil(threads);
+        thread::scope(|scope| {
+            let handles: Vec<_> = dirs
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    scope.spawn(move || {
+                        chunk.iter().map(|dir| Self::new(dir, fs)).collect::<Result<Vec<_>>>()
+                    })
+                })
+                .collect();
+            let mut probes = Vec::with_capacity(dirs.len());
+            for handle in handles {
+                let chunk = handle.join().unwrap_or_else(|e| std::panic::resume_unwind(e));
+                probes.extend(chunk?);
+            }
+            Ok(probes)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_probe_all() -> Result<()> {
+        let dir = tempdir()?;
+        let fs = Fs::new(dir.path(), false);
+        let dirs: Vec<PathBuf> =
+            (0..MIN_PARALLEL * 2).map(|i| dir.path().join(i.to_string())).collect();
+        for (i, realization) in dirs.iter().enumerate() {
+            match i % 4 {
+                0 => continue,
+                1 => fs.create_dir(realization)?,
+                2 => {
+                    fs.create_dir(realization)?;
+                    fs.write_file(realization.join("exit_code"), "1")?;
+                }
+                _ => {
+                    fs.create_dir(realization)?;
+                    fs.write_file(realization.join("exit_code"), "0\n")?;
+                    fs.write_file(realization.join("fingerprint"), &format!("{i}\n"))?;
+                }
+            }
+        }
+
+        let probes = Probe::all(&dirs, &fs)?;
+        assert_eq!(probes.len(), dirs.len());
+        for (i, probe) in probes.iter().enumerate() {
+            let expected = match i % 4 {
+                0 => Probe::default(),
+                1 | 2 => Probe {
+                    exists: true,
+                    ..Default::default()
+                },
+                _ => Probe {
+                    exists: true,
+                    succeeded: true,
+                    fingerprint: Some(i.to_string()),
+                },
+            };
+            assert_eq!(*probe, expected, "wrong probe for realization {i}");
+        }
+        Ok(())
+    }
+}
@@ -2,105 +2,106 @@ use std::path::{Path, PathBuf};
 
 use anyhow::Result;
 
-use intern::GetStr;
 use traverse::Node;
 use workflow::Workflow;
 
-use crate::fs::Fs;
+use crate::fs::{Fs, RealizationLocator};
 
 /// Reusable container for common paths in the task realization directory.
 pub struct TaskDirPaths {
     /// used for constructing other paths
     scratch: PathBuf,
-    /// absolute path to the task realization dir
-    realization: PathBuf,
-    /// task realization dir, relative to the root task dir
-    realization_relative: PathBuf,
-    /// convenient symlink to the task realization dir (absolute)
-    link_src: PathBuf,
-    /// absolute path to module used by task, or empty if no module
-    module: PathBuf,
+    /// realization dir, its path relative to the task dir, and its symlink
+    locator: RealizationLocator,
+    /// absolute paths to modules used by task (the task runs in the first one)
+    modules: Vec<PathBuf>,
+    /// absolute path to the dir the task is executed in:
+    /// either the realization dir itself, or a sibling to be renamed into place on success
+    exec: PathBuf,
+    /// if true, execute in a temporary sibling of the realization dir
+    atomic: bool,
 }
 
 impl TaskDirPaths {
-    pub fn new() -> Self {
+    pub fn new(fs: &Fs, atomic: bool) -> Self {
         Self {
             scratch: PathBuf::with_capacity(512),
-            realization: PathBuf::with_capacity(512),
-            link_src: PathBuf::with_capacity(512),
-            realization_relative: PathBuf::with_capacity(512),
-            module: PathBuf::with_capacity(512),
+            locator: fs.locator(),
+            exec: PathBuf::with_capacity(512),
+            atomic,
+            modules: Vec::with_capacity(2),
         }
     }
 
-    pub fn make_paths(
-        &mut self,
-        task: &Node,
-        wf: &Workflow,
-        fs: &Fs,
-        strbuf: &mut String,
-    ) -> Result<()> {
-        strbuf.clear();
-        wf.strings.make_compact_branch_string(&task.key.branch, strbuf)?;
-        fs.realization_relative(&*strbuf, &mut self.realization_relative);
-
-        let base = fs.task_base(wf.strings.tasks.get(task.key.id)?, &mut self.scratch);
-
-        fs.realization(base, &self.realization_relative, &mut self.realization);
-        fs.link_src(
-            base,
-            &wf.strings.get_full_branch_str(&task.key.branch)?,
-            &mut self.link_src,
-        );
-
-        self.module.clear();
-        if let Some(module_id) = task.module {
-            let path_str = wf.get_module_path(module_id)?;
-            self.module.push(path_str);
+    pub fn make_paths(&mut self, task: &Node, wf: &Workflow, strbuf: &mut String) -> Result<()> {
+        self.locator.locate_key(&task.key, wf, strbuf)?;
+        if self.atomic {
+            self.locator.make_scratch_realization(&mut self.exec);
+        } else {
+            self.exec.clear();
+            self.exec.push(self.locator.realization());
+        }
+
+        // reuse the PathBufs we already have, since most tasks use the same number of modules.
+        // module paths can depend on the branch, so they're filled in with `set_module`:
+        self.modules.resize_with(task.modules.len(), || PathBuf::with_capacity(512));
+        for path in &mut self.modules {
+            path.clear();
         }
 
         Ok(())
     }
 
     pub fn realization(&self) -> &Path {
-        &self.realization
+        self.locator.realization()
+    }
+
+    pub fn exec(&self) -> &Path {
+        &self.exec
     }
 
     pub fn realization_relative(&self) -> &Path {
-        &self.realization_relative
+        self.locator.realization_relative()
     }
 
     pub fn link_src(&self) -> &Path {
-        &self.link_src
+        self.locator.link_src()
+    }
+
+    /// paths to all modules used by the task, in the order of `task.modules`
+    pub fn modules(&self) -> &[PathBuf] {
+        &self.modules
     }
 
-    pub fn module(&self) -> &Path {
-        &self.module
+    /// use `path` for the task's `i`th module: either its resolved dir,
+    /// or a checkout of a version of it
+    pub fn set_module(&mut self, i: usize, path: &Path) {
+        self.modules[i].clear();
+        self.modules[i].push(path);
     }
 
+    /// path to an output in the dir the task is executed in
     pub fn normal_output(&mut self, file_relative: &str) -> &Path {
         self.scratch.clear();
-        self.scratch.push(&self.realization);
+        self.scratch.push(&self.exec);
         self.scratch.push(file_relative);
         &self.scratch
     }
 
-    pub fn module_output(&mut self, file_relative: &str) -> &Path {
+    /// path to an output in the realization dir, where dependent tasks will find it
+    pub fn final_output(&mut self, file_relative: &str) -> &Path {
         self.scratch.clear();
-        self.scratch.push(&self.module);
+        self.scratch.push(self.locator.realization());
         self.scratch.push(file_relative);
         &self.scratch
     }
 
-    /// return true if `exit_code` file exists and contains just the string "0".
-    pub fn exit_code_success(&mut self, fs: &Fs, strbuf: &mut String) -> Result<bool> {
-        let exit_code_file = fs.exit_code(&self.realization, &mut self.scratch);
-        if fs.exists(exit_code_file) {
-            fs.read_to_buf(exit_code_file, strbuf)?;
-            if strbuf.trim() == "0" {
-                return Ok(true);
-            }
+    pub fn module_output(&mut self, file_relative: &str) -> &Path {
+        self.scratch.clear();
+        if let Some(module) = self.modules.first() {
+            self.scratch.push(module);
         }
-        Ok(false)
+        self.scratch.push(file_relative);
+        &self.scratch
     }
 }
@@ -0,0 +1,260 @@
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use intern::{GetStr, PackedInterner, TypedInterner};
+use util::{Digest, Digester};
+use workflow::{BranchpointId, RunStrId, Workflow};
+
+use super::{Error, ModuleGitState, TaskRunnerBuilder};
+use crate::fs::{Artifact, Fs};
+
+/// Version of the `task_info.json` format; bump it on incompatible changes.
+const VERSION: u64 = 1;
+
+/// Machine-readable manifest of a task realization, written to its `task_info.json`.
+///
+/// Everything that's known before the task runs is filled in during prep;
+/// the `WorkflowRunner` adds the rest as the task executes.
+/// Timestamps are seconds since the unix epoch.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TaskInfo {
+    /// Name of the task.
+    pub task: String,
+    /// Branch of this realization, including only non-baseline branches.
+    #[serde(with = "util::ordered_map")]
+    pub branch: Vec<(String, String)>,
+    /// Absolute path of the realization dir.
+    pub realization: String,
+    /// Absolute path of the module dir the task runs in, if any.
+    pub module: Option<String>,
+    /// Names and absolute paths of all modules the task uses (including `module`).
+    #[serde(with = "util::ordered_map", default)]
+    pub modules: Vec<(String, String)>,
+    /// Git commit and dirty state of the module dir, if it's a git checkout.
+    #[serde(default)]
+    pub module_git: Option<ModuleGitState>,
+    /// Digest of the task's code.
+    pub code_digest: Digest,
+    /// Input names and resolved paths.
+    #[serde(with = "util::ordered_map")]
+    pub inputs: Vec<(String, String)>,
+    /// Output names and resolved paths (in the realization dir, even if the task
+    /// ran in a scratch dir).
+    #[serde(with = "util::ordered_map")]
+    pub outputs: Vec<(String, String)>,
+    /// Param names and values.
+    #[serde(with = "util::ordered_map")]
+    pub params: Vec<(String, String)>,
+    /// Dot param names (without the '.') and values, other than ones hr uses itself.
+    #[serde(with = "util::ordered_map", default)]
+    pub dot_params: Vec<(String, String)>,
+    /// When the realization dir was prepared.
+    pub prepared: u64,
+    /// When the task started running.
+    pub started: Option<u64>,
+    /// When the task finished running, successfully or not.
+    pub finished: Option<u64>,
+    /// Whether the task succeeded, once it has finished.
+    pub succeeded: Option<bool>,
+    /// true if the task's outputs were restored from the cache instead of running it.
+    pub restored_from_cache: bool,
+}
+
+impl TaskInfo {
+    /// Gather the manifest of the task that's about to be prepared by `builder`.
+    pub fn new(
+        builder: &TaskRunnerBuilder,
+        run_strs: &TypedInterner<RunStrId, PackedInterner>,
+        wf: &Workflow,
+    ) -> Result<Self> {
+        let mut branch = Vec::new();
+        for k in (0..builder.key.branch.len()).map(BranchpointId::from) {
+            if let Some(v) = builder.key.branch.get_specified(k) {
+                let k = wf.strings.branchpoints.get(k)?;
+                branch.push((k.to_owned(), wf.strings.idents.get(v)?.to_owned()));
+            }
+        }
+
+        let mut code_digest = Digester::default();
+        code_digest.update_str(wf.strings.literals.get(builder.code)?);
+
+        let vars = |vars: &[(workflow::IdentId, RunStrId)]| -> Result<Vec<(String, String)>> {
+            let mut pairs = Vec::with_capacity(vars.len());
+            for (id, val) in vars {
+                let k = wf.strings.idents.get(*id)?;
+                pairs.push((k.to_owned(), run_strs.get(*val)?.to_owned()));
+            }
+            Ok(pairs)
+        };
+
+        Ok(Self {
+            task: wf.strings.tasks.get(builder.key.id)?.to_owned(),
+            branch,
+            realization: run_strs.get(builder.realization_id)?.to_owned(),
+            module: builder.module_id.map(|id| run_strs.get(id).map(str::to_owned)).transpose()?,
+            modules: builder
+                .modules
+                .iter()
+                .map(|(id, dir)| {
+                    Ok((
+                        wf.strings.modules.get(*id)?.to_owned(),
+                        run_strs.get(*dir)?.to_owned(),
+                    ))
+                })
+                .collect::<Result<_>>()?,
+            module_git: builder.module_git.clone(),
+            code_digest: code_digest.finish(),
+            inputs: vars(&builder.vars.inputs)?,
+            outputs: promoted(vars(&builder.vars.outputs)?, builder, run_strs)?,
+            params: vars(&builder.vars.params)?,
+            dot_params: builder
+                .dot_params
+                .iter()
+                .map(|(k, v)| {
+                    Ok((
+                        wf.strings.idents.get(*k)?.to_owned(),
+                        wf.strings.literals.get(*v)?.to_owned(),
+                    ))
+                })
+                .collect::<Result<_>>()?,
+            prepared: unix_time_now(),
+            started: None,
+            finished: None,
+            succeeded: None,
+            restored_from_cache: false,
+        })
+    }
+
+    /// Record that the task is starting now.
+    pub fn start(&mut self) {
+        self.started = Some(unix_time_now());
+    }
+
+    /// Record that the task finished now.
+    pub fn finish(&mut self, succeeded: bool) {
+        self.finished = Some(unix_time_now());
+        self.succeeded = Some(succeeded);
+    }
+
+    /// Write this manifest to `task_info.json` in `dir`.
+    pub fn write(&self, dir: impl AsRef<Path>, fs: &Fs) -> Result<()> {
+        let path = dir.as_ref().join(Artifact::TaskInfo.file_name());
+        fs.write_file(path, &format!("{}\n", self.to_json()?))
+    }
+
+    /// Read the manifest from `task_info.json` in the given realization dir.
+    pub fn read(dir: impl AsRef<Path>, fs: &Fs) -> Result<Self> {
+        let path = dir.as_ref().join(Artifact::TaskInfo.file_name());
+        let mut text = String::new();
+        fs.read_to_buf(path, &mut text)?;
+        Ok(Self::from_json(&text)?)
+    }
+
+    /// This manifest as the contents of a `task_info.json` file.
+    pub fn to_json(&self) -> Result<String> {
+        let file = TaskInfoFile {
+            version: VERSION,
+            info: self,
+        };
+        Ok(serde_json::to_string_pretty(&file)?)
+    }
+
+    /// Parse the contents of a `task_info.json` file.
+    pub fn from_json(text: &str) -> Result<Self, Error> {
+        let file: TaskInfoFile<Self> =
+            serde_json::from_str(text).map_err(|e| Error::InvalidTaskInfo(e.to_string()))?;
+        if file.version != VERSION {
+            return Err(Error::TaskInfoVersion(file.version, VERSION));
+        }
+        Ok(file.info)
+    }
+}
+
+/// The contents of a `task_info.json` file: a [`TaskInfo`], tagged with the format version.
+#[derive(Serialize, Deserialize)]
+struct TaskInfoFile<T> {
+    version: u64,
+    #[serde(flatten)]
+    info: T,
+}
+
+/// Move paths in `vars` that are in the task's scratch dir into its realization dir,
+/// where they end up once the task completes.
+fn promoted(
+    mut vars: Vec<(String, String)>,
+    builder: &TaskRunnerBuilder,
+    run_strs: &TypedInterner<RunStrId, PackedInterner>,
+) -> Result<Vec<(String, String)>> {
+    if builder.exec_id == builder.realization_id {
+        return Ok(vars);
+    }
+    let exec_dir = run_strs.get(builder.exec_id)?;
+    let realization = run_strs.get(builder.realization_id)?;
+    for (_, path) in &mut vars {
+        if let Ok(relative) = Path::new(path.as_str()).strip_prefix(exec_dir) {
+            *path = Path::new(realization).join(relative).to_string_lossy().into_owned();
+        }
+    }
+    Ok(vars)
+}
+
+/// Current time in seconds since the unix epoch.
+fn unix_time_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_json_round_trip() -> Result<()> {
+        let pair = |k: &str, v: &str| (k.to_owned(), v.to_owned());
+        let info = TaskInfo {
+            task: "build".to_owned(),
+            branch: vec![pair("Profile", "debug")],
+            realization: "/out/build/realizations/Profile.debug".to_owned(),
+            module: None,
+            modules: Vec::new(),
+            module_git: None,
+            code_digest: Digest([0xab; 32]),
+            inputs: vec![pair("src", "/src/main.c")],
+            outputs: vec![pair("bin", "/out/build/realizations/Profile.debug/main")],
+            params: vec![pair("flags", "-O0 \"-g\"")],
+            dot_params: Vec::new(),
+            prepared: 1_700_000_000,
+            started: Some(1_700_000_001),
+            finished: None,
+            succeeded: None,
+            restored_from_cache: false,
+        };
+        let json = info.to_json()?;
+        assert!(json.starts_with("{\n  \"version\": 1,\n  \"task\": \"build\","));
+        assert_eq!(TaskInfo::from_json(&json)?, info);
+
+        let info = TaskInfo {
+            module: Some("/src".to_owned()),
+            modules: vec![pair("src", "/src"), pair("sdk", "/sdk")],
+            dot_params: vec![pair("cpus", "4"), pair("queue", "gpu")],
+            module_git: Some(ModuleGitState {
+                commit: "0123abcd".to_owned(),
+                dirty: true,
+            }),
+            finished: Some(1_700_000_002),
+            succeeded: Some(true),
+            ..info
+        };
+        assert_eq!(TaskInfo::from_json(&info.to_json()?)?, info);
+
+        assert!(TaskInfo::from_json("{\"version\": 1}").is_err());
+        let json = info.to_json()?.replace("\"version\": 1", "\"version\": 2");
+        assert!(matches!(
+            TaskInfo::from_json(&json),
+            Err(Error::TaskInfoVersion(2, 1))
+        ));
+        Ok(())
+    }
+}
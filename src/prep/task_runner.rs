@@ -1,10 +1,29 @@
 use anyhow::Result;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
 use intern::{GetStr, PackedInterner, TypedInterner};
-use workflow::{IdentId, LiteralId, RunStrId, TaskVars, Workflow};
+use util::{Digester, PathEncodingError};
+use workflow::{
+    IdentId, LiteralId, ModuleId, RealTaskKey, RunStrId, TaskVars, Workflow, MODULE_VAR_PREFIX,
+};
 
-use super::TaskScriptBuilder;
+use crate::fs::Artifact;
+
+use super::{fingerprint_base, ModuleGitState, TaskInfo, TaskScriptBuilder};
+
+/// Runs the task's code (its args after the first) while a background loop rewrites
+/// the heartbeat file (its first arg) every few seconds, so the heartbeat comes from
+/// the task itself rather than from hr. Not traced by bash -x, unlike the task's code.
+const HEARTBEAT_WRAPPER: &str = r#"heartbeat=$1; shift
+{ while :; do echo "time $(date +%s)" > "$heartbeat"; sleep 5; done; } </dev/null >/dev/null 2>&1 &
+beating=$!
+trap 'kill "$beating" 2>/dev/null; rm -f "$heartbeat"' EXIT
+trap 'exit 130' INT
+trap 'exit 143' TERM
+"$@"
+status=$?
+exit "$status""#;
 
 /// Contains all information required to run a single task realization.
 #[derive(Debug)]
@@ -15,22 +34,49 @@ pub struct TaskRunner {
     pub cmd: Command,
     /// Id of the directory in which artifacts (`stdout.txt`, `task.sh`, `exit_code` etc.) will live.
     pub realization_dir: RunStrId,
+    /// Id of the directory artifacts are written to during execution;
+    /// if it differs from `realization_dir`, it is renamed into place on success.
+    pub exec_dir: RunStrId,
     /// Ids of input files so we can verify they exist before execution.
     pub inputs: Vec<RunStrId>,
     /// Ids of output files so we can verify they exist after execution.
     pub outputs: Vec<RunStrId>,
     /// Ids of file paths to copy output files to (only used by module tasks).
     pub copy_outputs_to: Vec<RunStrId>,
+    /// Maximum allowed size of the realization dir after execution, in bytes.
+    pub max_output_size: Option<u64>,
+    /// Names and values of the task's other dot params (e.g. ".cpus"),
+    /// for whatever schedules the task; these aren't exported to its code.
+    pub dot_params: Vec<(IdentId, LiteralId)>,
+    /// Digest of this task's code and variables, to be completed with the digests of
+    /// its input files to make a cache key. None if the task can't be cached.
+    pub cache_key: Option<Digester>,
+    /// Paths of output files relative to `exec_dir` (only used for caching).
+    pub relative_outputs: Vec<PathBuf>,
+    /// Fingerprint of this task's code and variables, to be completed with
+    /// the metadata of its input files and recorded when it completes.
+    pub fingerprint: Digester,
+    /// Manifest recorded in `task_info.json`.
+    pub info: TaskInfo,
 }
 
 /// Temporary struct for constructing a `TaskRunner`.
 pub struct TaskRunnerBuilder {
+    /// Task and branch of this realization.
+    pub key: RealTaskKey,
     /// Id of directory in which artifacts live.
     pub realization_id: RunStrId,
+    /// Id of directory in which the task executes (same as `realization_id` with '--in-place').
+    pub exec_id: RunStrId,
     /// String that uniquely identifies this task, used for logging.
     pub print_id: RunStrId,
-    /// Id of this task's module directory, if it has one.
+    /// Id of the module directory this task runs in, if it has one.
     pub module_id: Option<RunStrId>,
+    /// Ids and directories of all modules used by this task (including `module_id`),
+    /// which are exported as `HR_MODULE_<name>`.
+    pub modules: Vec<(ModuleId, RunStrId)>,
+    /// Git state of this task's module directory, if it's a git checkout.
+    pub module_git: Option<ModuleGitState>,
     /// Id of the symlink that links to this task's realization directory
     /// (this is just for the user's convenience and isn't used anywhere else internally).
     pub symlink_id: RunStrId,
@@ -38,13 +84,90 @@ pub struct TaskRunnerBuilder {
     pub link_target_id: RunStrId,
     /// list of variables that need to be defined for this task to run (inputs, outputs, & params).
     pub vars: TaskVars<(IdentId, RunStrId)>,
+    /// Ids of every input file, which differ from the input values when an input is a list.
+    pub input_files: Vec<RunStrId>,
     /// If this task will execute in a module directory, ids of files to copy its outputs to.
     pub copy_outputs_to: Vec<RunStrId>,
     /// Id of string containing this task's execution code.
     pub code: LiteralId,
+    /// Maximum allowed size of the realization dir after execution, in bytes.
+    pub max_output_size: Option<u64>,
+    /// Names and values of the task's other dot params.
+    pub dot_params: Vec<(IdentId, LiteralId)>,
 }
 
 impl TaskRunnerBuilder {
+    /// Get the id of the directory this task's code runs in
+    /// (its module dir if it has one, otherwise its realization dir).
+    pub fn cmd_dir_id(&self) -> RunStrId {
+        self.module_id.unwrap_or(self.exec_id)
+    }
+
+    /// Ids and paths of this task's output files, where they end up once it completes
+    /// (module tasks write their outputs in the module dir, then copy them here).
+    pub fn final_outputs(&self) -> impl Iterator<Item = (IdentId, RunStrId)> + '_ {
+        self.vars
+            .outputs
+            .iter()
+            .enumerate()
+            .map(|(i, (id, file))| (*id, self.copy_outputs_to.get(i).copied().unwrap_or(*file)))
+    }
+
+    /// Create an interactive bash shell in this task's execution directory,
+    /// with all of its inputs, outputs, and params exported.
+    pub fn into_shell_cmd(
+        self,
+        run_strs: &TypedInterner<RunStrId, PackedInterner>,
+        wf: &Workflow,
+    ) -> Result<Command> {
+        let mut cmd = Command::new("/usr/bin/env");
+        cmd.arg("bash").arg("-i");
+        cmd.current_dir(run_strs.get(self.cmd_dir_id())?);
+
+        let vars = self.vars.inputs.iter().chain(&self.vars.outputs).chain(&self.vars.params);
+        for (id, val) in vars {
+            cmd.env(wf.strings.idents.get(*id)?, run_strs.get(*val)?);
+        }
+        for (id, dir) in &self.modules {
+            cmd.env(module_var(*id, wf)?, run_strs.get(*dir)?);
+        }
+        Ok(cmd)
+    }
+
+    /// Digest the parts of this task that don't depend on input file contents,
+    /// and find its outputs' paths relative to its exec dir.
+    /// Module tasks depend on the state of their module dir, so they can't be cached.
+    fn partial_cache_key(
+        &self,
+        run_strs: &TypedInterner<RunStrId, PackedInterner>,
+        wf: &Workflow,
+    ) -> Result<Option<(Digester, Vec<PathBuf>)>> {
+        if !self.modules.is_empty() {
+            return Ok(None);
+        }
+        let exec_dir = Path::new(run_strs.get(self.exec_id)?);
+
+        let mut digester = Digester::default();
+        digester.update_str(wf.strings.literals.get(self.code)?);
+        for (id, _) in &self.vars.inputs {
+            digester.update_str(wf.strings.idents.get(*id)?);
+        }
+        let mut relative_outputs = Vec::with_capacity(self.vars.outputs.len());
+        for (id, file) in &self.vars.outputs {
+            let Ok(relative) = Path::new(run_strs.get(*file)?).strip_prefix(exec_dir) else {
+                return Ok(None);
+            };
+            digester.update_str(wf.strings.idents.get(*id)?);
+            digester.update_str(relative.to_str().ok_or(PathEncodingError)?);
+            relative_outputs.push(relative.to_path_buf());
+        }
+        for (id, val) in &self.vars.params {
+            digester.update_str(wf.strings.idents.get(*id)?);
+            digester.update_str(run_strs.get(*val)?);
+        }
+        Ok(Some((digester, relative_outputs)))
+    }
+
     /// Convert self to a `TaskRunner` that can be run by the `WorkflowRunner`.
     pub fn into_task_runner(
         self,
@@ -52,10 +175,12 @@ impl TaskRunnerBuilder {
         wf: &Workflow,
         strbuf: &mut String,
     ) -> Result<TaskRunner> {
-        // we will store inputs and outputs (so we can verify them before and after running),
+        let fingerprint = fingerprint_base(self.code, &self.vars, run_strs, wf)?;
+        let info = TaskInfo::new(&self, run_strs, wf)?;
+
+        // we will store input files and outputs (so we can verify them before and after running),
         // but params can be discarded after we add them to the command.
         // however, we don't need the ident_ids, just the file handles.
-        let mut inputs = Vec::with_capacity(self.vars.inputs.len());
         let mut outputs = Vec::with_capacity(self.vars.outputs.len());
 
         // cmd dir is either realization_dir, or module_dir if we're in a module:
@@ -68,12 +193,15 @@ impl TaskRunnerBuilder {
             cmd_dir = run_strs.get(module_dir_id)?;
             output_strs = Some(Vec::with_capacity(self.vars.outputs.len()));
         } else {
-            cmd_dir = run_strs.get(self.realization_id)?;
+            cmd_dir = run_strs.get(self.exec_id)?;
             output_strs = None;
         }
 
         // set up cmd and task.sh /////////////////////
+        let heartbeat =
+            std::path::absolute(run_strs.get(self.exec_id)?)?.join(Artifact::Heartbeat.file_name());
         let mut cmd = Command::new("/usr/bin/env");
+        cmd.arg("sh").arg("-c").arg(HEARTBEAT_WRAPPER).arg("hr-task").arg(heartbeat);
         cmd.arg("bash").arg("-xeuo").arg("pipefail");
 
         strbuf.clear();
@@ -84,7 +212,6 @@ impl TaskRunnerBuilder {
 
         // add inputs to cmd and task.sh /////////////
         for (id, file) in &self.vars.inputs {
-            inputs.push(*file);
             let id = wf.strings.idents.get(*id)?;
             let file = run_strs.get(*file)?;
             cmd.env(id, file);
@@ -111,6 +238,14 @@ impl TaskRunnerBuilder {
             script.write_assignment_line(id, file);
         }
 
+        // add module dirs to cmd and task.sh ////////
+        for (id, dir) in &self.modules {
+            let var = module_var(*id, wf)?;
+            let dir = run_strs.get(*dir)?;
+            cmd.env(&var, dir);
+            script.write_assignment_line(&var, dir);
+        }
+
         // write actual code + suffix to cmd and task.sh ///
         let code = wf.strings.literals.get(self.code)?;
         if let Some(output_strs) = output_strs {
@@ -125,13 +260,33 @@ impl TaskRunnerBuilder {
         }
         cmd.arg("-c").arg(code);
 
+        let (cache_key, relative_outputs) = match self.partial_cache_key(run_strs, wf)? {
+            Some((digester, relative_outputs)) => (Some(digester), relative_outputs),
+            None => (None, Vec::with_capacity(0)),
+        };
+
         Ok(TaskRunner {
             cmd,
             print_id: self.print_id,
             realization_dir: self.realization_id,
-            inputs,
+            exec_dir: self.exec_id,
+            inputs: self.input_files,
             outputs,
             copy_outputs_to: self.copy_outputs_to,
+            max_output_size: self.max_output_size,
+            dot_params: self.dot_params,
+            cache_key,
+            relative_outputs,
+            fingerprint,
+            info,
         })
     }
 }
+
+/// Name of the env var containing the path of a module, e.g. "HR_MODULE_cargo".
+fn module_var(id: ModuleId, wf: &Workflow) -> Result<String> {
+    Ok(format!(
+        "{MODULE_VAR_PREFIX}{}",
+        wf.strings.modules.get(id)?
+    ))
+}
@@ -19,14 +19,19 @@ impl TaskScriptBuilder<'_> {
         self.strbuf.push_str("#!/usr/bin/env bash\nset -xeuo pipefail\n\n");
     }
 
-    /// a single variable assignment
+    /// a single variable assignment, quoted if the value isn't a plain word
+    /// (e.g. a list of input files), so that the script can be run as-is.
     pub fn write_assignment_line(&mut self, var_name: &str, var_val: &str) {
         self.strbuf.push_str(var_name);
         self.strbuf.push('=');
         if var_val.is_empty() {
             self.strbuf.push_str("\"\"");
-        } else {
+        } else if var_val.chars().all(is_plain) {
             self.strbuf.push_str(var_val);
+        } else {
+            self.strbuf.push('\'');
+            self.strbuf.push_str(&var_val.replace('\'', "'\\''"));
+            self.strbuf.push('\'');
         }
         self.strbuf.push('\n');
     }
@@ -81,3 +86,30 @@ impl TaskScriptBuilder<'_> {
         self.strbuf.push_str("\nexit 0\n");
     }
 }
+
+/// true if `c` can appear unquoted in a shell word.
+fn is_plain(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "/._-+=:,@%".contains(c)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_write_assignment_line() {
+        let mut strbuf = String::new();
+        let mut script = TaskScriptBuilder::new(&mut strbuf);
+        script.write_assignment_line("a", "/out/a/Baseline.baseline/out.txt");
+        script.write_assignment_line("b", "");
+        script.write_assignment_line("c", "/x/one.txt /y/two.txt");
+        script.write_assignment_line("d", "it's $HOME");
+        assert_eq!(
+            strbuf,
+            "a=/out/a/Baseline.baseline/out.txt\n\
+             b=\"\"\n\
+             c='/x/one.txt /y/two.txt'\n\
+             d='it'\\''s $HOME'\n"
+        );
+    }
+}
@@ -1,20 +1,64 @@
 use anyhow::Result;
+use colored::Colorize;
 
 use intern::GetStr;
 use traverse::Node;
 use util::{HashSet, Hasher};
-use workflow::{IdentId, Workflow};
+use workflow::shell::{self, Token};
+use workflow::{AbstractTaskId, IdentId, Recapper, Workflow, WorkflowStrings};
+
+use crate::run_log::mirror_eprintln;
+
+use super::Error;
+
+/// Variables bash sets itself, which tasks can use without defining.
+const BASH_VARS: &[&str] = &[
+    "BASHPID",
+    "BASH_SOURCE",
+    "BASH_REMATCH",
+    "EUID",
+    "FUNCNAME",
+    "HOSTNAME",
+    "IFS",
+    "LINENO",
+    "OLDPWD",
+    "OPTARG",
+    "OPTIND",
+    "PIPESTATUS",
+    "PPID",
+    "PWD",
+    "RANDOM",
+    "REPLY",
+    "SECONDS",
+    "UID",
+];
+
+/// Variables every login environment sets, which tasks can also use without defining.
+/// Other environment variables aren't checked for, so that whether a task's vars are
+/// defined doesn't depend on the environment hr happens to run in.
+const LOGIN_VARS: &[&str] = &["HOME", "LOGNAME", "PATH", "SHELL", "USER"];
+
+/// Commands whose (non-option) arguments are names of variables they set.
+const ASSIGNERS: &[&str] = &[
+    "declare", "export", "for", "local", "read", "readonly", "select", "typeset",
+];
 
 /// Checks that task variables are defined.
 pub struct TaskVarChecker {
     vars: HashSet<IdentId>,
+    /// error out on undefined vars, instead of just warning:
+    strict: bool,
+    /// tasks we've already reported, so each is only reported once per run:
+    reported: HashSet<AbstractTaskId>,
 }
 
 impl TaskVarChecker {
     /// Create a new `TaskVarChecker` with capacity (should be max vars expected from a single task).
-    pub fn with_capacity(cap: usize) -> Self {
+    pub fn with_capacity(cap: usize, strict: bool) -> Self {
         Self {
             vars: HashSet::with_capacity_and_hasher(cap, Hasher::default()),
+            strict,
+            reported: HashSet::default(),
         }
     }
 
@@ -28,19 +72,110 @@ impl TaskVarChecker {
         self.vars.insert(k);
     }
 
-    /// Check that each variable used in execution code is defined.
-    /// Currently, since checking for definitions could use some improvement,
-    /// just prints a warning rather than erroring out.
-    pub fn check(&self, node: &Node, wf: &Workflow) -> Result<()> {
+    /// Check that each variable used in execution code is defined: either as one of
+    /// the task's inputs, outputs, or params, by bash or the login environment,
+    /// or by the code itself. Undefined vars are a warning, or an error if `strict`.
+    pub fn check(&mut self, node: &Node, wf: &Workflow) -> Result<()> {
+        if self.reported.contains(&node.key.id) {
+            return Ok(());
+        }
+        let code = wf.strings.literals.get(node.code)?;
+        let mut undefined = Vec::new();
         for k in &node.code_vars {
-            if !self.vars.contains(k) {
-                let name = wf.strings.idents.get(*k)?;
-                log::debug!(
-                    "missing var {:?}: {name:?} (hope it's defined in the code...)",
-                    *k
-                );
+            if self.vars.contains(k) || undefined.contains(k) {
+                continue;
+            }
+            let name = wf.strings.idents.get(*k)?;
+            if BASH_VARS.contains(&name)
+                || LOGIN_VARS.contains(&name)
+                || is_defined_in_code(code, name)
+            {
+                log::debug!("var {name:?} isn't a task var, but is defined elsewhere");
+            } else {
+                undefined.push(*k);
             }
         }
+        if undefined.is_empty() {
+            return Ok(());
+        }
+        self.reported.insert(node.key.id);
+        if self.strict {
+            return Err(Recapper::new(Error::UndefinedVars(node.key.id, undefined)).into());
+        }
+        mirror_eprintln!(
+            "{} Task {} uses {}, which {} not one of its inputs, outputs, or params",
+            "WARNING".yellow(),
+            wf.strings.tasks.get(node.key.id)?,
+            format_vars(&undefined, &wf.strings)?,
+            if undefined.len() == 1 { "is" } else { "are" },
+        );
         Ok(())
     }
 }
+
+/// e.g. "$a, $b"
+pub(super) fn format_vars(vars: &[IdentId], strings: &WorkflowStrings) -> Result<String> {
+    let mut s = String::new();
+    for (i, k) in vars.iter().enumerate() {
+        if i > 0 {
+            s.push_str(", ");
+        }
+        s.push('$');
+        s.push_str(strings.idents.get(*k)?);
+    }
+    Ok(s)
+}
+
+/// Best guess at whether `code` sets `name` itself (e.g. `name=x`, `for name in ...`,
+/// `read -r name`), or only uses it with a default (e.g. `${name:-x}`).
+fn is_defined_in_code(code: &str, name: &str) -> bool {
+    shell::statements(code).iter().any(|statement| {
+        let assigns = statement.iter().any(|token| match *token {
+            Token::Word(word) => word
+                .strip_prefix(name)
+                .is_some_and(|after| after.starts_with('=') || after.starts_with("+=")),
+            Token::Var {
+                name: var,
+                braced: true,
+                rest,
+            } => var == name && ["-", ":-", "=", ":="].iter().any(|op| rest.starts_with(op)),
+            Token::Var { .. } => false,
+        });
+        assigns
+            || shell::command(statement).is_some_and(|(command, args)| {
+                // e.g. "read -r a name", where every word before the name is an option or a name:
+                let words = args.iter().map_while(|token| match token {
+                    Token::Word(word) => Some(*word),
+                    Token::Var { .. } => None,
+                });
+                ASSIGNERS.contains(&command)
+                    && words
+                        .take_while(|w| w.starts_with('-') || shell::is_name(w))
+                        .any(|w| w == name)
+            })
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_is_defined_in_code() {
+        let defined = |code| is_defined_in_code(code, "x");
+        assert!(defined("x=1\necho $x"));
+        assert!(defined("echo hi; x+=\" more\""));
+        assert!(defined("for x in a b; do echo $x; done"));
+        assert!(defined("cat f | while read -r y x; do echo $x; done"));
+        assert!(defined("local -i x"));
+        assert!(defined("echo ${x:-default}"));
+        assert!(!defined("echo $x"));
+        assert!(!defined("echo ${x}"));
+        assert!(!defined("xx=1; echo $x"));
+        assert!(!defined("echo $x=1"));
+        assert!(!defined("echo ${x:+set}"));
+        assert!(!defined("read -r y; echo $x"));
+        assert!(!defined("echo hi # x=1"));
+        assert!(!defined("echo 'x=1' \"for x in\""));
+    }
+}
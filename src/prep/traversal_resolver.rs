@@ -1,19 +1,77 @@
-use std::path::Path;
+use std::collections::hash_map::Entry;
+use std::path::{Component, Path, PathBuf};
 
 use anyhow::Result;
 
 use intern::{GetStr, InternStr};
 use traverse::{Node, RealInput, RealOutput, Traversal, ValueContext};
-use util::PathEncodingError;
-use workflow::{Errors, IdentId, RealTaskKey, Recapper, RunStrId, TaskVars, Workflow};
+use util::{Digest, HashMap, HashSet, PathEncodingError};
+use workflow::{Errors, IdentId, ModuleId, RealTaskKey, Recapper, RunStrId, TaskVars, Workflow};
 
-use crate::fs::Fs;
+use crate::fs::{Fs, ModuleRev};
 
 use super::{
-    Actions, ActualTaskId, Deduper, Error, ModuleChecker, RealInputs, RealOutputsParams,
-    TaskDirPaths, TaskRunnerBuilder, TaskVarChecker,
+    fingerprint_base, finish_fingerprint, Actions, ActualTaskId, Deduper, Error, ModuleChecker,
+    ModuleGitState, Probe, RealInputs, RealOutputsParams, TaskDirPaths, TaskRunnerBuilder,
+    TaskVarChecker,
 };
 
+/// A version of a module that tasks run in, resolved from the rev given in the config.
+struct ModuleVersion {
+    /// what the rev resolved to
+    rev: ModuleRev,
+    /// id of the dir the repo will be checked out in
+    checkout_id: RunStrId,
+    /// id of the full commit hash
+    commit_id: RunStrId,
+    /// true once the checkout has been added to the actions (if it needs creating)
+    scheduled: bool,
+}
+
+/// What's on disk for a realization, and whether that means it needs to run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RealizationState {
+    /// Completed, and up to date.
+    Complete,
+    /// Ran, but didn't succeed (or never finished).
+    Failed,
+    /// Hasn't run.
+    Missing,
+    /// Completed, but its code, params, or input files have changed since.
+    StaleHash,
+    /// Completed, but a realization it depends on needs to run again.
+    StaleInput,
+}
+
+impl RealizationState {
+    /// true if the realization needs to run.
+    pub fn should_run(self) -> bool {
+        self != Self::Complete
+    }
+
+    pub fn describe(self) -> &'static str {
+        match self {
+            Self::Complete => "complete",
+            Self::Failed => "failed",
+            Self::Missing => "missing",
+            Self::StaleHash => "stale-hash",
+            Self::StaleInput => "stale-input",
+        }
+    }
+}
+
+/// Settings that change how a [`TraversalResolver`] prepares tasks;
+/// the defaults are for read-only commands, which don't run anything.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ResolverOptions {
+    /// execute tasks in temporary dirs that are renamed into place on success
+    pub atomic: bool,
+    /// fail if a task's module has uncommitted changes
+    pub require_clean_modules: bool,
+    /// fail on undefined variables in task code, instead of warning
+    pub strict_vars: bool,
+}
+
 /// `TraversalResolver` turns Nodes into workflow actions to run.
 /// Actions can be either to delete, or create and run.
 /// Also returns a list of already completed tasks so they can be printed out to the user.
@@ -42,13 +100,34 @@ pub struct TraversalResolver<'a> {
     strbuf: String,
     /// store errors here and display them at the end:
     errors: Errors,
+    /// execute tasks in temporary dirs that are renamed into place on success:
+    atomic: bool,
+    /// final path of every output seen so far, to detect tasks that would overwrite each other:
+    output_paths: HashMap<PathBuf, (IdentId, RealTaskKey)>,
+    /// resolved module dirs, by the value of the module's path param:
+    module_dirs: HashMap<RunStrId, RunStrId>,
+    /// versions of versioned modules, by module, dir, and rev (None if the rev couldn't be resolved):
+    module_versions: HashMap<ModuleVersionKey, Option<ModuleVersion>>,
+    /// keys of the module versions used by the task being resolved:
+    task_module_versions: Vec<ModuleVersionKey>,
+    /// realizations to treat as complete even if they aren't (with '--from'):
+    assumed_complete: HashSet<RealTaskKey>,
 }
 
+/// (module, id of the module's dir, id of the rev)
+type ModuleVersionKey = (ModuleId, RunStrId, RunStrId);
+
 impl<'a> TraversalResolver<'a> {
-    pub fn new(len: usize, fs: &'a Fs, wf: &'a mut Workflow) -> Self {
+    pub fn new(len: usize, fs: &'a Fs, wf: &'a mut Workflow, options: ResolverOptions) -> Self {
         Self {
-            var_checker: TaskVarChecker::with_capacity(wf.sizes().max_vars as usize),
-            module_checker: ModuleChecker::with_capacity(wf.strings.modules.len()),
+            var_checker: TaskVarChecker::with_capacity(
+                wf.sizes().max_vars as usize,
+                options.strict_vars,
+            ),
+            module_checker: ModuleChecker::with_capacity(
+                wf.strings.modules.len(),
+                options.require_clean_modules,
+            ),
             outputs: Vec::with_capacity(len),
             should_run: Vec::with_capacity(len),
             deduper: Deduper::with_capacity(len),
@@ -56,95 +135,427 @@ impl<'a> TraversalResolver<'a> {
             fs,
             strbuf: String::with_capacity(256),
             errors: Errors::default(),
+            atomic: options.atomic,
+            output_paths: HashMap::with_capacity_and_hasher(len, Default::default()),
+            module_dirs: HashMap::default(),
+            module_versions: HashMap::default(),
+            task_module_versions: Vec::with_capacity(2),
+            assumed_complete: HashSet::default(),
         }
     }
+
+    /// Treat these realizations as complete, so they don't run even if they haven't completed
+    /// (and neither do the realizations that depend on them, if they have).
+    pub fn set_assumed_complete(&mut self, keys: HashSet<RealTaskKey>) {
+        self.assumed_complete = keys;
+    }
 }
 
 impl TraversalResolver<'_> {
     /// NB should only be run once; if we want multiple runs for some reason need a reset fn.
     pub fn resolve_to_actions(&mut self, traversal: Traversal) -> Result<Actions> {
-        let mut paths = TaskDirPaths::new();
+        let mut paths = TaskDirPaths::new(self.fs, self.atomic);
         let mut actions = Actions::new(traversal.nodes.len());
-        for task in &traversal.nodes {
+        let probes = self.probe_realizations(&traversal)?;
+        for (task, probe) in traversal.nodes.iter().zip(&probes) {
             if self.deduper.is_dupe(&task.key) {
                 continue;
             }
 
-            let should_run = self.resolve_to_action(
+            let state = self.resolve_to_action(
                 task,
+                probe,
                 &traversal.inputs,
                 &traversal.outputs_params,
                 &mut actions,
                 &mut paths,
             )?;
 
-            self.should_run.push(should_run);
+            self.should_run.push(state.should_run());
         }
 
         self.errors.print_recap("preparing workflow", &self.wf.strings)?;
         Ok(actions)
     }
 
-    /// returns true if task should run
+    /// Resolve the traversal like `resolve_to_actions`, but instead of actions,
+    /// return the key, realization dir, and state of each (deduplicated) realization.
+    /// NB like `resolve_to_actions`, should only be run once.
+    pub fn resolve_to_states(
+        &mut self,
+        traversal: Traversal,
+    ) -> Result<Vec<(RealTaskKey, PathBuf, RealizationState)>> {
+        let mut paths = TaskDirPaths::new(self.fs, self.atomic);
+        let mut actions = Actions::new(traversal.nodes.len());
+        let dirs = self.realization_dirs(&traversal)?;
+        let probes = Probe::all(&dirs, self.fs)?;
+        let mut states = Vec::with_capacity(traversal.nodes.len());
+        for ((task, dir), probe) in traversal.nodes.iter().zip(dirs).zip(&probes) {
+            if self.deduper.is_dupe(&task.key) {
+                continue;
+            }
+            let state = self.resolve_to_action(
+                task,
+                probe,
+                &traversal.inputs,
+                &traversal.outputs_params,
+                &mut actions,
+                &mut paths,
+            )?;
+            self.should_run.push(state.should_run());
+            states.push((task.key.clone(), dir, state));
+        }
+
+        self.errors.print_recap("preparing workflow", &self.wf.strings)?;
+        Ok(states)
+    }
+
+    /// Fully resolve the goal task of the traversal (i.e. the last node),
+    /// even if it has already completed, so that its variables can be used
+    /// outside of a normal run (e.g. in 'hr shell').
+    /// NB like `resolve_to_actions`, should only be run once.
+    pub fn resolve_goal(&mut self, traversal: Traversal) -> Result<TaskRunnerBuilder> {
+        let mut paths = TaskDirPaths::new(self.fs, self.atomic);
+        let mut actions = Actions::new(traversal.nodes.len());
+        let goal_idx = traversal.nodes.len().checked_sub(1).ok_or(Error::EmptyTraversal)?;
+        let probes = self.probe_realizations(&traversal)?;
+        let no_probe = Probe::default();
+        for (i, (task, probe)) in traversal.nodes.iter().zip(&probes).enumerate() {
+            if self.deduper.is_dupe(&task.key) {
+                continue;
+            }
+
+            // the goal runs whether or not it has completed:
+            let probe = if i == goal_idx { &no_probe } else { probe };
+            let state = self.resolve_to_action(
+                task,
+                probe,
+                &traversal.inputs,
+                &traversal.outputs_params,
+                &mut actions,
+                &mut paths,
+            )?;
+
+            self.should_run.push(state.should_run());
+        }
+
+        self.errors.print_recap("preparing workflow", &self.wf.strings)?;
+        actions.pop_run().ok_or_else(|| Error::EmptyTraversal.into())
+    }
+
+    /// Resolve every task in the traversal as if none of them had run yet, so that
+    /// all of the checks done before running a task are done for each of them,
+    /// regardless of what's in the output dir.
+    /// NB like `resolve_to_actions`, should only be run once.
+    pub fn check(&mut self, traversal: Traversal) -> Result<()> {
+        let mut paths = TaskDirPaths::new(self.fs, self.atomic);
+        let mut actions = Actions::new(traversal.nodes.len());
+        let no_probe = Probe::default();
+        for task in &traversal.nodes {
+            if self.deduper.is_dupe(&task.key) {
+                continue;
+            }
+            let state = self.resolve_to_action(
+                task,
+                &no_probe,
+                &traversal.inputs,
+                &traversal.outputs_params,
+                &mut actions,
+                &mut paths,
+            )?;
+            self.should_run.push(state.should_run());
+        }
+        self.errors.print_recap("checking workflow", &self.wf.strings)
+    }
+
+    /// Check the realization dirs of all nodes in the traversal concurrently,
+    /// since checking them one at a time is slow for large traversals.
+    /// NB this doesn't include fingerprinting the inputs of completed tasks, which needs
+    /// their paths and whether their antecedents will run, so happens as each node is resolved.
+    fn probe_realizations(&mut self, traversal: &Traversal) -> Result<Vec<Probe>> {
+        let dirs = self.realization_dirs(traversal)?;
+        Probe::all(&dirs, self.fs)
+    }
+
+    /// Realization dir of each node in the traversal.
+    fn realization_dirs(&mut self, traversal: &Traversal) -> Result<Vec<PathBuf>> {
+        let mut locator = self.fs.locator();
+        let mut dirs = Vec::with_capacity(traversal.nodes.len());
+        for task in &traversal.nodes {
+            locator.locate_key(&task.key, self.wf, &mut self.strbuf)?;
+            dirs.push(locator.realization().to_owned());
+        }
+        Ok(dirs)
+    }
+
+    /// Returns the state of the task's realization; the task should run unless it's complete.
+    /// `probe` is what's on disk for the task's realization; with an empty probe,
+    /// the task is treated as missing whether or not it has completed.
     fn resolve_to_action(
         &mut self,
         task: &Node,
+        probe: &Probe,
         inputs: &RealInputs,
         outputs_params: &RealOutputsParams,
         actions: &mut Actions,
         paths: &mut TaskDirPaths,
-    ) -> Result<bool> {
+    ) -> Result<RealizationState> {
         self.var_checker.clear();
-        paths.make_paths(task, self.wf, self.fs, &mut self.strbuf)?;
+        paths.make_paths(task, self.wf, &mut self.strbuf)?;
         let mut vars = TaskVars::new_with_sizes(&task.vars);
 
-        // handle inputs and outputs first, since we need those even if task won't run:
-        let invalidated = self.handle_inputs(task, &mut vars.inputs, inputs)?;
+        // handle inputs, outputs, and params first, since we need those even if task won't run
+        // (params are part of the task's fingerprint):
+        let mut input_files = Vec::with_capacity(task.vars.inputs.len());
+        let invalidated = self.handle_inputs(task, &mut vars.inputs, &mut input_files, inputs)?;
+        self.handle_params(task, &mut vars.params, outputs_params)?;
+        // module versions determine module dirs, and module outputs are copied from the first:
+        self.resolve_module_dirs(task, &mut vars.params, paths)?;
+        self.resolve_module_versions(task, &mut vars.params, paths)?;
         let copy_outputs_to =
             self.handle_outputs(task, &mut vars.outputs, outputs_params, paths)?;
 
         let real_task_string = self.wf.strings.get_real_task_str(&task.key)?.to_owned();
         let print_id = self.wf.strings.run.intern(real_task_string)?;
         let realization_id = self.make_path_id(paths.realization())?;
+        let exec_id = self.make_path_id(paths.exec())?;
 
         // if task dir exists, check if it's complete; add to delete list if not:
-        if self.fs.exists(paths.realization()) {
-            if !invalidated && paths.exit_code_success(self.fs, &mut self.strbuf)? {
-                actions.add_completed(print_id);
-                return Ok(false);
-            } else {
-                actions.add_delete(print_id, realization_id);
-            }
+        let state = if !probe.exists {
+            RealizationState::Missing
+        } else if !probe.succeeded {
+            RealizationState::Failed
+        } else if invalidated {
+            RealizationState::StaleInput
+        } else if !self.fingerprint_matches(task, &vars, &input_files, probe, paths)? {
+            RealizationState::StaleHash
+        } else {
+            RealizationState::Complete
+        };
+        if state != RealizationState::Complete && self.assumed_complete.contains(&task.key) {
+            actions.add_skipped(task.key.id, print_id);
+            return Ok(RealizationState::Complete);
+        }
+        if state == RealizationState::Complete {
+            actions.add_completed(task.key.id, print_id);
+            let symlink_id = self.make_path_id(paths.link_src())?;
+            let link_target_id = self.make_path_id(paths.realization_relative())?;
+            actions.add_link(symlink_id, link_target_id);
+            return Ok(state);
+        } else if probe.exists {
+            actions.add_delete(task.key.id, print_id, realization_id);
         }
 
-        // at this point we know the task will run, so handle params:
-        self.handle_params(task, &mut vars.params, outputs_params)?;
-
-        // and perform some checks:
+        // at this point we know the task will run, so perform some checks:
         let _ = self.var_checker.check(task, self.wf).map_err(|e| self.errors.add(e));
         let _ = self
             .module_checker
-            .check(task, paths, self.fs, actions.modules_mut())
+            .check(task, paths, self.wf, self.fs, actions.modules_mut())
             .map_err(|e| self.errors.add(e));
+        let main_module = task.modules.first().copied();
+        let mut module_git = None;
+        for i in 0..self.task_module_versions.len() {
+            let key = self.task_module_versions[i];
+            let git = self.schedule_module_checkout(key, actions)?;
+            if Some(key.0) == main_module {
+                module_git = git;
+            }
+        }
+        if module_git.is_none() {
+            module_git = paths
+                .modules()
+                .first()
+                .and_then(|path| self.module_checker.git_state(path).cloned());
+        }
 
-        let module_id = if task.module.is_some() {
-            Some(self.make_path_id(paths.module())?)
-        } else {
-            None
-        };
+        let mut modules = Vec::with_capacity(task.modules.len());
+        for (id, path) in task.modules.iter().zip(paths.modules()) {
+            modules.push((*id, self.make_path_id(path)?));
+        }
+        let module_id = modules.first().map(|(_, path_id)| *path_id);
 
         actions.add_run(TaskRunnerBuilder {
+            key: task.key.clone(),
             print_id,
             realization_id,
+            exec_id,
             vars,
+            input_files,
             copy_outputs_to,
             module_id,
+            modules,
+            module_git,
             symlink_id: self.make_path_id(paths.link_src())?,
             link_target_id: self.make_path_id(paths.realization_relative())?,
             code: task.code,
+            max_output_size: task.max_output_size,
+            dot_params: task.dot_params.clone(),
         });
 
-        Ok(true)
+        Ok(state)
+    }
+
+    /// false if the code, params, or inputs of a completed task have changed since it ran.
+    /// Realizations without a recorded fingerprint are assumed to be up to date.
+    fn fingerprint_matches(
+        &mut self,
+        task: &Node,
+        vars: &TaskVars<(IdentId, RunStrId)>,
+        input_files: &[RunStrId],
+        probe: &Probe,
+        paths: &TaskDirPaths,
+    ) -> Result<bool> {
+        let Some(recorded) = &probe.fingerprint else {
+            return Ok(true);
+        };
+        let recorded = recorded.parse::<Digest>().ok();
+
+        let run_strs = &self.wf.strings.run;
+        let base = fingerprint_base(task.code, vars, run_strs, self.wf)?;
+        let inputs = input_files
+            .iter()
+            .map(|file| run_strs.get(*file))
+            .collect::<Result<Vec<_>, _>>()?;
+        match finish_fingerprint(base, inputs, self.fs) {
+            Ok(current) if recorded == Some(current) => Ok(true),
+            Ok(_) => {
+                log::info!(
+                    "{} has changed since it completed",
+                    paths.realization().display()
+                );
+                Ok(false)
+            }
+            Err(e) => {
+                log::info!(
+                    "unable to fingerprint {}: {e:?}",
+                    paths.realization().display()
+                );
+                Ok(false)
+            }
+        }
+    }
+
+    /// Resolve the dir of each module a task uses from the module's implicit path param,
+    /// which can refer to config values or branch like any other value.
+    /// Relative paths are relative to the config file's dir.
+    /// The path params are removed from `params`: module dirs are exported separately,
+    /// and moving a module shouldn't invalidate the tasks that use it.
+    fn resolve_module_dirs(
+        &mut self,
+        task: &Node,
+        params: &mut Vec<(IdentId, RunStrId)>,
+        paths: &mut TaskDirPaths,
+    ) -> Result<()> {
+        for (i, module) in task.modules.iter().enumerate() {
+            let param = match self.wf.module_path_param(*module) {
+                Ok(param) => param,
+                Err(e) => {
+                    // the module isn't defined in the config file:
+                    self.errors.add(Recapper::new(e).into());
+                    continue;
+                }
+            };
+            let Some(pos) = params.iter().position(|(k, _)| *k == param) else {
+                // the value couldn't be resolved; the error has already been recorded.
+                continue;
+            };
+            let (_, val) = params.remove(pos);
+            let dir_id = match self.module_dirs.get(&val) {
+                Some(dir_id) => *dir_id,
+                None => {
+                    let mut dir = self.wf.config_dir().join(self.wf.strings.run.get(val)?);
+                    // missing dirs are reported by the module checker:
+                    if let Ok(canonical) = dir.canonicalize() {
+                        dir = canonical;
+                    }
+                    let dir_id = self.make_path_id(&dir)?;
+                    self.module_dirs.insert(val, dir_id);
+                    dir_id
+                }
+            };
+            paths.set_module(i, Path::new(self.wf.strings.run.get(dir_id)?));
+        }
+        Ok(())
+    }
+
+    /// For each versioned module of a task, resolve the module's rev to a commit,
+    /// use a checkout of that commit as the module dir, and replace the rev in its implicit
+    /// commit param with the commit, so that a rev that moves (like a branch) invalidates the task.
+    /// Keys of the resolved versions are left in `self.task_module_versions`.
+    fn resolve_module_versions(
+        &mut self,
+        task: &Node,
+        params: &mut [(IdentId, RunStrId)],
+        paths: &mut TaskDirPaths,
+    ) -> Result<()> {
+        self.task_module_versions.clear();
+        for (i, module) in task.modules.iter().enumerate() {
+            let Some(param) = self.wf.module_commit_param(*module) else {
+                continue;
+            };
+            let Some((_, val)) = params.iter_mut().find(|(k, _)| *k == param) else {
+                continue;
+            };
+
+            let dir_id = self.make_path_id(&paths.modules()[i])?;
+            let key = (*module, dir_id, *val);
+            if !self.module_versions.contains_key(&key) {
+                let version = match self.make_module_version(key) {
+                    Ok(version) => Some(version),
+                    Err(e) => {
+                        let task_str = self.wf.strings.get_real_task_str(&task.key)?;
+                        self.errors.add_context(e, format!("while preparing task {task_str}"));
+                        None
+                    }
+                };
+                self.module_versions.insert(key, version);
+            }
+            let Some(version) = &self.module_versions[&key] else {
+                continue;
+            };
+            let checkout = Path::new(self.wf.strings.run.get(version.checkout_id)?);
+            paths.set_module(i, &checkout.join(&version.rev.prefix));
+            *val = version.commit_id;
+            self.task_module_versions.push(key);
+        }
+        Ok(())
+    }
+
+    fn make_module_version(&mut self, key: ModuleVersionKey) -> Result<ModuleVersion> {
+        let (module, dir, rev) = key;
+        let module_path = Path::new(self.wf.strings.run.get(dir)?);
+        let rev = self.fs.resolve_module_rev(module_path, self.wf.strings.run.get(rev)?)?;
+        let name = self.wf.strings.modules.get(module)?;
+        let checkout = self.fs.module_checkout_dir(name, &rev.commit);
+        Ok(ModuleVersion {
+            checkout_id: self.make_path_id(&checkout)?,
+            commit_id: self.wf.strings.run.intern(&rev.commit)?,
+            rev,
+            scheduled: false,
+        })
+    }
+
+    /// Add the checkout of a module version to the actions, the first time a task that will
+    /// run needs it (if it doesn't exist yet), and return its git state.
+    fn schedule_module_checkout(
+        &mut self,
+        key: ModuleVersionKey,
+        actions: &mut Actions,
+    ) -> Result<Option<ModuleGitState>> {
+        let Some(Some(version)) = self.module_versions.get_mut(&key) else {
+            return Ok(None);
+        };
+        if !version.scheduled {
+            version.scheduled = true;
+            let checkout = self.wf.strings.run.get(version.checkout_id)?;
+            if !self.fs.exists(checkout) {
+                actions.add_checkout(key.0, key.2, version.checkout_id, version.rev.clone());
+            }
+        }
+        Ok(Some(ModuleGitState {
+            commit: version.rev.commit.clone(),
+            dirty: false,
+        }))
     }
 
     fn make_path_id(&mut self, path: &Path) -> Result<RunStrId> {
@@ -156,10 +567,12 @@ impl TraversalResolver<'_> {
 // INPUTS ///////////////////
 impl TraversalResolver<'_> {
     /// true if any of this task's inputs are invalid, i.e. the task should run.
+    /// Individual input files are added to `files` (a list input has several).
     fn handle_inputs(
         &mut self,
         task: &Node,
         inputs: &mut Vec<(IdentId, RunStrId)>,
+        files: &mut Vec<RunStrId>,
         values: &RealInputs,
     ) -> Result<bool> {
         let mut should_run = false;
@@ -167,7 +580,7 @@ impl TraversalResolver<'_> {
             self.var_checker.insert(*k);
             let val = values.get(*v).ok_or(Error::MissingValue(*k, *v))?;
 
-            match self.handle_input(val) {
+            match self.handle_input(val, files) {
                 Ok((file_id, this_input_should_run)) => {
                     inputs.push((*k, file_id));
                     should_run = this_input_should_run || should_run;
@@ -178,20 +591,53 @@ impl TraversalResolver<'_> {
         Ok(should_run)
     }
 
-    fn handle_input(&mut self, v: &RealInput) -> Result<(RunStrId, bool)> {
-        match v {
+    fn handle_input(
+        &mut self,
+        v: &RealInput,
+        files: &mut Vec<RunStrId>,
+    ) -> Result<(RunStrId, bool)> {
+        let (file_id, should_run) = match v {
             RealInput::Literal(lit_id) => {
                 let lit_val = self.wf.strings.literals.get(*lit_id)?;
                 let file_id = self.wf.strings.run.intern(lit_val)?;
-                Ok((file_id, false))
+                (file_id, false)
             }
             RealInput::Task(task_id, output_id) => {
                 let actual_id = self.deduper.get_actual_task_id(*task_id)?;
                 let file_id = self.get_task_output_string(actual_id, *output_id)?;
                 let antecedent_should_run = self.should_run[actual_id as usize];
-                Ok((file_id, antecedent_should_run))
+                (file_id, antecedent_should_run)
+            }
+            RealInput::List(items) => return self.handle_input_list(items, files),
+        };
+        if !files.contains(&file_id) {
+            files.push(file_id);
+        }
+        Ok((file_id, should_run))
+    }
+
+    /// Join the files of a list input (from a branch glob graft) with spaces.
+    fn handle_input_list(
+        &mut self,
+        items: &[RealInput],
+        files: &mut Vec<RunStrId>,
+    ) -> Result<(RunStrId, bool)> {
+        let mut file_ids = Vec::with_capacity(items.len());
+        let mut should_run = false;
+        for item in items {
+            let (file_id, item_should_run) = self.handle_input(item, files)?;
+            should_run = item_should_run || should_run;
+            // branches the grafted value doesn't depend on all give the same file:
+            if !file_ids.contains(&file_id) {
+                file_ids.push(file_id);
             }
         }
+        let file_strs = file_ids
+            .iter()
+            .map(|id| self.wf.strings.run.get(*id))
+            .collect::<Result<Vec<_>, _>>()?;
+        let list = file_strs.join(" ");
+        Ok((self.wf.strings.run.intern(&list)?, should_run))
     }
 
     fn get_task_output_string(&self, t: ActualTaskId, o: IdentId) -> Result<RunStrId> {
@@ -213,7 +659,7 @@ impl TraversalResolver<'_> {
         values: &RealOutputsParams,
         paths: &mut TaskDirPaths,
     ) -> Result<Vec<RunStrId>> {
-        if task.module.is_some() {
+        if !task.modules.is_empty() {
             let mut copy_outputs_to = Vec::with_capacity(outputs.len());
             let mut outputs_metadata = Vec::with_capacity(outputs.len());
             for (k, v) in &task.vars.outputs {
@@ -221,10 +667,11 @@ impl TraversalResolver<'_> {
                 let val = values.get(*v).ok_or(Error::MissingValue(*k, *v))?;
 
                 match self.handle_module_output(val, paths) {
-                    Ok((task_id, module_id)) => {
+                    Ok((task_id, module_id, final_id)) => {
                         outputs.push((*k, module_id));
                         copy_outputs_to.push(task_id);
-                        outputs_metadata.push((*k, task_id));
+                        outputs_metadata.push((*k, final_id));
+                        self.check_output_conflict(*k, &task.key, final_id)?;
                     }
                     Err(e) => self.var_err("output", *k, &task.key, e)?,
                 }
@@ -232,42 +679,90 @@ impl TraversalResolver<'_> {
             self.outputs.push(outputs_metadata);
             Ok(copy_outputs_to)
         } else {
+            let mut outputs_metadata = Vec::with_capacity(outputs.len());
             for (k, v) in &task.vars.outputs {
                 self.var_checker.insert(*k);
                 let val = values.get(*v).ok_or(Error::MissingValue(*k, *v))?;
 
                 match self.handle_normal_output(val, paths) {
-                    Ok(task_id) => outputs.push((*k, task_id)),
+                    Ok((task_id, final_id)) => {
+                        outputs.push((*k, task_id));
+                        outputs_metadata.push((*k, final_id));
+                        self.check_output_conflict(*k, &task.key, final_id)?;
+                    }
                     Err(e) => self.var_err("output", *k, &task.key, e)?,
                 }
             }
-            self.outputs.push(outputs.clone());
+            self.outputs.push(outputs_metadata);
             Ok(Vec::with_capacity(0))
         }
     }
 
+    /// returns ids of (output in exec dir, output in module dir, output in realization dir).
     fn handle_module_output(
         &mut self,
         val: &RealOutput,
         paths: &mut TaskDirPaths,
-    ) -> Result<(RunStrId, RunStrId)> {
+    ) -> Result<(RunStrId, RunStrId, RunStrId)> {
         let file = lit_str(val, self.wf, &self.wf.strings.literals, &mut self.strbuf)?;
         let task_id = path_id(paths.normal_output(file), &mut self.wf.strings.run)?;
         let module_id = path_id(paths.module_output(file), &mut self.wf.strings.run)?;
-        Ok((task_id, module_id))
+        let final_id = path_id(paths.final_output(file), &mut self.wf.strings.run)?;
+        Ok((task_id, module_id, final_id))
     }
 
+    /// returns ids of (output in exec dir, output in realization dir).
     fn handle_normal_output(
         &mut self,
         val: &RealOutput,
         paths: &mut TaskDirPaths,
-    ) -> Result<RunStrId> {
+    ) -> Result<(RunStrId, RunStrId)> {
         let file = lit_str(val, self.wf, &self.wf.strings.literals, &mut self.strbuf)?;
         let task_id = path_id(paths.normal_output(file), &mut self.wf.strings.run)?;
-        Ok(task_id)
+        let final_id = path_id(paths.final_output(file), &mut self.wf.strings.run)?;
+        Ok((task_id, final_id))
+    }
+}
+
+// OUTPUT CONFLICTS /////////
+impl TraversalResolver<'_> {
+    /// Record the final path of a task output, and store an error if another
+    /// task realization in the traversal already writes to the same path.
+    fn check_output_conflict(&mut self, k: IdentId, key: &RealTaskKey, id: RunStrId) -> Result<()> {
+        let path = normalize_path(Path::new(self.wf.strings.run.get(id)?));
+        match self.output_paths.entry(path) {
+            Entry::Vacant(entry) => {
+                entry.insert((k, key.clone()));
+            }
+            Entry::Occupied(entry) => {
+                let (other_k, other_key) = entry.get().clone();
+                let path = entry.key().to_str().ok_or(PathEncodingError)?.to_owned();
+                let e = Error::ConflictingOutput(path, other_k, other_key, k, key.clone());
+                self.errors.add(Recapper::new(e).into());
+            }
+        }
+        Ok(())
     }
 }
 
+/// Lexically resolve `.` and `..` components, so that different spellings
+/// of the same path compare equal (without touching the filesystem).
+fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::with_capacity(path.as_os_str().len());
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    normalized.push(component);
+                }
+            }
+            _ => normalized.push(component),
+        }
+    }
+    normalized
+}
+
 // PARAMS ///////////////////
 impl TraversalResolver<'_> {
     fn handle_params(
@@ -282,6 +777,17 @@ impl TraversalResolver<'_> {
 
             match lit_str(val, self.wf, &self.wf.strings.literals, &mut self.strbuf) {
                 Ok(val_str) => {
+                    if let Some(ty) = self.wf.get_task(task.key.id)?.param_type(*k) {
+                        if !ty.accepts(val_str, self.wf.config_dir()) {
+                            let e = Error::InvalidParamValue(
+                                *k,
+                                task.key.clone(),
+                                val_str.to_owned(),
+                                ty.clone(),
+                            );
+                            self.errors.add(Recapper::new(e).into());
+                        }
+                    }
                     let val_id = self.wf.strings.run.intern(val_str)?;
                     params.push((*k, val_id));
                 }
@@ -312,7 +818,7 @@ impl TraversalResolver<'_> {
 fn lit_str<'a>(
     v: &RealOutput,
     wf: &Workflow,
-    literals: &'a intern::TypedInterner<workflow::LiteralId, intern::LooseInterner<u8, u16>>,
+    literals: &'a intern::TypedInterner<workflow::LiteralId, intern::LooseInterner<u16, u32>>,
     strbuf: &'a mut String,
 ) -> Result<&'a str> {
     match v {
@@ -333,3 +839,20 @@ fn path_id(
     let path_str = path.to_str().ok_or(PathEncodingError)?;
     run_strs.intern(path_str)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_normalize_path() {
+        assert_eq!(
+            normalize_path(Path::new("/out/a/realizations/X.x1/../../../b/./c.txt")),
+            PathBuf::from("/out/b/c.txt")
+        );
+        assert_eq!(
+            normalize_path(Path::new("../a/./b")),
+            PathBuf::from("../a/b")
+        );
+    }
+}
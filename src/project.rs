@@ -0,0 +1,288 @@
+use std::path::{Path, PathBuf};
+
+use toml::{Table, Value};
+
+use crate::args::NotifyOn;
+
+/// Settings file checked in at the root of a project, looked for in the current dir and its parents.
+pub const PROJECT_FILE: &str = ".heron-rebuild.toml";
+/// Shorter name the project settings file can have instead.
+pub const SHORT_PROJECT_FILE: &str = ".hr.toml";
+
+/// Keys a project file may set; command-line args and environment variables override them.
+const KEYS: [&str; 6] = [
+    "config",
+    "output",
+    "plan",
+    "verbose",
+    "jobs",
+    "notifications",
+];
+
+/// Keys of the `[notifications]` table.
+const NOTIFICATION_KEYS: [&str; 2] = ["command", "on"];
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("{0} has both {PROJECT_FILE} and {SHORT_PROJECT_FILE}; project settings should be in one of them")]
+    TwoProjectFiles(String),
+    #[error("Unable to read project settings in {0}")]
+    Read(String, #[source] std::io::Error),
+    #[error("Invalid TOML in project settings {0}")]
+    Toml(String, #[source] toml::de::Error),
+    #[error("Unknown key '{1}' in project settings {0}{2}")]
+    UnknownKey(String, String, String),
+    #[error("'{1}' in project settings {0} should be {2}")]
+    InvalidValue(String, String, &'static str),
+}
+
+/// Per-project defaults read from a `.heron-rebuild.toml` file, e.g.:
+/// ```toml
+/// config = "pipeline/rebuild.hr"
+/// output = "build/output"
+/// plan = ["mac", "win"]
+/// verbose = 1
+/// jobs = 4
+///
+/// [notifications]
+/// command = "notify-send hr \"$HR_RUN_STATUS\""
+/// on = "failure"
+/// ```
+/// Relative paths are relative to the dir the file is in.
+#[derive(Debug, Default, PartialEq)]
+pub struct ProjectSettings {
+    /// the settings file itself:
+    pub path: PathBuf,
+    pub configs: Vec<PathBuf>,
+    pub output: Option<PathBuf>,
+    pub plans: Vec<String>,
+    pub verbose: Option<u8>,
+    pub jobs: Option<usize>,
+    pub notifications: Option<Notifications>,
+}
+
+/// Command to run when a run finishes (see `exec::Notifier`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notifications {
+    pub command: String,
+    pub on: NotifyOn,
+}
+
+impl ProjectSettings {
+    /// Read the project settings file (`PROJECT_FILE` or `SHORT_PROJECT_FILE`)
+    /// in `start` or the nearest of its ancestors that has one.
+    pub fn discover(start: &Path) -> Result<Option<Self>, Error> {
+        for dir in start.ancestors() {
+            let mut found = [PROJECT_FILE, SHORT_PROJECT_FILE]
+                .into_iter()
+                .map(|name| dir.join(name))
+                .filter(|path| path.is_file());
+            let Some(path) = found.next() else {
+                continue;
+            };
+            if found.next().is_some() {
+                return Err(Error::TwoProjectFiles(dir.display().to_string()));
+            }
+            let text = std::fs::read_to_string(&path)
+                .map_err(|e| Error::Read(path.display().to_string(), e))?;
+            return Self::parse(&text, path).map(Some);
+        }
+        Ok(None)
+    }
+
+    /// Parse the contents of the project settings file at `path`.
+    fn parse(text: &str, path: PathBuf) -> Result<Self, Error> {
+        let name = path.display().to_string();
+        let table: Table = text.parse().map_err(|e| Error::Toml(name.clone(), e))?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        let mut settings = Self::default();
+        for (key, value) in table {
+            let invalid = |expected| Error::InvalidValue(name.clone(), key.clone(), expected);
+            match key.as_str() {
+                "config" => {
+                    let configs =
+                        strings(&value).ok_or_else(|| invalid("a path or list of paths"))?;
+                    settings.configs = configs.into_iter().map(|c| dir.join(c)).collect();
+                }
+                "output" => {
+                    let output = value.as_str().ok_or_else(|| invalid("a path"))?;
+                    settings.output = Some(dir.join(output));
+                }
+                "plan" => {
+                    settings.plans =
+                        strings(&value).ok_or_else(|| invalid("a plan or list of plans"))?;
+                }
+                "verbose" => {
+                    let verbose = value.as_integer().and_then(|v| u8::try_from(v).ok());
+                    settings.verbose = Some(
+                        verbose
+                            .filter(|v| *v <= 3)
+                            .ok_or_else(|| invalid("a number from 0 to 3"))?,
+                    );
+                }
+                "jobs" => {
+                    let jobs = value.as_integer().and_then(|v| usize::try_from(v).ok());
+                    settings.jobs =
+                        Some(jobs.filter(|j| *j > 0).ok_or_else(|| invalid("a number above 0"))?);
+                }
+                "notifications" => {
+                    let table = value.as_table().ok_or_else(|| invalid("a table"))?;
+                    settings.notifications = Some(notifications(table, &name)?);
+                }
+                _ => {
+                    let suggestion = util::did_you_mean(util::closest_match(&key, KEYS));
+                    return Err(Error::UnknownKey(name, key, suggestion));
+                }
+            }
+        }
+        settings.path = path;
+        Ok(settings)
+    }
+}
+
+/// The `[notifications]` table of the project settings file `name`.
+fn notifications(table: &Table, name: &str) -> Result<Notifications, Error> {
+    let mut command = None;
+    let mut on = NotifyOn::Always;
+    for (key, value) in table {
+        let full_key = || format!("notifications.{key}");
+        let invalid = |expected| Error::InvalidValue(name.to_owned(), full_key(), expected);
+        match key.as_str() {
+            "command" => {
+                command = Some(value.as_str().ok_or_else(|| invalid("a command"))?.to_owned());
+            }
+            "on" => {
+                on = match value.as_str() {
+                    Some("always") => NotifyOn::Always,
+                    Some("success") => NotifyOn::Success,
+                    Some("failure") => NotifyOn::Failure,
+                    _ => return Err(invalid("\"always\", \"success\", or \"failure\"")),
+                };
+            }
+            _ => {
+                let suggestion = util::did_you_mean(util::closest_match(key, NOTIFICATION_KEYS));
+                return Err(Error::UnknownKey(name.to_owned(), full_key(), suggestion));
+            }
+        }
+    }
+    let Some(command) = command else {
+        let key = "notifications".to_owned();
+        return Err(Error::InvalidValue(
+            name.to_owned(),
+            key,
+            "a table with a command",
+        ));
+    };
+    Ok(Notifications { command, on })
+}
+
+/// A string, or an array of strings, as a list of strings.
+fn strings(value: &Value) -> Option<Vec<String>> {
+    match value {
+        Value::String(s) => Some(vec![s.clone()]),
+        Value::Array(items) => items.iter().map(|item| item.as_str().map(str::to_owned)).collect(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Result;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_parse() -> Result<()> {
+        let path = PathBuf::from("/proj").join(PROJECT_FILE);
+        let settings = ProjectSettings::parse(
+            "config = 'pipeline/rebuild.hr'\n\
+             output = \"/tmp/out\"\n\
+             plan = [\"mac\", \"win\"]\n\
+             verbose = 2\n\
+             jobs = 4\n\
+             \n\
+             [notifications]\n\
+             command = 'notify-send hr'\n\
+             on = 'failure'\n",
+            path.clone(),
+        )?;
+        assert_eq!(
+            settings,
+            ProjectSettings {
+                path: path.clone(),
+                configs: vec![PathBuf::from("/proj/pipeline/rebuild.hr")],
+                output: Some(PathBuf::from("/tmp/out")),
+                plans: vec!["mac".to_owned(), "win".to_owned()],
+                verbose: Some(2),
+                jobs: Some(4),
+                notifications: Some(Notifications {
+                    command: "notify-send hr".to_owned(),
+                    on: NotifyOn::Failure,
+                }),
+            }
+        );
+
+        let err = |text| ProjectSettings::parse(text, path.clone()).unwrap_err().to_string();
+        assert_eq!(
+            err("outptu = 'out'"),
+            "Unknown key 'outptu' in project settings /proj/.heron-rebuild.toml \
+             (did you mean `output`?)"
+        );
+        assert_eq!(
+            err("plan = [1]"),
+            "'plan' in project settings /proj/.heron-rebuild.toml should be a plan or list of plans"
+        );
+        assert_eq!(
+            err("verbose = -1"),
+            "'verbose' in project settings /proj/.heron-rebuild.toml should be a number from 0 to 3"
+        );
+        assert_eq!(
+            err("jobs = 0"),
+            "'jobs' in project settings /proj/.heron-rebuild.toml should be a number above 0"
+        );
+        assert_eq!(
+            err("[notifications]\non = 'failure'"),
+            "'notifications' in project settings /proj/.heron-rebuild.toml \
+             should be a table with a command"
+        );
+        assert_eq!(
+            err("[notifications]\ncomand = 'true'"),
+            "Unknown key 'notifications.comand' in project settings /proj/.heron-rebuild.toml \
+             (did you mean `command`?)"
+        );
+        assert_eq!(
+            err("plan = "),
+            "Invalid TOML in project settings /proj/.heron-rebuild.toml"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_discover() -> Result<()> {
+        let dir = tempdir()?;
+        let root = dir.path().canonicalize()?;
+        let sub = root.join("a/b");
+        std::fs::create_dir_all(&sub)?;
+        assert_eq!(ProjectSettings::discover(&sub)?, None);
+
+        std::fs::write(root.join(SHORT_PROJECT_FILE), "output = 'out'\n")?;
+        let settings = ProjectSettings::discover(&sub)?.unwrap();
+        assert_eq!(settings.path, root.join(SHORT_PROJECT_FILE));
+        assert_eq!(settings.output, Some(root.join("out")));
+
+        // the nearest settings file wins:
+        std::fs::write(root.join("a").join(PROJECT_FILE), "output = 'a-out'\n")?;
+        let settings = ProjectSettings::discover(&sub)?.unwrap();
+        assert_eq!(settings.path, root.join("a").join(PROJECT_FILE));
+        assert_eq!(settings.output, Some(root.join("a/a-out")));
+
+        std::fs::write(root.join("a").join(SHORT_PROJECT_FILE), "output = 'out'\n")?;
+        let err = ProjectSettings::discover(&sub).unwrap_err();
+        assert!(
+            matches!(err, Error::TwoProjectFiles(d) if d == root.join("a").display().to_string())
+        );
+
+        dir.close()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,393 @@
+//! A log file for each run, mirroring what we print to stderr during it (with [`mirror_eprintln`]
+//! and friends), the output tasks print to it, and debug logs, so a run can be looked into
+//! after the terminal is gone.
+//!
+//! ```text
+//! $OUTPUT/logs/run-<timestamp>.log
+//! ```
+//!
+//! Output is buffered in memory until we know whether the command runs anything,
+//! so a run's log also has what was printed while preparing it.
+
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::Result;
+use log::{LevelFilter, Log, Metadata, Record};
+use serde::Serialize;
+
+use crate::args::LogFormat;
+use crate::exec::{ExecObserver, TaskEvent};
+use crate::fs::Fs;
+
+/// How many run logs to keep in the output dir, by default.
+pub const DEFAULT_KEEP: usize = 20;
+
+/// Most output to buffer before the run log starts; anything after this is left out of it.
+const BUFFER_LIMIT: usize = 1 << 20;
+
+enum Sink {
+    /// no run log yet; keep output in case one starts.
+    Buffer(Vec<u8>),
+    File(File),
+    Off,
+}
+
+struct RunLog {
+    sink: Sink,
+    format: LogFormat,
+    /// with the json format, printed output that doesn't end in a newline yet:
+    partial: String,
+    /// what the run is doing, e.g. "resolve" or "execute":
+    phase: Option<String>,
+    /// task and branch being run:
+    task: Option<(String, String)>,
+}
+
+static RUN_LOG: Mutex<RunLog> = Mutex::new(RunLog {
+    sink: Sink::Buffer(Vec::new()),
+    format: LogFormat::Text,
+    partial: String::new(),
+    phase: None,
+    task: None,
+});
+
+fn lock() -> MutexGuard<'static, RunLog> {
+    RUN_LOG.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+impl RunLog {
+    fn write(&mut self, text: &str) {
+        match &mut self.sink {
+            Sink::Buffer(buf) => {
+                if buf.len() + text.len() <= BUFFER_LIMIT {
+                    buf.extend_from_slice(text.as_bytes());
+                }
+            }
+            // a run shouldn't fail because its log can't be written:
+            Sink::File(file) => {
+                let _ = file.write_all(text.as_bytes());
+            }
+            Sink::Off => (),
+        }
+    }
+
+    /// A json-lines event with `level` and `message`, in the current phase and task.
+    fn event(&self, level: &str, message: &str) -> String {
+        let (task, branch) = self.task.as_ref().map(|(t, b)| (t.as_str(), b.as_str())).unzip();
+        let event = Event {
+            timestamp: util::format_rfc3339(SystemTime::now()),
+            level,
+            phase: self.phase.as_deref(),
+            task,
+            branch,
+            message,
+        };
+        let json =
+            serde_json::to_string(&event).expect("Should never fail -- an event is only strings");
+        format!("{json}\n")
+    }
+}
+
+/// A json-lines event in the run log.
+#[derive(Debug, Serialize)]
+struct Event<'a> {
+    timestamp: String,
+    level: &'a str,
+    phase: Option<&'a str>,
+    task: Option<&'a str>,
+    branch: Option<&'a str>,
+    message: &'a str,
+}
+
+/// Write `text` to the run log (or the buffer, if it hasn't started), without color codes.
+/// With the json format, each line of it is an event with level "output".
+pub fn mirror(text: &str) {
+    let text = strip_ansi(text);
+    let mut run_log = lock();
+    if run_log.format == LogFormat::Text {
+        run_log.write(&text);
+        return;
+    }
+    run_log.partial.push_str(&text);
+    while let Some(end) = run_log.partial.find('\n') {
+        let line: String = run_log.partial.drain(..=end).collect();
+        let line = line.trim_end();
+        // blank lines just space out what we print:
+        if !line.is_empty() {
+            let event = run_log.event("output", line);
+            run_log.write(&event);
+        }
+    }
+}
+
+/// Like `eprint!`, but also [`mirror`]ed to the run log; for what we print during a run.
+macro_rules! mirror_eprint {
+    ($($arg:tt)*) => {{
+        let text = format!($($arg)*);
+        ::std::eprint!("{text}");
+        $crate::run_log::mirror(&text);
+    }};
+}
+
+/// Like `eprintln!`, but also [`mirror`]ed to the run log; for what we print during a run.
+macro_rules! mirror_eprintln {
+    () => {
+        $crate::run_log::mirror_eprint!("\n")
+    };
+    ($($arg:tt)*) => {
+        $crate::run_log::mirror_eprint!("{}\n", format_args!($($arg)*))
+    };
+}
+
+pub(crate) use {mirror_eprint, mirror_eprintln};
+
+/// Write the error a command failed with to the run log, since it's printed after we return.
+pub fn record_error(error: &anyhow::Error) {
+    let mut run_log = lock();
+    let text = match run_log.format {
+        LogFormat::Text => format!("Error: {error:?}\n"),
+        LogFormat::Json => run_log.event("error", &format!("{error:#}")),
+    };
+    run_log.write(&text);
+}
+
+/// Set what the run is doing, e.g. "parse" or "execute", for json log events.
+pub fn set_phase(phase: &str) {
+    lock().phase = Some(phase.to_owned());
+}
+
+/// Start writing the run log in the output dir, beginning with whatever has been buffered,
+/// and delete old run logs so only the newest `keep` are left. If `keep` is 0, just stop
+/// buffering. Returns the new log's path.
+pub fn start(fs: &Fs, keep: usize) -> Result<Option<PathBuf>> {
+    let mut run_log = lock();
+    let buffered = match std::mem::replace(&mut run_log.sink, Sink::Off) {
+        Sink::Buffer(buf) => buf,
+        _ => Vec::with_capacity(0),
+    };
+    if keep == 0 {
+        return Ok(None);
+    }
+
+    let dir = fs.run_logs_dir();
+    fs.create_dir(&dir)?;
+    let now = SystemTime::now();
+    let timestamp = util::format_utc_timestamp(now);
+    let mut path = dir.join(format!("run-{timestamp}.log"));
+    // runs can start within the same second:
+    let mut n = 1;
+    while fs.exists(&path) {
+        n += 1;
+        path = dir.join(format!("run-{timestamp}-{n}.log"));
+    }
+
+    let mut file = fs.create_file(&path)?;
+    let args: Vec<String> = std::env::args().collect();
+    match run_log.format {
+        LogFormat::Text => writeln!(file, "# {} at {timestamp}", args.join(" "))?,
+        LogFormat::Json => {
+            let event = run_log.event("info", &format!("started {}", args.join(" ")));
+            file.write_all(event.as_bytes())?;
+        }
+    }
+    file.write_all(&buffered)?;
+    run_log.sink = Sink::File(file);
+    drop(run_log);
+
+    remove_old_logs(fs, &dir, keep)?;
+    Ok(Some(path))
+}
+
+/// Delete all but the newest `keep` run logs in `dir`.
+fn remove_old_logs(fs: &Fs, dir: &Path, keep: usize) -> Result<()> {
+    let mut logs = Vec::new();
+    for entry in fs.read_dir(dir)? {
+        let path = entry?.path();
+        let is_log = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with("run-") && name.ends_with(".log"));
+        if is_log {
+            logs.push((fs.modified(&path)?, path));
+        }
+    }
+    logs.sort_unstable_by(|a, b| b.cmp(a));
+    for (_, path) in logs.iter().skip(keep) {
+        log::debug!("removing old run log {path:?}");
+        fs.delete_file(path)?;
+    }
+    Ok(())
+}
+
+/// Remove terminal color codes (`ESC [ ... m` and the like) from `text`.
+fn strip_ansi(text: &str) -> std::borrow::Cow<'_, str> {
+    if !text.contains('\x1b') {
+        return text.into();
+    }
+    let mut stripped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            stripped.push(c);
+        } else if chars.clone().next() == Some('[') {
+            // skip parameters up to the final byte of the sequence:
+            for c in chars.by_ref().skip(1) {
+                if ('\x40'..='\x7e').contains(&c) {
+                    break;
+                }
+            }
+        }
+    }
+    stripped.into()
+}
+
+/// Our stderr, also mirrored to the run log; for copying the output of tasks.
+pub struct Stderr;
+
+impl Write for Stderr {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = std::io::stderr().write(buf)?;
+        mirror(&String::from_utf8_lossy(&buf[..n]));
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        std::io::stderr().flush()
+    }
+}
+
+/// Logs records up to `level` to stderr, and records up to debug level to the run log,
+/// as text or json-lines events.
+pub struct Logger {
+    level: LevelFilter,
+    start: Instant,
+}
+
+impl Logger {
+    /// Make this the global logger.
+    pub fn init(level: LevelFilter, format: LogFormat) {
+        lock().format = format;
+        let logger = Self {
+            level,
+            start: Instant::now(),
+        };
+        if log::set_logger(Box::leak(Box::new(logger))).is_ok() {
+            log::set_max_level(level.max(LevelFilter::Debug));
+        }
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level.max(LevelFilter::Debug)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let mut run_log = lock();
+        let line = match run_log.format {
+            LogFormat::Text => format_text(self.start.elapsed(), record),
+            LogFormat::Json => {
+                let level = record.level().as_str().to_ascii_lowercase();
+                run_log.event(&level, &record.args().to_string())
+            }
+        };
+        if record.level() <= self.level {
+            eprint!("{line}");
+        }
+        run_log.write(&strip_ansi(&line));
+    }
+
+    fn flush(&self) {}
+}
+
+/// A log record as text, with the time since we started.
+fn format_text(elapsed: Duration, record: &Record) -> String {
+    let secs = elapsed.as_secs();
+    format!(
+        "[{:02}:{:02}:{:02}.{:03}] {:<5} {}\n",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60,
+        elapsed.subsec_millis(),
+        record.level(),
+        record.args()
+    )
+}
+
+/// Keeps track of the task being run, for json log events.
+#[derive(Debug, Default)]
+pub struct TaskContext;
+
+impl ExecObserver for TaskContext {
+    fn on_task_start(&mut self, task: &TaskEvent) {
+        lock().task = Some((task.task.to_owned(), task.branch.to_owned()));
+    }
+
+    fn on_workflow_complete(&mut self, _succeeded: bool) {
+        lock().task = None;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_strip_ansi() {
+        assert_eq!(strip_ansi("plain"), "plain");
+        assert_eq!(strip_ansi("\x1b[32mdone\x1b[0m."), "done.");
+        assert_eq!(strip_ansi("\x1b[1;35mRUN\x1b[0m task"), "RUN task");
+    }
+
+    #[test]
+    fn test_event() -> Result<()> {
+        let run_log = RunLog {
+            sink: Sink::Off,
+            format: LogFormat::Json,
+            partial: String::new(),
+            phase: Some("execute".to_owned()),
+            task: Some(("build".to_owned(), "Os.mac".to_owned())),
+        };
+        let line = run_log.event("output", "RUN build[Os.mac]");
+        assert!(line.ends_with("}\n") && line.lines().count() == 1, "{line}");
+        let event: serde_json::Value = serde_json::from_str(&line)?;
+        let field = |key| event[key].as_str();
+        assert!(field("timestamp").is_some_and(|t| t.ends_with('Z')));
+        assert_eq!(field("level"), Some("output"));
+        assert_eq!(field("phase"), Some("execute"));
+        assert_eq!(field("task"), Some("build"));
+        assert_eq!(field("branch"), Some("Os.mac"));
+        assert_eq!(field("message"), Some("RUN build[Os.mac]"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_remove_old_logs() -> Result<()> {
+        let dir = tempdir()?;
+        let fs = Fs::new(dir.path(), false);
+        let logs = fs.run_logs_dir();
+        fs.create_dir(&logs)?;
+        for name in ["run-1.log", "run-2.log", "run-3.log", "notes.txt"] {
+            fs.write_file(logs.join(name), name)?;
+            // make sure modification times differ:
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        remove_old_logs(&fs, &logs, 2)?;
+        let mut left: Vec<_> = fs
+            .read_dir(&logs)?
+            .map(|entry| entry.map(|entry| entry.file_name()))
+            .collect::<Result<_, _>>()?;
+        left.sort();
+        assert_eq!(left, ["notes.txt", "run-2.log", "run-3.log"]);
+        dir.close()?;
+        Ok(())
+    }
+}
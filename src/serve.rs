@@ -0,0 +1,210 @@
+//! Minimal HTTP/1.1 server for 'hr serve': read-only GET requests, one at a time.
+
+use std::fmt;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::Duration;
+
+use anyhow::Result;
+use serde::Serialize;
+
+/// How long to wait for a client to send its request before giving up on it.
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Response to a request, always sent with 'Connection: close'.
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    /// 200 OK with a json body.
+    pub fn json(value: &impl Serialize) -> Result<Self> {
+        Ok(Self {
+            status: 200,
+            content_type: "application/json",
+            body: format!("{}\n", serde_json::to_string_pretty(value)?).into_bytes(),
+        })
+    }
+
+    /// 200 OK with a plain text body, e.g. the contents of a log file.
+    pub fn text(body: Vec<u8>) -> Self {
+        Self {
+            status: 200,
+            content_type: "text/plain; charset=utf-8",
+            body,
+        }
+    }
+
+    /// An error, with a json body like `{"error": "msg"}`.
+    pub fn error(status: u16, msg: impl fmt::Display) -> Self {
+        let json = serde_json::json!({ "error": msg.to_string() });
+        Self {
+            status,
+            content_type: "application/json",
+            body: format!("{json:#}\n").into_bytes(),
+        }
+    }
+}
+
+/// Answer requests on `listener` one at a time, forever. `handle` is given the
+/// percent-decoded segments of each request's path, e.g. `["logs", "task", "latest"]`.
+/// Problems with individual connections are logged, not returned.
+pub fn serve(listener: &TcpListener, mut handle: impl FnMut(&[String]) -> Response) -> Result<()> {
+    for stream in listener.incoming() {
+        let result = stream
+            .map_err(anyhow::Error::from)
+            .and_then(|stream| handle_connection(&stream, &mut handle));
+        if let Err(e) = result {
+            log::warn!("error answering request: {e:?}");
+        }
+    }
+    Ok(())
+}
+
+fn handle_connection(
+    stream: &TcpStream,
+    handle: &mut impl FnMut(&[String]) -> Response,
+) -> Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // we don't need any of the headers, but they have to be read before responding:
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 0 && !header.trim().is_empty() {
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default();
+    let target = parts.next().unwrap_or_default();
+    let response = match method {
+        "GET" | "HEAD" => match parse_path(target) {
+            Some(path) => handle(&path),
+            None => Response::error(400, format!("Invalid path: {target}")),
+        },
+        _ => Response::error(405, format!("Method not allowed: {method}")),
+    };
+    log::info!("{method} {target} {}", response.status);
+    write_response(stream, &response, method == "HEAD")
+}
+
+fn write_response(mut stream: &TcpStream, response: &Response, head: bool) -> Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\n\
+         Content-Type: {}\r\n\
+         Content-Length: {}\r\n\
+         Access-Control-Allow-Origin: *\r\n\
+         Connection: close\r\n\r\n",
+        response.status,
+        reason(response.status),
+        response.content_type,
+        response.body.len(),
+    )?;
+    if !head {
+        stream.write_all(&response.body)?;
+    }
+    stream.flush()?;
+    Ok(())
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Internal Server Error",
+    }
+}
+
+/// Split the path of a request target (ignoring any query) into percent-decoded segments,
+/// leaving out empty ones. Returns None if it isn't a valid path.
+fn parse_path(target: &str) -> Option<Vec<String>> {
+    let path = target.split(['?', '#']).next()?;
+    if !path.starts_with('/') {
+        return None;
+    }
+    path.split('/').filter(|s| !s.is_empty()).map(percent_decode).collect()
+}
+
+fn percent_decode(s: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut iter = s.bytes();
+    while let Some(b) = iter.next() {
+        if b == b'%' {
+            let hex = [iter.next()?, iter.next()?];
+            bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            bytes.push(b);
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_parse_path() {
+        assert_eq!(parse_path("/"), Some(vec![]));
+        assert_eq!(
+            parse_path("/logs/my%20task//latest?tail=10"),
+            Some(vec![
+                "logs".to_owned(),
+                "my task".to_owned(),
+                "latest".to_owned()
+            ])
+        );
+        assert_eq!(
+            parse_path("/status/a%2Fb"),
+            Some(vec!["status".to_owned(), "a/b".to_owned()])
+        );
+        assert_eq!(parse_path("status"), None);
+        assert_eq!(parse_path("/bad%2"), None);
+        assert_eq!(parse_path("/bad%zz"), None);
+    }
+
+    #[test]
+    fn test_handle_connection() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let request = |req: &'static str| {
+            std::thread::spawn(move || -> std::io::Result<String> {
+                let mut stream = TcpStream::connect(addr)?;
+                stream.write_all(req.as_bytes())?;
+                let mut response = String::new();
+                stream.read_to_string(&mut response)?;
+                Ok(response)
+            })
+        };
+        let mut handle = |path: &[String]| match path {
+            [s] if s == "hello" => Response::text(b"hi".to_vec()),
+            _ => Response::error(404, "nope"),
+        };
+
+        let client = request("GET /hello HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        handle_connection(&listener.accept()?.0, &mut handle)?;
+        let response = client.join().unwrap()?;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Content-Length: 2\r\n"));
+        assert!(response.ends_with("\r\n\r\nhi"));
+
+        let client = request("GET /other HTTP/1.1\r\n\r\n");
+        handle_connection(&listener.accept()?.0, &mut handle)?;
+        let response = client.join().unwrap()?;
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(response.contains("\"error\": \"nope\""));
+
+        let client = request("POST /hello HTTP/1.1\r\n\r\n");
+        handle_connection(&listener.accept()?.0, &mut handle)?;
+        assert!(client.join().unwrap()?.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+        Ok(())
+    }
+}
@@ -1,54 +1,354 @@
-use workflow::{BRANCH_DELIM, BRANCH_KV_DELIM};
+use intern::InternStr;
+use workflow::{cross_product, BranchSpec, Workflow, BRANCH_DELIM, BRANCH_KV_DELIM};
 
-use crate::args::Args;
+use crate::args::{
+    Args, Command, Compat, ExportFormat, HistoryKind, ListKind, LogFormat, OutputFormat, VizFormat,
+};
+use crate::fs::CancelRequest;
+use crate::project::{Notifications, ProjectSettings};
 use anyhow::Result;
+use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Config file looked for in the current dir and its parents, when none is given.
+const DEFAULT_CONFIG: &str = "rebuild.hr";
+/// Extension of other config files looked for, if there's no `DEFAULT_CONFIG`.
+const CONFIG_EXT: &str = "tconf";
+const DEFAULT_OUTPUT: &str = "output";
+/// Shorter alternatives to `HERON_REBUILD_CONFIG` and `HERON_REBUILD_OUTPUT`,
+/// which take precedence over these.
+const CONFIG_ENV: &str = "HR_CONFIG";
+const OUTPUT_ENV: &str = "HR_OUTPUT";
+/// Separates branches given in a single '-b' flag, e.g. "Os.mac,Os.win".
+const BRANCH_LIST_DELIM: char = ',';
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     #[error("no plan specified")]
     NoPlanSpecified,
-    #[error("invalid branch flag '{0}' (should be formatted 'Key1.Val1[+Key2.Val2...]')")]
+    #[error("invalid branch flag '{0}' (should be formatted 'Key1.Val1[+Key2.Val2...][,...]')")]
     InvalidBranchFlag(String),
+    #[error("invalid --task-regex '{0}'")]
+    InvalidTaskRegex(String, #[source] regex::Error),
+    #[error("invalid define flag '{0}' (should be formatted 'key=value')")]
+    InvalidDefineFlag(String),
     #[error("Invalid config path has no parent (should not happen)")]
     ConfigHasNoParent,
     #[error("Config file '{0}' does not exist")]
     ConfigDoesNotExist(String),
+    #[error("No config file given, and no rebuild.hr or *.tconf file in {0} or its parent dirs (create one with 'hr init')")]
+    NoConfigFound(String),
+    #[error("Found more than one config file in {0} ({1}); choose one with -c")]
+    AmbiguousConfig(String, String),
+    #[error("'hr {0}' works on a single realization, so it needs a single branch")]
+    MultiBranch(&'static str),
+    #[error("'hr {0}' works on the task given to it, so it can't be given a plan with -p")]
+    PlanWithSingleTask(&'static str),
+    #[error("'-x' invalidates instead of running, so it can't be given with 'hr {0}'")]
+    InvalidateAndRun(&'static str),
+    #[error("--downstream, --older-than, --failed-only, and --branch-glob only apply to 'hr invalidate' (or '-x')")]
+    InvalidateOptionsWithoutInvalidate,
+    #[error("--interactive and --non-interactive can't be given together")]
+    InteractiveAndNonInteractive,
+    #[error("--branch-glob can't be empty")]
+    EmptyBranchGlob,
+}
+
+/// What to show with 'hr history'.
+#[derive(Debug, Clone, Copy)]
+pub struct HistoryQuery {
+    pub kind: HistoryKind,
+    /// only look at this many of the most recent runs
+    pub last: usize,
+}
+
+/// What to do with 'hr fmt'.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FmtAction {
+    /// Rewrite config files that aren't formatted.
+    Write,
+    /// Only list config files that aren't formatted.
+    Check,
+}
+
+/// Which log to print with 'hr logs'.
+#[derive(Debug, Clone, Copy)]
+pub struct LogsQuery {
+    /// print stderr.txt instead of stdout.txt
+    pub stderr: bool,
+    /// keep printing until the task finishes
+    pub follow: bool,
 }
 
 /// Representation of '-b' and '-B' arg values
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum ArgsBranch {
     /// When no branch is specified, we apply operations to all branches of a task.
     Empty,
     /// If baseline is explicitly specified, we only apply operations to the baseline.
     Baseline,
-    /// If any branches are specified, we apply operations to all matching branches.
-    Specified(Vec<(String, String)>),
+    /// If any branches are specified, we apply operations to all matching branches:
+    /// the cross product of the values given for each branchpoint.
+    Specified(Vec<(String, Vec<String>)>),
+}
+
+impl ArgsBranch {
+    /// The branch as it would be written on the command line, e.g. "K1.V1+K2.V2",
+    /// or "K1.V1,K1.V2+K2.V3" for more than one value of K1 (empty if no branch was given).
+    pub fn describe(&self) -> String {
+        match self {
+            Self::Empty => String::new(),
+            Self::Baseline => "Baseline.baseline".to_owned(),
+            Self::Specified(branches) => branches
+                .iter()
+                .map(|(k, vs)| {
+                    vs.iter()
+                        .map(|v| format!("{k}{BRANCH_KV_DELIM}{v}"))
+                        .collect::<Vec<_>>()
+                        .join(&BRANCH_LIST_DELIM.to_string())
+                })
+                .collect::<Vec<_>>()
+                .join(&BRANCH_DELIM.to_string()),
+        }
+    }
+
+    /// The branches to target: the cross product of the values given for each branchpoint,
+    /// or just the baseline if none were given.
+    pub fn intern(&self, wf: &mut Workflow) -> Result<Vec<BranchSpec>> {
+        let Self::Specified(branches) = self else {
+            return Ok(vec![BranchSpec::default()]);
+        };
+        let mut choices = Vec::with_capacity(branches.len());
+        for (k, vs) in branches {
+            let k = wf.strings.branchpoints.intern(k)?;
+            let vs = vs.iter().map(|v| wf.strings.idents.intern(v)).collect::<Result<_, _>>()?;
+            choices.push((k, vs));
+        }
+        Ok(cross_product(&choices))
+    }
+
+    /// true if more than one branch was given, i.e. some branchpoint has several values.
+    pub fn is_multiple(&self) -> bool {
+        matches!(self, Self::Specified(branches) if branches.iter().any(|(_, vs)| vs.len() > 1))
+    }
+}
+
+/// How to handle a point where the user would be asked to confirm an action.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfirmPolicy {
+    /// Ask the user.
+    Prompt,
+    /// Proceed without asking.
+    Yes,
+    /// Refuse without asking.
+    No,
+}
+
+impl ConfirmPolicy {
+    fn from_flags(yes: bool, no: bool) -> Self {
+        if no {
+            Self::No
+        } else if yes {
+            Self::Yes
+        } else {
+            Self::Prompt
+        }
+    }
+}
+
+/// Confirmation policy for each class of action that requires confirmation.
+#[derive(Debug, Clone, Copy)]
+pub struct Confirmations {
+    /// Creating task dirs and running tasks.
+    pub run: ConfirmPolicy,
+    /// Deleting incomplete realizations before a run.
+    pub delete: ConfirmPolicy,
+    /// Invalidating tasks with '-x'.
+    pub invalidate: ConfirmPolicy,
+}
+
+/// What to do with the trash, for 'hr trash'.
+#[derive(Debug, Clone)]
+pub enum TrashAction {
+    /// List trash batches and their contents.
+    List,
+    /// Permanently delete everything in the trash.
+    Empty,
+    /// Restore the given batch, or the most recent one.
+    Restore(Option<String>),
+}
+
+/// Which paths of a realization to print, for 'hr which' and 'hr outputs'.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Locate {
+    /// The realization dir.
+    Realization,
+    /// The paths of its output files, or just of the given output.
+    Outputs(Option<String>),
+}
+
+impl Locate {
+    /// Name of the command that prints these paths.
+    pub fn command(&self) -> &'static str {
+        match self {
+            Self::Realization => "which",
+            Self::Outputs(_) => "outputs",
+        }
+    }
+}
+
+/// Filters on which realizations '-x' applies to.
+#[derive(Debug, Clone, Default)]
+pub struct InvalidateFilter {
+    /// Only realizations last run at least this long ago.
+    pub older_than: Option<Duration>,
+    /// Only realizations that failed or never finished.
+    pub failed_only: bool,
+    /// Only realizations whose branch string (or one of its 'Key.value' parts) matches this glob.
+    pub branch_glob: Option<String>,
+}
+
+impl InvalidateFilter {
+    /// true if any filter was specified.
+    pub fn is_active(&self) -> bool {
+        self.older_than.is_some() || self.failed_only || self.branch_glob.is_some()
+    }
+}
+
+/// What the app does: running the target by default, or one of the other commands.
+#[derive(Debug, Clone)]
+pub enum Mode {
+    /// Run the target.
+    Run,
+    /// Run the target, and again whenever the config changes.
+    Watch,
+    /// Invalidate the target's realizations, for 'hr invalidate' (or '-x').
+    Invalidate,
+    /// Open a shell in a task's realization.
+    Shell,
+    /// Print the paths of a task's realization, for 'hr which' and 'hr outputs'.
+    Locate(Locate),
+    Logs(LogsQuery),
+    CatScript,
+    Affected,
+    /// Explain the place of this task in the traversal.
+    Explain(String),
+    Cancel(CancelRequest),
+    Gc,
+    Trash(TrashAction),
+    RepairBranchpoints,
+    Check,
+    Fmt(FmtAction),
+    /// Compare the first config file to the second.
+    Diff,
+    Status,
+    /// 'hr stats', and whether to report memory usage too.
+    Stats(bool),
+    List(ListKind),
+    History(HistoryQuery),
+    Viz(VizFormat),
+    /// Serve status on this address.
+    Serve(String),
+    /// Export a build file; formats that need prepared tasks are prepared like a run,
+    /// but exported instead of run, while the others export the abstract workflow.
+    Export(ExportFormat),
+}
+
+impl Mode {
+    /// true if the target is prepared to run (even if it's exported instead of running).
+    pub fn runs(&self) -> bool {
+        match self {
+            Self::Run | Self::Watch => true,
+            Self::Export(format) => format.is_prepared(),
+            _ => false,
+        }
+    }
+
+    /// Name of the command, if it works on a single realization of a single task (e.g. 'shell').
+    pub fn single_realization(&self) -> Option<&'static str> {
+        match self {
+            Self::Shell => Some("shell"),
+            Self::Logs(_) => Some("logs"),
+            Self::CatScript => Some("cat-script"),
+            Self::Locate(locate) => Some(locate.command()),
+            _ => None,
+        }
+    }
 }
 
 /// Settings are like Args, except all the logic has
 /// been applied so e.g. defaults are added in.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Settings {
-    pub config: PathBuf,
+    /// config files, in the order they're layered:
+    pub configs: Vec<PathBuf>,
+    /// global config values set on the command line, in order:
+    pub defines: Vec<(String, String)>,
+    /// other workflow tool whose syntax config files may use:
+    pub compat: Option<Compat>,
     pub output: PathBuf,
-    pub yes: bool,
+    pub cache: Option<PathBuf>,
+    pub remote_cache: Option<String>,
+    pub confirm: Confirmations,
+    /// fail instead of prompting, and when there's nothing to run:
+    pub non_interactive: bool,
     pub verbose: u8,
+    pub output_format: OutputFormat,
+    pub log_format: LogFormat,
+    pub junit: Option<PathBuf>,
+    pub metrics_file: Option<PathBuf>,
+    pub otlp_endpoint: Option<String>,
     pub branches: ArgsBranch,
+    /// task names, and globs matching task names, until the config is loaded:
     pub tasks: Vec<String>,
+    pub task_regexes: Vec<regex::Regex>,
     pub dry_run: bool,
+    pub atomic: bool,
+    pub short_names: bool,
+    pub require_clean_modules: bool,
+    pub strict_vars: bool,
+    pub warnings: bool,
+    pub explain_dedup: bool,
+    pub force_unlock: bool,
+    pub idle_timeout: Option<u64>,
+    pub kill_idle: bool,
+    /// most tasks to run at once:
+    pub jobs: usize,
+    /// command to run when the run finishes:
+    pub notify: Option<Notifications>,
+    /// only run this task of the target (and its antecedents):
+    pub until: Option<String>,
+    /// assume this task of the target (and its antecedents) has completed:
+    pub from: Option<String>,
+    /// target the tasks producing these outputs, given by name or path:
+    pub produces: Vec<String>,
+    /// ask about each realization to delete and each task to run, instead of all at once:
+    pub interactive: bool,
+    /// list every realization before running, however many there are:
+    pub full: bool,
+    /// how many run logs to keep in the output dir (0 for none):
+    pub keep_run_logs: usize,
+    /// reuse the workflow and traversal of the last run, if nothing they depend on has changed:
+    pub traversal_cache: bool,
+    pub downstream: bool,
+    pub filter: InvalidateFilter,
 
-    pub invalidate: bool,
-    pub run: bool,
+    pub mode: Mode,
 
-    pub plan: Option<String>,
+    pub plans: Vec<String>,
 }
 
 impl Settings {
-    /// Get canonicalized parent dir of config file:
-    pub fn config_parent_dir(&self) -> Result<&Path, Error> {
-        let parent_dir = self.config.parent().ok_or(Error::ConfigHasNoParent)?;
+    /// true if any target task was given as a glob or regex, to be matched against the
+    /// tasks defined in the config file.
+    pub fn has_task_patterns(&self) -> bool {
+        !self.task_regexes.is_empty() || self.tasks.iter().any(|task| is_glob(task))
+    }
+
+    /// Get canonicalized parent dir of a config file:
+    pub fn config_parent_dir(config: &Path) -> Result<&Path, Error> {
+        let parent_dir = config.parent().ok_or(Error::ConfigHasNoParent)?;
         Ok(parent_dir)
     }
 }
@@ -62,45 +362,339 @@ impl TryFrom<Args> for Settings {
         } else if args.branch.is_empty() {
             branches = ArgsBranch::Empty;
         } else {
-            let mut inner = Vec::with_capacity(8);
-            for branch_arg in args.branch {
-                for branch_kv in branch_arg.split(BRANCH_DELIM) {
+            // each value given for a branchpoint is added to its list, so that e.g.
+            // '-b Os.mac,Os.win -b Profile.debug' targets both Os branches with Profile.debug:
+            let mut inner: Vec<(String, Vec<String>)> = Vec::with_capacity(8);
+            for branch_arg in &args.branch {
+                for branch_kv in branch_arg.split([BRANCH_LIST_DELIM, BRANCH_DELIM]) {
                     let (k, v) = branch_kv
                         .split_once(BRANCH_KV_DELIM)
                         .ok_or_else(|| Error::InvalidBranchFlag(branch_arg.to_owned()))?;
-                    inner.push((k.to_owned(), v.to_owned()));
+                    match inner.iter_mut().find(|(name, _)| name == k) {
+                        Some((_, vs)) if vs.iter().any(|existing| existing == v) => (),
+                        Some((_, vs)) => vs.push(v.to_owned()),
+                        None => inner.push((k.to_owned(), vec![v.to_owned()])),
+                    }
                 }
             }
             branches = ArgsBranch::Specified(inner);
         }
 
-        // figure out which actions to take.
-        // for now, we invalidate if invalidate is specified, run otherwise.
-        // in the future we will allow to do both or neither w/ different combinations.
+        let mut defines = Vec::with_capacity(args.defines.len());
+        for define in &args.defines {
+            match define.split_once('=') {
+                Some((k, v)) if !k.is_empty() => defines.push((k.to_owned(), v.to_owned())),
+                _ => return Err(Error::InvalidDefineFlag(define.to_owned()).into()),
+            }
+        }
+
+        // figure out which command to run; running the target is the default:
+        let mut tasks = args.tasks;
+        let mut diff = None;
+        let mut run_args = args.run_args;
+        let mut invalidate_args = args.invalidate_args;
         let invalidate = args.invalidate;
-        let run = !args.invalidate;
+        let mode = match args.command {
+            Some(Command::Run(_)) if invalidate => {
+                return Err(Error::InvalidateAndRun("run").into())
+            }
+            Some(Command::Run(sub)) => {
+                run_args.merge(sub);
+                Mode::Run
+            }
+            Some(Command::Watch(_)) if invalidate => {
+                return Err(Error::InvalidateAndRun("watch").into());
+            }
+            Some(Command::Watch(sub)) => {
+                run_args.merge(sub);
+                Mode::Watch
+            }
+            Some(Command::Invalidate(sub)) => {
+                invalidate_args.merge(sub);
+                Mode::Invalidate
+            }
+            Some(Command::Shell { task }) => {
+                tasks = vec![task];
+                Mode::Shell
+            }
+            Some(Command::Which { task }) => {
+                tasks = vec![task];
+                Mode::Locate(Locate::Realization)
+            }
+            Some(Command::Outputs { task, name }) => {
+                tasks = vec![task];
+                Mode::Locate(Locate::Outputs(name))
+            }
+            Some(Command::Logs {
+                task,
+                stderr,
+                follow,
+            }) => {
+                tasks = vec![task];
+                Mode::Logs(LogsQuery { stderr, follow })
+            }
+            Some(Command::CatScript { task }) => {
+                tasks = vec![task];
+                Mode::CatScript
+            }
+            Some(Command::Affected { task }) => {
+                tasks = vec![task];
+                Mode::Affected
+            }
+            Some(Command::Explain { task }) => Mode::Explain(task),
+            Some(Command::Cancel { tasks, kill }) => Mode::Cancel(CancelRequest { tasks, kill }),
+            Some(Command::Gc) => Mode::Gc,
+            Some(Command::RepairBranchpoints) => Mode::RepairBranchpoints,
+            Some(Command::Check) => Mode::Check,
+            Some(Command::Diff { old, new }) => {
+                diff = Some(vec![old, new]);
+                Mode::Diff
+            }
+            Some(Command::Fmt { check }) => Mode::Fmt(if check {
+                FmtAction::Check
+            } else {
+                FmtAction::Write
+            }),
+            Some(Command::Status) => Mode::Status,
+            Some(Command::Stats { memory }) => Mode::Stats(memory),
+            Some(Command::List { what }) => Mode::List(what),
+            Some(Command::History { what, last }) => {
+                Mode::History(HistoryQuery { kind: what, last })
+            }
+            Some(Command::Viz { format }) => Mode::Viz(format),
+            Some(Command::Export { format }) => Mode::Export(format),
+            Some(Command::Serve { addr }) => Mode::Serve(addr),
+            Some(Command::Trash { empty, restore }) => Mode::Trash(match (empty, restore) {
+                (true, _) => TrashAction::Empty,
+                (false, Some(batch)) => TrashAction::Restore(batch),
+                (false, None) => TrashAction::List,
+            }),
+            Some(Command::Init { .. }) => unreachable!("'hr init' doesn't need settings"),
+            None if invalidate => Mode::Invalidate,
+            None => Mode::Run,
+        };
+        if let Some(cmd) = mode.single_realization().filter(|_| branches.is_multiple()) {
+            return Err(Error::MultiBranch(cmd).into());
+        }
+        // the named task is the target, so a plan would only replace it:
+        if let Some(cmd) = mode.single_realization().filter(|_| !args.plans.is_empty()) {
+            return Err(Error::PlanWithSingleTask(cmd).into());
+        }
+        if !matches!(mode, Mode::Invalidate) && invalidate_args.is_given() {
+            return Err(Error::InvalidateOptionsWithoutInvalidate.into());
+        }
+        if args.non_interactive && run_args.interactive {
+            return Err(Error::InteractiveAndNonInteractive.into());
+        }
 
-        let mut config = PathBuf::from(&args.config);
-        if config.exists() {
-            config = config.canonicalize()?;
-        } else {
-            return Err(Error::ConfigDoesNotExist(args.config.to_owned()).into());
+        // args and environment variables override a project settings file:
+        let cwd = std::env::current_dir()?;
+        let project = ProjectSettings::discover(&cwd)?.unwrap_or_default();
+
+        // without -c, use $HR_CONFIG, the project's config, or look for a config file,
+        // with the output dir next to it:
+        let mut config_args = diff.unwrap_or(args.configs);
+        let mut default_output = PathBuf::from(DEFAULT_OUTPUT);
+        if config_args.is_empty() {
+            if let Some(config) = env_var(CONFIG_ENV) {
+                config_args.push(config);
+            } else if !project.configs.is_empty() {
+                match project.path.parent() {
+                    Some(dir) if dir != cwd => default_output = dir.join(DEFAULT_OUTPUT),
+                    _ => (),
+                }
+                for config in &project.configs {
+                    config_args.push(config.to_str().ok_or(util::PathEncodingError)?.to_owned());
+                }
+            } else {
+                let config = discover_config(&cwd)?
+                    .ok_or_else(|| Error::NoConfigFound(cwd.display().to_string()))?;
+                match config.parent() {
+                    Some(dir) if dir != cwd => default_output = dir.join(DEFAULT_OUTPUT),
+                    _ => (),
+                }
+                config_args.push(config.to_str().ok_or(util::PathEncodingError)?.to_owned());
+            }
         }
-        let output = PathBuf::from(&args.output);
+
+        let mut configs = Vec::with_capacity(config_args.len());
+        for config_arg in &config_args {
+            let config = PathBuf::from(config_arg);
+            if config.exists() {
+                configs.push(config.canonicalize()?);
+            } else {
+                return Err(Error::ConfigDoesNotExist(config_arg.to_owned()).into());
+            }
+        }
+        let output = match args.output.or_else(|| env_var(OUTPUT_ENV)) {
+            Some(output) => PathBuf::from(output),
+            None => project.output.unwrap_or(default_output),
+        };
+
+        // the project's plans are only a default target for runs:
+        let mut plans = args.plans;
+        let task_regexes = args
+            .task_regexes
+            .iter()
+            .map(|re| regex::Regex::new(re).map_err(|e| Error::InvalidTaskRegex(re.clone(), e)))
+            .collect::<Result<Vec<_>, _>>()?;
+        let has_targets =
+            !tasks.is_empty() || !task_regexes.is_empty() || !run_args.produces.is_empty();
+        if plans.is_empty() && !has_targets && mode.runs() {
+            plans = project.plans;
+        }
+        let verbose = match args.verbose {
+            0 => project.verbose.unwrap_or(0),
+            verbose => verbose,
+        };
+        let jobs = run_args.jobs.map(NonZeroUsize::get).or(project.jobs).unwrap_or(1);
+        let notify = match run_args.notify {
+            Some(command) => Some(Notifications {
+                command,
+                on: run_args.notify_on.unwrap_or_default(),
+            }),
+            None => project.notifications,
+        };
+
+        // '-y' confirms everything, but '--no-delete' always wins:
+        let confirm = Confirmations {
+            run: ConfirmPolicy::from_flags(args.yes || args.yes_run, false),
+            delete: ConfirmPolicy::from_flags(args.yes || args.yes_delete, args.no_delete),
+            invalidate: ConfirmPolicy::from_flags(args.yes || args.yes_invalidate, false),
+        };
+
+        // an empty glob would match nothing, so it's most likely a mistake:
+        if invalidate_args.branch_glob.as_deref() == Some("") {
+            return Err(Error::EmptyBranchGlob.into());
+        }
+        let filter = InvalidateFilter {
+            older_than: invalidate_args
+                .older_than
+                .as_deref()
+                .map(util::parse_duration)
+                .transpose()?,
+            failed_only: invalidate_args.failed_only,
+            branch_glob: invalidate_args.branch_glob,
+        };
 
         Ok(Self {
-            config,
+            configs,
+            defines,
+            compat: args.compat,
             output,
-            yes: args.yes,
-            verbose: args.verbose,
+            cache: args.cache.map(PathBuf::from),
+            remote_cache: args.remote_cache,
+            confirm,
+            non_interactive: args.non_interactive,
+            verbose,
+            output_format: run_args.output_format.unwrap_or(OutputFormat::Text),
+            log_format: args.log_format.unwrap_or(LogFormat::Text),
+            junit: run_args.junit.map(PathBuf::from),
+            metrics_file: run_args.metrics_file.map(PathBuf::from),
+            otlp_endpoint: run_args.otlp_endpoint,
             branches,
-            tasks: args.tasks,
+            tasks,
+            task_regexes,
             dry_run: args.dry_run,
+            // an exported build runs tasks in their realization dirs:
+            atomic: !run_args.in_place && !matches!(mode, Mode::Export(_)),
+            short_names: args.short_names,
+            require_clean_modules: args.require_clean_modules,
+            strict_vars: args.strict_vars,
+            warnings: run_args.warnings,
+            explain_dedup: run_args.explain_dedup,
+            force_unlock: args.force_unlock,
+            idle_timeout: run_args.idle_timeout,
+            kill_idle: run_args.kill_idle,
+            jobs,
+            notify,
+            until: run_args.until,
+            from: run_args.from,
+            produces: run_args.produces,
+            interactive: run_args.interactive,
+            full: run_args.full,
+            keep_run_logs: run_args.keep_run_logs.unwrap_or(crate::run_log::DEFAULT_KEEP),
+            traversal_cache: !run_args.no_traversal_cache,
+            downstream: invalidate_args.downstream,
+            filter,
 
-            invalidate,
-            run,
+            mode,
 
-            plan: args.plan,
+            plans,
         })
     }
 }
+
+/// true if `task` is a glob rather than a task name.
+pub fn is_glob(task: &str) -> bool {
+    task.contains(['*', '?'])
+}
+
+/// Value of an environment variable, if it's set and not empty.
+fn env_var(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+/// Find the config file to use when none is given: `rebuild.hr`, or else the only `*.tconf` file,
+/// in `start` or the nearest of its ancestors that has either.
+fn discover_config(start: &Path) -> Result<Option<PathBuf>, Error> {
+    for dir in start.ancestors() {
+        let default = dir.join(DEFAULT_CONFIG);
+        if default.is_file() {
+            return Ok(Some(default));
+        }
+        // dirs we can't read are skipped:
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            continue;
+        };
+        let mut found: Vec<PathBuf> = entries
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == CONFIG_EXT) && path.is_file())
+            .collect();
+        match found.len() {
+            0 => (),
+            1 => return Ok(found.pop()),
+            _ => {
+                found.sort_unstable();
+                let names: Vec<_> =
+                    found.iter().filter_map(|path| path.file_name()?.to_str()).collect();
+                return Err(Error::AmbiguousConfig(
+                    dir.display().to_string(),
+                    names.join(", "),
+                ));
+            }
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_discover_config() -> Result<()> {
+        let dir = tempdir()?;
+        let root = dir.path().canonicalize()?;
+        let sub = root.join("a/b");
+        std::fs::create_dir_all(&sub)?;
+
+        std::fs::write(root.join("pipeline.tconf"), "")?;
+        assert_eq!(discover_config(&sub)?, Some(root.join("pipeline.tconf")));
+
+        // the nearest dir wins, and rebuild.hr wins over *.tconf:
+        std::fs::write(root.join("a/other.tconf"), "")?;
+        assert_eq!(discover_config(&sub)?, Some(root.join("a/other.tconf")));
+        std::fs::write(root.join("a/rebuild.hr"), "")?;
+        std::fs::write(root.join("a/third.tconf"), "")?;
+        assert_eq!(discover_config(&sub)?, Some(root.join("a/rebuild.hr")));
+
+        std::fs::remove_file(root.join("a/rebuild.hr"))?;
+        let e = discover_config(&sub).unwrap_err();
+        assert!(e.to_string().contains("(other.tconf, third.tconf)"));
+
+        dir.close()?;
+        Ok(())
+    }
+}
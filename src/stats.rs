@@ -0,0 +1,79 @@
+use std::io::Write;
+
+use anyhow::Result;
+
+use traverse::TraversalStats;
+use util::{format_size, MemoryUsage};
+
+/// Write the size of a traversal and how long creating it took to `out`, for 'hr stats',
+/// followed by the memory used by each data structure in `memory`, grouped into sections.
+pub fn write_stats(
+    stats: &TraversalStats,
+    memory: &[(&str, Vec<MemoryUsage>)],
+    out: &mut impl Write,
+) -> Result<()> {
+    writeln!(out, "{stats}")?;
+    if memory.is_empty() {
+        return Ok(());
+    }
+    writeln!(out, "\n{:<24} {:>8} {:>10}", "memory:", "items", "bytes")?;
+    let mut total = 0;
+    for (section, usages) in memory {
+        writeln!(out, "{section}:")?;
+        for usage in usages {
+            writeln!(
+                out,
+                "  {:<22} {:>8} {:>10}",
+                usage.name,
+                usage.len,
+                format_size(usage.bytes as u64)
+            )?;
+            total += usage.bytes;
+        }
+    }
+    writeln!(out, "{:<33} {:>10}", "total:", format_size(total as u64))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_write_stats() -> Result<()> {
+        let stats = TraversalStats {
+            nodes: 4,
+            realizations: 3,
+            roots: 1,
+            depth: 2,
+            mask_bits: 8,
+            index_bits: 16,
+            ..Default::default()
+        };
+        let usage = |name, len, bytes| MemoryUsage { name, len, bytes };
+        let memory = [
+            (
+                "strings",
+                vec![usage("tasks", 2, 96), usage("idents", 12, 1200)],
+            ),
+            ("traversal", vec![usage("nodes", 4, 2048)]),
+        ];
+        let mut out = Vec::new();
+        write_stats(&stats, &memory, &mut out)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "Traversal has 4 nodes (3 after deduplication), 1 roots, and 2 levels; \
+             8-bit branch masks, 16-bit node indices.\n\
+             BFS took 0.00s, cleanup 0.00s, sort 0.00s.\n\
+             \n\
+             memory:                     items      bytes\n\
+             strings:\n\
+             \x20 tasks                         2       96 B\n\
+             \x20 idents                       12    1.2 KiB\n\
+             traversal:\n\
+             \x20 nodes                         4    2.0 KiB\n\
+             total:                               3.3 KiB\n"
+        );
+        Ok(())
+    }
+}
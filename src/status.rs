@@ -0,0 +1,158 @@
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{Duration, UNIX_EPOCH};
+
+use anyhow::Result;
+use serde::Serialize;
+
+use util::format_size;
+use workflow::{RealTaskKey, Workflow};
+
+use crate::fs::Fs;
+use crate::prep::{RealizationState, TaskInfo};
+
+/// One row of the 'hr status' table.
+#[derive(Debug, Serialize)]
+struct StatusRow {
+    /// task and branch, e.g. 'task[Profile.debug]'
+    realization: String,
+    /// state of the realization, as described by `describe_state`
+    state: &'static str,
+    /// disk usage in bytes, if the realization dir exists
+    size: Option<u64>,
+    /// from `task_info.json`, in seconds since the unix epoch
+    started: Option<u64>,
+    finished: Option<u64>,
+}
+
+/// States a realization can be in, in the order they're counted.
+const STATES: [&str; 6] = [
+    "complete",
+    "failed",
+    "incomplete",
+    "missing",
+    "stale-hash",
+    "stale-input",
+];
+
+/// Write a table of the state, size, and start and finish times of each realization
+/// in `states` (as returned by `TraversalResolver::resolve_to_states`) to `out`,
+/// followed by the number of realizations in each state.
+pub fn write_status(
+    states: &[(RealTaskKey, PathBuf, RealizationState)],
+    wf: &Workflow,
+    fs: &Fs,
+    out: &mut impl Write,
+) -> Result<()> {
+    let rows = status_rows(states, wf, fs)?;
+
+    writeln!(
+        out,
+        "{:<11}  {:>10}  {:<16}  {:<16}  REALIZATION",
+        "STATE", "SIZE", "STARTED", "FINISHED"
+    )?;
+    for row in &rows {
+        writeln!(
+            out,
+            "{:<11}  {:>10}  {:<16}  {:<16}  {}",
+            row.state,
+            row.size.map_or_else(|| "-".to_owned(), format_size),
+            format_time(row.started),
+            format_time(row.finished),
+            row.realization,
+        )?;
+    }
+
+    let mut counts = Vec::new();
+    for (state, count) in count_states(&rows) {
+        counts.push(format!("{count} {state}"));
+    }
+    writeln!(out, "\n{} realizations: {}", rows.len(), counts.join(", "))?;
+    Ok(())
+}
+
+/// The same information as `write_status`, for 'hr serve': the `target` it's for,
+/// the realizations (sizes in bytes, times in seconds since the unix epoch, or null),
+/// and counts by state.
+#[derive(Debug, Serialize)]
+pub struct StatusReport {
+    target: String,
+    realizations: Vec<StatusRow>,
+    #[serde(with = "util::ordered_map")]
+    counts: Vec<(&'static str, usize)>,
+}
+
+impl StatusReport {
+    pub fn new(
+        target: String,
+        states: &[(RealTaskKey, PathBuf, RealizationState)],
+        wf: &Workflow,
+        fs: &Fs,
+    ) -> Result<Self> {
+        let realizations = status_rows(states, wf, fs)?;
+        let counts = count_states(&realizations).collect();
+        Ok(Self {
+            target,
+            realizations,
+            counts,
+        })
+    }
+}
+
+fn status_rows(
+    states: &[(RealTaskKey, PathBuf, RealizationState)],
+    wf: &Workflow,
+    fs: &Fs,
+) -> Result<Vec<StatusRow>> {
+    let mut rows = Vec::with_capacity(states.len());
+    for (key, dir, state) in states {
+        // a task that didn't finish leaves its scratch dir instead, unless it ran in place:
+        let dir = if fs.exists(dir) {
+            Some(dir.clone())
+        } else {
+            fs.newest_scratch_dir(dir)?
+        };
+        let info = dir.as_ref().and_then(|dir| TaskInfo::read(dir, fs).ok());
+        rows.push(StatusRow {
+            state: describe_state(*state, info.as_ref()),
+            realization: wf.strings.get_real_task_str(key)?.to_owned(),
+            size: match &dir {
+                Some(dir) => Some(fs.disk_usage(dir)?),
+                None => None,
+            },
+            started: info.as_ref().and_then(|info| info.started),
+            finished: info.as_ref().and_then(|info| info.finished),
+        });
+    }
+    Ok(rows)
+}
+
+/// Number of `rows` in each state that at least one of them is in.
+fn count_states(rows: &[StatusRow]) -> impl Iterator<Item = (&'static str, usize)> + '_ {
+    STATES.into_iter().filter_map(|state| {
+        let count = rows.iter().filter(|row| row.state == state).count();
+        (count > 0).then_some((state, count))
+    })
+}
+
+/// Like `RealizationState::describe`, except that realizations that were prepared
+/// but never finished running (e.g. b/c an earlier task failed) are "incomplete":
+/// only a realization whose `task_info.json` says it didn't succeed has "failed".
+/// A missing realization with a scratch dir is described by its scratch dir's `task_info.json`.
+fn describe_state(state: RealizationState, info: Option<&TaskInfo>) -> &'static str {
+    let failed = info.and_then(|info| info.succeeded) == Some(false);
+    match state {
+        RealizationState::Failed | RealizationState::Missing if failed => "failed",
+        RealizationState::Failed => "incomplete",
+        RealizationState::Missing if info.is_some() => "incomplete",
+        state => state.describe(),
+    }
+}
+
+/// Format seconds since the unix epoch as a UTC timestamp, or "-" if there isn't one.
+fn format_time(secs: Option<u64>) -> String {
+    secs.map_or_else(
+        || "-".to_owned(),
+        |secs| util::format_utc_timestamp(UNIX_EPOCH + Duration::from_secs(secs)),
+    )
+}
@@ -1,20 +1,82 @@
 use std::cell::RefCell;
+use std::io::{IsTerminal, Write};
+use std::process::{Command, Stdio};
+use std::rc::Rc;
+use std::time::Duration;
 
 use anyhow::Result;
 use colored::Colorize;
 
 use util::Timer;
 
-use crate::settings::Settings;
+use crate::run_log::{mirror_eprint, mirror_eprintln};
+use crate::settings::{ConfirmPolicy, Confirmations, Settings};
+
+/// Classes of action that require user confirmation.
+#[derive(Debug, Clone, Copy)]
+pub enum Confirm {
+    Run,
+    Delete,
+    Invalidate,
+}
+
+impl Confirm {
+    /// Flags that confirm (or refuse) this class of action in advance.
+    fn flags(self) -> &'static str {
+        match self {
+            Self::Run => "-y or --yes-run",
+            Self::Delete => "-y, --yes-delete, or --no-delete",
+            Self::Invalidate => "-y or --yes-invalidate",
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Would ask \"{0}\", but --non-interactive was given; answer in advance with {1}")]
+    ConfirmationRequired(String, &'static str),
+}
+
+/// Answers to the per-item prompts of '--interactive'.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Select {
+    /// Yes to this item.
+    Yes,
+    /// No to this item.
+    No,
+    /// Yes to this item and every remaining item of the same kind.
+    All,
+    /// No to this item and every remaining item of the same kind.
+    None,
+    /// Stop without doing anything.
+    Quit,
+}
+
+/// Help for the per-item prompts of '--interactive'.
+const SELECT_HELP: &str = "y - yes
+n - no
+a - yes to this and all remaining ones
+d - no to this and all remaining ones
+q - quit without doing anything
+? - print help";
+
+/// Text longer than this many lines is shown in a pager, if stdout and stderr are terminals.
+const PAGE_LINES: usize = 40;
+
+/// Pager to use if $PAGER isn't set; quits right away if the text fits on one screen.
+const DEFAULT_PAGER: &str = "less -FRX";
 
 /// All interactions with the text UI should go through this struct.
 pub struct Ui {
     /// -v setting, displays extra text info to user
     pub verbose: bool,
-    /// -y setting, ignores all points where the user is prompted to enter 'y'
-    override_confirmation: bool,
-    /// keeps track of time for each task
-    timer: Timer,
+    /// -y/--yes-*/--no-* settings, determine which prompts are skipped
+    confirmations: Confirmations,
+    /// --non-interactive setting; fail instead of prompting
+    non_interactive: bool,
+    /// keeps track of the time spent in each phase of a run, and each task;
+    /// shared so the summary can be printed after the Ui is handed to the workflow runner.
+    timer: Rc<RefCell<Timer>>,
     /// buffer to hold strings internally when getting input
     strbuf: RefCell<String>,
 }
@@ -23,18 +85,29 @@ impl Ui {
     pub fn new(settings: &Settings) -> Self {
         Self {
             verbose: settings.verbose > 0,
-            override_confirmation: settings.yes,
-            timer: Timer::now(),
+            confirmations: settings.confirm,
+            non_interactive: settings.non_interactive,
+            timer: Rc::default(),
             // Refcell so we can call confirm() w/o needing a unique reference:
             strbuf: RefCell::new(String::with_capacity(16)),
         }
     }
 
-    pub fn confirm(&self, prompt: &str) -> Result<bool> {
-        if self.override_confirmation {
-            return Ok(true);
+    pub fn confirm(&self, prompt: &str, class: Confirm) -> Result<bool> {
+        let policy = match class {
+            Confirm::Run => self.confirmations.run,
+            Confirm::Delete => self.confirmations.delete,
+            Confirm::Invalidate => self.confirmations.invalidate,
+        };
+        match policy {
+            ConfirmPolicy::Yes => return Ok(true),
+            ConfirmPolicy::No => return Ok(false),
+            ConfirmPolicy::Prompt if self.non_interactive => {
+                return Err(Error::ConfirmationRequired(prompt.to_owned(), class.flags()).into());
+            }
+            ConfirmPolicy::Prompt => (),
         }
-        eprintln!("{} (y/N)", prompt);
+        mirror_eprintln!("{} (y/N)", prompt);
 
         let mut strbuf = self.strbuf.borrow_mut();
 
@@ -46,41 +119,110 @@ impl Ui {
         }
     }
 
-    pub fn start_timer(&mut self) {
-        if self.verbose {
-            self.timer.reset();
+    /// Ask the user about a single item (with '--interactive'), until they give a valid answer.
+    /// Quits if stdin is closed.
+    pub fn select(&self, prompt: &str) -> Result<Select> {
+        let mut strbuf = self.strbuf.borrow_mut();
+        loop {
+            mirror_eprint!("{} [y,n,a,d,q,?] ", prompt);
+            strbuf.clear();
+            if std::io::stdin().read_line(&mut strbuf)? == 0 {
+                return Ok(Select::Quit);
+            }
+            match strbuf.trim() {
+                "y" => return Ok(Select::Yes),
+                "n" => return Ok(Select::No),
+                "a" => return Ok(Select::All),
+                "d" => return Ok(Select::None),
+                "q" => return Ok(Select::Quit),
+                _ => mirror_eprintln!("{}", SELECT_HELP.yellow()),
+            }
         }
     }
 
-    pub fn print_elapsed(&mut self, task: &str) -> Result<(), std::time::SystemTimeError> {
-        if self.verbose {
-            self.timer.print_elapsed(task)
-        } else {
-            Ok(())
+    /// Show `text` to the user on stderr, or in a pager if it's long and we're attached to
+    /// a terminal (using $PAGER, or `less`). Falls back to stderr if the pager can't be run.
+    pub fn page(&self, text: &str) -> Result<()> {
+        let long = text.lines().count() > PAGE_LINES;
+        let terminal = std::io::stdout().is_terminal() && std::io::stderr().is_terminal();
+        if long && terminal && !self.non_interactive {
+            let pager = std::env::var("PAGER").unwrap_or_else(|_| DEFAULT_PAGER.to_owned());
+            if !pager.is_empty() {
+                match run_pager(&pager, text) {
+                    Ok(()) => {
+                        crate::run_log::mirror(text);
+                        return Ok(());
+                    }
+                    Err(e) => log::warn!("unable to run pager {pager:?}: {e:?}"),
+                }
+            }
         }
+        mirror_eprint!("{text}");
+        Ok(())
+    }
+
+    /// Handle to the timer that phases are recorded in.
+    pub fn timer(&self) -> Rc<RefCell<Timer>> {
+        Rc::clone(&self.timer)
+    }
+
+    /// Run `f` as a phase called `name`, within the phase that's currently running.
+    pub fn phase<T>(&self, name: &str, f: impl FnOnce() -> T) -> T {
+        timed(&self.timer, name, f)
+    }
+
+    /// Record a phase called `name` that was timed elsewhere, within the current phase.
+    pub fn add_phase(&self, name: &str, elapsed: Duration) {
+        self.timer.borrow_mut().add(name, elapsed);
     }
 
     pub fn verbose_msg(&self, msg: &str) {
         if self.verbose {
-            eprintln!("{}", msg);
+            mirror_eprintln!("{}", msg);
         }
     }
 
     pub fn verbose_progress(&self, msg: &str) {
         if self.verbose {
-            eprint!("{}... ", msg.magenta());
+            mirror_eprint!("{}... ", msg.magenta());
         }
     }
 
     pub fn verbose_progress_debug<T: std::fmt::Debug>(&self, msg: &str, arg: T) {
         if self.verbose {
-            eprint!("{} {:?}... ", msg.magenta(), arg);
+            mirror_eprint!("{} {:?}... ", msg.magenta(), arg);
         }
     }
 
     pub fn done(&self) {
         if self.verbose {
-            eprintln!("{}.", "done".green());
+            mirror_eprintln!("{}.", "done".green());
         }
     }
 }
+
+/// Run `f` as a span called `name` in `timer`.
+pub fn timed<T>(timer: &RefCell<Timer>, name: &str, f: impl FnOnce() -> T) -> T {
+    timer.borrow_mut().start(name);
+    let result = f();
+    timer.borrow_mut().stop();
+    result
+}
+
+/// Print the time spent in each phase recorded in `timer`, for -v.
+pub fn print_timings(timer: &Timer) {
+    if !timer.is_empty() {
+        mirror_eprintln!("\n{}\n{timer}", "Timings:".magenta());
+    }
+}
+
+/// Pipe `text` to the shell command `pager`, and wait for the user to quit it.
+fn run_pager(pager: &str, text: &str) -> Result<()> {
+    let mut child = Command::new("/bin/sh").arg("-c").arg(pager).stdin(Stdio::piped()).spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // the user may quit before reading everything:
+        let _ = stdin.write_all(text.as_bytes());
+    }
+    child.wait()?;
+    Ok(())
+}
@@ -0,0 +1,360 @@
+use std::io::Write;
+
+use anyhow::Result;
+use serde::Serialize;
+
+use intern::GetStr;
+use traverse::{Node, RealOutputOrParam};
+use traverse::{RealInput, Traversal};
+use util::{HashMap, HashSet};
+use workflow::{AbstractTaskId, BranchpointId, IdentId, RealTaskId, RealTaskKey, Workflow};
+
+use crate::args::VizFormat;
+
+/// A task (or task realization) in a workflow graph.
+#[derive(Debug, Serialize)]
+struct GraphNode {
+    /// index of the node, which edges refer to it by.
+    id: usize,
+    /// name of the task.
+    task: String,
+    /// branchpoints the task branches on (only for abstract tasks).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    branchpoints: Option<Vec<String>>,
+    /// branch and resolved values, for task realizations.
+    #[serde(flatten)]
+    realization: Option<Realization>,
+}
+
+/// The parts of a graph node that only task realizations have.
+#[derive(Debug, Default, Serialize)]
+struct Realization {
+    /// task and branch, e.g. 'task[Profile.debug]'.
+    #[serde(skip)]
+    name: String,
+    /// (branchpoint, branch) pairs.
+    #[serde(with = "util::ordered_map")]
+    branch: Vec<(String, String)>,
+    /// (name, value) pairs, with task outputs written as '$output@task[branch]'.
+    #[serde(with = "util::ordered_map")]
+    inputs: Vec<(String, String)>,
+    #[serde(with = "util::ordered_map")]
+    outputs: Vec<(String, String)>,
+    #[serde(with = "util::ordered_map")]
+    params: Vec<(String, String)>,
+}
+
+impl GraphNode {
+    /// Task name and branch, or, for abstract tasks, branchpoints on a second line.
+    fn label(&self) -> String {
+        match (&self.realization, &self.branchpoints) {
+            (Some(realization), _) => realization.name.clone(),
+            (None, Some(branchpoints)) if !branchpoints.is_empty() => {
+                format!("{}\n{}", self.task, branchpoints.join(", "))
+            }
+            (None, _) => self.task.clone(),
+        }
+    }
+}
+
+/// A dependency of one task on the output of another.
+#[derive(Debug, Serialize)]
+struct GraphEdge {
+    /// index of the task that produces the output.
+    from: usize,
+    /// index of the task that uses it.
+    to: usize,
+    /// name of the input (or param) that uses it.
+    var: String,
+    /// true if the dependent grafts a specific branch of the output.
+    grafted: bool,
+}
+
+/// Tasks and the dependency edges between them, for exporting with 'hr viz'.
+/// As json, nodes (with their branches and resolved values, for realizations)
+/// and edges, which refer to nodes by their index in the node list.
+#[derive(Debug, Default, Serialize)]
+pub struct Graph {
+    nodes: Vec<GraphNode>,
+    edges: Vec<GraphEdge>,
+}
+
+impl Graph {
+    /// Graph of every task in the abstract workflow, labeled with the branchpoints they use.
+    pub fn from_workflow(wf: &Workflow) -> Result<Self> {
+        let mut graph = Self::default();
+        let mut idxs: HashMap<AbstractTaskId, usize> = HashMap::default();
+        // tasks that are referenced but never defined aren't part of the graph:
+        let tasks: Vec<_> =
+            wf.strings.tasks.iter().filter(|(id, _)| wf.get_task(*id).is_ok()).collect();
+        for (id, name) in &tasks {
+            let id = *id;
+            let mut branchpoints = Vec::new();
+            for k in wf.task_branchpoints(id)? {
+                branchpoints.push(wf.strings.branchpoints.get(k)?.to_owned());
+            }
+            idxs.insert(id, graph.nodes.len());
+            graph.nodes.push(GraphNode {
+                id: graph.nodes.len(),
+                task: (*name).to_owned(),
+                branchpoints: Some(branchpoints),
+                realization: None,
+            });
+        }
+        for (id, _) in tasks {
+            for edge in wf.task_edges(id)? {
+                let Some(from) = idxs.get(&edge.task) else {
+                    continue;
+                };
+                graph.edges.push(GraphEdge {
+                    from: *from,
+                    to: idxs[&id],
+                    var: wf.strings.idents.get(edge.var)?.to_owned(),
+                    grafted: edge.grafted,
+                });
+            }
+        }
+        Ok(graph)
+    }
+
+    /// Graph of the task realizations in a traversal, with duplicate realizations merged.
+    pub fn from_traversal(traversal: &Traversal, wf: &Workflow) -> Result<Self> {
+        let mut graph = Self::default();
+        let mut idxs: HashMap<&RealTaskKey, usize> = HashMap::default();
+        for node in &traversal.nodes {
+            if !idxs.contains_key(&node.key) {
+                idxs.insert(&node.key, graph.nodes.len());
+                graph.nodes.push(GraphNode {
+                    id: graph.nodes.len(),
+                    task: wf.strings.tasks.get(node.key.id)?.to_owned(),
+                    branchpoints: None,
+                    realization: Some(realize(node, traversal, wf)?),
+                });
+            }
+        }
+        // a realization can be reached more than once, so only add each edge once:
+        let mut seen: HashSet<(usize, usize, IdentId, bool)> = HashSet::default();
+        for node in &traversal.nodes {
+            let to = idxs[&node.key];
+            for (var, value) in &node.vars.inputs {
+                let mut deps = Vec::new();
+                if let Some(input) = traversal.inputs.get(*value) {
+                    collect_deps(input, false, &mut deps);
+                }
+                for (dep, via_list) in deps {
+                    let dep = &traversal.nodes[usize::from(dep)];
+                    let from = idxs[&dep.key];
+                    let grafted = via_list || !dep.grafted.is_empty();
+                    if seen.insert((from, to, *var, grafted)) {
+                        graph.edges.push(GraphEdge {
+                            from,
+                            to,
+                            var: wf.strings.idents.get(*var)?.to_owned(),
+                            grafted,
+                        });
+                    }
+                }
+            }
+        }
+        Ok(graph)
+    }
+
+    /// Write the graph in the given format.
+    pub fn write(&self, format: VizFormat, out: &mut impl Write) -> Result<()> {
+        match format {
+            VizFormat::Dot => self.write_dot(out)?,
+            VizFormat::Mermaid => self.write_mermaid(out)?,
+            VizFormat::Json => writeln!(out, "{}", serde_json::to_string_pretty(self)?)?,
+        }
+        Ok(())
+    }
+
+    /// Write the graph in Graphviz DOT format, with grafted edges dashed.
+    fn write_dot(&self, out: &mut impl Write) -> std::io::Result<()> {
+        writeln!(out, "digraph workflow {{")?;
+        writeln!(out, "  node [shape=box];")?;
+        for (i, node) in self.nodes.iter().enumerate() {
+            writeln!(out, "  n{i} [label=\"{}\"];", dot_escape(&node.label()))?;
+        }
+        for edge in &self.edges {
+            write!(
+                out,
+                "  n{} -> n{} [label=\"{}\"",
+                edge.from,
+                edge.to,
+                dot_escape(&edge.var)
+            )?;
+            if edge.grafted {
+                write!(out, ", style=dashed")?;
+            }
+            writeln!(out, "];")?;
+        }
+        writeln!(out, "}}")
+    }
+
+    /// Write the graph as a Mermaid flowchart, with grafted edges dotted.
+    fn write_mermaid(&self, out: &mut impl Write) -> std::io::Result<()> {
+        writeln!(out, "flowchart TD")?;
+        for (i, node) in self.nodes.iter().enumerate() {
+            let label = node.label().replace('"', "#quot;").replace('\n', "<br/>");
+            writeln!(out, "  n{i}[\"{label}\"]")?;
+        }
+        for edge in &self.edges {
+            let arrow = if edge.grafted { "-.->" } else { "-->" };
+            writeln!(out, "  n{} {arrow}|{}| n{}", edge.from, edge.var, edge.to)?;
+        }
+        Ok(())
+    }
+}
+
+/// Branch and resolved values of the realization in `node`.
+fn realize(node: &Node, traversal: &Traversal, wf: &Workflow) -> Result<Realization> {
+    let mut realization = Realization {
+        name: wf.strings.get_real_task_str(&node.key)?.to_owned(),
+        ..Default::default()
+    };
+    for k in (0..node.key.branch.len()).map(BranchpointId::from) {
+        if let Some(v) = node.key.branch.get_specified(k) {
+            realization.branch.push((
+                wf.strings.branchpoints.get(k)?.to_owned(),
+                wf.strings.idents.get(v)?.to_owned(),
+            ));
+        }
+    }
+    for (k, v) in &node.vars.inputs {
+        let mut val = String::new();
+        if let Some(input) = traversal.inputs.get(*v) {
+            input_str(input, traversal, wf, &mut val)?;
+        }
+        realization.inputs.push((wf.strings.idents.get(*k)?.to_owned(), val));
+    }
+    let mut strbuf = String::new();
+    for (vars, vals) in [
+        (&node.vars.outputs, &mut realization.outputs),
+        (&node.vars.params, &mut realization.params),
+    ] {
+        for (k, v) in vars {
+            let val = match traversal.outputs_params.get(*v) {
+                Some(RealOutputOrParam::Literal(lit)) => wf.strings.literals.get(*lit)?,
+                Some(RealOutputOrParam::Interp(lit, interp_vars)) => {
+                    strbuf.clear();
+                    wf.strings.make_interpolated(*lit, interp_vars, &mut strbuf)?;
+                    &strbuf
+                }
+                None => "",
+            };
+            vals.push((wf.strings.idents.get(*k)?.to_owned(), val.to_owned()));
+        }
+    }
+    Ok(realization)
+}
+
+/// Append `input` to `buf`, with task outputs written as '$output@task[branch]'
+/// and lists separated by spaces.
+fn input_str(
+    input: &RealInput,
+    traversal: &Traversal,
+    wf: &Workflow,
+    buf: &mut String,
+) -> Result<()> {
+    match input {
+        RealInput::Literal(lit) => buf.push_str(wf.strings.literals.get(*lit)?),
+        RealInput::Task(id, output) => {
+            let dep = &traversal.nodes[usize::from(*id)];
+            buf.push('$');
+            buf.push_str(wf.strings.idents.get(*output)?);
+            buf.push('@');
+            buf.push_str(&wf.strings.get_real_task_str(&dep.key)?);
+        }
+        RealInput::List(items) => {
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    buf.push(' ');
+                }
+                input_str(item, traversal, wf, buf)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Collect the ids of the nodes whose outputs an input uses,
+/// and whether each was reached through a branch glob graft.
+fn collect_deps(input: &RealInput, via_list: bool, deps: &mut Vec<(RealTaskId, bool)>) {
+    match input {
+        RealInput::Literal(_) => (),
+        RealInput::Task(id, _) => deps.push((*id, via_list)),
+        RealInput::List(inputs) => {
+            for input in inputs {
+                collect_deps(input, true, deps);
+            }
+        }
+    }
+}
+
+/// Escape a string for use inside a double-quoted DOT id.
+fn dot_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn write_workflow(format: VizFormat) -> Result<String> {
+        let text = "task build > out :: p=(Profile: debug release) { touch $out }\n\
+                    task test < in=$out@build rel=$out@build[Profile: release] { echo $in $rel }\n";
+        let mut wf = Workflow::default();
+        wf.load(syntax::parse(text)?, std::path::Path::new("."))?;
+        let mut out = Vec::new();
+        Graph::from_workflow(&wf)?.write(format, &mut out)?;
+        Ok(String::from_utf8(out)?)
+    }
+
+    #[test]
+    fn test_workflow_dot() -> Result<()> {
+        assert_eq!(
+            write_workflow(VizFormat::Dot)?,
+            "digraph workflow {\n  node [shape=box];\n  \
+             n0 [label=\"build\\nProfile\"];\n  \
+             n1 [label=\"test\"];\n  \
+             n0 -> n1 [label=\"in\"];\n  \
+             n0 -> n1 [label=\"rel\", style=dashed];\n}\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_workflow_mermaid() -> Result<()> {
+        assert_eq!(
+            write_workflow(VizFormat::Mermaid)?,
+            "flowchart TD\n  \
+             n0[\"build<br/>Profile\"]\n  \
+             n1[\"test\"]\n  \
+             n0 -->|in| n1\n  \
+             n0 -.->|rel| n1\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_workflow_json() -> Result<()> {
+        let json: serde_json::Value = serde_json::from_str(&write_workflow(VizFormat::Json)?)?;
+        assert_eq!(
+            json["nodes"][0],
+            serde_json::json!({"id": 0, "task": "build", "branchpoints": ["Profile"]})
+        );
+        let edges = json["edges"].as_array().expect("edges should be an array");
+        assert_eq!(edges.len(), 2);
+        assert_eq!(
+            edges[1],
+            serde_json::json!({"from": 0, "to": 1, "var": "rel", "grafted": true})
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_dot_escape() {
+        assert_eq!(dot_escape("a \"b\"\nc\\d"), "a \\\"b\\\"\\nc\\\\d");
+    }
+}
@@ -0,0 +1,160 @@
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::Duration;
+
+use colored::Colorize;
+
+use util::Digest;
+
+use crate::exec::{ExecObserver, TaskEvent};
+use crate::fs::Fs;
+use crate::prep::ModuleGitState;
+use crate::run_log::mirror_eprintln;
+
+/// How often to check the watched files for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How long the watched files have to stay the same before we run again,
+/// so that e.g. an editor saving several files only causes one run.
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Module dirs a run uses, filled in while it's prepared.
+pub type ModuleDirs = Rc<RefCell<Vec<PathBuf>>>;
+
+/// State of everything being watched at one point in time.
+#[derive(Debug, PartialEq, Eq)]
+struct Snapshot {
+    /// metadata digest of each config file (None if it can't be read):
+    configs: Vec<Option<Digest>>,
+    /// git state of each module dir (None if it's not a git checkout):
+    modules: Vec<Option<ModuleGitState>>,
+}
+
+/// Watches config files, and the git commits (and working tree state) of module dirs,
+/// for 'hr watch'. Other changes to module dirs don't make tasks run again, so they're ignored.
+pub struct Watcher {
+    configs: Vec<PathBuf>,
+    modules: Vec<PathBuf>,
+    snapshot: Snapshot,
+}
+
+impl Watcher {
+    pub fn new(configs: Vec<PathBuf>) -> Self {
+        Self {
+            configs,
+            modules: Vec::with_capacity(0),
+            snapshot: Snapshot {
+                configs: Vec::with_capacity(0),
+                modules: Vec::with_capacity(0),
+            },
+        }
+    }
+
+    /// Watch these module dirs (as well as the config files) from now on, starting from
+    /// their current state. Keeps the ones we had if `modules` is empty, e.g. b/c the last
+    /// run failed before it got to them.
+    pub fn set_modules(&mut self, mut modules: Vec<PathBuf>, fs: &Fs) {
+        modules.sort_unstable();
+        modules.dedup();
+        if !modules.is_empty() && modules != self.modules {
+            self.modules = modules;
+            self.snapshot.modules = self.take_snapshot(fs).modules;
+        }
+    }
+
+    /// Remember the current state of everything watched, to compare against later.
+    pub fn update(&mut self, fs: &Fs) {
+        self.snapshot = self.take_snapshot(fs);
+    }
+
+    /// Block until something watched changes, and then stays the same for a moment.
+    pub fn wait_for_change(&mut self, fs: &Fs) {
+        mirror_eprintln!(
+            "{} {} config files and {} modules for changes (Ctrl-C to stop)...",
+            "Watching".magenta(),
+            self.configs.len(),
+            self.modules.len(),
+        );
+        loop {
+            std::thread::sleep(POLL_INTERVAL);
+            let snapshot = self.take_snapshot(fs);
+            if snapshot != self.snapshot {
+                self.snapshot = snapshot;
+                break;
+            }
+        }
+        loop {
+            std::thread::sleep(DEBOUNCE);
+            let snapshot = self.take_snapshot(fs);
+            if snapshot == self.snapshot {
+                break;
+            }
+            self.snapshot = snapshot;
+        }
+        mirror_eprintln!("{}; running again.\n", "Changes detected".magenta());
+    }
+
+    fn take_snapshot(&self, fs: &Fs) -> Snapshot {
+        let digest = |path: &PathBuf| {
+            let mut digester = util::Digester::default();
+            fs.digest_metadata(path, &mut digester).ok()?;
+            Some(digester.finish())
+        };
+        Snapshot {
+            configs: self.configs.iter().map(digest).collect(),
+            modules: self.modules.iter().map(|dir| ModuleGitState::read(dir)).collect(),
+        }
+    }
+}
+
+/// Lets every run of 'hr watch' tell the same observers about its tasks.
+pub struct SharedObserver(pub Rc<RefCell<Box<dyn ExecObserver>>>);
+
+impl ExecObserver for SharedObserver {
+    fn on_task_start(&mut self, task: &TaskEvent) {
+        self.0.borrow_mut().on_task_start(task);
+    }
+
+    fn on_task_complete(&mut self, task: &TaskEvent, restored: bool, duration: Duration) {
+        self.0.borrow_mut().on_task_complete(task, restored, duration);
+    }
+
+    fn on_task_failed(&mut self, task: &TaskEvent, error: &anyhow::Error, duration: Duration) {
+        self.0.borrow_mut().on_task_failed(task, error, duration);
+    }
+
+    fn on_workflow_complete(&mut self, succeeded: bool) {
+        self.0.borrow_mut().on_workflow_complete(succeeded);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Result;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_snapshot() -> Result<()> {
+        let dir = tempdir()?;
+        let config = dir.path().join("rebuild.hr");
+        std::fs::write(&config, "task a { echo a }\n")?;
+        let fs = Fs::new(dir.path(), true);
+        let mut watcher = Watcher::new(vec![config.clone()]);
+        watcher.update(&fs);
+        assert!(watcher.snapshot.configs[0].is_some());
+        assert_eq!(watcher.take_snapshot(&fs), watcher.snapshot);
+
+        std::fs::write(&config, "task a { echo b; }\n")?;
+        assert_ne!(watcher.take_snapshot(&fs), watcher.snapshot);
+
+        // a config that's gone missing is a change too:
+        watcher.update(&fs);
+        std::fs::remove_file(&config)?;
+        assert_eq!(watcher.take_snapshot(&fs).configs, vec![None]);
+
+        dir.close()?;
+        Ok(())
+    }
+}
@@ -2,10 +2,12 @@
 pub type Ident<'a> = &'a str;
 /// type alias to make branch-related type signatures more readable.
 pub type Branch<'a> = Vec<(&'a str, &'a str)>;
+/// branch value in a graft that stands for every branch of the branchpoint.
+pub const BRANCH_GLOB: &str = "*";
+/// A comment, from its `#` to the end of its line.
+pub type Comment<'a> = &'a str;
 
 /// The right-hand side of any value expression.
-/// Ducttape originally had another rhs type:
-/// Sequential branchpoint expressions, written (Branchpoint: 0..10..1).
 #[derive(Debug, PartialEq, Eq)]
 pub enum Rhs<'a> {
     /// no rhs (e.g. in output specs)
@@ -40,6 +42,14 @@ pub enum Rhs<'a> {
     },
     /// "foo-$bla-blee" or just 'foo'
     Interp { text: &'a str, vars: Vec<&'a str> },
+    /// (Branchpoint: 1..10..2), ducttape's sequential branchpoint:
+    /// each number in the sequence is a branch, whose value is that number.
+    Sequence {
+        branchpoint: &'a str,
+        start: u64,
+        end: u64,
+        step: u64,
+    },
 }
 
 // These methods are just to assist with writing more legible tests.
@@ -79,7 +89,6 @@ impl<'a> Rhs<'a> {
 }
 
 /// One part of the header of a [`TasklikeBlock`].
-/// Ducttape had an additional spec type: package (syntax: ': package_name').
 #[derive(Debug, PartialEq, Eq)]
 pub enum BlockSpec<'a> {
     Output {
@@ -94,10 +103,16 @@ pub enum BlockSpec<'a> {
         lhs: &'a str,
         rhs: Rhs<'a>,
         dot: bool,
+        /// Type the param's value must have, if declared (`name:int=4`).
+        ty: Option<ParamType<'a>>,
     },
     Module {
         name: Ident<'a>,
     },
+    /// `: package_name`, a reference to a ducttape package.
+    Package {
+        name: Ident<'a>,
+    },
 }
 
 #[cfg(test)]
@@ -113,6 +128,15 @@ impl<'a> BlockSpec<'a> {
             lhs,
             rhs,
             dot: false,
+            ty: None,
+        }
+    }
+    pub fn typed_param(lhs: Ident<'a>, ty: ParamType<'a>, rhs: Rhs<'a>) -> Self {
+        Self::Param {
+            lhs,
+            rhs,
+            dot: false,
+            ty: Some(ty),
         }
     }
     pub fn dot_param(lhs: Ident<'a>, rhs: Rhs<'a>) -> Self {
@@ -120,17 +144,41 @@ impl<'a> BlockSpec<'a> {
             lhs,
             rhs,
             dot: true,
+            ty: None,
         }
     }
+    pub fn package(name: Ident<'a>) -> Self {
+        Self::Package { name }
+    }
+}
+
+/// Declared type of a param's value, checked before its task runs.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ParamType<'a> {
+    /// `int`: an integer.
+    Int,
+    /// `bool`: `true` or `false`.
+    Bool,
+    /// `path`: a file or dir that exists.
+    Path,
+    /// `a|b|c`: one of the listed values.
+    OneOf(Vec<&'a str>),
 }
 
 /// Specific type of a [`TasklikeBlock`].
-/// Ducttape had the following additional types:
-/// package, action, versioner, submitter, function.
+/// Ducttape also had a submitter type.
 /// We would like to at least add an equivalent to submitter in the future.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum BlockType {
     Task,
+    /// A bash function that tasks can call.
+    Func,
+    /// A ducttape package, which is checked out by a versioner and then built.
+    Package,
+    /// A ducttape versioner, made up of actions.
+    Versioner,
+    /// One of the actions of a ducttape versioner.
+    Action,
 }
 
 /// A block which uses the task structure.
@@ -144,6 +192,8 @@ pub struct TasklikeBlock<'a> {
     pub specs: Vec<BlockSpec<'a>>,
     /// Bash code contained within braces
     pub code: BashCode<'a>,
+    /// Comments in the header, in order
+    pub comments: Vec<Comment<'a>>,
 }
 
 /// A block which consists of multiple nested [`TasklikeBlock`]s.
@@ -184,6 +234,8 @@ pub struct CrossProduct<'a> {
     pub goals: Vec<Ident<'a>>,
     /// List of (branchpoint name, branches) pairs used to form traversal.
     pub branches: Vec<(Ident<'a>, Branches<'a>)>,
+    /// Combinations of branches to leave out of the traversal (`except (Os: windows * Arch: arm64)`).
+    pub excludes: Vec<Vec<(Ident<'a>, Branches<'a>)>>,
 }
 
 /// A block of one or more [`CrossProduct`]s that specify a traversal through the workflow.
@@ -193,21 +245,62 @@ pub struct Plan<'a> {
     pub name: &'a str,
     /// List of contained [`CrossProduct`]s
     pub cross_products: Vec<CrossProduct<'a>>,
+    /// Names of other plans whose cross products this one includes (`include debug`)
+    pub includes: Vec<Ident<'a>>,
+    /// Branches this plan uses as baseline instead of the defined ones (`baseline (Profile: release)`)
+    pub baselines: Vec<(Ident<'a>, Ident<'a>)>,
+    /// Comments inside the plan, in order
+    pub comments: Vec<Comment<'a>>,
+}
+
+/// Whether the first branch of a [`Constraint`] needs, or rules out, the second.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConstraintKind {
+    Requires,
+    Excludes,
+}
+
+/// A restriction on which branches can be combined,
+/// e.g. `constraint Os.windows requires Arch.x64`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Constraint<'a> {
+    /// Branchpoint and branch the constraint applies to
+    pub branch: (Ident<'a>, Ident<'a>),
+    pub kind: ConstraintKind,
+    /// Branchpoint and branch that `branch` requires or excludes
+    pub other: (Ident<'a>, Ident<'a>),
+}
+
+/// A block of config variables.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct GlobalConfig<'a> {
+    /// Variable names and values
+    pub assignments: Vec<(Ident<'a>, Rhs<'a>)>,
+    /// Comments inside the block, in order
+    pub comments: Vec<Comment<'a>>,
 }
 
 /// One high-level item in the workflow.
 #[derive(Debug, PartialEq, Eq)]
 pub enum Item<'a> {
-    // Versioner(GrouplikeBlock<'a>),
+    /// A ducttape versioner definition.
+    Versioner(GrouplikeBlock<'a>),
     /// A task definition.
     Task(TasklikeBlock<'a>),
+    /// A function definition, which tasks that call it get a copy of.
+    Func(TasklikeBlock<'a>),
     /// An import statement.
     Import(&'a str),
-    // Package(TasklikeBlock<'a>),
+    /// A ducttape package definition.
+    Package(TasklikeBlock<'a>),
     /// A block of config variables.
-    GlobalConfig(Vec<(&'a str, Rhs<'a>)>),
+    GlobalConfig(GlobalConfig<'a>),
     /// A [`Plan`].
     Plan(Plan<'a>),
-    /// A module definition.
-    Module(Ident<'a>, Rhs<'a>),
+    /// A module definition: name, path, and optional version to check out (`:: rev=...`).
+    Module(Ident<'a>, Rhs<'a>, Option<Rhs<'a>>),
+    /// A [`Constraint`] on which branches can be combined.
+    Constraint(Constraint<'a>),
+    /// A comment between other items.
+    Comment(Comment<'a>),
 }
@@ -102,9 +102,9 @@ p! {
 
 p! {
     internal_variable() -> &'a str, {
-        recognize(
+        attempt(recognize(
             char('$').and(one_of("*@#?-$!0_".chars()))
-        ).or(recognize(
+        )).or(recognize(
             char('$').and(non_zero_int())
         ))
     }
@@ -317,6 +317,10 @@ mod test {
             ("$variable", vec!["variable"]),
             super::variable_like().easy_parse("$variable").unwrap().0
         );
+        assert_eq!(
+            ("$12", vec![]),
+            super::variable_like().easy_parse("$12").unwrap().0
+        );
         Ok(())
     }
 }
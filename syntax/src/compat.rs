@@ -0,0 +1,228 @@
+use std::fmt;
+
+use crate::ast::{BlockSpec, Item, Rhs};
+use crate::parse::Location;
+
+/// A ducttape feature that isn't part of our own syntax.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    /// `package name :: .versioner=git ... { build code }`
+    Package,
+    /// `versioner name :: params { action checkout ... }`
+    Versioner,
+    /// `: package_name` in a task header
+    PackageRef,
+    /// `(Branchpoint: 1..10..2)`
+    SequentialBranchpoint,
+}
+
+impl Feature {
+    /// True if this feature has no equivalent in our model,
+    /// so it's left out of workflows parsed in compatibility mode.
+    pub fn is_unsupported(self) -> bool {
+        !matches!(self, Self::SequentialBranchpoint)
+    }
+
+    /// How to migrate this feature by hand.
+    fn hint(self) -> &'static str {
+        match self {
+            Self::Package => "build it in a task, or check it out with a module",
+            Self::Versioner => {
+                "check code out with a module instead (`module name=path :: rev=...`)"
+            }
+            Self::PackageRef => "use an output of the task that builds it, or `@module` instead",
+            Self::SequentialBranchpoint => "list its branches instead",
+        }
+    }
+}
+
+impl fmt::Display for Feature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Package => "Package",
+            Self::Versioner => "Versioner",
+            Self::PackageRef => "Package reference",
+            Self::SequentialBranchpoint => "Sequential branchpoint",
+        })
+    }
+}
+
+/// A ducttape feature found while parsing in compatibility mode,
+/// which was left out of the parsed items.
+#[derive(Debug)]
+pub struct Unsupported {
+    pub feature: Feature,
+    /// Name of the package, versioner, or package referred to.
+    pub name: String,
+    pub(crate) loc: Location,
+}
+
+/// Renders like a parse error, e.g.:
+/// ```text
+/// Package "lm" isn't supported, so it was left out; build it in a task, or check it out with a module
+///  --> pipeline.tape:3:9
+///   |
+/// 3 | package lm :: .versioner=git {
+///   |         ^
+/// ```
+impl fmt::Display for Unsupported {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} \"{}\" isn't supported, so it was left out; {}",
+            self.feature,
+            self.name,
+            self.feature.hint()
+        )?;
+        write!(f, "{}", self.loc)
+    }
+}
+
+/// A use of a ducttape feature in some parsed items.
+pub(crate) struct Found<'a> {
+    pub feature: Feature,
+    /// Name of the package, versioner, package referred to, or branchpoint;
+    /// a slice of the parsed text, so we can tell where it is.
+    pub name: &'a str,
+}
+
+/// Every use of a ducttape feature in `items`, in the order they appear.
+pub(crate) fn find<'a, 'b>(items: impl IntoIterator<Item = &'b Item<'a>>) -> Vec<Found<'a>>
+where
+    'a: 'b,
+{
+    let mut found = Vec::new();
+    for item in items {
+        match item {
+            Item::Package(block) => found.push(Found {
+                feature: Feature::Package,
+                name: block.name,
+            }),
+            Item::Versioner(block) => found.push(Found {
+                feature: Feature::Versioner,
+                name: block.name,
+            }),
+            Item::Task(block) => {
+                for spec in &block.specs {
+                    match spec {
+                        BlockSpec::Package { name } => found.push(Found {
+                            feature: Feature::PackageRef,
+                            name,
+                        }),
+                        BlockSpec::Input { rhs, .. }
+                        | BlockSpec::Output { rhs, .. }
+                        | BlockSpec::Param { rhs, .. } => find_sequences(rhs, &mut found),
+                        BlockSpec::Module { .. } => (),
+                    }
+                }
+            }
+            Item::GlobalConfig(config) => {
+                for (_, rhs) in &config.assignments {
+                    find_sequences(rhs, &mut found);
+                }
+            }
+            Item::Module(_, path, rev) => {
+                find_sequences(path, &mut found);
+                if let Some(rev) = rev {
+                    find_sequences(rev, &mut found);
+                }
+            }
+            Item::Func(_)
+            | Item::Import(_)
+            | Item::Plan(_)
+            | Item::Constraint(_)
+            | Item::Comment(_) => (),
+        }
+    }
+    found
+}
+
+fn find_sequences<'a>(rhs: &Rhs<'a>, found: &mut Vec<Found<'a>>) {
+    match rhs {
+        Rhs::Sequence { branchpoint, .. } => found.push(Found {
+            feature: Feature::SequentialBranchpoint,
+            name: branchpoint,
+        }),
+        Rhs::Branchpoint { vals, .. } => {
+            for (_, val) in vals {
+                find_sequences(val, found);
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Remove the unsupported ducttape features from `items`.
+pub(crate) fn strip(items: &mut Vec<Item>) {
+    items.retain(|item| !matches!(item, Item::Package(_) | Item::Versioner(_)));
+    for item in items {
+        if let Item::Task(block) = item {
+            block.specs.retain(|spec| !matches!(spec, BlockSpec::Package { .. }));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{parse, parse_ducttape};
+
+    const TAPE: &str = "\
+versioner git :: repo ref {
+  action checkout > dir {
+    git clone $repo $dir
+  }
+}
+package lm :: .versioner=git .repo=\"https://example.com/lm.git\" .ref=HEAD {
+  make
+}
+global {
+  seed=(Seed: 1..5..2)
+}
+task train : lm < corpus=data.txt > model :: seed=@ {
+  $lm/bin/train --seed $seed $corpus > $model
+}
+";
+
+    #[test]
+    fn test_parse_ducttape() {
+        let (items, unsupported) = parse_ducttape(TAPE, Some("lm.tape")).unwrap();
+        assert_eq!(items.len(), 2);
+        let Item::Task(task) = &items[1] else {
+            panic!("expected a task, got {:?}", items[1]);
+        };
+        assert_eq!(task.specs.len(), 3);
+        let found: Vec<_> = unsupported.iter().map(|u| (u.feature, &*u.name)).collect();
+        assert_eq!(
+            found,
+            [
+                (Feature::Versioner, "git"),
+                (Feature::Package, "lm"),
+                (Feature::PackageRef, "lm"),
+            ]
+        );
+        let rendered = unsupported[2].to_string();
+        let lines: Vec<&str> = rendered.lines().collect();
+        assert!(lines[0].starts_with("Package reference \"lm\" isn't supported"));
+        assert_eq!(lines[1], "  --> lm.tape:12:14");
+        assert_eq!(lines[4], format!("   | {}^", " ".repeat(13)));
+    }
+
+    #[test]
+    fn test_native_rejects_ducttape() {
+        let e = parse(TAPE).unwrap_err().downcast::<crate::ParseErrors>().unwrap();
+        let msgs: Vec<String> =
+            e.0.iter().map(|e| e.to_string().lines().next().unwrap().to_owned()).collect();
+        assert_eq!(
+            msgs,
+            [
+                "ParseError: Versioner \"git\" is ducttape syntax, which needs --compat ducttape",
+                "ParseError: Package \"lm\" is ducttape syntax, which needs --compat ducttape",
+                "ParseError: Sequential branchpoint \"Seed\" is ducttape syntax, \
+                 which needs --compat ducttape",
+                "ParseError: Package reference \"lm\" is ducttape syntax, \
+                 which needs --compat ducttape",
+            ]
+        );
+    }
+}
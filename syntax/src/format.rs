@@ -0,0 +1,614 @@
+//! Canonical formatting of config files, for 'hr fmt'.
+
+use std::ops::Range;
+
+use anyhow::Result;
+
+use crate::ast::{
+    BlockSpec, BlockType, Branches, ConstraintKind, CrossProduct, GlobalConfig, GrouplikeBlock,
+    Item, ParamType, Plan, Rhs, TasklikeBlock,
+};
+use crate::parse::{parse_file, parse_file_spanned, span_of};
+
+/// Task headers and branchpoints longer than this are split over several lines.
+const MAX_WIDTH: usize = 100;
+
+const INDENT: &str = "    ";
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Formatting {0} would change what it means; this is a bug in 'hr fmt'")]
+    Changed(String),
+}
+
+/// Format the text of a config file, using `file` to describe where any syntax errors are.
+///
+/// Blocks are separated by blank lines and their contents indented by four spaces; task headers
+/// go on one line if they fit (with one line for each kind of spec if they don't), and the
+/// branches of a branchpoint that doesn't fit on one line (or didn't before) go on lines of their
+/// own, with their values aligned. Comments and single blank lines inside blocks are kept,
+/// and so is the code of each task, exactly as written.
+pub fn format(text: &str, file: Option<&str>) -> Result<String> {
+    let items = parse_file_spanned(text, file)?;
+    let formatted = Formatter { text }.items(&items);
+
+    // the formatted text should mean exactly the same thing, comments included:
+    let unchanged = parse_file(&formatted, file)
+        .is_ok_and(|reparsed| reparsed.iter().eq(items.iter().map(|(_, item)| item)));
+    if !unchanged {
+        return Err(Error::Changed(file.unwrap_or("<config>").to_owned()).into());
+    }
+    Ok(formatted)
+}
+
+/// One line (or a few, for values that span lines) in the body of a block.
+enum Line<'a> {
+    Comment(&'a str),
+    Code(String),
+}
+
+impl Line<'_> {
+    fn text(&self) -> &str {
+        match self {
+            Line::Comment(comment) => comment,
+            Line::Code(code) => code,
+        }
+    }
+}
+
+/// Formats items parsed from `text`, using their positions in it to tell where blank lines
+/// and comments were, and which literals were quoted.
+struct Formatter<'t> {
+    text: &'t str,
+}
+
+impl Formatter<'_> {
+    fn items(&self, items: &[(Range<usize>, Item)]) -> String {
+        let mut out = String::with_capacity(self.text.len());
+        let mut prev: Option<(&Range<usize>, &Item)> = None;
+        for (span, item) in items {
+            if let Some((prev_span, prev_item)) = prev {
+                if let Item::Comment(comment) = item {
+                    // after the closing brace of a block:
+                    if is_block(prev_item) && self.same_line(prev_span.end, span.start) {
+                        end_line_with(&mut out, comment);
+                        prev = Some((span, item));
+                        continue;
+                    }
+                }
+                // comments stay right before the blocks they're about:
+                if is_block(prev_item)
+                    || (is_block(item) && !matches!(prev_item, Item::Comment(_)))
+                    || self.blank_between(prev_span.start, span.start)
+                {
+                    out.push('\n');
+                }
+            }
+            self.item(item, &mut out);
+            prev = Some((span, item));
+        }
+        out
+    }
+
+    fn item(&self, item: &Item, out: &mut String) {
+        match item {
+            Item::Comment(comment) => {
+                out.push_str(comment);
+                out.push('\n');
+            }
+            Item::Import(path) => {
+                out.push_str("import ");
+                out.push_str(&self.literal(path));
+                out.push('\n');
+            }
+            Item::Module(name, path, rev) => {
+                let start = "module ".len();
+                out.push_str("module ");
+                out.push_str(&self.assignment(name, path, 0, start));
+                if let Some(rev) = rev {
+                    out.push_str(" :: rev=");
+                    out.push_str(&self.rhs(rev, 0, 0));
+                }
+                out.push('\n');
+            }
+            Item::Constraint(constraint) => {
+                let kind = match constraint.kind {
+                    ConstraintKind::Requires => "requires",
+                    ConstraintKind::Excludes => "excludes",
+                };
+                let ((k, v), (other_k, other_v)) = (constraint.branch, constraint.other);
+                out.push_str(&format!("constraint {k}.{v} {kind} {other_k}.{other_v}\n"));
+            }
+            Item::GlobalConfig(config) => self.global_config(config, out),
+            Item::Plan(plan) => self.plan(plan, out),
+            Item::Task(block) | Item::Func(block) | Item::Package(block) => {
+                self.tasklike(block, 0, out);
+            }
+            Item::Versioner(group) => self.grouplike(group, out),
+        }
+    }
+
+    fn global_config(&self, config: &GlobalConfig, out: &mut String) {
+        let mut lines: Vec<(usize, Line)> = config
+            .assignments
+            .iter()
+            .map(|(lhs, rhs)| (self.pos(lhs), Line::Code(self.assignment(lhs, rhs, 1, 4))))
+            .collect();
+        lines.extend(config.comments.iter().map(|c| (self.pos(c), Line::Comment(c))));
+        let open = config
+            .assignments
+            .first()
+            .map(|(lhs, _)| self.pos(lhs))
+            .into_iter()
+            .chain(config.comments.first().map(|c| self.pos(c)))
+            .min();
+        self.block("global", open, lines, out);
+    }
+
+    fn plan(&self, plan: &Plan, out: &mut String) {
+        let mut lines: Vec<(usize, Line)> = Vec::new();
+        for include in &plan.includes {
+            lines.push((self.pos(include), Line::Code(format!("include {include}"))));
+        }
+        for (branchpoint, branch) in &plan.baselines {
+            let line = format!("baseline ({branchpoint}: {branch})");
+            lines.push((self.pos(branchpoint), Line::Code(line)));
+        }
+        for cross_product in &plan.cross_products {
+            let pos = self.pos(cross_product.goals[0]);
+            lines.push((pos, Line::Code(reach(cross_product))));
+        }
+        lines.extend(plan.comments.iter().map(|c| (self.pos(c), Line::Comment(c))));
+        self.block(
+            &format!("plan {}", plan.name),
+            Some(self.pos(plan.name)),
+            lines,
+            out,
+        );
+    }
+
+    /// Write a block named `header`, that starts at position `open`, containing `lines`.
+    fn block(
+        &self,
+        header: &str,
+        open: Option<usize>,
+        mut lines: Vec<(usize, Line)>,
+        out: &mut String,
+    ) {
+        out.push_str(header);
+        if lines.is_empty() {
+            out.push_str(" {}\n");
+            return;
+        }
+        out.push_str(" {\n");
+        lines.sort_by_key(|(pos, _)| *pos);
+        let mut prev = open.filter(|_| matches!(lines[0].1, Line::Comment(_)));
+        for (i, (pos, line)) in lines.into_iter().enumerate() {
+            match line {
+                Line::Comment(comment) if prev.is_some_and(|prev| self.same_line(prev, pos)) => {
+                    end_line_with(out, comment);
+                }
+                line => {
+                    if i > 0 && prev.is_some_and(|prev| self.blank_between(prev, pos)) {
+                        out.push('\n');
+                    }
+                    out.push_str(INDENT);
+                    out.push_str(line.text());
+                    out.push('\n');
+                }
+            }
+            prev = Some(pos);
+        }
+        out.push_str("}\n");
+    }
+
+    fn tasklike(&self, block: &TasklikeBlock, indent: usize, out: &mut String) {
+        let keyword = match block.subtype {
+            BlockType::Task => "task",
+            BlockType::Func => "func",
+            BlockType::Package => "package",
+            BlockType::Versioner => "versioner",
+            BlockType::Action => "action",
+        };
+        let prefix = INDENT.repeat(indent);
+        let header = format!("{prefix}{keyword} {}", block.name);
+        let col = INDENT.len() * (indent + 1) + 3;
+        let specs: Vec<(&BlockSpec, String)> = block
+            .specs
+            .iter()
+            .map(|spec| (spec, self.spec(spec, indent + 1, col)))
+            .collect();
+
+        let one_line = specs_line(&header, &specs);
+        if block.comments.is_empty() && !one_line.contains('\n') && one_line.len() + 2 <= MAX_WIDTH
+        {
+            out.push_str(&one_line);
+            out.push(' ');
+        } else {
+            // one line per spec, in the order they were given, with any modules first
+            // on the header line:
+            out.push_str(&header);
+            let mut lines: Vec<(usize, Line)> = specs
+                .into_iter()
+                .map(|(spec, text)| {
+                    let line = format!("{}{text}", chunk_symbol(spec));
+                    (self.pos(spec_name(spec)), Line::Code(line))
+                })
+                .collect();
+            lines.extend(block.comments.iter().map(|c| (self.pos(c), Line::Comment(c))));
+            lines.sort_by_key(|(pos, _)| *pos);
+
+            let spec_prefix = INDENT.repeat(indent + 1);
+            let mut in_header = true;
+            let mut prev = self.pos(block.name);
+            for (pos, line) in lines {
+                match line {
+                    // modules are the only specs that start with '@':
+                    Line::Code(module) if in_header && module.starts_with('@') => {
+                        out.push(' ');
+                        out.push_str(&module);
+                    }
+                    Line::Comment(comment) if self.same_line(prev, pos) => {
+                        end_line_with(out, comment);
+                        in_header = false;
+                    }
+                    line => {
+                        if in_header {
+                            out.push('\n');
+                            in_header = false;
+                        }
+                        out.push_str(&spec_prefix);
+                        out.push_str(line.text());
+                        out.push('\n');
+                    }
+                }
+                prev = pos;
+            }
+            if in_header {
+                out.push('\n');
+            }
+            out.push_str(&prefix);
+        }
+        out.push('{');
+        out.push_str(block.code.text);
+        out.push_str("}\n");
+    }
+
+    fn grouplike(&self, group: &GrouplikeBlock, out: &mut String) {
+        let header = format!("versioner {}", group.name);
+        let specs: Vec<(&BlockSpec, String)> =
+            group.specs.iter().map(|spec| (spec, self.spec(spec, 1, 0))).collect();
+        out.push_str(&specs_line(&header, &specs));
+        out.push_str(" {\n");
+        for (i, block) in group.blocks.iter().enumerate() {
+            if i > 0 {
+                out.push('\n');
+            }
+            self.tasklike(block, 1, out);
+        }
+        out.push_str("}\n");
+    }
+
+    /// A single input, output, param, module, or package, without the symbol before it.
+    fn spec(&self, spec: &BlockSpec, indent: usize, col: usize) -> String {
+        match spec {
+            BlockSpec::Input { lhs, rhs } | BlockSpec::Output { lhs, rhs } => {
+                self.assignment(lhs, rhs, indent, col)
+            }
+            BlockSpec::Param { lhs, rhs, dot, ty } => {
+                let mut text = String::new();
+                if *dot {
+                    text.push('.');
+                }
+                text.push_str(lhs);
+                if let Some(ty) = ty {
+                    text.push(':');
+                    match ty {
+                        ParamType::Int => text.push_str("int"),
+                        ParamType::Bool => text.push_str("bool"),
+                        ParamType::Path => text.push_str("path"),
+                        ParamType::OneOf(vals) => text.push_str(&vals.join("|")),
+                    }
+                }
+                if !matches!(rhs, Rhs::Unbound) {
+                    text.push('=');
+                    text.push_str(&self.rhs(rhs, indent, col + text.len()));
+                }
+                text
+            }
+            BlockSpec::Module { name } => format!("@{name}"),
+            BlockSpec::Package { name } => name.to_string(),
+        }
+    }
+
+    /// `lhs=rhs`, or just `lhs` if it's unbound.
+    fn assignment(&self, lhs: &str, rhs: &Rhs, indent: usize, col: usize) -> String {
+        match rhs {
+            Rhs::Unbound => lhs.to_owned(),
+            rhs => format!("{lhs}={}", self.rhs(rhs, indent, col + lhs.len() + 1)),
+        }
+    }
+
+    /// A value on a line indented `indent` times, starting at column `col`.
+    fn rhs(&self, rhs: &Rhs, indent: usize, col: usize) -> String {
+        match rhs {
+            Rhs::Unbound => String::new(),
+            Rhs::Literal { val } => self.literal(val),
+            Rhs::Variable { name } => format!("${name}"),
+            Rhs::ShorthandVariable => "@".to_owned(),
+            Rhs::GraftedVariable { name, branch } => format!("${name}{}", graft(branch)),
+            Rhs::TaskOutput { task, output } => format!("${output}@{task}"),
+            Rhs::ShorthandTaskOutput { task } => format!("@{task}"),
+            Rhs::GraftedTaskOutput {
+                task,
+                output,
+                branch,
+            } => format!("${output}@{task}{}", graft(branch)),
+            Rhs::ShorthandGraftedTaskOutput { task, branch } => format!("@{task}{}", graft(branch)),
+            Rhs::Interp { text, .. } => format!("\"{text}\""),
+            Rhs::Sequence {
+                branchpoint,
+                start,
+                end,
+                step,
+            } => match step {
+                1 => format!("({branchpoint}: {start}..{end})"),
+                step => format!("({branchpoint}: {start}..{end}..{step})"),
+            },
+            Rhs::Branchpoint { branchpoint, vals } => {
+                self.branchpoint(branchpoint, vals, indent, col)
+            }
+        }
+    }
+
+    /// `(Branchpoint: a=1 b=2)`, or if it doesn't fit (or was written over several lines),
+    /// each branch on a line of its own, with the values aligned.
+    fn branchpoint(
+        &self,
+        branchpoint: &str,
+        vals: &[(&str, Rhs)],
+        indent: usize,
+        col: usize,
+    ) -> String {
+        let branches: Vec<String> =
+            vals.iter().map(|(name, val)| self.assignment(name, val, indent, 0)).collect();
+        let one_line = format!("({branchpoint}: {})", branches.join(" "));
+        if !self.was_multiline(branchpoint, vals)
+            && !one_line.contains('\n')
+            && col + one_line.len() <= MAX_WIDTH
+        {
+            return one_line;
+        }
+
+        let width = vals
+            .iter()
+            .filter(|(_, val)| !matches!(val, Rhs::Unbound))
+            .map(|(name, _)| name.len())
+            .max()
+            .unwrap_or(0);
+        let prefix = INDENT.repeat(indent + 1);
+        let mut text = format!("({branchpoint}:\n");
+        for (name, val) in vals {
+            text.push_str(&prefix);
+            if matches!(val, Rhs::Unbound) {
+                text.push_str(name);
+            } else {
+                let lhs = format!("{name:<width$} = ");
+                let col = prefix.len() + lhs.len();
+                text.push_str(&lhs);
+                text.push_str(&self.rhs(val, indent + 1, col));
+            }
+            text.push('\n');
+        }
+        text.push_str(&INDENT.repeat(indent));
+        text.push(')');
+        text
+    }
+
+    /// Whether the branches of a branchpoint were written on more than one line.
+    fn was_multiline(&self, branchpoint: &str, vals: &[(&str, Rhs)]) -> bool {
+        let start = self.pos(branchpoint);
+        let open = self.text[..start].rfind('(').unwrap_or(start);
+        let end = vals.last().map_or(start, |(name, _)| self.pos(name));
+        self.text[open..end].contains('\n')
+    }
+
+    /// A literal, in double quotes if it was quoted.
+    fn literal(&self, val: &str) -> String {
+        let pos = self.pos(val);
+        if val.is_empty() || (pos > 0 && self.text.as_bytes()[pos - 1] == b'"') {
+            format!("\"{val}\"")
+        } else {
+            val.to_owned()
+        }
+    }
+
+    fn pos(&self, part: &str) -> usize {
+        span_of(self.text, part).start
+    }
+
+    /// Whether there's no line break between positions `a` and `b`.
+    fn same_line(&self, a: usize, b: usize) -> bool {
+        a <= b && !self.text[a..b].contains('\n')
+    }
+
+    /// Whether there's a blank line between the lines positions `a` and `b` are on.
+    fn blank_between(&self, a: usize, b: usize) -> bool {
+        if a >= b {
+            return false;
+        }
+        let lines: Vec<&str> = self.text[a..b].split('\n').collect();
+        lines.len() > 2 && lines[1..lines.len() - 1].iter().any(|line| line.trim().is_empty())
+    }
+}
+
+/// Add `comment` to the end of the last line of `out`.
+fn end_line_with(out: &mut String, comment: &str) {
+    if out.ends_with('\n') {
+        out.pop();
+    }
+    out.push(' ');
+    out.push_str(comment);
+    out.push('\n');
+}
+
+fn is_block(item: &Item) -> bool {
+    !matches!(
+        item,
+        Item::Comment(_) | Item::Import(_) | Item::Module(..) | Item::Constraint(_)
+    )
+}
+
+/// `header` followed by every spec, grouped by kind, on one line.
+fn specs_line(header: &str, specs: &[(&BlockSpec, String)]) -> String {
+    let mut line = header.to_owned();
+    let mut prev: Option<&BlockSpec> = None;
+    for (spec, text) in specs {
+        line.push(' ');
+        if !prev.is_some_and(|prev| same_kind(prev, spec)) {
+            line.push_str(chunk_symbol(spec));
+        }
+        line.push_str(text);
+        prev = Some(spec);
+    }
+    line
+}
+
+/// What goes before `spec` (and any specs of the same kind right after it).
+fn chunk_symbol(spec: &BlockSpec) -> &'static str {
+    match spec {
+        BlockSpec::Input { .. } => "< ",
+        BlockSpec::Output { .. } => "> ",
+        BlockSpec::Param { .. } => ":: ",
+        BlockSpec::Module { .. } => "",
+        BlockSpec::Package { .. } => ": ",
+    }
+}
+
+fn same_kind(a: &BlockSpec, b: &BlockSpec) -> bool {
+    std::mem::discriminant(a) == std::mem::discriminant(b)
+}
+
+/// The name a spec starts with, to tell where it is.
+fn spec_name<'a>(spec: &BlockSpec<'a>) -> &'a str {
+    match spec {
+        BlockSpec::Input { lhs, .. }
+        | BlockSpec::Output { lhs, .. }
+        | BlockSpec::Param { lhs, .. } => lhs,
+        BlockSpec::Module { name } | BlockSpec::Package { name } => name,
+    }
+}
+
+fn reach(cross_product: &CrossProduct) -> String {
+    let mut line = format!("reach {}", cross_product.goals.join(", "));
+    let selection = |branches: &[(&str, Branches)]| -> Vec<String> {
+        branches
+            .iter()
+            .map(|(branchpoint, branches)| match branches {
+                Branches::Glob => format!("{branchpoint}: *"),
+                Branches::Specified(branches) => format!("{branchpoint}: {}", branches.join(" ")),
+            })
+            .collect()
+    };
+    if !cross_product.branches.is_empty() {
+        let branches: Vec<String> = selection(&cross_product.branches)
+            .into_iter()
+            .map(|b| format!("({b})"))
+            .collect();
+        line.push_str(" via ");
+        line.push_str(&branches.join(" * "));
+    }
+    for exclude in &cross_product.excludes {
+        line.push_str(&format!(" except ({})", selection(exclude).join(" * ")));
+    }
+    line
+}
+
+/// `[Branchpoint: branch, ...]`
+fn graft(branch: &[(&str, &str)]) -> String {
+    let branch: Vec<String> = branch.iter().map(|(k, v)| format!("{k}: {v}")).collect();
+    format!("[{}]", branch.join(", "))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn fmt(text: &str) -> String {
+        let formatted = format(text, None).unwrap();
+        assert_eq!(
+            format(&formatted, None).unwrap(),
+            formatted,
+            "not idempotent"
+        );
+        formatted
+    }
+
+    #[test]
+    fn test_format_task() {
+        assert_eq!(
+            fmt("task  a   <in=$x@b>out::p=1   .size=2G\n@src{\n  touch $out\n}"),
+            "task a < in=$x@b > out :: p=1 .size=2G @src {\n  touch $out\n}\n"
+        );
+        // headers that are too long (or have comments) get one line per spec:
+        let long = format!("task a > out :: {} {{ true }}", "param=value ".repeat(8));
+        assert_eq!(
+            fmt(&long),
+            format!(
+                "task a\n    > out\n{}{{ true }}\n",
+                "    :: param=value\n".repeat(8)
+            )
+        );
+        assert_eq!(
+            fmt("task a @m # about a\n  < x y # y\n  < z\n  # the rest\n  > out @n { true }"),
+            "task a @m # about a\n    < x\n    < y # y\n    < z\n    # the rest\n    > out\n    @n\n{ true }\n"
+        );
+    }
+
+    #[test]
+    fn test_format_values() {
+        assert_eq!(
+            fmt("global {\n  a = \"quoted\"\n  b=unquoted\n\n\n  c=$a[X:y,Z:*]\n  d=\"$a-$b\"\n}\n"),
+            "global {\n    a=\"quoted\"\n    b=unquoted\n\n    c=$a[X: y, Z: *]\n    d=\"$a-$b\"\n}\n"
+        );
+        // branchpoints that were written on several lines stay that way, with values aligned:
+        assert_eq!(
+            fmt("global {\n  x=(Arch:\n x64=x86_64\n arm64=aarch64 # newer\n )\n  y=(Y: a=1 b)\n}"),
+            "global {\n    x=(Arch:\n        x64   = x86_64\n        arm64 = aarch64\n    )\n    \
+             # newer\n    y=(Y: a=1 b)\n}\n"
+        );
+        let wide = format!("global {{\n  x=(X: a={0} b={0})\n}}\n", "v".repeat(50));
+        assert_eq!(
+            fmt(&wide),
+            format!(
+                "global {{\n    x=(X:\n        a = {0}\n        b = {0}\n    )\n}}\n",
+                "v".repeat(50)
+            )
+        );
+    }
+
+    #[test]
+    fn test_format_items() {
+        let text = "# header\n\n\nmodule a=./a\n# b is next\nmodule b=./b :: rev=(V: x y)\n\
+                    import \"other file.hr\"\nconstraint  X.x   excludes Y.y\n# about p\nplan p {\n  include q;reach a,b   via (X: *)*(Y: y z) \
+                    except (X: x*Y: y) # most\n\n  reach c\n}\nplan q {baseline (Y:z) reach b}\n\n\n\
+                    func f {\n  echo $1\n} # end\n";
+        assert_eq!(
+            fmt(text),
+            "# header\n\nmodule a=./a\n# b is next\nmodule b=./b :: rev=(V: x y)\nimport \"other file.hr\"\n\
+             constraint X.x excludes Y.y\n# about p\nplan p {\n    include q\n    reach a, b via (X: *) * (Y: y z) except (X: x * Y: y) # most\n\n    \
+             reach c\n}\n\nplan q {\n    baseline (Y: z)\n    reach b\n}\n\nfunc f {\n  echo $1\n} # end\n"
+        );
+    }
+
+    #[test]
+    fn test_format_examples() {
+        for example in ["basic.hr", "stub.tconf"] {
+            let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("../examples")
+                .join(example);
+            let text = std::fs::read_to_string(path).unwrap();
+            fmt(&text);
+        }
+    }
+}
@@ -1,9 +1,13 @@
 #[macro_use]
 mod macros;
 mod parse;
-pub use parse::parse;
+pub use parse::{parse, parse_ducttape, parse_file, Error as ParseError, Errors as ParseErrors};
 pub mod ast;
 mod bash;
+mod compat;
+mod format;
+pub use compat::{Feature as DucttapeFeature, Unsupported as UnsupportedFeature};
+pub use format::{format, Error as FormatError};
 
 type Hasher = std::hash::BuildHasherDefault<rustc_hash::FxHasher>;
 type HashSet<T> = std::collections::HashSet<T, Hasher>;
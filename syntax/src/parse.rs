@@ -1,39 +1,349 @@
+use std::fmt;
+use std::ops::Range;
+
 use anyhow::Result;
 
+use crate::ast::Item;
+use crate::compat;
+
+/// A position in a config file, with enough context to point at it in a message.
+#[derive(Debug)]
+pub struct Location {
+    /// Name of the file being parsed, if we know it.
+    file: Option<String>,
+    /// 1-based line number.
+    line_num: usize,
+    /// 1-based column number, in chars.
+    col: usize,
+    /// Full text of the line the position is on.
+    line: String,
+}
+
+impl Location {
+    pub(crate) fn new(text: &str, pos: usize, file: Option<&str>) -> Self {
+        // isolate the line in question:
+        let before = &text[0..pos];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        let line_end = text[pos..].find('\n').map_or(text.len(), |i| pos + i);
+        Self {
+            file: file.map(str::to_owned),
+            line_num: before.matches('\n').count() + 1,
+            col: text[line_start..pos].chars().count() + 1,
+            line: text[line_start..line_end].to_owned(),
+        }
+    }
+
+    /// Location of `part`, which must be a slice of `text`.
+    pub(crate) fn of(text: &str, part: &str, file: Option<&str>) -> Self {
+        Self::new(text, span_of(text, part).start, file)
+    }
+}
+
+/// Renders the location part of rustc-like diagnostics, e.g.:
+/// ```text
+///  --> rebuild.hr:3:14
+///   |
+/// 3 | task foo > x {
+///   |              ^
+/// ```
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let gutter = " ".repeat(self.line_num.to_string().len());
+        let file = self.file.as_deref().unwrap_or("<config>");
+        writeln!(f, "{gutter}--> {file}:{}:{}", self.line_num, self.col)?;
+        writeln!(f, "{gutter} |")?;
+        writeln!(f, "{} | {}", self.line_num, self.line)?;
+        // keep tabs, so the caret lines up with the text above it:
+        let pad: String = self
+            .line
+            .chars()
+            .take(self.col - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        write!(f, "{gutter} | {pad}^")
+    }
+}
+
+/// A syntax error, with enough context to point at exactly where it happened.
 #[derive(Debug, thiserror::Error)]
-#[error("ParseError on line '{line}': {msg}")]
 pub struct Error {
     msg: String,
-    pos: usize,
-    line: String,
+    loc: Location,
 }
 
-pub fn parse(text: &str) -> Result<Vec<crate::ast::Item<'_>>> {
-    use combine::EasyParser;
-    tapefile::items()
-        .easy_parse(text)
-        .map(|(items, _remainder)| {
-            // TODO do something w/ the remainder.
-            items
-        })
-        .map_err(|e| {
-            let pos = e.position.translate_position(text);
-            // isolate the line in question:
-            let before = &text[0..pos];
-            let after = &text[pos..text.len()];
-            let prefix: String = before.chars().rev().take_while(|&c| c != '\n').collect();
-            let prefix: String = prefix.chars().rev().collect();
-            let suffix: String = after.chars().take_while(|&c| c != '\n').collect();
-            let line = prefix + &suffix;
-            // since converting combine's errors is a lifetime nightmare,
-            // we just stringify the error before returning it.
-            Error {
-                pos,
-                line,
-                msg: format!("{}", e),
+impl Error {
+    fn new(text: &str, pos: usize, msg: String, file: Option<&str>) -> Self {
+        Self {
+            msg,
+            loc: Location::new(text, pos, file),
+        }
+    }
+}
+
+/// Renders like rustc's diagnostics, e.g.:
+/// ```text
+/// ParseError: Unexpected `{`; Expected `=`
+///  --> rebuild.hr:3:14
+///   |
+/// 3 | task foo > x {
+///   |              ^
+/// ```
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg: Vec<&str> = self.msg.lines().map(str::trim).filter(|l| !l.is_empty()).collect();
+        writeln!(f, "ParseError: {}", msg.join("; "))?;
+        write!(f, "{}", self.loc)
+    }
+}
+
+/// Parse the text of a config file.
+pub fn parse(text: &str) -> Result<Vec<Item<'_>>> {
+    parse_file(text, None)
+}
+
+/// One or more syntax errors, in the order they appear in the file.
+#[derive(Debug, thiserror::Error)]
+pub struct Errors(pub Vec<Error>);
+
+impl fmt::Display for Errors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, e) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str("\n\n")?;
             }
-            .into()
+            write!(f, "{e}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Keywords that start a top-level block; we resume parsing at the next line
+/// starting with one of these after a syntax error.
+const BLOCK_KEYWORDS: &[&str] = &[
+    "task",
+    "func",
+    "plan",
+    "global",
+    "module",
+    "import",
+    "package",
+    "versioner",
+    "constraint",
+];
+
+/// Parse the text of a config file, using `file` to describe where any errors are.
+/// If a block doesn't parse, we skip to the next one and keep going,
+/// so that every syntax error in the file is reported (as [`Errors`]) at once.
+pub fn parse_file<'a>(text: &'a str, file: Option<&str>) -> Result<Vec<Item<'a>>> {
+    Ok(parse_file_spanned(text, file)?.into_iter().map(|(_, item)| item).collect())
+}
+
+/// Parse the text of a config file as [`parse_file`] does,
+/// along with the span of text each item covers.
+pub(crate) fn parse_file_spanned<'a>(
+    text: &'a str,
+    file: Option<&str>,
+) -> Result<Vec<(Range<usize>, Item<'a>)>> {
+    let items = parse_spanned(text, file)?;
+    // ducttape syntax is only allowed in compatibility mode:
+    let errors: Vec<Error> = compat::find(items.iter().map(|(_, item)| item))
+        .into_iter()
+        .map(|found| Error {
+            msg: format!(
+                "{} \"{}\" is ducttape syntax, which needs --compat ducttape",
+                found.feature, found.name
+            ),
+            loc: Location::of(text, found.name, file),
+        })
+        .collect();
+    if errors.is_empty() {
+        Ok(items)
+    } else {
+        Err(Errors(errors).into())
+    }
+}
+
+/// Parse the text of a config file written for ducttape, as [`parse_file`] does.
+/// Sequential branchpoints are kept, since they map onto ordinary branchpoints;
+/// packages, versioners, and references to packages are left out of the items,
+/// and returned as diagnostics so they can be migrated one by one.
+pub fn parse_ducttape<'a>(
+    text: &'a str,
+    file: Option<&str>,
+) -> Result<(Vec<Item<'a>>, Vec<compat::Unsupported>)> {
+    let mut items = parse_items(text, file)?;
+    let unsupported = compat::find(&items)
+        .into_iter()
+        .filter(|found| found.feature.is_unsupported())
+        .map(|found| compat::Unsupported {
+            feature: found.feature,
+            name: found.name.to_owned(),
+            loc: Location::of(text, found.name, file),
         })
+        .collect();
+    compat::strip(&mut items);
+    Ok((items, unsupported))
+}
+
+/// Parse every item in `text`, including ducttape syntax.
+fn parse_items<'a>(text: &'a str, file: Option<&str>) -> Result<Vec<Item<'a>>> {
+    Ok(parse_spanned(text, file)?.into_iter().map(|(_, item)| item).collect())
+}
+
+/// Parse every item in `text` as [`parse_items`] does, along with the span of text each one
+/// covers, not counting the whitespace around it.
+pub(crate) fn parse_spanned<'a>(
+    text: &'a str,
+    file: Option<&str>,
+) -> Result<Vec<(Range<usize>, Item<'a>)>> {
+    use combine::EasyParser;
+    use prelude::{optional, Parser};
+
+    let mut items = Vec::new();
+    let mut errors = Vec::new();
+    let mut rest = text;
+    loop {
+        // comments are found separately, once we know where the items are:
+        if let Ok(((), after)) = optional(util::whitespace()).map(|_| ()).easy_parse(rest) {
+            rest = after;
+        }
+        if rest.is_empty() {
+            break;
+        }
+        let start = text.len() - rest.len();
+        match tapefile::item().easy_parse(rest) {
+            Ok((item, remainder)) => {
+                items.push((start..text.len() - remainder.len(), item));
+                rest = remainder;
+            }
+            Err(e) => {
+                let pos = start + e.position.translate_position(rest);
+                // since converting combine's errors is a lifetime nightmare,
+                // we just stringify the error before returning it.
+                let msg = ErrorMsg(&e.errors).to_string();
+                errors.push(Error::new(text, pos, msg, file));
+                match next_block_start(text, pos.max(start + 1)) {
+                    Some(next) => rest = &text[next..],
+                    None => break,
+                }
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(attach_comments(text, items))
+    } else {
+        Err(Errors(errors).into())
+    }
+}
+
+/// Add the comments in `text` to the items they're in (tasks, funcs, plans, and global blocks),
+/// and the rest as items of their own, in order. Comments in other items come before them.
+fn attach_comments<'a>(
+    text: &'a str,
+    items: Vec<(Range<usize>, Item<'a>)>,
+) -> Vec<(Range<usize>, Item<'a>)> {
+    let mut code = Vec::new();
+    for (_, item) in &items {
+        match item {
+            Item::Task(block) | Item::Func(block) | Item::Package(block) => {
+                code.push(span_of(text, block.code.text));
+            }
+            Item::Versioner(group) => {
+                code.extend(group.blocks.iter().map(|block| span_of(text, block.code.text)));
+            }
+            _ => (),
+        }
+    }
+
+    // sorted by position, and for items in the same place, comments go first:
+    let mut sorted: Vec<((usize, bool), Range<usize>, Item<'a>)> =
+        items.into_iter().map(|(span, item)| ((span.start, true), span, item)).collect();
+    let item_count = sorted.len();
+    for comment in find_comments(text, &code) {
+        let i = sorted[..item_count].partition_point(|(_, span, _)| span.start <= comment.start);
+        let containing = i.checked_sub(1).filter(|&i| sorted[i].1.end > comment.start);
+        let comment_text = &text[comment.clone()];
+        let key = match containing.map(|i| &mut sorted[i]) {
+            Some((_, _, Item::Task(block) | Item::Func(block) | Item::Package(block))) => {
+                block.comments.push(comment_text);
+                continue;
+            }
+            Some((_, _, Item::Plan(plan))) => {
+                plan.comments.push(comment_text);
+                continue;
+            }
+            Some((_, _, Item::GlobalConfig(config))) => {
+                config.comments.push(comment_text);
+                continue;
+            }
+            Some((_, span, _)) => (span.start, false),
+            None => (comment.start, false),
+        };
+        sorted.push((key, comment, Item::Comment(comment_text)));
+    }
+    sorted.sort_by_key(|(key, span, _)| (*key, span.start));
+    sorted.into_iter().map(|(_, span, item)| (span, item)).collect()
+}
+
+/// Spans of the comments in `text`, skipping the (sorted) spans of `code`,
+/// and anything in double quotes.
+fn find_comments(text: &str, code: &[Range<usize>]) -> Vec<Range<usize>> {
+    let bytes = text.as_bytes();
+    let mut comments = Vec::new();
+    let mut code = code.iter().peekable();
+    let mut quoted = false;
+    let mut i = 0;
+    while i < bytes.len() {
+        if let Some(span) = code.next_if(|span| span.start <= i) {
+            i = i.max(span.end);
+            continue;
+        }
+        match bytes[i] {
+            b'"' => quoted = !quoted,
+            b'#' if !quoted => {
+                let end = text[i..].find('\n').map_or(text.len(), |n| i + n);
+                comments.push(i..end);
+                i = end;
+                continue;
+            }
+            _ => (),
+        }
+        i += 1;
+    }
+    comments
+}
+
+/// Span of `part`, which must be a slice of `text`.
+pub(crate) fn span_of(text: &str, part: &str) -> Range<usize> {
+    let start = part.as_ptr() as usize - text.as_ptr() as usize;
+    start..start + part.len()
+}
+
+/// Position of the first line at or after `pos` that starts with a block keyword.
+fn next_block_start(text: &str, pos: usize) -> Option<usize> {
+    let mut line_start = text[..pos].rfind('\n').map_or(0, |i| i + 1);
+    // the line containing `pos` is part of the block that failed:
+    loop {
+        line_start += text[line_start..].find('\n')? + 1;
+        let line = &text[line_start..];
+        let starts_block = BLOCK_KEYWORDS.iter().any(|keyword| {
+            line.strip_prefix(keyword)
+                .is_some_and(|after| after.starts_with(|c: char| c.is_whitespace()))
+        });
+        if starts_block {
+            return Some(line_start);
+        }
+    }
+}
+
+/// Displays combine's errors without the (meaningless to users) pointer position.
+struct ErrorMsg<'e, 'a>(&'e [combine::easy::Error<char, &'a str>]);
+
+impl fmt::Display for ErrorMsg<'_, '_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        combine::easy::Error::fmt_errors(self.0, f)
+    }
 }
 
 pub mod prelude {
@@ -55,9 +365,15 @@ pub mod util {
         }
     }
 
+    p! {
+        ident_rest_char() -> char, {
+            char('_').or(alpha_num())
+        }
+    }
+
     p! {
         ident_rest() -> Vec<char>, {
-            many(char('_').or(alpha_num()))
+            many(ident_rest_char())
         }
     }
 
@@ -86,7 +402,7 @@ pub mod util {
             recognize(
                 char('#')
                 .and(skip_many(none_of("\n".chars())))
-                .and(char('\n'))
+                .and(char('\n').map(|_| ()).or(eof()))
             )
         }
     }
@@ -163,6 +479,13 @@ pub mod util {
         }
     }
 
+    // like `line`, but leaves what follows the end of the line (e.g. comments) alone:
+    wrapper! {
+        statement(parser), {
+            lex_inline(parser).skip(eof().or(char('\n').map(|_| ())))
+        }
+    }
+
     repeater! {
         comma_delim(parser), {
             sep_by1(lex(parser), char(','))
@@ -261,7 +584,7 @@ mod literal {
     p! {
         unquoted_literal_char() -> char, {
             satisfy(|c: char|
-                !c.is_whitespace() && !FORBID_UNQUOTED.iter().any(|&forbidden| forbidden == c)
+                !c.is_whitespace() && !FORBID_UNQUOTED.contains(&c)
             )
         }
     }
@@ -278,10 +601,18 @@ mod literal {
         }
     }
 
+    p! {
+        unquoted_interp_literal() -> &'a str, {
+            recognize(skip_many1(
+                unquoted_literal_char().map(|_| ()).or(super::interp::env_var().map(|_| ()))
+            ))
+        }
+    }
+
     p! {
         interp_literal() -> (&'a str, Vec<&'a str>), {
             super::interp::double_quoted_interp_string()
-                .or(unquoted_literal().map(|s| (s, Vec::with_capacity(0))))
+                .or(unquoted_interp_literal().map(|s| (s, Vec::with_capacity(0))))
         }
     }
 
@@ -309,12 +640,26 @@ mod literal {
             );
             Ok(())
         }
+        #[test]
+        fn test_unquoted_interp_literal() -> Result<()> {
+            assert_eq!(
+                ("$ENV{HOME}/src", vec![]),
+                super::interp_literal().easy_parse("$ENV{HOME}/src").unwrap().0
+            );
+            assert_eq!(
+                ("${env:HOME}", vec![]),
+                super::interp_literal().easy_parse("${env:HOME}").unwrap().0
+            );
+            assert!(super::interp_literal().easy_parse("$HOME").is_err());
+            Ok(())
+        }
     }
 }
 
 mod interp {
     use super::prelude::*;
     use super::rhs::variable;
+    use super::util::ident;
     use combine::parser::range::recognize_with_value;
 
     p! {
@@ -323,10 +668,20 @@ mod interp {
         }
     }
 
+    p! {
+        env_var() -> &'a str, {
+            // '$ENV{NAME}' or '${env:NAME}'; expanded when the workflow is loaded:
+            recognize(
+                attempt(string("$ENV{").with(ident()).skip(char('}')))
+                    .or(attempt(string("${env:").with(ident()).skip(char('}'))))
+            )
+        }
+    }
+
     p! {
         interp_content() -> (&'a str, Vec<&'a str>), {
             recognize_with_value(
-                skip_many(none_of("$\"\\".chars()))
+                skip_many(none_of("$\"\\".chars()).map(|_| ()).or(env_var().map(|_| ())))
                     .with(optional(variable().and(interp_content())))
             ).map(|(full_text, parsed_suffix)| {
                 if let Some((var, (_, mut rest_vars))) = parsed_suffix {
@@ -353,7 +708,10 @@ mod graft {
 
     p! {
         branch_element() -> (&'a str, &'a str), {
-            ident().skip(char(':')).and(lex_inline(branch_ident()))
+            // '*' grafts every branch of the branchpoint:
+            ident().skip(char(':')).and(lex_inline(
+                recognize(char('*')).or(branch_ident())
+            ))
         }
     }
 
@@ -384,6 +742,10 @@ mod graft {
                     .unwrap()
                     .0
             );
+            assert_eq!(
+                vec![("Bp1", "*"), ("Bp2", "val2")],
+                super::branch_graft().easy_parse("[Bp1: *, Bp2: val2]").unwrap().0
+            );
             Ok(())
         }
     }
@@ -396,6 +758,7 @@ mod rhs {
     use super::prelude::*;
     use super::util::{branch_ident, ident, lex_inline, parens, whitespace};
     use crate::ast::Rhs;
+    use combine::parser::char::digit;
 
     p! {
         shorthand_variable() -> char, {
@@ -484,9 +847,36 @@ mod rhs {
         }
     }
 
+    p! {
+        number() -> u64, {
+            recognize(skip_many1(digit())).and_then(|digits: &'a str| digits.parse())
+        }
+    }
+
+    p! {
+        sequence() -> (&'a str, u64, u64, u64), {
+            // ducttape's sequential branchpoints, (Branchpoint: start..end..step):
+            parens(
+                optional(whitespace())
+                    .with(branchpoint_prefix())
+                    .skip(optional(whitespace()))
+                    .and(number())
+                    .skip(string(".."))
+                    .and(number())
+                    .and(optional(string("..").with(number())))
+                    .skip(optional(whitespace()))
+            ).map(|(((branchpoint, start), end), step)| {
+                (branchpoint, start, end, step.unwrap_or(1))
+            })
+        }
+    }
+
     p! {
         rhs() -> Rhs<'a>, {
             choice!(
+                attempt(sequence()).map(|(branchpoint, start, end, step)| {
+                    Rhs::Sequence { branchpoint, start, end, step }
+                }),
                 branchpoint().map(|(branchpoint, vals)| Rhs::Branchpoint { branchpoint, vals }),
                 attempt(
                     shorthand_grafted_task_output()
@@ -556,6 +946,21 @@ mod rhs {
             Ok(())
         }
         #[test]
+        fn test_env_var() -> Result<()> {
+            assert_eq!(
+                Rhs::literal("$ENV{HOME}/sdk"),
+                super::rhs().easy_parse("$ENV{HOME}/sdk").unwrap().0
+            );
+            assert_eq!(
+                Rhs::Interp {
+                    text: "${env:HOME}/$name",
+                    vars: vec!["name"]
+                },
+                super::rhs().easy_parse("\"${env:HOME}/$name\"").unwrap().0
+            );
+            Ok(())
+        }
+        #[test]
         fn test_task_output() -> Result<()> {
             assert_eq!(
                 Rhs::shorthand_grafted_task_output("task", vec![("Bp1", "val1")]),
@@ -611,6 +1016,33 @@ mod rhs {
             );
             Ok(())
         }
+        #[test]
+        fn test_sequence() -> Result<()> {
+            assert_eq!(
+                Rhs::Sequence {
+                    branchpoint: "N",
+                    start: 1,
+                    end: 10,
+                    step: 2
+                },
+                super::rhs().easy_parse("(N: 1..10..2)").unwrap().0
+            );
+            assert_eq!(
+                Rhs::Sequence {
+                    branchpoint: "Seed",
+                    start: 0,
+                    end: 3,
+                    step: 1
+                },
+                super::rhs().easy_parse("(\n  Seed:\n  0..3\n)").unwrap().0
+            );
+            // branches that start with numbers are still branches:
+            assert_eq!(
+                Rhs::branchpoint("N", vec![("1", Rhs::Unbound), ("2", Rhs::Unbound)]),
+                super::rhs().easy_parse("(N: 1 2)").unwrap().0
+            );
+            Ok(())
+        }
     }
 }
 
@@ -618,8 +1050,8 @@ mod assignment {
 
     use super::prelude::*;
     use super::rhs::rhs;
-    use super::util::{ident, lex_inline, line_internal_whitespace};
-    use crate::ast::Rhs;
+    use super::util::{ident, ident_rest_char, lex_inline, line_internal_whitespace};
+    use crate::ast::{ParamType, Rhs};
 
     p! {
         assignment() -> (&'a str, Rhs<'a>), {
@@ -632,6 +1064,42 @@ mod assignment {
         }
     }
 
+    p! {
+        enum_value() -> &'a str, {
+            recognize(skip_many1(satisfy(|c: char| c.is_alphanumeric() || "_-.".contains(c))))
+        }
+    }
+
+    p! {
+        param_type() -> ParamType<'a>, {
+            choice!(
+                attempt(string("int").skip(not_followed_by(ident_rest_char()))).map(|_| ParamType::Int),
+                attempt(string("bool").skip(not_followed_by(ident_rest_char()))).map(|_| ParamType::Bool),
+                attempt(string("path").skip(not_followed_by(ident_rest_char()))).map(|_| ParamType::Path),
+                enum_value()
+                    .and(many1(char('|').with(enum_value())))
+                    .map(|(first, mut rest): (&'a str, Vec<&'a str>)| {
+                        rest.insert(0, first);
+                        ParamType::OneOf(rest)
+                    })
+            ).expected("param type (int, bool, path, or a|b|c)")
+        }
+    }
+
+    p! {
+        typed_assignment() -> (&'a str, Option<ParamType<'a>>, Rhs<'a>), {
+            ident()
+                .and(optional(attempt(char(':').with(param_type()))))
+                .and(
+                    choice!(
+                        attempt(lex_inline(char('=')).with(rhs())),
+                        optional(line_internal_whitespace()).map(|_| Rhs::Unbound)
+                    )
+                )
+                .map(|((lhs, ty), rhs)| (lhs, ty, rhs))
+        }
+    }
+
     p! {
         dot_assignment() -> (&'a str, Rhs<'a>), {
             char('.').with(ident()).and(
@@ -697,31 +1165,30 @@ mod assignment {
             );
             Ok(())
         }
-        // // in DT, I think a grafted glob produces a space-separated list,
-        // // but presumably it only works for a single branchpoint.
-        // #[test]
-        // fn test_graft_shorthand_glob() -> Result<()> {
-        //     assert_eq!(
-        //         (
-        //             "dataset_json",
-        //             Rhs::ShorthandGraftedTaskOutput {
-        //                 task: "DumpHFDataset",
-        //                 branch: vec![("Dataset", "*")],
-        //             }
-        //         ),
-        //         super::assignment()
-        //             .easy_parse("dataset_json=@DumpHFDataset[Dataset:*]")
-        //             .unwrap()
-        //             .0
-        //     );
-        //     Ok(())
-        // }
+        // a grafted glob produces a space-separated list of the value in every branch:
+        #[test]
+        fn test_graft_shorthand_glob() -> Result<()> {
+            assert_eq!(
+                (
+                    "dataset_json",
+                    Rhs::ShorthandGraftedTaskOutput {
+                        task: "DumpHFDataset",
+                        branch: vec![("Dataset", "*")],
+                    }
+                ),
+                super::assignment()
+                    .easy_parse("dataset_json=@DumpHFDataset[Dataset:*]")
+                    .unwrap()
+                    .0
+            );
+            Ok(())
+        }
     }
 }
 
 mod spec {
 
-    use super::assignment::{assignment, dot_assignment};
+    use super::assignment::{assignment, dot_assignment, typed_assignment};
     use super::prelude::*;
     use super::util::{ident, lex, lex_inline};
     use crate::ast::BlockSpec;
@@ -746,8 +1213,8 @@ mod spec {
         param_assignment() -> BlockSpec<'a>, {
             // special case since params can start with '.':
             choice! (
-                assignment().map(|(lhs, rhs)| BlockSpec::Param{lhs, rhs, dot: false}),
-                dot_assignment().map(|(lhs, rhs)| BlockSpec::Param{lhs, rhs, dot: true})
+                typed_assignment().map(|(lhs, ty, rhs)| BlockSpec::Param{lhs, rhs, dot: false, ty}),
+                dot_assignment().map(|(lhs, rhs)| BlockSpec::Param{lhs, rhs, dot: true, ty: None})
             )
         }
     }
@@ -759,13 +1226,14 @@ mod spec {
         }
     }
 
-    // p! {
-    //     package_chunk() -> Vec<BlockSpec<'a>>, {
-    //         lex_inline(char(':')).with(many(
-    //             lex_inline(ident()).map(|name| BlockSpec::Package{name})
-    //         ))
-    //     }
-    // }
+    p! {
+        package_chunk() -> Vec<BlockSpec<'a>>, {
+            // a single ':', as opposed to the '::' that starts params:
+            lex_inline(char(':').skip(not_followed_by(char(':')))).with(many1(
+                lex_inline(ident()).map(|name| BlockSpec::Package{name})
+            ))
+        }
+    }
 
     p! {
         module_chunk() -> Vec<BlockSpec<'a>>, {
@@ -783,8 +1251,8 @@ mod spec {
                 attempt(input_chunk()),
                 attempt(output_chunk()),
                 attempt(param_chunk()),
-                module_chunk()
-                // package_chunk()
+                module_chunk(),
+                package_chunk()
             )
         }
     }
@@ -814,14 +1282,45 @@ mod spec {
                 vec![
                     BlockSpec::output("output", Rhs::literal("filename.tgz")),
                     BlockSpec::input("input1", Rhs::task_output("output", "task")),
-                    // BlockSpec::package("package_name"),
+                    BlockSpec::package("package_name"),
                     BlockSpec::param("param1", Rhs::variable("var")),
                     BlockSpec::dot_param("param2", Rhs::literal("value")),
                 ],
-                super::specs().easy_parse(
-                    "> output=filename.tgz < input1=$output@task \n:: param1=$var .param2=value"
-                ).unwrap().0
+                super::specs()
+                    .easy_parse(
+                        "> output=filename.tgz < input1=$output@task : package_name\n\
+                     :: param1=$var .param2=value"
+                    )
+                    .unwrap()
+                    .0
+            );
+            Ok(())
+        }
+        #[test]
+        fn test_typed_params() -> Result<()> {
+            use crate::ast::ParamType;
+            assert_eq!(
+                vec![
+                    BlockSpec::typed_param("jobs", ParamType::Int, Rhs::literal("4")),
+                    BlockSpec::typed_param("fast", ParamType::Bool, Rhs::variable("fast")),
+                    BlockSpec::typed_param("sdk", ParamType::Path, Rhs::Unbound),
+                    BlockSpec::typed_param(
+                        "arch",
+                        ParamType::OneOf(vec!["x86-64", "arm64"]),
+                        Rhs::branchpoint("Arch", vec![("x86_64", Rhs::literal("x86-64"))]),
+                    ),
+                    BlockSpec::param("integer", Rhs::literal("1")),
+                ],
+                super::param_chunk()
+                    .easy_parse(
+                        ":: jobs:int=4 fast:bool=$fast sdk:path arch:x86-64|arm64=(Arch: x86_64=x86-64) integer=1"
+                    )
+                    .unwrap()
+                    .0
             );
+            // unknown types aren't part of the param:
+            let parsed = super::param_chunk().easy_parse(":: jobs:integer=4");
+            assert!(parsed.map_or(true, |(_, rest)| !rest.is_empty()));
             Ok(())
         }
         #[test]
@@ -842,19 +1341,21 @@ mod spec {
 mod tasklike {
     use super::prelude::*;
     use super::spec::specs;
-    use super::util::{braces, ident, lex_inline};
+    use super::util::{braces, ident, lex_inline, whitespace};
     use crate::ast::{BlockType, TasklikeBlock};
     use crate::bash::bash_code;
 
     p! {
         block_name(keyword: &'static str) -> &'a str, {
-            lex_inline(string(keyword)).with(ident())
+            // keywords can share a prefix (e.g. "plan" and "package"):
+            lex_inline(attempt(string(keyword))).with(ident())
         }
     }
 
     p! {
         tasklike_block(keyword: &'static str, subtype: BlockType) -> TasklikeBlock<'a>, {
             block_name(keyword)
+                .skip(optional(whitespace()))
                 .and(specs())
                 .and(braces(bash_code()))
                 .map(|((name, specs), code)| {
@@ -863,6 +1364,7 @@ mod tasklike {
                         subtype: *subtype,
                         specs,
                         code,
+                        comments: Vec::new(),
                     }
                 })
 
@@ -875,38 +1377,55 @@ mod tasklike {
         }
     }
 
-    // p! {
-    //     package() -> TasklikeBlock<'a>, {
-    //         tasklike_block("package", BlockType::Package)
-    //     }
-    // }
+    p! {
+        func() -> TasklikeBlock<'a>, {
+            tasklike_block("func", BlockType::Func)
+        }
+    }
+
+    p! {
+        package() -> TasklikeBlock<'a>, {
+            tasklike_block("package", BlockType::Package)
+        }
+    }
 
     #[cfg(test)]
     mod test {
+        use crate::ast::{BlockSpec, BlockType, Rhs};
         use anyhow::Result;
         use combine::EasyParser;
-        // use crate::HashSet;
-        // use crate::ast::{TasklikeBlock, BlockSpec, BlockType, BashCode};
         #[test]
         fn test_task() -> Result<()> {
             assert_eq!(
                 "task_name",
                 super::block_name("task").easy_parse("task task_name").unwrap().0
             );
-            // assert_eq!(
-            //     TasklikeBlock {
-            //         name: "task_name",
-            //         subtype: BlockType::Task,
-            //         specs: vec![BlockSpec::package("package_name")],
-            //         code: BashCode {
-            //             code: "echo 'hi'",
-            //             vars: HashSet::default(),
-            //         }
-            //     },
-            //     super::task().easy_parse(
-            //         "task task_name\n  : package_name\n{\n  echo 'hi'\n}"
-            //     ).unwrap().0
-            // );
+            let func = super::func().easy_parse("func greet {\n  echo \"hi $1\"\n}").unwrap().0;
+            assert_eq!(func.name, "greet");
+            assert_eq!(func.subtype, crate::ast::BlockType::Func);
+            assert!(func.specs.is_empty());
+            let task = super::task()
+                .easy_parse("task task_name\n  : package_name\n{\n  echo 'hi'\n}")
+                .unwrap()
+                .0;
+            assert_eq!(task.specs, vec![BlockSpec::package("package_name")]);
+            Ok(())
+        }
+        #[test]
+        fn test_package() -> Result<()> {
+            let package = super::package()
+                .easy_parse("package lm :: .versioner=git .ref=HEAD {\n  make\n}")
+                .unwrap()
+                .0;
+            assert_eq!(package.name, "lm");
+            assert_eq!(package.subtype, BlockType::Package);
+            assert_eq!(
+                package.specs,
+                vec![
+                    BlockSpec::dot_param("versioner", Rhs::literal("git")),
+                    BlockSpec::dot_param("ref", Rhs::literal("HEAD")),
+                ]
+            );
             Ok(())
         }
     }
@@ -927,9 +1446,13 @@ mod grouplike {
             internal_subtype: BlockType
         ) -> GrouplikeBlock<'a>, {
             block_name(keyword)
+                .skip(optional(whitespace()))
                 .and(specs())
                 .and(braces(
-                    sep_by(tasklike_block(internal_keyword, *internal_subtype), whitespace())
+                    optional(whitespace()).with(sep_end_by(
+                        tasklike_block(internal_keyword, *internal_subtype),
+                        whitespace(),
+                    ))
                 ))
                 .map(|((name, specs), blocks)| {
                     GrouplikeBlock {
@@ -942,31 +1465,59 @@ mod grouplike {
         }
     }
 
-    // p! {
-    //     versioner() -> GrouplikeBlock<'a>, {
-    //         grouplike_block(
-    //             "versioner",
-    //             BlockType::Versioner,
-    //             "action",
-    //             BlockType::Action,
-    //         )
-    //     }
-    // }
+    p! {
+        versioner() -> GrouplikeBlock<'a>, {
+            grouplike_block(
+                "versioner",
+                BlockType::Versioner,
+                "action",
+                BlockType::Action,
+            )
+        }
+    }
+
+    #[cfg(test)]
+    mod test {
+        use crate::ast::BlockType;
+        use combine::EasyParser;
+        #[test]
+        fn test_versioner() {
+            let versioner = super::versioner()
+                .easy_parse(
+                    "versioner git :: repo ref {\n  \
+                       action checkout > dir {\n    git clone $repo $dir\n  }\n  \
+                       action repo_version > version {\n    echo $ref > $version\n  }\n\
+                     }",
+                )
+                .unwrap()
+                .0;
+            assert_eq!(versioner.name, "git");
+            assert_eq!(versioner.specs.len(), 2);
+            let actions: Vec<_> = versioner.blocks.iter().map(|a| (a.name, a.subtype)).collect();
+            assert_eq!(
+                actions,
+                [
+                    ("checkout", BlockType::Action),
+                    ("repo_version", BlockType::Action)
+                ]
+            );
+        }
+    }
 }
 
 mod config {
     use super::assignment::assignment;
     use super::prelude::*;
     use super::util::{braces, lex, line, whitespace};
-    use crate::ast::Rhs;
+    use crate::ast::GlobalConfig;
 
     p! {
-        global_config() -> Vec<(&'a str, Rhs<'a>)>, {
+        global_config() -> GlobalConfig<'a>, {
             lex(string("global")).with(braces(
                 optional(whitespace()).with(
                     many(line(assignment()))
                 )
-            ))
+            )).map(|assignments| GlobalConfig { assignments, comments: Vec::new() })
         }
     }
 }
@@ -1001,26 +1552,81 @@ mod plan {
         }
     }
 
+    p! {
+        exclusion() -> Vec<(&'a str, Branches<'a>)>, {
+            attempt(lex(string("except"))).with(parens(
+                sep_by1(
+                    lex(ident()).skip(lex(char(':'))).and(lex(branches())),
+                    lex(char('*')),
+                )
+            ))
+        }
+    }
+
     p! {
         cross_product() -> CrossProduct<'a>, {
             lex(string("reach"))
                 .with(comma_delim(ident()))
                 .and(optional(branch_selections()))
-                .map(|(goals, branches)| {
+                .and(many(exclusion()))
+                .map(|((goals, branches), excludes)| {
                     let branches = branches.unwrap_or_default();
-                    CrossProduct { goals, branches }
+                    CrossProduct { goals, branches, excludes }
                 })
         }
     }
 
+    /// One line of a plan.
+    pub enum PlanItem<'a> {
+        Include(&'a str),
+        Baseline(Vec<(&'a str, &'a str)>),
+        Reach(CrossProduct<'a>),
+    }
+
+    p! {
+        include() -> &'a str, {
+            lex_inline(string("include")).with(ident())
+        }
+    }
+
+    p! {
+        baseline() -> Vec<(&'a str, &'a str)>, {
+            lex(string("baseline")).with(sep_by1(
+                parens(lex(ident()).skip(lex(char(':'))).and(lex(branch_ident()))),
+                attempt(lex(char('*'))),
+            ))
+        }
+    }
+
+    p! {
+        plan_item() -> PlanItem<'a>, {
+            include().map(PlanItem::Include)
+                .or(baseline().map(PlanItem::Baseline))
+                .or(cross_product().map(PlanItem::Reach))
+        }
+    }
+
     p! {
         plan() -> Plan<'a>, {
             lex_inline(string("plan")).with(ident())
                 .skip(whitespace())
                 .and(braces(
-                    many(lex(cross_product()))
+                    // lines can optionally be separated by semicolons:
+                    many(lex(plan_item()).skip(optional(lex(char(';')))))
                 ))
-                .map(|(name, cross_products)| Plan { name, cross_products })
+                .map(|(name, items): (_, Vec<PlanItem<'a>>)| {
+                    let mut cross_products = Vec::new();
+                    let mut includes = Vec::new();
+                    let mut baselines = Vec::new();
+                    for item in items {
+                        match item {
+                            PlanItem::Include(plan) => includes.push(plan),
+                            PlanItem::Baseline(branches) => baselines.extend(branches),
+                            PlanItem::Reach(cross_product) => cross_products.push(cross_product),
+                        }
+                    }
+                    Plan { name, cross_products, includes, baselines, comments: Vec::new() }
+                })
         }
     }
 
@@ -1035,9 +1641,30 @@ mod plan {
                 CrossProduct {
                     goals: vec!["task"],
                     branches: vec![],
+                    excludes: vec![],
                 },
                 cross_product().easy_parse("reach task").unwrap().0
             );
+            assert_eq!(
+                CrossProduct {
+                    goals: vec!["task"],
+                    branches: vec![("Os", Branches::Glob), ("Arch", Branches::Glob)],
+                    excludes: vec![
+                        vec![
+                            ("Os", Branches::Specified(vec!["windows"])),
+                            ("Arch", Branches::Specified(vec!["arm64"])),
+                        ],
+                        vec![("Os", Branches::Specified(vec!["mac", "linux"]))],
+                    ],
+                },
+                cross_product()
+                    .easy_parse(
+                        "reach task via (Os: *) * (Arch: *) except (Os: windows * Arch: arm64) \
+                         except (Os: mac linux)"
+                    )
+                    .unwrap()
+                    .0
+            );
         }
         #[test]
         fn test_plan() {
@@ -1047,10 +1674,54 @@ mod plan {
                     cross_products: vec![CrossProduct {
                         goals: vec!["task"],
                         branches: vec![],
+                        excludes: vec![],
                     }],
+                    includes: vec![],
+                    baselines: vec![],
+                    comments: vec![],
                 },
                 plan().easy_parse("plan plan {\n  reach task\n}").unwrap().0
             );
+            assert_eq!(
+                Plan {
+                    name: "release",
+                    cross_products: vec![CrossProduct {
+                        goals: vec!["notarize"],
+                        branches: vec![("Profile", Branches::Specified(vec!["release"]))],
+                        excludes: vec![],
+                    }],
+                    includes: vec!["debug", "test"],
+                    baselines: vec![],
+                    comments: vec![],
+                },
+                plan()
+                    .easy_parse(
+                        "plan release { include debug; include test\n  \
+                         reach notarize via (Profile: release) }"
+                    )
+                    .unwrap()
+                    .0
+            );
+            assert_eq!(
+                Plan {
+                    name: "release",
+                    cross_products: vec![CrossProduct {
+                        goals: vec!["notarize"],
+                        branches: vec![],
+                        excludes: vec![],
+                    }],
+                    includes: vec!["debug"],
+                    baselines: vec![("Profile", "release"), ("Os", "linux"), ("Arch", "arm64")],
+                    comments: vec![],
+                },
+                plan()
+                    .easy_parse(
+                        "plan release {\n  baseline (Profile: release) * (Os: linux)\n  \
+                         baseline (Arch: arm64)\n  include debug\n  reach notarize\n}"
+                    )
+                    .unwrap()
+                    .0
+            );
         }
         #[test]
         fn test_branches() {
@@ -1072,31 +1743,94 @@ mod misc {
     use super::assignment::assignment;
     use super::literal::literal;
     use super::prelude::*;
-    use super::util::{lex_inline, line};
-    use crate::ast::Rhs;
+    use super::rhs::rhs;
+    use super::util::{branch_ident, ident, lex_inline, statement};
+    use crate::ast::{Constraint, ConstraintKind, Rhs};
 
     p! {
         import_statement() -> &'a str, {
-            line(
+            statement(
                 lex_inline(string("import")).with(literal())
             )
         }
     }
 
     p! {
-        module_statement() -> (&'a str, Rhs<'a>), {
-            line(
-                lex_inline(string("module")).with(assignment())
-            )
+        module_statement() -> (&'a str, Rhs<'a>, Option<Rhs<'a>>), {
+            statement(
+                lex_inline(string("module"))
+                    .with(assignment())
+                    .and(optional(module_rev()))
+            ).map(|((name, path), rev)| (name, path, rev))
+        }
+    }
+
+    p! {
+        module_rev() -> Rhs<'a>, {
+            lex_inline(string("::"))
+                .with(lex_inline(string("rev")))
+                .with(lex_inline(char('=')))
+                .with(rhs())
+        }
+    }
+
+    p! {
+        branch_pair() -> (&'a str, &'a str), {
+            ident().skip(char('.')).and(branch_ident())
+        }
+    }
+
+    p! {
+        constraint_kind() -> ConstraintKind, {
+            string("requires").map(|_| ConstraintKind::Requires)
+                .or(string("excludes").map(|_| ConstraintKind::Excludes))
+        }
+    }
+
+    p! {
+        constraint_statement() -> Constraint<'a>, {
+            statement(
+                lex_inline(string("constraint"))
+                    .with(lex_inline(branch_pair()))
+                    .and(lex_inline(constraint_kind()))
+                    .and(branch_pair())
+            ).map(|((branch, kind), other)| Constraint { branch, kind, other })
         }
     }
 
     #[cfg(test)]
     mod test {
+        use crate::ast::{Constraint, ConstraintKind};
         use anyhow::Result;
         use combine::EasyParser;
         // use crate::HashSet;
         // use crate::ast::Item;
+        #[test]
+        fn test_constraint() {
+            assert_eq!(
+                Constraint {
+                    branch: ("Os", "windows"),
+                    kind: ConstraintKind::Requires,
+                    other: ("Arch", "x64"),
+                },
+                super::constraint_statement()
+                    .easy_parse("constraint Os.windows requires Arch.x64\n")
+                    .unwrap()
+                    .0
+            );
+            assert_eq!(
+                ConstraintKind::Excludes,
+                super::constraint_statement()
+                    .easy_parse("constraint  Os.mac  excludes Arch.32bit")
+                    .unwrap()
+                    .0
+                    .kind
+            );
+            assert!(super::constraint_statement()
+                .easy_parse("constraint Os.windows needs Arch.x64")
+                .is_err());
+        }
+
         #[test]
         fn test_import() -> Result<()> {
             assert_eq!(
@@ -1119,16 +1853,43 @@ mod misc {
             // );
             Ok(())
         }
+        #[test]
+        fn test_module() -> Result<()> {
+            use crate::ast::Rhs;
+            assert_eq!(
+                ("cargo", Rhs::literal("/code/crate"), None),
+                super::module_statement().easy_parse("module cargo=/code/crate\n").unwrap().0
+            );
+            assert_eq!(
+                (
+                    "cargo",
+                    Rhs::literal("/code/crate"),
+                    Some(Rhs::branchpoint(
+                        "Ver",
+                        vec![("old", Rhs::literal("v1.0")), ("new", Rhs::literal("main"))]
+                    ))
+                ),
+                super::module_statement()
+                    .easy_parse("module cargo=/code/crate :: rev=(Ver: old=v1.0 new=main)\n")
+                    .unwrap()
+                    .0
+            );
+            assert!(super::module_statement()
+                .easy_parse("module cargo=/code/crate :: tag=v1.0\n")
+                .is_err());
+            Ok(())
+        }
     }
 }
 
 mod tapefile {
     use super::{
         config::global_config,
-        misc::{import_statement, module_statement},
+        grouplike::versioner,
+        misc::{constraint_statement, import_statement, module_statement},
         plan::plan,
         prelude::*,
-        tasklike::task,
+        tasklike::{func, package, task},
         util::lex,
     };
     use crate::ast::Item;
@@ -1136,15 +1897,15 @@ mod tapefile {
     p! {
         item() -> Item<'a>, {
             choice!(
-                //versioner().map(Item::Versioner),
+                versioner().map(Item::Versioner),
                 import_statement().map(Item::Import),
-                module_statement().map(|(k, v)| Item::Module(k, v)),
+                module_statement().map(|(k, v, rev)| Item::Module(k, v, rev)),
                 task().map(Item::Task),
+                func().map(Item::Func),
                 global_config().map(Item::GlobalConfig),
-                plan().map(Item::Plan)
-                // NB this wouldn't parse, b/c the "p" gets picked up by "plan":
-                // package().map(Item::Package)
-
+                package().map(Item::Package),
+                plan().map(Item::Plan),
+                constraint_statement().map(Item::Constraint)
             )
         }
     }
@@ -1155,3 +1916,84 @@ mod tapefile {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::ast::Item;
+    #[test]
+    fn test_error_display() {
+        let text = "global {\n  x=1\n}\n\ntask foo > out=x.txt :: y=(Y: {\n}\n";
+        let e = super::parse_file(text, Some("rebuild.hr")).unwrap_err();
+        let mut errors = e.downcast::<super::Errors>().unwrap().0;
+        assert_eq!(errors.len(), 1);
+        let e = errors.remove(0);
+        assert_eq!((e.loc.line_num, e.loc.col), (5, 26));
+        let rendered = e.to_string();
+        let lines: Vec<&str> = rendered.lines().collect();
+        assert!(lines[0].starts_with("ParseError: Unexpected `=`; Expected"));
+        assert_eq!(lines[1], " --> rebuild.hr:5:26");
+        assert_eq!(lines[3], "5 | task foo > out=x.txt :: y=(Y: {");
+        assert_eq!(lines[4], format!("  | {}^", " ".repeat(25)));
+
+        let e = super::parse("plan p {\n\treach x via (A b)\n}\n").unwrap_err().to_string();
+        assert!(e.contains(" --> <config>:2:17\n"));
+        assert!(
+            e.ends_with("2 | \treach x via (A b)\n  | \t               ^"),
+            "{e}"
+        );
+    }
+    #[test]
+    fn test_comments() {
+        let text = "# header\n\
+                    module src=. # not allowed here, so not a comment\n";
+        assert!(super::parse(text).is_err());
+
+        let text = "# header\n\
+                    module src=.\n\
+                    # about a\n\
+                    task a # the task\n  > out=\"#1.txt\" # output\n{\n  # code\n  touch $out\n}\n\
+                    global {\n  # config\n  x=(X:\n    # inside a value\n    y z\n  )\n}\n\
+                    plan p {\n  reach a # all of it\n}\n# trailer";
+        let items = super::parse(text).unwrap();
+        assert_eq!(items[0], Item::Comment("# header"));
+        assert!(matches!(items[1], Item::Module(..)));
+        assert_eq!(items[2], Item::Comment("# about a"));
+        let Item::Task(task) = &items[3] else {
+            panic!("expected a task, got {:?}", items[3]);
+        };
+        assert_eq!(task.comments, ["# the task", "# output"]);
+        let Item::GlobalConfig(config) = &items[4] else {
+            panic!("expected a global block, got {:?}", items[4]);
+        };
+        assert_eq!(config.comments, ["# config", "# inside a value"]);
+        let Item::Plan(plan) = &items[5] else {
+            panic!("expected a plan, got {:?}", items[5]);
+        };
+        assert_eq!(plan.comments, ["# all of it"]);
+        assert_eq!(items[6], Item::Comment("# trailer"));
+        assert_eq!(items.len(), 7);
+    }
+    #[test]
+    fn test_error_recovery() {
+        let text = "task a > out {\n  echo hi > $out\n}\n\
+                    task b :: x=(Y: {\n  echo\n}\n\
+                    plan p {\n  reach a via (Y b)\n}\n\
+                    # a comment\n\
+                    task c > out {\n  touch $out\n}\n\
+                    tsk d {}\n";
+        let e = super::parse(text).unwrap_err();
+        let errors = e.downcast::<super::Errors>().unwrap().0;
+        let lines: Vec<usize> = errors.iter().map(|e| e.loc.line_num).collect();
+        assert_eq!(lines, [4, 8, 14]);
+
+        let text = "\n# just a comment\ntask a > out {\n  touch $out\n}\n\n";
+        let items = super::parse(text).unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0], Item::Comment("# just a comment"));
+        assert_eq!(
+            super::next_block_start("task a {\n  task\n}\ntask b {}", 3),
+            Some(18)
+        );
+        assert_eq!(super::next_block_start("task a {\n  task\n}\n", 3), None);
+    }
+}
@@ -1,28 +1,60 @@
 use anyhow::Result;
-use heron_rebuild::{App, Args};
+use heron_rebuild::{
+    App, Args, Command, Compat, ExecObserver, ExportFormat, HistoryKind, InvalidateArgs, ListKind,
+    Mode, NotifyOn, OutputFormat, RunArgs, Settings, TaskEvent, TaskInfo,
+};
+use serde_json::{json, Value};
 use std::path::PathBuf;
-use std::sync::{LazyLock, Mutex};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Duration;
 use tempfile::tempdir;
 
+#[allow(clippy::redundant_closure)]
 static MODULE_LOCK: LazyLock<Mutex<()>> = LazyLock::new(|| Mutex::default());
 
 const MODULE_PATH: &str = "examples/test-module";
 
 fn basic_args(output: String) -> Args {
     Args {
-        config: String::from("examples/stub.tconf"),
-        output: output,
-        plan: None,
+        command: None,
+        configs: vec![String::from("examples/stub.tconf")],
+        output: Some(output),
+        cache: None,
+        remote_cache: None,
+        plans: Vec::new(),
+        defines: Vec::new(),
+        compat: None,
         tasks: Vec::with_capacity(0),
+        task_regexes: Vec::new(),
         invalidate: false,
+        invalidate_args: InvalidateArgs::default(),
+        run_args: RunArgs::default(),
         yes: true,
+        yes_run: false,
+        yes_delete: false,
+        yes_invalidate: false,
+        no_delete: false,
+        non_interactive: false,
         verbose: 1,
+        log_format: None,
         branch: Vec::with_capacity(0),
         baseline: false,
+        short_names: false,
+        require_clean_modules: false,
+        strict_vars: false,
+        force_unlock: false,
         dry_run: false,
     }
 }
 
+/// Run the `hr` binary with `args`, and get what it printed to stdout.
+fn hr_stdout(args: &[&str]) -> Result<String> {
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_hr")).args(args).output()?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "hr {args:?} failed: {stderr}");
+    Ok(String::from_utf8(output.stdout)?)
+}
+
 fn stringify_dir(dir: &tempfile::TempDir) -> String {
     dir.path().to_str().unwrap().to_owned()
 }
@@ -45,7 +77,7 @@ fn run_plan(plan: &str) -> Result<tempfile::TempDir> {
     let output = tempdir()?;
     let mut args = basic_args(stringify_dir(&output));
 
-    args.plan = Some(plan.to_owned());
+    args.plans = vec![plan.to_owned()];
     let settings = args.try_into()?;
     let app = App::new(settings);
     app.run()?;
@@ -67,7 +99,7 @@ fn run_task(task: &str) -> Result<tempfile::TempDir> {
     let output = tempdir()?;
     let mut args = basic_args(stringify_dir(&output));
 
-    // args.plan = Some(plan.to_owned());
+    // args.plans = vec![plan.to_owned()];
     args.tasks.push(task.to_owned());
     let settings = args.try_into()?;
     let app = App::new(settings);
@@ -92,6 +124,46 @@ fn test_basic() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_task_info() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    std::fs::write(
+        &config,
+        "plan main { reach b via (X: x2) }\n\
+         task a > out=a.txt :: p=(X: x1=1 x2=2) { echo $p > $out }\n\
+         task b < in=$out@a > out=b.txt { cat $in > $out }\n",
+    )?;
+    let output = dir.path().join("output");
+    let mut args = basic_args(output.to_str().unwrap().to_owned());
+    args.configs = vec![config.to_str().unwrap().to_owned()];
+    args.plans = vec!["main".to_owned()];
+    App::new(args.try_into()?).run()?;
+
+    let read_info = |task: &str| -> Result<TaskInfo> {
+        let realization = output.join(task).join("realizations/X.x2");
+        let text = std::fs::read_to_string(realization.join("task_info.json"))?;
+        Ok(TaskInfo::from_json(&text)?)
+    };
+    let a = read_info("a")?;
+    let b = read_info("b")?;
+
+    let pair = |k: &str, v: &str| (k.to_owned(), v.to_owned());
+    assert_eq!(a.task, "a");
+    assert_eq!(a.branch, vec![pair("X", "x2")]);
+    assert_eq!(a.params, vec![pair("p", "2")]);
+    assert_eq!(a.module, None);
+    assert_eq!(a.outputs.len(), 1);
+    assert_eq!(b.inputs, vec![pair("in", &a.outputs[0].1)]);
+    assert!(b.realization.ends_with("b/realizations/X.x2"));
+    assert_eq!(b.succeeded, Some(true));
+    let (started, finished) = (b.started.unwrap(), b.finished.unwrap());
+    assert!(b.prepared <= started && started <= finished);
+
+    dir.close()?;
+    Ok(())
+}
+
 #[test]
 fn test_invalidate_goal_empty_branch() -> Result<()> {
     let output = run_basic()?;
@@ -113,7 +185,7 @@ fn test_invalidate_goal_empty_branch() -> Result<()> {
 
     // re-run and confirm goal node now exists:
     let mut args = basic_args(stringify_dir(&output));
-    args.plan = Some("debug".to_owned());
+    args.plans = vec!["debug".to_owned()];
     let settings = args.try_into()?;
     App::new(settings).run()?;
 
@@ -177,7 +249,7 @@ fn test_invalidate_earlier_task() -> Result<()> {
 
     // now run it again:
     let mut args = basic_args(output_string);
-    args.plan = Some("debug".to_owned());
+    args.plans = vec!["debug".to_owned()];
     App::new(args.try_into()?).run()?;
 
     assert!(
@@ -222,7 +294,7 @@ fn test_invalidate_earlier_task_baseline() -> Result<()> {
 
     // now run it again:
     let mut args = basic_args(output_string);
-    args.plan = Some("debug".to_owned());
+    args.plans = vec!["debug".to_owned()];
     App::new(args.try_into()?).run()?;
 
     assert!(
@@ -291,3 +363,3551 @@ fn test_nonexistent_task_output() -> Result<()> {
     assert!(run_task("nonexistent_task_output").is_err());
     Ok(())
 }
+
+#[test]
+fn test_no_delete_refuses_to_delete_invalidated_task() -> Result<()> {
+    let output = run_basic()?;
+    let output_string = stringify_dir(&output);
+
+    let mut args = basic_args(output_string.clone());
+    args.invalidate = true;
+    args.tasks = vec!["pkgbuild".to_owned()];
+    args.branch = vec!["Framework.vst".to_owned()];
+    App::new(args.try_into()?).run()?;
+
+    // automation can run new work, but may not delete the invalidated realization:
+    let mut args = basic_args(output_string);
+    args.yes = false;
+    args.yes_run = true;
+    args.no_delete = true;
+    args.plans = vec!["debug".to_owned()];
+    assert!(
+        App::new(args.try_into()?).run().is_err(),
+        "Run with pending deletes fails under --no-delete"
+    );
+
+    let mut vst = PathBuf::from(output.path());
+    vst.push("pkgbuild/realizations/Baseline.baseline+Framework.vst");
+    assert!(vst.exists(), "Invalidated realization was not deleted");
+    vst.push("exit_code");
+    assert!(!vst.exists(), "Invalidated realization was not re-run");
+
+    output.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_no_delete_refuses_to_invalidate_realization_dirs() -> Result<()> {
+    let output = run_basic()?;
+
+    let mut args = basic_args(stringify_dir(&output));
+    args.invalidate = true;
+    args.tasks = vec!["pkgbuild".to_owned()];
+    args.no_delete = true;
+    assert!(
+        App::new(args.try_into()?).run().is_err(),
+        "Invalidating whole task fails under --no-delete"
+    );
+
+    let mut realizations = PathBuf::from(output.path());
+    realizations.push("pkgbuild/realizations");
+    assert!(realizations.exists(), "Realizations were not deleted");
+
+    output.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_shell_requires_existing_realization() -> Result<()> {
+    let output = run_basic()?;
+
+    // release branch of productbuild was never run, so there's nothing to open a shell in:
+    let mut args = basic_args(stringify_dir(&output));
+    args.command = Some(Command::Shell {
+        task: "productbuild".to_owned(),
+    });
+    args.branch = vec!["Profile.release".to_owned()];
+    assert!(
+        App::new(args.try_into()?).run().is_err(),
+        "Shell in nonexistent realization fails"
+    );
+
+    output.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_cancel_request_is_cleared_by_next_run() -> Result<()> {
+    let output = tempdir()?;
+    let output_string = stringify_dir(&output);
+
+    let mut args = basic_args(output_string.clone());
+    args.command = Some(Command::Cancel {
+        tasks: Vec::new(),
+        kill: false,
+    });
+    App::new(args.try_into()?).run()?;
+
+    let mut cancel_request = PathBuf::from(output.path());
+    cancel_request.push("cancel_request");
+    assert!(cancel_request.exists(), "Cancel request was written");
+
+    // with no workflow running, the request is stale and shouldn't stop a new run:
+    {
+        let _lock = MODULE_LOCK.lock();
+        let module_dir = PathBuf::from(MODULE_PATH);
+        if !module_dir.exists() {
+            std::fs::create_dir(&module_dir)?;
+        }
+    }
+    let mut args = basic_args(output_string);
+    args.plans = vec!["debug".to_owned()];
+    App::new(args.try_into()?).run()?;
+
+    assert!(!cancel_request.exists(), "Stale cancel request was deleted");
+    let mut goal = PathBuf::from(output.path());
+    goal.push("productbuild/realizations/Baseline.baseline/exit_code");
+    assert!(goal.exists(), "Workflow ran to completion");
+
+    output.close()?;
+    Ok(())
+}
+
+/// Run plan `main` of `config` in the background, and once `task` has started,
+/// cancel it with 'hr cancel' as given. Returns the run's result and the metrics it wrote.
+fn cancel_while_running(
+    dir: &tempfile::TempDir,
+    config: &str,
+    task: &str,
+    cancel: Command,
+) -> Result<(Result<()>, String)> {
+    let config_path = dir.path().join("rebuild.hr");
+    std::fs::write(&config_path, config)?;
+    let output = dir.path().join("output");
+    let metrics = dir.path().join("hr.prom");
+    let args = |command| {
+        let mut args = basic_args(output.to_str().unwrap().to_owned());
+        args.configs = vec![config_path.to_str().unwrap().to_owned()];
+        args.command = command;
+        args
+    };
+
+    let mut run_args = args(None);
+    run_args.plans = vec!["main".to_owned()];
+    run_args.run_args.metrics_file = Some(metrics.to_str().unwrap().to_owned());
+    let settings: Settings = run_args.try_into()?;
+    let run = std::thread::spawn(move || App::new(settings).run());
+
+    // the task runs in a scratch dir next to its realization dir:
+    let realizations = output.join(task).join("realizations");
+    let started = || {
+        let entries = std::fs::read_dir(&realizations).into_iter().flatten();
+        entries.flatten().any(|entry| entry.path().join("heartbeat").exists())
+    };
+    for _ in 0..100 {
+        if started() {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    assert!(started(), "Task {task} started");
+    App::new(args(Some(cancel)).try_into()?).run()?;
+
+    let result = run.join().expect("run thread panicked");
+    Ok((result, std::fs::read_to_string(&metrics)?))
+}
+
+#[test]
+fn test_cancel_stops_run_after_running_task() -> Result<()> {
+    let dir = tempdir()?;
+    let config = "plan main { reach b }\n\
+                  task a > out=out.txt { sleep 2; touch $out }\n\
+                  task b < in=$out@a > out=out.txt { cp $in $out }\n";
+    let cancel = Command::Cancel {
+        tasks: Vec::new(),
+        kill: false,
+    };
+    let (result, metrics) = cancel_while_running(&dir, config, "a", cancel)?;
+
+    assert!(result.is_err(), "Cancelled run failed");
+    let output = dir.path().join("output");
+    assert!(
+        output.join("a/realizations/Baseline.baseline/exit_code").exists(),
+        "Running task was allowed to finish"
+    );
+    assert!(
+        !output.join("b/realizations/Baseline.baseline/exit_code").exists(),
+        "Next task didn't run"
+    );
+    for sample in [
+        "hr_run_tasks{plan=\"main\",state=\"completed\"} 1\n",
+        "hr_run_tasks{plan=\"main\",state=\"cancelled\"} 1\n",
+    ] {
+        assert!(metrics.contains(sample), "{sample:?} not in {metrics}");
+    }
+    assert!(
+        !output.join("cancel_request").exists(),
+        "Cancel request was consumed"
+    );
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_cancel_kills_running_task() -> Result<()> {
+    let dir = tempdir()?;
+    let config = "plan main { reach c }\n\
+                  task a > out=out.txt { sleep 30; touch $out }\n\
+                  task b > out=out.txt { touch $out }\n\
+                  task c < x=$out@a y=$out@b { true }\n";
+    let cancel = Command::Cancel {
+        tasks: vec!["a".to_owned()],
+        kill: true,
+    };
+    let start = std::time::Instant::now();
+    let (result, metrics) = cancel_while_running(&dir, config, "a", cancel)?;
+
+    assert!(result.is_err(), "Cancelled run failed");
+    assert!(
+        start.elapsed() < Duration::from_secs(20),
+        "Cancelled task was killed"
+    );
+    let output = dir.path().join("output");
+    assert!(
+        !output.join("a/realizations/Baseline.baseline/exit_code").exists(),
+        "Killed task isn't marked complete"
+    );
+    assert!(
+        output.join("b/realizations/Baseline.baseline/exit_code").exists(),
+        "Task that wasn't cancelled still ran"
+    );
+    for sample in [
+        // 'c' needs 'a', so it's cancelled too:
+        "hr_run_tasks{plan=\"main\",state=\"cancelled\"} 2\n",
+        "hr_run_tasks{plan=\"main\",state=\"completed\"} 1\n",
+        "hr_run_tasks{plan=\"main\",state=\"failed\"} 0\n",
+    ] {
+        assert!(metrics.contains(sample), "{sample:?} not in {metrics}");
+    }
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_jobs() -> Result<()> {
+    let dir = tempdir()?;
+    let sync = dir.path().join("sync");
+    std::fs::create_dir(&sync)?;
+    let sync = sync.to_str().unwrap();
+    // a and b each wait for the other to start, so they only finish if they run at once:
+    let wait_for = |name: &str, other: &str| {
+        format!(
+            "task {name} > out=out.txt {{\n\
+             touch {sync}/{name}\n\
+             for i in $(seq 100); do [ -e {sync}/{other} ] && break; sleep 0.1; done\n\
+             [ -e {sync}/{other} ] && echo {name} > $out\n\
+             }}\n"
+        )
+    };
+    let config = dir.path().join("rebuild.hr");
+    std::fs::write(
+        &config,
+        format!(
+            "{}{}task c < a=$out@a b=$out@b > out=out.txt {{ cat $a $b > $out }}\n",
+            wait_for("a", "b"),
+            wait_for("b", "a"),
+        ),
+    )?;
+    let output = dir.path().join("output");
+    let mut args = basic_args(output.to_str().unwrap().to_owned());
+    args.configs = vec![config.to_str().unwrap().to_owned()];
+    args.tasks = vec!["c".to_owned()];
+    args.run_args.jobs = std::num::NonZeroUsize::new(2);
+    App::new(args.try_into()?).run()?;
+
+    let text = std::fs::read_to_string(output.join("c/realizations/Baseline.baseline/out.txt"))?;
+    assert_eq!(text, "a\nb\n", "c runs after both of the tasks it needs");
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_notify() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    std::fs::write(
+        &config,
+        "plan good { reach good }\n\
+         plan bad { reach bad }\n\
+         task good > out=out.txt { echo hi > $out }\n\
+         task bad > out=out.txt { false }\n",
+    )?;
+    let output = dir.path().join("output");
+    let notified = dir.path().join("notified.txt");
+    let run = |plan: &str| -> Result<()> {
+        let mut args = basic_args(output.to_str().unwrap().to_owned());
+        args.configs = vec![config.to_str().unwrap().to_owned()];
+        args.plans = vec![plan.to_owned()];
+        args.run_args.notify = Some(format!(
+            "echo \"$HR_RUN_STATUS $HR_FAILED_TASKS\" > {}",
+            notified.display()
+        ));
+        args.run_args.notify_on = Some(NotifyOn::Failure);
+        App::new(args.try_into()?).run()
+    };
+
+    run("good")?;
+    assert!(!notified.exists(), "no notification for a successful run");
+    assert!(run("bad").is_err());
+    assert_eq!(
+        std::fs::read_to_string(&notified)?,
+        "failed bad[Baseline.baseline]\n"
+    );
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_max_output_size() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    std::fs::write(
+        &config,
+        "plan small { reach small }\n\
+         plan big { reach big }\n\
+         task small > out=out.txt :: .max_output_size=1M { echo hi > $out }\n\
+         task big > out=out.txt :: .max_output_size=1K { head -c 4096 /dev/zero > $out }\n",
+    )?;
+    let output = dir.path().join("output");
+
+    let mut args = basic_args(output.to_str().unwrap().to_owned());
+    args.configs = vec![config.to_str().unwrap().to_owned()];
+    args.plans = vec!["small".to_owned()];
+    App::new(args.try_into()?).run()?;
+    assert!(
+        output.join("small/realizations/Baseline.baseline/exit_code").exists(),
+        "Task within size limit completed"
+    );
+
+    let mut args = basic_args(output.to_str().unwrap().to_owned());
+    args.configs = vec![config.to_str().unwrap().to_owned()];
+    args.plans = vec!["big".to_owned()];
+    assert!(
+        App::new(args.try_into()?).run().is_err(),
+        "Task exceeding size limit fails"
+    );
+    assert!(
+        !output.join("big/realizations/Baseline.baseline/exit_code").exists(),
+        "Task exceeding size limit is not marked complete"
+    );
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_dot_params() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    std::fs::write(
+        &config,
+        "task train > out=out.txt :: .cpus=4 .queue=gpu { (printenv cpus || echo unset) > $out }\n",
+    )?;
+    let output = dir.path().join("output");
+    let mut args = basic_args(output.to_str().unwrap().to_owned());
+    args.configs = vec![config.to_str().unwrap().to_owned()];
+    args.tasks = vec!["train".to_owned()];
+    App::new(args.try_into()?).run()?;
+
+    // dot params aren't exported to the task's code, but are recorded in its task_info.json:
+    let realization = output.join("train/realizations/Baseline.baseline");
+    let text = std::fs::read_to_string(realization.join("out.txt"))?;
+    assert_eq!(text, "unset\n");
+    let text = std::fs::read_to_string(realization.join("task_info.json"))?;
+    let info = TaskInfo::from_json(&text)?;
+    let pair = |k: &str, v: &str| (k.to_owned(), v.to_owned());
+    assert_eq!(
+        info.dot_params,
+        vec![pair("cpus", "4"), pair("queue", "gpu")]
+    );
+    assert!(info.params.is_empty());
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_glob_graft() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    std::fs::write(
+        &config,
+        "task prep > out=out.txt :: name=(Dataset: a b c) { echo $name > $out }\n\
+         task summarize < ins=$out@prep[Dataset: *] > out=summary.txt { cat $ins > $out }\n",
+    )?;
+    let output = dir.path().join("output");
+    let mut args = basic_args(output.to_str().unwrap().to_owned());
+    args.configs = vec![config.to_str().unwrap().to_owned()];
+    args.tasks = vec!["summarize".to_owned()];
+    App::new(args.try_into()?).run()?;
+
+    // summarize depends on every branch of prep, but doesn't branch itself:
+    for branch in ["Baseline.baseline", "Dataset.b", "Dataset.c"] {
+        assert!(output.join("prep/realizations").join(branch).join("out.txt").exists());
+    }
+    let summary = output.join("summarize/realizations/Baseline.baseline/summary.txt");
+    assert_eq!(std::fs::read_to_string(&summary)?, "a\nb\nc\n");
+
+    // a removed branch is still listed in branchpoints.txt, but the glob leaves it out:
+    std::fs::write(
+        &config,
+        "task prep > out=out.txt :: name=(Dataset: a b) { echo $name > $out }\n\
+         task summarize < ins=$out@prep[Dataset: *] > out=summary.txt { cat $ins > $out }\n",
+    )?;
+    let mut args = basic_args(output.to_str().unwrap().to_owned());
+    args.configs = vec![config.to_str().unwrap().to_owned()];
+    args.tasks = vec!["summarize".to_owned()];
+    App::new(args.try_into()?).run()?;
+    assert_eq!(std::fs::read_to_string(&summary)?, "a\nb\n");
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_explain_dedup() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    // prep doesn't use Size, so both branches of report share one prep realization:
+    std::fs::write(
+        &config,
+        "plan all { reach report via (Size: small big) }\n\
+         task prep > out=out.txt { echo prep > $out }\n\
+         task report < in=$out@prep > out=out.txt :: size=(Size: small big) { cat $in > $out }\n",
+    )?;
+    let output = dir.path().join("output");
+    let mut args = basic_args(output.to_str().unwrap().to_owned());
+    args.configs = vec![config.to_str().unwrap().to_owned()];
+    args.plans = vec!["all".to_owned()];
+    args.run_args.explain_dedup = true;
+    App::new(args.try_into()?).run()?;
+
+    let prep = output.join("prep/realizations");
+    assert_eq!(std::fs::read_dir(prep)?.count(), 1);
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_inputs_with_different_branchpoints() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    std::fs::write(
+        &config,
+        "plan all { reach d via (X: x1 x2) * (Y: y1 y2) }\n\
+         task b > out=out.txt :: x=(X: x1 x2) { echo $x > $out }\n\
+         task c > out=out.txt :: y=(Y: y1 y2) { echo $y > $out }\n\
+         task d < b=$out@b c=$out@c > out=out.txt { cat $b $c > $out }\n",
+    )?;
+    let output = dir.path().join("output");
+    let mut args = basic_args(output.to_str().unwrap().to_owned());
+    args.configs = vec![config.to_str().unwrap().to_owned()];
+    args.plans = vec!["all".to_owned()];
+    App::new(args.try_into()?).run()?;
+
+    // d keeps the branchpoints of both of its inputs:
+    assert_eq!(std::fs::read_dir(output.join("d/realizations"))?.count(), 4);
+    let out = output.join("d/realizations/X.x2+Y.y2/out.txt");
+    assert_eq!(std::fs::read_to_string(out)?, "x2\ny2\n");
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_plan_baseline_override() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    std::fs::write(
+        &config,
+        "plan debug { reach test }\n\
+         plan release { baseline (Profile: release); include debug }\n\
+         task build > out=out.txt :: p=(Profile: debug release) { echo $p > $out }\n\
+         task test < in=$out@build > out=out.txt { cp $in $out }\n",
+    )?;
+    let output = dir.path().join("output");
+    let run = |plan: &str| -> Result<()> {
+        let mut args = basic_args(output.to_str().unwrap().to_owned());
+        args.configs = vec![config.to_str().unwrap().to_owned()];
+        args.plans = vec![plan.to_owned()];
+        App::new(args.try_into()?).run()
+    };
+    run("release")?;
+
+    // unspecified branches default to the plan's baseline, but dir names still
+    // leave out the defined baseline, so the override is written out:
+    let out = |branch: &str| output.join("test/realizations").join(branch).join("out.txt");
+    assert_eq!(
+        std::fs::read_to_string(out("Profile.release"))?,
+        "release\n"
+    );
+    assert!(output.join("build/Profile.release").exists());
+    // and branchpoints.txt keeps the defined baseline:
+    let branchpoints = std::fs::read_to_string(output.join("branchpoints.txt"))?;
+    assert!(branchpoints.contains("debug release"), "{branchpoints}");
+
+    // so the two plans' realizations don't overwrite each other:
+    run("debug")?;
+    assert_eq!(
+        std::fs::read_to_string(out("Baseline.baseline"))?,
+        "debug\n"
+    );
+    assert_eq!(
+        std::fs::read_to_string(out("Profile.release"))?,
+        "release\n"
+    );
+    let modified = std::fs::metadata(out("Profile.release"))?.modified()?;
+    run("release")?;
+    assert_eq!(
+        std::fs::metadata(out("Profile.release"))?.modified()?,
+        modified,
+        "Release realization wasn't rerun"
+    );
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_env_var_values() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    std::env::set_var("HR_INTEG_GREETING", "hello");
+    std::fs::write(
+        &config,
+        "global {\n  name=world\n}\n\
+         task greet > out=out.txt :: greeting=$ENV{HR_INTEG_GREETING} msg=\"${env:HR_INTEG_GREETING} $name\" {\n\
+           echo \"$greeting $msg\" > $out\n\
+         }\n",
+    )?;
+    let output = dir.path().join("output");
+    let mut args = basic_args(output.to_str().unwrap().to_owned());
+    args.configs = vec![config.to_str().unwrap().to_owned()];
+    args.tasks = vec!["greet".to_owned()];
+    App::new(args.try_into()?).run()?;
+    let out = output.join("greet/realizations/Baseline.baseline/out.txt");
+    assert_eq!(std::fs::read_to_string(out)?, "hello hello world\n");
+
+    std::fs::write(
+        &config,
+        "task greet > out=out.txt :: greeting=$ENV{HR_INTEG_UNSET} { echo $greeting > $out }\n",
+    )?;
+    let mut args = basic_args(output.to_str().unwrap().to_owned());
+    args.configs = vec![config.to_str().unwrap().to_owned()];
+    args.tasks = vec!["greet".to_owned()];
+    assert!(App::new(args.try_into()?).run().is_err());
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_define_overrides_config() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    std::fs::write(
+        &config,
+        "global {\n  identity=dev\n}\n\
+         task sign > out=out.txt :: identity=@ team=$team { echo $identity $team > $out }\n",
+    )?;
+    let output = dir.path().join("output");
+    let mut args = basic_args(output.to_str().unwrap().to_owned());
+    args.configs = vec![config.to_str().unwrap().to_owned()];
+    args.tasks = vec!["sign".to_owned()];
+    args.defines = vec!["identity=Release ID".to_owned(), "team=abc".to_owned()];
+    App::new(args.try_into()?).run()?;
+    let out = output.join("sign/realizations/Baseline.baseline/out.txt");
+    assert_eq!(std::fs::read_to_string(out)?, "Release ID abc\n");
+
+    let mut args = basic_args(output.to_str().unwrap().to_owned());
+    args.configs = vec![config.to_str().unwrap().to_owned()];
+    args.defines = vec!["no_equals_sign".to_owned()];
+    assert!(Settings::try_from(args).is_err());
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_layered_configs() -> Result<()> {
+    let dir = tempdir()?;
+    let base = dir.path().join("base.tconf");
+    let local = dir.path().join("local.tconf");
+    std::fs::write(
+        &base,
+        "global {\n  greeting=hello\n  name=world\n}\n\
+         plan main { reach greet }\n\
+         task greet > out=out.txt :: greeting=@ name=@ { echo $greeting $name > $out }\n\
+         task other > out=out.txt { echo base > $out }\n",
+    )?;
+    std::fs::write(
+        &local,
+        "global {\n  name=local\n}\n\
+         plan main { reach greet, other }\n\
+         task other > out=out.txt { echo local > $out }\n",
+    )?;
+    let output = dir.path().join("output");
+    let mut args = basic_args(output.to_str().unwrap().to_owned());
+    args.configs = vec![
+        base.to_str().unwrap().to_owned(),
+        local.to_str().unwrap().to_owned(),
+    ];
+    args.plans = vec!["main".to_owned()];
+    App::new(args.try_into()?).run()?;
+
+    let read = |task: &str| {
+        std::fs::read_to_string(output.join(task).join("realizations/Baseline.baseline/out.txt"))
+    };
+    assert_eq!(read("greet")?, "hello local\n");
+    assert_eq!(read("other")?, "local\n");
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_plan_glob() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    // plans can come before the values that define their branches:
+    std::fs::write(
+        &config,
+        "plan all { reach prep via (Dataset: *) }\n\
+         plan unknown { reach prep via (Nope: *) }\n\
+         task prep > out=out.txt :: name=(Dataset: a b c) { echo $name > $out }\n",
+    )?;
+    let output = dir.path().join("output");
+    let args = |plan: &str| {
+        let mut args = basic_args(output.to_str().unwrap().to_owned());
+        args.configs = vec![config.to_str().unwrap().to_owned()];
+        args.plans = vec![plan.to_owned()];
+        args
+    };
+    assert!(App::new(args("unknown").try_into()?).run().is_err());
+
+    std::fs::write(
+        &config,
+        "plan all { reach prep via (Dataset: *) }\n\
+         task prep > out=out.txt :: name=(Dataset: a b c) { echo $name > $out }\n",
+    )?;
+    App::new(args("all").try_into()?).run()?;
+    for branch in ["Baseline.baseline", "Dataset.b", "Dataset.c"] {
+        assert!(output.join("prep/realizations").join(branch).join("out.txt").exists());
+    }
+
+    // a removed branch is still listed in branchpoints.txt, but the glob leaves it out:
+    std::fs::write(
+        &config,
+        "plan all { reach prep via (Dataset: *) }\n\
+         task prep > out=out.txt :: name=(Dataset: a b) { echo $name > $out }\n",
+    )?;
+    std::fs::remove_dir_all(output.join("prep/realizations/Dataset.c"))?;
+    App::new(args("all").try_into()?).run()?;
+    assert!(!output.join("prep/realizations/Dataset.c").exists());
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_atomic() -> Result<()> {
+    let output = tempdir()?;
+    {
+        let _lock = MODULE_LOCK.lock();
+        let module_dir = PathBuf::from(MODULE_PATH);
+        if !module_dir.exists() {
+            std::fs::create_dir(&module_dir)?;
+        }
+    }
+    // tasks run in scratch dirs by default:
+    let mut args = basic_args(stringify_dir(&output));
+    args.plans = vec!["debug".to_owned()];
+    App::new(args.try_into()?).run()?;
+
+    let realizations = output.path().join("productbuild/realizations");
+    assert!(
+        realizations.join("Baseline.baseline/exit_code").exists(),
+        "Goal task was moved into place"
+    );
+    for entry in std::fs::read_dir(&realizations)? {
+        let fname = entry?.file_name();
+        assert!(
+            !fname.to_str().unwrap().starts_with(".tmp-"),
+            "No scratch dirs are left after success"
+        );
+    }
+
+    output.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_atomic_failure_leaves_no_realization() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    std::fs::write(
+        &config,
+        "plan fail { reach fail }\ntask fail > out=out.txt { echo partial > $out; exit 1 }\n",
+    )?;
+    let output = dir.path().join("output");
+
+    for _ in 0..2 {
+        let mut args = basic_args(output.to_str().unwrap().to_owned());
+        args.configs = vec![config.to_str().unwrap().to_owned()];
+        args.plans = vec!["fail".to_owned()];
+        assert!(App::new(args.try_into()?).run().is_err(), "Task failed");
+    }
+
+    let realizations = output.join("fail/realizations");
+    assert!(
+        !realizations.join("Baseline.baseline").exists(),
+        "Failed task was not moved into place"
+    );
+    let scratch_dirs = std::fs::read_dir(&realizations)?.count();
+    assert_eq!(
+        scratch_dirs, 1,
+        "Stale scratch dir was replaced by the rerun"
+    );
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_in_place_failure_leaves_realization() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    std::fs::write(
+        &config,
+        "plan fail { reach fail }\ntask fail > out=out.txt { echo partial > $out; exit 1 }\n",
+    )?;
+    let output = dir.path().join("output");
+
+    let mut args = basic_args(output.to_str().unwrap().to_owned());
+    args.configs = vec![config.to_str().unwrap().to_owned()];
+    args.run_args.in_place = true;
+    args.plans = vec!["fail".to_owned()];
+    assert!(App::new(args.try_into()?).run().is_err(), "Task failed");
+
+    let realization = output.join("fail/realizations/Baseline.baseline");
+    assert!(
+        realization.join("out.txt").exists(),
+        "Failed task ran in its realization dir"
+    );
+    assert!(!realization.join("exit_code").exists());
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_output_dir_lock() -> Result<()> {
+    let output = run_basic()?;
+    let output_string = stringify_dir(&output);
+
+    let lock = output.path().join(".lock");
+    assert!(!lock.exists(), "Lock is released after a run");
+
+    // pretend another run holds the lock, and invalidate so there's work to do:
+    std::fs::write(&lock, "pid 1\ntime 0\n")?;
+    let mut args = basic_args(output_string.clone());
+    args.invalidate = true;
+    args.tasks = vec!["productbuild".to_owned()];
+    assert!(
+        App::new(args.try_into()?).run().is_err(),
+        "Invalidation fails while locked"
+    );
+
+    let mut args = basic_args(output_string.clone());
+    args.invalidate = true;
+    args.tasks = vec!["productbuild".to_owned()];
+    args.force_unlock = true;
+    App::new(args.try_into()?).run()?;
+    assert!(!lock.exists(), "Forced lock is released after invalidation");
+
+    std::fs::write(&lock, "pid 1\ntime 0\n")?;
+    let mut args = basic_args(output_string.clone());
+    args.plans = vec!["debug".to_owned()];
+    assert!(
+        App::new(args.try_into()?).run().is_err(),
+        "Run fails while locked"
+    );
+    let goal = output.path().join("productbuild/realizations/Baseline.baseline");
+    assert!(!goal.exists(), "Locked run did not recreate goal task");
+
+    let mut args = basic_args(output_string);
+    args.plans = vec!["debug".to_owned()];
+    args.force_unlock = true;
+    App::new(args.try_into()?).run()?;
+    assert!(goal.exists(), "Forced run recreated goal task");
+    assert!(!lock.exists(), "Forced lock is released after run");
+
+    output.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_kill_idle_task() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    std::fs::write(
+        &config,
+        "plan hang { reach hang }\ntask hang > out=out.txt { sleep 30; touch $out }\n",
+    )?;
+    let output = dir.path().join("output");
+
+    let mut args = basic_args(output.to_str().unwrap().to_owned());
+    args.configs = vec![config.to_str().unwrap().to_owned()];
+    args.plans = vec!["hang".to_owned()];
+    args.run_args.idle_timeout = Some(1);
+    args.run_args.kill_idle = true;
+
+    let start = std::time::Instant::now();
+    assert!(
+        App::new(args.try_into()?).run().is_err(),
+        "Idle task failed"
+    );
+    assert!(
+        start.elapsed() < std::time::Duration::from_secs(20),
+        "Idle task was killed before finishing"
+    );
+    let realization = output.join("hang/realizations/Baseline.baseline");
+    assert!(
+        !realization.join("exit_code").exists(),
+        "Idle task not marked complete"
+    );
+    assert!(
+        !realization.join("heartbeat").exists(),
+        "Heartbeat file was removed"
+    );
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_task_writes_heartbeat() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    std::fs::write(
+        &config,
+        "plan beat { reach beat }\ntask beat > out=out.txt { sleep 1; cp heartbeat $out }\n",
+    )?;
+    let output = dir.path().join("output");
+
+    let mut args = basic_args(output.to_str().unwrap().to_owned());
+    args.configs = vec![config.to_str().unwrap().to_owned()];
+    args.plans = vec!["beat".to_owned()];
+    App::new(args.try_into()?).run()?;
+
+    let realization = output.join("beat/realizations/Baseline.baseline");
+    let beat = std::fs::read_to_string(realization.join("out.txt"))?;
+    assert!(
+        beat.starts_with("time "),
+        "Heartbeat was written while task ran"
+    );
+    assert!(
+        !realization.join("heartbeat").exists(),
+        "Heartbeat file was removed"
+    );
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_background_process_doesnt_hold_up_run() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    std::fs::write(
+        &config,
+        "plan bg { reach bg }\ntask bg > out=out.txt { sleep 30 & touch $out }\n",
+    )?;
+    let output = dir.path().join("output");
+
+    let mut args = basic_args(output.to_str().unwrap().to_owned());
+    args.configs = vec![config.to_str().unwrap().to_owned()];
+    args.plans = vec!["bg".to_owned()];
+
+    let start = std::time::Instant::now();
+    App::new(args.try_into()?).run()?;
+    assert!(
+        start.elapsed() < std::time::Duration::from_secs(20),
+        "Run finished without waiting for the task's background process"
+    );
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_cache_across_output_dirs() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    let counter = dir.path().join("counter.txt");
+    std::fs::write(
+        &config,
+        format!(
+            "plan main {{ reach b }}\n\
+             task a > out=a.txt :: msg=hello {{ echo ran >> {counter:?}; echo $msg > $out }}\n\
+             task b < in=$out@a > out=b.txt {{ echo ran >> {counter:?}; cat $in > $out }}\n",
+        ),
+    )?;
+    let cache = dir.path().join("cache");
+
+    for output in ["output1", "output2"] {
+        let output = dir.path().join(output);
+        let mut args = basic_args(output.to_str().unwrap().to_owned());
+        args.configs = vec![config.to_str().unwrap().to_owned()];
+        args.cache = Some(cache.to_str().unwrap().to_owned());
+        args.plans = vec!["main".to_owned()];
+        App::new(args.try_into()?).run()?;
+
+        let b = output.join("b/realizations/Baseline.baseline");
+        assert!(b.join("exit_code").exists(), "Goal task completed");
+        assert_eq!(std::fs::read_to_string(b.join("b.txt"))?, "hello\n");
+    }
+
+    assert_eq!(
+        std::fs::read_to_string(&counter)?.lines().count(),
+        2,
+        "Tasks only executed in the first output dir"
+    );
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_broken_cache_entry_runs_task() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    let counter = dir.path().join("counter.txt");
+    std::fs::write(
+        &config,
+        format!(
+            "plan main {{ reach a }}\n\
+             task a > out=a.txt {{ echo ran >> {counter:?}; echo hello > $out }}\n",
+        ),
+    )?;
+    let cache = dir.path().join("cache");
+    let run = |output: &str| -> Result<()> {
+        let output = dir.path().join(output);
+        let mut args = basic_args(output.to_str().unwrap().to_owned());
+        args.configs = vec![config.to_str().unwrap().to_owned()];
+        args.cache = Some(cache.to_str().unwrap().to_owned());
+        args.plans = vec!["main".to_owned()];
+        App::new(args.try_into()?).run()?;
+        let a = output.join("a/realizations/Baseline.baseline");
+        assert_eq!(std::fs::read_to_string(a.join("a.txt"))?, "hello\n");
+        Ok(())
+    };
+
+    run("output1")?;
+    // remove the cached output, so that the entry exists but can't be restored:
+    for entry in std::fs::read_dir(&cache)? {
+        std::fs::remove_file(entry?.path().join("outputs/a.txt"))?;
+    }
+    run("output2")?;
+    assert_eq!(
+        std::fs::read_to_string(&counter)?.lines().count(),
+        2,
+        "Task ran instead of failing to restore from the cache"
+    );
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_remote_cache() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    let counter = dir.path().join("counter.txt");
+    std::fs::write(
+        &config,
+        format!(
+            "plan main {{ reach b }}\n\
+             task a > out=a.txt :: msg=hello {{ echo ran >> {counter:?}; echo $msg > $out }}\n\
+             task b < in=$out@a > out=b.txt {{ echo ran >> {counter:?}; cat $in > $out }}\n",
+        ),
+    )?;
+    let remote = dir.path().join("remote");
+    std::fs::create_dir(&remote)?;
+    let count_runs = || -> Result<usize> { Ok(std::fs::read_to_string(&counter)?.lines().count()) };
+
+    // each "machine" has its own output and local cache dirs:
+    let run = |machine: &str| -> Result<()> {
+        let output = dir.path().join(machine).join("output");
+        let mut args = basic_args(output.to_str().unwrap().to_owned());
+        args.configs = vec![config.to_str().unwrap().to_owned()];
+        args.cache = Some(dir.path().join(machine).join("cache").to_str().unwrap().to_owned());
+        args.remote_cache = Some(format!("file://{}", remote.to_str().unwrap()));
+        args.plans = vec!["main".to_owned()];
+        App::new(args.try_into()?).run()?;
+
+        let b = output.join("b/realizations/Baseline.baseline");
+        assert_eq!(std::fs::read_to_string(b.join("b.txt"))?, "hello\n");
+        Ok(())
+    };
+
+    run("machine1")?;
+    assert_eq!(count_runs()?, 2);
+    run("machine2")?;
+    assert_eq!(count_runs()?, 2, "Outputs pulled from the remote cache");
+
+    // corrupt downloads are ignored:
+    for entry in std::fs::read_dir(&remote)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "tar") {
+            std::fs::write(path, "garbage")?;
+        }
+    }
+    run("machine3")?;
+    assert_eq!(
+        count_runs()?,
+        4,
+        "Tasks rerun when remote entries are corrupt"
+    );
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_rerun_when_fingerprint_changes() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    let input = dir.path().join("input.txt");
+    let counter = dir.path().join("counter.txt");
+    let write_config = |msg: &str| {
+        std::fs::write(
+            &config,
+            format!(
+                "plan main {{ reach a }}\n\
+                 task a < in={input:?} > out=a.txt :: msg={msg} \
+                 {{ echo ran >> {counter:?}; cat $in > $out; echo $msg >> $out }}\n",
+            ),
+        )
+    };
+    let run = || -> Result<usize> {
+        let output = dir.path().join("output");
+        let mut args = basic_args(output.to_str().unwrap().to_owned());
+        args.configs = vec![config.to_str().unwrap().to_owned()];
+        args.plans = vec!["main".to_owned()];
+        App::new(args.try_into()?).run()?;
+        Ok(std::fs::read_to_string(&counter)?.lines().count())
+    };
+
+    std::fs::write(&input, "input\n")?;
+    write_config("hello")?;
+    assert_eq!(run()?, 1);
+    assert_eq!(run()?, 1, "Unchanged task doesn't rerun");
+
+    write_config("goodbye")?;
+    assert_eq!(run()?, 2, "Task reruns when a param changes");
+
+    std::fs::write(&input, "changed input\n")?;
+    assert_eq!(run()?, 3, "Task reruns when an input file changes");
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_invalidate_downstream() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    std::fs::write(
+        &config,
+        "plan main { reach c, d via (X: x1 x2) }\n\
+         task a > out=a.txt :: p=(X: x1=1 x2=2) { echo $p > $out }\n\
+         task b < in=$out@a > out=b.txt { cat $in > $out }\n\
+         task c < in=$out@b > out=c.txt { cat $in > $out }\n\
+         task d > out=d.txt { echo d > $out }\n",
+    )?;
+    let output = dir.path().join("output");
+    let mut args = basic_args(output.to_str().unwrap().to_owned());
+    args.configs = vec![config.to_str().unwrap().to_owned()];
+    args.plans = vec!["main".to_owned()];
+    App::new(args.try_into()?).run()?;
+
+    let mut args = basic_args(output.to_str().unwrap().to_owned());
+    args.configs = vec![config.to_str().unwrap().to_owned()];
+    args.invalidate = true;
+    args.invalidate_args.downstream = true;
+    args.tasks = vec!["a".to_owned()];
+    args.branch = vec!["X.x2".to_owned()];
+    App::new(args.try_into()?).run()?;
+
+    let exit_code = |task: &str, branch: &str| {
+        output.join(task).join("realizations").join(branch).join("exit_code")
+    };
+    for task in ["a", "b", "c"] {
+        assert!(
+            !exit_code(task, "X.x2").exists(),
+            "{task} on X.x2 was invalidated"
+        );
+        assert!(
+            exit_code(task, "Baseline.baseline").exists(),
+            "{task} on baseline was not invalidated"
+        );
+    }
+    assert!(
+        exit_code("d", "Baseline.baseline").exists(),
+        "Unrelated task was not invalidated"
+    );
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_invalidate_downstream_graft() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    std::fs::write(
+        &config,
+        "plan main { reach b, c via (X: x1 x2) }\n\
+         task a > out=a.txt :: p=(X: x1=1 x2=2) { echo $p > $out }\n\
+         task b < in=$out@a[X: x2] > out=b.txt { cat $in > $out }\n\
+         task c < in=$out@a > out=c.txt { cat $in > $out }\n",
+    )?;
+    let output = dir.path().join("output");
+    let mut args = basic_args(output.to_str().unwrap().to_owned());
+    args.configs = vec![config.to_str().unwrap().to_owned()];
+    args.plans = vec!["main".to_owned()];
+    App::new(args.try_into()?).run()?;
+
+    let invalidate = |branch: &str| -> Result<()> {
+        let mut args = basic_args(output.to_str().unwrap().to_owned());
+        args.configs = vec![config.to_str().unwrap().to_owned()];
+        args.invalidate = true;
+        args.invalidate_args.downstream = true;
+        args.tasks = vec!["a".to_owned()];
+        args.branch = vec![branch.to_owned()];
+        App::new(args.try_into()?).run()
+    };
+    let exists = |task: &str, branch: &str| {
+        output.join(task).join("realizations").join(branch).join("exit_code").exists()
+    };
+
+    // b only uses a on x2 through its graft, so it isn't affected:
+    invalidate("Baseline.baseline")?;
+    assert!(!exists("c", "Baseline.baseline"));
+    assert!(exists("b", "Baseline.baseline"), "b grafts a from x2");
+    assert!(exists("c", "X.x2"));
+
+    // while invalidating x2 invalidates b, even though b has no X branch:
+    invalidate("X.x2")?;
+    assert!(!exists("b", "Baseline.baseline"), "b grafts a from x2");
+    assert!(!exists("c", "X.x2"));
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_invalidate_filters() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    std::fs::write(
+        &config,
+        "plan main { reach b via (X: x1 x2) }\n\
+         task a > out=a.txt :: p=(X: x1=1 x2=2) { echo $p > $out }\n\
+         task b < in=$out@a > out=b.txt { cat $in > $out }\n",
+    )?;
+    let output = dir.path().join("output");
+    let mut args = basic_args(output.to_str().unwrap().to_owned());
+    args.configs = vec![config.to_str().unwrap().to_owned()];
+    args.plans = vec!["main".to_owned()];
+    App::new(args.try_into()?).run()?;
+
+    let realization =
+        |task: &str, branch: &str| output.join(task).join("realizations").join(branch);
+    let invalidate_args = || {
+        let mut args = basic_args(output.to_str().unwrap().to_owned());
+        args.configs = vec![config.to_str().unwrap().to_owned()];
+        args.invalidate = true;
+        args
+    };
+
+    // pretend a task failed:
+    std::fs::write(realization("a", "X.x2").join("exit_code"), "1")?;
+
+    // no tasks specified, so this looks at every task in the output dir:
+    let mut args = invalidate_args();
+    args.invalidate_args.failed_only = true;
+    App::new(args.try_into()?).run()?;
+    assert!(
+        !realization("a", "X.x2").exists(),
+        "Failed realization was not deleted"
+    );
+    assert!(
+        realization("a", "Baseline.baseline").exists(),
+        "Successful realization was deleted"
+    );
+    assert!(
+        realization("b", "X.x2").exists(),
+        "Successful realization was deleted"
+    );
+
+    // everything was just run:
+    let mut args = invalidate_args();
+    args.invalidate_args.branch_glob = Some("X.*".to_owned());
+    args.invalidate_args.older_than = Some("1h".to_owned());
+    assert!(
+        App::new(args.try_into()?).run().is_err(),
+        "No realization is old enough"
+    );
+    assert!(
+        realization("b", "X.x2").exists(),
+        "Recent realization was deleted"
+    );
+
+    // a filter that matches nothing is an error, and doesn't touch anything:
+    let mut args = invalidate_args();
+    args.invalidate_args.branch_glob = Some("Y.*".to_owned());
+    assert!(App::new(args.try_into()?).run().is_err());
+    let mut args = invalidate_args();
+    args.tasks = vec!["b".to_owned()];
+    args.invalidate_args.branch_glob = Some("Y.*".to_owned());
+    args.invalidate_args.downstream = true;
+    assert!(App::new(args.try_into()?).run().is_err());
+    assert!(realization("b", "X.x2").exists());
+    assert!(realization("b", "Baseline.baseline").exists());
+    let mut args = invalidate_args();
+    args.invalidate_args.branch_glob = Some(String::new());
+    assert!(Settings::try_from(args).is_err(), "Empty glob is rejected");
+
+    let mut args = invalidate_args();
+    args.invalidate_args.branch_glob = Some("X.*".to_owned());
+    App::new(args.try_into()?).run()?;
+    assert!(
+        !realization("b", "X.x2").exists(),
+        "Matching realization was not deleted"
+    );
+    assert!(
+        realization("b", "Baseline.baseline").exists(),
+        "Non-matching realization was deleted"
+    );
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_gc() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    std::fs::write(
+        &config,
+        "plan main { reach b, c via (X: x1 x2) }\n\
+         task a > out=a.txt :: p=(X: x1=1 x2=2) { echo $p > $out }\n\
+         task b < in=$out@a > out=b.txt { cat $in > $out }\n\
+         task c > out=c.txt { echo c > $out }\n",
+    )?;
+    let output = dir.path().join("output");
+    let mut args = basic_args(output.to_str().unwrap().to_owned());
+    args.configs = vec![config.to_str().unwrap().to_owned()];
+    args.plans = vec!["main".to_owned()];
+    App::new(args.try_into()?).run()?;
+
+    // drop the second branch and task c:
+    std::fs::write(
+        &config,
+        "plan main { reach b via (X: x1) }\n\
+         task a > out=a.txt :: p=(X: x1=1) { echo $p > $out }\n\
+         task b < in=$out@a > out=b.txt { cat $in > $out }\n",
+    )?;
+    let gc_args = |dry_run| {
+        let mut args = basic_args(output.to_str().unwrap().to_owned());
+        args.configs = vec![config.to_str().unwrap().to_owned()];
+        args.command = Some(Command::Gc);
+        args.dry_run = dry_run;
+        args
+    };
+
+    App::new(gc_args(true).try_into()?).run()?;
+    assert!(output.join("c").exists(), "Dry run deleted orphaned task");
+
+    App::new(gc_args(false).try_into()?).run()?;
+    assert!(!output.join("c").exists(), "Orphaned task was not deleted");
+    for task in ["a", "b"] {
+        let task_dir = output.join(task);
+        assert!(
+            !task_dir.join("realizations/X.x2").exists(),
+            "Orphaned realization of {task} was not deleted"
+        );
+        assert!(
+            task_dir.join("realizations/Baseline.baseline/exit_code").exists(),
+            "Live realization of {task} was deleted"
+        );
+        assert!(
+            std::fs::symlink_metadata(task_dir.join("X.x2")).is_err(),
+            "Stale symlink of {task} was not deleted"
+        );
+        assert!(
+            task_dir.join("X.x1").exists(),
+            "Live symlink of {task} was deleted"
+        );
+    }
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_trash() -> Result<()> {
+    let output = run_basic()?;
+    let goal = output.path().join("productbuild/realizations/Baseline.baseline");
+    let trash_args = |empty, restore| {
+        let mut args = basic_args(stringify_dir(&output));
+        args.command = Some(Command::Trash { empty, restore });
+        args
+    };
+
+    let mut args = basic_args(stringify_dir(&output));
+    args.invalidate = true;
+    args.tasks = vec![String::from("productbuild")];
+    App::new(args.try_into()?).run()?;
+    assert!(!goal.exists(), "Invalidated realization was not removed");
+    assert!(
+        output.path().join(".trash").exists(),
+        "Invalidated realization was not moved to trash"
+    );
+
+    App::new(trash_args(false, None).try_into()?).run()?;
+    App::new(trash_args(false, Some(None)).try_into()?).run()?;
+    assert!(
+        goal.join("exit_code").exists(),
+        "Trashed realization was not restored"
+    );
+
+    let mut args = basic_args(stringify_dir(&output));
+    args.invalidate = true;
+    args.tasks = vec![String::from("productbuild")];
+    App::new(args.try_into()?).run()?;
+    App::new(trash_args(true, None).try_into()?).run()?;
+    assert!(
+        !output.path().join(".trash").exists(),
+        "Trash was not emptied"
+    );
+
+    output.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_invalidate_dry_run_deletes_nothing() -> Result<()> {
+    let output = run_basic()?;
+    let goal = output.path().join("productbuild/realizations/Baseline.baseline");
+
+    let mut args = basic_args(stringify_dir(&output));
+    args.invalidate = true;
+    args.invalidate_args.downstream = true;
+    args.dry_run = true;
+    args.tasks = vec![String::from("productbuild")];
+    App::new(args.try_into()?).run()?;
+    assert!(
+        goal.join("exit_code").exists(),
+        "Dry run didn't invalidate anything"
+    );
+
+    output.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_conflicting_outputs() -> Result<()> {
+    let dir = tempdir()?;
+    let shared = dir.path().join("shared.txt");
+    let config = dir.path().join("rebuild.hr");
+    std::fs::write(
+        &config,
+        format!(
+            "plan main {{ reach a via (X: x1 x2) }}\n\
+             task a > out={} :: p=(X: x1=1 x2=2) {{ echo $p > $out }}\n",
+            shared.to_str().unwrap()
+        ),
+    )?;
+    let output = dir.path().join("output");
+    let mut args = basic_args(output.to_str().unwrap().to_owned());
+    args.configs = vec![config.to_str().unwrap().to_owned()];
+    args.plans = vec!["main".to_owned()];
+    assert!(
+        App::new(args.try_into()?).run().is_err(),
+        "Tasks writing to the same output path were allowed to run"
+    );
+    assert!(!shared.exists(), "Conflicting task was run");
+    Ok(())
+}
+
+#[test]
+fn test_prune_stale_links() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    std::fs::write(
+        &config,
+        "plan main { reach a via (X: x1 x2) }\n\
+         task a > out=a.txt :: p=(X: x1=1 x2=2) { echo $p > $out }\n",
+    )?;
+    let output = dir.path().join("output");
+    let run = || -> Result<()> {
+        let mut args = basic_args(output.to_str().unwrap().to_owned());
+        args.configs = vec![config.to_str().unwrap().to_owned()];
+        args.plans = vec!["main".to_owned()];
+        App::new(args.try_into()?).run()
+    };
+    run()?;
+
+    let task_dir = output.join("a");
+    let link_target = |link: &str| std::fs::read_link(task_dir.join(link)).ok();
+    // point one link at the wrong realization, delete another, and leave a dangling one:
+    std::fs::remove_file(task_dir.join("X.x1"))?;
+    std::os::unix::fs::symlink("realizations/X.x2", task_dir.join("X.x1"))?;
+    std::fs::remove_file(task_dir.join("X.x2"))?;
+    std::os::unix::fs::symlink("realizations/X.gone", task_dir.join("X.gone"))?;
+
+    // add a task so that there's something to run:
+    std::fs::write(
+        &config,
+        "plan main { reach b via (X: x1 x2) }\n\
+         task a > out=a.txt :: p=(X: x1=1 x2=2) { echo $p > $out }\n\
+         task b < in=$out@a > out=b.txt { cat $in > $out }\n",
+    )?;
+    run()?;
+    assert_eq!(
+        link_target("X.x1"),
+        Some(PathBuf::from("realizations/Baseline.baseline")),
+        "Mismatched symlink was not repaired"
+    );
+    assert_eq!(
+        link_target("X.x2"),
+        Some(PathBuf::from("realizations/X.x2")),
+        "Missing symlink was not recreated"
+    );
+    assert!(
+        link_target("X.gone").is_none(),
+        "Dangling symlink was not deleted"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_latest_link() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    std::fs::write(
+        &config,
+        "task a > out=a.txt :: p=(X: x1=1 x2=2) { echo $p > $out }\n",
+    )?;
+    let output = dir.path().join("output");
+    let run = |branch: &str| -> Result<()> {
+        let mut args = basic_args(output.to_str().unwrap().to_owned());
+        args.configs = vec![config.to_str().unwrap().to_owned()];
+        args.tasks = vec!["a".to_owned()];
+        args.branch = vec![branch.to_owned()];
+        App::new(args.try_into()?).run()
+    };
+    let latest = output.join("a/latest");
+
+    run("X.x2")?;
+    assert_eq!(
+        std::fs::read_link(&latest)?,
+        PathBuf::from("realizations/X.x2")
+    );
+    assert_eq!(std::fs::read_to_string(latest.join("a.txt"))?, "2\n");
+
+    run("X.x1")?;
+    assert_eq!(
+        std::fs::read_link(&latest)?,
+        PathBuf::from("realizations/Baseline.baseline")
+    );
+    Ok(())
+}
+
+#[test]
+fn test_short_names() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    std::fs::write(
+        &config,
+        "plan main { reach a via (X: x1 x2) }\n\
+         task a > out=a.txt :: p=(X: x1=1 x2=2) { echo $p > $out }\n",
+    )?;
+    let output = dir.path().join("output");
+    let args = || {
+        let mut args = basic_args(output.to_str().unwrap().to_owned());
+        args.configs = vec![config.to_str().unwrap().to_owned()];
+        args.plans = vec!["main".to_owned()];
+        args
+    };
+    let mut short_args = args();
+    short_args.short_names = true;
+    App::new(short_args.try_into()?).run()?;
+
+    let realizations = output.join("a/realizations");
+    let mut branches = Vec::new();
+    for entry in std::fs::read_dir(&realizations)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap().to_str().unwrap().to_owned();
+        assert_eq!(
+            name.len(),
+            16,
+            "Realization dir {name} was not named by hash"
+        );
+        branches.push(std::fs::read_to_string(path.join("branch.txt"))?);
+    }
+    branches.sort();
+    assert_eq!(branches, vec!["Baseline.baseline\n", "X.x2\n"]);
+    let branchpoints = std::fs::read_to_string(output.join("branchpoints.txt"))?;
+    assert!(branchpoints.lines().any(|line| line.ends_with(" X.x2")));
+
+    // short names are remembered, so nothing needs to run again:
+    App::new(args().try_into()?).run()?;
+    assert_eq!(std::fs::read_dir(&realizations)?.count(), 2);
+
+    // realizations can still be found by branch:
+    let mut args = args();
+    args.plans = Vec::new();
+    args.invalidate = true;
+    args.tasks = vec!["a".to_owned()];
+    args.branch = vec!["X.x2".to_owned()];
+    App::new(args.try_into()?).run()?;
+    for entry in std::fs::read_dir(&realizations)? {
+        let path = entry?.path();
+        let invalidated = std::fs::read_to_string(path.join("branch.txt"))? == "X.x2\n";
+        assert_eq!(
+            path.join("exit_code").exists(),
+            !invalidated,
+            "Wrong realization was invalidated"
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn test_repair_branchpoints() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    std::fs::write(
+        &config,
+        "plan main { reach a via (X: x1 x2) }\n\
+         task a > out=a.txt :: p=(X: x1=1 x2=2) { echo $p > $out }\n",
+    )?;
+    let output = dir.path().join("output");
+    let args = || {
+        let mut args = basic_args(output.to_str().unwrap().to_owned());
+        args.configs = vec![config.to_str().unwrap().to_owned()];
+        args
+    };
+    let mut run_args = args();
+    run_args.plans = vec!["main".to_owned()];
+    App::new(run_args.try_into()?).run()?;
+
+    let branchpoints = output.join("branchpoints.txt");
+    assert_eq!(
+        std::fs::read_to_string(&branchpoints)?,
+        "version 2\nbranchpoint X x1 x2\n"
+    );
+
+    // a corrupt file is an error:
+    std::fs::write(&branchpoints, "version 2\nbranchpoint X\n")?;
+    let mut run_args = args();
+    run_args.plans = vec!["main".to_owned()];
+    assert!(App::new(run_args.try_into()?).run().is_err());
+
+    // repairing skips the invalid line, and rebuilds it from the config:
+    let repair = || -> Result<()> {
+        let mut repair_args = args();
+        repair_args.command = Some(Command::RepairBranchpoints);
+        App::new(repair_args.try_into()?).run()
+    };
+    repair()?;
+    assert_eq!(
+        std::fs::read_to_string(&branchpoints)?,
+        "version 2\nbranchpoint X x1 x2\n"
+    );
+
+    // x2 is no longer in the config, so it's dropped even though its realization is on disk:
+    std::fs::write(
+        &config,
+        "task a > out=a.txt :: p=(X: x1=1) { echo $p > $out }\n",
+    )?;
+    repair()?;
+    assert_eq!(
+        std::fs::read_to_string(&branchpoints)?,
+        "version 2\nbranchpoint X x1\n"
+    );
+    assert!(
+        output.join("a/realizations/X.x2").exists(),
+        "Realizations aren't touched"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_module_git_state() -> Result<()> {
+    let dir = tempdir()?;
+    let module = dir.path().join("module");
+    std::fs::create_dir(&module)?;
+    std::fs::write(module.join("src.txt"), "v1\n")?;
+    let git = |args: &[&str]| -> Result<String> {
+        let output = std::process::Command::new("git")
+            .arg("-C")
+            .arg(&module)
+            .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .output()?;
+        assert!(output.status.success(), "git {args:?}");
+        Ok(String::from_utf8(output.stdout)?.trim().to_owned())
+    };
+    git(&["init", "-q"])?;
+    git(&["add", "src.txt"])?;
+    git(&["commit", "-q", "-m", "init"])?;
+    let commit = git(&["rev-parse", "HEAD"])?;
+
+    let config = dir.path().join("rebuild.hr");
+    std::fs::write(
+        &config,
+        format!(
+            "module m={}\n\
+             task a @m > out=out.txt {{ cp src.txt $out }}\n",
+            module.display()
+        ),
+    )?;
+    let output = dir.path().join("output");
+    let args = || {
+        let mut args = basic_args(output.to_str().unwrap().to_owned());
+        args.configs = vec![config.to_str().unwrap().to_owned()];
+        args.tasks = vec!["a".to_owned()];
+        args
+    };
+    App::new(args().try_into()?).run()?;
+
+    let realization = output.join("a/realizations/Baseline.baseline");
+    let text = std::fs::read_to_string(realization.join("task_info.json"))?;
+    let info = TaskInfo::from_json(&text)?;
+    let git_state = info.module_git.expect("module git state");
+    assert_eq!(git_state.commit, commit);
+    assert!(!git_state.dirty);
+
+    // a dirty module runs by default, but not with --require-clean-modules:
+    std::fs::write(module.join("src.txt"), "v2\n")?;
+    std::fs::remove_dir_all(&realization)?;
+    let mut strict_args = args();
+    strict_args.require_clean_modules = true;
+    assert!(App::new(strict_args.try_into()?).run().is_err());
+    assert!(!realization.join("exit_code").exists());
+
+    App::new(args().try_into()?).run()?;
+    let text = std::fs::read_to_string(realization.join("task_info.json"))?;
+    let info = TaskInfo::from_json(&text)?;
+    assert!(info.module_git.expect("module git state").dirty);
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_versioned_module() -> Result<()> {
+    let dir = tempdir()?;
+    let repo = dir.path().join("repo");
+    std::fs::create_dir(&repo)?;
+    let git = |args: &[&str]| -> Result<String> {
+        let output = std::process::Command::new("git")
+            .arg("-C")
+            .arg(&repo)
+            .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .output()?;
+        assert!(output.status.success(), "git {args:?}");
+        Ok(String::from_utf8(output.stdout)?.trim().to_owned())
+    };
+    let commit_version = |version: &str| -> Result<String> {
+        std::fs::write(repo.join("src.txt"), format!("{version}\n"))?;
+        git(&["add", "src.txt"])?;
+        git(&["commit", "-q", "-m", version])?;
+        git(&["rev-parse", "HEAD"])
+    };
+    git(&["init", "-q", "-b", "main"])?;
+    let v1 = commit_version("v1")?;
+    git(&["tag", "v1"])?;
+    let v2 = commit_version("v2")?;
+
+    let config = dir.path().join("rebuild.hr");
+    std::fs::write(
+        &config,
+        format!(
+            "module m={} :: rev=(Ver: old=v1 new=main)\n\
+             plan both {{ reach a via (Ver: old new) }}\n\
+             task a @m > out=out.txt {{ cat src.txt > $out; echo $HR_MODULE_m_COMMIT >> $out }}\n",
+            repo.display()
+        ),
+    )?;
+    let output = dir.path().join("output");
+    let args = || {
+        let mut args = basic_args(output.to_str().unwrap().to_owned());
+        args.configs = vec![config.to_str().unwrap().to_owned()];
+        args.plans = vec!["both".to_owned()];
+        args
+    };
+    App::new(args().try_into()?).run()?;
+
+    let old = output.join("a/realizations/Baseline.baseline");
+    let new = output.join("a/realizations/Ver.new");
+    let out = |realization: &PathBuf| std::fs::read_to_string(realization.join("out.txt"));
+    assert_eq!(out(&old)?, format!("v1\n{v1}\n"));
+    assert_eq!(out(&new)?, format!("v2\n{v2}\n"));
+    assert!(output.join(".modules/m").join(&v1).join("src.txt").exists());
+
+    let text = std::fs::read_to_string(old.join("task_info.json"))?;
+    let info = TaskInfo::from_json(&text)?;
+    assert_eq!(info.module_git.expect("module git state").commit, v1);
+    assert_eq!(
+        info.params,
+        vec![("HR_MODULE_m_COMMIT".to_owned(), v1.clone())]
+    );
+
+    // when the branch moves, only the realization that uses it reruns:
+    let v3 = commit_version("v3")?;
+    std::fs::write(old.join("out.txt"), "untouched\n")?;
+    App::new(args().try_into()?).run()?;
+    assert_eq!(out(&old)?, "untouched\n");
+    assert_eq!(out(&new)?, format!("v3\n{v3}\n"));
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_multiple_modules() -> Result<()> {
+    let dir = tempdir()?;
+    for module in ["app", "sdk"] {
+        std::fs::create_dir(dir.path().join(module))?;
+        std::fs::write(
+            dir.path().join(module).join("name.txt"),
+            format!("{module}\n"),
+        )?;
+    }
+    let config = dir.path().join("rebuild.hr");
+    std::fs::write(
+        &config,
+        "module app=app\n\
+         module sdk=sdk\n\
+         module missing=missing\n\
+         task build @app @sdk > out=out.txt { cat name.txt $HR_MODULE_sdk/name.txt > $out }\n\
+         task broken @app @missing > out=out.txt { touch $out }\n",
+    )?;
+    let output = dir.path().join("output");
+    let args = |task: &str| {
+        let mut args = basic_args(output.to_str().unwrap().to_owned());
+        args.configs = vec![config.to_str().unwrap().to_owned()];
+        args.tasks = vec![task.to_owned()];
+        args
+    };
+    App::new(args("build").try_into()?).run()?;
+
+    let realization = output.join("build/realizations/Baseline.baseline");
+    let text = std::fs::read_to_string(realization.join("out.txt"))?;
+    assert_eq!(text, "app\nsdk\n");
+    let text = std::fs::read_to_string(realization.join("task_info.json"))?;
+    let info = TaskInfo::from_json(&text)?;
+    let names: Vec<&str> = info.modules.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(names, vec!["app", "sdk"]);
+    assert_eq!(info.module.as_ref(), Some(&info.modules[0].1));
+
+    // every module is checked, not just the one the task runs in:
+    assert!(App::new(args("broken").try_into()?).run().is_err());
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_module_path_values() -> Result<()> {
+    let dir = tempdir()?;
+    let repo_root = dir.path().join("repo");
+    for module in ["plugins", "sdk1", "sdk2"] {
+        let module_dir = if module == "plugins" {
+            repo_root.join(module)
+        } else {
+            dir.path().join(module)
+        };
+        std::fs::create_dir_all(&module_dir)?;
+        std::fs::write(module_dir.join("name.txt"), format!("{module}\n"))?;
+    }
+    let config = dir.path().join("rebuild.hr");
+    std::fs::write(
+        &config,
+        format!(
+            "global {{\n  repo_root={}\n}}\n\
+             module plugins=\"$repo_root/plugins\"\n\
+             module sdk=(Sdk: v1=sdk1 v2=sdk2 v3=sdk3)\n\
+             task build @sdk @plugins > out=out.txt {{ cat $HR_MODULE_plugins/name.txt name.txt > $out }}\n\
+             plan all {{ reach build via (Sdk: v1 v2) }}\n\
+             plan missing {{ reach build via (Sdk: v3) }}\n",
+            repo_root.display()
+        ),
+    )?;
+    let output = dir.path().join("output");
+    let args = |plan: &str| {
+        let mut args = basic_args(output.to_str().unwrap().to_owned());
+        args.configs = vec![config.to_str().unwrap().to_owned()];
+        args.plans = vec![plan.to_owned()];
+        args
+    };
+    App::new(args("all").try_into()?).run()?;
+
+    let text = std::fs::read_to_string(output.join("build/Sdk.v1/out.txt"))?;
+    assert_eq!(text, "plugins\nsdk1\n");
+    let text = std::fs::read_to_string(output.join("build/Sdk.v2/out.txt"))?;
+    assert_eq!(text, "plugins\nsdk2\n");
+
+    // module paths aren't params, so they don't show up in the task's params:
+    let text = std::fs::read_to_string(output.join("build/Sdk.v2/task_info.json"))?;
+    let info = TaskInfo::from_json(&text)?;
+    assert!(info.params.is_empty());
+    let sdk = dir.path().join("sdk2").canonicalize()?;
+    assert_eq!(info.module.as_deref(), sdk.to_str());
+
+    // the dir for this branch doesn't exist:
+    assert!(App::new(args("missing").try_into()?).run().is_err());
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_func() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    std::fs::write(
+        &config,
+        "task greet > out=out.txt :: who=world { shout \"hello $who\" > $out }\n\
+         func shout {\n  echo \"$(upper \"$1\")!\"\n}\n\
+         func upper {\n  echo \"$1\" | tr a-z A-Z\n}\n\
+         func unused {\n  echo unused\n}\n",
+    )?;
+    let output = dir.path().join("output");
+    let mut args = basic_args(output.to_str().unwrap().to_owned());
+    args.configs = vec![config.to_str().unwrap().to_owned()];
+    args.tasks = vec!["greet".to_owned()];
+    App::new(args.try_into()?).run()?;
+
+    // funcs can be defined after the tasks that use them, and can call each other:
+    let realization = output.join("greet/realizations/Baseline.baseline");
+    let text = std::fs::read_to_string(realization.join("out.txt"))?;
+    assert_eq!(text, "HELLO WORLD!\n");
+    let script = std::fs::read_to_string(realization.join("task.sh"))?;
+    assert!(script.contains("shout() {\n"));
+    assert!(script.contains("upper() {\n"));
+    assert!(!script.contains("unused"));
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_plan_union() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    let runs = dir.path().join("runs.txt");
+    std::fs::write(
+        &config,
+        format!(
+            "plan debug {{ reach build via (Profile: debug) }}\n\
+             plan both {{ reach build via (Profile: debug release) }}\n\
+             task build > out=out.txt :: profile=(Profile: debug release) {{\n  \
+               echo $profile >> {runs}\n  echo $profile > $out\n}}\n",
+            runs = runs.display(),
+        ),
+    )?;
+    let output = dir.path().join("output");
+    let mut args = basic_args(output.to_str().unwrap().to_owned());
+    args.configs = vec![config.to_str().unwrap().to_owned()];
+    args.plans = vec!["debug".to_owned(), "both".to_owned()];
+    App::new(args.try_into()?).run()?;
+
+    // realizations reached by both plans only run once:
+    let text = std::fs::read_to_string(&runs)?;
+    let mut lines: Vec<_> = text.lines().collect();
+    lines.sort_unstable();
+    assert_eq!(lines, ["debug", "release"]);
+
+    let mut args = basic_args(output.to_str().unwrap().to_owned());
+    args.configs = vec![config.to_str().unwrap().to_owned()];
+    args.plans = vec!["debug".to_owned(), "missing".to_owned()];
+    assert!(App::new(args.try_into()?).run().is_err());
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_plan_include() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    std::fs::write(
+        &config,
+        "plan all { include release; include debug }\n\
+         plan release { include debug; reach notarize via (Profile: release) }\n\
+         plan debug { reach build via (Profile: debug) }\n\
+         task build > out=out.txt :: profile=(Profile: debug release) { echo $profile > $out }\n\
+         task notarize < in=$out@build > out=out.txt { cp $in $out }\n",
+    )?;
+    let output = dir.path().join("output");
+    let args = |plan: &str| {
+        let mut args = basic_args(output.to_str().unwrap().to_owned());
+        args.configs = vec![config.to_str().unwrap().to_owned()];
+        args.plans = vec![plan.to_owned()];
+        args
+    };
+    App::new(args("release").try_into()?).run()?;
+
+    // plans can include plans defined after them, which can include other plans:
+    assert!(output.join("build/realizations/Baseline.baseline/out.txt").exists());
+    assert!(output.join("notarize/realizations/Profile.release/out.txt").exists());
+    assert!(!output.join("notarize/realizations/Baseline.baseline").exists());
+    App::new(args("all").try_into()?).run()?;
+
+    // a plan can't include itself, even indirectly:
+    let mut text = std::fs::read_to_string(&config)?;
+    text.push_str("plan loop { include loop2 }\nplan loop2 { include loop }\n");
+    std::fs::write(&config, text)?;
+    assert!(App::new(args("debug").try_into()?).run().is_err());
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_plan_except() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    std::fs::write(
+        &config,
+        "plan main { reach build via (Os: *) * (Arch: *) except (Os: windows * Arch: arm64) }\n\
+         task build > out=out.txt :: os=(Os: mac windows) arch=(Arch: x86 arm64) {\n  \
+           echo $os $arch > $out\n}\n",
+    )?;
+    let output = dir.path().join("output");
+    let args = |plan: &str| {
+        let mut args = basic_args(output.to_str().unwrap().to_owned());
+        args.configs = vec![config.to_str().unwrap().to_owned()];
+        args.plans = vec![plan.to_owned()];
+        args
+    };
+    App::new(args("main").try_into()?).run()?;
+
+    let mut realizations = std::fs::read_dir(output.join("build/realizations"))?
+        .map(|entry| Ok(entry?.file_name().to_str().unwrap().to_owned()))
+        .collect::<Result<Vec<_>>>()?;
+    realizations.sort_unstable();
+    // (Os: windows) * (Arch: arm64) is the only combination excluded:
+    assert_eq!(
+        realizations,
+        [
+            "Baseline.baseline",
+            "Baseline.baseline+Arch.arm64",
+            "Baseline.baseline+Os.windows"
+        ]
+    );
+
+    // excluding everything a line reaches is an error:
+    let mut text = std::fs::read_to_string(&config)?;
+    text.push_str("plan none { reach build via (Os: mac) except (Os: mac windows) }\n");
+    std::fs::write(&config, text)?;
+    assert!(App::new(args("main").try_into()?).run().is_err());
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_multiple_branch_flags() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    std::fs::write(
+        &config,
+        "task build > out=out.txt :: os=(Os: mac windows) arch=(Arch: x86 arm64) {\n  \
+           echo $os $arch > $out\n}\n",
+    )?;
+    let output = dir.path().join("output");
+    let args = |branches: &[&str]| {
+        let mut args = basic_args(output.to_str().unwrap().to_owned());
+        args.configs = vec![config.to_str().unwrap().to_owned()];
+        args.tasks = vec!["build".to_owned()];
+        args.branch = branches.iter().map(|b| (*b).to_owned()).collect();
+        args
+    };
+    let realizations = || {
+        let mut realizations = std::fs::read_dir(output.join("build/realizations"))?
+            .map(|entry| Ok(entry?.file_name().to_str().unwrap().to_owned()))
+            .collect::<Result<Vec<_>>>()?;
+        realizations.sort_unstable();
+        Ok::<_, anyhow::Error>(realizations)
+    };
+
+    // repeated flags and comma lists give the cross product of each branchpoint's values:
+    App::new(args(&["Os.mac,Os.windows", "Arch.x86", "Arch.arm64"]).try_into()?).run()?;
+    assert_eq!(
+        realizations()?,
+        [
+            "Baseline.baseline",
+            "Baseline.baseline+Arch.arm64",
+            "Baseline.baseline+Os.windows",
+            "Os.windows+Arch.arm64"
+        ]
+    );
+
+    let finished = || -> Result<Vec<String>> {
+        let realizations = realizations()?.into_iter();
+        Ok(realizations
+            .filter(|r| output.join("build/realizations").join(r).join("exit_code").exists())
+            .collect())
+    };
+    let invalidate = |branches: &[&str]| -> Result<()> {
+        let mut args = args(branches);
+        args.invalidate = true;
+        App::new(args.try_into()?).run()
+    };
+
+    // flags for different branchpoints only touch the branch where they meet:
+    invalidate(&["Os.windows", "Arch.arm64"])?;
+    assert_eq!(
+        finished()?,
+        [
+            "Baseline.baseline",
+            "Baseline.baseline+Arch.arm64",
+            "Baseline.baseline+Os.windows"
+        ]
+    );
+    App::new(args(&["Os.mac,Os.windows", "Arch.x86", "Arch.arm64"]).try_into()?).run()?;
+    assert_eq!(finished()?, realizations()?);
+
+    invalidate(&["Os.windows+Arch.x86,Arch.arm64"])?;
+    assert_eq!(
+        finished()?,
+        ["Baseline.baseline", "Baseline.baseline+Arch.arm64"]
+    );
+
+    // a shell can only be opened in one realization:
+    let mut shell = args(&["Os.mac,Os.windows"]);
+    shell.command = Some(Command::Shell {
+        task: "build".to_owned(),
+    });
+    let e = Settings::try_from(shell).unwrap_err();
+    assert!(e.to_string().contains("needs a single branch"));
+
+    // nor be pointed at a plan instead of the task:
+    let mut shell = args(&["Os.mac"]);
+    shell.plans = vec!["main".to_owned()];
+    shell.command = Some(Command::Which {
+        task: "build".to_owned(),
+    });
+    let e = Settings::try_from(shell).unwrap_err();
+    assert_eq!(
+        e.to_string(),
+        "'hr which' works on the task given to it, so it can't be given a plan with -p"
+    );
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_task_patterns() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    std::fs::write(
+        &config,
+        "task build_mac > out { touch $out }\n\
+         task build_win > out { touch $out }\n\
+         task test_unit { true }\n\
+         task test_integ { true }\n\
+         task package { true }\n",
+    )?;
+    let output = dir.path().join("output");
+    let args = |tasks: &[&str], regexes: &[&str]| {
+        let mut args = basic_args(output.to_str().unwrap().to_owned());
+        args.configs = vec![config.to_str().unwrap().to_owned()];
+        args.tasks = tasks.iter().map(|t| (*t).to_owned()).collect();
+        args.task_regexes = regexes.iter().map(|re| (*re).to_owned()).collect();
+        args
+    };
+    let ran = |task: &str| output.join(task).join("realizations").exists();
+
+    App::new(args(&["build_*"], &[]).try_into()?).run()?;
+    assert!(ran("build_mac") && ran("build_win"));
+    assert!(!ran("test_unit") && !ran("package"));
+
+    App::new(args(&["package"], &["^test_"]).try_into()?).run()?;
+    assert!(ran("test_unit") && ran("test_integ") && ran("package"));
+
+    let e = App::new(args(&["deploy_*"], &[]).try_into()?).run().unwrap_err();
+    assert_eq!(e.to_string(), "No tasks match 'deploy_*'");
+    let e = App::new(args(&[], &["^lint"]).try_into()?).run().unwrap_err();
+    assert_eq!(e.to_string(), "No tasks match '^lint'");
+    let e = Settings::try_from(args(&[], &["(test"])).unwrap_err();
+    assert_eq!(e.to_string(), "invalid --task-regex '(test'");
+
+    let mut shell = args(&[], &[]);
+    shell.command = Some(Command::Shell {
+        task: "test_*".to_owned(),
+    });
+    let e = App::new(shell.try_into()?).run().unwrap_err();
+    assert!(e.to_string().contains("matches test_unit, test_integ"));
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_until_and_from() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    std::fs::write(
+        &config,
+        "plan main { reach c via (X: x1 x2) }\n\
+         task a > out=a.txt { echo a > $out }\n\
+         task b < in=$out@a > out=b.txt :: x=(X: x1 x2) { cat $in > $out; echo $x >> $out }\n\
+         task c < in=$out@b > out=c.txt { echo c > $out }\n",
+    )?;
+    let args = |output: &std::path::Path, run_args: RunArgs| {
+        let mut args = basic_args(output.to_str().unwrap().to_owned());
+        args.configs = vec![config.to_str().unwrap().to_owned()];
+        args.plans = vec!["main".to_owned()];
+        args.run_args = run_args;
+        args
+    };
+    let ran = |output: &std::path::Path, task: &str| output.join(task).exists();
+
+    // --until stops after every realization of the task that the plan reaches:
+    let output = dir.path().join("until");
+    let until = || RunArgs {
+        until: Some("b".to_owned()),
+        ..RunArgs::default()
+    };
+    App::new(args(&output, until()).try_into()?).run()?;
+    assert!(ran(&output, "a") && !ran(&output, "c"));
+    assert!(output.join("b/realizations/Baseline.baseline/exit_code").exists());
+    assert!(output.join("b/realizations/X.x2/exit_code").exists());
+
+    // --from skips the task and its antecedents, even though a's code has changed:
+    App::new(args(&output, RunArgs::default()).try_into()?).run()?;
+    let c = output.join("c/realizations/X.x2/c.txt");
+    let text = std::fs::read_to_string(&config)?;
+    let text = text.replace("echo a", "echo A").replace("echo c", "echo C");
+    std::fs::write(&config, text)?;
+    let from = RunArgs {
+        from: Some("b".to_owned()),
+        ..RunArgs::default()
+    };
+    App::new(args(&output, from).try_into()?).run()?;
+    assert_eq!(
+        std::fs::read_to_string(output.join("a/realizations/Baseline.baseline/a.txt"))?,
+        "a\n"
+    );
+    assert_eq!(std::fs::read_to_string(c)?, "C\n");
+
+    let bad = RunArgs {
+        until: Some("deploy".to_owned()),
+        ..RunArgs::default()
+    };
+    let e = App::new(args(&output, bad).try_into()?).run().unwrap_err();
+    assert_eq!(
+        e.to_string(),
+        "Task 'deploy' given to --until isn't part of the target"
+    );
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_produces() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    std::fs::write(
+        &config,
+        "task a > out=a.txt { echo a > $out }\n\
+         task pkg < in=$out@a > pkg=(Os: mac=installer.pkg win=setup.msi) { cp $in $pkg }\n\
+         task other > log { echo other > $log }\n\
+         task copy < in=$out@a > out=copy.txt { cp $in $out }\n",
+    )?;
+    let output = dir.path().join("output");
+    let run = |produces: &[&str]| {
+        let mut args = basic_args(output.to_str().unwrap().to_owned());
+        args.configs = vec![config.to_str().unwrap().to_owned()];
+        args.run_args.produces = produces.iter().map(|s| s.to_string()).collect();
+        App::new(args.try_into()?).run()
+    };
+
+    // a file name finds the task, and the branch it has that name on:
+    run(&["setup.msi"])?;
+    assert!(output.join("a/realizations/Baseline.baseline/a.txt").exists());
+    assert!(output.join("pkg/realizations/Os.win/setup.msi").exists());
+    assert!(!output.join("pkg/realizations/Baseline.baseline").exists());
+    assert!(!output.join("other").exists());
+
+    // so does a path in the output dir:
+    let log = output.join("other/realizations/Baseline.baseline/log");
+    run(&[log.to_str().unwrap(), "installer.pkg"])?;
+    assert!(log.exists());
+    assert!(output.join("pkg/realizations/Baseline.baseline/installer.pkg").exists());
+
+    let e = run(&["setup.ms"]).unwrap_err();
+    assert_eq!(
+        e.to_string(),
+        "No task produces 'setup.ms' (did you mean `setup.msi`?)"
+    );
+
+    // an output name that several tasks have doesn't pick one of them:
+    let e = run(&["out"]).unwrap_err();
+    assert_eq!(
+        e.to_string(),
+        "'out' is produced by several tasks (a, copy); give its path in the output dir instead"
+    );
+    assert!(!output.join("copy").exists());
+
+    // a branch link in the task dir leads to its realization, but other files there don't:
+    std::fs::remove_dir_all(output.join("pkg/realizations/Os.win"))?;
+    run(&[output.join("pkg/Os.win/setup.msi").to_str().unwrap()])?;
+    assert!(output.join("pkg/realizations/Os.win/setup.msi").exists());
+    std::fs::write(output.join("pkg/notes.txt"), "notes")?;
+    let notes = output.join("pkg/notes.txt");
+    let e = run(&[notes.to_str().unwrap()]).unwrap_err();
+    assert!(e.to_string().starts_with("No task produces"), "{e}");
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_check() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    std::fs::write(
+        &config,
+        "plan main { reach b via (X: *) }\n\
+         task a > out=out.txt :: x=(X: x1 x2) { echo $x > $out }\n\
+         task b < in=$out@a > out=out.txt { cp $in $out }\n",
+    )?;
+    let output = dir.path().join("output");
+    let args = || {
+        let mut args = basic_args(output.to_str().unwrap().to_owned());
+        args.configs = vec![config.to_str().unwrap().to_owned()];
+        args.command = Some(Command::Check);
+        args
+    };
+    App::new(args().try_into()?).run()?;
+    // checking doesn't create the output dir:
+    assert!(!output.exists());
+
+    // errors in any plan fail the check:
+    let mut text = std::fs::read_to_string(&config)?;
+    text.push_str("plan bad { reach c }\ntask c :: y=$nope { echo $y }\n");
+    std::fs::write(&config, text)?;
+    assert!(App::new(args().try_into()?).run().is_err());
+    // unless only the plan without errors is checked:
+    let mut args = args();
+    args.plans = vec!["main".to_owned()];
+    App::new(args.try_into()?).run()?;
+    assert!(!output.exists());
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_ducttape_compat() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("lm.tape");
+    std::fs::write(
+        &config,
+        "package lm :: .versioner=git .repo=\"https://example.com/lm.git\" .ref=HEAD {\n  make\n}\n\
+         plan main { reach train via (Seed: *) }\n\
+         task train : lm > model :: seed=(Seed: 1..5..2) { echo $seed > $model }\n",
+    )?;
+    let output = dir.path().join("output");
+    let args = |compat| {
+        let mut args = basic_args(output.to_str().unwrap().to_owned());
+        args.configs = vec![config.to_str().unwrap().to_owned()];
+        args.plans = vec!["main".to_owned()];
+        args.compat = compat;
+        args
+    };
+    // ducttape syntax is a parse error without '--compat':
+    assert!(App::new(args(None).try_into()?).run().is_err());
+
+    // with it, the sequence becomes a branchpoint, and the package is left out:
+    App::new(args(Some(Compat::Ducttape)).try_into()?).run()?;
+    let mut seeds = Vec::new();
+    for entry in std::fs::read_dir(output.join("train/realizations"))? {
+        seeds.push(std::fs::read_to_string(entry?.path().join("model"))?);
+    }
+    seeds.sort();
+    assert_eq!(seeds, ["1\n", "3\n", "5\n"]);
+
+    dir.close()?;
+    Ok(())
+}
+
+/// Records the events it's given, e.g. "start a".
+struct Recorder(Arc<Mutex<Vec<String>>>);
+
+impl ExecObserver for Recorder {
+    fn on_task_start(&mut self, task: &TaskEvent) {
+        self.0.lock().unwrap().push(format!("start {}", task.task));
+    }
+    fn on_task_complete(&mut self, task: &TaskEvent, restored: bool, _duration: Duration) {
+        self.0.lock().unwrap().push(format!("complete {} {restored}", task.task));
+    }
+    fn on_task_failed(&mut self, task: &TaskEvent, _error: &anyhow::Error, _duration: Duration) {
+        self.0.lock().unwrap().push(format!("failed {}", task.task));
+    }
+    fn on_workflow_complete(&mut self, succeeded: bool) {
+        self.0.lock().unwrap().push(format!("workflow {succeeded}"));
+    }
+}
+
+#[test]
+fn test_observer() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    std::fs::write(
+        &config,
+        "plan main { reach b }\n\
+         task a > out=out.txt { echo hi > $out }\n\
+         task b < in=$out@a { exit 3 }\n",
+    )?;
+    let output = dir.path().join("output");
+    let mut args = basic_args(output.to_str().unwrap().to_owned());
+    args.configs = vec![config.to_str().unwrap().to_owned()];
+    args.plans = vec!["main".to_owned()];
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let mut app = App::new(args.try_into()?);
+    app.add_observer(Box::new(Recorder(events.clone())));
+    assert!(app.run().is_err());
+    assert_eq!(
+        *events.lock().unwrap(),
+        [
+            "start a",
+            "complete a false",
+            "start b",
+            "failed b",
+            "workflow false"
+        ]
+    );
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_subcommands() -> Result<()> {
+    use clap::Parser;
+    let settings = |argv: &[&str]| -> Result<Settings> {
+        let argv = ["hr", "-c", "examples/stub.tconf"].iter().chain(argv);
+        Args::try_parse_from(argv)?.try_into()
+    };
+
+    // 'hr run' does what 'hr' does, with run options given before or after it:
+    for argv in [
+        &["-p", "debug", "--in-place", "-y"][..],
+        &["run", "-p", "debug", "--in-place", "-y"],
+        &["--in-place", "run", "-y", "-p", "debug"],
+    ] {
+        let settings = settings(argv)?;
+        assert!(matches!(settings.mode, Mode::Run) && !settings.atomic);
+        assert_eq!(settings.plans, ["debug"]);
+    }
+
+    // 'hr invalidate' does what 'hr -x' does:
+    for argv in [
+        &["-x", "-t", "a", "--downstream"][..],
+        &["invalidate", "-t", "a", "--downstream"],
+    ] {
+        let settings = settings(argv)?;
+        assert!(matches!(settings.mode, Mode::Invalidate) && settings.downstream);
+        assert_eq!(settings.tasks, ["a"]);
+    }
+
+    // targets can be given after any command:
+    let status = settings(&["status", "-t", "a", "-b", "X.x1"])?;
+    assert!(matches!(status.mode, Mode::Status));
+    assert_eq!(status.tasks, ["a"]);
+
+    // but options only go with the commands they apply to:
+    assert!(settings(&["-t", "a", "--downstream"]).is_err());
+    assert!(settings(&["-x", "run", "-t", "a"]).is_err());
+    assert!(settings(&["status", "--in-place"]).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_status() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    std::fs::write(
+        &config,
+        "plan main { reach b via (X: *) }\n\
+         global {\n  x=(X: x1 x2)\n}\n\
+         task a > out=out.txt :: x=$x { echo $x > $out }\n\
+         task b < in=$out@a > out=out.txt :: x=$x { test $x = x1 && cp $in $out }\n",
+    )?;
+    let output = dir.path().join("output");
+    let args = |command| {
+        let mut args = basic_args(output.to_str().unwrap().to_owned());
+        args.configs = vec![config.to_str().unwrap().to_owned()];
+        args.plans = vec!["main".to_owned()];
+        args.command = command;
+        args
+    };
+    // nothing has run yet, and status doesn't create the output dir:
+    App::new(args(Some(Command::Status)).try_into()?).run()?;
+    assert!(!output.exists());
+
+    // b fails on X.x2, leaving failed and incomplete realizations behind:
+    assert!(App::new(args(None).try_into()?).run().is_err());
+    let realizations = |task: &str| -> Result<usize> {
+        Ok(std::fs::read_dir(output.join(task).join("realizations"))?.count())
+    };
+    let before = (realizations("a")?, realizations("b")?);
+
+    // status doesn't delete them:
+    App::new(args(Some(Command::Status)).try_into()?).run()?;
+    assert_eq!((realizations("a")?, realizations("b")?), before);
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_affected() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    std::fs::write(
+        &config,
+        "task a > out=out.txt :: x=(X: x1 x2) { echo $x > $out }\n\
+         task b < in=$out@a > out=out.txt { cp $in $out }\n",
+    )?;
+    let output = dir.path().join("output");
+    let args = |task: &str| {
+        let mut args = basic_args(output.to_str().unwrap().to_owned());
+        args.configs = vec![config.to_str().unwrap().to_owned()];
+        args.command = Some(Command::Affected {
+            task: task.to_owned(),
+        });
+        args
+    };
+    App::new(args("a").try_into()?).run()?;
+    // it doesn't create the output dir:
+    assert!(!output.exists());
+
+    let e = App::new(args("aa").try_into()?).run().unwrap_err();
+    assert!(e.to_string().contains("did you mean `a`?"), "{e}");
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_explain() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    std::fs::write(
+        &config,
+        "plan main { reach b via (X: *) }\n\
+         task a > out=out.txt :: x=(X: x1 x2) { echo $x > $out }\n\
+         task b < in=$out@a > out=out.txt { cp $in $out }\n",
+    )?;
+    let output = dir.path().join("output");
+    let args = |task: &str| {
+        let mut args = basic_args(output.to_str().unwrap().to_owned());
+        args.configs = vec![config.to_str().unwrap().to_owned()];
+        args.plans = vec!["main".to_owned()];
+        args.command = Some(Command::Explain {
+            task: task.to_owned(),
+        });
+        args
+    };
+    App::new(args("a").try_into()?).run()?;
+    // it doesn't create the output dir:
+    assert!(!output.exists());
+
+    let e = App::new(args("aa").try_into()?).run().unwrap_err();
+    assert!(e.to_string().contains("did you mean `a`?"), "{e}");
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_which_and_outputs() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    std::fs::write(
+        &config,
+        "task a > out=out.txt :: x=(X: x1 x2) { echo $x > $out }\n\
+         task b < in=$out@a > out=out.txt { cp $in $out }\n",
+    )?;
+    let output = dir.path().join("output");
+    let args = |command, branch: Option<&str>| {
+        let mut args = basic_args(output.to_str().unwrap().to_owned());
+        args.configs = vec![config.to_str().unwrap().to_owned()];
+        args.branch = branch.into_iter().map(str::to_owned).collect();
+        args.command = Some(command);
+        args
+    };
+    let which = |task: &str| Command::Which {
+        task: task.to_owned(),
+    };
+    let outputs = |task: &str, name: Option<&str>| Command::Outputs {
+        task: task.to_owned(),
+        name: name.map(str::to_owned),
+    };
+    App::new(args(which("b"), Some("X.x2")).try_into()?).run()?;
+    App::new(args(outputs("a", None), None).try_into()?).run()?;
+    App::new(args(outputs("a", Some("out")), None).try_into()?).run()?;
+    // they don't create the output dir:
+    assert!(!output.exists());
+
+    let e = App::new(args(outputs("a", Some("ot")), None).try_into()?).run().unwrap_err();
+    assert_eq!(
+        e.to_string(),
+        "Task 'a' has no output 'ot' (did you mean `out`?)"
+    );
+    let e = App::new(args(which("*"), None).try_into()?).run().unwrap_err();
+    assert_eq!(
+        e.to_string(),
+        "'hr which' needs a single task, but '*' matches a, b"
+    );
+    let e = Settings::try_from(args(which("a"), Some("X.x1,X.x2"))).unwrap_err();
+    assert!(e.to_string().contains("needs a single branch"), "{e}");
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_cat_script() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    std::fs::write(
+        &config,
+        "task a > out=out.txt :: x=(X: x1 x2) { echo $x > $out }\n",
+    )?;
+    let output = dir.path().join("output");
+    let args = |branch: &[&str]| {
+        let mut args = basic_args(output.to_str().unwrap().to_owned());
+        args.configs = vec![config.to_str().unwrap().to_owned()];
+        args.branch = branch.iter().map(|b| (*b).to_owned()).collect();
+        args.command = Some(Command::CatScript {
+            task: "a".to_owned(),
+        });
+        args
+    };
+    App::new(args(&["X.x2"]).try_into()?).run()?;
+    // it doesn't create the output dir:
+    assert!(!output.exists());
+
+    let e = Settings::try_from(args(&["X.x1", "X.x2"])).unwrap_err();
+    assert_eq!(
+        e.to_string(),
+        "'hr cat-script' works on a single realization, so it needs a single branch"
+    );
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_logs() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    std::fs::write(&config, "task a > out=out.txt { echo hi; touch $out }\n")?;
+    let output = dir.path().join("output");
+    let args = |command| {
+        let mut args = basic_args(output.to_str().unwrap().to_owned());
+        args.configs = vec![config.to_str().unwrap().to_owned()];
+        args.command = command;
+        args
+    };
+    let logs = |stderr| {
+        Some(Command::Logs {
+            task: "a".to_owned(),
+            stderr,
+            follow: true,
+        })
+    };
+    let e = App::new(args(logs(false)).try_into()?).run().unwrap_err();
+    assert!(
+        e.to_string().ends_with("does not exist; has the task run?"),
+        "{e}"
+    );
+    assert!(!output.exists());
+
+    let mut run = args(None);
+    run.tasks = vec!["a".to_owned()];
+    App::new(run.try_into()?).run()?;
+    // the task has finished, so following its logs returns right away:
+    App::new(args(logs(false)).try_into()?).run()?;
+    App::new(args(logs(true)).try_into()?).run()?;
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_serve() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    std::fs::write(
+        &config,
+        "plan main { reach b via (X: *) }\n\
+         global {\n  x=(X: x1 x2)\n}\n\
+         task a > out=out.txt :: x=$x { echo $x > $out }\n\
+         task b < in=$out@a > out=out.txt :: x=$x { echo checking $x; test $x = x1 && cp $in $out }\n",
+    )?;
+    let output = dir.path().join("output");
+    let args = |command| {
+        let mut args = basic_args(output.to_str().unwrap().to_owned());
+        args.configs = vec![config.to_str().unwrap().to_owned()];
+        args.command = command;
+        args
+    };
+    let mut app = App::new(
+        args(Some(Command::Serve {
+            addr: "127.0.0.1:0".to_owned(),
+        }))
+        .try_into()?,
+    );
+    let mut get = |path: &[&str]| -> Result<(u16, String)> {
+        let path: Vec<String> = path.iter().map(|s| (*s).to_owned()).collect();
+        let response = app.respond(&path)?;
+        Ok((response.status, String::from_utf8(response.body)?))
+    };
+    let json = |body: &str| serde_json::from_str::<Value>(body).unwrap();
+    let count = |body: &str, state: &str| json(body)["counts"][state].as_u64();
+
+    // nothing has run yet, and serving doesn't create the output dir:
+    let (status, body) = get(&["status", "main"])?;
+    assert_eq!(status, 200);
+    assert_eq!(count(&body, "missing"), Some(4));
+    let (_, body) = get(&[])?;
+    assert!(json(&body)["running"].is_null());
+    assert!(!output.exists());
+
+    // b fails on X.x2:
+    let mut run_args = args(None);
+    run_args.plans = vec!["main".to_owned()];
+    assert!(App::new(run_args.try_into()?).run().is_err());
+
+    let (_, body) = get(&["status"])?;
+    let statuses = json(&body);
+    let statuses = statuses.as_array().unwrap();
+    assert_eq!(statuses.len(), 1);
+    assert_eq!(statuses[0]["target"], "plan main");
+    assert_eq!(count(&get(&["status", "main"])?.1, "failed"), Some(1));
+
+    assert_eq!(
+        get(&["logs", "b", "X.x2", "stdout"])?,
+        (200, "checking x2\n".to_owned())
+    );
+    // b's realizations run in branch order, so X.x1 completed before X.x2 failed:
+    assert_eq!(get(&["logs", "a", "latest", "stderr"])?.0, 200);
+    assert_eq!(get(&["logs", "b", "latest", "stderr"])?.0, 200);
+    assert_eq!(get(&["logs", "b", "..", "stdout"])?.0, 400);
+    assert_eq!(get(&["logs", "c", "X.x1", "stdout"])?.0, 404);
+    assert_eq!(get(&["logs", "b", "X.x1", "exit_code"])?.0, 404);
+
+    let (_, body) = get(&["runs", "latest"])?;
+    assert_eq!(json(&body)["succeeded"], 0);
+    assert_eq!(get(&["status", "other"])?.0, 404);
+    assert_eq!(get(&["nothing"])?.0, 404);
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_output_format_json() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    std::fs::write(
+        &config,
+        "plan main { reach b via (X: *) }\n\
+         global {\n  x=(X: x1 x2)\n}\n\
+         task a > out=out.txt :: x=$x { echo $x > $out }\n\
+         task b < in=$out@a > out=out.txt :: x=$x { test $x = x1 && cp $in $out }\n",
+    )?;
+    let output = dir.path().join("output");
+    let args = |dry_run| {
+        let mut args = basic_args(output.to_str().unwrap().to_owned());
+        args.configs = vec![config.to_str().unwrap().to_owned()];
+        args.plans = vec!["main".to_owned()];
+        args.run_args.output_format = Some(OutputFormat::Json);
+        args.dry_run = dry_run;
+        args
+    };
+    App::new(args(true).try_into()?).run()?;
+    assert!(!output.join("a").exists());
+
+    // the report is written when a task fails, but the error is still returned:
+    assert!(App::new(args(false).try_into()?).run().is_err());
+    // the failed task's scratch dir is left for inspection:
+    let scratch = std::fs::read_dir(output.join("b/realizations"))?
+        .flatten()
+        .map(|entry| entry.path())
+        .find(|path| path.to_string_lossy().contains("/.tmp-X.x2."))
+        .expect("failed task left its scratch dir");
+    let text = std::fs::read_to_string(scratch.join("task_info.json"))?;
+    let info = TaskInfo::from_json(&text)?;
+    assert_eq!(info.succeeded, Some(false));
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_junit() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    std::fs::write(
+        &config,
+        "plan main { reach b via (X: x2) }\n\
+         task a > out=out.txt { echo a > $out }\n\
+         task b < in=$out@a > out=out.txt :: x=(X: x1 x2) { echo \"oops <$x>\" >&2; test $x = x1 }\n",
+    )?;
+    let output = dir.path().join("output");
+    let junit = dir.path().join("junit.xml");
+    let mut args = basic_args(output.to_str().unwrap().to_owned());
+    args.configs = vec![config.to_str().unwrap().to_owned()];
+    args.plans = vec!["main".to_owned()];
+    args.run_args.junit = Some(junit.to_str().unwrap().to_owned());
+    assert!(App::new(args.try_into()?).run().is_err());
+
+    let xml = std::fs::read_to_string(&junit)?;
+    assert!(xml.contains("<testsuites tests=\"2\" failures=\"1\" skipped=\"0\""));
+    assert!(xml.contains("<testcase name=\"a[Baseline.baseline]\" classname=\"a\""));
+    assert!(xml.contains("<failure message=\"Subprocess failed\">"));
+    assert!(xml.contains("oops &lt;x2&gt;"));
+
+    // a report that can't be written doesn't hide why the run failed:
+    let mut args = basic_args(output.to_str().unwrap().to_owned());
+    args.configs = vec![config.to_str().unwrap().to_owned()];
+    args.plans = vec!["main".to_owned()];
+    let unwritable = dir.path().join("missing/junit.xml");
+    args.run_args.junit = Some(unwritable.to_str().unwrap().to_owned());
+    let e = App::new(args.try_into()?).run().unwrap_err();
+    assert_eq!(e.to_string(), "while running workflow");
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_history() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    std::fs::write(
+        &config,
+        "plan main { reach a }\n\
+         task a > out=out.txt { echo a > $out }\n",
+    )?;
+    let output = dir.path().join("output");
+    let args = |command| {
+        let mut args = basic_args(output.to_str().unwrap().to_owned());
+        args.configs = vec![config.to_str().unwrap().to_owned()];
+        args.plans = vec!["main".to_owned()];
+        args.command = command;
+        args
+    };
+    let history = |what| Command::History { what, last: 10 };
+
+    // no history yet, and looking doesn't create the output dir:
+    App::new(args(Some(history(HistoryKind::Runs))).try_into()?).run()?;
+    assert!(!output.exists());
+
+    App::new(args(None).try_into()?).run()?;
+    assert!(output.join("history.db").exists());
+    for what in [
+        HistoryKind::Runs,
+        HistoryKind::Failures,
+        HistoryKind::Durations,
+    ] {
+        App::new(args(Some(history(what))).try_into()?).run()?;
+    }
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_metrics_file() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    std::fs::write(
+        &config,
+        "plan main { reach b }\n\
+         task a > out=out.txt { echo a > $out }\n\
+         task b < in=$out@a { test -s $in }\n",
+    )?;
+    let output = dir.path().join("output");
+    let metrics = dir.path().join("hr.prom");
+    let mut args = basic_args(output.to_str().unwrap().to_owned());
+    args.configs = vec![config.to_str().unwrap().to_owned()];
+    args.plans = vec!["main".to_owned()];
+    args.run_args.metrics_file = Some(metrics.to_str().unwrap().to_owned());
+    App::new(args.try_into()?).run()?;
+
+    let text = std::fs::read_to_string(&metrics)?;
+    for sample in [
+        "hr_run_in_progress{plan=\"main\"} 0\n",
+        "hr_run_success{plan=\"main\"} 1\n",
+        "hr_run_queue_depth{plan=\"main\"} 0\n",
+        "hr_run_tasks{plan=\"main\",state=\"completed\"} 2\n",
+        "hr_task_success{plan=\"main\",task=\"b\",realization=\"b[Baseline.baseline]\"} 1\n",
+        "hr_runs_total{result=\"succeeded\"} 1\n",
+    ] {
+        assert!(text.contains(sample), "{sample:?} not in {text}");
+    }
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_otlp_trace() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    std::fs::write(
+        &config,
+        "plan main { reach b }\n\
+         task a > out=out.txt { echo a > $out }\n\
+         task b < in=$out@a { exit 3 }\n",
+    )?;
+    let output = dir.path().join("output");
+    let collector = dir.path().join("collector");
+    std::fs::create_dir_all(collector.join("v1"))?;
+    let mut args = basic_args(output.to_str().unwrap().to_owned());
+    args.configs = vec![config.to_str().unwrap().to_owned()];
+    args.plans = vec!["main".to_owned()];
+    args.run_args.otlp_endpoint = Some(format!("file://{}", collector.to_str().unwrap()));
+    assert!(App::new(args.try_into()?).run().is_err());
+
+    let text = std::fs::read_to_string(collector.join("v1").join("traces"))?;
+    let json: Value = serde_json::from_str(&text)?;
+    let spans = json["resourceSpans"][0]["scopeSpans"][0]["spans"].as_array().unwrap();
+    let names: Vec<_> = spans.iter().map(|span| span["name"].as_str().unwrap()).collect();
+    assert_eq!(
+        names,
+        [
+            "hr run",
+            "parse",
+            "traverse",
+            "resolve",
+            "prepare",
+            "execute",
+            "a[Baseline.baseline]",
+            "b[Baseline.baseline]",
+        ]
+    );
+    assert_eq!(spans[0]["status"]["code"], 2);
+    assert_eq!(spans[6]["status"]["code"], 0);
+    assert_eq!(spans[7]["status"]["code"], 2);
+    assert_eq!(spans[6]["parentSpanId"], spans[5]["spanId"]);
+    assert_eq!(spans[5]["parentSpanId"], spans[0]["spanId"]);
+
+    let attr = |span: &Value, key: &str| {
+        let attrs = span["attributes"].as_array().unwrap();
+        attrs.iter().find(|attr| attr["key"] == key).map(|attr| attr["value"].clone())
+    };
+    assert_eq!(
+        attr(&spans[0], "hr.plans"),
+        Some(json!({"stringValue": "main"}))
+    );
+    assert_eq!(
+        attr(&spans[7], "hr.task"),
+        Some(json!({"stringValue": "b"}))
+    );
+    assert_eq!(
+        attr(&spans[7], "hr.branch"),
+        Some(json!({"stringValue": "Baseline.baseline"}))
+    );
+    assert_eq!(attr(&spans[7], "hr.module"), None);
+    assert_eq!(
+        attr(&spans[6], "process.exit.code"),
+        Some(json!({"intValue": "0"}))
+    );
+    assert_eq!(
+        attr(&spans[7], "process.exit.code"),
+        Some(json!({"intValue": "3"}))
+    );
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_list() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    std::fs::write(
+        &config,
+        "plan main { reach b via (X: *) }\n\
+         task a > out=out.txt :: x=(X: x1 x2) { echo $x > $out }\n\
+         task b < in=$out@a > out=out.txt { cp $in $out }\n",
+    )?;
+    let output = dir.path().join("output");
+    for what in [
+        ListKind::All,
+        ListKind::Tasks,
+        ListKind::Plans,
+        ListKind::Branchpoints,
+    ] {
+        let mut args = basic_args(output.to_str().unwrap().to_owned());
+        args.configs = vec![config.to_str().unwrap().to_owned()];
+        args.command = Some(Command::List { what });
+        App::new(args.try_into()?).run()?;
+    }
+    // listing doesn't create the output dir:
+    assert!(!output.exists());
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_stats() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    std::fs::write(
+        &config,
+        "plan main { reach b via (X: *) }\n\
+         task a > out=out.txt :: x=(X: x1 x2) { echo $x > $out }\n\
+         task b < in=$out@a > out=out.txt { cp $in $out }\n",
+    )?;
+    let output = dir.path().join("output");
+    let (config, output_str) = (config.to_str().unwrap(), output.to_str().unwrap());
+    let stats = |extra: &[&str]| -> Result<String> {
+        let args = ["-c", config, "-o", output_str, "-p", "main", "stats"];
+        hr_stdout(&[&args[..], extra].concat())
+    };
+
+    let plain = stats(&[])?;
+    assert!(
+        plain.starts_with("Traversal has 4 nodes (4 after deduplication), 2 roots, and 2 levels"),
+        "{plain}"
+    );
+    assert!(plain.contains("BFS took"), "{plain}");
+    assert!(!plain.contains("memory:"), "{plain}");
+
+    let memory = stats(&["--memory"])?;
+    assert!(memory.starts_with(&plain), "{memory}");
+    let lines: Vec<&str> = memory.lines().collect();
+    let section = |name: &str| lines.iter().position(|line| *line == name);
+    let (strings, traversal) = (section("strings:"), section("traversal:"));
+    assert!(
+        lines.iter().any(|line| line.starts_with("memory:")),
+        "{memory}"
+    );
+    assert!(strings.is_some() && strings < traversal, "{memory}");
+    for usage in [
+        "tasks",
+        "idents",
+        "run strings",
+        "branch strings",
+        "task strings",
+    ] {
+        let line = lines.iter().find(|line| line.trim_start().starts_with(usage));
+        assert!(line.is_some(), "no {usage} in {memory}");
+    }
+    assert!(lines.last().unwrap().starts_with("total:"), "{memory}");
+    // it doesn't create the output dir:
+    assert!(!output.exists());
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_run_order() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    // a1 is the baseline, but the plans list a2 first:
+    std::fs::write(
+        &config,
+        "plan one { reach r2, r1 via (A: a2 a1) }\n\
+         plan two { reach r1, r2 via (A: *) }\n\
+         task r1 > out=out.txt :: a=(A: a1 a2) { echo $a > $out }\n\
+         task r2 < in=$out@r1 { cat $in }\n",
+    )?;
+    // the realizations printed as "RUN" in the list of actions, and then as they run:
+    let run_lines = |plan: &str| -> Result<Vec<String>> {
+        let output = dir.path().join(plan);
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_hr"))
+            .args([
+                "-c",
+                config.to_str().unwrap(),
+                "-o",
+                output.to_str().unwrap(),
+            ])
+            .args(["-p", plan, "-y"])
+            .env("NO_COLOR", "1")
+            .output()?;
+        assert!(output.status.success());
+        let stderr = String::from_utf8(output.stderr)?;
+        let lines = stderr.lines().filter(|line| line.starts_with("RUN "));
+        Ok(lines.map(str::to_owned).collect())
+    };
+    let order = [
+        "RUN r1[A.a1]",
+        "RUN r1[A.a2]",
+        "RUN r2[A.a1]",
+        "RUN r2[A.a2]",
+    ];
+    let expected = [order, order].concat();
+    assert_eq!(run_lines("one")?, expected);
+    assert_eq!(run_lines("two")?, expected);
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_viz() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    std::fs::write(
+        &config,
+        "plan main { reach b via (X: *) }\n\
+         task a > out=out.txt :: x=(X: x1 x2) { echo $x > $out }\n\
+         task b < in=$out@a first=$out@a[X: x1] > out=out.txt { cat $in $first > $out }\n",
+    )?;
+    let output = dir.path().join("output");
+    let (config, output_str) = (config.to_str().unwrap(), output.to_str().unwrap());
+    // the whole workflow, or with a plan, its traversal:
+    let viz = |format: &str, plan: &[&str]| -> Result<String> {
+        let args = ["-c", config, "-o", output_str, "viz", "--format", format];
+        hr_stdout(&[plan, &args[..]].concat())
+    };
+
+    assert_eq!(
+        viz("dot", &[])?,
+        "digraph workflow {\n\
+         \x20 node [shape=box];\n\
+         \x20 n0 [label=\"b\"];\n\
+         \x20 n1 [label=\"a\\nX\"];\n\
+         \x20 n1 -> n0 [label=\"in\"];\n\
+         \x20 n1 -> n0 [label=\"first\", style=dashed];\n\
+         }\n"
+    );
+    assert_eq!(
+        viz("dot", &["-p", "main"])?,
+        "digraph workflow {\n\
+         \x20 node [shape=box];\n\
+         \x20 n0 [label=\"a[X.x1]\"];\n\
+         \x20 n1 [label=\"a[X.x2]\"];\n\
+         \x20 n2 [label=\"b[X.x1]\"];\n\
+         \x20 n3 [label=\"b[X.x2]\"];\n\
+         \x20 n0 -> n2 [label=\"in\"];\n\
+         \x20 n0 -> n2 [label=\"first\"];\n\
+         \x20 n1 -> n3 [label=\"in\"];\n\
+         \x20 n0 -> n3 [label=\"first\"];\n\
+         }\n"
+    );
+    assert_eq!(
+        viz("mermaid", &[])?,
+        "flowchart TD\n\
+         \x20 n0[\"b\"]\n\
+         \x20 n1[\"a<br/>X\"]\n\
+         \x20 n1 -->|in| n0\n\
+         \x20 n1 -.->|first| n0\n"
+    );
+    assert_eq!(
+        viz("mermaid", &["-p", "main"])?,
+        "flowchart TD\n\
+         \x20 n0[\"a[X.x1]\"]\n\
+         \x20 n1[\"a[X.x2]\"]\n\
+         \x20 n2[\"b[X.x1]\"]\n\
+         \x20 n3[\"b[X.x2]\"]\n\
+         \x20 n0 -->|in| n2\n\
+         \x20 n0 -->|first| n2\n\
+         \x20 n1 -->|in| n3\n\
+         \x20 n0 -->|first| n3\n"
+    );
+
+    let json: Value = serde_json::from_str(&viz("json", &[])?)?;
+    assert_eq!(json["nodes"][1]["task"], "a");
+    assert_eq!(json["nodes"][1]["branchpoints"], json!(["X"]));
+    assert_eq!(
+        json["edges"],
+        json!([
+            {"from": 1, "to": 0, "var": "in", "grafted": false},
+            {"from": 1, "to": 0, "var": "first", "grafted": true},
+        ])
+    );
+
+    let text = viz("json", &["-p", "main"])?;
+    let json: Value = serde_json::from_str(&text)?;
+    let nodes = json["nodes"].as_array().unwrap();
+    assert_eq!(nodes.len(), 4);
+    assert_eq!(nodes[3]["branch"], json!({"X": "x2"}));
+    assert_eq!(nodes[1]["params"], json!({"x": "x2"}));
+    assert_eq!(json["edges"].as_array().unwrap().len(), 4);
+    // inputs are written in the order the task declares them:
+    #[derive(serde::Deserialize)]
+    struct Graph {
+        nodes: Vec<Node>,
+    }
+    #[derive(serde::Deserialize)]
+    struct Node {
+        #[serde(with = "util::ordered_map")]
+        inputs: Vec<(String, String)>,
+    }
+    let graph: Graph = serde_json::from_str(&text)?;
+    let pair = |k: &str, v: &str| (k.to_owned(), v.to_owned());
+    assert_eq!(
+        graph.nodes[3].inputs,
+        [pair("in", "$out@a[X.x2]"), pair("first", "$out@a[X.x1]")]
+    );
+    // neither creates the output dir:
+    assert!(!output.exists());
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_export() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    std::fs::write(
+        &config,
+        "plan main { reach b }\n\
+         task a > out=out.txt :: x=(X: x1 x2) { echo $x > $out }\n\
+         task b < in=$out@a[X:*] > out=out.txt { cat $in > $out }\n",
+    )?;
+    let output = dir.path().join("output");
+    let args = |command| {
+        let mut args = basic_args(output.to_str().unwrap().to_owned());
+        args.configs = vec![config.to_str().unwrap().to_owned()];
+        args.plans = vec!["main".to_owned()];
+        args.command = command;
+        args
+    };
+    // exporting the abstract workflow doesn't touch the output dir:
+    for format in [ExportFormat::Snakemake, ExportFormat::Cwl] {
+        App::new(args(Some(Command::Export { format })).try_into()?).run()?;
+    }
+    assert!(!output.exists());
+
+    App::new(
+        args(Some(Command::Export {
+            format: ExportFormat::Make,
+        }))
+        .try_into()?,
+    )
+    .run()?;
+
+    // realization dirs are prepared, but nothing runs:
+    let a = output.join("a/realizations");
+    let b = output.join("b/realizations/Baseline.baseline");
+    for realization in [a.join("Baseline.baseline"), a.join("X.x2"), b.clone()] {
+        assert!(realization.join("task.sh").exists());
+        assert!(!realization.join("exit_code").exists());
+    }
+    // so task.sh can be run as-is, list inputs are quoted:
+    let script = std::fs::read_to_string(b.join("task.sh"))?;
+    let inputs = format!(
+        "in='{} {}'\n",
+        a.join("Baseline.baseline/out.txt").display(),
+        a.join("X.x2/out.txt").display()
+    );
+    assert!(script.contains(&inputs), "{script}");
+
+    App::new(args(None).try_into()?).run()?;
+    assert_eq!(std::fs::read_to_string(b.join("out.txt"))?, "x1\nx2\n");
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_strict_vars() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    std::fs::write(
+        &config,
+        "task a > out=out.txt :: x=1 {\n\
+         \x20 n=$x; for f in $HOME; do n+=\"$f\"; done; echo $n ${unset:-default} > $out\n\
+         }\n",
+    )?;
+    let output = dir.path().join("output");
+    let args = |strict_vars| {
+        let mut args = basic_args(output.to_str().unwrap().to_owned());
+        args.configs = vec![config.to_str().unwrap().to_owned()];
+        args.tasks = vec!["a".to_owned()];
+        args.strict_vars = strict_vars;
+        args
+    };
+    // vars defined in the code or the login environment are fine:
+    let mut check = args(true);
+    check.command = Some(Command::Check);
+    App::new(check.try_into()?).run()?;
+
+    let mut text = std::fs::read_to_string(&config)?;
+    text.push_str("task b > out=out.txt { echo $typo > $out }\n");
+    std::fs::write(&config, text)?;
+    // with strict vars, an undefined var is an error before anything runs:
+    let mut strict = args(true);
+    strict.tasks = vec!["b".to_owned()];
+    assert!(App::new(strict.try_into()?).run().is_err());
+    assert!(!output.join("b").exists());
+    // by default it's just a warning (and here the task fails when bash hits it):
+    let mut default = args(false);
+    default.tasks = vec!["b".to_owned()];
+    assert!(App::new(default.try_into()?).run().is_err());
+    assert!(output.join("b/realizations").exists());
+
+    // a var that's only set in the environment hr runs in isn't defined by the workflow:
+    std::env::set_var("HR_INTEG_STRICT_VAR", "set");
+    let mut text = std::fs::read_to_string(&config)?;
+    text.push_str("task c > out=out.txt { echo $HR_INTEG_STRICT_VAR > $out }\n");
+    std::fs::write(&config, text)?;
+    let mut strict = args(true);
+    strict.tasks = vec!["c".to_owned()];
+    assert!(App::new(strict.try_into()?).run().is_err());
+    assert!(!output.join("c").exists());
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_unused_warnings() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    std::fs::write(
+        &config,
+        "global {\n  unused=1\n}\n\
+         task a > out=out.txt :: p=1 { echo hi > $out }\n",
+    )?;
+    let output = dir.path().join("output");
+    let args = || {
+        let mut args = basic_args(output.to_str().unwrap().to_owned());
+        args.configs = vec![config.to_str().unwrap().to_owned()];
+        args.tasks = vec!["a".to_owned()];
+        args
+    };
+    // unused values are only warnings, for both check and run:
+    let mut check = args();
+    check.command = Some(Command::Check);
+    App::new(check.try_into()?).run()?;
+    let mut run = args();
+    run.run_args.warnings = true;
+    App::new(run.try_into()?).run()?;
+    assert!(output.join("a/realizations/Baseline.baseline/out.txt").exists());
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_circular_dependency() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    std::fs::write(
+        &config,
+        "plan main { reach c via (X: x1) }\n\
+         task a < in=$out@b > out { cp $in $out }\n\
+         task b < in=$out@a > out :: x=(X: x1 x2) { echo $x; cp $in $out }\n\
+         task c < in=$out@b > out { cp $in $out }\n",
+    )?;
+    let args = || {
+        let mut args = basic_args(stringify_dir(&dir) + "/output");
+        args.configs = vec![config.to_str().unwrap().to_owned()];
+        args.plans = vec!["main".to_owned()];
+        args
+    };
+    // the cycle is reported instead of growing the traversal forever:
+    assert!(App::new(args().try_into()?).run().is_err());
+    let mut check = args();
+    check.command = Some(Command::Check);
+    assert!(App::new(check.try_into()?).run().is_err());
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_did_you_mean() -> Result<()> {
+    let dir = tempdir()?;
+    let args = || {
+        let mut args = basic_args(stringify_dir(&dir));
+        args.configs = vec!["examples/basic.hr".to_owned()];
+        args
+    };
+    let mut task = args();
+    task.tasks = vec!["replace_txt".to_owned()];
+    let e = App::new(task.try_into()?).run().unwrap_err();
+    assert!(
+        format!("{e:#}").contains("did you mean `replace_text`?"),
+        "{e:#}"
+    );
+
+    let mut plan = args();
+    plan.plans = vec!["basik".to_owned()];
+    let e = App::new(plan.try_into()?).run().unwrap_err();
+    assert!(format!("{e:#}").contains("did you mean `basic`?"), "{e:#}");
+
+    let mut unrelated = args();
+    unrelated.plans = vec!["nothing_like_it".to_owned()];
+    let e = App::new(unrelated.try_into()?).run().unwrap_err();
+    assert!(!format!("{e:#}").contains("did you mean"), "{e:#}");
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_typed_params() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    std::fs::create_dir(dir.path().join("sdk"))?;
+    let write_config = |jobs: &str, mode: &str| {
+        std::fs::write(
+            &config,
+            format!(
+                "task a > out=out.txt :: jobs:int={jobs} fast:bool=true sdk:path=sdk \
+                 mode:debug|release={mode} {{\n\
+                 \x20 echo $jobs $fast $sdk $mode > $out\n\
+                 }}\n"
+            ),
+        )
+    };
+    let output = dir.path().join("output");
+    let args = |command| {
+        let mut args = basic_args(output.to_str().unwrap().to_owned());
+        args.configs = vec![config.to_str().unwrap().to_owned()];
+        args.tasks = vec!["a".to_owned()];
+        args.command = command;
+        args
+    };
+
+    write_config("4", "release")?;
+    App::new(args(None).try_into()?).run()?;
+    let out = output.join("a/realizations/Baseline.baseline/out.txt");
+    assert_eq!(std::fs::read_to_string(&out)?, "4 true sdk release\n");
+    std::fs::remove_dir_all(&output)?;
+
+    // bad values are reported before anything runs:
+    write_config("four", "fastest")?;
+    assert!(App::new(args(Some(Command::Check)).try_into()?).run().is_err());
+    assert!(App::new(args(None).try_into()?).run().is_err());
+    assert!(!output.join("a/realizations").exists());
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_run_log() -> Result<()> {
+    let output = run_basic()?;
+
+    let logs: Vec<PathBuf> = std::fs::read_dir(output.path().join("logs"))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<_, _>>()?;
+    assert_eq!(logs.len(), 1);
+    let name = logs[0].file_name().unwrap().to_str().unwrap();
+    assert!(name.starts_with("run-") && name.ends_with(".log"), "{name}");
+    assert!(std::fs::read_to_string(&logs[0])?.starts_with("# "));
+
+    output.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_non_interactive() -> Result<()> {
+    let output = tempdir()?;
+    let mut args = basic_args(stringify_dir(&output));
+    args.plans = vec!["debug".to_owned()];
+    args.yes = false;
+    args.non_interactive = true;
+    let e = App::new(args.try_into()?).run().unwrap_err();
+    assert_eq!(
+        e.to_string(),
+        "Would ask \"Proceed?\", but --non-interactive was given; answer in advance with -y or --yes-run"
+    );
+
+    let mut args = basic_args(stringify_dir(&output));
+    args.plans = vec!["debug".to_owned()];
+    args.non_interactive = true;
+    App::new(args.try_into()?).run()?;
+
+    // now that everything has run, there's nothing to do:
+    let mut args = basic_args(stringify_dir(&output));
+    args.plans = vec!["debug".to_owned()];
+    args.non_interactive = true;
+    let e = App::new(args.try_into()?).run().unwrap_err();
+    assert_eq!(e.to_string(), "No tasks to run");
+
+    output.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_watch_settings() -> Result<()> {
+    let output = tempdir()?;
+    let mut args = basic_args(stringify_dir(&output));
+    args.plans = vec!["debug".to_owned()];
+    args.command = Some(Command::Watch(RunArgs {
+        in_place: true,
+        ..RunArgs::default()
+    }));
+    let settings: Settings = args.try_into()?;
+    assert!(matches!(settings.mode, Mode::Watch));
+    assert!(!settings.atomic);
+
+    let mut args = basic_args(stringify_dir(&output));
+    args.invalidate = true;
+    args.command = Some(Command::Watch(RunArgs::default()));
+    let e = Settings::try_from(args).unwrap_err();
+    assert!(
+        e.to_string().contains("can't be given with 'hr watch'"),
+        "{e}"
+    );
+
+    output.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_fmt() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    std::fs::write(
+        &config,
+        "# greet\ntask hello  >out   :: who=(Who:\n  world=World\n  you=\"you there\") {\n  echo $who > $out\n}\n",
+    )?;
+    let fmt = |check| -> Result<()> {
+        let mut args = basic_args(stringify_dir(&dir));
+        args.configs = vec![config.to_str().unwrap().to_owned()];
+        args.command = Some(Command::Fmt { check });
+        App::new(args.try_into()?).run()
+    };
+
+    let e = fmt(true).unwrap_err();
+    assert!(
+        e.to_string().contains("1 config file(s) aren't formatted"),
+        "{e}"
+    );
+    fmt(false)?;
+    assert_eq!(
+        std::fs::read_to_string(&config)?,
+        "# greet\ntask hello\n    > out\n    :: who=(Who:\n        world = World\n        \
+         you   = \"you there\"\n    )\n{\n  echo $who > $out\n}\n"
+    );
+    fmt(true)?;
+
+    dir.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_diff() -> Result<()> {
+    let output = run_basic()?;
+    let dir = tempdir()?;
+    let new = dir.path().join("new.tconf");
+    let old = std::fs::read_to_string("examples/stub.tconf")?;
+    std::fs::write(&new, old.replace("headers/charmer.h", "headers/charmer2.h"))?;
+
+    let mut args = basic_args(stringify_dir(&output));
+    args.command = Some(Command::Diff {
+        old: "examples/stub.tconf".to_owned(),
+        new: new.to_str().unwrap().to_owned(),
+    });
+    let settings: Settings = args.try_into()?;
+    assert!(matches!(settings.mode, Mode::Diff));
+    assert_eq!(settings.configs.len(), 2);
+    assert!(settings.configs[1].ends_with("new.tconf"));
+    App::new(settings).run()?;
+
+    dir.close()?;
+    output.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_traversal_cache() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("rebuild.hr");
+    let write_config = |greeting: &str| {
+        std::fs::write(
+            &config,
+            format!(
+                "plan main {{ reach b via (X: *) }}\n\
+                 task a > out=out.txt :: x=(X: x1 x2) {{ echo $x > $out }}\n\
+                 task b < in=$out@a > out=out.txt :: greeting={greeting} {{\n\
+                   echo $greeting $(cat $in) > $out\n\
+                 }}\n"
+            ),
+        )
+    };
+    write_config("hi")?;
+    let output = dir.path().join("output");
+    let run = |no_cache: bool| -> Result<()> {
+        let mut args = basic_args(output.to_str().unwrap().to_owned());
+        args.configs = vec![config.to_str().unwrap().to_owned()];
+        args.plans = vec!["main".to_owned()];
+        args.run_args.no_traversal_cache = no_cache;
+        App::new(args.try_into()?).run()
+    };
+    let cache = output.join("traversal.cache");
+    let modified = || std::fs::metadata(&cache).and_then(|m| m.modified());
+    let b_out = |x: &str| output.join(format!("b/realizations/X.{x}/out.txt"));
+
+    // the first run creates branchpoints.txt, so the second is keyed differently:
+    run(false)?;
+    run(false)?;
+    let cached = modified()?;
+    // nothing changed, so the third reuses the cache without rewriting it:
+    run(false)?;
+    assert_eq!(modified()?, cached);
+    run(true)?;
+    assert_eq!(modified()?, cached);
+
+    // changing the config invalidates it:
+    std::thread::sleep(std::time::Duration::from_millis(10));
+    write_config("hello")?;
+    let mut invalidate = basic_args(output.to_str().unwrap().to_owned());
+    invalidate.configs = vec![config.to_str().unwrap().to_owned()];
+    invalidate.tasks = vec!["b".to_owned()];
+    invalidate.invalidate = true;
+    App::new(invalidate.try_into()?).run()?;
+    run(false)?;
+    assert_ne!(modified()?, cached);
+    assert_eq!(std::fs::read_to_string(b_out("x2"))?, "hello x2\n");
+
+    dir.close()?;
+    Ok(())
+}
@@ -2,8 +2,11 @@ use anyhow::Result;
 use std::collections::VecDeque;
 
 use intern::GetStr;
-use util::{Bitmask, IdVec};
-use workflow::{AbstractValueId, Errors, IdentId, RealTaskKey, RealValueId, Recapper, Workflow};
+use util::{Bitmask, HashMap, IdVec};
+use workflow::{
+    AbstractValueId, BranchSpec, BranchpointId, Errors, IdentId, RealTaskKey, RealValueId,
+    Recapper, Workflow,
+};
 
 use super::value::{PartialRealInput, RealInput, ValueResolver};
 use super::{Error, NodeBuilder, NodeIdx, TraversalBuilder};
@@ -11,31 +14,51 @@ use super::{Error, NodeBuilder, NodeIdx, TraversalBuilder};
 const QUEUE_CAPACITY: usize = 32;
 const ROOTS_CAPACITY: usize = 8;
 
-struct QueueNode {
+struct QueueNode<I> {
     key: RealTaskKey,
-    next_idx: NodeIdx,
+    next_idx: I,
+    /// branchpoints grafted by the dependent that enqueued this node:
+    grafted: Vec<BranchpointId>,
 }
 
 /// Breadth-first search traversal strategy
-pub struct BfsTraverser<'a, B> {
+pub struct BfsTraverser<'a, B, I> {
     /// workflow info
     wf: &'a Workflow,
     /// used internally to create bfs traversal
-    queue: VecDeque<QueueNode>,
+    queue: VecDeque<QueueNode<I>>,
+    /// index of the node for each task realization we've enqueued, so that a realization
+    /// reached more than once (e.g. by each branch of a big cross product) is only visited once:
+    visited: HashMap<RealTaskKey, I>,
+    /// number of nodes enqueued so far; they're added to the traversal in the same order.
+    num_enqueued: usize,
+    /// branchpoints each task's realizations could depend on (see [`Workflow::upstream_branchpoints`]):
+    upstream: Vec<B>,
     /// traversal we will build iteratively w/ calls to traverse()
-    traversal: TraversalBuilder<B>,
+    traversal: TraversalBuilder<B, I>,
     /// turns abstract values into real values:
     resolver: ValueResolver,
 }
 
-impl<'a, B: Bitmask> BfsTraverser<'a, B> {
+impl<'a, B: Bitmask, I: NodeIdx> BfsTraverser<'a, B, I> {
     /// Create a new BfsTraverser with the given workflow info
-    pub fn new(wf: &'a Workflow) -> Self {
+    pub fn new(wf: &'a Workflow) -> Result<Self> {
         let len_x2 = wf.strings.tasks.len() * 2;
         let len_x8 = len_x2 * 4;
-        Self {
+        let mut upstream = Vec::with_capacity(wf.strings.tasks.len());
+        for ks in wf.upstream_branchpoints()?.iter() {
+            let mut mask = B::default();
+            for k in ks {
+                mask.set(usize::from(*k));
+            }
+            upstream.push(mask);
+        }
+        Ok(Self {
             wf,
             queue: VecDeque::with_capacity(QUEUE_CAPACITY),
+            visited: HashMap::with_capacity_and_hasher(len_x2, Default::default()),
+            num_enqueued: 0,
+            upstream,
             traversal: TraversalBuilder {
                 nodes: Vec::with_capacity(len_x2),
                 inputs: IdVec::with_capacity(len_x2),
@@ -44,17 +67,19 @@ impl<'a, B: Bitmask> BfsTraverser<'a, B> {
                 errors: Errors::default(),
             },
             resolver: ValueResolver,
-        }
+        })
     }
 
     /// Consume this struct and return its completed Traversal.
-    pub fn into_traversal(self) -> TraversalBuilder<B> {
+    pub fn into_traversal(self) -> TraversalBuilder<B, I> {
         self.traversal
     }
 
     /// Add a traversal to the given goal node to our internal Traversal.
-    pub fn traverse(&mut self, key: RealTaskKey) -> Result<()> {
-        self.enqueue(key, downcast(self.traversal.nodes.len())?)?;
+    pub fn traverse(&mut self, mut key: RealTaskKey) -> Result<()> {
+        self.restrict(&mut key);
+        // NB a goal is terminal, so it's its own next node:
+        self.enqueue(key, downcast(self.num_enqueued)?, Vec::new())?;
         while let Some(node) = self.queue.pop_front() {
             self.handle(node)?;
         }
@@ -62,7 +87,7 @@ impl<'a, B: Bitmask> BfsTraverser<'a, B> {
     }
 
     /// Handle a single node popped off the queue.
-    fn handle(&mut self, node: QueueNode) -> Result<()> {
+    fn handle(&mut self, node: QueueNode<I>) -> Result<()> {
         let task_id = node.key.id;
         log::debug!(
             "Handling enqueued node {}",
@@ -72,7 +97,7 @@ impl<'a, B: Bitmask> BfsTraverser<'a, B> {
         // fetch task info and create new node
         let this_node_id = downcast(self.traversal.nodes.len())?;
         let task = self.wf.get_task(task_id)?;
-        let mut node = NodeBuilder::new(node.key, node.next_idx, task);
+        let mut node = NodeBuilder::new(node.key, node.next_idx, node.grafted, task);
 
         // handle inputs
         for (k, input) in &task.vars.inputs {
@@ -112,52 +137,133 @@ impl<'a, B: Bitmask> BfsTraverser<'a, B> {
         Ok(())
     }
 
-    fn enqueue(&mut self, key: RealTaskKey, next_idx: NodeIdx) -> Result<()> {
+    /// Leave only the branchpoints that could make a difference to the task realization `key`
+    /// specified in its branch, so that realizations that only differ in others
+    /// (e.g. the branches of a cross product that the task doesn't use) are the same.
+    fn restrict(&self, key: &mut RealTaskKey) {
+        let Some(upstream) = self.upstream.get(usize::from(key.id)) else {
+            return;
+        };
+        let mut branch = BranchSpec::default();
+        for (k, v) in key.branch.specified() {
+            if upstream.get(usize::from(k)) {
+                branch.insert(k, v);
+            }
+        }
+        key.branch = branch;
+    }
+
+    /// Enqueue the task realization `key`, unless it's already been enqueued,
+    /// returning the index of its node in the traversal.
+    fn enqueue(&mut self, key: RealTaskKey, next_idx: I, grafted: Vec<BranchpointId>) -> Result<I> {
+        if let Some(idx) = self.visited.get(&key) {
+            log::debug!(
+                "Already visited {}",
+                self.wf.strings.get_real_task_str(&key)?
+            );
+            return Ok(*idx);
+        }
         log::debug!("Enqueueing {}", self.wf.strings.get_real_task_str(&key)?);
-        self.queue.push_back(QueueNode { key, next_idx });
-        Ok(())
+        let idx = downcast(self.num_enqueued)?;
+        self.num_enqueued += 1;
+        self.visited.insert(key.clone(), idx);
+        self.queue.push_back(QueueNode {
+            key,
+            next_idx,
+            grafted,
+        });
+        Ok(idx)
     }
 
     fn handle_input(
         &mut self,
         val: AbstractValueId,
-        node: &mut NodeBuilder<B>,
-        this_node_id: NodeIdx,
+        node: &mut NodeBuilder<B, I>,
+        this_node_id: I,
     ) -> Result<RealValueId> {
         let val = self.wf.get_value(val)?;
         let (val, masks) = self.resolver.resolve::<_, B>(val, &node.key.branch, self.wf)?;
+        let real_val = self.realize_input(val, node, this_node_id, &masks.rm)?;
+        let val_id = self.traversal.inputs.push(real_val);
+        node.input_rms.push(masks.rm.clone());
+        node.masks.or_eq(&masks);
+        Ok(val_id)
+    }
 
-        let real_val = match val {
+    /// Enqueue the task(s) an input comes from, and convert it to use real task ids.
+    fn realize_input(
+        &mut self,
+        val: PartialRealInput,
+        node: &mut NodeBuilder<B, I>,
+        this_node_id: I,
+        grafts: &B,
+    ) -> Result<RealInput> {
+        match val {
             PartialRealInput::Task(task, ident, branch) => {
                 node.is_root = false;
 
                 if task == node.key.id {
                     return Err(Recapper::new(Error::ReflexiveTask(task)).into());
                 }
+                self.wf.get_task(task)?;
 
-                let key = RealTaskKey { id: task, branch };
-                self.enqueue(key, this_node_id)?;
-
-                let real_task_id = downcast(this_node_id as usize + self.queue.len())?.into();
+                let mut key = RealTaskKey { id: task, branch };
+                self.restrict(&mut key);
+                let grafted = grafted_branchpoints(grafts, &key.branch);
+                if let Some(cycle) = self.find_cycle(&key, node, this_node_id) {
+                    return Err(Recapper::new(Error::CircularDependency(cycle)).into());
+                }
+                let real_task_id = self.enqueue(key, this_node_id, grafted)?.index().into();
 
                 // NB we don't check if the task actually has an output with that ident here,
                 // b/c we haven't necessarily processed that task yet.
                 // We will check during workflow prep.
 
-                RealInput::Task(real_task_id, ident)
+                Ok(RealInput::Task(real_task_id, ident))
             }
-            PartialRealInput::Literal(lit_id) => RealInput::Literal(lit_id),
-        };
+            PartialRealInput::Literal(lit_id) => Ok(RealInput::Literal(lit_id)),
+            PartialRealInput::List(items) => {
+                let mut real_items = Vec::with_capacity(items.len());
+                for item in items {
+                    real_items.push(self.realize_input(item, node, this_node_id, grafts)?);
+                }
+                Ok(RealInput::List(real_items))
+            }
+        }
+    }
 
-        let val_id = self.traversal.inputs.push(real_val);
-        node.masks.or_eq(&masks);
-        Ok(val_id)
+    /// If `node` depending on `dep` would close a cycle, i.e. `dep` is already waiting
+    /// (directly or indirectly) on `node`, return the cycle, starting and ending with `dep`.
+    /// Each task in the cycle depends on the one after it.
+    fn find_cycle(
+        &self,
+        dep: &RealTaskKey,
+        node: &NodeBuilder<B, I>,
+        this_node_id: I,
+    ) -> Option<Vec<RealTaskKey>> {
+        // follow the chain of dependents from `node` back to the goal:
+        let mut chain = vec![&node.key];
+        let mut idx = node.next_idx;
+        while idx != this_node_id {
+            let dependent = &self.traversal.nodes[idx.index()];
+            chain.push(&dependent.key);
+            if dependent.key == *dep {
+                let mut cycle: Vec<RealTaskKey> = chain.into_iter().rev().cloned().collect();
+                cycle.push(dep.clone());
+                return Some(cycle);
+            }
+            if dependent.next_idx == idx {
+                break;
+            }
+            idx = dependent.next_idx;
+        }
+        None
     }
 
     fn handle_output_or_param(
         &mut self,
         val: AbstractValueId,
-        node: &mut NodeBuilder<B>,
+        node: &mut NodeBuilder<B, I>,
     ) -> Result<RealValueId> {
         let val = self.wf.get_value(val)?;
         let (val, masks) = self.resolver.resolve::<_, B>(val, &node.key.branch, self.wf)?;
@@ -198,7 +304,16 @@ impl<'a, B: Bitmask> BfsTraverser<'a, B> {
     }
 }
 
+/// Branchpoints that are specified in `dep`, the branch an input's task was resolved to,
+/// and that the input's value grafted (i.e. removed from the using task's branch).
+fn grafted_branchpoints<B: Bitmask>(grafts: &B, dep: &BranchSpec) -> Vec<BranchpointId> {
+    (0..dep.len())
+        .filter(|k| grafts.get(*k) && dep.is_specified(BranchpointId::from(*k)))
+        .map(BranchpointId::from)
+        .collect()
+}
+
 /// (try to) downcast a usize into our NodeIdx int type.
-fn downcast(val: usize) -> Result<NodeIdx, Error> {
+fn downcast<I: NodeIdx>(val: usize) -> Result<I, Error> {
     val.try_into().map_err(|_| Error::OutOfIndices(val))
 }
@@ -1,72 +1,229 @@
 use anyhow::Result;
 
 use intern::GetStr;
-use util::Bitmask;
-use workflow::{BranchSpec, Workflow};
+use util::{Bitmask, HashSet, IdVec};
+use workflow::{BranchSpec, RealTaskId, RealTaskKey, RealValueId, Recapper, Workflow};
 
-use super::{value::RealInput, Node, Traversal, TraversalBuilder};
+use super::{value::RealInput, Error, Node, NodeIdx, Traversal, TraversalBuilder, TraversalStats};
 
-/// Reverse the traversal, and convert to `Traversal` type,
-/// stripping unnecessary info from the TraversalBuilder.
-pub fn reverse_and_strip<B>(mut traversal: TraversalBuilder<B>) -> Traversal {
-    let nodes: Vec<_> = traversal.nodes.into_iter().map(Node::from).rev().collect();
-
-    let final_idx = nodes.len() - 1;
-    for val in traversal.inputs.iter_mut() {
-        if let RealInput::Task(t, _) = val {
-            let reversed_task_id = final_idx - usize::from(*t);
-            *t = reversed_task_id.into();
-        }
-    }
+/// Convert to `Traversal` type, stripping unnecessary info from the TraversalBuilder,
+/// and sorting the nodes into run order by dependency level (see `dependency_levels`).
+pub fn strip_and_sort<B, I>(
+    mut traversal: TraversalBuilder<B, I>,
+    levels: Vec<Vec<RealTaskId>>,
+    wf: &Workflow,
+) -> Result<Traversal> {
+    let nodes: Vec<_> = traversal.nodes.into_iter().map(Node::from).collect();
+    let (nodes, levels) = sort_levels(nodes, levels, &mut traversal.inputs, wf)?;
 
-    Traversal {
+    Ok(Traversal {
         nodes,
         inputs: traversal.inputs,
         outputs_params: traversal.outputs_params,
+        levels,
+        stats: TraversalStats::default(),
+    })
+}
+
+/// Group the nodes into levels, so that all of a node's antecedents are in earlier levels
+/// than it is. Since the bfs only visits each task realization once, a node can be used by
+/// nodes both before and after it, so this also checks that the nodes don't depend on each
+/// other in a cycle.
+pub fn dependency_levels<B, I>(traversal: &TraversalBuilder<B, I>) -> Result<Vec<Vec<RealTaskId>>> {
+    let nodes = &traversal.nodes;
+    let antecedents: Vec<Vec<RealTaskId>> =
+        nodes.iter().map(|node| node.antecedents(&traversal.inputs)).collect();
+    let mut dependents: Vec<Vec<usize>> = vec![Vec::new(); nodes.len()];
+    for (i, deps) in antecedents.iter().enumerate() {
+        for dep in deps {
+            dependents[usize::from(*dep)].push(i);
+        }
+    }
+
+    // number of each node's antecedents that aren't in a level yet:
+    let mut waiting: Vec<usize> = antecedents.iter().map(Vec::len).collect();
+    let mut level: Vec<RealTaskId> =
+        (0..nodes.len()).filter(|i| waiting[*i] == 0).map(RealTaskId::from).collect();
+    let mut levels = Vec::new();
+    let mut num_leveled = 0;
+    while !level.is_empty() {
+        let mut next = Vec::new();
+        for id in &level {
+            for dependent in &dependents[usize::from(*id)] {
+                waiting[*dependent] -= 1;
+                if waiting[*dependent] == 0 {
+                    next.push(RealTaskId::from(*dependent));
+                }
+            }
+        }
+        num_leveled += level.len();
+        levels.push(std::mem::replace(&mut level, next));
+    }
+
+    if num_leveled < nodes.len() {
+        let cycle = find_cycle(&antecedents, &waiting)
+            .into_iter()
+            .map(|i| nodes[i].key.clone())
+            .collect();
+        return Err(Recapper::new(Error::CircularDependency(cycle)).into());
     }
+    Ok(levels)
 }
 
-pub fn clean_branches_reversed<B: Bitmask>(
-    traversal: &mut TraversalBuilder<B>,
+/// Find a cycle among the nodes that are still `waiting` on antecedents,
+/// starting and ending with the same node. Each node in it depends on the one after it.
+fn find_cycle(antecedents: &[Vec<RealTaskId>], waiting: &[usize]) -> Vec<usize> {
+    // every waiting node has a waiting antecedent, so following them has to repeat a node:
+    let mut path: Vec<usize> = Vec::new();
+    let mut idx = waiting.iter().position(|w| *w > 0).expect("some node is waiting");
+    while !path.contains(&idx) {
+        path.push(idx);
+        idx = antecedents[idx]
+            .iter()
+            .map(|dep| usize::from(*dep))
+            .find(|dep| waiting[*dep] > 0)
+            .expect("a waiting node has a waiting antecedent");
+    }
+    let start = path.iter().position(|i| *i == idx).expect("path contains idx");
+    let mut cycle = path.split_off(start);
+    cycle.push(idx);
+    cycle
+}
+
+/// Reorder the nodes level by level, sorting each level by task name and then
+/// full branch string (the realization as it's printed, e.g. 'task[A.a1]'), so that the same
+/// workflow and targets always give the same run order, however its plans, goals,
+/// and inputs are listed. (Not the compact branch string, where e.g. 'Baseline.baseline'
+/// would come after 'A.a2', though 'A.a1' is the baseline.)
+/// Returns the reordered nodes and their levels.
+fn sort_levels(
+    nodes: Vec<Node>,
+    mut levels: Vec<Vec<RealTaskId>>,
+    inputs: &mut IdVec<RealValueId, RealInput>,
+    wf: &Workflow,
+) -> Result<(Vec<Node>, Vec<Vec<RealTaskId>>)> {
+    let mut keys = Vec::with_capacity(nodes.len());
+    for node in &nodes {
+        let branch = wf.strings.get_full_branch_str(&node.key.branch)?.to_owned();
+        keys.push((wf.strings.tasks.get(node.key.id)?, branch));
+    }
+
+    // new index of each node:
+    let mut new_idxs = vec![0; nodes.len()];
+    let mut order = Vec::with_capacity(nodes.len());
+    for level in &mut levels {
+        // NB stable sort, so duplicate realizations keep their relative order:
+        level.sort_by(|a, b| keys[usize::from(*a)].cmp(&keys[usize::from(*b)]));
+        for id in level.iter_mut() {
+            new_idxs[usize::from(*id)] = order.len();
+            order.push(usize::from(*id));
+            *id = RealTaskId::from(order.len() - 1);
+        }
+    }
+
+    for val in inputs.iter_mut() {
+        remap_task_ids(val, &|t| new_idxs[t]);
+    }
+
+    let mut nodes: Vec<Option<Node>> = nodes.into_iter().map(Some).collect();
+    let nodes = order
+        .into_iter()
+        .map(|i| nodes[i].take().expect("each node is in exactly one level"))
+        .collect();
+    Ok((nodes, levels))
+}
+
+/// Replace the task id `t` of each task output in `val` with `f(t)`.
+fn remap_task_ids(val: &mut RealInput, f: &impl Fn(usize) -> usize) {
+    match val {
+        RealInput::Task(t, _) => *t = f(usize::from(*t)).into(),
+        RealInput::List(items) => {
+            for item in items {
+                remap_task_ids(item, f);
+            }
+        }
+        RealInput::Literal(_) => (),
+    }
+}
+
+/// Remove the branchpoints that each node doesn't need from its branch, i.e. the ones that
+/// none of its values or antecedents add, or that it grafts out of its inputs.
+/// `levels` are the node's dependency levels, so each node's antecedents are cleaned before it.
+pub fn clean_branches<B: Bitmask, I: NodeIdx>(
+    traversal: &mut TraversalBuilder<B, I>,
+    levels: &[Vec<RealTaskId>],
     wf: &Workflow,
 ) -> Result<()> {
     log::debug!(
         "Cleaning branches for traversal with {} roots",
         traversal.roots.len(),
     );
-    for root_idx in &traversal.roots {
-        let mut idx = *root_idx;
-        let mut node = &mut traversal.nodes[idx as usize];
+    // branchpoints used by each node, once it's cleaned:
+    let mut masks = vec![B::default(); traversal.nodes.len()];
+    for id in levels.iter().flatten() {
+        let idx = usize::from(*id);
+        let node = &traversal.nodes[idx];
+        // each input only removes the branchpoints it grafts from the tasks it uses itself,
+        // so that grafting a branchpoint out of one input leaves it in the others:
         let mut traversal_mask = B::default();
-        loop {
-            log::debug!(
-                "Cleaning branches for {}",
-                wf.strings.get_real_task_str(&node.key)?,
-            );
-
-            log::trace!("traversal mask: {:#b}", traversal_mask);
-            log::trace!("this node removes: {:#b}", node.masks.rm);
-            log::trace!("this node adds: {:#b}", node.masks.add);
-
-            // filter first, then add, b/c we can prune a branchpoint and then add it in the same node:
-            traversal_mask &= !node.masks.rm;
-            traversal_mask |= node.masks.add;
-
-            rm_filtered_branchpoints(&mut node.key.branch, &traversal_mask, wf)?;
-
-            log::debug!(
-                "After cleaning: {}",
-                wf.strings.get_full_branch_str(&node.key.branch)?,
-            );
-
-            // if node is terminal/is a goal node, this traversal is done:
-            if node.next_idx == idx {
-                break;
-            } else {
-                idx = node.next_idx;
-                node = &mut traversal.nodes[idx as usize];
+        for ((_, val_id), rm) in node.vars.inputs.iter().zip(&node.input_rms) {
+            let mut input_mask = B::default();
+            if let Some(input) = traversal.inputs.get(*val_id) {
+                input.for_each_task(&mut |dep| input_mask |= masks[usize::from(dep)].clone());
             }
+            input_mask &= !rm.clone();
+            traversal_mask |= input_mask;
+        }
+
+        let node = &mut traversal.nodes[idx];
+        log::debug!(
+            "Cleaning branches for {}",
+            wf.strings.get_real_task_str(&node.key)?,
+        );
+
+        log::trace!("traversal mask: {:#b}", traversal_mask);
+        log::trace!("this node adds: {:#b}", node.masks.add);
+
+        // inputs are filtered first, then we add, b/c we can prune a branchpoint
+        // and then add it in the same node:
+        traversal_mask |= node.masks.add.clone();
+
+        rm_filtered_branchpoints(&mut node.key.branch, &traversal_mask, wf)?;
+
+        log::debug!(
+            "After cleaning: {}",
+            wf.strings.get_full_branch_str(&node.key.branch)?,
+        );
+        masks[idx] = traversal_mask;
+    }
+    Ok(())
+}
+
+/// Add an error to `traversal` for each realization whose (cleaned) branch breaks one of
+/// the workflow's constraints, so they're all reported together.
+pub fn check_constraints<B, I>(
+    traversal: &mut TraversalBuilder<B, I>,
+    wf: &Workflow,
+) -> Result<()> {
+    if wf.constraints().is_empty() {
+        return Ok(());
+    }
+    let mut checked: HashSet<&RealTaskKey> = HashSet::default();
+    let mut broken = Vec::new();
+    for node in &traversal.nodes {
+        if !checked.insert(&node.key) {
+            continue;
         }
+        for constraint in wf.constraints().iter().filter(|c| c.is_broken_by(&node.key.branch)) {
+            let desc = constraint.describe(&wf.strings)?;
+            broken.push(Recapper::new(Error::BrokenConstraint(
+                node.key.clone(),
+                desc,
+            )));
+        }
+    }
+    for e in broken {
+        traversal.errors.add(e.into());
     }
     Ok(())
 }
@@ -77,13 +234,9 @@ fn rm_filtered_branchpoints<B: Bitmask>(
     mask: &B,
     wf: &Workflow,
 ) -> Result<()> {
-    for i in 0..wf.strings.branchpoints.len() {
-        let branchpoint_id = i.into();
-        log::trace!(
-            "checking branchpoint {}",
-            wf.strings.branchpoints.get(branchpoint_id)?
-        );
-        if !mask.get(i) {
+    for (branchpoint_id, name) in wf.strings.branchpoints.iter() {
+        log::trace!("checking branchpoint {name}");
+        if !mask.get(usize::from(branchpoint_id)) {
             log::trace!("not in mask; removing.");
             branch.unset(branchpoint_id);
         } else if branch.is_unspecified(branchpoint_id) {
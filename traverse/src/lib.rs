@@ -3,9 +3,11 @@
 //! list of tasks that can be run by structs in the `exec` mod.
 //!
 //! The traversal is created in 3 steps:
-//! 1. Perform a BFS search backwards from the goal node(s), adding all necessary antecedent tasks.
-//! 2. Reverse the list of tasks, correcting inter-task links.
-//! 3. Step forward through the tasks, removing branchpoints that have been grafted out.
+//! 1. Perform a BFS search backwards from the goal node(s), adding all necessary antecedent tasks,
+//!    and visiting each task realization only once.
+//! 2. Step forward through the tasks' dependency levels, removing branchpoints that have been
+//!    grafted out.
+//! 3. Sort the tasks into run order, correcting inter-task links.
 //!
 //! In the end, you will have an ordered traversal of tasks
 //! With only the minimal set of branchpoints required to uniquely identify each task.
@@ -15,16 +17,20 @@
 //! actual paths to task execution directories on disk; those paths will be provided by structs
 //! in [`crate::prep`].
 
-/// full BFS traversal (in reverse order) of the workflow
+/// full BFS traversal (backwards from the goals) of the workflow
 mod bfs;
 
-/// reverse and simplify branches
+/// simplify branches and sort into run order
 mod cleanup;
 
 /// struct returned by this mod
 mod traversal;
 pub use traversal::Traversal;
 
+/// size and timing info about a traversal
+mod stats;
+pub use stats::TraversalStats;
+
 mod traversal_builder;
 use traversal_builder::TraversalBuilder;
 
@@ -36,19 +42,37 @@ use node::NodeBuilder;
 mod value;
 pub use value::{RealInput, RealOutput, RealOutputOrParam, RealParam, ValueContext};
 
-// so we can have max ~16k task realizations, which should be enough.
-// this is before deduping though, so realistically the cap is lower.
-type NodeIdx = u16;
+/// Int type used to index nodes while building a traversal.
+/// [`Traversal::create`] starts with `u16` (up to 65536 task realizations, before deduping),
+/// and tries again with `u32` if the workflow has too many realizations for that.
+/// Only node indices are promoted: [`workflow::RealTaskId`], [`workflow::RealValueId`],
+/// and the offsets into the branch string caches are always `u32`, so they fit either way.
+pub trait NodeIdx: Copy + Eq + TryFrom<usize> {
+    fn index(self) -> usize;
+}
+
+impl NodeIdx for u16 {
+    fn index(self) -> usize {
+        self.into()
+    }
+}
+
+impl NodeIdx for u32 {
+    fn index(self) -> usize {
+        self as usize
+    }
+}
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
-    #[error(
-        "Out of node indices; {0} is greater than max index \
-        (this may be due to a circular dependency)"
-    )]
+    #[error("Out of node indices; {0} is greater than max index (too many task realizations)")]
     OutOfIndices(usize),
     #[error("Task depends on itself: {0:?}")]
     ReflexiveTask(workflow::AbstractTaskId),
+    #[error("Circular dependency: {0:?}")]
+    CircularDependency(Vec<workflow::RealTaskKey>),
+    #[error("Realization breaks constraint '{1}': {0:?}")]
+    BrokenConstraint(workflow::RealTaskKey, String),
 }
 
 impl workflow::Recap for Error {
@@ -60,6 +84,22 @@ impl workflow::Recap for Error {
                 let name = wf.tasks.get(*id)?;
                 Ok(Some(format!("Task depends on itself: {name}")))
             }
+            CircularDependency(cycle) => {
+                let mut path = String::new();
+                for (i, key) in cycle.iter().enumerate() {
+                    if i > 0 {
+                        path.push_str(" -> ");
+                    }
+                    path.push_str(&wf.get_real_task_str(key)?);
+                }
+                Ok(Some(format!(
+                    "Circular dependency (each task depends on the next): {path}"
+                )))
+            }
+            BrokenConstraint(key, constraint) => Ok(Some(format!(
+                "{} breaks constraint '{constraint}'",
+                &*wf.get_real_task_str(key)?
+            ))),
             _ => Ok(None),
         }
     }
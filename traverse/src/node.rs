@@ -1,15 +1,23 @@
-use workflow::{IdentId, LiteralId, ModuleId, RealTaskKey, RealValueId, Task, TaskVars};
+use util::{AllocSize, IdVec};
+use workflow::{
+    BranchSpec, BranchpointId, IdentId, LiteralId, ModuleId, RealTaskId, RealTaskKey, RealValueId,
+    Task, TaskVars,
+};
 
-use crate::value::BranchMasks;
-use crate::NodeIdx;
+use crate::value::{BranchMasks, RealInput};
 
 /// Partially-resolved task used internally by traversal fns.
 #[derive(Debug)]
-pub struct NodeBuilder<B> {
+pub struct NodeBuilder<B, I> {
     /// Unique id of the task contained in this node.
     pub key: RealTaskKey,
-    /// traversal index of next task. if equal to this task's idx, the task is terminal.
-    pub next_idx: NodeIdx,
+    /// branch the task was reached with, before unneeded branchpoints are removed from `key`.
+    pub requested_branch: BranchSpec,
+    /// branchpoints whose values were grafted by the dependent that reached this task.
+    pub grafted: Vec<BranchpointId>,
+    /// traversal index of the task that first reached this one (other tasks can reach it too).
+    /// if equal to this task's idx, the task is terminal.
+    pub next_idx: I,
     /// true if this task has no antecedents.
     pub is_root: bool,
     /// inputs, outputs, and params for this task.
@@ -18,48 +26,114 @@ pub struct NodeBuilder<B> {
     pub code: LiteralId,
     /// vars referenced in code (so we can check them later).
     pub code_vars: Vec<IdentId>,
-    /// optional module to run this task in.
-    pub module: Option<ModuleId>,
+    /// modules used by this task; it runs in the first one.
+    pub modules: Vec<ModuleId>,
+    /// optional limit on the size of this task's realization dir.
+    pub max_output_size: Option<u64>,
+    /// metadata from the task's other dot params.
+    pub dot_params: Vec<(IdentId, LiteralId)>,
     /// branches added and removed at this task.
     pub masks: BranchMasks<B>,
+    /// branchpoints each input grafts out of the branches of the tasks it uses,
+    /// in the same order as `vars.inputs`.
+    pub input_rms: Vec<B>,
 }
 
-impl<B: Default> NodeBuilder<B> {
+impl<B: Default, I> NodeBuilder<B, I> {
     /// Create a new Node with the given `key`, `next_idx`, and values
     /// copied from `task`.
-    pub fn new(key: RealTaskKey, next_idx: NodeIdx, task: &Task) -> Self {
+    pub fn new(key: RealTaskKey, next_idx: I, grafted: Vec<BranchpointId>, task: &Task) -> Self {
         NodeBuilder {
+            requested_branch: key.branch.clone(),
+            grafted,
             key,
             next_idx,
             code: task.code,
             code_vars: task.referenced_vars.clone(),
-            module: task.module,
+            modules: task.modules.clone(),
+            max_output_size: task.max_output_size,
+            dot_params: task.dot_params.clone(),
             vars: TaskVars::new_with_sizes(&task.vars),
             masks: BranchMasks::default(),
+            input_rms: Vec::with_capacity(task.vars.inputs.len()),
             // NB we will set this to false if we find an antecedent during handling:
             is_root: true,
         }
     }
 }
 
+impl<B, I> NodeBuilder<B, I> {
+    /// Ids of the nodes whose outputs this node's inputs use, each listed once.
+    pub fn antecedents(&self, inputs: &IdVec<RealValueId, RealInput>) -> Vec<RealTaskId> {
+        antecedents(&self.vars.inputs, inputs)
+    }
+}
+
 /// Resolved task exported to run subsystem
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Node {
     pub key: RealTaskKey,
+    pub requested_branch: BranchSpec,
+    pub grafted: Vec<BranchpointId>,
     pub vars: TaskVars<(IdentId, RealValueId)>,
     pub code: LiteralId,
     pub code_vars: Vec<IdentId>,
-    pub module: Option<ModuleId>,
+    pub modules: Vec<ModuleId>,
+    pub max_output_size: Option<u64>,
+    pub dot_params: Vec<(IdentId, LiteralId)>,
 }
 
-impl<B> From<NodeBuilder<B>> for Node {
-    fn from(node: NodeBuilder<B>) -> Self {
+impl Node {
+    /// Ids of the nodes whose outputs this node's inputs use, in the order its inputs use
+    /// them, each listed once. `inputs` are the inputs of the traversal containing this node.
+    pub fn antecedents(&self, inputs: &IdVec<RealValueId, RealInput>) -> Vec<RealTaskId> {
+        antecedents(&self.vars.inputs, inputs)
+    }
+}
+
+/// Ids of the nodes whose outputs `vars` (a node's inputs) use, each listed once.
+fn antecedents(
+    vars: &[(IdentId, RealValueId)],
+    inputs: &IdVec<RealValueId, RealInput>,
+) -> Vec<RealTaskId> {
+    let mut antecedents = Vec::with_capacity(vars.len());
+    for (_, val_id) in vars {
+        if let Some(input) = inputs.get(*val_id) {
+            input.for_each_task(&mut |dep| {
+                if !antecedents.contains(&dep) {
+                    antecedents.push(dep);
+                }
+            });
+        }
+    }
+    antecedents
+}
+
+impl<B, I> From<NodeBuilder<B, I>> for Node {
+    fn from(node: NodeBuilder<B, I>) -> Self {
         Self {
             key: node.key,
+            requested_branch: node.requested_branch,
+            grafted: node.grafted,
             vars: node.vars,
             code: node.code,
             code_vars: node.code_vars,
-            module: node.module,
+            modules: node.modules,
+            max_output_size: node.max_output_size,
+            dot_params: node.dot_params,
         }
     }
 }
+
+impl AllocSize for Node {
+    fn alloc_size(&self) -> usize {
+        self.key.alloc_size()
+            + self.requested_branch.alloc_size()
+            + self.grafted.alloc_size()
+            + self.vars.alloc_size()
+            + self.code_vars.alloc_size()
+            + self.modules.alloc_size()
+            + self.dot_params.alloc_size()
+    }
+}
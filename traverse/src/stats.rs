@@ -0,0 +1,48 @@
+use std::fmt;
+use std::time::Duration;
+
+use util::format_duration;
+
+/// Size of a traversal and how long each phase of creating it took,
+/// for reasoning about performance on big workflows.
+#[derive(Debug, Default, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TraversalStats {
+    /// nodes enqueued by the BFS, which only visits each task realization once, but can
+    /// still reach realizations that are the same once their branches are cleaned.
+    pub nodes: usize,
+    /// distinct task realizations, after deduplication.
+    pub realizations: usize,
+    /// nodes with no antecedents.
+    pub roots: usize,
+    /// number of dependency levels, i.e. the length of the longest chain of dependencies.
+    pub depth: usize,
+    /// bits of the branch masks used while cleaning branches.
+    pub mask_bits: usize,
+    /// bits of the node indices used while building the traversal.
+    pub index_bits: usize,
+    /// time spent searching backwards from the goals.
+    pub bfs: Duration,
+    /// time spent removing unused branchpoints from each node.
+    pub cleanup: Duration,
+    /// time spent sorting the nodes into run order.
+    pub sort: Duration,
+}
+
+impl fmt::Display for TraversalStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Traversal has {} nodes ({} after deduplication), {} roots, and {} levels; \
+             {}-bit branch masks, {}-bit node indices.",
+            self.nodes, self.realizations, self.roots, self.depth, self.mask_bits, self.index_bits,
+        )?;
+        write!(
+            f,
+            "BFS took {}, cleanup {}, sort {}.",
+            format_duration(self.bfs),
+            format_duration(self.cleanup),
+            format_duration(self.sort),
+        )
+    }
+}
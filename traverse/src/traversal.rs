@@ -1,29 +1,52 @@
+use std::time::Instant;
+
 use anyhow::Result;
 
 use intern::GetStr;
-use util::{Bitmask, IdVec};
-use workflow::{Plan, RealTaskKey, RealValueId, Workflow};
+use util::{AllocSize, Bitmask, HashSet, IdVec, MemoryUsage};
+use workflow::{Plan, RealTaskId, RealTaskKey, RealValueId, Workflow};
 
-use super::{bfs, cleanup, Node};
+use super::{bfs, cleanup, Error, Node, NodeIdx, TraversalStats};
 use crate::value::{RealInput, RealOutputOrParam};
 
 /// Represents a specific traversal through the tasks in the workflow.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Traversal {
     pub nodes: Vec<Node>,
     pub inputs: IdVec<RealValueId, RealInput>,
     pub outputs_params: IdVec<RealValueId, RealOutputOrParam>,
+    /// Ids of `nodes` grouped into dependency levels: every antecedent of a node
+    /// is in an earlier level, so the nodes in a level can run in parallel.
+    pub levels: Vec<Vec<RealTaskId>>,
+    /// Size of the traversal and how long creating it took.
+    pub stats: TraversalStats,
 }
 
 impl Traversal {
     /// The returned traversal may contain duplicates, but it is guaranteed
     /// to be ordered in run/dependency order and fully resolved with clean branches.
+    /// Nodes are indexed with `u16` if possible, falling back to `u32` for very large traversals.
     pub fn create<B: Bitmask>(wf: &Workflow, plan: Plan) -> Result<Self> {
+        match Self::create_with_idx::<B, u16>(wf, &plan) {
+            Err(e) if matches!(e.downcast_ref(), Some(Error::OutOfIndices(_))) => {
+                log::debug!("too many task realizations for u16 node indices; using u32");
+                Self::create_with_idx::<B, u32>(wf, &plan)
+            }
+            res => res,
+        }
+    }
+
+    /// Create a traversal using node index type `I`, failing with
+    /// [`Error::OutOfIndices`] if it has too many task realizations to index.
+    pub fn create_with_idx<B: Bitmask, I: NodeIdx>(wf: &Workflow, plan: &Plan) -> Result<Self> {
         debug_assert!(wf.strings.branchpoints.len() <= B::BITS);
 
-        let mut traverser = bfs::BfsTraverser::<B>::new(wf);
+        let start = Instant::now();
+        let mut traverser = bfs::BfsTraverser::<B, I>::new(wf)?;
 
         for plan in &plan.subplans {
             for goal in &plan.goals {
+                wf.get_task(*goal)?;
                 for branch in &plan.branches {
                     let goal = RealTaskKey {
                         id: *goal,
@@ -35,6 +58,7 @@ impl Traversal {
         }
 
         let mut traversal = traverser.into_traversal();
+        let bfs = start.elapsed();
 
         log::debug!(
             "created unpruned traversal with {} nodes",
@@ -44,9 +68,391 @@ impl Traversal {
             log::trace!("{}", wf.strings.get_real_task_str(&node.key)?,);
         }
 
-        cleanup::clean_branches_reversed(&mut traversal, wf)?;
+        let start = Instant::now();
+        let levels = cleanup::dependency_levels(&traversal)?;
+        cleanup::clean_branches(&mut traversal, &levels, wf)?;
+        cleanup::check_constraints(&mut traversal, wf)?;
+        let cleanup = start.elapsed();
 
         traversal.errors.print_recap("building traversal", &wf.strings)?;
-        Ok(cleanup::reverse_and_strip(traversal))
+
+        let (nodes, roots) = (traversal.nodes.len(), traversal.roots.len());
+        let start = Instant::now();
+        let mut traversal = cleanup::strip_and_sort(traversal, levels, wf)?;
+        let sort = start.elapsed();
+
+        let realizations: HashSet<&RealTaskKey> = traversal.nodes.iter().map(|n| &n.key).collect();
+        traversal.stats = TraversalStats {
+            nodes,
+            realizations: realizations.len(),
+            roots,
+            depth: traversal.levels.len(),
+            // a heap-allocated mask uses as many 64-bit words as it needs:
+            mask_bits: B::BITS.min(wf.strings.branchpoints.len().div_ceil(64).max(1) * 64),
+            index_bits: std::mem::size_of::<I>() * 8,
+            bfs,
+            cleanup,
+            sort,
+        };
+        Ok(traversal)
+    }
+}
+
+impl Traversal {
+    /// Ids of the antecedents of the node with id `id`: the nodes whose outputs it uses,
+    /// each listed once. They're always earlier in `nodes`, and in earlier `levels`.
+    pub fn antecedents(&self, id: RealTaskId) -> Vec<RealTaskId> {
+        self.nodes[usize::from(id)].antecedents(&self.inputs)
+    }
+
+    /// Memory used by the nodes, values, and levels of the traversal, for 'hr stats --memory'.
+    pub fn memory_usage(&self) -> Vec<MemoryUsage> {
+        vec![
+            MemoryUsage::of("nodes", &self.nodes),
+            MemoryUsage {
+                name: "inputs",
+                len: self.inputs.len(),
+                bytes: self.inputs.alloc_size(),
+            },
+            MemoryUsage {
+                name: "outputs and params",
+                len: self.outputs_params.len(),
+                bytes: self.outputs_params.alloc_size(),
+            },
+            MemoryUsage::of("levels", &self.levels),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use intern::InternStr;
+    use std::path::Path;
+    use util::DynBitmask;
+
+    // 16^4 = 65536 realizations of `sweep` fill every u16 node index; one more doesn't fit.
+    fn config() -> String {
+        let vals: Vec<_> = (1..=16).map(|i| format!("v{i}")).collect();
+        let vals = vals.join(" ");
+        format!(
+            "task sweep :: a=(A: {vals}) b=(B: {vals}) c=(C: {vals}) d=(D: {vals}) {{\n\
+               echo $a $b $c $d\n\
+             }}\n\
+             task extra {{ echo extra }}\n\
+             plan fits {{ reach sweep via (A: *) * (B: *) * (C: *) * (D: *) }}\n\
+             plan overflows {{\n\
+               reach sweep via (A: *) * (B: *) * (C: *) * (D: *)\n\
+               reach extra\n\
+             }}\n"
+        )
+    }
+
+    fn plan(wf: &mut Workflow, name: &str) -> Result<Plan> {
+        let id = wf.strings.idents.intern(name)?;
+        Ok(wf.get_plan(id)?.clone())
+    }
+
+    #[test]
+    fn test_node_idx_boundary() -> Result<()> {
+        let mut wf = Workflow::default();
+        wf.load(syntax::parse(&config())?, Path::new("."))?;
+        let fits = plan(&mut wf, "fits")?;
+        let overflows = plan(&mut wf, "overflows")?;
+        wf.strings.alloc_for_traversal();
+
+        let traversal = Traversal::create_with_idx::<u8, u16>(&wf, &fits)?;
+        assert_eq!(traversal.nodes.len(), 65536);
+
+        let e = Traversal::create_with_idx::<u8, u16>(&wf, &overflows).err().unwrap();
+        assert!(matches!(e.downcast_ref(), Some(Error::OutOfIndices(65536))));
+
+        let traversal = Traversal::create::<u8>(&wf, overflows)?;
+        assert_eq!(traversal.nodes.len(), 65537);
+        assert_eq!(traversal.stats.index_bits, 32);
+        Ok(())
+    }
+
+    #[test]
+    fn test_dyn_bitmask() -> Result<()> {
+        // two tasks that add 65 branchpoints each, more than fit in a u128:
+        let params = |range: std::ops::Range<usize>| -> String {
+            range.map(|i| format!("p{i}=(B{i}: a b)")).collect::<Vec<_>>().join(" ")
+        };
+        let config = format!(
+            "plan wide {{ reach t1 via (B129: b) }}\n\
+             task t0 > out :: {} {{ touch $out }}\n\
+             task t1 < in=$out@t0 > out :: {} {{ touch $out }}\n",
+            params(0..65),
+            params(65..130),
+        );
+        let mut wf = Workflow::default();
+        wf.load(syntax::parse(&config)?, Path::new("."))?;
+        let wide = plan(&mut wf, "wide")?;
+        let first = wf.strings.branchpoints.intern("B0")?;
+        let last = wf.strings.branchpoints.intern("B129")?;
+        let b = wf.strings.idents.intern("b")?;
+        wf.strings.alloc_for_traversal();
+
+        let traversal = Traversal::create::<DynBitmask>(&wf, wide)?;
+        assert_eq!(traversal.nodes.len(), 2);
+        assert_eq!(traversal.stats.mask_bits, 192);
+        let root = &traversal.nodes[0].key.branch;
+        assert!(root.is_specified(first));
+        assert!(root.is_unspecified(last));
+        let goal = &traversal.nodes[1].key.branch;
+        assert!(goal.is_specified(first));
+        assert_eq!(goal.get_specified(last), Some(b));
+        Ok(())
+    }
+
+    #[test]
+    fn test_levels() -> Result<()> {
+        let config = "plan main { reach d }\n\
+                      task a > out { touch $out }\n\
+                      task b < in=$out@a > out { cp $in $out }\n\
+                      task c < in=$out@a > out { cp $in $out }\n\
+                      task d < b=$out@b c=$out@c > out { cat $b $c > $out }\n";
+        let mut wf = Workflow::default();
+        wf.load(syntax::parse(config)?, Path::new("."))?;
+        let main = plan(&mut wf, "main")?;
+        wf.strings.alloc_for_traversal();
+
+        let traversal = Traversal::create::<u8>(&wf, main)?;
+        let mut levels = Vec::new();
+        for level in &traversal.levels {
+            let mut names = Vec::new();
+            for id in level {
+                let node = &traversal.nodes[usize::from(*id)];
+                names.push(wf.strings.tasks.get(node.key.id)?);
+            }
+            names.sort();
+            levels.push(names);
+        }
+        // `a` is reached through both `b` and `c`, but only visited once:
+        assert_eq!(levels, vec![vec!["a"], vec!["b", "c"], vec!["d"]]);
+
+        let stats = &traversal.stats;
+        assert_eq!((stats.nodes, stats.realizations), (4, 4));
+        assert_eq!((stats.roots, stats.depth), (1, 3));
+        assert_eq!((stats.mask_bits, stats.index_bits), (8, 16));
+
+        let memory = traversal.memory_usage();
+        let names: Vec<_> = memory.iter().map(|usage| (usage.name, usage.len)).collect();
+        assert_eq!(
+            names,
+            [
+                ("nodes", 4),
+                ("inputs", 4),
+                ("outputs and params", 4),
+                ("levels", 3)
+            ]
+        );
+        assert!(memory[0].bytes >= 4 * std::mem::size_of::<Node>());
+        Ok(())
+    }
+
+    #[test]
+    fn test_antecedents() -> Result<()> {
+        let config = "plan main { reach c }\n\
+                      task a > out { touch $out }\n\
+                      task b < x=$out@a y=$out@a > out { cat $x $y > $out }\n\
+                      task c < a=$out@a b=$out@b { cat $a $b }\n";
+        let mut wf = Workflow::default();
+        wf.load(syntax::parse(config)?, Path::new("."))?;
+        let main = plan(&mut wf, "main")?;
+        wf.strings.alloc_for_traversal();
+
+        let traversal = Traversal::create::<u8>(&wf, main)?;
+        let names = |ids: Vec<RealTaskId>| -> Result<Vec<&str>> {
+            let tasks = ids.iter().map(|id| traversal.nodes[usize::from(*id)].key.id);
+            tasks.map(|t| wf.strings.tasks.get(t)).collect()
+        };
+        for (id, node) in traversal.nodes.iter().enumerate() {
+            let antecedents = names(traversal.antecedents(id.into()))?;
+            let expected: &[&str] = match wf.strings.tasks.get(node.key.id)? {
+                "a" => &[],
+                // b uses a twice, but it's only listed once:
+                "b" => &["a"],
+                _ => &["a", "b"],
+            };
+            assert_eq!(antecedents, expected);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_stable_order() -> Result<()> {
+        // NB the baseline A.a1 comes first, though its compact branch is 'Baseline.baseline':
+        let config = "plan one { reach b, a via (A: a1 a2) }\n\
+                      plan two { reach a, b via (A: a2 a1) }\n\
+                      task a > out :: x=(A: a1 a2) { touch $out }\n\
+                      task b < in=$out@a > out { cp $in $out }\n";
+        let mut wf = Workflow::default();
+        wf.load(syntax::parse(config)?, Path::new("."))?;
+        let one = plan(&mut wf, "one")?;
+        let two = plan(&mut wf, "two")?;
+        wf.strings.alloc_for_traversal();
+
+        let order = |plan| -> Result<Vec<String>> {
+            let traversal = Traversal::create::<u8>(&wf, plan)?;
+            let mut order = Vec::new();
+            for node in &traversal.nodes {
+                order.push(wf.strings.get_real_task_str(&node.key)?.to_owned());
+            }
+            Ok(order)
+        };
+        let one = order(one)?;
+        assert_eq!(one, order(two)?);
+        // level by level, then by task name and branch:
+        assert_eq!(one, vec!["a[A.a1]", "a[A.a2]", "b[A.a1]", "b[A.a2]"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_visit_once() -> Result<()> {
+        // every branch of `d` needs `b` and `c`, which each only need one of its branchpoints:
+        let config = "plan main { reach d via (X: x1 x2) * (Y: y1 y2) * (Z: z1 z2) }\n\
+                      plan cycle { reach g }\n\
+                      task b > out :: x=(X: x1 x2) { echo $x > $out }\n\
+                      task c > out :: y=(Y: y1 y2) { echo $y > $out }\n\
+                      task d < b=$out@b c=$out@c > out :: z=(Z: z1 z2) { cat $b $c > $out }\n\
+                      task g < a=$out@e b=$out@f { cat $a $b }\n\
+                      task e < in=$out@f > out { cp $in $out }\n\
+                      task f < in=$out@e > out { cp $in $out }\n";
+        let mut wf = Workflow::default();
+        wf.load(syntax::parse(config)?, Path::new("."))?;
+        let main = plan(&mut wf, "main")?;
+        let cycle = plan(&mut wf, "cycle")?;
+        wf.strings.alloc_for_traversal();
+
+        let traversal = Traversal::create::<u8>(&wf, main)?;
+        let mut keys = Vec::new();
+        for node in &traversal.nodes {
+            keys.push(wf.strings.get_real_task_str(&node.key)?.to_owned());
+        }
+        // each realization of `b` and `c` is reached by 4 branches of `d`, but only visited once,
+        // and `d` keeps the branchpoints of both of its inputs:
+        assert_eq!(traversal.stats.nodes, 8 + 2 + 2);
+        assert_eq!(keys.len(), 12);
+        assert_eq!(&keys[..4], ["b[X.x1]", "b[X.x2]", "c[Y.y1]", "c[Y.y2]"]);
+        assert!(keys.contains(&"d[X.x2+Y.y1+Z.z2]".to_owned()));
+
+        // `e` and `f` are each reached from `g` first, so the cycle closes through nodes
+        // that were already visited:
+        let e = Traversal::create::<u8>(&wf, cycle).err().expect("cycle is an error");
+        assert!(format!("{e:?}").contains("CircularDependency"), "{e:?}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_graft_one_input() -> Result<()> {
+        // `e` grafts B out of `m` and A out of `n`, but still gets A from `m` and B from `n`:
+        let config = "plan main { reach e via (A: *) * (B: *) }\n\
+                      task m > out :: a=(A: a1 a2) b=(B: b1 b2) { echo $a $b > $out }\n\
+                      task n > out :: a=(A: a1 a2) b=(B: b1 b2) { echo $a $b > $out }\n\
+                      task e < m=$out@m[B: b2] n=$out@n[A: *] { cat $m $n }\n";
+        let mut wf = Workflow::default();
+        wf.load(syntax::parse(config)?, Path::new("."))?;
+        let main = plan(&mut wf, "main")?;
+        wf.strings.alloc_for_traversal();
+
+        let traversal = Traversal::create::<u8>(&wf, main)?;
+        let mut keys = Vec::new();
+        for node in &traversal.nodes {
+            let key = wf.strings.get_real_task_str(&node.key)?.to_owned();
+            if key.starts_with("e[") {
+                keys.push(key);
+            }
+        }
+        keys.sort();
+        assert_eq!(
+            keys,
+            [
+                "e[A.a1+B.b1]",
+                "e[A.a1+B.b2]",
+                "e[A.a2+B.b1]",
+                "e[A.a2+B.b2]"
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_constraints() -> Result<()> {
+        let config = "constraint Os.windows requires Arch.x64\n\
+                      task build :: os=(Os: linux windows) arch=(Arch: arm64 x64) { echo $os $arch }\n\
+                      task docs :: os=(Os: linux windows) { echo $os }\n\
+                      plan ok { reach build via (Os: windows) * (Arch: x64) }\n\
+                      plan defaults { reach build, docs via (Os: windows) }\n";
+        let mut wf = Workflow::default();
+        wf.load(syntax::parse(config)?, Path::new("."))?;
+        let ok = plan(&mut wf, "ok")?;
+        let defaults = plan(&mut wf, "defaults")?;
+        wf.strings.alloc_for_traversal();
+        Traversal::create::<u8>(&wf, ok)?;
+
+        // `build` gets the baseline Arch.arm64, which windows doesn't allow; `docs` doesn't use Arch:
+        let e = Traversal::create::<u8>(&wf, defaults).err().expect("constraint is broken");
+        let e = e.downcast::<workflow::AggregatedErrors>()?;
+        assert_eq!(e.1, 1);
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() -> Result<()> {
+        let config = "plan main { reach c via (X: *) }\n\
+                      global {\n  greeting=\"hi $name\"\n  name=(X: x1 x2)\n}\n\
+                      task a > out :: x=(X: x1 x2) { touch $out }\n\
+                      task b < in=$out@a[X: x1] > out :: greeting=$greeting { cp $in $out }\n\
+                      task c < all=$out@a[X: *] b=$out@b > out { cat $all $b > $out }\n";
+        let mut wf = Workflow::default();
+        wf.load(syntax::parse(config)?, Path::new("."))?;
+        let main = plan(&mut wf, "main")?;
+        wf.strings.alloc_for_traversal();
+        let traversal = Traversal::create::<u8>(&wf, main)?;
+
+        let bytes = bincode::serialize(&(&wf, &traversal))?;
+        let (mut decoded_wf, decoded): (Workflow, Traversal) = bincode::deserialize(&bytes)?;
+
+        assert_eq!(
+            format!("{:?}", decoded.nodes),
+            format!("{:?}", traversal.nodes)
+        );
+        assert_eq!(
+            format!("{:?}", decoded.inputs),
+            format!("{:?}", traversal.inputs)
+        );
+        assert_eq!(
+            format!("{:?}", decoded.outputs_params),
+            format!("{:?}", traversal.outputs_params)
+        );
+        assert_eq!(decoded.levels, traversal.levels);
+        assert_eq!(decoded.stats.realizations, traversal.stats.realizations);
+
+        // the decoded workflow has the same strings, and traverses the same way:
+        assert_eq!(
+            decoded_wf.task_names().collect::<Vec<_>>(),
+            wf.task_names().collect::<Vec<_>>()
+        );
+        for node in &decoded.nodes {
+            assert_eq!(
+                *decoded_wf.strings.get_real_task_str(&node.key)?,
+                *wf.strings.get_real_task_str(&node.key)?
+            );
+        }
+        let main = plan(&mut decoded_wf, "main")?;
+        decoded_wf.strings.alloc_for_traversal();
+        let retraversed = Traversal::create::<u8>(&decoded_wf, main)?;
+        assert_eq!(
+            format!("{:?}", retraversed.nodes),
+            format!("{:?}", traversal.nodes)
+        );
+        assert_eq!(
+            format!("{:?}", retraversed.inputs),
+            format!("{:?}", traversal.inputs)
+        );
+        Ok(())
     }
 }
@@ -7,7 +7,9 @@ pub use real_value::{
 mod value_resolver;
 pub use value_resolver::ValueResolver;
 
-use workflow::{IdentId, Recap};
+use anyhow::Result;
+
+use workflow::{BranchSpec, IdentId, Recap};
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -17,27 +19,72 @@ pub enum Error {
     UnsupportedTaskOutput,
     #[error("Variable interpolation not supported in this position")]
     UnsupportedInterp,
+    #[error("Branch glob grafts (e.g. '[Branchpoint: *]') are only supported in inputs")]
+    UnsupportedGlob,
     #[error("Expected literal value, got {0}")]
     ExpectedLiteral(String),
-    #[error("Specified branch does not exist")]
-    BranchNotFound,
+    /// The branch a branched value was resolved for, and the branches it's defined for.
+    #[error("No value is defined for branch {0:?}; the value's branches are {1:?}")]
+    BranchNotFound(BranchSpec, Vec<BranchSpec>),
     #[error("Reference to nonexistent config value: {0:?}")]
-    UndefinedConfigValue(IdentId),
+    UndefinedConfigValue(IdentId, Option<String>),
 }
 
 impl Recap for Error {
     fn recap(&self, wf: &workflow::WorkflowStrings) -> anyhow::Result<Option<String>> {
         use intern::GetStr;
         match self {
-            Self::UndefinedConfigValue(id) => Ok(Some(format!(
-                "Reference to nonexistent config value: {}",
+            Self::UndefinedConfigValue(id, closest) => Ok(Some(format!(
+                "Reference to nonexistent config value: {}{}",
                 wf.idents.get(*id)?,
+                util::did_you_mean(closest.as_deref()),
             ))),
+            Self::BranchNotFound(branch, arms) => {
+                let mut arm_strs = Vec::with_capacity(arms.len());
+                for arm in arms {
+                    arm_strs.push(wf.get_full_branch_str(arm)?.to_owned());
+                }
+                let suggestion = closest_arm(branch, arms, &arm_strs, wf)?;
+                Ok(Some(format!(
+                    "No value is defined for branch {}; the value's branches are {}{}",
+                    &*wf.get_full_branch_str(branch)?,
+                    arm_strs.join(", "),
+                    util::did_you_mean(suggestion),
+                )))
+            }
             _ => Ok(None),
         }
     }
 }
 
+/// The branch of a branched value (`arms`, rendered as `arm_strs`) that `branch` was
+/// probably meant to match: the one whose string is closest to the same branchpoints
+/// of `branch`, if any is close enough to be a likely typo.
+fn closest_arm<'a>(
+    branch: &BranchSpec,
+    arms: &[BranchSpec],
+    arm_strs: &'a [String],
+    wf: &workflow::WorkflowStrings,
+) -> Result<Option<&'a str>> {
+    let mut closest = None;
+    for (arm, arm_str) in arms.iter().zip(arm_strs) {
+        let mut requested = BranchSpec::default();
+        for (k, _) in arm.specified() {
+            if let Some(v) = branch.get_specified(k) {
+                requested.insert(k, v);
+            }
+        }
+        let requested = wf.get_full_branch_str(&requested)?;
+        if util::closest_match(&requested, [arm_str.as_str()]).is_some() {
+            let distance = util::edit_distance(&requested, arm_str);
+            if closest.is_none_or(|(d, _)| distance < d) {
+                closest = Some((distance, arm_str.as_str()));
+            }
+        }
+    }
+    Ok(closest.map(|(_, s)| s))
+}
+
 /// for more helpful error messages
 #[derive(Debug)]
 pub struct ValueContext {
@@ -58,3 +105,41 @@ impl Recap for ValueContext {
         )))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use intern::InternStr;
+    use std::path::Path;
+    use workflow::Workflow;
+
+    #[test]
+    fn test_branch_not_found_recap() -> Result<()> {
+        let text = "task a :: os=(Os: mac linux) { echo $os }\n\
+                    task b :: os=(Os: mac linux windows) { echo $os }\n";
+        let mut wf = Workflow::default();
+        wf.load(syntax::parse(text)?, Path::new("."))?;
+        wf.strings.alloc_for_traversal();
+        let os = wf.strings.branchpoints.intern("Os")?;
+        let mut branch = |name: &str| -> Result<BranchSpec> {
+            Ok(BranchSpec::simple(os, wf.strings.idents.intern(name)?))
+        };
+        let (mac, linux) = (branch("mac")?, branch("linux")?);
+        let (windows, linx) = (branch("windows")?, branch("linx")?);
+        let arms = vec![mac, linux];
+        let windows = Error::BranchNotFound(windows, arms.clone());
+        assert_eq!(
+            windows.recap(&wf.strings)?.as_deref(),
+            Some("No value is defined for branch Os.windows; the value's branches are Os.mac, Os.linux")
+        );
+        let typo = Error::BranchNotFound(linx, arms);
+        assert_eq!(
+            typo.recap(&wf.strings)?.as_deref(),
+            Some(
+                "No value is defined for branch Os.linx; the value's branches are Os.mac, Os.linux \
+                 (did you mean `Os.linux`?)"
+            )
+        );
+        Ok(())
+    }
+}
@@ -1,5 +1,6 @@
 use anyhow::Result;
 
+use util::AllocSize;
 use workflow::{AbstractTaskId, BranchSpec, IdentId, LiteralId, RealTaskId};
 
 use super::Error;
@@ -24,6 +25,11 @@ pub trait RealValueLike: Sized {
         Err(Error::UnsupportedTaskOutput)
     }
 
+    /// Create a new list of values (from a branch glob graft), if allowed.
+    fn list(_items: Vec<Self>) -> Result<Self, Error> {
+        Err(Error::UnsupportedGlob)
+    }
+
     /// If value needs to return a branch
     /// (i.e. if it's a task output value that will be used to create a new node in a traversal),
     /// update that branch with new branch information generated during value resolution.
@@ -36,11 +42,38 @@ pub trait RealValueLike: Sized {
 
 /// A fully-realized input value
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum RealInput {
     /// literally-specified input value
     Literal(LiteralId),
     /// input value taken from the output of another task
     Task(RealTaskId, IdentId),
+    /// space-separated list of input values, from a branch glob graft
+    List(Vec<RealInput>),
+}
+
+impl RealInput {
+    /// Call `f` with the id of each task whose output this input uses.
+    pub fn for_each_task(&self, f: &mut impl FnMut(RealTaskId)) {
+        match self {
+            Self::Literal(_) => (),
+            Self::Task(id, _) => f(*id),
+            Self::List(items) => {
+                for item in items {
+                    item.for_each_task(f);
+                }
+            }
+        }
+    }
+}
+
+impl AllocSize for RealInput {
+    fn alloc_size(&self) -> usize {
+        match self {
+            Self::List(items) => items.alloc_size(),
+            _ => 0,
+        }
+    }
 }
 
 /// A partially-realized input value,
@@ -52,6 +85,8 @@ pub enum PartialRealInput {
     /// input value taken from the output of another task,
     /// that still needs to be converted to use a real task id.
     Task(AbstractTaskId, IdentId, BranchSpec),
+    /// list of input values, from a branch glob graft
+    List(Vec<PartialRealInput>),
 }
 
 impl RealValueLike for PartialRealInput {
@@ -63,9 +98,27 @@ impl RealValueLike for PartialRealInput {
         Ok(Self::Task(task, ident, branch))
     }
 
+    fn list(items: Vec<Self>) -> Result<Self, Error> {
+        // flatten, in case a grafted config value is itself a glob graft:
+        let mut flattened = Vec::with_capacity(items.len());
+        for item in items {
+            match item {
+                Self::List(nested) => flattened.extend(nested),
+                item => flattened.push(item),
+            }
+        }
+        Ok(Self::List(flattened))
+    }
+
     fn update_branch(&mut self, branch: &BranchSpec) {
-        if let Self::Task(_, _, existing_branch) = self {
-            existing_branch.insert_all(branch);
+        match self {
+            Self::Task(_, _, existing_branch) => existing_branch.insert_all(branch),
+            Self::List(items) => {
+                for item in items {
+                    item.update_branch(branch);
+                }
+            }
+            Self::Literal(_) => (),
         }
     }
 
@@ -79,6 +132,7 @@ impl RealValueLike for PartialRealInput {
 
 /// A fully-realized output or param value.
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum RealOutputOrParam {
     /// literally-specified output or param value.
     Literal(LiteralId),
@@ -87,6 +141,15 @@ pub enum RealOutputOrParam {
     Interp(LiteralId, Vec<(IdentId, LiteralId)>),
 }
 
+impl AllocSize for RealOutputOrParam {
+    fn alloc_size(&self) -> usize {
+        match self {
+            Self::Literal(_) => 0,
+            Self::Interp(_, vars) => vars.alloc_size(),
+        }
+    }
+}
+
 impl RealValueLike for RealOutputOrParam {
     fn literal(lit_id: LiteralId) -> Result<Self, Error> {
         Ok(Self::Literal(lit_id))
@@ -113,10 +176,10 @@ pub struct BranchMasks<B> {
     pub rm: B,
 }
 
-impl<B: std::ops::BitOrAssign + Copy> BranchMasks<B> {
+impl<B: std::ops::BitOrAssign + Clone> BranchMasks<B> {
     /// union this set of masks with another set.
     pub fn or_eq(&mut self, other: &Self) {
-        self.add |= other.add;
-        self.rm |= other.rm;
+        self.add |= other.add.clone();
+        self.rm |= other.rm.clone();
     }
 }
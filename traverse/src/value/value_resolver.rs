@@ -1,8 +1,10 @@
 use anyhow::Result;
 
+use intern::GetStr;
 use util::Bitmask;
 use workflow::{
-    BaseValue, BranchSpec, DirectValue, IdentId, Recapper, Value, Workflow, NULL_IDENT,
+    BaseValue, BranchSpec, BranchpointId, DirectValue, IdentId, Recapper, Value, Workflow,
+    NULL_IDENT,
 };
 
 use super::{BranchMasks, Error, RealValueLike};
@@ -37,7 +39,8 @@ impl ValueResolver {
                         return Ok((real_val, masks));
                     }
                 }
-                Err(Error::BranchNotFound.into())
+                let arms = vals.iter().map(|(val_branch, _)| val_branch.clone()).collect();
+                Err(Recapper::new(Error::BranchNotFound(branch.clone(), arms)).into())
             }
         }
     }
@@ -65,7 +68,55 @@ impl ValueResolver {
                 }
                 Ok((real_val, masks))
             }
+            DirectValue::GlobGraft(v, graft_branch, globs) => {
+                let mut new_branch = branch.clone();
+                new_branch.insert_all(graft_branch);
+                let mut items = Vec::new();
+                let mut masks = BranchMasks::default();
+                self.resolve_glob::<T, B>(v, &mut new_branch, globs, wf, &mut items, &mut masks)?;
+                for (k, v) in graft_branch.iter().enumerate() {
+                    if *v != NULL_IDENT {
+                        masks.rm.set(k);
+                    }
+                }
+                // the value doesn't depend on globbed branchpoints, since it uses all of them:
+                let mut glob_mask = B::default();
+                for k in globs {
+                    glob_mask.set(usize::from(*k));
+                }
+                masks.add &= !glob_mask.clone();
+                masks.rm |= glob_mask;
+                Ok((T::list(items)?, masks))
+            }
+        }
+    }
+
+    /// Resolve `value` in every combination of the known branches of the `globs` branchpoints,
+    /// adding the results to `items`.
+    fn resolve_glob<T, B>(
+        &self,
+        value: &BaseValue,
+        branch: &mut BranchSpec,
+        globs: &[BranchpointId],
+        wf: &Workflow,
+        items: &mut Vec<T>,
+        masks: &mut BranchMasks<B>,
+    ) -> Result<()>
+    where
+        T: RealValueLike,
+        B: Bitmask,
+    {
+        let Some((k, rest)) = globs.split_first() else {
+            let (item, item_masks) = self.resolve_base::<T, B>(value, branch, wf)?;
+            masks.or_eq(&item_masks);
+            items.push(item);
+            return Ok(());
+        };
+        for v in wf.strings.baselines.defined(*k) {
+            branch.insert(*k, v);
+            self.resolve_glob(value, branch, rest, wf, items, masks)?;
         }
+        Ok(())
     }
 
     fn resolve_base<T, B>(
@@ -114,9 +165,11 @@ impl ValueResolver {
         T: RealValueLike,
         B: Bitmask,
     {
-        let val_id = wf
-            .get_config_value(ident)
-            .ok_or_else(|| Recapper::new(Error::UndefinedConfigValue(ident)))?;
+        let val_id = wf.get_config_value(ident).ok_or_else(|| {
+            let name = wf.strings.idents.get(ident).unwrap_or_default();
+            let closest = wf.closest_config_value(name).map(str::to_owned);
+            Recapper::new(Error::UndefinedConfigValue(ident, closest))
+        })?;
         let val = wf.get_value(val_id)?;
         self.resolve(val, branch, wf)
     }
@@ -0,0 +1,88 @@
+use std::mem::size_of;
+
+/// A type that knows how many bytes it has allocated on the heap,
+/// for reporting memory usage (see [`MemoryUsage`]).
+pub trait AllocSize {
+    /// Bytes allocated on the heap by `self`, including unused capacity,
+    /// but not including the size of `self` itself.
+    fn alloc_size(&self) -> usize;
+}
+
+/// Memory used by one of the data structures in a memory usage report.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryUsage {
+    /// what the data structure holds, e.g. "tasks".
+    pub name: &'static str,
+    /// number of items it holds.
+    pub len: usize,
+    /// bytes allocated on the heap for it.
+    pub bytes: usize,
+}
+
+impl MemoryUsage {
+    /// Memory used by `vec` and its items.
+    pub fn of<T: AllocSize>(name: &'static str, vec: &Vec<T>) -> Self {
+        Self {
+            name,
+            len: vec.len(),
+            bytes: vec.alloc_size(),
+        }
+    }
+}
+
+// types that don't allocate ////////////
+macro_rules! no_alloc {
+    ($($ty:ty),*) => {
+        $(
+            impl AllocSize for $ty {
+                #[inline]
+                fn alloc_size(&self) -> usize {
+                    0
+                }
+            }
+        )*
+    };
+}
+
+no_alloc!(u8, u16, u32, u64, u128, usize, bool);
+
+// containers ///////////////////////////
+impl AllocSize for String {
+    fn alloc_size(&self) -> usize {
+        self.capacity()
+    }
+}
+
+impl<T: AllocSize> AllocSize for Option<T> {
+    fn alloc_size(&self) -> usize {
+        self.as_ref().map_or(0, T::alloc_size)
+    }
+}
+
+impl<T: AllocSize> AllocSize for Vec<T> {
+    fn alloc_size(&self) -> usize {
+        self.capacity() * size_of::<T>() + self.iter().map(T::alloc_size).sum::<usize>()
+    }
+}
+
+impl<A: AllocSize, B: AllocSize> AllocSize for (A, B) {
+    fn alloc_size(&self) -> usize {
+        self.0.alloc_size() + self.1.alloc_size()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_alloc_size() {
+        let mut vec: Vec<Vec<u16>> = Vec::with_capacity(4);
+        vec.push(Vec::with_capacity(8));
+        vec.push(Vec::new());
+        assert_eq!(vec.alloc_size(), 4 * size_of::<Vec<u16>>() + 8 * 2);
+        let pair = (String::with_capacity(10), Some(Vec::<u8>::with_capacity(3)));
+        assert_eq!(pair.alloc_size(), 13);
+        assert_eq!(MemoryUsage::of("vecs", &vec).len, 2);
+    }
+}
@@ -24,15 +24,14 @@ const INDEX_MASKS_U64: [u64; 64] = [
 ];
 
 /// Trait for types that can be used as the underlying type of a bitmask.
-/// In practice, should only be implemented for unsigned int types.
+/// In practice, should only be implemented for unsigned int types and [`DynBitmask`].
 pub trait Bitmask:
     Sized
     + 'static
-    + Copy
+    + Clone
     + Default
     + std::fmt::Binary
     + cmp::PartialEq
-    + ops::Shr<usize, Output = Self>
     + ops::BitOrAssign<Self>
     + ops::Not<Output = Self>
     + ops::BitAnd<Self, Output = Self>
@@ -41,14 +40,8 @@ pub trait Bitmask:
     /// Number of bits contained in this type
     const BITS: usize;
 
-    /// Reference to the number one
-    const ONE: Self;
-
     /// return true if the i'th bit is set
-    #[inline]
-    fn get(&self, i: usize) -> bool {
-        (*self >> i) & Self::ONE == Self::ONE
-    }
+    fn get(&self, i: usize) -> bool;
 
     /// set the i'th bit to true
     // NB this needs to be defined on the types themselves,
@@ -58,7 +51,10 @@ pub trait Bitmask:
 
 impl Bitmask for u8 {
     const BITS: usize = u8::BITS as usize;
-    const ONE: Self = 1;
+    #[inline]
+    fn get(&self, i: usize) -> bool {
+        (*self >> i) & 1 == 1
+    }
     #[inline]
     fn set(&mut self, i: usize) {
         *self |= INDEX_MASKS_U8[i]
@@ -67,7 +63,10 @@ impl Bitmask for u8 {
 
 impl Bitmask for u16 {
     const BITS: usize = u16::BITS as usize;
-    const ONE: Self = 1;
+    #[inline]
+    fn get(&self, i: usize) -> bool {
+        (*self >> i) & 1 == 1
+    }
     #[inline]
     fn set(&mut self, i: usize) {
         *self |= INDEX_MASKS_U16[i]
@@ -76,7 +75,10 @@ impl Bitmask for u16 {
 
 impl Bitmask for u32 {
     const BITS: usize = u32::BITS as usize;
-    const ONE: Self = 1;
+    #[inline]
+    fn get(&self, i: usize) -> bool {
+        (*self >> i) & 1 == 1
+    }
     #[inline]
     fn set(&mut self, i: usize) {
         *self |= INDEX_MASKS_U32[i]
@@ -85,7 +87,10 @@ impl Bitmask for u32 {
 
 impl Bitmask for u64 {
     const BITS: usize = u64::BITS as usize;
-    const ONE: Self = 1;
+    #[inline]
+    fn get(&self, i: usize) -> bool {
+        (*self >> i) & 1 == 1
+    }
     #[inline]
     fn set(&mut self, i: usize) {
         *self |= INDEX_MASKS_U64[i]
@@ -94,7 +99,10 @@ impl Bitmask for u64 {
 
 impl Bitmask for u128 {
     const BITS: usize = u128::BITS as usize;
-    const ONE: Self = 1;
+    #[inline]
+    fn get(&self, i: usize) -> bool {
+        (*self >> i) & 1 == 1
+    }
     // didn't want to bother w/ an index mask for this one:
     #[inline]
     fn set(&mut self, i: usize) {
@@ -102,7 +110,115 @@ impl Bitmask for u128 {
     }
 }
 
+/// Heap-allocated bitmask with as many bits as needed,
+/// for workflows with more branchpoints than fit in a `u128`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct DynBitmask {
+    /// bits, starting with the least significant word.
+    words: Vec<u64>,
+    /// value of every word past the end of `words`: either 0 or all ones (after a `!`).
+    rest: u64,
+}
+
+impl DynBitmask {
+    #[inline]
+    fn word(&self, i: usize) -> u64 {
+        self.words.get(i).copied().unwrap_or(self.rest)
+    }
+
+    /// Drop trailing words that are the same as `rest`, so equal masks compare equal.
+    fn trim(&mut self) {
+        while self.words.last() == Some(&self.rest) {
+            self.words.pop();
+        }
+    }
+
+    /// Combine each word of this mask with the same word of `other`.
+    fn combine(&mut self, other: &Self, f: impl Fn(u64, u64) -> u64) {
+        let len = cmp::max(self.words.len(), other.words.len());
+        self.words.resize(len, self.rest);
+        for (i, word) in self.words.iter_mut().enumerate() {
+            *word = f(*word, other.word(i));
+        }
+        self.rest = f(self.rest, other.rest);
+        self.trim();
+    }
+}
+
+impl Bitmask for DynBitmask {
+    const BITS: usize = usize::MAX;
+    #[inline]
+    fn get(&self, i: usize) -> bool {
+        self.word(i / 64) & INDEX_MASKS_U64[i % 64] != 0
+    }
+    fn set(&mut self, i: usize) {
+        if self.get(i) {
+            return;
+        }
+        let word = i / 64;
+        if word >= self.words.len() {
+            self.words.resize(word + 1, self.rest);
+        }
+        self.words[word] |= INDEX_MASKS_U64[i % 64];
+        self.trim();
+    }
+}
+
+impl ops::BitOrAssign for DynBitmask {
+    fn bitor_assign(&mut self, other: Self) {
+        self.combine(&other, |a, b| a | b);
+    }
+}
+
+impl ops::BitAndAssign for DynBitmask {
+    fn bitand_assign(&mut self, other: Self) {
+        self.combine(&other, |a, b| a & b);
+    }
+}
+
+impl ops::BitAnd for DynBitmask {
+    type Output = Self;
+    fn bitand(mut self, other: Self) -> Self {
+        self &= other;
+        self
+    }
+}
+
+impl ops::Not for DynBitmask {
+    type Output = Self;
+    fn not(self) -> Self {
+        Self {
+            words: self.words.into_iter().map(|word| !word).collect(),
+            rest: !self.rest,
+        }
+    }
+}
+
+impl std::fmt::Binary for DynBitmask {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if f.alternate() {
+            f.write_str("0b")?;
+        }
+        // bits past the last word are all ones after a `!`:
+        if self.rest != 0 {
+            f.write_str("1...")?;
+        }
+        let mut words = self.words.iter().rev();
+        match words.next() {
+            Some(word) if self.rest == 0 => write!(f, "{word:b}")?,
+            Some(word) => write!(f, "{word:064b}")?,
+            None if self.rest == 0 => f.write_str("0")?,
+            None => (),
+        }
+        for word in words {
+            write!(f, "{word:064b}")?;
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison, clippy::needless_range_loop)]
 mod test {
     use super::*;
     #[test]
@@ -122,6 +238,10 @@ mod test {
         let mut mask = 0u128;
         mask.set(1);
         assert_eq!(0b10, mask);
+        let mut mask = DynBitmask::default();
+        mask.set(1);
+        mask.set(200);
+        assert_eq!(vec![0b10, 0, 0, 1 << 8], mask.words);
     }
     #[test]
     fn test_get() {
@@ -140,6 +260,40 @@ mod test {
         let mask = 0b100u128;
         assert_eq!(mask.get(0), false);
         assert_eq!(mask.get(2), true);
+        let mut mask = DynBitmask::default();
+        mask.set(130);
+        assert!(!mask.get(2));
+        assert!(mask.get(130));
+        assert!(!mask.get(1000));
+    }
+    #[test]
+    fn test_dyn_ops() {
+        let mut a = DynBitmask::default();
+        a.set(1);
+        a.set(129);
+        let mut b = DynBitmask::default();
+        b.set(129);
+        b.set(300);
+
+        let mut or = a.clone();
+        or |= b.clone();
+        assert!(or.get(1) && or.get(129) && or.get(300));
+
+        let and = a.clone() & b.clone();
+        assert!(!and.get(1) && and.get(129) && !and.get(300));
+
+        // bits past the end of `b` are set in `!b`, so they're kept in `a`:
+        let mut not = DynBitmask::default();
+        not.set(500);
+        not &= !b;
+        assert!(not.get(500));
+        assert!(!not.get(129));
+
+        // masks compare equal however they were built:
+        let mut c = a.clone();
+        c &= !a.clone();
+        assert_eq!(DynBitmask::default(), c);
+        assert_eq!(format!("{:#b}", and), format!("0b1{}", "0".repeat(129)));
     }
     #[test]
     fn test_mask_lookups() {
@@ -0,0 +1,117 @@
+use std::fmt;
+use std::str::FromStr;
+
+use sha2::{Digest as _, Sha256};
+
+const DIGEST_LEN: usize = 32;
+
+/// A stable 256-bit content digest (SHA-256), suitable for cache keys that
+/// must be consistent between runs and machines, and for checking downloads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Digest(pub [u8; DIGEST_LEN]);
+
+impl fmt::Display for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Error returned when parsing an invalid digest string.
+#[derive(thiserror::Error, Debug)]
+#[error("Invalid digest \"{0}\" (should be 64 hex digits)")]
+pub struct DigestParseError(pub String);
+
+impl FromStr for Digest {
+    type Err = DigestParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || DigestParseError(s.to_owned());
+        if s.len() != DIGEST_LEN * 2 || !s.is_ascii() {
+            return Err(err());
+        }
+        let mut bytes = [0; DIGEST_LEN];
+        for (byte, hex) in bytes.iter_mut().zip(s.as_bytes().chunks(2)) {
+            // hex is ascii, so this can't fail:
+            let hex = std::str::from_utf8(hex).map_err(|_| err())?;
+            *byte = u8::from_str_radix(hex, 16).map_err(|_| err())?;
+        }
+        Ok(Self(bytes))
+    }
+}
+
+/// Digests are serialized as their hex strings.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Digest {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Digest {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Incrementally computes a [`Digest`].
+#[derive(Debug, Clone, Default)]
+pub struct Digester {
+    state: Sha256,
+}
+
+impl Digester {
+    /// Add raw bytes to the digest.
+    pub fn update(&mut self, bytes: &[u8]) {
+        self.state.update(bytes);
+    }
+
+    /// Add a length-prefixed str to the digest, so that e.g.
+    /// ("ab", "c") and ("a", "bc") produce different digests.
+    pub fn update_str(&mut self, s: &str) {
+        self.update(&(s.len() as u64).to_le_bytes());
+        self.update(s.as_bytes());
+    }
+
+    /// Get the digest of everything added so far.
+    pub fn finish(&self) -> Digest {
+        Digest(self.state.clone().finalize().into())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Digest, Digester};
+
+    #[test]
+    fn test_digest() {
+        let empty = Digester::default().finish();
+        assert_eq!(
+            empty.to_string(),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+
+        let mut d = Digester::default();
+        d.update(b"abc");
+        assert_eq!(
+            d.finish().to_string(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+
+        let mut d1 = Digester::default();
+        d1.update_str("ab");
+        d1.update_str("c");
+        let mut d2 = Digester::default();
+        d2.update_str("a");
+        d2.update_str("bc");
+        assert_ne!(d1.finish(), d2.finish());
+
+        let parsed: Digest = d1.finish().to_string().parse().unwrap();
+        assert_eq!(parsed, d1.finish());
+        assert!("xyz".parse::<Digest>().is_err());
+        assert!("g".repeat(64).parse::<Digest>().is_err());
+    }
+}
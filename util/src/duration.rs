@@ -0,0 +1,84 @@
+use std::time::Duration;
+
+/// Error returned when a duration string can't be parsed.
+#[derive(thiserror::Error, Debug)]
+#[error("Invalid duration \"{0}\" (should be a number followed by s, m, h, d, or w, e.g. \"30d\")")]
+pub struct DurationParseError(pub String);
+
+/// Parse a human-readable duration like "90s", "45m", "12h", "30d", or "2w".
+/// A bare number is a number of seconds.
+pub fn parse_duration(s: &str) -> Result<Duration, DurationParseError> {
+    let err = || DurationParseError(s.to_owned());
+    let trimmed = s.trim();
+
+    let (num, unit_secs) = match trimmed.char_indices().last() {
+        Some((i, c)) if c.is_ascii_alphabetic() => {
+            let unit_secs = match c.to_ascii_lowercase() {
+                's' => 1,
+                'm' => 60,
+                'h' => 60 * 60,
+                'd' => 24 * 60 * 60,
+                'w' => 7 * 24 * 60 * 60,
+                _ => return Err(err()),
+            };
+            (&trimmed[..i], unit_secs)
+        }
+        _ => (trimmed, 1),
+    };
+
+    let num: f64 = num.trim().parse().map_err(|_| err())?;
+    if !num.is_finite() || num < 0.0 {
+        return Err(err());
+    }
+    Ok(Duration::from_secs_f64(num * unit_secs as f64))
+}
+
+/// Format a duration for humans, e.g. "0.25s", "42s", "3m05s", "2h00m".
+pub fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    if secs < 10 {
+        format!("{:.2}s", d.as_secs_f64())
+    } else if secs < 60 {
+        format!("{secs}s")
+    } else if secs < 60 * 60 {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    } else {
+        format!("{}h{:02}m", secs / 3600, secs / 60 % 60)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("90s").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("45m").unwrap(), Duration::from_secs(45 * 60));
+        assert_eq!(
+            parse_duration("1.5h").unwrap(),
+            Duration::from_secs(90 * 60)
+        );
+        assert_eq!(
+            parse_duration("30d").unwrap(),
+            Duration::from_secs(30 * 86400)
+        );
+        assert_eq!(
+            parse_duration("2W").unwrap(),
+            Duration::from_secs(14 * 86400)
+        );
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("d").is_err());
+        assert!(parse_duration("10y").is_err());
+        assert!(parse_duration("-1d").is_err());
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::from_millis(250)), "0.25s");
+        assert_eq!(format_duration(Duration::from_secs(42)), "42s");
+        assert_eq!(format_duration(Duration::from_secs(185)), "3m05s");
+        assert_eq!(format_duration(Duration::from_secs(7200)), "2h00m");
+    }
+}
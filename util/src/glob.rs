@@ -0,0 +1,45 @@
+/// Match `s` against a shell-style glob `pattern`, where `*` matches any
+/// sequence of characters and `?` matches any single character.
+pub fn glob_match(pattern: &str, s: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let s: Vec<char> = s.chars().collect();
+
+    // classic backtracking matcher: remember the last '*' we saw,
+    // and on a mismatch, let it swallow one more character.
+    let (mut p, mut i) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while i < s.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == s[i]) {
+            p += 1;
+            i += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, i));
+            p += 1;
+        } else if let Some((star_p, star_i)) = star {
+            p = star_p + 1;
+            i = star_i + 1;
+            star = Some((star_p, star_i + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod test {
+    use super::glob_match;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("Profile.*", "Profile.debug"));
+        assert!(glob_match("*.debug", "Profile.debug"));
+        assert!(glob_match("P?ofile.d*g", "Profile.debug"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("a*b*c", "aXXbYYc"));
+        assert!(!glob_match("Profile.*", "Os.mac"));
+        assert!(!glob_match("a*b*c", "aXXbYY"));
+        assert!(!glob_match("", "a"));
+        assert!(!glob_match("?", ""));
+    }
+}
@@ -1,5 +1,7 @@
 use std::marker::PhantomData;
 
+use crate::AllocSize;
+
 /// Vec wrapper that uses typed indexes.
 #[derive(Debug, Default, Hash, PartialEq, Eq, Clone)]
 pub struct IdVec<K, V> {
@@ -99,3 +101,23 @@ impl<K: Into<usize>, V: Default + Clone> IdVec<K, V> {
         self.vec[k] = v;
     }
 }
+
+impl<K, V: AllocSize> AllocSize for IdVec<K, V> {
+    fn alloc_size(&self) -> usize {
+        self.vec.alloc_size()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<K, V: serde::Serialize> serde::Serialize for IdVec<K, V> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.vec.serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, K, V: serde::Deserialize<'de>> serde::Deserialize<'de> for IdVec<K, V> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Vec::deserialize(deserializer).map(Self::new)
+    }
+}
@@ -1,5 +1,5 @@
 mod bitmask;
-pub use bitmask::Bitmask;
+pub use bitmask::{Bitmask, DynBitmask};
 
 mod id_vec;
 pub use id_vec::IdVec;
@@ -7,6 +7,31 @@ pub use id_vec::IdVec;
 mod timer;
 pub use timer::Timer;
 
+mod alloc_size;
+pub use alloc_size::{AllocSize, MemoryUsage};
+
+mod digest;
+pub use digest::{Digest, DigestParseError, Digester};
+
+mod duration;
+pub use duration::{format_duration, parse_duration, DurationParseError};
+
+mod glob;
+pub use glob::glob_match;
+
+/// Serialize key-value pairs as a map, in order.
+#[cfg(feature = "serde")]
+pub mod ordered_map;
+
+mod timestamp;
+pub use timestamp::{format_rfc3339, format_utc_timestamp};
+
+mod size;
+pub use size::{format_size, parse_size, SizeParseError};
+
+mod suggest;
+pub use suggest::{closest_match, did_you_mean, edit_distance};
+
 #[derive(thiserror::Error, Debug)]
 #[error("Filesystem path is not valid UTF-8")]
 pub struct PathEncodingError;
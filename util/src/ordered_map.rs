@@ -0,0 +1,64 @@
+//! For `#[serde(with = "util::ordered_map")]` on a `Vec` of key-value pairs,
+//! so that they're written as a map with the keys in the same order they're read in.
+
+use std::fmt;
+use std::marker::PhantomData;
+
+use serde::de::{Deserialize, Deserializer, MapAccess, Visitor};
+use serde::ser::{Serialize, Serializer};
+
+pub fn serialize<K, V, S>(pairs: &[(K, V)], serializer: S) -> Result<S::Ok, S::Error>
+where
+    K: Serialize,
+    V: Serialize,
+    S: Serializer,
+{
+    serializer.collect_map(pairs.iter().map(|(k, v)| (k, v)))
+}
+
+pub fn deserialize<'de, K, V, D>(deserializer: D) -> Result<Vec<(K, V)>, D::Error>
+where
+    K: Deserialize<'de>,
+    V: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_map(PairsVisitor(PhantomData))
+}
+
+/// Collects the entries of a map in order.
+struct PairsVisitor<K, V>(PhantomData<(K, V)>);
+
+impl<'de, K: Deserialize<'de>, V: Deserialize<'de>> Visitor<'de> for PairsVisitor<K, V> {
+    type Value = Vec<(K, V)>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a map")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut pairs = Vec::with_capacity(map.size_hint().unwrap_or_default());
+        while let Some(pair) = map.next_entry()? {
+            pairs.push(pair);
+        }
+        Ok(pairs)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+    struct Vars {
+        #[serde(with = "super")]
+        vars: Vec<(String, u64)>,
+    }
+
+    #[test]
+    fn test_ordered_map() {
+        let vars = Vars {
+            vars: vec![("b".to_owned(), 1), ("a".to_owned(), u64::MAX)],
+        };
+        let json = serde_json::to_string(&vars).unwrap();
+        assert_eq!(json, r#"{"vars":{"b":1,"a":18446744073709551615}}"#);
+        assert_eq!(serde_json::from_str::<Vars>(&json).unwrap(), vars);
+    }
+}
@@ -0,0 +1,75 @@
+/// Error returned when a size string can't be parsed.
+#[derive(thiserror::Error, Debug)]
+#[error("Invalid size \"{0}\" (should be a number of bytes, optionally followed by K, M, G, or T)")]
+pub struct SizeParseError(pub String);
+
+/// Parse a human-readable size like "512", "10K", "1.5G" into a number of bytes.
+/// Suffixes are binary (i.e. 1K = 1024 bytes), and an optional trailing "B" is allowed.
+pub fn parse_size(s: &str) -> Result<u64, SizeParseError> {
+    let err = || SizeParseError(s.to_owned());
+    let trimmed = s.trim();
+    let trimmed = trimmed.strip_suffix(['B', 'b']).unwrap_or(trimmed);
+
+    let (num, multiplier) = match trimmed.char_indices().last() {
+        Some((i, c)) if c.is_ascii_alphabetic() => {
+            let shift = match c.to_ascii_uppercase() {
+                'K' => 10,
+                'M' => 20,
+                'G' => 30,
+                'T' => 40,
+                _ => return Err(err()),
+            };
+            (&trimmed[..i], 1u64 << shift)
+        }
+        _ => (trimmed, 1),
+    };
+
+    let num: f64 = num.trim().parse().map_err(|_| err())?;
+    if !num.is_finite() || num < 0.0 {
+        return Err(err());
+    }
+    Ok((num * multiplier as f64) as u64)
+}
+
+/// Format a number of bytes for humans, e.g. "512 B", "1.5 KiB", "10.0 GiB".
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{size:.1} {}", UNITS[unit])
+}
+
+#[cfg(test)]
+mod test {
+    use super::{format_size, parse_size};
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("512").unwrap(), 512);
+        assert_eq!(parse_size("10K").unwrap(), 10 * 1024);
+        assert_eq!(parse_size("10kb").unwrap(), 10 * 1024);
+        assert_eq!(parse_size("1.5G").unwrap(), 3 * 512 * 1024 * 1024);
+        assert_eq!(parse_size("2T").unwrap(), 2 << 40);
+        assert_eq!(parse_size("100B").unwrap(), 100);
+        assert!(parse_size("").is_err());
+        assert!(parse_size("10X").is_err());
+        assert!(parse_size("-1M").is_err());
+        assert!(parse_size("M").is_err());
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(0), "0 B");
+        assert_eq!(format_size(1023), "1023 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(10 << 30), "10.0 GiB");
+        assert_eq!(format_size(2048 << 40), "2048.0 TiB");
+    }
+}
@@ -0,0 +1,71 @@
+/// Number of single-character insertions, deletions, and substitutions
+/// needed to turn `a` into `b` (Levenshtein distance).
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    // distances from the prefix of `a` seen so far to each prefix of `b`:
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diag = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let sub = diag + usize::from(ca != *cb);
+            diag = row[j + 1];
+            row[j + 1] = sub.min(row[j] + 1).min(diag + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// The candidate closest to `name`, if any is close enough to be a likely typo
+/// (at most one edit per three characters, or a difference only in case).
+pub fn closest_match<'a>(
+    name: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> Option<&'a str> {
+    let max_distance = (name.chars().count() / 3).max(1);
+    candidates
+        .into_iter()
+        .filter(|c| *c != name)
+        .map(|c| {
+            let distance = if c.eq_ignore_ascii_case(name) {
+                0
+            } else {
+                edit_distance(name, c)
+            };
+            (distance, c)
+        })
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, c)| (*distance, *c))
+        .map(|(_, c)| c)
+}
+
+/// " (did you mean `suggestion`?)", or nothing if there's no suggestion.
+pub fn did_you_mean<T: AsRef<str>>(suggestion: Option<T>) -> String {
+    suggestion.map_or_else(String::new, |s| {
+        format!(" (did you mean `{}`?)", s.as_ref())
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_suggest() {
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("pkgbuild", "pkgbuild"), 0);
+
+        let names = ["pkgbuild", "productbuild", "cargo_build"];
+        assert_eq!(closest_match("pkgbiuld", names), Some("pkgbuild"));
+        assert_eq!(closest_match("PKGBUILD", names), Some("pkgbuild"));
+        assert_eq!(closest_match("cargobuild", names), Some("cargo_build"));
+        assert_eq!(closest_match("test", names), None);
+        assert_eq!(closest_match("pkgbuild", names), None);
+        assert_eq!(
+            did_you_mean(Some("pkgbuild")),
+            " (did you mean `pkgbuild`?)"
+        );
+        assert_eq!(did_you_mean(None::<&str>), "");
+    }
+}
@@ -1,26 +1,162 @@
-use std::time::{SystemTime, SystemTimeError};
+use std::fmt;
+use std::time::{Duration, Instant};
 
-/// Utility for keeping track of the time it took to perform some operation.
+use crate::format_duration;
+
+/// Utility for keeping track of how long each phase of an operation took,
+/// as nested, named spans (e.g. "parse", then "load" within it), to summarize at the end.
+/// Starting a span with the same name as an earlier one with the same parent adds to it,
+/// so phases that happen more than once are totaled.
+#[derive(Debug, Default)]
 pub struct Timer {
-    start_time: SystemTime,
+    spans: Vec<Span>,
+    /// indices of the spans that have been started but not stopped, innermost last.
+    open: Vec<usize>,
+}
+
+#[derive(Debug)]
+struct Span {
+    name: String,
+    parent: Option<usize>,
+    /// total time of every time the span was started and stopped.
+    elapsed: Duration,
+    /// number of times the span was started.
+    count: usize,
+    /// when the span was started, if it's open.
+    started: Option<Instant>,
 }
 
 impl Timer {
-    /// Create a new `Timer`.
-    pub fn now() -> Self {
-        Self {
-            start_time: SystemTime::now(),
-        }
+    /// Start a span called `name`, within the innermost span that's still open.
+    pub fn start(&mut self, name: &str) {
+        let idx = self.find_or_add(name);
+        let span = &mut self.spans[idx];
+        span.count += 1;
+        span.started = Some(Instant::now());
+        self.open.push(idx);
     }
 
-    /// Reset internal timer to now.
-    pub fn reset(&mut self) {
-        self.start_time = SystemTime::now();
+    /// Stop the innermost open span, returning how long it took this time.
+    pub fn stop(&mut self) -> Option<Duration> {
+        let span = &mut self.spans[self.open.pop()?];
+        let elapsed = span.started.take()?.elapsed();
+        span.elapsed += elapsed;
+        Some(elapsed)
     }
 
-    /// Print a message with the elapsed time since the timer was last reset.
-    pub fn print_elapsed(&self, task: &str) -> Result<(), SystemTimeError> {
-        eprintln!("{} took {:?}", task, self.start_time.elapsed()?);
+    /// Add a span called `name` that took `elapsed`, within the innermost open span,
+    /// for phases that were timed elsewhere.
+    pub fn add(&mut self, name: &str, elapsed: Duration) {
+        let idx = self.find_or_add(name);
+        let span = &mut self.spans[idx];
+        span.count += 1;
+        span.elapsed += elapsed;
+    }
+
+    /// true if no span has been started.
+    pub fn is_empty(&self) -> bool {
+        self.spans.is_empty()
+    }
+
+    fn find_or_add(&mut self, name: &str) -> usize {
+        let parent = self.open.last().copied();
+        let existing = self.spans.iter().position(|s| s.parent == parent && s.name == name);
+        existing.unwrap_or_else(|| {
+            self.spans.push(Span {
+                name: name.to_owned(),
+                parent,
+                elapsed: Duration::ZERO,
+                count: 0,
+                started: None,
+            });
+            self.spans.len() - 1
+        })
+    }
+
+    /// Time of span `idx` so far, including the current run of an open span.
+    fn elapsed(&self, idx: usize) -> Duration {
+        let span = &self.spans[idx];
+        span.elapsed + span.started.map_or(Duration::ZERO, |started| started.elapsed())
+    }
+
+    /// Write the span at `idx` and its descendants, in the order they were first started.
+    fn fmt_span(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        idx: usize,
+        depth: usize,
+        total: Duration,
+    ) -> fmt::Result {
+        let span = &self.spans[idx];
+        let elapsed = self.elapsed(idx);
+        let percent = if total.is_zero() {
+            0.0
+        } else {
+            elapsed.as_secs_f64() / total.as_secs_f64() * 100.0
+        };
+        let name = format!("{:indent$}{}", "", span.name, indent = depth * 2);
+        write!(
+            f,
+            "\n{name:<40} {:>8} {percent:>5.1}%",
+            format_duration(elapsed)
+        )?;
+        if span.count > 1 {
+            write!(f, " ({} times)", span.count)?;
+        }
+        for child in (0..self.spans.len()).filter(|i| self.spans[*i].parent == Some(idx)) {
+            self.fmt_span(f, child, depth + 1, total)?;
+        }
+        Ok(())
+    }
+}
+
+/// A table of every span, indented under its parent, with its time
+/// and its percentage of the time of all the outermost spans.
+impl fmt::Display for Timer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let roots: Vec<usize> =
+            (0..self.spans.len()).filter(|i| self.spans[*i].parent.is_none()).collect();
+        let total = roots.iter().map(|i| self.elapsed(*i)).sum();
+        write!(f, "{:<40} {:>8} {:>6}", "phase", "time", "share")?;
+        for idx in roots {
+            self.fmt_span(f, idx, 0, total)?;
+        }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_nested_spans() {
+        let mut timer = Timer::default();
+        assert!(timer.is_empty());
+        timer.start("parse");
+        timer.start("load");
+        assert!(timer.stop().is_some());
+        timer.stop();
+        timer.start("traverse");
+        timer.add("bfs", Duration::from_millis(1500));
+        timer.add("bfs", Duration::from_millis(500));
+        timer.stop();
+        timer.start("parse");
+        timer.stop();
+        assert_eq!(timer.stop(), None);
+
+        assert_eq!(timer.spans.len(), 4);
+        assert_eq!(timer.spans[0].count, 2);
+        assert_eq!(timer.spans[1].parent, Some(0));
+        assert_eq!(timer.spans[3].elapsed, Duration::from_secs(2));
+
+        let table = timer.to_string();
+        let names: Vec<_> = table.lines().map(|line| line[..40].trim_end()).collect();
+        assert_eq!(names, ["phase", "parse", "  load", "traverse", "  bfs"]);
+        assert!(
+            table.lines().nth(1).unwrap().ends_with("(2 times)"),
+            "{table}"
+        );
+        assert!(table.lines().nth(4).unwrap().contains("2.00s"), "{table}");
+    }
+}
@@ -1,10 +1,15 @@
 use crate::{BranchpointId, IdentId};
 
 /// Keeps track of which branch is baseline for each defined branch
-/// in the workflow.
+/// in the workflow, along with all known branches of each branchpoint.
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct BaselineBranches {
     vec: Vec<IdentId>,
+    /// all known branches of each branchpoint, in the order they were added:
+    values: Vec<Vec<IdentId>>,
+    /// whether each of `values` is defined in the config (rather than only in branchpoints.txt):
+    defined: Vec<Vec<bool>>,
 }
 
 impl BaselineBranches {
@@ -12,11 +17,24 @@ impl BaselineBranches {
     pub fn with_capacity(cap: usize) -> Self {
         Self {
             vec: Vec::with_capacity(cap),
+            values: Vec::with_capacity(cap),
+            defined: Vec::with_capacity(cap),
         }
     }
 
-    /// Set the given `IdentId` as the baseline for the given branchpoint.
+    /// Set the given `IdentId` as the baseline for the given branchpoint,
+    /// unless it already has one, and record it as a branch defined in the config.
     pub fn add(&mut self, k: BranchpointId, v: IdentId) {
+        self.add_known(k, v, true);
+    }
+
+    /// Like `add`, but for a branch loaded from branchpoints.txt,
+    /// which isn't necessarily still defined in the config.
+    pub fn preload(&mut self, k: BranchpointId, v: IdentId) {
+        self.add_known(k, v, false);
+    }
+
+    fn add_known(&mut self, k: BranchpointId, v: IdentId, defined: bool) {
         let k: usize = k.into();
         let len = self.vec.len();
         if k >= len {
@@ -27,6 +45,37 @@ impl BaselineBranches {
         if *existing_v == crate::NULL_IDENT {
             *existing_v = v;
         }
+        if k >= self.values.len() {
+            self.values.resize_with(k + 1, Vec::new);
+            self.defined.resize_with(k + 1, Vec::new);
+        }
+        match self.values[k].iter().position(|existing| *existing == v) {
+            Some(i) => self.defined[k][i] |= defined,
+            None => {
+                self.values[k].push(v);
+                self.defined[k].push(defined);
+            }
+        }
+    }
+
+    /// All known branches of the given branchpoint, starting with the defined baseline
+    /// (which is the one written to branchpoints.txt, even while a plan overrides it).
+    pub fn values(&self, k: BranchpointId) -> &[IdentId] {
+        let k: usize = k.into();
+        self.values.get(k).map_or(&[], Vec::as_slice)
+    }
+
+    /// The branches of the given branchpoint that are defined in the config, in the same
+    /// order as `values` (i.e. without any that are only left over in branchpoints.txt).
+    pub fn defined(&self, k: BranchpointId) -> impl Iterator<Item = IdentId> + '_ {
+        let k: usize = k.into();
+        let defined = self.defined.get(k).map_or(&[][..], Vec::as_slice);
+        self.values(k.into()).iter().zip(defined).filter(|(_, d)| **d).map(|(v, _)| *v)
+    }
+
+    /// True if `v` is a branch of `k` that's defined in the config.
+    pub fn is_defined(&self, k: BranchpointId, v: IdentId) -> bool {
+        self.defined(k).any(|defined| defined == v)
     }
 
     /// Get the `IdentId` of baseline branch for the given branchpoint.
@@ -35,6 +84,29 @@ impl BaselineBranches {
         self.vec[k]
     }
 
+    /// The baseline of the given branchpoint before any plan overrides it, i.e. the one
+    /// in branchpoints.txt. Realization dir names leave out this branch, not the override,
+    /// so that each dir stands for the same branch no matter which plan is run.
+    pub fn original(&self, k: BranchpointId) -> IdentId {
+        self.values(k).first().copied().unwrap_or_else(|| self.get(k))
+    }
+
+    /// Use the branches in `overrides` as baseline instead of the defined ones,
+    /// restoring the defined baseline of every other branchpoint.
+    pub fn set_overrides(&mut self, overrides: &[(BranchpointId, IdentId)]) {
+        for (baseline, values) in self.vec.iter_mut().zip(&self.values) {
+            if let Some(v) = values.first() {
+                *baseline = *v;
+            }
+        }
+        for (k, v) in overrides {
+            let k: usize = (*k).into();
+            if let Some(baseline) = self.vec.get_mut(k) {
+                *baseline = *v;
+            }
+        }
+    }
+
     /// Iterate through baseline branch values.
     // NB the first part of the tuple is equivalent to a BranchpointId.
     pub fn iter(&self) -> impl Iterator<Item = (usize, &IdentId)> {
@@ -1,4 +1,4 @@
-use util::{Bitmask, IdVec};
+use util::{AllocSize, Bitmask, IdVec};
 
 use crate::{BranchpointId, IdentId, NULL_IDENT};
 
@@ -76,6 +76,15 @@ impl BranchSpec {
         }
     }
 
+    /// Iterate through the specified (non-baseline) branchpoints and their branches, in order.
+    pub fn specified(&self) -> impl Iterator<Item = (BranchpointId, IdentId)> + '_ {
+        self.branches
+            .iter()
+            .enumerate()
+            .filter(|(_, v)| **v != NULL_IDENT)
+            .map(|(k, v)| (BranchpointId::from(k), *v))
+    }
+
     /// Iterate through branchpoints
     pub fn iter(&self) -> impl Iterator<Item = &IdentId> {
         self.branches.iter()
@@ -134,6 +143,29 @@ impl BranchSpec {
     }
 }
 
+impl AllocSize for BranchSpec {
+    fn alloc_size(&self) -> usize {
+        self.branches.alloc_size()
+    }
+}
+
+// serialized as the branch of each branchpoint, with 0 (NULL_IDENT) for unspecified ones:
+#[cfg(feature = "serde")]
+impl serde::Serialize for BranchSpec {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.branches.serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for BranchSpec {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Self {
+            branches: IdVec::deserialize(deserializer)?,
+        })
+    }
+}
+
 // Convert to branch mask
 impl BranchSpec {
     pub fn as_mask<T>(&self) -> Result<T, Error>
@@ -164,3 +196,20 @@ fn none_if_baseline(id: IdentId) -> Option<IdentId> {
         Some(id)
     }
 }
+
+#[cfg(all(test, feature = "serde"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_serde_round_trip() {
+        let mut branch = BranchSpec::default();
+        branch.insert(BranchpointId::from(2usize), IdentId::from(5usize));
+        branch.insert(BranchpointId::from(0usize), IdentId::from(3usize));
+        let json = serde_json::to_string(&branch).unwrap();
+        assert_eq!(json, "[3,0,5]");
+        let decoded: BranchSpec = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, branch);
+        assert!(decoded.is_unspecified(BranchpointId::from(1usize)));
+    }
+}
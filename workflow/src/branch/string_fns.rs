@@ -42,7 +42,10 @@ impl StringMaker<BranchSpec> for CompactBranchStrings {
 
 /// Branch string with all branches specified, even if they are baseline.
 /// If there are no branches at all, uses "Baseline.baseline".
+/// NB `buf` may already contain other strings (see `StringCache`), so we only look at
+/// what we've appended to it.
 fn make_full_string(branch: &BranchSpec, wf: &WorkflowStrings, buf: &mut String) -> Result<()> {
+    let start = buf.len();
     let mut first = true;
     for (k, _) in wf.baselines.iter() {
         if k >= branch.len() {
@@ -58,27 +61,30 @@ fn make_full_string(branch: &BranchSpec, wf: &WorkflowStrings, buf: &mut String)
             push_branch_pair(k, v, wf, buf)?;
         }
     }
-    if buf.is_empty() {
+    if buf.len() == start {
         buf.push_str(BASELINE_STR);
     }
     Ok(())
 }
 
-/// Branch string with only non-baseline branches specified.
+/// Branch string with only non-baseline branches specified
+/// (relative to the original baseline, not a plan's override).
 /// If there are no branches, or if any branch is baseline,
 /// Starts with "Baseline.baseline".
 /// These strings will always stay valid between runs, as long
 /// as the branch ordering doesn't change (specified in branchpoints.txt).
 fn make_compact_string(branch: &BranchSpec, wf: &WorkflowStrings, buf: &mut String) -> Result<()> {
+    let start = buf.len();
     let mut first = true;
     let mut needs_baseline = false;
-    for (k, baseline_v) in wf.baselines.iter() {
+    for (k, _) in wf.baselines.iter() {
         if k >= branch.len() {
             break;
         }
         let k: BranchpointId = k.into();
         if let Some(v) = branch.get_specified(k) {
-            if v == *baseline_v {
+            // a plan's baseline override still gets written out:
+            if v == wf.baselines.original(k) {
                 needs_baseline = true;
             } else {
                 if first {
@@ -91,10 +97,10 @@ fn make_compact_string(branch: &BranchSpec, wf: &WorkflowStrings, buf: &mut Stri
         }
     }
 
-    if buf.is_empty() {
-        buf.insert_str(0, BASELINE_STR);
+    if buf.len() == start {
+        buf.push_str(BASELINE_STR);
     } else if needs_baseline {
-        buf.insert_str(0, BASELINE_STR_PLUS);
+        buf.insert_str(start, BASELINE_STR_PLUS);
     }
 
     Ok(())
@@ -126,10 +132,10 @@ pub fn parse_compact_branch_str(wf: &mut Workflow, s: &str) -> Result<BranchSpec
             }
         }
     }
-    for (k, v) in wf.strings.baselines.iter() {
+    for (k, _) in wf.strings.baselines.iter() {
         let id: BranchpointId = k.into();
         if branch.is_unspecified(id) {
-            branch.insert(id, *v);
+            branch.insert(id, wf.strings.baselines.original(id));
         }
     }
     Ok(branch)
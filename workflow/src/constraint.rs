@@ -0,0 +1,73 @@
+use anyhow::Result;
+
+use intern::GetStr;
+use syntax::ast;
+
+use crate::{BranchSpec, BranchpointId, IdentId, WorkflowStrings, BRANCH_KV_DELIM};
+
+/// Whether the branch of a [`Constraint`] needs, or rules out, the other branch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ConstraintKind {
+    Requires,
+    Excludes,
+}
+
+/// A restriction on which branches can be combined, defined in a config file
+/// (e.g. `constraint Os.windows requires Arch.x64`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Constraint {
+    pub branchpoint: BranchpointId,
+    pub branch: IdentId,
+    pub kind: ConstraintKind,
+    pub other_branchpoint: BranchpointId,
+    pub other_branch: IdentId,
+}
+
+impl Constraint {
+    /// Create a Constraint from its ast representation.
+    pub fn create(strings: &mut WorkflowStrings, constraint: &ast::Constraint) -> Result<Self> {
+        let ((k, v), (other_k, other_v)) = (constraint.branch, constraint.other);
+        let branchpoint = strings.add_branchpoint(k)?;
+        let other_branchpoint = strings.add_branchpoint(other_k)?;
+        Ok(Self {
+            branchpoint,
+            branch: strings.add_branch(branchpoint, v)?,
+            kind: match constraint.kind {
+                ast::ConstraintKind::Requires => ConstraintKind::Requires,
+                ast::ConstraintKind::Excludes => ConstraintKind::Excludes,
+            },
+            other_branchpoint,
+            other_branch: strings.add_branch(other_branchpoint, other_v)?,
+        })
+    }
+
+    /// true if `branch` combines this constraint's branch with one it doesn't allow.
+    /// Unspecified branchpoints never break a constraint.
+    pub fn is_broken_by(&self, branch: &BranchSpec) -> bool {
+        if branch.get_specified(self.branchpoint) != Some(self.branch) {
+            return false;
+        }
+        match (branch.get_specified(self.other_branchpoint), self.kind) {
+            (Some(other), ConstraintKind::Requires) => other != self.other_branch,
+            (Some(other), ConstraintKind::Excludes) => other == self.other_branch,
+            (None, _) => false,
+        }
+    }
+
+    /// The constraint as written in the config file, e.g. "Os.windows requires Arch.x64".
+    pub fn describe(&self, strings: &WorkflowStrings) -> Result<String> {
+        let kind = match self.kind {
+            ConstraintKind::Requires => "requires",
+            ConstraintKind::Excludes => "excludes",
+        };
+        Ok(format!(
+            "{}{BRANCH_KV_DELIM}{} {kind} {}{BRANCH_KV_DELIM}{}",
+            strings.branchpoints.get(self.branchpoint)?,
+            strings.idents.get(self.branch)?,
+            strings.branchpoints.get(self.other_branchpoint)?,
+            strings.idents.get(self.other_branch)?,
+        ))
+    }
+}
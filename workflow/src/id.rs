@@ -36,17 +36,40 @@ macro_rules! id {
                 Self(val)
             }
         }
+
+        impl util::AllocSize for $name {
+            #[inline]
+            fn alloc_size(&self) -> usize {
+                0
+            }
+        }
+
+        #[cfg(feature = "serde")]
+        impl serde::Serialize for $name {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                self.0.serialize(serializer)
+            }
+        }
+
+        #[cfg(feature = "serde")]
+        impl<'de> serde::Deserialize<'de> for $name {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                <$ty>::deserialize(deserializer).map(Self)
+            }
+        }
     };
 }
 
 id!(ModuleId, u8);
-id!(BranchpointId, u8);
+id!(BranchpointId, u16);
 id!(IdentId, u16);
-id!(LiteralId, u8);
-id!(AbstractTaskId, u8);
+id!(LiteralId, u16);
+id!(AbstractTaskId, u16);
 id!(AbstractValueId, u16);
 
-id!(RealTaskId, u16);
-id!(RealValueId, u16);
+// always u32 (unlike traversal node indices, which start at u16),
+// so traversals of more than 65536 task realizations fit:
+id!(RealTaskId, u32);
+id!(RealValueId, u32);
 
 id!(RunStrId, u32);
@@ -2,13 +2,16 @@ mod strings;
 pub use strings::WorkflowStrings;
 
 mod value;
-pub use value::{BaseValue, DirectValue, Value};
+pub use value::{env_var_names, BaseValue, DirectValue, Value};
 
 mod task;
-pub use task::{Task, TaskVars};
+pub use task::{ParamType, Task, TaskVars};
 
 mod plan;
-pub use plan::{Plan, Subplan};
+pub use plan::{cross_product, Plan, Subplan};
+
+mod constraint;
+pub use constraint::{Constraint, ConstraintKind};
 
 mod branch;
 pub use branch::{BaselineBranches, BranchSpec};
@@ -20,10 +23,10 @@ pub use id::{
 };
 
 mod error;
-pub use error::{Errors, Recap, Recapper};
+pub use error::{AggregatedErrors, Errors, Recap, Recapper};
 
 mod workflow;
-pub use workflow::{SizeHints, Workflow};
+pub use workflow::{Dependent, Producer, SizeHints, TaskEdge, TaskRedefinition, Workflow};
 
 mod string_cache;
 pub use string_cache::{StringCache, StringMaker};
@@ -31,31 +34,94 @@ pub use string_cache::{StringCache, StringMaker};
 mod real_task;
 pub use real_task::{RealTaskKey, RealTaskStrings};
 
+mod unused;
+pub use unused::Unused;
+
+/// Splitting up the bash code of tasks and funcs
+pub mod shell;
+
 // used to separate branchpoint from branch value e.g. "Profile.debug"
 pub const BRANCH_KV_DELIM: char = '.';
 // used to separate multiple branchpoint/value pairs e.g. "Profile.debug+Os.windows"
 pub const BRANCH_DELIM: char = '+';
+// prefix of the env var with the path of each module a task uses, e.g. "HR_MODULE_cargo"
+pub const MODULE_VAR_PREFIX: &str = "HR_MODULE_";
+// suffix of the implicit param given to tasks in a versioned module, e.g. "HR_MODULE_cargo_COMMIT";
+// it evaluates to the module's rev, which is replaced by its commit when the task is prepared.
+pub const MODULE_COMMIT_SUFFIX: &str = "_COMMIT";
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     #[error("Unsupported feature: {0}")]
     Unsupported(String),
-    #[error("Plan not found: {0:?}")]
-    PlanNotFound(IdentId),
-    #[error("Task defines multiple modules with '@'. Only one module is allowed.")]
-    MultipleModulesDefined,
-    #[error("Dot parameters (\".var\") are not yet supported")]
-    DotParamsUnsupported,
+    #[error("Plan not found: '{0}'{}", util::did_you_mean(.1.as_deref()))]
+    PlanNotFound(String, Option<String>),
+    #[error("Task uses module \"{0}\" more than once")]
+    DuplicateModule(String),
+    #[error("Dot parameter \".{0}\" is defined more than once")]
+    DuplicateDotParam(String),
+    #[error("Func \"{0}\" is defined more than once")]
+    DuplicateFunc(String),
+    #[error("Dot parameter \".{0}\" must have a literal value")]
+    DotParamNotLiteral(String),
     #[error("Unable to interpolate \"{0}\" into \"{1}\"")]
     Interp(String, String),
+    #[error("Included plan not found: '{0}'{}", util::did_you_mean(.1.as_deref()))]
+    IncludedPlanNotFound(String, Option<String>),
+    #[error("Plan '{0}' includes itself")]
+    PlanIncludesItself(String),
+    #[error("Plan excludes every branch of a 'reach' line")]
+    AllBranchesExcluded,
     #[error("Plan is empty: '{0}'")]
     EmptyPlan(String),
+    #[error("Plan uses a branch glob for branchpoint '{0}', which has no known branches")]
+    EmptyBranchGlob(String),
     #[error("Module not found: {0:?}")]
     ModuleNotFound(ModuleId),
-    #[error("Task not found: {0:?}")]
-    TaskNotFound(AbstractTaskId),
+    #[error("Task not found: '{0}'{}", util::did_you_mean(.1.as_deref()))]
+    TaskNotFound(String, Option<String>),
     #[error("Value not found: {0:?}")]
     ValueNotFound(AbstractValueId),
+    #[error("Environment variable '{0}' (used by \"{1}\") is not set")]
+    UnsetEnvVar(String, String),
+    #[error(
+        "Sequential branchpoint '{0}' has no branches (its step is 0, or it ends before it starts)"
+    )]
+    EmptySequence(String),
+    #[error(
+        "{0} grafts '{1}' from branch [{2}: {3}], but branchpoint '{2}' isn't defined anywhere{}",
+        util::did_you_mean(.4.as_deref())
+    )]
+    GraftBranchpointNotFound(String, String, String, String, Option<String>),
+    #[error(
+        "{0} grafts '{1}' from branch [{2}: {3}], but '{2}' has no branch '{3}'{}",
+        util::did_you_mean(.4.as_deref())
+    )]
+    GraftBranchNotFound(String, String, String, String, Option<String>),
+    #[error(
+        "Plan \"{0}\" uses [{1}: {2}] as baseline, but branchpoint '{1}' isn't defined anywhere{}",
+        util::did_you_mean(.3.as_deref())
+    )]
+    BaselineBranchpointNotFound(String, String, String, Option<String>),
+    #[error(
+        "Plan \"{0}\" uses [{1}: {2}] as baseline, but '{1}' has no branch '{2}'{}",
+        util::did_you_mean(.3.as_deref())
+    )]
+    BaselineBranchNotFound(String, String, String, Option<String>),
+    #[error("Constraint '{0}' relates two branches of the same branchpoint")]
+    ConstraintOnOneBranchpoint(String),
+    #[error(
+        "Constraint '{0}' uses branchpoint '{1}', which isn't defined anywhere{}",
+        util::did_you_mean(.2.as_deref())
+    )]
+    ConstraintBranchpointNotFound(String, String, Option<String>),
+    #[error(
+        "Constraint '{0}' uses [{1}: {2}], but '{1}' has no branch '{2}'{}",
+        util::did_you_mean(.3.as_deref())
+    )]
+    ConstraintBranchNotFound(String, String, String, Option<String>),
+    #[error("Plan \"{0}\" reaches branches that break constraints: {1}")]
+    PlanBreaksConstraints(String, String),
 }
 
 impl Recap for Error {
@@ -65,11 +131,6 @@ impl Recap for Error {
             Self::ModuleNotFound(id) => {
                 Ok(Some(format!("Module not found: {}", wf.modules.get(*id)?)))
             }
-            Self::TaskNotFound(id) => Ok(Some(format!("Task not found: {}", wf.tasks.get(*id)?))),
-            Self::PlanNotFound(id) => Ok(Some(format!(
-                "Plan not found in config file: {}",
-                wf.idents.get(*id)?
-            ))),
             _ => Ok(None),
         }
     }
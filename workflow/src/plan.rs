@@ -1,14 +1,19 @@
 use anyhow::Result;
 
-use intern::InternStr;
+use intern::{GetStr, InternStr};
 use syntax::ast;
 
-use crate::{AbstractTaskId, BranchSpec, Error, WorkflowStrings};
+use crate::{AbstractTaskId, BranchSpec, BranchpointId, Error, IdentId, WorkflowStrings};
 
 /// Representation of a plan defined in a config file.
-#[derive(Debug, Clone)]
+#[derive(Debug, Default, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Plan {
     pub subplans: Vec<Subplan>,
+    /// Plans whose subplans this one includes, which haven't been added to `subplans` yet.
+    pub(crate) includes: Vec<IdentId>,
+    /// Branches used as baseline instead of the defined ones while traversing this plan.
+    pub baselines: Vec<(BranchpointId, IdentId)>,
 }
 
 impl Plan {
@@ -16,39 +21,106 @@ impl Plan {
     pub fn create(
         strings: &mut WorkflowStrings,
         cross_products: Vec<ast::CrossProduct>,
+        includes: &[&str],
+        baselines: &[(&str, &str)],
     ) -> Result<Self> {
-        debug_assert!(!cross_products.is_empty());
+        debug_assert!(!cross_products.is_empty() || !includes.is_empty());
         let mut subplans = Vec::with_capacity(cross_products.len());
         for cross_product in cross_products {
             subplans.push(Subplan::create(strings, cross_product)?);
         }
-        Ok(Self { subplans })
+        let includes = includes
+            .iter()
+            .map(|plan| strings.idents.intern(plan))
+            .collect::<Result<_, _>>()?;
+        let mut overrides: Vec<(BranchpointId, IdentId)> = Vec::with_capacity(baselines.len());
+        for (k, v) in baselines {
+            let k = strings.add_branchpoint(k)?;
+            let v = strings.add_branch(k, v)?;
+            // a later line for the same branchpoint wins:
+            match overrides.iter_mut().find(|(other, _)| *other == k) {
+                Some(existing) => existing.1 = v,
+                None => overrides.push((k, v)),
+            }
+        }
+        Ok(Self {
+            subplans,
+            includes,
+            baselines: overrides,
+        })
+    }
+
+    /// Expand branch globs (e.g. `(Dataset: *)`) into every known branch of their branchpoints,
+    /// including ones only known from branchpoints.txt.
+    pub fn expand_globs(&mut self, strings: &WorkflowStrings) -> Result<()> {
+        for subplan in &mut self.subplans {
+            subplan.expand_globs(strings)?;
+        }
+        Ok(())
+    }
+
+    /// Add the subplans of `other` to this plan. Realizations reached by both
+    /// are only run once, since the traversal removes duplicates.
+    /// If both override the baseline of a branchpoint, this plan's override wins.
+    pub fn extend(&mut self, other: Plan) {
+        self.subplans.extend(other.subplans);
+        for (k, v) in other.baselines {
+            if !self.baselines.iter().any(|(other, _)| *other == k) {
+                self.baselines.push((k, v));
+            }
+        }
     }
 
-    /// Create an anonymous plan defined on the command line
+    /// Make this plan's baselines the ones used by `strings`, and use them for the branchpoints
+    /// its branches leave unspecified, so that values pick the overridden branch too.
+    /// Call this before traversing the plan; a plan without overrides restores the defined baselines.
+    pub fn apply_baselines(&mut self, strings: &mut WorkflowStrings) {
+        strings.baselines.set_overrides(&self.baselines);
+        for subplan in &mut self.subplans {
+            for branch in &mut subplan.branches {
+                for (k, v) in &self.baselines {
+                    if branch.is_unspecified(*k) {
+                        branch.insert(*k, *v);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Create an anonymous plan defined on the command line, reaching `tasks` via each of `branches`
     pub fn create_anonymous(
         strings: &mut WorkflowStrings,
         tasks: &[String],
-        branch: BranchSpec,
+        branches: Vec<BranchSpec>,
     ) -> Result<Self> {
         let goals = tasks.iter().map(|t| strings.tasks.intern(t)).collect::<Result<_, _>>()?;
 
         Ok(Self {
             subplans: vec![Subplan {
                 goals,
-                branches: vec![branch],
+                branches,
+                globs: Vec::new(),
+                excludes: Vec::new(),
             }],
+            includes: Vec::new(),
+            baselines: Vec::new(),
         })
     }
 }
 
 /// One line of a plan (aka a cross-product; e.g. "reach task via (Branch: val1 val2)").
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Subplan {
     /// Tasks we want to reach.
     pub goals: Vec<AbstractTaskId>,
     /// Branches to realize tasks for.
     pub branches: Vec<BranchSpec>,
+    /// Branchpoints given as globs, which haven't been expanded into `branches` yet.
+    globs: Vec<BranchpointId>,
+    /// Combinations of branches to remove from `branches` once globs are expanded;
+    /// None stands for any branch of the branchpoint.
+    excludes: Vec<Vec<(BranchpointId, Option<Vec<IdentId>>)>>,
 }
 
 impl Subplan {
@@ -61,53 +133,124 @@ impl Subplan {
         }
 
         let mut branches = vec![BranchSpec::default()];
+        let mut globs = Vec::new();
         for (k, vs) in &cross_product.branches {
             let k = strings.add_branchpoint(k)?; // strings.branchpoints.intern(k);
             let vs = match vs {
                 ast::Branches::Specified(vec) => vec,
-                _ => {
-                    return Err(Error::Unsupported(
-                        "plans with branch glob specifications".to_owned(),
-                    )
-                    .into())
+                ast::Branches::Glob => {
+                    globs.push(k);
+                    continue;
                 }
             };
+            let vs = vs.iter().map(|v| strings.add_branch(k, v)).collect::<Result<Vec<_>>>()?;
+            cross(&mut branches, k, &vs);
+        }
 
-            match vs.len() {
-                0 => unreachable!(
-                    "Plan branch specifications should have at least one branch, \
-                    but the parser should catch this."
-                ),
-                1 => {
-                    // if len is 1, no need to split. just add to each existing branch.
-                    let v = strings.add_branch(k, vs[0])?;
-                    for branch in &mut branches {
-                        branch.insert(k, v);
-                    }
-                }
-                len => {
-                    branches.reserve(branches.len() * len);
-                    // insert the first val:
-                    let v0 = strings.add_branch(k, vs[0])?;
-                    for branch in &mut branches {
-                        branch.insert(k, v0);
-                    }
-                    // now clone for each subsequent val, and insert:
-                    let mut new_branches = Vec::with_capacity(branches.len() * len);
-                    for v in vs.iter().skip(1) {
-                        let v = strings.add_branch(k, v)?;
-                        for branch in &branches {
-                            let mut new_branch = branch.clone();
-                            new_branch.insert(k, v);
-                            new_branches.push(new_branch);
-                        }
-                    }
-                    // now add those to the original branches array:
-                    branches.append(&mut new_branches);
-                }
+        let mut excludes = Vec::with_capacity(cross_product.excludes.len());
+        for exclude in &cross_product.excludes {
+            let mut combination = Vec::with_capacity(exclude.len());
+            for (k, vs) in exclude {
+                let k = strings.add_branchpoint(k)?;
+                let vs = match vs {
+                    ast::Branches::Specified(vs) => Some(
+                        vs.iter().map(|v| strings.add_branch(k, v)).collect::<Result<Vec<_>>>()?,
+                    ),
+                    ast::Branches::Glob => None,
+                };
+                combination.push((k, vs));
             }
+            excludes.push(combination);
+        }
+
+        let mut subplan = Self {
+            goals,
+            branches,
+            globs,
+            excludes,
+        };
+        if subplan.globs.is_empty() {
+            subplan.apply_excludes()?;
+        }
+        Ok(subplan)
+    }
+
+    fn expand_globs(&mut self, strings: &WorkflowStrings) -> Result<()> {
+        for k in std::mem::take(&mut self.globs) {
+            // only the branches still defined in the config, not ones left in branchpoints.txt:
+            let vs: Vec<IdentId> = strings.baselines.defined(k).collect();
+            if vs.is_empty() {
+                let name = strings.branchpoints.get(k)?;
+                return Err(Error::EmptyBranchGlob(name.to_owned()).into());
+            }
+            cross(&mut self.branches, k, &vs);
+        }
+        self.apply_excludes()
+    }
+
+    /// Remove the branches matching any of `excludes`, i.e. whose branch for each branchpoint
+    /// in an exclusion is one of the ones it lists.
+    fn apply_excludes(&mut self) -> Result<()> {
+        if self.excludes.is_empty() {
+            return Ok(());
+        }
+        let excludes = std::mem::take(&mut self.excludes);
+        self.branches.retain(|branch| {
+            !excludes.iter().any(|combination| {
+                combination.iter().all(|(k, vs)| match (branch.get_specified(*k), vs) {
+                    (Some(v), Some(vs)) => vs.contains(&v),
+                    (Some(_), None) => true,
+                    (None, _) => false,
+                })
+            })
+        });
+        if self.branches.is_empty() {
+            return Err(Error::AllBranchesExcluded.into());
         }
+        Ok(())
+    }
+}
+
+/// Every combination of one branch for each branchpoint, e.g. the four branches
+/// `(Os: mac win) * (Profile: debug release)`. Each branchpoint needs at least one branch.
+pub fn cross_product(choices: &[(BranchpointId, Vec<IdentId>)]) -> Vec<BranchSpec> {
+    let mut branches = vec![BranchSpec::default()];
+    for (k, vs) in choices {
+        cross(&mut branches, *k, vs);
+    }
+    branches
+}
 
-        Ok(Self { goals, branches })
+/// Replace each of `branches` with one copy for each of `vs` on branchpoint `k`.
+fn cross(branches: &mut Vec<BranchSpec>, k: BranchpointId, vs: &[IdentId]) {
+    match vs.len() {
+        0 => unreachable!(
+            "Plan branch specifications should have at least one branch, \
+            but the parser should catch this."
+        ),
+        1 => {
+            // if len is 1, no need to split. just add to each existing branch.
+            for branch in branches {
+                branch.insert(k, vs[0]);
+            }
+        }
+        len => {
+            branches.reserve(branches.len() * len);
+            // insert the first val:
+            for branch in branches.iter_mut() {
+                branch.insert(k, vs[0]);
+            }
+            // now clone for each subsequent val, and insert:
+            let mut new_branches = Vec::with_capacity(branches.len() * len);
+            for v in vs.iter().skip(1) {
+                for branch in branches.iter() {
+                    let mut new_branch = branch.clone();
+                    new_branch.insert(k, *v);
+                    new_branches.push(new_branch);
+                }
+            }
+            // now add those to the original branches array:
+            branches.append(&mut new_branches);
+        }
     }
 }
@@ -6,11 +6,18 @@ use crate::{AbstractTaskId, BranchSpec, StringMaker, WorkflowStrings};
 /// Unique id of a resolved (real) task: an abstract task id
 /// plus the branch that resolves it to an actual task.
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RealTaskKey {
     pub id: AbstractTaskId,
     pub branch: BranchSpec,
 }
 
+impl util::AllocSize for RealTaskKey {
+    fn alloc_size(&self) -> usize {
+        self.branch.alloc_size()
+    }
+}
+
 #[derive(Debug)]
 pub struct RealTaskStrings;
 
@@ -0,0 +1,218 @@
+/// A piece of a task's bash code, as split up by [`statements`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Token<'a> {
+    /// A word outside of quotes, e.g. a command, an argument, or `name=value`.
+    Word(&'a str),
+    /// A variable, as `$name` or `${name...}`, where `rest` is the code after its name
+    /// (e.g. `:-default}` for `${name:-default}`).
+    Var {
+        name: &'a str,
+        braced: bool,
+        rest: &'a str,
+    },
+}
+
+/// Words that can come before the command of a statement, e.g. `while` in `while read -r x`.
+const KEYWORDS: &[&str] = &[
+    "!", "{", "do", "elif", "else", "if", "then", "time", "until", "while",
+];
+
+/// The command a statement runs and its arguments, e.g. `read` and `-r x`
+/// in `while FOO=1 read -r x`, if it starts with a word.
+pub fn command<'s, 'a>(statement: &'s [Token<'a>]) -> Option<(&'a str, &'s [Token<'a>])> {
+    let start = statement.iter().position(|token| match token {
+        Token::Word(word) => !KEYWORDS.contains(word) && !is_assignment(word),
+        Token::Var { .. } => true,
+    })?;
+    match statement[start] {
+        Token::Word(word) => Some((word, &statement[start + 1..])),
+        Token::Var { .. } => None,
+    }
+}
+
+/// True if `word` is an assignment like `name=value` or `name+=value`.
+fn is_assignment(word: &str) -> bool {
+    let name = word.split_once('=').map_or("", |(name, _)| name);
+    let name = name.strip_suffix('+').unwrap_or(name);
+    is_name(name)
+}
+
+/// True if `s` is a valid variable name.
+pub fn is_name(s: &str) -> bool {
+    s.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') && s.chars().all(is_name_char)
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// What we're in the middle of while splitting up code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Context {
+    /// Commands, in the code itself or in `(...)` or `$(...)`.
+    Code,
+    /// Commands inside backticks.
+    Backticks,
+    /// A double-quoted string, where only variables (and command substitutions) count.
+    Quoted,
+    /// The rest of a `${...}`, after the name; like a double-quoted string.
+    Braced,
+}
+
+/// Variables with special names, like `$?`.
+const SPECIAL_VARS: &str = "?#@*!$-";
+
+/// Split bash `code` into statements, each a list of the words and variables in it,
+/// at newlines, `;`, `|`, `&`, parentheses, and backticks. Comments, single-quoted strings,
+/// and the text of double-quoted strings are skipped (but not the variables in them),
+/// so e.g. `'$x'`, `"greet"`, and `# greet $x` have neither a word nor a variable.
+/// This is a best guess, which doesn't understand e.g. heredocs or arithmetic.
+pub fn statements(code: &str) -> Vec<Vec<Token<'_>>> {
+    let mut statements = vec![Vec::new()];
+    let mut contexts = vec![Context::Code];
+    let mut word_start = None;
+    let mut chars = code.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let context = *contexts.last().unwrap_or(&Context::Code);
+        let in_code = matches!(context, Context::Code | Context::Backticks);
+        let ends_word = c.is_whitespace() || "#;|&()<>'\"`$".contains(c);
+        if let (true, Some(start)) = (ends_word, word_start) {
+            // '#' only starts a comment at the start of a word:
+            if c != '#' {
+                let current = statements.last_mut().expect("always a statement");
+                current.push(Token::Word(&code[start..i]));
+                word_start = None;
+            }
+        }
+        match c {
+            '\\' => {
+                chars.next();
+                if in_code && word_start.is_none() {
+                    word_start = Some(i);
+                }
+            }
+            '$' if code[i + 1..].starts_with('(') => {
+                chars.next();
+                contexts.push(Context::Code);
+                statements.push(Vec::new());
+            }
+            '$' => {
+                let rest = &code[i + 1..];
+                let braced = rest.strip_prefix('{').map(|rest| rest.trim_start_matches(['#', '!']));
+                let after = braced.unwrap_or(rest);
+                let mut len = after.find(|c| !is_name_char(c)).unwrap_or(after.len());
+                if len == 0 && braced.is_none() && after.starts_with(|c| SPECIAL_VARS.contains(c)) {
+                    len = 1;
+                }
+                push_var(
+                    &mut statements,
+                    &after[..len],
+                    braced.is_some(),
+                    &after[len..],
+                );
+                // skip the name, so it isn't taken for a word:
+                let end = code.len() - after.len() + len;
+                while chars.next_if(|(j, _)| *j < end).is_some() {}
+                if braced.is_some() {
+                    contexts.push(Context::Braced);
+                }
+            }
+            '}' if context == Context::Braced => {
+                contexts.pop();
+            }
+            '"' if context == Context::Quoted => {
+                contexts.pop();
+            }
+            '"' => contexts.push(Context::Quoted),
+            '`' if context == Context::Backticks => {
+                contexts.pop();
+                statements.push(Vec::new());
+            }
+            '`' => {
+                contexts.push(Context::Backticks);
+                statements.push(Vec::new());
+            }
+            _ if !in_code => (),
+            '#' if word_start.is_none() => while chars.next_if(|(_, c)| *c != '\n').is_some() {},
+            '\'' => while chars.next().is_some_and(|(_, c)| c != '\'') {},
+            '(' => {
+                contexts.push(Context::Code);
+                statements.push(Vec::new());
+            }
+            ')' => {
+                if contexts.len() > 1 {
+                    contexts.pop();
+                }
+                statements.push(Vec::new());
+            }
+            // e.g. '2>&1' doesn't end the statement:
+            '&' if code[..i].ends_with(['<', '>']) => (),
+            '\n' | ';' | '|' | '&' => statements.push(Vec::new()),
+            '<' | '>' => (),
+            _ if c.is_whitespace() => (),
+            _ => {
+                if word_start.is_none() {
+                    word_start = Some(i);
+                }
+            }
+        }
+    }
+    if let Some(start) = word_start {
+        let current = statements.last_mut().expect("always a statement");
+        current.push(Token::Word(&code[start..]));
+    }
+    statements.retain(|statement| !statement.is_empty());
+    statements
+}
+
+/// Add a variable to the current statement, unless it's a special one like `$?`.
+fn push_var<'a>(statements: &mut [Vec<Token<'a>>], name: &'a str, braced: bool, rest: &'a str) {
+    if is_name(name) {
+        let current = statements.last_mut().expect("always a statement");
+        current.push(Token::Var { name, braced, rest });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_statements() {
+        let var = |name, braced, rest| Token::Var { name, braced, rest };
+        let code = "x=$(greet \"$name\") # greet $y\necho '$z' \"${w:-q}\" | tee -a log";
+        assert_eq!(
+            statements(code),
+            vec![
+                vec![Token::Word("x=")],
+                vec![
+                    Token::Word("greet"),
+                    var(
+                        "name",
+                        false,
+                        "\") # greet $y\necho '$z' \"${w:-q}\" | tee -a log"
+                    ),
+                ],
+                vec![Token::Word("echo"), var("w", true, ":-q}\" | tee -a log"),],
+                vec![Token::Word("tee"), Token::Word("-a"), Token::Word("log")],
+            ]
+        );
+        assert_eq!(
+            statements("echo `greet` $?"),
+            vec![vec![Token::Word("echo")], vec![Token::Word("greet")],]
+        );
+
+        let commands = |code| -> Vec<_> {
+            statements(code).iter().filter_map(|s| Some(command(s)?.0)).collect()
+        };
+        assert_eq!(
+            commands("while X=1 read -r x; do echo $x; done"),
+            ["read", "echo", "done"]
+        );
+        assert_eq!(
+            commands("if [ -e f ]; then greet; fi"),
+            ["[", "greet", "fi"]
+        );
+        assert_eq!(commands("$cmd arg"), Vec::<&str>::new());
+    }
+}
@@ -2,7 +2,7 @@ use std::cell::{Ref, RefCell};
 
 use anyhow::Result;
 
-use util::{HashMap, Hasher};
+use util::{AllocSize, HashMap, Hasher, MemoryUsage};
 
 use crate::WorkflowStrings;
 
@@ -10,8 +10,10 @@ pub trait StringMaker<T> {
     fn make_string(&self, val: &T, wf: &WorkflowStrings, buf: &mut String) -> Result<()>;
 }
 
+/// Strings made from `T`s, stored in a single buffer at offsets of type `Idx`
+/// (`u32` by default, so the strings of very large traversals fit).
 #[derive(Debug)]
-pub struct StringCache<T, M, Idx = u16> {
+pub struct StringCache<T, M, Idx = u32> {
     strings: RefCell<String>,
     idxs: RefCell<HashMap<T, (Idx, Idx)>>,
     maker: M,
@@ -27,16 +29,34 @@ impl<T, M, Idx> StringCache<T, M, Idx> {
     }
 }
 
+impl<T: AllocSize, M, Idx> StringCache<T, M, Idx> {
+    /// Memory used by the cached strings and the values they were made from.
+    pub fn memory_usage(&self, name: &'static str) -> MemoryUsage {
+        let idxs = self.idxs.borrow();
+        // like the map in a PackedInterner, a control byte per entry:
+        let entry_size = std::mem::size_of::<(T, (Idx, Idx))>() + 1;
+        MemoryUsage {
+            name,
+            len: idxs.len(),
+            bytes: self.strings.borrow().capacity()
+                + idxs.capacity() * entry_size
+                + idxs.keys().map(T::alloc_size).sum::<usize>(),
+        }
+    }
+}
+
 impl<T, M, Idx> StringCache<T, M, Idx>
 where
     T: Clone + Eq + std::hash::Hash,
     M: StringMaker<T>,
-    Idx: Copy + TryFrom<usize> + Into<usize>,
+    Idx: Copy + TryFrom<usize>,
     Idx::Error: std::error::Error + Send + Sync + 'static,
+    usize: TryFrom<Idx>,
+    <usize as TryFrom<Idx>>::Error: std::error::Error + Send + Sync + 'static,
 {
-    pub fn get_or_insert(&self, val: &T, wf: &WorkflowStrings) -> Result<Ref<str>> {
+    pub fn get_or_insert(&self, val: &T, wf: &WorkflowStrings) -> Result<Ref<'_, str>> {
         if let Some((start, end)) = self.idxs.borrow().get(val).copied() {
-            return Ok(self.get_substr(start.into(), end.into()));
+            return Ok(self.get_substr(start.try_into()?, end.try_into()?));
         }
         let (start, end) = self.push_new_str(val, wf)?;
         self.idxs.borrow_mut().insert(val.clone(), (start.try_into()?, end.try_into()?));
@@ -50,7 +70,7 @@ where
         Ok((start, s.len()))
     }
 
-    fn get_substr(&self, start: usize, end: usize) -> Ref<str> {
+    fn get_substr(&self, start: usize, end: usize) -> Ref<'_, str> {
         let s = self.strings.borrow();
         Ref::map(s, |s| &s[start..end])
     }
@@ -2,8 +2,9 @@ use std::cell::Ref;
 
 use anyhow::Result;
 
-use intern::{GetStr, InternStr, LooseInterner, PackedInterner, TypedInterner};
+use intern::{GetStr, InternStr, LooseInterner, PackedInterner, SyncInterner, TypedInterner};
 use syntax::ast;
+use util::MemoryUsage;
 
 use crate::value::create_value;
 use crate::{
@@ -14,27 +15,32 @@ use crate::{
 use crate::branch::{CompactBranchStrings, FullBranchStrings};
 
 /// Stores all the interned strings associated with a Workflow.
+/// Interners are serialized as their strings in key order; the string caches are left empty.
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct WorkflowStrings {
     /// Names of branchpoints
-    pub branchpoints: TypedInterner<BranchpointId, PackedInterner<u8, u8>>,
+    pub branchpoints: TypedInterner<BranchpointId, PackedInterner<u16, u16>>,
     /// Names of tasks
-    pub tasks: TypedInterner<AbstractTaskId, PackedInterner<u8, u16>>,
+    pub tasks: TypedInterner<AbstractTaskId, PackedInterner<u16, u32>>,
     /// Names of other idents (variables, branches, etc.)
-    pub idents: TypedInterner<IdentId, PackedInterner<u16, u16>>,
+    pub idents: TypedInterner<IdentId, PackedInterner<u16, u32>>,
     /// Names of modules
-    pub modules: TypedInterner<ModuleId, PackedInterner<u8, u8>>,
+    pub modules: TypedInterner<ModuleId, PackedInterner<u8, u16>>,
     /// Literal strings (code blocks, variable values)
-    pub literals: TypedInterner<LiteralId, LooseInterner<u8, u16>>,
+    pub literals: TypedInterner<LiteralId, LooseInterner<u16, u32>>,
     /// Keep track of which branch is baseline for each branchpoint
     pub baselines: BaselineBranches,
     /// Strings used while running workflow: full file paths, debug strings etc.
     pub run: TypedInterner<RunStrId, PackedInterner<u32, usize>>,
     /// Cache for user-friendly branch strs e.g. 'A.p1+B.p2' etc.
+    #[cfg_attr(feature = "serde", serde(skip, default = "empty_branch_strs"))]
     branch_strs: StringCache<BranchSpec, FullBranchStrings>,
     /// Create compact branch strings that use 'Baseline.baseline' for baseline branches:
+    #[cfg_attr(feature = "serde", serde(skip, default = "compact_branch_strs"))]
     compact_branch_strs: CompactBranchStrings,
     /// Cache for user-friendly task strings e.g. 'task_name[A.p1+B.p2]'
+    #[cfg_attr(feature = "serde", serde(skip, default = "empty_real_task_strs"))]
     real_task_strs: StringCache<RealTaskKey, RealTaskStrings>,
 }
 
@@ -51,15 +57,27 @@ impl Default for WorkflowStrings {
             literals: TypedInterner::new(LooseInterner::with_capacity_and_str_len(64, 4096)),
             modules: TypedInterner::new(PackedInterner::with_capacity_and_str_len(8, 16)),
             baselines: BaselineBranches::with_capacity(8),
-            compact_branch_strs: CompactBranchStrings,
+            compact_branch_strs: compact_branch_strs(),
             // we'll re-alloc these later when we need them:
             run: TypedInterner::new(PackedInterner::with_capacity_and_str_len(0, 0)),
-            branch_strs: StringCache::with_capacity_and_str_len(FullBranchStrings, 0, 0),
-            real_task_strs: StringCache::with_capacity_and_str_len(RealTaskStrings, 0, 0),
+            branch_strs: empty_branch_strs(),
+            real_task_strs: empty_real_task_strs(),
         }
     }
 }
 
+fn empty_branch_strs() -> StringCache<BranchSpec, FullBranchStrings> {
+    StringCache::with_capacity_and_str_len(FullBranchStrings, 0, 0)
+}
+
+fn compact_branch_strs() -> CompactBranchStrings {
+    CompactBranchStrings
+}
+
+fn empty_real_task_strs() -> StringCache<RealTaskKey, RealTaskStrings> {
+    StringCache::with_capacity_and_str_len(RealTaskStrings, 0, 0)
+}
+
 impl WorkflowStrings {
     /// Allocate space for new strings created during traversal:
     pub fn alloc_for_traversal(&mut self) {
@@ -73,15 +91,34 @@ impl WorkflowStrings {
         self.run = TypedInterner::new(PackedInterner::with_capacity_and_str_len(64, 4096));
     }
 
+    /// Move the runtime strings into an interner that worker threads can share,
+    /// leaving `run` empty until they're given back with `restore_run`.
+    pub fn take_run_sync(&mut self) -> TypedInterner<RunStrId, SyncInterner<u32, usize>> {
+        let run = std::mem::replace(
+            &mut self.run,
+            TypedInterner::new(PackedInterner::with_capacity_and_str_len(0, 0)),
+        );
+        TypedInterner::new(run.into_inner().into())
+    }
+
+    /// Give back the runtime strings taken by `take_run_sync`.
+    pub fn restore_run(
+        &mut self,
+        run: TypedInterner<RunStrId, SyncInterner<u32, usize>>,
+    ) -> Result<()> {
+        self.run = TypedInterner::new(run.into_inner().into_inner()?);
+        Ok(())
+    }
+
     /// Get user-friendly branch str, w/ all branches shown.
     #[inline]
-    pub fn get_full_branch_str(&self, branch: &BranchSpec) -> Result<Ref<str>> {
+    pub fn get_full_branch_str(&self, branch: &BranchSpec) -> Result<Ref<'_, str>> {
         self.branch_strs.get_or_insert(branch, self)
     }
 
     /// Get user-friendly task str, e.g. 'task_name[full_branch_str]'.
     #[inline]
-    pub fn get_real_task_str(&self, task: &RealTaskKey) -> Result<Ref<str>> {
+    pub fn get_real_task_str(&self, task: &RealTaskKey) -> Result<Ref<'_, str>> {
         self.real_task_strs.get_or_insert(task, self)
     }
 
@@ -102,10 +139,11 @@ impl WorkflowStrings {
 
     /// Used while loading branchpoints.txt to make sure our branchpoints are
     /// ordered consistently, and baselines stay consistent between runs.
+    /// The first branch loaded for each branchpoint is its baseline.
     pub fn pre_load_baseline(&mut self, branchpoint: &str, branchval: &str) -> Result<()> {
         let k = self.branchpoints.intern(branchpoint)?;
         let v = self.idents.intern(branchval)?;
-        self.baselines.add(k, v);
+        self.baselines.preload(k, v);
         Ok(())
     }
 
@@ -125,18 +163,38 @@ impl WorkflowStrings {
         self.idents.intern(branch_name)
     }
 
-    /// Log sizes of interners at debug level:
+    /// Log sizes of interners and string caches at debug level:
     pub fn log_sizes(&self) {
-        self.log_sizes_for("Branchpoints", &self.branchpoints);
-        self.log_sizes_for("Tasks", &self.tasks);
-        self.log_sizes_for("Idents", &self.idents);
-        self.log_sizes_for("Modules", &self.modules);
-        self.log_sizes_for("Literals", &self.literals);
+        for usage in self.memory_usage() {
+            log::debug!(
+                "{} {}, {} bytes allocated",
+                usage.len,
+                usage.name,
+                usage.bytes
+            );
+        }
     }
 
-    #[inline]
-    fn log_sizes_for<T: GetStr>(&self, name: &str, interner: &T) {
-        log::debug!("{} {name}, str len {}", interner.len(), interner.str_len());
+    /// Memory used by each interner and string cache, for 'hr stats --memory'.
+    pub fn memory_usage(&self) -> Vec<MemoryUsage> {
+        vec![
+            interner_usage("branchpoints", &self.branchpoints),
+            interner_usage("tasks", &self.tasks),
+            interner_usage("idents", &self.idents),
+            interner_usage("modules", &self.modules),
+            interner_usage("literals", &self.literals),
+            interner_usage("run strings", &self.run),
+            self.branch_strs.memory_usage("branch strings"),
+            self.real_task_strs.memory_usage("task strings"),
+        ]
+    }
+}
+
+fn interner_usage<T: GetStr>(name: &'static str, interner: &T) -> MemoryUsage {
+    MemoryUsage {
+        name,
+        len: interner.len(),
+        bytes: interner.alloc_size(),
     }
 }
 
@@ -187,7 +245,7 @@ mod test {
     #[test]
     fn test_interpolate() -> Result<()> {
         let mut strings = WorkflowStrings::default();
-        let orig_id = strings.literals.intern("$v1 and $v2 $v1-$v2.$v2 etc");
+        let orig_id = strings.literals.intern("$v1 and $v2 $v1-$v2.$v2 etc")?;
         let v1 = strings.idents.intern("v1")?;
         let v2 = strings.idents.intern("v2")?;
         let v1_val = strings.literals.intern("value for var one")?;
@@ -218,4 +276,46 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn test_cached_baseline_branch_strs() -> Result<()> {
+        let mut strings = WorkflowStrings::default();
+        let k = strings.add_branchpoint("X")?;
+        let baseline = strings.idents.intern("x1")?;
+        let other = strings.idents.intern("x2")?;
+        strings.baselines.add(k, baseline);
+
+        // the cache stores all strings in one buffer, so make sure
+        // we don't mistake an earlier string for part of a later one:
+        let specified = BranchSpec::simple(k, other);
+        assert_eq!(&*strings.get_full_branch_str(&specified)?, "X.x2");
+        assert_eq!(
+            &*strings.get_full_branch_str(&BranchSpec::default())?,
+            "Baseline.baseline"
+        );
+
+        let mut buf = String::from("prefix.");
+        strings.make_compact_branch_string(&BranchSpec::default(), &mut buf)?;
+        assert_eq!(buf, "prefix.Baseline.baseline");
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_sync() -> Result<()> {
+        use intern::SyncInternStr;
+        let mut strings = WorkflowStrings::default();
+        strings.alloc_for_run();
+        let out = strings.run.intern("out/prep")?;
+        let run = strings.take_run_sync();
+        let ids = std::thread::scope(|scope| {
+            let run = &run;
+            let a = scope.spawn(move || run.intern_sync("out/build"));
+            let b = scope.spawn(move || run.intern_sync("out/prep"));
+            (a.join().unwrap(), b.join().unwrap())
+        });
+        strings.restore_run(run)?;
+        assert_eq!(ids.1?, out);
+        assert_eq!(strings.run.get(ids.0?)?, "out/build");
+        Ok(())
+    }
 }
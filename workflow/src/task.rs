@@ -1,8 +1,9 @@
 use anyhow::Result;
+use std::path::Path;
 
 use intern::InternStr;
 use syntax::ast;
-use util::IdVec;
+use util::{AllocSize, IdVec};
 
 use crate::{AbstractValueId, Error, IdentId, LiteralId, ModuleId, Value, WorkflowStrings};
 
@@ -11,6 +12,7 @@ const DEFAULT_VARS_LEN: usize = 8;
 /// Utility representing a task's inputs, outputs, and params.
 /// We'll use this with several different types throughout the process.
 #[derive(Debug, Default, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TaskVars<T> {
     pub inputs: Vec<T>,
     pub outputs: Vec<T>,
@@ -38,8 +40,65 @@ impl<T> TaskVars<T> {
     }
 }
 
+impl<T: AllocSize> AllocSize for TaskVars<T> {
+    fn alloc_size(&self) -> usize {
+        self.inputs.alloc_size() + self.outputs.alloc_size() + self.params.alloc_size()
+    }
+}
+
+/// Declared type of a param's value (`:: jobs:int=4`), checked when the task is prepared.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ParamType {
+    Int,
+    /// `true` or `false`.
+    Bool,
+    /// A file or dir that exists; relative paths are relative to the config file's dir.
+    Path,
+    /// One of a list of allowed values.
+    OneOf(Vec<String>),
+}
+
+impl ParamType {
+    /// true if `val` is a valid value of this type.
+    pub fn accepts(&self, val: &str, config_dir: &Path) -> bool {
+        match self {
+            Self::Int => val.parse::<i64>().is_ok(),
+            Self::Bool => val == "true" || val == "false",
+            Self::Path => config_dir.join(val).exists(),
+            Self::OneOf(vals) => vals.iter().any(|v| v == val),
+        }
+    }
+}
+
+/// e.g. "an int", "one of: debug, release".
+impl std::fmt::Display for ParamType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Int => f.write_str("an int"),
+            Self::Bool => f.write_str("a bool (true or false)"),
+            Self::Path => f.write_str("a path that exists"),
+            Self::OneOf(vals) => write!(f, "one of: {}", vals.join(", ")),
+        }
+    }
+}
+
+impl From<ast::ParamType<'_>> for ParamType {
+    fn from(ty: ast::ParamType) -> Self {
+        match ty {
+            ast::ParamType::Int => Self::Int,
+            ast::ParamType::Bool => Self::Bool,
+            ast::ParamType::Path => Self::Path,
+            ast::ParamType::OneOf(vals) => {
+                Self::OneOf(vals.into_iter().map(str::to_owned).collect())
+            }
+        }
+    }
+}
+
 /// Representation of a task defined in a workflow file.
 #[derive(Debug, Default, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Task {
     /// Inputs, Outputs and Params to this task (var name, value)
     pub vars: TaskVars<(IdentId, AbstractValueId)>,
@@ -47,13 +106,25 @@ pub struct Task {
     pub code: LiteralId,
     /// List of var names referenced in this task's code (for validation)
     pub referenced_vars: Vec<IdentId>,
-    /// Optional id of module that this task should run in instead of its task directory
-    pub module: Option<ModuleId>,
+    /// Ids of modules used by this task; it runs in the first one instead of its task directory
+    pub modules: Vec<ModuleId>,
+    /// Maximum total size in bytes of this task's realization dir after it runs (".max_output_size")
+    pub max_output_size: Option<u64>,
+    /// Other dot params (e.g. ".cpus", ".queue"): metadata for whatever schedules the task,
+    /// which isn't exported to its code.
+    pub dot_params: Vec<(IdentId, LiteralId)>,
+    /// Declared types of params that have them.
+    pub param_types: Vec<(IdentId, ParamType)>,
     /// So we can tell if this task is real, or just a default:
     pub exists: bool,
 }
 
 impl Task {
+    /// Declared type of the param named `k`, if it has one.
+    pub fn param_type(&self, k: IdentId) -> Option<&ParamType> {
+        self.param_types.iter().find(|(name, _)| *name == k).map(|(_, ty)| ty)
+    }
+
     /// Create a new task from its ast representation.
     pub fn create(
         block: ast::TasklikeBlock,
@@ -63,26 +134,36 @@ impl Task {
         // If there are few or zero specs, we may be able to avoid an alloc:
         let default_len = block.specs.len().min(DEFAULT_VARS_LEN);
         let mut vars = TaskVars::with_default_capacity(default_len);
-        let mut module = None;
+        let mut modules = Vec::new();
+        let mut max_output_size = None;
+        let mut dot_params = Vec::new();
+        let mut param_types = Vec::new();
 
         use ast::BlockSpec::*;
         for spec in block.specs {
             match spec {
                 Input { lhs, rhs } => vars.inputs.push(add_spec(lhs, rhs, strings, values)?),
                 Output { lhs, rhs } => vars.outputs.push(add_spec(lhs, rhs, strings, values)?),
-                Param { lhs, rhs, dot } => {
+                Param { lhs, rhs, dot, ty } => {
                     if dot {
-                        return Err(Error::DotParamsUnsupported.into());
+                        add_dot_param(lhs, rhs, strings, &mut max_output_size, &mut dot_params)?;
                     } else {
-                        vars.params.push(add_spec(lhs, rhs, strings, values)?);
+                        let param = add_spec(lhs, rhs, strings, values)?;
+                        if let Some(ty) = ty {
+                            param_types.push((param.0, ty.into()));
+                        }
+                        vars.params.push(param);
                     }
                 }
                 Module { name } => {
-                    if module.is_none() {
-                        module = Some(strings.modules.intern(name)?);
-                    } else {
-                        return Err(Error::MultipleModulesDefined.into());
+                    let id = strings.modules.intern(name)?;
+                    if modules.contains(&id) {
+                        return Err(Error::DuplicateModule(name.to_owned()).into());
                     }
+                    modules.push(id);
+                }
+                Package { name } => {
+                    return Err(Error::Unsupported(format!("ducttape package \"{name}\"")).into());
                 }
             }
         }
@@ -99,12 +180,43 @@ impl Task {
             vars,
             code,
             referenced_vars,
-            module,
+            modules,
+            max_output_size,
+            dot_params,
+            param_types,
             exists: true,
         })
     }
 }
 
+/// Dot params are task metadata, rather than variables passed to the task's code.
+/// `.max_output_size` is used by hr itself; any others are just recorded.
+fn add_dot_param(
+    lhs: ast::Ident,
+    rhs: ast::Rhs,
+    strings: &mut WorkflowStrings,
+    max_output_size: &mut Option<u64>,
+    dot_params: &mut Vec<(IdentId, LiteralId)>,
+) -> Result<()> {
+    let ast::Rhs::Literal { val } = rhs else {
+        return Err(Error::DotParamNotLiteral(lhs.to_owned()).into());
+    };
+    let val = &*crate::value::expand_env_vars(val, lhs)?;
+    if lhs == "max_output_size" {
+        if max_output_size.is_some() {
+            return Err(Error::DuplicateDotParam(lhs.to_owned()).into());
+        }
+        *max_output_size = Some(util::parse_size(val)?);
+    } else {
+        let name = strings.idents.intern(lhs)?;
+        if dot_params.iter().any(|(k, _)| *k == name) {
+            return Err(Error::DuplicateDotParam(lhs.to_owned()).into());
+        }
+        dot_params.push((name, strings.literals.intern(val)?));
+    }
+    Ok(())
+}
+
 fn add_spec(
     lhs: ast::Ident,
     rhs: ast::Rhs,
@@ -0,0 +1,182 @@
+use anyhow::Result;
+
+use intern::GetStr;
+use util::HashSet;
+
+use crate::shell::{self, Token};
+use crate::{
+    AbstractTaskId, AbstractValueId, BaseValue, DirectValue, IdentId, Value, Workflow,
+    WorkflowStrings, MODULE_VAR_PREFIX,
+};
+
+/// Something defined in the config file that's never used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unused {
+    /// An input of a task whose code never references it.
+    Input(AbstractTaskId, IdentId),
+    /// A param of a task whose code never references it.
+    Param(AbstractTaskId, IdentId),
+    /// A global config value that no task uses, directly or through other values.
+    Config(IdentId),
+}
+
+impl Unused {
+    /// Human-readable description, for warnings.
+    pub fn describe(&self, strings: &WorkflowStrings) -> Result<String> {
+        Ok(match self {
+            Self::Input(task, var) => format!(
+                "Task {} never uses its input ${}",
+                strings.tasks.get(*task)?,
+                strings.idents.get(*var)?
+            ),
+            Self::Param(task, var) => format!(
+                "Task {} never uses its param ${}",
+                strings.tasks.get(*task)?,
+                strings.idents.get(*var)?
+            ),
+            Self::Config(k) => format!(
+                "Config value {} isn't used by any task",
+                strings.idents.get(*k)?
+            ),
+        })
+    }
+}
+
+impl Workflow {
+    /// Find task inputs and params that their code never references,
+    /// and config values that no task uses.
+    pub fn find_unused(&self) -> Result<Vec<Unused>> {
+        let mut unused = Vec::new();
+        // values we know are used, whose config refs we still have to follow:
+        let mut to_visit: Vec<AbstractValueId> = Vec::new();
+
+        for (i, task) in self.tasks.iter().enumerate().filter(|(_, task)| task.exists) {
+            let id = AbstractTaskId::from(i);
+            let code_vars = code_vars(self.strings.literals.get(task.code)?);
+            let is_used = |k: IdentId| -> Result<bool> {
+                let name = self.strings.idents.get(k)?;
+                Ok(task.referenced_vars.contains(&k)
+                    || name.starts_with(MODULE_VAR_PREFIX)
+                    // the code may include funcs, whose vars aren't in `referenced_vars`:
+                    || code_vars.contains(name))
+            };
+            for (k, _) in &task.vars.inputs {
+                if !is_used(*k)? {
+                    unused.push(Unused::Input(id, *k));
+                }
+            }
+            for (k, _) in &task.vars.params {
+                if !is_used(*k)? {
+                    unused.push(Unused::Param(id, *k));
+                }
+            }
+            let vars = task.vars.inputs.iter().chain(&task.vars.outputs).chain(&task.vars.params);
+            to_visit.extend(vars.map(|(_, v)| *v));
+        }
+        let module_values = self.modules.iter().chain(self.module_revs.iter());
+        to_visit.extend(module_values.copied().flatten());
+
+        let mut used = HashSet::default();
+        let mut refs = Vec::new();
+        while let Some(v) = to_visit.pop() {
+            config_refs(self.get_value(v)?, &mut refs);
+            for k in refs.drain(..) {
+                if used.insert(k) {
+                    to_visit.extend(self.get_config_value(k));
+                }
+            }
+        }
+        let mut unused_config: Vec<IdentId> =
+            self.config.keys().filter(|k| !used.contains(*k)).copied().collect();
+        unused_config.sort_unstable_by_key(|k| usize::from(*k));
+        unused.extend(unused_config.into_iter().map(Unused::Config));
+        Ok(unused)
+    }
+
+    /// Names of the config values the inputs and params of `task` use,
+    /// directly or through other config values.
+    pub fn config_values_used_by(&self, task: AbstractTaskId) -> Result<HashSet<IdentId>> {
+        let task = self.get_task(task)?;
+        let mut to_visit: Vec<AbstractValueId> =
+            task.vars.inputs.iter().chain(&task.vars.params).map(|(_, v)| *v).collect();
+        let mut used = HashSet::default();
+        let mut refs = Vec::new();
+        while let Some(v) = to_visit.pop() {
+            config_refs(self.get_value(v)?, &mut refs);
+            for k in refs.drain(..) {
+                if used.insert(k) {
+                    to_visit.extend(self.get_config_value(k));
+                }
+            }
+        }
+        Ok(used)
+    }
+}
+
+/// Add the names of config values referred to by `value` to `refs`.
+fn config_refs(value: &Value, refs: &mut Vec<IdentId>) {
+    let mut add = |direct: &DirectValue| {
+        let base = match direct {
+            DirectValue::Simple(base) => base,
+            DirectValue::Graft(base, _) => base,
+            DirectValue::GlobGraft(base, _, _) => base,
+        };
+        match base {
+            BaseValue::Config(k) => refs.push(*k),
+            BaseValue::Interp(_, ks) => refs.extend(ks),
+            BaseValue::Literal(_) | BaseValue::Task(_, _) => (),
+        }
+    };
+    match value {
+        Value::Direct(direct) => add(direct),
+        Value::Branched(vals) => vals.iter().for_each(|(_, direct)| add(direct)),
+    }
+}
+
+/// Names of the variables `code` uses as `$name` or `${name...`,
+/// outside of comments and single quotes.
+fn code_vars(code: &str) -> HashSet<&str> {
+    let tokens = shell::statements(code).into_iter().flatten();
+    tokens
+        .filter_map(|token| match token {
+            Token::Var { name, .. } => Some(name),
+            Token::Word(_) => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn test_find_unused() -> Result<()> {
+        let text = "global {\n  used=1\n  indirect=2\n  via=\"x/$indirect\"\n  unused=3\n}\n\
+                    task a > out :: p=$used q=$via r=1 { echo $p ${q} > $out }\n\
+                    task b < in=$out@a { cat in }\n";
+        let mut wf = Workflow::default();
+        wf.load(syntax::parse(text)?, Path::new("."))?;
+        let unused: Vec<String> = wf
+            .find_unused()?
+            .iter()
+            .map(|u| u.describe(&wf.strings))
+            .collect::<Result<_>>()?;
+        assert_eq!(
+            unused,
+            vec![
+                "Task a never uses its param $r",
+                "Task b never uses its input $in",
+                "Config value unused isn't used by any task",
+            ]
+        );
+        let vars = |code| -> Vec<&str> {
+            let mut vars: Vec<_> = code_vars(code).into_iter().collect();
+            vars.sort_unstable();
+            vars
+        };
+        assert_eq!(vars("echo $x ${y:-$z} $xy x"), ["x", "xy", "y", "z"]);
+        assert_eq!(vars("echo '$x' # $y\necho \"$z\""), ["z"]);
+        Ok(())
+    }
+}
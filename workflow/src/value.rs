@@ -1,12 +1,21 @@
+use std::borrow::Cow;
+
 use anyhow::Result;
 
-use intern::InternStr;
+use intern::{GetStr, InternStr};
 use syntax::ast;
 
-use crate::{AbstractTaskId, BranchSpec, IdentId, LiteralId, WorkflowStrings};
+use crate::{
+    AbstractTaskId, BranchSpec, BranchpointId, Error, IdentId, LiteralId, WorkflowStrings,
+    BRANCH_DELIM, BRANCH_KV_DELIM,
+};
+
+/// Prefixes of an environment variable reference in a value: `$ENV{NAME}` or `${env:NAME}`.
+const ENV_VAR_PREFIXES: [&str; 2] = ["$ENV{", "${env:"];
 
 /// The base type of value, with no branching or grafting.
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum BaseValue {
     /// A literal value
     Literal(LiteralId),
@@ -20,15 +29,20 @@ pub enum BaseValue {
 
 /// A single (non-branching) right-hand-side value in a config file.
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum DirectValue {
     /// A simple value that doesn't need to evaluate a branch.
     Simple(BaseValue),
     /// A value to be pulled from a specific branch.
     Graft(BaseValue, BranchSpec),
+    /// A value to be pulled from every branch of some branchpoints (e.g. `[Dataset: *]`),
+    /// producing a list. The branch spec holds any branchpoints that aren't globbed.
+    GlobGraft(BaseValue, BranchSpec, Vec<BranchpointId>),
 }
 
 /// Any right-hand-side value in a workflow file.
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Value {
     /// Non-branching value
     Direct(DirectValue),
@@ -65,6 +79,26 @@ pub fn create_value(
             }
             Ok(Value::Branched(flattened_vals))
         }
+        Sequence {
+            branchpoint,
+            start,
+            end,
+            step,
+        } => {
+            if step == 0 || start > end {
+                return Err(Error::EmptySequence(branchpoint.to_owned()).into());
+            }
+            let k = strings.branchpoints.intern(branchpoint)?;
+            let mut vals = Vec::new();
+            for n in (start..=end).step_by(step as usize) {
+                let n = n.to_string();
+                let v = strings.idents.intern(&n)?;
+                strings.baselines.add(k, v);
+                let val = strings.literals.intern(&n).map(BaseValue::Literal)?;
+                vals.push((BranchSpec::simple(k, v), DirectValue::Simple(val)));
+            }
+            Ok(Value::Branched(vals))
+        }
         direct_rhs => Ok(Value::Direct(create_direct(strings, lhs, direct_rhs)?)),
     }
 }
@@ -79,8 +113,7 @@ fn create_direct(
         GraftedVariable { name, branch } => {
             let name = strings.idents.intern(name)?;
             let value = BaseValue::Config(name);
-            let branch = create_branch(strings, branch)?;
-            Ok(DirectValue::Graft(value, branch))
+            create_graft(strings, value, branch)
         }
         GraftedTaskOutput {
             task,
@@ -90,15 +123,13 @@ fn create_direct(
             let task = strings.tasks.intern(task)?;
             let output = strings.idents.intern(output)?;
             let value = BaseValue::Task(task, output);
-            let branch = create_branch(strings, branch)?;
-            Ok(DirectValue::Graft(value, branch))
+            create_graft(strings, value, branch)
         }
         ShorthandGraftedTaskOutput { task, branch } => {
             let task = strings.tasks.intern(task)?;
             let output = strings.idents.intern(lhs)?;
             let value = BaseValue::Task(task, output);
-            let branch = create_branch(strings, branch)?;
-            Ok(DirectValue::Graft(value, branch))
+            create_graft(strings, value, branch)
         }
         _ => Ok(DirectValue::Simple(create_base(strings, lhs, rhs)?)),
     }
@@ -109,7 +140,10 @@ fn create_base(strings: &mut WorkflowStrings, lhs: ast::Ident, rhs: ast::Rhs) ->
     use ast::Rhs::*;
     match rhs {
         Unbound             => strings.literals.intern(lhs).map(BaseValue::Literal),
-        Literal { val }     => strings.literals.intern(val).map(BaseValue::Literal),
+        Literal { val }     => {
+            let val = expand_env_vars(val, lhs)?;
+            strings.literals.intern(&val).map(BaseValue::Literal)
+        }
         Variable { name }   => strings.idents.intern(name).map(BaseValue::Config),
         ShorthandVariable   => strings.idents.intern(lhs).map(BaseValue::Config),
         TaskOutput { task, output } => {
@@ -123,7 +157,8 @@ fn create_base(strings: &mut WorkflowStrings, lhs: ast::Ident, rhs: ast::Rhs) ->
             Ok(BaseValue::Task(task, output))
         }
         Interp { text, vars } => {
-            let val = strings.literals.intern(text)?;
+            let text = expand_env_vars(text, lhs)?;
+            let val = strings.literals.intern(&text)?;
             let mut vars: Vec<IdentId> = vars
                 .into_iter()
                 .map(|var| strings.idents.intern(var))
@@ -139,12 +174,258 @@ fn create_base(strings: &mut WorkflowStrings, lhs: ast::Ident, rhs: ast::Rhs) ->
     }
 }
 
-fn create_branch(strings: &mut WorkflowStrings, branch: ast::Branch) -> Result<BranchSpec> {
+fn create_graft(
+    strings: &mut WorkflowStrings,
+    value: BaseValue,
+    branch: ast::Branch,
+) -> Result<DirectValue> {
     let mut spec = BranchSpec::default();
+    let mut globs = Vec::new();
     for (k, v) in branch {
         let k = strings.branchpoints.intern(k)?;
-        let v = strings.idents.intern(v)?;
-        spec.insert(k, v);
+        if v == ast::BRANCH_GLOB {
+            globs.push(k);
+        } else {
+            let v = strings.idents.intern(v)?;
+            spec.insert(k, v);
+        }
+    }
+    if globs.is_empty() {
+        Ok(DirectValue::Graft(value, spec))
+    } else {
+        Ok(DirectValue::GlobGraft(value, spec, globs))
+    }
+}
+
+impl Value {
+    /// Human-readable version of this value, in (roughly) the syntax of the config file,
+    /// e.g. `$out@task[Profile: release]` or `(Os: mac=brew windows=choco)`.
+    pub fn describe(&self, strings: &WorkflowStrings) -> Result<String> {
+        let vals = match self {
+            Self::Direct(val) => return val.describe(strings),
+            Self::Branched(vals) => vals,
+        };
+        let mut s = String::from("(");
+        // a single branchpoint can use the same notation as the config file:
+        let first = vals.first().and_then(|(branch, _)| branch.specified().next());
+        let single = vals.iter().all(|(branch, _)| {
+            let mut ks = branch.specified().map(|(k, _)| k);
+            ks.next() == first.map(|(k, _)| k) && ks.next().is_none()
+        });
+        if let Some((k, _)) = first.filter(|_| single) {
+            s.push_str(strings.branchpoints.get(k)?);
+            s.push(':');
+        }
+        for (i, (branch, val)) in vals.iter().enumerate() {
+            if i > 0 || single {
+                s.push(' ');
+            }
+            for (j, (k, v)) in branch.specified().enumerate() {
+                if j > 0 {
+                    s.push(BRANCH_DELIM);
+                }
+                if !single {
+                    s.push_str(strings.branchpoints.get(k)?);
+                    s.push(BRANCH_KV_DELIM);
+                }
+                s.push_str(strings.idents.get(v)?);
+            }
+            s.push('=');
+            s.push_str(&val.describe(strings)?);
+        }
+        s.push(')');
+        Ok(s)
+    }
+}
+
+impl DirectValue {
+    /// Human-readable version of this value, as in [`Value::describe`].
+    pub fn describe(&self, strings: &WorkflowStrings) -> Result<String> {
+        let (base, branch, globs) = match self {
+            Self::Simple(base) => return base.describe(strings),
+            Self::Graft(base, branch) => (base, branch, &[][..]),
+            Self::GlobGraft(base, branch, globs) => (base, branch, &globs[..]),
+        };
+        let mut grafts = Vec::with_capacity(branch.len() + globs.len());
+        for (k, v) in branch.specified() {
+            grafts.push(format!(
+                "{}: {}",
+                strings.branchpoints.get(k)?,
+                strings.idents.get(v)?
+            ));
+        }
+        for k in globs {
+            grafts.push(format!(
+                "{}: {}",
+                strings.branchpoints.get(*k)?,
+                ast::BRANCH_GLOB
+            ));
+        }
+        Ok(format!(
+            "{}[{}]",
+            base.describe(strings)?,
+            grafts.join(", ")
+        ))
+    }
+}
+
+impl BaseValue {
+    fn describe(&self, strings: &WorkflowStrings) -> Result<String> {
+        Ok(match self {
+            Self::Literal(lit) => {
+                let lit = strings.literals.get(*lit)?;
+                if lit.is_empty() || lit.contains(char::is_whitespace) {
+                    format!("{lit:?}")
+                } else {
+                    lit.to_owned()
+                }
+            }
+            Self::Config(k) => format!("${}", strings.idents.get(*k)?),
+            Self::Task(task, output) => format!(
+                "${}@{}",
+                strings.idents.get(*output)?,
+                strings.tasks.get(*task)?
+            ),
+            Self::Interp(lit, _) => format!("{:?}", strings.literals.get(*lit)?),
+        })
+    }
+}
+
+/// Names of the environment variables referenced (`$ENV{NAME}` or `${env:NAME}`) in `text`,
+/// whose values are expanded into the workflow's values when it's loaded.
+pub fn env_var_names(text: &str) -> impl Iterator<Item = &str> {
+    text.match_indices('$')
+        .filter_map(|(i, _)| env_var_ref(&text[i..]))
+        .map(|(name, _)| name)
+}
+
+/// Replace environment variable references (`$ENV{NAME}` or `${env:NAME}`) in `text`
+/// with the variables' values. It's an error if any of them isn't set.
+/// `lhs` is the name of the value `text` belongs to, for error messages.
+pub(crate) fn expand_env_vars<'a>(text: &'a str, lhs: &str) -> Result<Cow<'a, str>, Error> {
+    if !ENV_VAR_PREFIXES.iter().any(|prefix| text.contains(prefix)) {
+        return Ok(Cow::Borrowed(text));
+    }
+    let mut expanded = String::with_capacity(text.len() * 2);
+    let mut rest = text;
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        match env_var_ref(rest) {
+            Some((name, len)) => {
+                let val = std::env::var(name)
+                    .map_err(|_| Error::UnsetEnvVar(name.to_owned(), lhs.to_owned()))?;
+                expanded.push_str(&val);
+                rest = &rest[len..];
+            }
+            None => {
+                // a config variable, or a '$' that isn't part of a reference at all:
+                expanded.push('$');
+                rest = &rest[1..];
+            }
+        }
+    }
+    expanded.push_str(rest);
+    Ok(Cow::Owned(expanded))
+}
+
+/// If `text` starts with an environment variable reference,
+/// the name of the variable and the length of the reference.
+fn env_var_ref(text: &str) -> Option<(&str, usize)> {
+    for prefix in ENV_VAR_PREFIXES {
+        if let Some(after) = text.strip_prefix(prefix) {
+            let name = &after[..after.find('}')?];
+            let mut chars = name.chars();
+            let valid = chars.next().is_some_and(|c| c == '_' || c.is_alphabetic())
+                && chars.all(|c| c == '_' || c.is_alphanumeric());
+            if valid {
+                return Some((name, prefix.len() + name.len() + 1));
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn test_expand_env_vars() -> Result<()> {
+        std::env::set_var("HR_TEST_ENV_VAR", "/home/hr");
+        assert_eq!(expand_env_vars("plain/$var", "x")?, "plain/$var");
+        assert_eq!(
+            expand_env_vars("$ENV{HR_TEST_ENV_VAR}/src/$var", "x")?,
+            "/home/hr/src/$var"
+        );
+        assert_eq!(
+            expand_env_vars("${env:HR_TEST_ENV_VAR}+${env:HR_TEST_ENV_VAR}", "x")?,
+            "/home/hr+/home/hr"
+        );
+        // not a valid reference, so left alone:
+        assert_eq!(expand_env_vars("$ENV{not valid}", "x")?, "$ENV{not valid}");
+        assert!(expand_env_vars("$ENV{HR_TEST_UNSET_ENV_VAR}", "x").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_describe() -> Result<()> {
+        let text = "global {\n\
+                      lit=plain\n\
+                      sentence=\"two words\"\n\
+                      renamed=$lit\n\
+                      interp=\"the $lit\"\n\
+                      out=$out@build\n\
+                      grafted=$out@build[Profile: release, Os: *]\n\
+                      os=(Os: mac=brew linux=$lit)\n\
+                      nested=(Os: mac=(Profile: debug=x release=y) linux=z)\n\
+                    }\n\
+                    task build > out :: p=(Profile: debug release) { touch $out }\n";
+        let mut wf = crate::Workflow::default();
+        wf.load(syntax::parse(text)?, std::path::Path::new("."))?;
+        let mut describe = |name: &str| -> Result<String> {
+            let k = wf.strings.idents.intern(name)?;
+            let v = wf.get_config_value(k).expect("config value should exist");
+            wf.get_value(v)?.describe(&wf.strings)
+        };
+        assert_eq!(describe("lit")?, "plain");
+        assert_eq!(describe("sentence")?, "\"two words\"");
+        assert_eq!(describe("renamed")?, "$lit");
+        assert_eq!(describe("interp")?, "\"the $lit\"");
+        assert_eq!(describe("out")?, "$out@build");
+        assert_eq!(describe("grafted")?, "$out@build[Profile: release, Os: *]");
+        assert_eq!(describe("os")?, "(Os: mac=brew linux=$lit)");
+        assert_eq!(
+            describe("nested")?,
+            "(Profile.debug+Os.mac=x Profile.release+Os.mac=y Os.linux=z)"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_sequence() -> Result<()> {
+        let text = "global {\n  seed=(Seed: 1..6..2)\n}\n";
+        let mut wf = crate::Workflow::default();
+        wf.load(
+            syntax::parse_ducttape(text, None)?.0,
+            std::path::Path::new("."),
+        )?;
+        let k = wf.strings.idents.intern("seed")?;
+        let v = wf.get_config_value(k).expect("config value should exist");
+        assert_eq!(
+            wf.get_value(v)?.describe(&wf.strings)?,
+            "(Seed: 1=1 3=3 5=5)"
+        );
+        let seed = wf.strings.branchpoints.intern("Seed")?;
+        assert_eq!(wf.strings.idents.get(wf.strings.baselines.get(seed))?, "1");
+        assert_eq!(wf.strings.baselines.values(seed).len(), 3);
+
+        let text = "global {\n  seed=(Seed: 5..1)\n}\n";
+        let mut wf = crate::Workflow::default();
+        let e = wf.load(
+            syntax::parse_ducttape(text, None)?.0,
+            std::path::Path::new("."),
+        );
+        assert!(e.unwrap_err().to_string().contains("'Seed' has no branches"));
+        Ok(())
     }
-    Ok(spec)
 }
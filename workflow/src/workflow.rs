@@ -3,15 +3,17 @@ use std::path::{Path, PathBuf};
 
 use intern::{GetStr, InternStr};
 use syntax::ast;
-use util::{HashMap, Hasher, IdVec, PathEncodingError};
+use util::{HashMap, HashSet, Hasher, IdVec, PathEncodingError};
 
 use crate::{
-    branch::parse_compact_branch_str, AbstractTaskId, AbstractValueId, BranchSpec, Error, IdentId,
-    LiteralId, ModuleId, Plan, Task, Value, WorkflowStrings,
+    branch::parse_compact_branch_str, shell, AbstractTaskId, AbstractValueId, BaseValue,
+    BranchSpec, BranchpointId, Constraint, DirectValue, Error, Errors, IdentId, LiteralId,
+    ModuleId, Plan, Task, Value, WorkflowStrings, MODULE_COMMIT_SUFFIX, MODULE_VAR_PREFIX,
 };
 
 /// Used to initialize collections later in the process.
 #[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SizeHints {
     pub max_inputs: u8,
     pub max_outputs: u8,
@@ -19,24 +21,54 @@ pub struct SizeHints {
     pub max_vars: u8,
 }
 
+impl SizeHints {
+    fn update(&mut self, task: &Task) {
+        let num_inputs = task.vars.inputs.len() as u8;
+        let num_outputs = task.vars.outputs.len() as u8;
+        let num_params = task.vars.params.len() as u8;
+        let num_vars = num_inputs + num_outputs + num_params;
+        self.max_inputs = self.max_inputs.max(num_inputs);
+        self.max_outputs = self.max_outputs.max(num_outputs);
+        self.max_params = self.max_params.max(num_params);
+        self.max_vars = self.max_vars.max(num_vars);
+    }
+}
+
 /// Contains all the information about a workflow,
 /// in a form that can be used to generate a traversal to run.
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Workflow {
     /// All strings defined in the config file
     pub strings: WorkflowStrings,
     /// lookup global config values by name
-    config: HashMap<IdentId, AbstractValueId>,
+    pub(crate) config: HashMap<IdentId, AbstractValueId>,
     /// all tasks defined in the config file
-    tasks: IdVec<AbstractTaskId, Task>,
+    pub(crate) tasks: IdVec<AbstractTaskId, Task>,
     /// all plans defined in the config file
     plans: Vec<(IdentId, Plan)>,
-    /// all modules defined in the config file
-    modules: IdVec<ModuleId, LiteralId>,
+    /// restrictions on which branches can be combined
+    constraints: Vec<Constraint>,
+    /// value of the path of each module defined in the config file
+    pub(crate) modules: IdVec<ModuleId, Option<AbstractValueId>>,
+    /// id of the implicit path param that tasks using each module get
+    module_path_params: IdVec<ModuleId, Option<IdentId>>,
+    /// value of each module's version to check out (`:: rev=...`), if it has one
+    pub(crate) module_revs: IdVec<ModuleId, Option<AbstractValueId>>,
+    /// id of the implicit commit param that tasks in each versioned module get
+    module_commit_params: IdVec<ModuleId, Option<IdentId>>,
     /// all values, including global config values and task variables
     values: IdVec<AbstractValueId, Value>,
+    /// bash functions defined in the config file (name, full definition)
+    funcs: Vec<(IdentId, LiteralId)>,
     /// sizes we'll use to allocate collections later
     sizes: SizeHints,
+    /// dir containing the config file, which relative module paths are relative to
+    config_dir: PathBuf,
+    /// tasks producing each output, by output name, literal path, and file name
+    output_index: HashMap<String, Vec<Producer>>,
+    /// tasks using each task's outputs
+    dependents: IdVec<AbstractTaskId, Vec<Dependent>>,
 }
 
 impl Default for Workflow {
@@ -46,32 +78,120 @@ impl Default for Workflow {
             config: HashMap::with_capacity_and_hasher(64, Hasher::default()),
             tasks: IdVec::with_capacity(16),
             plans: Vec::with_capacity(8),
+            constraints: Vec::new(),
             modules: IdVec::with_capacity(8),
+            module_path_params: IdVec::with_capacity(8),
+            module_revs: IdVec::with_capacity(8),
+            module_commit_params: IdVec::with_capacity(8),
             values: IdVec::with_capacity(128),
+            funcs: Vec::with_capacity(8),
             sizes: SizeHints::default(),
+            config_dir: PathBuf::new(),
+            output_index: HashMap::default(),
+            dependents: IdVec::with_capacity(16),
         }
     }
 }
 
+/// A task defined in more than one layered config file; the definition in the later file is used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TaskRedefinition {
+    pub task: AbstractTaskId,
+    /// index of the config file the task was first defined in.
+    pub first_layer: usize,
+    /// index of the config file that redefined it.
+    pub layer: usize,
+}
+
 impl Workflow {
     /// Load the given ast representations of blocks into this `Workflow`.
     /// `config_dir` is used to interpret relative paths to modules.
-    #[rustfmt::skip]
     pub fn load(&mut self, blocks: Vec<ast::Item>, config_dir: &Path) -> Result<()> {
+        self.load_layers(vec![(blocks, config_dir)])?;
+        Ok(())
+    }
+
+    /// Load the blocks of several config files, each with the dir containing it, in order.
+    /// Later files override global config values and plans from earlier ones,
+    /// and replace tasks with the same name; the replaced tasks are returned.
+    /// Relative literal module paths are relative to the dir of the file they're in;
+    /// any other relative paths are relative to the dir of the first file.
+    pub fn load_layers(
+        &mut self,
+        layers: Vec<(Vec<ast::Item>, &Path)>,
+    ) -> Result<Vec<TaskRedefinition>> {
+        let mut task_layers: HashMap<AbstractTaskId, usize> = HashMap::default();
+        let mut redefinitions = Vec::new();
+        for (layer, (blocks, config_dir)) in layers.into_iter().enumerate() {
+            if layer == 0 {
+                self.config_dir = config_dir.to_owned();
+            }
+            for task in self.add_blocks(blocks, config_dir)? {
+                match task_layers.insert(task, layer) {
+                    Some(first_layer) if first_layer != layer => {
+                        redefinitions.push(TaskRedefinition {
+                            task,
+                            first_layer,
+                            layer,
+                        });
+                    }
+                    _ => (),
+                }
+            }
+        }
+        self.finish_loading()?;
+        Ok(redefinitions)
+    }
+
+    /// Add the blocks of a single config file, returning the ids of the tasks it defines.
+    #[rustfmt::skip]
+    fn add_blocks(&mut self, blocks: Vec<ast::Item>, config_dir: &Path) -> Result<Vec<AbstractTaskId>> {
+        let mut tasks = Vec::new();
         for block in blocks {
             match block {
-                ast::Item::GlobalConfig(assts)  => self.add_config(assts)?,
-                ast::Item::Task(task)           => self.add_task(task)?,
+                ast::Item::GlobalConfig(config) => self.add_config(config.assignments)?,
+                ast::Item::Task(task)           => tasks.push(self.add_task(task)?),
                 ast::Item::Plan(plan)           => self.add_plan(plan)?,
-                ast::Item::Module(name, path)   => self.add_module(name, path, config_dir)?,
+                ast::Item::Func(func)           => self.add_func(func)?,
+                ast::Item::Constraint(c)        => self.add_constraint(&c)?,
+                ast::Item::Module(name, path, rev) => {
+                    self.add_module(name, path, config_dir)?;
+                    if let Some(rev) = rev {
+                        self.add_module_rev(name, rev)?;
+                    }
+                }
+                ast::Item::Comment(_)           => (),
                 _ => {
                     return Err(Error::Unsupported(
-                        "blocks other than config, task, plan, module".to_owned(),
+                        "blocks other than config, task, plan, module, func, constraint".to_owned(),
                     )
                     .into())
                 }
             }
         }
+        Ok(tasks)
+    }
+
+    /// Resolve everything that can only be resolved once all the blocks have been added.
+    fn finish_loading(&mut self) -> Result<()> {
+        self.add_module_params()?;
+        self.add_funcs_to_tasks()?;
+        self.check_plan_baselines()?;
+        self.check_constraints()?;
+        self.resolve_plan_includes()?;
+        self.index_outputs()?;
+        self.index_dependents()?;
+        self.check_grafts()?;
+        // branches can be defined after plans, so plan globs have to be expanded last:
+        for (name, plan) in &mut self.plans {
+            let name = self.strings.idents.get(*name)?;
+            plan.expand_globs(&self.strings)
+                .with_context(|| format!("while expanding branch globs in plan \"{name}\""))?;
+        }
+        for (name, plan) in &self.plans {
+            self.check_plan_constraints(*name, plan)?;
+        }
         Ok(())
     }
 
@@ -81,17 +201,43 @@ impl Workflow {
         &self.sizes
     }
 
-    /// Get a string containing the path to the module with the given id.
+    /// Dir containing the config file; relative module paths are relative to it.
+    #[inline]
+    pub fn config_dir(&self) -> &Path {
+        &self.config_dir
+    }
+
+    /// Id of the implicit param containing the module's path that tasks using it get.
+    #[inline]
+    pub fn module_path_param(&self, module: ModuleId) -> Result<IdentId, Error> {
+        self.module_path_params
+            .get(module)
+            .copied()
+            .flatten()
+            .ok_or(Error::ModuleNotFound(module))
+    }
+
+    /// Get the value of the version to check out for the module with the given id,
+    /// or None if the module runs in place.
+    #[inline]
+    pub fn get_module_rev(&self, module: ModuleId) -> Option<AbstractValueId> {
+        self.module_revs.get(module).copied().flatten()
+    }
+
+    /// Id of the implicit commit param that tasks in the given module get,
+    /// or None if the module isn't versioned.
     #[inline]
-    pub fn get_module_path(&self, module: ModuleId) -> Result<&str> {
-        let lit_id = self.modules.get(module).ok_or(Error::ModuleNotFound(module))?;
-        self.strings.literals.get(*lit_id)
+    pub fn module_commit_param(&self, module: ModuleId) -> Option<IdentId> {
+        self.module_commit_params.get(module).copied().flatten()
     }
 
     /// Get the task with the given id.
     #[inline]
     pub fn get_task(&self, task: AbstractTaskId) -> Result<&Task, Error> {
-        self.tasks.get(task).filter(|t| t.exists).ok_or(Error::TaskNotFound(task))
+        self.tasks.get(task).filter(|t| t.exists).ok_or_else(|| {
+            let name = self.strings.tasks.get(task).unwrap_or_default();
+            Error::TaskNotFound(name.to_owned(), self.closest_task(name).map(str::to_owned))
+        })
     }
 
     /// Get the value with the given id.
@@ -105,6 +251,21 @@ impl Workflow {
         self.config.get(&ident).copied()
     }
 
+    /// Iterate over the global config values, with their names, in no particular order.
+    pub fn config_values(&self) -> impl Iterator<Item = (IdentId, AbstractValueId)> + '_ {
+        self.config.iter().map(|(k, v)| (*k, *v))
+    }
+
+    /// Set global config value `name` to the literal `val`, replacing any value
+    /// of the same name from the config file (e.g. from '-D name=val' on the command line).
+    /// Returns true if there was a value to replace.
+    pub fn override_config_value(&mut self, name: &str, val: &str) -> Result<bool> {
+        let v = self.strings.create_value(name, ast::Rhs::Literal { val })?;
+        let vid = self.values.push(v);
+        let k = self.strings.idents.intern(name)?;
+        Ok(self.config.insert(k, vid).is_some())
+    }
+
     /// Total number of values defined (including task variables and config values).
     #[inline]
     pub fn num_values(&self) -> usize {
@@ -118,7 +279,57 @@ impl Workflow {
                 return Ok(plan);
             }
         }
-        Err(Error::PlanNotFound(plan_name))
+        let name = self.strings.idents.get(plan_name).unwrap_or_default();
+        Err(Error::PlanNotFound(
+            name.to_owned(),
+            self.closest_plan(name).map(str::to_owned),
+        ))
+    }
+
+    /// Names of the tasks defined in the config file, in the order they were first seen.
+    pub fn task_names(&self) -> impl Iterator<Item = &str> {
+        let tasks = self.strings.tasks.iter();
+        let defined = tasks.filter(|(k, _)| self.tasks.get(*k).is_some_and(|t| t.exists));
+        defined.map(|(_, s)| s)
+    }
+
+    /// Name of the defined task most like `name`, if it looks like a typo of one.
+    pub fn closest_task(&self, name: &str) -> Option<&str> {
+        util::closest_match(name, self.task_names())
+    }
+
+    /// Name of the defined plan most like `name`, if it looks like a typo of one.
+    pub fn closest_plan(&self, name: &str) -> Option<&str> {
+        let plans = self.plans.iter().filter_map(|(k, _)| self.strings.idents.get(*k).ok());
+        util::closest_match(name, plans)
+    }
+
+    /// Outputs that `name` refers to: outputs with that var name, or with a literal value
+    /// that's that path or has that file name.
+    pub fn producers(&self, name: &str) -> &[Producer] {
+        self.output_index.get(name).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// Output name or path most like `name`, if it looks like a typo of one.
+    pub fn closest_output(&self, name: &str) -> Option<&str> {
+        util::closest_match(name, self.output_index.keys().map(String::as_str))
+    }
+
+    /// Name of the global config value most like `name`, if it looks like a typo of one.
+    pub fn closest_config_value(&self, name: &str) -> Option<&str> {
+        let values = self.config.keys().filter_map(|k| self.strings.idents.get(*k).ok());
+        util::closest_match(name, values)
+    }
+
+    /// Iterate over all plans defined in the config file, with their names.
+    pub fn plans(&self) -> impl Iterator<Item = (IdentId, &Plan)> {
+        self.plans.iter().map(|(name, plan)| (*name, plan))
+    }
+
+    /// Restrictions on which branches can be combined, in the order they were defined.
+    #[inline]
+    pub fn constraints(&self) -> &[Constraint] {
+        &self.constraints
     }
 
     /// Parse "compact" branch string (i.e. with "Baseline.baseline" standing in for baseline branches)
@@ -129,8 +340,566 @@ impl Workflow {
     }
 }
 
+/// A task output that a goal given by name or path (e.g. `--produces installer.pkg`) refers to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Producer {
+    /// the task with the output.
+    pub task: AbstractTaskId,
+    /// the output var.
+    pub output: IdentId,
+    /// branch the output has this path on, if its value branches (empty otherwise).
+    pub branch: BranchSpec,
+}
+
+/// A task that uses another task's outputs, seen from the task whose outputs are used.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Dependent {
+    /// the task using the outputs.
+    pub task: AbstractTaskId,
+    /// branches the outputs are grafted from, which don't follow the dependent's branch.
+    pub graft: BranchSpec,
+    /// branchpoints whose every branch is used at once (e.g. `$out@task[Dataset: *]`).
+    pub globs: Vec<BranchpointId>,
+}
+
+/// A task input or param that uses another task's output, directly or through config values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TaskEdge {
+    /// the input or param of the dependent task.
+    pub var: IdentId,
+    /// the task whose output is used.
+    pub task: AbstractTaskId,
+    /// the output that's used.
+    pub output: IdentId,
+    /// true if the output is taken from a specific branch, or from every branch (a graft).
+    pub grafted: bool,
+}
+
+/// A branch named in a graft, plan baseline, or constraint that isn't defined in the config.
+struct UndefinedBranch {
+    branchpoint: String,
+    /// the branch name, or `*` for a glob.
+    branch: String,
+    /// true if the branchpoint has no defined branches at all.
+    no_branchpoint: bool,
+    /// the closest defined branchpoint (or branch, if the branchpoint is defined).
+    suggestion: Option<String>,
+}
+
+// task graph ///////////////////////
+impl Workflow {
+    /// Every use of another task's output by the given task's inputs or params.
+    pub fn task_edges(&self, task: AbstractTaskId) -> Result<Vec<TaskEdge>> {
+        let task = self.get_task(task)?;
+        let mut edges = Vec::with_capacity(task.vars.inputs.len());
+        for (var, value) in task.vars.inputs.iter().chain(&task.vars.params) {
+            let mut values = vec![*value];
+            let mut seen = HashSet::default();
+            while let Some(value) = values.pop() {
+                if !seen.insert(value) {
+                    continue;
+                }
+                let directs: Vec<&DirectValue> = match self.get_value(value)? {
+                    Value::Direct(direct) => vec![direct],
+                    Value::Branched(branches) => branches.iter().map(|(_, d)| d).collect(),
+                };
+                for direct in directs {
+                    let (base, grafted) = match direct {
+                        DirectValue::Simple(base) => (base, false),
+                        DirectValue::Graft(base, _) | DirectValue::GlobGraft(base, _, _) => {
+                            (base, true)
+                        }
+                    };
+                    match base {
+                        BaseValue::Task(task, output) => {
+                            let edge = TaskEdge {
+                                var: *var,
+                                task: *task,
+                                output: *output,
+                                grafted,
+                            };
+                            if !edges.contains(&edge) {
+                                edges.push(edge);
+                            }
+                        }
+                        BaseValue::Config(ident) => values.extend(self.get_config_value(*ident)),
+                        BaseValue::Interp(_, idents) => {
+                            values.extend(idents.iter().filter_map(|k| self.get_config_value(*k)));
+                        }
+                        BaseValue::Literal(_) => (),
+                    }
+                }
+            }
+        }
+        Ok(edges)
+    }
+
+    /// Branchpoints that the given task's own variables branch on, directly or through
+    /// config values (not including ones it only inherits from the tasks it depends on).
+    pub fn task_branchpoints(&self, task: AbstractTaskId) -> Result<Vec<BranchpointId>> {
+        self.own_branchpoints(task, false)
+    }
+
+    /// For each task, every branchpoint its realizations could depend on: the ones its own
+    /// variables mention, including ones they graft, and the ones mentioned by the tasks it
+    /// depends on, transitively. A realization's branch for any other branchpoint
+    /// makes no difference to it.
+    pub fn upstream_branchpoints(&self) -> Result<IdVec<AbstractTaskId, Vec<BranchpointId>>> {
+        let mut upstream: Vec<Vec<BranchpointId>> = vec![Vec::new(); self.tasks.len()];
+        let mut deps: Vec<Vec<AbstractTaskId>> = vec![Vec::new(); self.tasks.len()];
+        for (i, task) in self.tasks.iter().enumerate() {
+            if task.exists {
+                upstream[i] = self.own_branchpoints(AbstractTaskId::from(i), true)?;
+                deps[i] = self.task_dependencies(AbstractTaskId::from(i))?;
+            }
+        }
+        // tasks can depend on tasks defined after them, so repeat until nothing changes:
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (i, task_deps) in deps.iter().enumerate() {
+                for dep in task_deps {
+                    let Some(dep_ks) = upstream.get(usize::from(*dep)) else {
+                        continue;
+                    };
+                    let inherited: Vec<BranchpointId> =
+                        dep_ks.iter().filter(|k| !upstream[i].contains(k)).copied().collect();
+                    changed |= !inherited.is_empty();
+                    upstream[i].extend(inherited);
+                }
+            }
+        }
+        let mut ids = IdVec::with_capacity(upstream.len());
+        for mut ks in upstream {
+            ks.sort_unstable_by_key(|k| usize::from(*k));
+            ids.push(ks);
+        }
+        Ok(ids)
+    }
+
+    /// Branchpoints that the given task's own variables branch on, directly or through
+    /// config values. If `include_fixed`, also the ones that are fixed by grafts,
+    /// i.e. every branchpoint they mention.
+    fn own_branchpoints(
+        &self,
+        task: AbstractTaskId,
+        include_fixed: bool,
+    ) -> Result<Vec<BranchpointId>> {
+        let task = self.get_task(task)?;
+        let vars = task.vars.inputs.iter().chain(&task.vars.outputs).chain(&task.vars.params);
+        // values to visit, with the branchpoints that grafts have fixed along the way:
+        let mut values: Vec<(AbstractValueId, Vec<BranchpointId>)> =
+            vars.map(|(_, v)| (*v, Vec::new())).collect();
+        let mut seen = HashSet::default();
+        let mut branchpoints = Vec::new();
+        let mut grafted = Vec::new();
+        while let Some((value, fixed)) = values.pop() {
+            if !seen.insert((value, fixed.clone())) {
+                continue;
+            }
+            let mut follow = |direct: &DirectValue| {
+                let mut fixed = fixed.clone();
+                let base = match direct {
+                    DirectValue::Simple(base) => base,
+                    DirectValue::Graft(base, branch) => {
+                        fixed.extend(branch.specified().map(|(k, _)| k));
+                        base
+                    }
+                    DirectValue::GlobGraft(base, branch, globs) => {
+                        fixed.extend(branch.specified().map(|(k, _)| k));
+                        fixed.extend(globs);
+                        base
+                    }
+                };
+                if include_fixed {
+                    grafted.extend(fixed.iter().copied());
+                }
+                let refs: Vec<IdentId> = match base {
+                    BaseValue::Config(ident) => vec![*ident],
+                    BaseValue::Interp(_, idents) => idents.clone(),
+                    BaseValue::Task(..) | BaseValue::Literal(_) => Vec::new(),
+                };
+                for k in refs {
+                    if let Some(value) = self.get_config_value(k) {
+                        values.push((value, fixed.clone()));
+                    }
+                }
+            };
+            match self.get_value(value)? {
+                Value::Direct(direct) => follow(direct),
+                Value::Branched(branches) => {
+                    for (branch, direct) in branches {
+                        for k in branch.specified().map(|(k, _)| k) {
+                            if (include_fixed || !fixed.contains(&k)) && !branchpoints.contains(&k)
+                            {
+                                branchpoints.push(k);
+                            }
+                        }
+                        follow(direct);
+                    }
+                }
+            }
+        }
+        for k in grafted {
+            if !branchpoints.contains(&k) {
+                branchpoints.push(k);
+            }
+        }
+        branchpoints.sort_unstable_by_key(|k| usize::from(*k));
+        Ok(branchpoints)
+    }
+
+    /// Ids of all tasks whose outputs are used by the given task's inputs or params,
+    /// whether directly or through config values.
+    pub fn task_dependencies(&self, task: AbstractTaskId) -> Result<Vec<AbstractTaskId>> {
+        let task = self.get_task(task)?;
+        let mut deps = Vec::with_capacity(task.vars.inputs.len());
+        for (_, value) in task.vars.inputs.iter().chain(&task.vars.params) {
+            self.add_value_dependencies(*value, &mut deps)?;
+        }
+        deps.sort_unstable_by_key(|id| usize::from(*id));
+        deps.dedup();
+        Ok(deps)
+    }
+
+    /// Every use of the given task's outputs by other tasks' inputs or params,
+    /// directly or through config values.
+    pub fn dependents(&self, task: AbstractTaskId) -> &[Dependent] {
+        self.dependents.get(task).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// Ids of all tasks that transitively depend on any of `roots` (not including `roots`),
+    /// ordered so that each task comes after all of the tasks it depends on.
+    pub fn downstream_tasks(&self, roots: &[AbstractTaskId]) -> Result<Vec<AbstractTaskId>> {
+        // find everything downstream of the roots:
+        let mut affected: HashSet<AbstractTaskId> = roots.iter().copied().collect();
+        let mut queue = roots.to_vec();
+        while let Some(task) = queue.pop() {
+            for dependent in self.dependents(task) {
+                if affected.insert(dependent.task) {
+                    queue.push(dependent.task);
+                }
+            }
+        }
+
+        let mut deps = Vec::with_capacity(affected.len());
+        for (i, task) in self.tasks.iter().enumerate() {
+            let id = AbstractTaskId::from(i);
+            if task.exists && affected.contains(&id) {
+                deps.push((id, self.task_dependencies(id)?));
+            }
+        }
+
+        // then sort it, so that tasks come after their dependencies:
+        let mut done: HashSet<AbstractTaskId> = roots.iter().copied().collect();
+        let mut sorted = Vec::with_capacity(affected.len() - done.len());
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (id, task_deps) in &deps {
+                if affected.contains(id)
+                    && !done.contains(id)
+                    && task_deps.iter().all(|d| done.contains(d) || !affected.contains(d))
+                {
+                    done.insert(*id);
+                    sorted.push(*id);
+                    changed = true;
+                }
+            }
+        }
+        Ok(sorted)
+    }
+
+    fn add_value_dependencies(
+        &self,
+        value: AbstractValueId,
+        deps: &mut Vec<AbstractTaskId>,
+    ) -> Result<()> {
+        match self.get_value(value)? {
+            Value::Direct(direct) => self.add_direct_dependencies(direct, deps),
+            Value::Branched(branches) => {
+                for (_, direct) in branches {
+                    self.add_direct_dependencies(direct, deps)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn add_direct_dependencies(
+        &self,
+        direct: &DirectValue,
+        deps: &mut Vec<AbstractTaskId>,
+    ) -> Result<()> {
+        let (DirectValue::Simple(base)
+        | DirectValue::Graft(base, _)
+        | DirectValue::GlobGraft(base, _, _)) = direct;
+        match base {
+            BaseValue::Task(task, _) => deps.push(*task),
+            BaseValue::Config(ident) => self.add_config_dependencies(*ident, deps)?,
+            BaseValue::Interp(_, idents) => {
+                for ident in idents {
+                    self.add_config_dependencies(*ident, deps)?;
+                }
+            }
+            BaseValue::Literal(_) => (),
+        }
+        Ok(())
+    }
+
+    fn add_config_dependencies(
+        &self,
+        ident: IdentId,
+        deps: &mut Vec<AbstractTaskId>,
+    ) -> Result<()> {
+        match self.get_config_value(ident) {
+            Some(value) => self.add_value_dependencies(value, deps),
+            None => Ok(()),
+        }
+    }
+}
+
 // building the workflow /////////////
 impl Workflow {
+    /// Index the uses of each task's outputs, so the workflow can be walked forwards,
+    /// from tasks to the tasks that depend on them.
+    fn index_dependents(&mut self) -> Result<()> {
+        let mut dependents = IdVec::fill(Vec::new(), self.strings.tasks.len());
+        for (i, task) in self.tasks.iter().enumerate() {
+            if !task.exists {
+                continue;
+            }
+            let task_id = AbstractTaskId::from(i);
+            // values to visit, with the grafts applied along the way:
+            let mut values: Vec<(AbstractValueId, BranchSpec, Vec<BranchpointId>)> = task
+                .vars
+                .inputs
+                .iter()
+                .chain(&task.vars.params)
+                .map(|(_, v)| (*v, BranchSpec::default(), Vec::new()))
+                .collect();
+            let mut seen = HashSet::default();
+            while let Some((value, graft, globs)) = values.pop() {
+                if !seen.insert((value, graft.clone(), globs.clone())) {
+                    continue;
+                }
+                let directs: Vec<&DirectValue> = match self.get_value(value)? {
+                    Value::Direct(direct) => vec![direct],
+                    Value::Branched(branches) => branches.iter().map(|(_, d)| d).collect(),
+                };
+                for direct in directs {
+                    let mut graft = graft.clone();
+                    let mut globs = globs.clone();
+                    let base = match direct {
+                        DirectValue::Simple(base) => base,
+                        DirectValue::Graft(base, branch) => {
+                            graft.insert_all(branch);
+                            base
+                        }
+                        DirectValue::GlobGraft(base, branch, glob_bps) => {
+                            graft.insert_all(branch);
+                            globs.extend(glob_bps);
+                            base
+                        }
+                    };
+                    let refs: Vec<IdentId> = match base {
+                        BaseValue::Task(used, _) => {
+                            let dependent = Dependent {
+                                task: task_id,
+                                graft,
+                                globs,
+                            };
+                            let uses: &mut Vec<Dependent> = dependents.get_mut_unchecked(*used);
+                            if !uses.contains(&dependent) {
+                                uses.push(dependent);
+                            }
+                            continue;
+                        }
+                        BaseValue::Config(ident) => vec![*ident],
+                        BaseValue::Interp(_, idents) => idents.clone(),
+                        BaseValue::Literal(_) => Vec::new(),
+                    };
+                    for k in refs {
+                        if let Some(value) = self.get_config_value(k) {
+                            values.push((value, graft.clone(), globs.clone()));
+                        }
+                    }
+                }
+            }
+        }
+        self.dependents = dependents;
+        Ok(())
+    }
+
+    /// Index every task's outputs by var name, and literal outputs by path and file name,
+    /// so goals can be given as the file a task produces.
+    fn index_outputs(&mut self) -> Result<()> {
+        let mut index: HashMap<String, Vec<Producer>> = HashMap::default();
+        let mut add = |key: &str, producer: &Producer| {
+            let producers = index.entry(key.to_owned()).or_default();
+            if !producers.contains(producer) {
+                producers.push(producer.clone());
+            }
+        };
+        for (i, task) in self.tasks.iter().enumerate() {
+            if !task.exists {
+                continue;
+            }
+            let task_id = AbstractTaskId::from(i);
+            for (output, value) in &task.vars.outputs {
+                let producer = |branch: &BranchSpec| Producer {
+                    task: task_id,
+                    output: *output,
+                    branch: branch.clone(),
+                };
+                add(
+                    self.strings.idents.get(*output)?,
+                    &producer(&BranchSpec::default()),
+                );
+                let literals: Vec<(BranchSpec, LiteralId)> = match self.get_value(*value)? {
+                    Value::Direct(DirectValue::Simple(BaseValue::Literal(lit))) => {
+                        vec![(BranchSpec::default(), *lit)]
+                    }
+                    Value::Branched(branches) => branches
+                        .iter()
+                        .filter_map(|(branch, direct)| match direct {
+                            DirectValue::Simple(BaseValue::Literal(lit)) => {
+                                Some((branch.clone(), *lit))
+                            }
+                            _ => None,
+                        })
+                        .collect(),
+                    // other outputs' paths aren't known until they're realized:
+                    _ => Vec::new(),
+                };
+                for (branch, lit) in literals {
+                    let path = self.strings.literals.get(lit)?;
+                    let producer = producer(&branch);
+                    add(path, &producer);
+                    let file_name = Path::new(path).file_name().and_then(|f| f.to_str());
+                    if let Some(file_name) = file_name.filter(|f| *f != path) {
+                        add(file_name, &producer);
+                    }
+                }
+            }
+        }
+        self.output_index = index;
+        Ok(())
+    }
+
+    /// Make sure every graft in a task variable or global config value uses a branch
+    /// that's defined somewhere, so a typo fails here instead of in the middle of a traversal.
+    /// Every bad graft is reported, not just the first.
+    fn check_grafts(&self) -> Result<()> {
+        let mut errors = Vec::new();
+        for (i, task) in self.tasks.iter().enumerate() {
+            if !task.exists {
+                continue;
+            }
+            let name = self.strings.tasks.get(AbstractTaskId::from(i))?;
+            for (var, value) in task.vars.inputs.iter().chain(&task.vars.params) {
+                self.check_value_grafts(&format!("Task \"{name}\""), *var, *value, &mut errors)?;
+            }
+        }
+        // config values are in a hashmap, so sort them into the order they were defined in:
+        let mut config: Vec<_> = self.config_values().collect();
+        config.sort_unstable_by_key(|(_, value)| usize::from(*value));
+        for (var, value) in config {
+            self.check_value_grafts("Global config", var, value, &mut errors)?;
+        }
+        self.report_all(errors, "checking grafts")
+    }
+
+    /// Add an error to `errors` for each graft in `value` that uses
+    /// a branch that isn't defined in the config.
+    fn check_value_grafts(
+        &self,
+        owner: &str,
+        var: IdentId,
+        value: AbstractValueId,
+        errors: &mut Vec<anyhow::Error>,
+    ) -> Result<()> {
+        let directs: Vec<&DirectValue> = match self.get_value(value)? {
+            Value::Direct(direct) => vec![direct],
+            Value::Branched(branches) => branches.iter().map(|(_, d)| d).collect(),
+        };
+        for direct in directs {
+            let (branch, globs) = match direct {
+                DirectValue::Simple(_) => continue,
+                DirectValue::Graft(_, branch) => (branch, &[][..]),
+                DirectValue::GlobGraft(_, branch, globs) => (branch, &globs[..]),
+            };
+            let specified = branch.specified().map(|(k, v)| (k, Some(v)));
+            for (k, v) in specified.chain(globs.iter().map(|k| (*k, None))) {
+                let Some(UndefinedBranch {
+                    branchpoint,
+                    branch,
+                    no_branchpoint,
+                    suggestion,
+                }) = self.undefined_branch(k, v)?
+                else {
+                    continue;
+                };
+                let (owner, var) = (owner.to_owned(), self.strings.idents.get(var)?.to_owned());
+                let e = if no_branchpoint {
+                    Error::GraftBranchpointNotFound(owner, var, branchpoint, branch, suggestion)
+                } else {
+                    Error::GraftBranchNotFound(owner, var, branchpoint, branch, suggestion)
+                };
+                errors.push(e.into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that branch `v` of `k` (or with `None`, any branch of `k`) is defined in the
+    /// config, as grafts, plan baselines, and constraints have to name defined branches.
+    /// Branches only left over in branchpoints.txt don't count.
+    fn undefined_branch(
+        &self,
+        k: BranchpointId,
+        v: Option<IdentId>,
+    ) -> Result<Option<UndefinedBranch>> {
+        let no_branchpoint = self.strings.baselines.defined(k).next().is_none();
+        let found = match v {
+            Some(v) => self.strings.baselines.is_defined(k, v),
+            None => !no_branchpoint,
+        };
+        if found {
+            return Ok(None);
+        }
+        let branchpoint = self.strings.branchpoints.get(k)?.to_owned();
+        let branch = match v {
+            Some(v) => self.strings.idents.get(v)?.to_owned(),
+            None => ast::BRANCH_GLOB.to_owned(),
+        };
+        let suggestion = if no_branchpoint {
+            self.closest_branchpoint(&branchpoint)
+        } else {
+            self.closest_branch(k, &branch)
+        };
+        Ok(Some(UndefinedBranch {
+            branchpoint,
+            branch,
+            no_branchpoint,
+            suggestion,
+        }))
+    }
+
+    /// Fail with the error in `errors` if there's just one, or else list them all.
+    fn report_all(&self, mut errors: Vec<anyhow::Error>, label: &str) -> Result<()> {
+        if errors.len() <= 1 {
+            return errors.pop().map_or(Ok(()), Err);
+        }
+        let mut recap = Errors::default();
+        for e in errors {
+            recap.add(e);
+        }
+        recap.print_recap(label, &self.strings)
+    }
+
     fn add_config(&mut self, assignments: Vec<(&str, ast::Rhs)>) -> Result<()> {
         for (lhs, rhs) in assignments {
             let v = self.strings.create_value(lhs, rhs)?;
@@ -141,50 +910,226 @@ impl Workflow {
         Ok(())
     }
 
-    fn add_task(&mut self, task: ast::TasklikeBlock) -> Result<()> {
+    fn add_task(&mut self, task: ast::TasklikeBlock) -> Result<AbstractTaskId> {
         let name_id = self.strings.tasks.intern(task.name)?;
         let task = Task::create(task, &mut self.strings, &mut self.values)?;
-        self.update_sizes(&task);
+        self.sizes.update(&task);
         // NB we have no easy, surefire way to tell if a task with the same
         // name was added, so if that happens then the task will just be
         // overwritten. Wd be nice to make that an error eventually.
         self.tasks.insert(name_id, task);
-        Ok(())
-    }
-
-    fn update_sizes(&mut self, task: &Task) {
-        let num_inputs = task.vars.inputs.len() as u8;
-        let num_outputs = task.vars.outputs.len() as u8;
-        let num_params = task.vars.params.len() as u8;
-        let num_vars = num_inputs + num_outputs + num_params;
-        self.sizes.max_inputs = self.sizes.max_inputs.max(num_inputs);
-        self.sizes.max_outputs = self.sizes.max_outputs.max(num_outputs);
-        self.sizes.max_params = self.sizes.max_params.max(num_params);
-        self.sizes.max_vars = self.sizes.max_vars.max(num_vars);
+        Ok(name_id)
     }
 
     fn add_plan(&mut self, plan: ast::Plan) -> Result<()> {
         let plan_id = self.strings.idents.intern(plan.name)?;
-        let ast::Plan { cross_products, .. } = plan;
+        let ast::Plan {
+            cross_products,
+            includes,
+            baselines,
+            ..
+        } = plan;
 
-        // the parser will catch this, but nice to have the error just in case
-        // that ever changes:
-        if cross_products.is_empty() {
+        if cross_products.is_empty() && includes.is_empty() {
             return Err(Error::EmptyPlan(plan.name.to_owned()).into());
         }
 
-        let plan = Plan::create(&mut self.strings, cross_products)
+        let plan = Plan::create(&mut self.strings, cross_products, &includes, &baselines)
             .with_context(|| format!("while creating AST for plan \"{}\"", plan.name))?;
 
         // NB we don't use an IdVec bc plans use the idents table,
         // so the vec would be very sparse. cd use a HashMap tho...
-        self.plans.push((plan_id, plan));
+        // a plan with the same name (e.g. from a later config file) replaces the earlier one:
+        match self.plans.iter_mut().find(|(id, _)| *id == plan_id) {
+            Some((_, existing)) => *existing = plan,
+            None => self.plans.push((plan_id, plan)),
+        }
         Ok(())
     }
 
+    fn add_func(&mut self, func: ast::TasklikeBlock) -> Result<()> {
+        if !func.specs.is_empty() {
+            return Err(Error::Unsupported(format!(
+                "inputs, outputs or params in func \"{}\" (use bash's positional params instead)",
+                func.name
+            ))
+            .into());
+        }
+        let name = self.strings.idents.intern(func.name)?;
+        if self.funcs.iter().any(|(other, _)| *other == name) {
+            return Err(Error::DuplicateFunc(func.name.to_owned()).into());
+        }
+        let def = format!("{}() {{\n{}\n}}\n", func.name, func.code.text.trim());
+        let def = self.strings.literals.intern(&def)?;
+        self.funcs.push((name, def));
+        Ok(())
+    }
+
+    /// Add the subplans and baselines of every plan each plan includes
+    /// (and the plans *they* include).
+    /// NB this has to wait until everything is loaded, since plans can include ones defined later.
+    fn resolve_plan_includes(&mut self) -> Result<()> {
+        let mut resolved = Vec::with_capacity(self.plans.len());
+        for (name, plan) in &self.plans {
+            if plan.includes.is_empty() {
+                resolved.push(None);
+                continue;
+            }
+            let mut path = Vec::with_capacity(4);
+            let included = self.all_subplans(*name, &mut path).with_context(|| {
+                let name = self.strings.idents.get(*name).unwrap_or_default();
+                format!("while resolving includes of plan \"{name}\"")
+            })?;
+            resolved.push(Some(included));
+        }
+        for ((_, plan), included) in self.plans.iter_mut().zip(resolved) {
+            if let Some(included) = included {
+                plan.subplans.clear();
+                plan.baselines.clear();
+                plan.extend(included);
+                plan.includes.clear();
+            }
+        }
+        Ok(())
+    }
+
+    /// Subplans of `plan` followed by those of the plans it includes, recursively,
+    /// along with their baselines (where a plan's own baselines win over included ones).
+    /// `path` is the chain of plans being included, to catch cycles.
+    fn all_subplans(&self, plan: IdentId, path: &mut Vec<IdentId>) -> Result<Plan> {
+        let name = self.strings.idents.get(plan)?;
+        if path.contains(&plan) {
+            return Err(Error::PlanIncludesItself(name.to_owned()).into());
+        }
+        let Some((_, found)) = self.plans.iter().find(|(id, _)| *id == plan) else {
+            let closest = self.closest_plan(name).map(str::to_owned);
+            return Err(Error::IncludedPlanNotFound(name.to_owned(), closest).into());
+        };
+        path.push(plan);
+        let mut all = Plan {
+            subplans: found.subplans.clone(),
+            baselines: found.baselines.clone(),
+            ..Default::default()
+        };
+        for include in &found.includes {
+            all.extend(self.all_subplans(*include, path)?);
+        }
+        path.pop();
+        Ok(all)
+    }
+
+    /// Make sure every baseline a plan overrides is a branch that's defined somewhere.
+    fn check_plan_baselines(&self) -> Result<()> {
+        let mut errors = Vec::new();
+        for (name, plan) in &self.plans {
+            for (k, v) in &plan.baselines {
+                let Some(UndefinedBranch {
+                    branchpoint,
+                    branch,
+                    no_branchpoint,
+                    suggestion,
+                }) = self.undefined_branch(*k, Some(*v))?
+                else {
+                    continue;
+                };
+                let name = self.strings.idents.get(*name)?.to_owned();
+                let e = if no_branchpoint {
+                    Error::BaselineBranchpointNotFound(name, branchpoint, branch, suggestion)
+                } else {
+                    Error::BaselineBranchNotFound(name, branchpoint, branch, suggestion)
+                };
+                errors.push(e.into());
+            }
+        }
+        self.report_all(errors, "checking plan baselines")
+    }
+
+    fn add_constraint(&mut self, constraint: &ast::Constraint) -> Result<()> {
+        let constraint = Constraint::create(&mut self.strings, constraint)?;
+        if constraint.branchpoint == constraint.other_branchpoint {
+            let desc = constraint.describe(&self.strings)?;
+            return Err(Error::ConstraintOnOneBranchpoint(desc).into());
+        }
+        self.constraints.push(constraint);
+        Ok(())
+    }
+
+    /// Make sure both branches of every constraint are defined somewhere.
+    fn check_constraints(&self) -> Result<()> {
+        let mut errors = Vec::new();
+        for constraint in &self.constraints {
+            for (k, v) in [
+                (constraint.branchpoint, constraint.branch),
+                (constraint.other_branchpoint, constraint.other_branch),
+            ] {
+                let Some(UndefinedBranch {
+                    branchpoint,
+                    branch,
+                    no_branchpoint,
+                    suggestion,
+                }) = self.undefined_branch(k, Some(v))?
+                else {
+                    continue;
+                };
+                let desc = constraint.describe(&self.strings)?;
+                let e = if no_branchpoint {
+                    Error::ConstraintBranchpointNotFound(desc, branchpoint, suggestion)
+                } else {
+                    Error::ConstraintBranchNotFound(desc, branchpoint, branch, suggestion)
+                };
+                errors.push(e.into());
+            }
+        }
+        self.report_all(errors, "checking constraints")
+    }
+
+    /// Fail if any branch `plan` reaches breaks a constraint, listing each one that does.
+    /// Branchpoints a plan leaves unspecified are checked once the plan is traversed.
+    fn check_plan_constraints(&self, name: IdentId, plan: &Plan) -> Result<()> {
+        if self.constraints.is_empty() {
+            return Ok(());
+        }
+        let mut rejected: Vec<String> = Vec::new();
+        for subplan in &plan.subplans {
+            for branch in &subplan.branches {
+                for constraint in self.constraints.iter().filter(|c| c.is_broken_by(branch)) {
+                    let rejection = format!(
+                        "[{}] ({})",
+                        &*self.strings.get_full_branch_str(branch)?,
+                        constraint.describe(&self.strings)?
+                    );
+                    if !rejected.contains(&rejection) {
+                        rejected.push(rejection);
+                    }
+                }
+            }
+        }
+        if rejected.is_empty() {
+            return Ok(());
+        }
+        let name = self.strings.idents.get(name)?.to_owned();
+        Err(Error::PlanBreaksConstraints(name, rejected.join(", ")).into())
+    }
+
+    /// The defined branchpoint whose name is closest to `name`, to suggest in place of it.
+    fn closest_branchpoint(&self, name: &str) -> Option<String> {
+        let defined = self.strings.branchpoints.iter();
+        let defined = defined.filter(|(k, _)| self.strings.baselines.defined(*k).next().is_some());
+        util::closest_match(name, defined.map(|(_, s)| s)).map(str::to_owned)
+    }
+
+    /// The defined branch of `k` whose name is closest to `name`, to suggest in place of it.
+    fn closest_branch(&self, k: BranchpointId, name: &str) -> Option<String> {
+        let defined = self.strings.baselines.defined(k);
+        let branches = defined.filter_map(|v| self.strings.idents.get(v).ok());
+        util::closest_match(name, branches).map(str::to_owned)
+    }
+
+    /// Literal module paths are made absolute here; other values (e.g. ones that refer to
+    /// config values, or that branch) are resolved for each task that uses the module.
     fn add_module(&mut self, name: &str, path: ast::Rhs, config_dir: &Path) -> Result<()> {
         let id = self.strings.modules.intern(name)?;
-        if let ast::Rhs::Literal { val } = path {
+        let val = if let ast::Rhs::Literal { val } = path {
             let mut path = PathBuf::from(val);
 
             if path.is_relative() {
@@ -200,15 +1145,378 @@ impl Workflow {
                 );
             }
             let path_str = path.to_str().ok_or(PathEncodingError)?;
-            let literal_id = self.strings.literals.intern(path_str)?;
-            self.modules.insert(id, literal_id);
-            Ok(())
+            self.strings.create_value(name, ast::Rhs::Literal { val: path_str })?
         } else {
-            Err(Error::Unsupported(format!(
-                "Module values other than literal strings (in module \"{}\")",
-                name
-            ))
-            .into())
+            self.strings.create_value(name, path)?
+        };
+        let val_id = self.values.push(val);
+        self.modules.insert(id, Some(val_id));
+        Ok(())
+    }
+
+    fn add_module_rev(&mut self, name: &str, rev: ast::Rhs) -> Result<()> {
+        let id = self.strings.modules.intern(name)?;
+        let val = self.strings.create_value(name, rev)?;
+        let val_id = self.values.push(val);
+        self.module_revs.insert(id, Some(val_id));
+        Ok(())
+    }
+
+    /// Give every task that uses a module an implicit param with the module's path,
+    /// so that the path can be resolved (and branch) like any other value.
+    /// Tasks in a versioned module also get a param with the module's rev,
+    /// so that its branchpoints (and the commit it resolves to) are part of the task's identity.
+    /// NB this has to wait until everything is loaded, since modules can be defined after tasks.
+    fn add_module_params(&mut self) -> Result<()> {
+        for module in (0..self.modules.len()).map(ModuleId::from) {
+            if self.modules.get(module).copied().flatten().is_none() {
+                continue;
+            }
+            let name = self.strings.modules.get(module)?;
+            let path_param = format!("{MODULE_VAR_PREFIX}{name}");
+            let commit_param = format!("{path_param}{MODULE_COMMIT_SUFFIX}");
+            let path_param = self.strings.idents.intern(&path_param)?;
+            self.module_path_params.insert(module, Some(path_param));
+            if self.get_module_rev(module).is_some() {
+                let commit_param = self.strings.idents.intern(&commit_param)?;
+                self.module_commit_params.insert(module, Some(commit_param));
+            }
+        }
+        for task in self.tasks.iter_mut() {
+            for module in &task.modules {
+                let params = [
+                    (&self.module_path_params, &self.modules),
+                    (&self.module_commit_params, &self.module_revs),
+                ];
+                for (params, values) in params {
+                    let param = params.get(*module).copied().flatten();
+                    let val = values.get(*module).copied().flatten();
+                    if let (Some(param), Some(val)) = (param, val) {
+                        task.vars.params.push((param, val));
+                    }
+                }
+            }
+            self.sizes.update(task);
+        }
+        Ok(())
+    }
+
+    /// Prepend the definitions of the funcs each task calls (and the funcs *they* call)
+    /// to the task's code, so they end up in its task.sh and are part of its identity.
+    /// NB this has to wait until everything is loaded, since funcs can be defined after tasks.
+    fn add_funcs_to_tasks(&mut self) -> Result<()> {
+        if self.funcs.is_empty() {
+            return Ok(());
+        }
+        let mut names = Vec::with_capacity(self.funcs.len());
+        let mut defs = Vec::with_capacity(self.funcs.len());
+        for (name, def) in &self.funcs {
+            names.push(self.strings.idents.get(*name)?.to_owned());
+            defs.push(self.strings.literals.get(*def)?.to_owned());
+        }
+
+        for task in self.tasks.iter_mut().filter(|task| task.exists) {
+            let code = self.strings.literals.get(task.code)?.to_owned();
+            let mut used = vec![false; names.len()];
+            let mut to_check = vec![code.as_str()];
+            while let Some(text) = to_check.pop() {
+                for (i, name) in names.iter().enumerate() {
+                    if !used[i] && calls_func(text, name) {
+                        used[i] = true;
+                        to_check.push(&defs[i]);
+                    }
+                }
+            }
+            if !used.contains(&true) {
+                continue;
+            }
+            // keep the funcs in the order they were defined in:
+            let mut new_code = String::with_capacity(code.len() + 256);
+            for (def, _) in defs.iter().zip(&used).filter(|(_, used)| **used) {
+                new_code.push_str(def);
+            }
+            new_code.push_str(&code);
+            task.code = self.strings.literals.intern(&new_code)?;
+        }
+        Ok(())
+    }
+}
+
+/// true if `code` runs `name` as a command, e.g. `greet world` or `x=$(greet)`,
+/// but not `echo greet`, `$greet`, or `# greet`.
+fn calls_func(code: &str, name: &str) -> bool {
+    shell::statements(code)
+        .iter()
+        .any(|statement| shell::command(statement).is_some_and(|(c, _)| c == name))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_task_graph() -> Result<()> {
+        let text = "global {\n  prof=(Profile: debug release)\n  lib=$out@build\n}\n\
+                    task build > out :: p=$prof { touch $out }\n\
+                    task test < in=$lib rel=$out@build[Profile: release] :: os=(Os: mac linux) {\n\
+                      echo $in $rel $os\n\
+                    }\n\
+                    task pinned :: p=$prof[Profile: release] { echo $p }\n";
+        let mut wf = Workflow::default();
+        wf.load(syntax::parse(text)?, Path::new("."))?;
+        let build = wf.strings.tasks.intern("build")?;
+        let test = wf.strings.tasks.intern("test")?;
+        let pinned = wf.strings.tasks.intern("pinned")?;
+
+        let edges = wf.task_edges(test)?;
+        let edges: Vec<_> = edges.iter().map(|e| (e.task, e.grafted)).collect();
+        assert_eq!(edges, vec![(build, false), (build, true)]);
+
+        let profile = wf.strings.branchpoints.intern("Profile")?;
+        let release = wf.strings.idents.intern("release")?;
+        let uses = wf.dependents(build);
+        assert_eq!(uses.len(), 2);
+        assert!(uses.iter().all(|d| d.task == test));
+        assert!(uses.iter().any(|d| d.graft == BranchSpec::simple(profile, release)));
+        assert!(wf.dependents(test).is_empty());
+
+        let names = |bps: Vec<BranchpointId>| -> Result<Vec<String>> {
+            bps.iter().map(|k| Ok(wf.strings.branchpoints.get(*k)?.to_owned())).collect()
+        };
+        assert_eq!(names(wf.task_branchpoints(build)?)?, vec!["Profile"]);
+        assert_eq!(names(wf.task_branchpoints(test)?)?, vec!["Os"]);
+        assert!(wf.task_branchpoints(pinned)?.is_empty());
+
+        // upstream branchpoints include inherited and grafted ones:
+        let upstream = wf.upstream_branchpoints()?;
+        let upstream = |task| names(upstream.get(task).cloned().unwrap_or_default());
+        assert_eq!(upstream(build)?, vec!["Profile"]);
+        assert_eq!(upstream(test)?, vec!["Profile", "Os"]);
+        assert_eq!(upstream(pinned)?, vec!["Profile"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_producers() -> Result<()> {
+        let text = "task build > lib=out/libfoo.a log {\n  touch $lib $log\n}\n\
+                    task package < lib=$lib@build > pkg=(Os: mac=installer.pkg win=setup.msi) {\n\
+                      cp $lib $pkg\n\
+                    }\n\
+                    task ship < pkg=$pkg@package > receipt=$pkg { echo $pkg }\n";
+        let mut wf = Workflow::default();
+        wf.load(syntax::parse(text)?, Path::new("."))?;
+        let build = wf.strings.tasks.intern("build")?;
+        let package = wf.strings.tasks.intern("package")?;
+        let ship = wf.strings.tasks.intern("ship")?;
+        let tasks =
+            |name| -> Vec<AbstractTaskId> { wf.producers(name).iter().map(|p| p.task).collect() };
+        assert_eq!(tasks("lib"), vec![build]);
+        assert_eq!(tasks("out/libfoo.a"), vec![build]);
+        assert_eq!(tasks("libfoo.a"), vec![build]);
+        assert_eq!(tasks("log"), vec![build]);
+        assert_eq!(tasks("installer.pkg"), vec![package]);
+        assert_eq!(tasks("pkg"), vec![package]);
+        // outputs that aren't literals are only indexed by name:
+        assert_eq!(tasks("receipt"), vec![ship]);
+        assert!(wf.producers("missing").is_empty());
+
+        let os = wf.strings.branchpoints.intern("Os")?;
+        let win = wf.strings.idents.intern("win")?;
+        assert_eq!(
+            wf.producers("setup.msi")[0].branch,
+            BranchSpec::simple(os, win)
+        );
+        assert!(wf.producers("lib")[0].branch.is_empty());
+        assert_eq!(wf.closest_output("instaler.pkg"), Some("installer.pkg"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_grafts() -> Result<()> {
+        let load = |grafts: &str| -> Result<()> {
+            let text = format!(
+                "task build > out :: p=(Profile: debug release) {{ touch $out }}\n\
+                 task test < in=$out@build{grafts} {{ echo $in }}\n"
+            );
+            let mut wf = Workflow::default();
+            wf.load(syntax::parse(&text)?, Path::new("."))
+        };
+        load("[Profile: release]")?;
+        load("[Profile: *]")?;
+        assert_eq!(
+            load("[Profile: relese]").unwrap_err().to_string(),
+            "Task \"test\" grafts 'in' from branch [Profile: relese], \
+             but 'Profile' has no branch 'relese' (did you mean `release`?)"
+        );
+        assert_eq!(
+            load("[Profle: release]").unwrap_err().to_string(),
+            "Task \"test\" grafts 'in' from branch [Profle: release], \
+             but branchpoint 'Profle' isn't defined anywhere (did you mean `Profile`?)"
+        );
+        assert!(load("[Os: *]").is_err());
+        // every bad graft is reported:
+        assert_eq!(
+            load("[Profile: relese] in2=$out@build[Profle: release]")
+                .unwrap_err()
+                .to_string(),
+            "checking grafts failed due to 2 errors"
+        );
+
+        // a branch that's only left in branchpoints.txt isn't defined:
+        let text = "task build > out :: p=(Profile: debug release) { touch $out }\n\
+                    task test < in=$out@build[Profile: profiling] { echo $in }\n";
+        let mut wf = Workflow::default();
+        wf.strings.pre_load_baseline("Profile", "profiling")?;
+        assert_eq!(
+            wf.load(syntax::parse(text)?, Path::new(".")).unwrap_err().to_string(),
+            "Task \"test\" grafts 'in' from branch [Profile: profiling], \
+             but 'Profile' has no branch 'profiling'"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_plan_baselines() -> Result<()> {
+        let load = |plans: &str| -> Result<Workflow> {
+            let text = format!(
+                "task build > out :: p=(Profile: debug release) os=(Os: mac linux) {{ touch $out }}\n\
+                 {plans}"
+            );
+            let mut wf = Workflow::default();
+            wf.load(syntax::parse(&text)?, Path::new("."))?;
+            Ok(wf)
+        };
+        let mut wf = load(
+            "plan release { baseline (Profile: release) * (Os: linux); include debug }\n\
+             plan debug { baseline (Os: mac); reach build }\n",
+        )?;
+        let release = wf.strings.idents.intern("release")?;
+        let linux = wf.strings.idents.intern("linux")?;
+        let profile = wf.strings.branchpoints.intern("Profile")?;
+        let os = wf.strings.branchpoints.intern("Os")?;
+        let plan = wf.get_plan(release)?;
+        // the plan's own baselines win over the ones it includes:
+        assert_eq!(plan.baselines, vec![(profile, release), (os, linux)]);
+        assert_eq!(plan.subplans.len(), 1);
+
+        let mut plan = plan.clone();
+        plan.apply_baselines(&mut wf.strings);
+        assert_eq!(wf.strings.baselines.get(profile), release);
+        assert_eq!(plan.subplans[0].branches[0].get_specified(os), Some(linux));
+        // the defined baseline is still the first known branch:
+        assert_ne!(wf.strings.baselines.values(profile)[0], release);
+        Plan::default().apply_baselines(&mut wf.strings);
+        assert_ne!(wf.strings.baselines.get(profile), release);
+
+        assert_eq!(
+            load("plan p { baseline (Profile: relase); reach build }")
+                .unwrap_err()
+                .to_string(),
+            "Plan \"p\" uses [Profile: relase] as baseline, but 'Profile' has no branch 'relase' \
+             (did you mean `release`?)"
+        );
+        assert_eq!(
+            load("plan p { baseline (Profle: release); reach build }")
+                .unwrap_err()
+                .to_string(),
+            "Plan \"p\" uses [Profle: release] as baseline, \
+             but branchpoint 'Profle' isn't defined anywhere (did you mean `Profile`?)"
+        );
+        assert_eq!(
+            load("plan p { baseline (Profle: release) * (Os: windows); reach build }")
+                .unwrap_err()
+                .to_string(),
+            "checking plan baselines failed due to 2 errors"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_constraints() -> Result<()> {
+        let load = |extra: &str| -> Result<Workflow> {
+            let text = format!(
+                "task build :: os=(Os: mac windows) arch=(Arch: arm64 x64) {{ echo $os $arch }}\n\
+                 constraint Os.windows requires Arch.x64\n\
+                 constraint Os.mac excludes Arch.x64\n\
+                 {extra}"
+            );
+            let mut wf = Workflow::default();
+            wf.load(syntax::parse(&text)?, Path::new("."))?;
+            Ok(wf)
+        };
+        let mut wf = load("plan ok { reach build via (Os: *) * (Arch: *) except (Os: windows * Arch: arm64) except (Os: mac * Arch: x64) }\n")?;
+        assert_eq!(wf.constraints().len(), 2);
+        let os = wf.strings.branchpoints.intern("Os")?;
+        let arch = wf.strings.branchpoints.intern("Arch")?;
+        let windows = wf.strings.idents.intern("windows")?;
+        let arm64 = wf.strings.idents.intern("arm64")?;
+        let mut branch = BranchSpec::default();
+        branch.insert(os, windows);
+        // unspecified branchpoints don't break constraints:
+        assert!(!wf.constraints()[0].is_broken_by(&branch));
+        branch.insert(arch, arm64);
+        assert!(wf.constraints()[0].is_broken_by(&branch));
+        assert!(!wf.constraints()[1].is_broken_by(&branch));
+
+        assert_eq!(
+            load("plan all { reach build via (Os: *) * (Arch: *) }")
+                .unwrap_err()
+                .to_string(),
+            "Plan \"all\" reaches branches that break constraints: \
+             [Os.windows+Arch.arm64] (Os.windows requires Arch.x64), \
+             [Os.mac+Arch.x64] (Os.mac excludes Arch.x64)"
+        );
+        assert_eq!(
+            load("constraint Os.windows requires Arch.x6").unwrap_err().to_string(),
+            "Constraint 'Os.windows requires Arch.x6' uses [Arch: x6], \
+             but 'Arch' has no branch 'x6' (did you mean `x64`?)"
+        );
+        assert_eq!(
+            load("constraint Os.windows requires Arc.x64").unwrap_err().to_string(),
+            "Constraint 'Os.windows requires Arc.x64' uses branchpoint 'Arc', \
+             which isn't defined anywhere (did you mean `Arch`?)"
+        );
+        assert!(load("constraint Os.windows excludes Os.mac").is_err());
+        assert_eq!(
+            load("constraint Os.win requires Arc.x64").unwrap_err().to_string(),
+            "checking constraints failed due to 2 errors"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_many_tasks() -> Result<()> {
+        // more tasks than fit in a u8, with more code than fits in a u16 string index:
+        let mut text = String::from("task t0 > out { touch $out }\n");
+        for i in 1..1000 {
+            text.push_str(&format!(
+                "task t{i} < in=$out@t{} > out {{\n  # step {i} of a long chain of tasks\n  \
+                 cp $in $out && echo \"copied $in to $out in step {i}\"\n}}\n",
+                i - 1
+            ));
         }
+        let mut wf = Workflow::default();
+        wf.load(syntax::parse(&text)?, Path::new("."))?;
+        assert_eq!(wf.strings.tasks.len(), 1000);
+        assert!(wf.strings.literals.str_len() > usize::from(u16::MAX));
+        let last = wf.strings.tasks.intern("t999")?;
+        assert_eq!(wf.strings.tasks.get(last)?, "t999");
+        let code = wf.strings.literals.get(wf.get_task(last)?.code)?;
+        assert!(code.contains("step 999"), "{code}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_calls_func() {
+        assert!(super::calls_func("greet world", "greet"));
+        assert!(super::calls_func("x=$(greet)\necho $x", "greet"));
+        assert!(!super::calls_func("greeter world", "greet"));
+        assert!(!super::calls_func("echo $my_greet", "greet"));
+        assert!(!super::calls_func("pre-greet", "greet"));
+        assert!(!super::calls_func("echo $greet", "greet"));
+        assert!(!super::calls_func("echo hi # then greet", "greet"));
+        assert!(!super::calls_func("echo greet 'greet'", "greet"));
+        assert!(super::calls_func("if true; then greet; fi", "greet"));
+        assert!(super::calls_func("echo \"$(greet)\"", "greet"));
     }
 }